@@ -0,0 +1,177 @@
+//! Benchmarks of reading, searching and indexing the messages of a hub generated by [`seed_hub`].
+//!
+//! The data is written to a temporary directory. Channels are small by default so that CI can run the benchmarks, set `WICRS_BENCH_MESSAGES` to the number of messages per channel to measure bigger ones.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use xactor::{Actor, Addr};
+
+use wicrs_server::{
+    channel::{AuthorType, Channel, Message},
+    config::InstrumentationConfig,
+    instrumentation::ActorStats,
+    server::{
+        rebuild_index, MessageServer, NewMessageForIndex, QueueMessageForIndex, SearchMessageIndex,
+    },
+    testing::{seed_hub, SeedOptions, SeededHub},
+    ChannelId, HubId, MessageId,
+};
+
+/// Messages per channel if `WICRS_BENCH_MESSAGES` is not set.
+const DEFAULT_MESSAGES: usize = 2000;
+
+/// Messages returned by each read, the size of a page in clients.
+const PAGE_SIZE: usize = 100;
+
+/// Messages queued for indexing at once, like a bot importing a channel.
+const QUEUED_MESSAGES: u128 = 10_000;
+
+/// Seeds a hub with one channel in an empty data directory.
+fn seed(runtime: &tokio::runtime::Runtime) -> (SeedOptions, SeededHub) {
+    let data = std::env::temp_dir().join("wicrs-bench");
+    let _ = std::fs::remove_dir_all(&data);
+    std::fs::create_dir_all(&data).expect("Unable to create the benchmark directory");
+    std::env::set_current_dir(&data).expect("Unable to enter the benchmark directory");
+    let options = SeedOptions {
+        channels: 1,
+        messages_per_channel: std::env::var("WICRS_BENCH_MESSAGES")
+            .ok()
+            .and_then(|messages| messages.parse().ok())
+            .unwrap_or(DEFAULT_MESSAGES),
+        ..SeedOptions::default()
+    };
+    let seeded = runtime
+        .block_on(seed_hub(&options, None))
+        .expect("Unable to seed the benchmark hub");
+    (options, seeded)
+}
+
+fn messages(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let (options, seeded) = seed(&runtime);
+    let channel = Channel::new(String::new(), seeded.channel_ids[0], seeded.hub_id);
+    let start = options.end - chrono::Duration::days(options.days.into());
+    let first = runtime
+        .block_on(channel.get_messages_between(start, options.end, false, 1))
+        .pop()
+        .expect("The benchmark channel has no messages")
+        .id;
+
+    c.bench_function("get_last_messages", |b| {
+        b.to_async(&runtime)
+            .iter(|| channel.get_last_messages(PAGE_SIZE))
+    });
+    c.bench_function("get_messages_between newest page", |b| {
+        b.to_async(&runtime)
+            .iter(|| channel.get_messages_between(start, options.end, true, PAGE_SIZE))
+    });
+    c.bench_function("get_messages_after first message", |b| {
+        b.to_async(&runtime)
+            .iter(|| channel.get_messages_after(first, PAGE_SIZE))
+    });
+
+    let stats = Arc::new(ActorStats::new(
+        "message_server",
+        &InstrumentationConfig::default(),
+    ));
+    let mut message_server: Addr<MessageServer> = runtime
+        .block_on(MessageServer::new(stats).start())
+        .expect("Unable to start the message server");
+    let search = SearchMessageIndex {
+        hub_id: seeded.hub_id,
+        channel_id: channel.id,
+        limit: PAGE_SIZE,
+        query: "deploy AND search".to_string(),
+        not_before: None,
+    };
+    c.bench_function("search", |b| {
+        b.to_async(&runtime).iter(|| async {
+            message_server
+                .call(search.clone())
+                .await
+                .unwrap()
+                .expect("Search failed")
+        })
+    });
+
+    let hub_id = seeded.hub_id;
+    let mut group = c.benchmark_group("queue");
+    group.sample_size(10);
+    group.bench_function("index queued messages", |b| {
+        b.to_async(&runtime).iter_custom(|iters| {
+            let message_server = message_server.clone();
+            async move {
+                let mut elapsed = Duration::default();
+                for _ in 0..iters {
+                    let start = Instant::now();
+                    index_queued_messages(&message_server, hub_id).await;
+                    elapsed += start.elapsed();
+                }
+                elapsed
+            }
+        })
+    });
+    group.finish();
+    // Reindexing must not happen while the message server has the index open.
+    message_server.stop(None).ok();
+
+    let mut group = c.benchmark_group("index");
+    group.sample_size(10);
+    group.bench_function("rebuild_index", |b| {
+        b.to_async(&runtime)
+            .iter(|| async { rebuild_index(&channel).await.expect("Reindexing failed") })
+    });
+    group.finish();
+}
+
+/// Queues [`QUEUED_MESSAGES`] new messages in a new channel without waiting for them to be indexed, then waits until a search finds the last one.
+async fn index_queued_messages(message_server: &Addr<MessageServer>, hub_id: HubId) {
+    let channel_id = ChannelId::random();
+    for n in 1..=QUEUED_MESSAGES {
+        let content = if n == QUEUED_MESSAGES {
+            "needle".to_string()
+        } else {
+            format!("message {}", n)
+        };
+        message_server
+            .send(QueueMessageForIndex(NewMessageForIndex {
+                hub_id,
+                channel_id,
+                message: Message {
+                    id: MessageId::from_u128(n),
+                    hub_id,
+                    channel_id,
+                    sender: "0123456789ABCDEF0123456789ABCDEF01234567".to_string(),
+                    created: chrono::Utc::now(),
+                    content,
+                    group_mentions: Vec::new(),
+                    forwarded_from: None,
+                    flags: 0,
+                    author_type: AuthorType::User,
+                    override_name: None,
+                    override_avatar: None,
+                },
+            }))
+            .expect("Unable to queue a message");
+    }
+    // Handled after every message that was queued before it.
+    let found = message_server
+        .call(SearchMessageIndex {
+            hub_id,
+            channel_id,
+            limit: 10,
+            query: "needle".to_string(),
+            not_before: None,
+        })
+        .await
+        .unwrap()
+        .expect("Search failed");
+    assert_eq!(found, vec![MessageId::from_u128(QUEUED_MESSAGES)]);
+}
+
+criterion_group!(benches, messages);
+criterion_main!(benches);
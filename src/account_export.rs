@@ -0,0 +1,347 @@
+use std::{collections::HashSet, convert::TryFrom, path::PathBuf, sync::Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::{
+    api::types::HubMemberInfo,
+    channel::Message,
+    error::{Error, IoContext},
+    hub::Hub,
+    quotas::{QuotaStatus, UserQuota},
+    HubId, Result,
+};
+
+/// Folder where the status and the finished export of each user are stored.
+pub const ACCOUNT_EXPORTS_FOLDER: &str = "data/users/exports/";
+
+/// Users whose export is being generated by this process, see [`ExportStatus::current`].
+static RUNNING_EXPORTS: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+/// State of a user's latest export.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportState {
+    /// The export is being generated.
+    Running,
+    /// The export can be downloaded.
+    Ready,
+    /// The export could not be generated, a new one can be started right away.
+    Failed,
+}
+
+/// Status of a user's latest export, served by `/v3/export_account/status`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ExportStatus {
+    pub state: ExportState,
+    /// Time the export was started.
+    pub requested: DateTime<Utc>,
+    /// Time the export was finished or failed.
+    pub finished: Option<DateTime<Utc>>,
+}
+
+impl ExportStatus {
+    /// Gets the path of the file that a user's export status is stored in.
+    pub fn get_path(user_id: &str) -> PathBuf {
+        crate::paths::export_status_file(user_id)
+    }
+
+    /// Gets the path of the file that a user's finished export is stored in.
+    pub fn get_export_path(user_id: &str) -> PathBuf {
+        crate::paths::export_file(user_id)
+    }
+
+    /// Loads the status of a user's latest export, `None` if the user never started one.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The user ID is not a hex encoded fingerprint.
+    /// * The file exists but could not be read.
+    /// * The file's contents could not be deserialized.
+    pub async fn load(user_id: &str) -> Result<Option<Self>> {
+        if hex::decode(user_id).is_err() {
+            return Err(Error::InvalidFingerprint);
+        }
+        let path = Self::get_path(user_id);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(Some(bincode::deserialize(&bytes)?)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err).with_path(path),
+        }
+    }
+
+    /// Saves the status of a user's latest export.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The exports folder does not exist and could not be created.
+    /// * The data could not be written to the disk.
+    pub async fn save(&self, user_id: &str) -> Result {
+        tokio::fs::create_dir_all(ACCOUNT_EXPORTS_FOLDER)
+            .await
+            .with_path(ACCOUNT_EXPORTS_FOLDER)?;
+        let path = Self::get_path(user_id);
+        tokio::fs::write(&path, bincode::serialize(self)?)
+            .await
+            .with_path(path)
+    }
+
+    /// Loads the status of a user's latest export, an export that is still marked as running but is not being generated by this process was interrupted by a restart and is reported as failed.
+    ///
+    /// # Errors
+    ///
+    /// This function may return an error for any of the reasons outlined in [`ExportStatus::load`].
+    pub async fn current(user_id: &str) -> Result<Option<Self>> {
+        let mut status = Self::load(user_id).await?;
+        if let Some(status) = status.as_mut() {
+            if status.state == ExportState::Running && !is_running(user_id) {
+                status.state = ExportState::Failed;
+            }
+        }
+        Ok(status)
+    }
+
+    /// Checks that a new export can be started, only one export that did not fail can be started per day.
+    ///
+    /// # Errors
+    ///
+    /// This function returns [`Error::RateLimited`] if an export is running or one was started less than a day before `now`.
+    pub fn check_can_start(status: Option<&Self>, now: DateTime<Utc>) -> Result {
+        match status {
+            Some(status) if status.state == ExportState::Running => Err(Error::RateLimited),
+            Some(status)
+                if status.state == ExportState::Ready
+                    && now.signed_duration_since(status.requested) < Duration::days(1) =>
+            {
+                Err(Error::RateLimited)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A user's membership of a hub, as included in their export.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ExportedMembership {
+    pub hub_id: HubId,
+    pub hub_name: String,
+    /// Whether the user owns the hub.
+    pub owner: bool,
+    pub member: HubMemberInfo,
+}
+
+/// Everything the server stores about a user, served by `/v3/export_account/download`.
+/// The server does not keep sessions, every request is signed by the user's PGP key instead, so there is no session data to include.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AccountExport {
+    pub user_id: String,
+    /// Time the export was generated.
+    pub generated: DateTime<Utc>,
+    /// The user's ASCII armoured public key, if the server has cached it.
+    pub public_key: Option<String>,
+    pub quota: QuotaStatus,
+    /// Hubs the user is a member of, sorted by ID.
+    pub hubs: Vec<ExportedMembership>,
+    /// Messages the user sent in the channels they can still read, oldest first in each channel.
+    pub messages: Vec<Message>,
+}
+
+/// Checks if this process is generating a user's export.
+fn is_running(user_id: &str) -> bool {
+    RUNNING_EXPORTS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .as_ref()
+        .is_some_and(|running| running.contains(user_id))
+}
+
+/// Marks a user's export as being generated by this process, returns false if it already was.
+fn set_running(user_id: &str, running: bool) -> bool {
+    let mut exports = RUNNING_EXPORTS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    let exports = exports.get_or_insert_with(HashSet::new);
+    if running {
+        exports.insert(user_id.to_string())
+    } else {
+        exports.remove(user_id)
+    }
+}
+
+/// Gathers everything the server stores about a user.
+/// Every hub is scanned for the user's membership, then every channel the user can read in those hubs is scanned for their messages, which can take a long time on big servers.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user's quota could not be loaded for any of the reasons outlined in [`UserQuota::load`].
+/// * The hubs could not be listed for any of the reasons outlined in [`crate::maintenance::list_hubs`].
+/// * A hub the user is in could not be loaded for any of the reasons outlined in [`Hub::load`].
+pub async fn generate(user_id: &str) -> Result<AccountExport> {
+    let public_key = tokio::fs::read_to_string(crate::paths::user_public_key_file(user_id))
+        .await
+        .ok();
+    let quota = UserQuota::load(user_id)
+        .await?
+        .status(&crate::quotas::limits());
+    let mut hubs = Vec::new();
+    let mut messages = Vec::new();
+    let mut hub_ids = crate::maintenance::list_hubs().await?;
+    hub_ids.sort();
+    for hub_id in hub_ids {
+        let hub = match Hub::load(hub_id).await {
+            Ok(hub) => hub,
+            Err(Error::HubNotFound) => continue,
+            Err(err) => return Err(err),
+        };
+        let member = match hub.members.get(user_id) {
+            Some(member) => member,
+            None => continue,
+        };
+        hubs.push(ExportedMembership {
+            hub_id,
+            hub_name: hub.name.clone(),
+            owner: hub.owner == user_id,
+            member: member.into(),
+        });
+        let mut channel_ids: Vec<_> = hub.channels.keys().copied().collect();
+        channel_ids.sort();
+        for channel_id in channel_ids {
+            if let Ok(channel) = hub.get_channel(user_id, channel_id) {
+                for signed in channel.get_all_messages().await {
+                    if let Ok(message) = Message::try_from(&signed) {
+                        if message.sender == user_id {
+                            messages.push(message);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(AccountExport {
+        user_id: user_id.to_string(),
+        generated: Utc::now(),
+        public_key,
+        quota,
+        hubs,
+        messages,
+    })
+}
+
+/// Generates a user's export and stores it, then marks the export as ready or failed.
+async fn run(user_id: String, mut status: ExportStatus) {
+    let result = async {
+        let export = generate(&user_id).await?;
+        let path = ExportStatus::get_export_path(&user_id);
+        let tmp_path = path.with_extension("json.tmp");
+        let bytes = crate::encryption::seal(serde_json::to_vec(&export)?)?;
+        tokio::fs::write(&tmp_path, bytes)
+            .await
+            .with_path(&tmp_path)?;
+        tokio::fs::rename(&tmp_path, &path).await.with_path(path)
+    }
+    .await;
+    status.finished = Some(Utc::now());
+    status.state = match result {
+        Ok(()) => ExportState::Ready,
+        Err(err) => {
+            error!("Account export for {} failed: {}", user_id, err.chain());
+            ExportState::Failed
+        }
+    };
+    if let Err(err) = status.save(&user_id).await {
+        error!(
+            "Unable to save the account export status of {}: {}",
+            user_id,
+            err.chain()
+        );
+    }
+    set_running(&user_id, false);
+}
+
+/// Starts generating a user's export in the background, see [`generate`]. The status can be followed with [`ExportStatus::current`].
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The status of the user's previous export could not be loaded for any of the reasons outlined in [`ExportStatus::load`].
+/// * An export can not be started yet, see [`ExportStatus::check_can_start`].
+/// * The new status could not be saved for any of the reasons outlined in [`ExportStatus::save`].
+pub async fn start(user_id: &str) -> Result<ExportStatus> {
+    let now = Utc::now();
+    ExportStatus::check_can_start(ExportStatus::current(user_id).await?.as_ref(), now)?;
+    if !set_running(user_id, true) {
+        return Err(Error::RateLimited);
+    }
+    let status = ExportStatus {
+        state: ExportState::Running,
+        requested: now,
+        finished: None,
+    };
+    if let Err(err) = status.save(user_id).await {
+        set_running(user_id, false);
+        return Err(err);
+    }
+    tokio::spawn(run(user_id.to_string(), status.clone()));
+    Ok(status)
+}
+
+/// Reads a user's finished export as JSON.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The status of the export could not be loaded for any of the reasons outlined in [`ExportStatus::load`].
+/// * The user has no export that is ready, [`Error::ExportNotReady`].
+/// * The export could not be read.
+/// * The export is encrypted and could not be decrypted, see [`crate::encryption::open`].
+pub async fn read(user_id: &str) -> Result<String> {
+    match ExportStatus::current(user_id).await? {
+        Some(status) if status.state == ExportState::Ready => {
+            let path = ExportStatus::get_export_path(user_id);
+            let bytes = tokio::fs::read(&path).await.with_path(path)?;
+            Ok(String::from_utf8_lossy(&crate::encryption::open(&bytes)?).into_owned())
+        }
+        _ => Err(Error::ExportNotReady),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+
+    use super::{ExportState, ExportStatus};
+    use crate::error::Error;
+
+    #[test]
+    fn one_export_per_day() {
+        let now = Utc::now();
+        let status = |state, hours_ago| ExportStatus {
+            state,
+            requested: now - Duration::hours(hours_ago),
+            finished: None,
+        };
+        assert!(ExportStatus::check_can_start(None, now).is_ok());
+        assert!(matches!(
+            ExportStatus::check_can_start(Some(&status(ExportState::Running, 30)), now),
+            Err(Error::RateLimited)
+        ));
+        assert!(matches!(
+            ExportStatus::check_can_start(Some(&status(ExportState::Ready, 23)), now),
+            Err(Error::RateLimited)
+        ));
+        assert!(ExportStatus::check_can_start(Some(&status(ExportState::Ready, 24)), now).is_ok());
+        // Failed exports can be retried right away.
+        assert!(ExportStatus::check_can_start(Some(&status(ExportState::Failed, 1)), now).is_ok());
+    }
+}
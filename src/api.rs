@@ -1,17 +1,65 @@
-use std::mem;
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
+    mem,
+};
 
 use chrono::{DateTime, Utc};
 
 use crate::{
-    channel::{Channel, SignedMessage},
-    check_name_validity, check_permission,
-    error::Error,
-    hub::{Hub, HubMember},
-    new_id,
-    permission::{ChannelPermission, HubPermission, PermissionSetting},
-    Result, ID,
+    account_export::{self, ExportStatus},
+    change_history::{self, Change, ChangeHistory, ChangedField},
+    channel::{Channel, Message, SignedMessage},
+    check_permission,
+    content_policy::ContentPolicy,
+    descriptions::LongDescriptions,
+    drafts::{self, Draft, UserDrafts},
+    error::{Error, IoContext},
+    group_display::{self, GroupDisplay, HubGroupDisplay},
+    hub::{Hub, HubMember, MemberSort, NewChannel, NewHub, MAX_BULK_HUBS, MAX_MEMBERS_PER_REQUEST},
+    hub_activity::{self, HubActivity},
+    hub_changes::{self, HubChanges, HubDelta},
+    hub_deletion::{self, DeletionToken},
+    hub_images::{HubImages, ImageKind, StoredImage},
+    hub_preview::{HubPreview, PreviewSettings},
+    hub_storage::{self, Compaction, HubStorage},
+    identity_links::{self, LinkRequest, LinkResult},
+    invites::{
+        self, Invite, InviteIndex, InviteJoin, InviteJoins, InviteOptions, InviteStats,
+        ResolvedCode,
+    },
+    leaderboard::{self, HubLeaderboard, Leaderboard, LeaderboardPeriod},
+    locks::KeyedLocks,
+    member_map::{MemberChanges, MemberMap, MAX_MEMBER_MAP_ENTRIES},
+    membership_log::{self, MemberHistory, MembershipEvent, MembershipEventKind},
+    mentions::MentionableGroups,
+    message_edits::MessageHistory,
+    message_move::{self, MoveResult, MAX_MOVE_MESSAGES},
+    message_purge::{self, PurgeFilter, PurgeStatus},
+    nicknames::{HubNicknames, NicknamePolicy},
+    permission::{ChannelPermission, HubPermission, PermissionPreset, PermissionSetting},
+    quotas::{self, QuotaOverrides, QuotaStatus},
+    rendering::{self, RenderedMessage},
+    security_log::{self, SecurityLogPage, SecurityLogQuery},
+    server::{self, HubUpdateType, ServerNotification},
+    subscription_profiles::{self, SubscriptionProfile},
+    unfurl::MessagePreviews,
+    validation::{validate_description, validate_name, DescriptionKind, NameKind},
+    webhooks::{self, HubWebhooks, NewWebhook, Webhook, MAX_WEBHOOKS_PER_CHANNEL},
+    ChannelId, HubId, MessageId, Result, ID,
+};
+
+/// Response types of the HTTP API with a stable JSON schema.
+pub mod types;
+
+use types::{
+    BulkHubResult, BulkMemberResult, EffectivePermissions, ExpandedMessage, HubFields,
+    HubMemberInfo, MemberList, MemberSection, OwnedHub, SenderInfo,
 };
 
+/// Makes the hubs of an owner get created one at a time while names are checked for duplicates, see [`create_hub_from`].
+static CREATE_LOCKS: KeyedLocks<String> = KeyedLocks::new();
+
 /// Creates a hub, returning the ID of the new hub if successful.
 /// Also adds a default channel named "chat" that all users have access to by default.
 ///
@@ -22,21 +70,71 @@ use crate::{
 ///
 /// # Errors
 ///
+/// This function may return an error for any of the reasons outlined in [`create_hub_from`].
+pub async fn create_hub<S: Into<String>>(owner_id: S, name: S) -> Result<HubId> {
+    create_hub_from(
+        owner_id.into(),
+        NewHub {
+            name: name.into(),
+            description: None,
+        },
+    )
+    .await
+}
+
+/// Creates a hub with the name and description given by its owner, returning the ID of the new hub if successful.
+/// Also adds a default channel named "chat" that all users have access to by default.
+///
+/// # Arguments
+///
+/// * `owner_id` - ID of the user who should be marked as the owner/creator of the hub.
+/// * `new_hub` - Name and optional description of the new hub.
+///
+/// # Errors
+///
 /// This function may return an error for any of the reasons outlined in the following functions:
 ///
-/// * The user's data could not be saved for any of the reasons outlined in [`User::save`].
 /// * The hub failed to save for any of the reasons outlined in [`Hub::save`].
-/// * The given name failed to pass the checks for any of the reasons outlined in [`check_name_validity`].
+/// * The given name failed to pass the checks for any of the reasons outlined in [`validate_name`].
+/// * The given description is too long, see [`validate_description`].
+/// * Hub names must be unique per owner (see [`crate::config::NamesConfig::unique_hub_names_per_owner`]) and the owner already has a hub with the same name, ignoring case, [`Error::NameTaken`].
 /// * The default channel could not be created for any of the reaons outlined in [`Hub::new_channel`].
-pub async fn create_hub<S: Into<String>>(owner_id: S, name: S) -> Result<ID> {
-    let name: String = name.into();
-    let owner_id: String = owner_id.into();
-    check_name_validity(&name)?;
-    let mut id = new_id();
+/// * The user already owns as many hubs as they can, see [`quotas::charge_hub`].
+pub async fn create_hub_from(owner_id: String, new_hub: NewHub) -> Result<HubId> {
+    let name = validate_name(NameKind::Hub, &new_hub.name)?;
+    let description = new_hub.description.unwrap_or_default();
+    validate_description(DescriptionKind::Short, &description)?;
+    let _guard = if crate::validation::unique_hub_names_per_owner() {
+        let guard = CREATE_LOCKS.lock(owner_id.clone()).await;
+        let lowercase = name.to_lowercase();
+        for hub_id in crate::maintenance::list_hubs().await? {
+            if let Ok(hub) = Hub::load(hub_id).await {
+                if hub.owner == owner_id && hub.name.to_lowercase() == lowercase {
+                    return Err(Error::NameTaken);
+                }
+            }
+        }
+        Some(guard)
+    } else {
+        None
+    };
+    quotas::charge_hub(&owner_id).await?;
+    let result = create_hub_charged(owner_id.clone(), name, description).await;
+    // The hub is the source of truth, if this fails the quota is corrected by the next reconciliation.
+    if result.is_err() {
+        let _ = quotas::release_hub(&owner_id).await;
+    }
+    result
+}
+
+/// Creates a hub for [`create_hub`] after the new hub has been counted against the owner's quota.
+async fn create_hub_charged(owner_id: String, name: String, description: String) -> Result<HubId> {
+    let mut id = HubId::random();
     while Hub::load(id).await.is_ok() {
-        id = new_id();
+        id = HubId::random();
     }
     let mut new_hub = Hub::new(name, id, owner_id.clone());
+    new_hub.description = description;
     let channel_id = new_hub.new_channel(&owner_id, "chat".to_string()).await?;
     if let Some(group) = new_hub.groups.get_mut(&new_hub.default_group) {
         group.set_channel_permission(
@@ -51,6 +149,12 @@ pub async fn create_hub<S: Into<String>>(owner_id: S, name: S) -> Result<ID> {
         );
     }
     new_hub.save().await?;
+    membership_log::record(
+        id,
+        MembershipEvent::new(owner_id.clone(), MembershipEventKind::Joined),
+    )
+    .await?;
+    crate::audit!(user = %owner_id, hub = %id, "Created hub.");
     Ok(id)
 }
 
@@ -67,32 +171,189 @@ pub async fn create_hub<S: Into<String>>(owner_id: S, name: S) -> Result<ID> {
 ///
 /// * The user is not in the hub.
 /// * The hub failed to load for any of the reasons outlined in [`Hub::load`].
-pub async fn get_hub(user_id: &str, hub_id: ID) -> Result<Hub> {
+pub async fn get_hub(user_id: &str, hub_id: HubId) -> Result<Hub> {
     let hub = Hub::load(hub_id).await?;
     hub.strip(user_id)
 }
 
-/// Deletes a hub.
+/// Gets some of the fields of a hub together with its version, so that clients that only need a few fields (for example the name and channels) do not have to download the whole hub.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user getting the hub.
+/// * `hub_id` - ID of the hub to get.
+/// * `fields` - Comma separated names of the fields to include, all fields if `None`.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The hub could not be loaded or stripped for any of the reasons outlined in [`get_hub`].
+/// * A requested field does not exist, see [`HubFields::select`].
+pub async fn get_hub_fields(
+    user_id: &str,
+    hub_id: HubId,
+    fields: Option<&str>,
+) -> Result<HubFields> {
+    let hub = get_hub(user_id, hub_id).await?;
+    HubFields::select(&hub, hub.version, fields)
+}
+
+/// Gets a hub without stripping anything from it, used by server admins to debug hubs.
+///
+/// # Arguments
+///
+/// * `actor_id` - ID of the user getting the hub.
+/// * `hub_id` - ID of the hub to get.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user getting the hub is not a server admin.
+/// * The hub failed to load for any of the reasons outlined in [`Hub::load`].
+pub async fn get_raw_hub(actor_id: &str, hub_id: HubId) -> Result<Hub> {
+    if !quotas::is_admin(actor_id) {
+        return Err(Error::NotAdmin);
+    }
+    let hub = Hub::load(hub_id).await?;
+    crate::audit!(actor = %actor_id, hub = %hub_id, kind = "admin", "Read an unstripped hub.");
+    Ok(hub)
+}
+
+/// Gets the changes made to a hub since the given version, used by clients to catch up after missing [`crate::server::ServerNotification::HubUpdated`] notifications.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user requesting the changes.
+/// * `hub_id` - ID of the hub to get the changes of.
+/// * `since_version` - Version of the hub that the user already has.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The hub failed to load for any of the reasons outlined in [`Hub::load`].
+/// * The changes failed to load for any of the reasons outlined in [`HubChanges::load`].
+pub async fn get_hub_delta(user_id: &str, hub_id: HubId, since_version: u64) -> Result<HubDelta> {
+    let hub = Hub::load(hub_id).await?;
+    hub.get_member(user_id)?;
+    Ok(HubChanges::load(hub_id)
+        .await?
+        .since(hub.version, since_version))
+}
+
+/// Creates the token a user needs to delete a hub (see [`delete_hub`]), which can be used once in the next [`hub_deletion::DELETION_TOKEN_MINUTES`] minutes.
+/// The user's WebSocket connections are told about the request, so a request the user did not make is noticed on their other devices.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The user does not have permission to delete the hub.
+pub async fn request_hub_deletion(user_id: &str, hub_id: HubId) -> Result<DeletionToken> {
+    let hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(user_id)?;
+    check_permission!(member, HubPermission::All, hub);
+    let token = hub_deletion::issue(hub_id, user_id, Utc::now());
+    server::publish(ServerNotification::HubDeletionRequested(
+        user_id.to_string(),
+        hub_id,
+        token.expires,
+    ))
+    .await;
+    crate::audit!(user = %user_id, hub = %hub_id, "Requested hub deletion.");
+    Ok(token)
+}
+
+/// Deletes a hub. The hub's channel data is removed by the [`crate::server::MessageServer`] once it has closed the channels' search indexes.
 ///
 /// # Arguments
 ///
 /// * `user_id` - ID of the user to check for permission to perform the operation.
 /// * `hub_id` - ID of the hub to delete.
+/// * `confirmation_token` - Token the user got from [`request_hub_deletion`], it is used up whether or not the hub is deleted.
 ///
 /// # Errors
 ///
 /// This function may return an error for any of the following reasons:
 ///
+/// * The token is wrong, expired or was already used, see [`hub_deletion::redeem`].
 /// * The user is not in the hub.
 /// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
 /// * The user does not have permission to delete the hub.
-/// * The hub's data files could not be deleted.
-pub async fn delete_hub(user_id: &str, hub_id: ID) -> Result {
-    let hub = Hub::load(hub_id).await?;
+/// * The hub's images could not be deleted for any of the reasons outlined by [`HubImages::remove`].
+/// * The hub's preview settings could not be deleted for any of the reasons outlined by [`PreviewSettings::remove`].
+/// * The hub's mentionable groups could not be deleted for any of the reasons outlined by [`MentionableGroups::remove`].
+/// * The hub's group display settings could not be deleted for any of the reasons outlined by [`HubGroupDisplay::remove`].
+/// * The hub's long descriptions could not be deleted for any of the reasons outlined by [`LongDescriptions::remove`].
+/// * The hub's nicknames could not be deleted for any of the reasons outlined by [`HubNicknames::remove`].
+/// * The hub's content policy could not be deleted for any of the reasons outlined by [`ContentPolicy::remove`].
+/// * The hub's activity could not be deleted for any of the reasons outlined by [`HubActivity::remove`].
+/// * The hub's leaderboard could not be deleted for any of the reasons outlined by [`HubLeaderboard::remove`].
+/// * The hub's invites could not be deleted for any of the reasons outlined by [`invites::remove_hub`].
+/// * The hub's membership log could not be deleted for any of the reasons outlined by [`membership_log::remove`].
+/// * The hub's member changes could not be deleted for any of the reasons outlined by [`MemberChanges::remove`].
+/// * The hub's change history could not be deleted for any of the reasons outlined by [`ChangeHistory::remove`].
+/// * The hub's webhooks could not be deleted for any of the reasons outlined by [`HubWebhooks::remove`].
+/// * The hub's info file could not be deleted.
+/// * The change could not be recorded for any of the reasons outlined by [`hub_changes::record`].
+///
+/// The owner's quota is updated after the hub is deleted, if that fails it is corrected the next time it is reconciled (see [`quotas::reconcile`]).
+pub async fn delete_hub(user_id: &str, hub_id: HubId, confirmation_token: &str) -> Result {
+    if let Err(err) = hub_deletion::redeem(hub_id, user_id, confirmation_token, Utc::now()) {
+        crate::audit!(
+            user = %user_id,
+            hub = %hub_id,
+            kind = "token_rejected",
+            "Rejected a hub deletion token."
+        );
+        return Err(err);
+    }
+    let lock = hub_changes::lock(hub_id).await;
+    let mut hub = Hub::load(hub_id).await?;
     let member = hub.get_member(user_id)?;
     check_permission!(member, HubPermission::All, hub);
-    tokio::fs::remove_file(hub.get_info_path()).await?;
-    tokio::fs::remove_dir_all(hub.get_data_path()).await?;
+    // The info file is removed last, so a hub whose side data could not all be removed still exists and its deletion can be retried with a new token.
+    HubImages::remove(hub_id).await?;
+    PreviewSettings::remove(hub_id).await?;
+    MentionableGroups::remove(hub_id).await?;
+    HubGroupDisplay::remove(hub_id).await?;
+    LongDescriptions::remove(hub_id).await?;
+    HubNicknames::remove(hub_id).await?;
+    ContentPolicy::remove(hub_id).await?;
+    hub_activity::forget(hub_id);
+    HubActivity::remove(hub_id).await?;
+    leaderboard::forget(hub_id);
+    HubLeaderboard::remove(hub_id).await?;
+    hub_storage::forget(hub_id);
+    invites::remove_hub(hub_id).await?;
+    membership_log::remove(hub_id).await?;
+    MemberChanges::remove(hub_id).await?;
+    ChangeHistory::remove(hub_id).await?;
+    HubWebhooks::remove(hub_id).await?;
+    let info_path = hub.get_info_path();
+    tokio::fs::remove_file(&info_path)
+        .await
+        .with_path(info_path)?;
+    if let Err(err) = quotas::release_hub(&hub.owner).await {
+        warn!(
+            "Could not release the quota of the owner of deleted hub {}: {}",
+            hub_id,
+            err.chain()
+        );
+    }
+    // The deletion is the last change of the hub, it has no file left to be saved to.
+    hub.version += 1;
+    hub_changes::record(&lock, &hub, HubUpdateType::HubDeleted).await?;
+    let changes_path = HubChanges::get_path(hub_id);
+    tokio::fs::remove_file(&changes_path)
+        .await
+        .with_path(changes_path)?;
+    crate::audit!(user = %user_id, hub = %hub_id, "Deleted hub.");
     Ok(())
 }
 
@@ -103,6 +364,7 @@ pub async fn delete_hub(user_id: &str, hub_id: ID) -> Result {
 /// * `user_id` - ID of the user to check for permission to perform the operation.
 /// * `hub_id` - The ID of the hub whose name is to be changed.
 /// * `new_name` - The new name to be given to the hub.
+/// * `expected_version` - Version the hub must be at for the change to be made (from an `If-Match` header), `None` to make it regardless.
 ///
 /// # Errors
 ///
@@ -110,170 +372,2100 @@ pub async fn delete_hub(user_id: &str, hub_id: ID) -> Result {
 ///
 /// * THe user is not in the hub.
 /// * The user does not have permission to rename the hub.
-/// * The given name failed to pass the checks for any of the reasons outlined in [`check_name_validity`].
+/// * The given name failed to pass the checks for any of the reasons outlined in [`validate_name`].
+/// * The hub is not at `expected_version`, see [`Hub::check_version`].
 /// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
 /// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The change could not be recorded for any of the reasons outlined by [`hub_changes::record`].
+/// * The change could not be added to the hub's change history for any of the reasons outlined by [`change_history::record`].
 pub async fn rename_hub<S: Into<String> + Clone>(
     user_id: &str,
-    hub_id: ID,
-    new_name: S,
-) -> Result<String> {
-    let new_name: String = new_name.into();
-    check_name_validity(&new_name)?;
-    let mut hub = Hub::load(hub_id).await?;
+    hub_id: HubId,
+    new_name: S,
+    expected_version: Option<u64>,
+) -> Result<String> {
+    let new_name = validate_name(NameKind::Hub, &new_name.into())?;
+    let lock = hub_changes::lock(hub_id).await;
+    let mut hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(user_id)?;
+    check_permission!(member, HubPermission::Administrate, hub);
+    hub.check_version(expected_version)?;
+    let old_name = mem::replace(&mut hub.name, new_name.clone());
+    hub.save().await?;
+    hub_changes::record(&lock, &hub, HubUpdateType::HubRenamed).await?;
+    change_history::record(
+        hub_id,
+        Change::new(
+            ChangedField::Name,
+            None,
+            old_name.clone(),
+            new_name,
+            user_id,
+        ),
+    )
+    .await?;
+    Ok(old_name)
+}
+
+/// Changes the description of a hub.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user to check for permission to perform the operation.
+/// * `hub_id` - The ID of the hub whose name is to be changed.
+/// * `new_description` - The content for the hub's description
+/// * `expected_version` - Version the hub must be at for the change to be made (from an `If-Match` header), `None` to make it regardless.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * THe user is not in the hub.
+/// * The user does not have permission to change the hub's description.
+/// * The description is longer than allowed, see [`validate_description`].
+/// * The hub is not at `expected_version`, see [`Hub::check_version`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The change could not be recorded for any of the reasons outlined by [`hub_changes::record`].
+/// * The change could not be added to the hub's change history for any of the reasons outlined by [`change_history::record`].
+pub async fn change_hub_description<S: Into<String> + Clone>(
+    user_id: &str,
+    hub_id: HubId,
+    new_description: S,
+    expected_version: Option<u64>,
+) -> Result<String> {
+    let new_description: String = new_description.into();
+    validate_description(DescriptionKind::Short, &new_description)?;
+    let lock = hub_changes::lock(hub_id).await;
+    let mut hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(user_id)?;
+    check_permission!(member, HubPermission::Administrate, hub);
+    hub.check_version(expected_version)?;
+    let old_name = mem::replace(&mut hub.description, new_description.clone());
+    hub.save().await?;
+    hub_changes::record(&lock, &hub, HubUpdateType::HubDescriptionUpdated).await?;
+    change_history::record(
+        hub_id,
+        Change::new(
+            ChangedField::Description,
+            None,
+            old_name.clone(),
+            new_description,
+            user_id,
+        ),
+    )
+    .await?;
+    Ok(old_name)
+}
+
+/// Changes the long (markdown) description of a hub, see [`LongDescriptions`].
+/// Returns the previous long description if successful.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user to check for permission to perform the operation.
+/// * `hub_id` - The ID of the hub whose description is to be changed.
+/// * `new_description` - The new long description, empty to remove it.
+/// * `expected_version` - Version the hub must be at for the change to be made (from an `If-Match` header), `None` to make it regardless.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The description is longer than allowed, see [`validate_description`].
+/// * The user is not in the hub.
+/// * The user does not have permission to administrate the hub.
+/// * The hub is not at `expected_version`, see [`Hub::check_version`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The descriptions could not be loaded or saved for any of the reasons outlined by [`LongDescriptions::load`] and [`LongDescriptions::save`].
+/// * The change could not be recorded for any of the reasons outlined by [`hub_changes::record`].
+/// * The change could not be added to the hub's change history for any of the reasons outlined by [`change_history::record`].
+pub async fn change_hub_long_description(
+    user_id: &str,
+    hub_id: HubId,
+    new_description: String,
+    expected_version: Option<u64>,
+) -> Result<String> {
+    validate_description(DescriptionKind::Long, &new_description)?;
+    let lock = hub_changes::lock(hub_id).await;
+    let mut hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(user_id)?;
+    check_permission!(member, HubPermission::Administrate, hub);
+    hub.check_version(expected_version)?;
+    let mut descriptions = LongDescriptions::load(hub_id).await?;
+    let old_description = mem::replace(&mut descriptions.hub, new_description.clone());
+    descriptions.save(hub_id).await?;
+    hub.save().await?;
+    hub_changes::record(&lock, &hub, HubUpdateType::HubDescriptionUpdated).await?;
+    change_history::record(
+        hub_id,
+        Change::new(
+            ChangedField::LongDescription,
+            None,
+            old_description.clone(),
+            new_description,
+            user_id,
+        ),
+    )
+    .await?;
+    Ok(old_description)
+}
+
+/// Changes the icon or banner of a hub, returning the new version of the image.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user to check for permission to perform the operation.
+/// * `hub_id` - The ID of the hub whose image is to be changed.
+/// * `kind` - Which of the hub's images to change.
+/// * `image` - The new image.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The user does not have permission to administrate the hub.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The image could not be stored for any of the reasons outlined by [`HubImages::set`].
+/// * The change could not be recorded for any of the reasons outlined by [`hub_changes::record`].
+pub async fn set_hub_image(
+    user_id: &str,
+    hub_id: HubId,
+    kind: ImageKind,
+    image: &[u8],
+) -> Result<u64> {
+    let lock = hub_changes::lock(hub_id).await;
+    let mut hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(user_id)?;
+    check_permission!(member, HubPermission::Administrate, hub);
+    let version = HubImages::set(hub_id, kind, image).await?;
+    hub.save().await?;
+    let update = match kind {
+        ImageKind::Icon => HubUpdateType::HubIconUpdated,
+        ImageKind::Banner => HubUpdateType::HubBannerUpdated,
+    };
+    hub_changes::record(&lock, &hub, update).await?;
+    Ok(version)
+}
+
+/// Gets the icon or banner of a hub, returning its information and contents.
+/// Hub images are public so that they can be shown to users that have not joined the hub yet.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The image could not be read for any of the reasons outlined by [`HubImages::read`].
+pub async fn get_hub_image(hub_id: HubId, kind: ImageKind) -> Result<(StoredImage, Vec<u8>)> {
+    Hub::load(hub_id).await?;
+    HubImages::read(hub_id, kind).await
+}
+
+/// Enables or disables the public preview of a hub.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user to check for permission to perform the operation.
+/// * `hub_id` - The ID of the hub whose preview is to be changed.
+/// * `enabled` - Whether users that are not in the hub can preview it.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The user does not have permission to administrate the hub.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The settings could not be saved for any of the reasons outlined by [`PreviewSettings::save`].
+/// * The change could not be recorded for any of the reasons outlined by [`hub_changes::record`].
+pub async fn set_hub_preview_enabled(user_id: &str, hub_id: HubId, enabled: bool) -> Result {
+    let lock = hub_changes::lock(hub_id).await;
+    let mut hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(user_id)?;
+    check_permission!(member, HubPermission::Administrate, hub);
+    PreviewSettings { enabled }.save(hub_id).await?;
+    hub.save().await?;
+    hub_changes::record(&lock, &hub, HubUpdateType::HubPreviewUpdated).await?;
+    crate::audit!(
+        actor = %user_id,
+        hub = %hub_id,
+        enabled = enabled,
+        "Changed hub preview."
+    );
+    Ok(())
+}
+
+/// Sets whether a permission group can be mentioned, mentioning it notifies all of its members (see [`crate::mentions`]).
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user to check for permission to perform the operation.
+/// * `hub_id` - The ID of the hub the group is in.
+/// * `group_id` - The ID of the group.
+/// * `mentionable` - Whether the group can be mentioned.
+/// * `expected_version` - Version the hub must be at for the change to be made (from an `If-Match` header), `None` to make it regardless.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The user does not have permission to administrate the hub.
+/// * The group does not exist.
+/// * The hub is not at `expected_version`, see [`Hub::check_version`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The mentionable groups could not be loaded or saved for any of the reasons outlined by [`MentionableGroups::load`] and [`MentionableGroups::save`].
+/// * The change could not be recorded for any of the reasons outlined by [`hub_changes::record`].
+pub async fn set_group_mentionable(
+    user_id: &str,
+    hub_id: HubId,
+    group_id: ID,
+    mentionable: bool,
+    expected_version: Option<u64>,
+) -> Result {
+    let lock = hub_changes::lock(hub_id).await;
+    let mut hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(user_id)?;
+    check_permission!(member, HubPermission::Administrate, hub);
+    hub.check_version(expected_version)?;
+    if !hub.groups.contains_key(&group_id) {
+        return Err(Error::GroupNotFound);
+    }
+    let mut groups = MentionableGroups::load(hub_id).await?;
+    if mentionable {
+        groups.groups.insert(group_id);
+    } else {
+        groups.groups.remove(&group_id);
+    }
+    groups.save(hub_id).await?;
+    hub.save().await?;
+    hub_changes::record(
+        &lock,
+        &hub,
+        HubUpdateType::GroupMentionableChanged(group_id),
+    )
+    .await?;
+    crate::audit!(
+        actor = %user_id,
+        hub = %hub_id,
+        group = %group_id,
+        mentionable = mentionable,
+        "Changed group mentionable setting."
+    );
+    Ok(())
+}
+
+/// Gets the display settings of every permission group of a hub, ordered by position.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The settings could not be loaded for any of the reasons outlined by [`HubGroupDisplay::load`].
+pub async fn get_group_display(user_id: &str, hub_id: HubId) -> Result<Vec<GroupDisplay>> {
+    let hub = Hub::load(hub_id).await?;
+    hub.get_member(user_id)?;
+    let mut display = HubGroupDisplay::load(hub_id).await?;
+    display.normalize(&hub);
+    Ok(display.groups)
+}
+
+/// Sets the color of a permission group and whether its members are listed separately, returns the group's new display settings.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user to check for permission to perform the operation.
+/// * `hub_id` - The ID of the hub the group is in.
+/// * `group_id` - The ID of the group.
+/// * `color` - Color of the group as `0xRRGGBB`, `None` to remove it.
+/// * `hoist` - Whether members of the group are listed separately.
+/// * `expected_version` - Version the hub must be at for the change to be made (from an `If-Match` header), `None` to make it regardless.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The user does not have permission to administrate the hub.
+/// * The group does not exist.
+/// * The color is not valid, see [`group_display::validate_color`].
+/// * The hub is not at `expected_version`, see [`Hub::check_version`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The settings could not be loaded or saved for any of the reasons outlined by [`HubGroupDisplay::load`] and [`HubGroupDisplay::save`].
+/// * The change could not be recorded for any of the reasons outlined by [`hub_changes::record`].
+pub async fn set_group_display(
+    user_id: &str,
+    hub_id: HubId,
+    group_id: ID,
+    color: Option<u32>,
+    hoist: bool,
+    expected_version: Option<u64>,
+) -> Result<GroupDisplay> {
+    group_display::validate_color(color)?;
+    let lock = hub_changes::lock(hub_id).await;
+    let mut hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(user_id)?;
+    check_permission!(member, HubPermission::Administrate, hub);
+    hub.check_version(expected_version)?;
+    if !hub.groups.contains_key(&group_id) {
+        return Err(Error::GroupNotFound);
+    }
+    let mut display = HubGroupDisplay::load(hub_id).await?;
+    display.normalize(&hub);
+    let group = display.get_mut(group_id)?;
+    group.color = color;
+    group.hoist = hoist;
+    let group = *group;
+    display.save(hub_id).await?;
+    hub.save().await?;
+    hub_changes::record(&lock, &hub, HubUpdateType::GroupDisplayChanged(group_id)).await?;
+    crate::audit!(
+        actor = %user_id,
+        hub = %hub_id,
+        group = %group_id,
+        ?color,
+        hoist = hoist,
+        "Changed group display."
+    );
+    Ok(group)
+}
+
+/// Moves a permission group to a position in the hub's list of groups, the groups after it move down by one. Returns the display settings of every group, ordered by position.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user to check for permission to perform the operation.
+/// * `hub_id` - The ID of the hub the group is in.
+/// * `group_id` - The ID of the group.
+/// * `position` - New position of the group, `0` is the highest and positions past the end move the group to the end.
+/// * `expected_version` - Version the hub must be at for the change to be made (from an `If-Match` header), `None` to make it regardless.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The user does not have permission to administrate the hub.
+/// * The group does not exist.
+/// * The hub is not at `expected_version`, see [`Hub::check_version`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The settings could not be loaded or saved for any of the reasons outlined by [`HubGroupDisplay::load`] and [`HubGroupDisplay::save`].
+/// * The change could not be recorded for any of the reasons outlined by [`hub_changes::record`].
+pub async fn set_group_position(
+    user_id: &str,
+    hub_id: HubId,
+    group_id: ID,
+    position: u32,
+    expected_version: Option<u64>,
+) -> Result<Vec<GroupDisplay>> {
+    // Positions are renumbered while the hub is locked, so two moves can not give groups the same position.
+    let lock = hub_changes::lock(hub_id).await;
+    let mut hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(user_id)?;
+    check_permission!(member, HubPermission::Administrate, hub);
+    hub.check_version(expected_version)?;
+    if !hub.groups.contains_key(&group_id) {
+        return Err(Error::GroupNotFound);
+    }
+    let mut display = HubGroupDisplay::load(hub_id).await?;
+    display.normalize(&hub);
+    display.set_position(group_id, position)?;
+    display.save(hub_id).await?;
+    hub.save().await?;
+    hub_changes::record(&lock, &hub, HubUpdateType::GroupDisplayChanged(group_id)).await?;
+    crate::audit!(
+        actor = %user_id,
+        hub = %hub_id,
+        group = %group_id,
+        position = position,
+        "Moved group."
+    );
+    Ok(display.groups)
+}
+
+/// Replaces the hub permission settings of a permission group with the ones of a preset, channel overrides of the group are kept.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user to check for permission to perform the operation.
+/// * `hub_id` - The ID of the hub the group is in.
+/// * `group_id` - The ID of the group.
+/// * `preset` - The preset whose permissions the group gets.
+/// * `expected_version` - Version the hub must be at for the change to be made (from an `If-Match` header), `None` to make it regardless.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The user does not have permission to administrate the hub.
+/// * The group does not exist.
+/// * The hub is not at `expected_version`, see [`Hub::check_version`].
+/// * The hub could not be loaded or saved for any of the reasons outlined by [`Hub::load`] and [`Hub::save`].
+/// * The change could not be recorded for any of the reasons outlined by [`hub_changes::record`].
+pub async fn apply_group_preset(
+    user_id: &str,
+    hub_id: HubId,
+    group_id: ID,
+    preset: PermissionPreset,
+    expected_version: Option<u64>,
+) -> Result {
+    let lock = hub_changes::lock(hub_id).await;
+    let mut hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(user_id)?;
+    check_permission!(member, HubPermission::Administrate, hub);
+    hub.check_version(expected_version)?;
+    hub.groups
+        .get_mut(&group_id)
+        .ok_or(Error::GroupNotFound)?
+        .hub_permissions = preset.hub_permissions();
+    hub.save().await?;
+    hub_changes::record(
+        &lock,
+        &hub,
+        HubUpdateType::GroupPermissionsChanged(group_id),
+    )
+    .await?;
+    crate::audit!(
+        actor = %user_id,
+        hub = %hub_id,
+        group = %group_id,
+        preset = %preset,
+        "Applied permission preset."
+    );
+    Ok(())
+}
+
+/// Gets the permissions a user has in a hub and in each of its channels they can read.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+pub async fn get_effective_permissions(
+    user_id: &str,
+    hub_id: HubId,
+) -> Result<EffectivePermissions> {
+    let hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(user_id)?;
+    let hub_permissions = HubPermission::VARIANTS
+        .iter()
+        .copied()
+        .filter(|permission| {
+            *permission != HubPermission::ReadHistory
+                && has_hub_permission(member, *permission, &hub)
+        })
+        .collect();
+    let channels = hub
+        .channels
+        .keys()
+        .filter(|channel_id| {
+            member.has_channel_permission(**channel_id, ChannelPermission::Read, &hub)
+        })
+        .map(|channel_id| {
+            let permissions = ChannelPermission::VARIANTS
+                .iter()
+                .copied()
+                .filter(|permission| match permission {
+                    // Granted unless it is denied, see `Hub::history_start`.
+                    ChannelPermission::ReadHistory => {
+                        hub.history_start(member, *channel_id).is_none()
+                    }
+                    _ => member.has_channel_permission(*channel_id, *permission, &hub),
+                })
+                .collect();
+            (*channel_id, permissions)
+        })
+        .collect();
+    Ok(EffectivePermissions {
+        hub: hub_permissions,
+        channels,
+    })
+}
+
+/// Gets the nicknames of the members of a hub and the hub's nickname policy.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The nicknames could not be loaded for any of the reasons outlined by [`HubNicknames::load`].
+pub async fn get_nicknames(user_id: &str, hub_id: HubId) -> Result<HubNicknames> {
+    let hub = Hub::load(hub_id).await?;
+    hub.get_member(user_id)?;
+    let mut nicknames = HubNicknames::load(hub_id).await?;
+    nicknames
+        .nicknames
+        .retain(|member, _| hub.members.contains_key(member));
+    Ok(nicknames)
+}
+
+/// Gets a page of the map of member IDs to nicknames of a hub.
+/// Only the members that changed since `since_version` are included if those changes are still known, otherwise the map of every member is returned.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user requesting the map, must be in the hub.
+/// * `hub_id` - The ID of the hub.
+/// * `since_version` - Version of the hub the client last synced its map at, `None` for the full map.
+/// * `after` - Continuation token of the previous page, `None` for the first page.
+/// * `limit` - Maximum number of members in the page, at most [`MAX_MEMBER_MAP_ENTRIES`].
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The member changes could not be loaded for any of the reasons outlined by [`MemberChanges::load`].
+/// * The nicknames could not be loaded for any of the reasons outlined by [`HubNicknames::load`].
+pub async fn get_member_map(
+    user_id: &str,
+    hub_id: HubId,
+    since_version: Option<u64>,
+    after: Option<&str>,
+    limit: usize,
+) -> Result<MemberMap> {
+    let hub = Hub::load(hub_id).await?;
+    hub.get_member(user_id)?;
+    let version = hub.version;
+    let changed = match since_version {
+        Some(since_version) => MemberChanges::load(hub_id)
+            .await?
+            .since(since_version, version),
+        None => None,
+    };
+    let nicknames = HubNicknames::load(hub_id).await?;
+    Ok(MemberMap::new(
+        &hub,
+        &nicknames,
+        version,
+        changed,
+        after,
+        limit.min(MAX_MEMBER_MAP_ENTRIES),
+    ))
+}
+
+/// Changes the nickname a user has in a hub, returning the previous nickname.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user whose nickname is to be changed.
+/// * `hub_id` - The ID of the hub the nickname is used in.
+/// * `nickname` - The new nickname, empty to remove it.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The hub does not allow nicknames and the user does not have permission to administrate the hub.
+/// * The nickname is not valid or is taken for any of the reasons outlined in [`HubNicknames::set`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The nicknames could not be loaded or saved for any of the reasons outlined by [`HubNicknames::load`] and [`HubNicknames::save`].
+/// * The change could not be recorded for any of the reasons outlined by [`hub_changes::record`].
+pub async fn change_nickname(user_id: &str, hub_id: HubId, nickname: &str) -> Result<String> {
+    let lock = hub_changes::lock(hub_id).await;
+    let mut hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(user_id)?;
+    let mut nicknames = HubNicknames::load(hub_id).await?;
+    if !nicknames.policy.allow {
+        check_permission!(member, HubPermission::Administrate, hub);
+    }
+    let (previous, new) = nicknames.set(&hub, user_id, nickname)?;
+    if previous != new {
+        nicknames.save(hub_id).await?;
+        hub.save().await?;
+        hub_changes::record(
+            &lock,
+            &hub,
+            HubUpdateType::MemberNicknameChanged(user_id.to_string(), previous.clone(), new),
+        )
+        .await?;
+    }
+    Ok(previous.unwrap_or_default())
+}
+
+/// Sets the draft a user has in a channel, empty content removes it. The user's WebSocket connections are sent the new draft.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user whose draft it is.
+/// * `hub_id` - ID of the hub the channel is in.
+/// * `channel_id` - ID of the channel the draft is for.
+/// * `content` - Text of the draft, empty to remove it.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user can not read the channel for any of the reasons outlined by [`Hub::get_channel`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The draft is too big or could not be saved for any of the reasons outlined by [`drafts::set`].
+pub async fn set_draft(
+    user_id: &str,
+    hub_id: HubId,
+    channel_id: ChannelId,
+    content: &str,
+) -> Result {
+    Hub::load(hub_id).await?.get_channel(user_id, channel_id)?;
+    drafts::set(user_id, hub_id, channel_id, content).await
+}
+
+/// Gets the drafts of a user in every channel, most recently changed first.
+///
+/// # Errors
+///
+/// This function returns an error if the drafts could not be loaded, see [`UserDrafts::load`].
+pub async fn get_drafts(user_id: &str) -> Result<Vec<Draft>> {
+    Ok(UserDrafts::load(user_id).await?.drafts)
+}
+
+/// Replaces the subscription profile of a user, the hubs and channels their clients subscribe to with [`crate::websocket::ClientMessage::ResumeProfile`]. Whether the user can read them is checked when the profile is resumed.
+///
+/// # Errors
+///
+/// This function returns an error if the profile is too big or could not be saved, see [`subscription_profiles::set`].
+pub async fn set_subscription_profile(user_id: &str, profile: &SubscriptionProfile) -> Result {
+    subscription_profiles::set(user_id, profile).await
+}
+
+/// Gets the subscription profile of a user.
+///
+/// # Errors
+///
+/// This function returns an error if the profile could not be loaded, see [`SubscriptionProfile::load`].
+pub async fn get_subscription_profile(user_id: &str) -> Result<SubscriptionProfile> {
+    SubscriptionProfile::load(user_id).await
+}
+
+/// Changes the rules for the nicknames members of a hub can have, returning the previous rules.
+/// Nicknames that were set before the change are kept even if they do not follow the new rules.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user to check for permission to perform the operation.
+/// * `hub_id` - The ID of the hub whose nickname policy is to be changed.
+/// * `policy` - The new nickname policy.
+/// * `expected_version` - Version the hub must be at for the change to be made (from an `If-Match` header), `None` to make it regardless.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The user does not have permission to administrate the hub.
+/// * The hub is not at `expected_version`, see [`Hub::check_version`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The nicknames could not be loaded or saved for any of the reasons outlined by [`HubNicknames::load`] and [`HubNicknames::save`].
+/// * The change could not be recorded for any of the reasons outlined by [`hub_changes::record`].
+pub async fn set_nickname_policy(
+    user_id: &str,
+    hub_id: HubId,
+    policy: NicknamePolicy,
+    expected_version: Option<u64>,
+) -> Result<NicknamePolicy> {
+    let lock = hub_changes::lock(hub_id).await;
+    let mut hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(user_id)?;
+    check_permission!(member, HubPermission::Administrate, hub);
+    hub.check_version(expected_version)?;
+    let mut nicknames = HubNicknames::load(hub_id).await?;
+    let previous = mem::replace(&mut nicknames.policy, policy);
+    nicknames.save(hub_id).await?;
+    hub.save().await?;
+    hub_changes::record(&lock, &hub, HubUpdateType::NicknamePolicyChanged).await?;
+    crate::audit!(
+        actor = %user_id,
+        hub = %hub_id,
+        allow = policy.allow,
+        unique = policy.unique,
+        max_length = policy.max_length,
+        "Changed nickname policy."
+    );
+    Ok(previous)
+}
+
+/// Gets the restrictions a hub puts on the content of its messages.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The policy could not be loaded for any of the reasons outlined by [`ContentPolicy::load`].
+pub async fn get_content_policy(user_id: &str, hub_id: HubId) -> Result<ContentPolicy> {
+    let hub = Hub::load(hub_id).await?;
+    hub.get_member(user_id)?;
+    ContentPolicy::load(hub_id).await
+}
+
+/// Changes the restrictions a hub puts on the content of its messages, returning the previous policy.
+/// Messages that were sent before the change are kept even if they do not follow the new policy.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user to check for permission to perform the operation.
+/// * `hub_id` - The ID of the hub whose content policy is to be changed.
+/// * `policy` - The new content policy.
+/// * `expected_version` - Version the hub must be at for the change to be made (from an `If-Match` header), `None` to make it regardless.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The user does not have permission to administrate the hub.
+/// * The policy can not be applied for any of the reasons outlined by [`ContentPolicy::validate`].
+/// * The hub is not at `expected_version`, see [`Hub::check_version`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The policy could not be loaded or saved for any of the reasons outlined by [`ContentPolicy::load`] and [`ContentPolicy::save`].
+/// * The change could not be recorded for any of the reasons outlined by [`hub_changes::record`].
+pub async fn set_content_policy(
+    user_id: &str,
+    hub_id: HubId,
+    policy: ContentPolicy,
+    expected_version: Option<u64>,
+) -> Result<ContentPolicy> {
+    policy.validate()?;
+    let lock = hub_changes::lock(hub_id).await;
+    let mut hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(user_id)?;
+    check_permission!(member, HubPermission::Administrate, hub);
+    hub.check_version(expected_version)?;
+    let previous = ContentPolicy::load(hub_id).await?;
+    policy.save(hub_id).await?;
+    hub.save().await?;
+    hub_changes::record(&lock, &hub, HubUpdateType::ContentPolicyChanged).await?;
+    crate::audit!(
+        actor = %user_id,
+        hub = %hub_id,
+        max_message_length = ?policy.max_message_length,
+        allow_link_previews = policy.allow_link_previews,
+        "Changed content policy."
+    );
+    Ok(previous)
+}
+
+/// Gets the public preview of a hub, available to everyone if the hub has it enabled.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * [`Error::HubNotFound`] if the hub does not exist or does not have its preview enabled.
+/// * The preview settings could not be loaded for any of the reasons outlined by [`PreviewSettings::load`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The hub's images could not be loaded for any of the reasons outlined by [`HubImages::load`].
+pub async fn get_hub_preview(hub_id: HubId) -> Result<HubPreview> {
+    if !PreviewSettings::load(hub_id).await?.enabled {
+        return Err(Error::HubNotFound);
+    }
+    let hub = Hub::load(hub_id).await?;
+    Ok(HubPreview::new(&hub, &HubImages::load(hub_id).await?))
+}
+
+/// Creates an invite to a hub with a new short code (see [`invites::CODE_ALPHABET`]) that can be shared instead of the hub's ID.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user to check for permission to perform the operation.
+/// * `hub_id` - The ID of the hub to create an invite to.
+/// * `options` - When the invite expires and how many times it can be used.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The user does not have permission to create invites, see [`check_invite_permission`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The invite index could not be loaded or saved for any of the reasons outlined by [`InviteIndex::load`] and [`InviteIndex::save`].
+/// * No free code could be found.
+pub async fn create_invite(user_id: &str, hub_id: HubId, options: InviteOptions) -> Result<Invite> {
+    let hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(user_id)?;
+    check_invite_permission(member, HubPermission::CreateInvite, &hub)?;
+    let _lock = invites::lock().await;
+    let mut index = InviteIndex::load().await?;
+    let now = Utc::now();
+    index.prune(now);
+    let code = index
+        .generate_code(&mut rand::thread_rng())
+        .ok_or_else(|| Error::Other("No free invite code could be found.".to_string()))?;
+    let invite = Invite {
+        code: code.clone(),
+        hub_id,
+        created_by: user_id.to_string(),
+        created: now,
+        expires: options
+            .expires_in_hours
+            .map(|hours| now + chrono::Duration::hours(hours.into())),
+        max_uses: options.max_uses,
+        uses: 0,
+        last_used: None,
+    };
+    index.invites.insert(code, invite.clone());
+    index.save().await?;
+    crate::audit!(
+        actor = %user_id,
+        hub = %hub_id,
+        code = %invite.code,
+        "Created invite."
+    );
+    Ok(invite)
+}
+
+/// Checks if a member has a hub permission, members that can administrate the hub have the invite and webhook permissions whether or not they are set so that hubs from before they existed keep working.
+fn has_hub_permission(member: &HubMember, permission: HubPermission, hub: &Hub) -> bool {
+    member.has_permission(permission, hub)
+        || (matches!(
+            permission,
+            HubPermission::CreateInvite
+                | HubPermission::ManageInvites
+                | HubPermission::ManageWebhooks
+        ) && member.has_permission(HubPermission::Administrate, hub))
+}
+
+/// Checks that a member has one of the invite permissions, see [`has_hub_permission`].
+///
+/// # Errors
+///
+/// This function returns [`Error::MissingHubPermission`] with `permission` if the member does not have it.
+fn check_invite_permission(member: &HubMember, permission: HubPermission, hub: &Hub) -> Result {
+    if has_hub_permission(member, permission, hub) {
+        Ok(())
+    } else {
+        Err(Error::MissingHubPermission(permission))
+    }
+}
+
+/// Gets the invites of a hub that can still be used, oldest first.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The user does not have permission to manage invites, see [`check_invite_permission`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The invite index could not be loaded for any of the reasons outlined by [`InviteIndex::load`].
+pub async fn get_invites(user_id: &str, hub_id: HubId) -> Result<Vec<Invite>> {
+    let hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(user_id)?;
+    check_invite_permission(member, HubPermission::ManageInvites, &hub)?;
+    let now = Utc::now();
+    let mut invites = InviteIndex::load().await?.hub_invites(hub_id);
+    invites.retain(|invite| invite.is_usable(now));
+    Ok(invites)
+}
+
+/// Gets how many users joined a hub with each of its invites and how many of them are still in it, ordered by code.
+/// Invites that were revoked or can no longer be used are included as long as someone joined with them.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The user does not have permission to manage invites, see [`check_invite_permission`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The invite index could not be loaded for any of the reasons outlined by [`InviteIndex::load`].
+/// * The hub's invite joins could not be loaded for any of the reasons outlined by [`InviteJoins::load`].
+pub async fn get_invite_stats(user_id: &str, hub_id: HubId) -> Result<Vec<InviteStats>> {
+    let hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(user_id)?;
+    check_invite_permission(member, HubPermission::ManageInvites, &hub)?;
+    let now = Utc::now();
+    let mut invites = InviteIndex::load().await?.hub_invites(hub_id);
+    invites.retain(|invite| invite.is_usable(now));
+    Ok(InviteJoins::load(hub_id)
+        .await?
+        .stats(invites, |user_id| hub.is_member(user_id)))
+}
+
+/// Kicks every member whose last join was with an invite, the invite does not have to be usable anymore.
+/// The owner and the user doing the kicking are never kicked, each other member is kicked with [`kick_user`].
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The user does not have permission to kick members.
+/// * [`Error::InviteNotFound`] if nobody ever joined the hub with the code.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The hub's invite joins could not be loaded for any of the reasons outlined by [`InviteJoins::load`].
+pub async fn kick_invite_members(
+    actor_id: &str,
+    hub_id: HubId,
+    code: &str,
+) -> Result<BulkMemberResult> {
+    let hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(actor_id)?;
+    check_permission!(member, HubPermission::Kick, hub);
+    let joins = InviteJoins::load(hub_id).await?;
+    let code = invites::normalize_code(code)
+        .filter(|code| joins.joins.iter().any(|join| &join.code == code))
+        .ok_or(Error::InviteNotFound)?;
+    let mut user_ids: Vec<&String> = hub
+        .members
+        .keys()
+        .filter(|user_id| {
+            *user_id != actor_id
+                && **user_id != hub.owner
+                && joins.invited_by(user_id) == Some(code.as_str())
+        })
+        .collect();
+    user_ids.sort();
+    let mut result = BulkMemberResult::default();
+    for user_id in user_ids {
+        result.push(user_id.clone(), kick_user(actor_id, hub_id, user_id).await);
+    }
+    Ok(result)
+}
+
+/// Removes an invite of a hub so its code can no longer be used.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The user does not have permission to manage invites, see [`check_invite_permission`].
+/// * [`Error::InviteNotFound`] if the hub has no invite with the code.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The invite index could not be loaded or saved for any of the reasons outlined by [`InviteIndex::load`] and [`InviteIndex::save`].
+pub async fn revoke_invite(user_id: &str, hub_id: HubId, code: &str) -> Result {
+    let hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(user_id)?;
+    check_invite_permission(member, HubPermission::ManageInvites, &hub)?;
+    let _lock = invites::lock().await;
+    let mut index = InviteIndex::load().await?;
+    let code = invites::normalize_code(code)
+        .filter(|code| {
+            index
+                .invites
+                .get(code)
+                .is_some_and(|invite| invite.hub_id == hub_id)
+        })
+        .ok_or(Error::InviteNotFound)?;
+    index.invites.remove(&code);
+    index.save().await?;
+    crate::audit!(
+        actor = %user_id,
+        hub = %hub_id,
+        code = %code,
+        "Revoked invite."
+    );
+    Ok(())
+}
+
+/// Checks that a member can manage the webhooks of a channel, with [`ChannelPermission::ManageWebhooks`] in the channel or hub wide (see [`has_hub_permission`]).
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The channel does not exist, [`Error::ChannelNotFound`].
+/// * The member does not have the permission, [`Error::MissingChannelPermission`].
+fn check_webhook_permission(member: &HubMember, channel_id: ChannelId, hub: &Hub) -> Result {
+    if !hub.channels.contains_key(&channel_id) {
+        return Err(Error::ChannelNotFound);
+    }
+    if member.has_channel_permission(channel_id, ChannelPermission::ManageWebhooks, hub)
+        || has_hub_permission(member, HubPermission::ManageWebhooks, hub)
+    {
+        Ok(())
+    } else {
+        Err(Error::MissingChannelPermission(
+            ChannelPermission::ManageWebhooks,
+        ))
+    }
+}
+
+/// Creates a webhook that posts in a channel, its token is only shown to members that can manage the channel's webhooks.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user to check for permission to perform the operation, the webhook's messages are sent in their name.
+/// * `hub_id` - The ID of the hub the channel is in.
+/// * `channel_id` - The ID of the channel the webhook posts in.
+/// * `new_webhook` - Name and avatar of the webhook.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The user can not manage the channel's webhooks, see [`check_webhook_permission`].
+/// * The name or avatar is not valid, see [`crate::channel::validate_override_name`] and [`crate::channel::validate_override_avatar`].
+/// * The channel already has [`MAX_WEBHOOKS_PER_CHANNEL`] webhooks, [`Error::TooManyWebhooks`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The webhooks could not be loaded or saved for any of the reasons outlined by [`HubWebhooks::load`] and [`HubWebhooks::save`].
+pub async fn create_webhook(
+    user_id: &str,
+    hub_id: HubId,
+    channel_id: ChannelId,
+    new_webhook: NewWebhook,
+) -> Result<Webhook> {
+    let hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(user_id)?;
+    check_webhook_permission(member, channel_id, &hub)?;
+    let name = crate::channel::validate_override_name(&new_webhook.name)?;
+    if let Some(avatar) = &new_webhook.avatar {
+        crate::channel::validate_override_avatar(avatar)?;
+    }
+    let _lock = webhooks::lock(hub_id).await;
+    let mut hub_webhooks = HubWebhooks::load(hub_id).await?;
+    if hub_webhooks.channel_webhooks(channel_id).len() >= MAX_WEBHOOKS_PER_CHANNEL {
+        return Err(Error::TooManyWebhooks(MAX_WEBHOOKS_PER_CHANNEL));
+    }
+    let webhook = Webhook {
+        id: crate::new_id(),
+        channel_id,
+        name,
+        avatar: new_webhook.avatar,
+        token: webhooks::generate_token(),
+        created_by: user_id.to_string(),
+        created: Utc::now(),
+    };
+    hub_webhooks.webhooks.push(webhook.clone());
+    hub_webhooks.save(hub_id).await?;
+    crate::audit!(
+        actor = %user_id,
+        hub = %hub_id,
+        channel = %channel_id,
+        webhook = %webhook.id,
+        "Created webhook."
+    );
+    Ok(webhook)
+}
+
+/// Gets the webhooks of a channel, oldest first.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The user can not manage the channel's webhooks, see [`check_webhook_permission`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The webhooks could not be loaded for any of the reasons outlined by [`HubWebhooks::load`].
+pub async fn get_webhooks(
+    user_id: &str,
+    hub_id: HubId,
+    channel_id: ChannelId,
+) -> Result<Vec<Webhook>> {
+    let hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(user_id)?;
+    check_webhook_permission(member, channel_id, &hub)?;
+    Ok(HubWebhooks::load(hub_id)
+        .await?
+        .channel_webhooks(channel_id))
+}
+
+/// Deletes a webhook, its token can no longer be used to post.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * [`Error::WebhookNotFound`] if the hub has no webhook with the ID.
+/// * The user can not manage the webhooks of the webhook's channel, see [`check_webhook_permission`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The webhooks could not be loaded or saved for any of the reasons outlined by [`HubWebhooks::load`] and [`HubWebhooks::save`].
+pub async fn delete_webhook(user_id: &str, hub_id: HubId, webhook_id: ID) -> Result {
+    let hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(user_id)?;
+    let _lock = webhooks::lock(hub_id).await;
+    let mut hub_webhooks = HubWebhooks::load(hub_id).await?;
+    let channel_id = hub_webhooks.get(webhook_id)?.channel_id;
+    check_webhook_permission(member, channel_id, &hub)?;
+    hub_webhooks.remove_webhook(webhook_id)?;
+    hub_webhooks.save(hub_id).await?;
+    crate::audit!(
+        actor = %user_id,
+        hub = %hub_id,
+        channel = %channel_id,
+        webhook = %webhook_id,
+        "Deleted webhook."
+    );
+    Ok(())
+}
+
+/// Gets what a code points at, available to everyone. Invites include a preview of their hub whether or not the hub's public preview is enabled.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * [`Error::InviteNotFound`] if there is no usable invite with the code, expired, used up and malformed codes give the same error as codes that never existed.
+/// * The invite index could not be loaded for any of the reasons outlined by [`InviteIndex::load`].
+/// * The hub's images could not be loaded for any of the reasons outlined by [`HubImages::load`].
+pub async fn resolve_code(code: &str) -> Result<ResolvedCode> {
+    let invite = InviteIndex::load()
+        .await?
+        .get_usable(code, Utc::now())
+        .cloned()
+        .ok_or(Error::InviteNotFound)?;
+    let hub = Hub::load(invite.hub_id).await.map_err(|err| match err {
+        Error::HubNotFound => Error::InviteNotFound,
+        err => err,
+    })?;
+    Ok(ResolvedCode::HubInvite {
+        code: invite.code,
+        hub: HubPreview::new(&hub, &HubImages::load(hub.id).await?),
+        expires: invite.expires,
+    })
+}
+
+/// Joins the hub an invite is for, returning the ID of the hub. Users that are already in the hub do not use up the invite.
+/// The join is recorded in the hub's [`InviteJoins`], so the user stays attributed to the invite after it is revoked.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * [`Error::InviteNotFound`] if there is no usable invite with the code, see [`resolve_code`].
+/// * [`Error::Banned`] if the user is banned from the hub.
+/// * The invite index could not be loaded or saved for any of the reasons outlined by [`InviteIndex::load`] and [`InviteIndex::save`].
+/// * The hub's invite joins could not be loaded or saved for any of the reasons outlined by [`InviteJoins::load`] and [`InviteJoins::save`].
+/// * The user could not join the hub for any of the reasons outlined by [`join_hub`].
+pub async fn join_with_invite(user_id: String, code: &str) -> Result<HubId> {
+    let _lock = invites::lock().await;
+    let mut index = InviteIndex::load().await?;
+    let invite = index
+        .get_usable(code, Utc::now())
+        .cloned()
+        .ok_or(Error::InviteNotFound)?;
+    let hub = Hub::load(invite.hub_id).await.map_err(|err| match err {
+        Error::HubNotFound => Error::InviteNotFound,
+        err => err,
+    })?;
+    if hub.is_member(&user_id) {
+        return Ok(hub.id);
+    }
+    if hub.bans.contains(&user_id) {
+        return Err(Error::Banned);
+    }
+    join_hub(user_id.clone(), hub.id).await?;
+    let now = Utc::now();
+    let mut joins = InviteJoins::load(hub.id).await?;
+    joins.joins.push(InviteJoin {
+        user_id,
+        code: invite.code.clone(),
+        joined: now,
+    });
+    joins.save(hub.id).await?;
+    if let Some(stored) = index.invites.get_mut(&invite.code) {
+        stored.uses = stored.uses.saturating_add(1);
+        stored.last_used = Some(now);
+    }
+    index.save().await?;
+    Ok(hub.id)
+}
+
+/// Gets the usage and limits of a user's quotas.
+///
+/// # Arguments
+///
+/// * `actor_id` - ID of the user requesting the quotas, must be a server admin unless it is the same as `user_id`.
+/// * `user_id` - ID of the user whose quotas to get.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user requesting the quotas is not a server admin and is not the same user.
+/// * The quota could not be reconciled for any of the reasons outlined by [`quotas::reconcile`].
+/// * The quota could not be loaded for any of the reasons outlined by [`quotas::UserQuota::load`].
+pub async fn get_user_quota(actor_id: &str, user_id: &str) -> Result<QuotaStatus> {
+    if actor_id != user_id && !quotas::is_admin(actor_id) {
+        return Err(Error::NotAdmin);
+    }
+    quotas::reconcile(user_id).await?;
+    Ok(quotas::UserQuota::load(user_id)
+        .await?
+        .status(&quotas::limits()))
+}
+
+/// Replaces the quota overrides of a user, returning the new usage and limits of their quotas.
+///
+/// # Arguments
+///
+/// * `actor_id` - ID of the user changing the overrides, must be a server admin.
+/// * `user_id` - ID of the user whose overrides to change.
+/// * `overrides` - The new overrides.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user changing the overrides is not a server admin.
+/// * The quota could not be changed for any of the reasons outlined by [`quotas::update`].
+pub async fn set_user_quota_overrides(
+    actor_id: &str,
+    user_id: &str,
+    overrides: QuotaOverrides,
+) -> Result<QuotaStatus> {
+    if !quotas::is_admin(actor_id) {
+        return Err(Error::NotAdmin);
+    }
+    let status = quotas::update(user_id, |quota, limits| {
+        quota.overrides = overrides.clone();
+        Ok(quota.status(limits))
+    })
+    .await?;
+    crate::audit!(
+        actor = %actor_id,
+        user = %user_id,
+        ?overrides,
+        kind = "admin",
+        "Changed quota overrides."
+    );
+    Ok(status)
+}
+
+/// Links a new key to a user, so a user that lost their key can sign in again, see [`identity_links::link`].
+///
+/// # Arguments
+///
+/// * `actor_id` - ID of the user linking the key, must be a server admin.
+/// * `user_id` - ID of the user the key should sign in as.
+/// * `request` - Fingerprint of the key and whether the user's other keys should stop working.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user linking the key is not a server admin.
+/// * The key could not be linked for any of the reasons outlined by [`identity_links::link`].
+pub async fn link_identity(
+    actor_id: &str,
+    user_id: &str,
+    request: LinkRequest,
+) -> Result<LinkResult> {
+    if !quotas::is_admin(actor_id) {
+        return Err(Error::NotAdmin);
+    }
+    let result = identity_links::link(actor_id, user_id, &request).await?;
+    crate::audit!(
+        actor = %actor_id,
+        user = %result.user_id,
+        key = %result.fingerprint,
+        revoked = ?result.revoked,
+        kind = "admin",
+        "Linked a key to a user."
+    );
+    Ok(result)
+}
+
+/// Gets entries of the security log, see [`security_log::query`].
+///
+/// # Arguments
+///
+/// * `actor_id` - ID of the user reading the log, must be a server admin.
+/// * `query` - Which entries to get.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user reading the log is not a server admin.
+/// * The log could not be read for any of the reasons outlined by [`security_log::read`].
+pub async fn get_security_log(actor_id: &str, query: &SecurityLogQuery) -> Result<SecurityLogPage> {
+    if !quotas::is_admin(actor_id) {
+        return Err(Error::NotAdmin);
+    }
+    security_log::query(query).await
+}
+
+/// Starts generating an export of everything the server stores about a user, see [`account_export::generate`].
+/// Returns the status of the new export, the export itself can be downloaded with [`get_account_export`] once it is ready.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user whose data to export, users can only export their own data.
+///
+/// # Errors
+///
+/// This function may return an error for any of the reasons outlined by [`account_export::start`], including when the user already started an export in the last day.
+pub async fn start_account_export(user_id: &str) -> Result<ExportStatus> {
+    let status = account_export::start(user_id).await?;
+    crate::audit!(user = %user_id, "Started account export.");
+    Ok(status)
+}
+
+/// Gets the status of the latest export of a user's data, `None` if they never started one.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user whose export status to get.
+///
+/// # Errors
+///
+/// This function may return an error for any of the reasons outlined by [`ExportStatus::current`].
+pub async fn get_account_export_status(user_id: &str) -> Result<Option<ExportStatus>> {
+    ExportStatus::current(user_id).await
+}
+
+/// Gets a user's finished export as JSON.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user whose export to get.
+///
+/// # Errors
+///
+/// This function may return an error for any of the reasons outlined by [`account_export::read`], including when no export is ready.
+pub async fn get_account_export(user_id: &str) -> Result<String> {
+    account_export::read(user_id).await
+}
+
+/// Checks if a user is banned from a hub.
+/// Returns `true` if they are and `false` if they aren't.
+///
+/// # Arguments
+///
+/// * `actor_id` - ID of the user who is checking.
+/// * `hub_id` - The hub in which to check the ban status.
+/// * `user_id` - The user whose ban status is to be checked.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user who is checking is not in the hub.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+pub async fn user_banned(actor_id: &str, hub_id: HubId, user_id: &str) -> Result<bool> {
+    let hub = Hub::load(hub_id).await?;
+    hub.check_membership(actor_id)?;
+    Ok(hub.bans.contains(user_id))
+}
+
+/// Checks if a user is muted in a hub.
+/// Returns `true` if they are and `false` if they aren't.
+///
+/// # Arguments
+///
+/// * `actor_id` - ID of the user who is checking.
+/// * `hub_id` - The hub in which to check the mute status.
+/// * `user_id` - The user whose mute status is to be checked.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user who is checking is not in the hub.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+pub async fn user_muted(actor_id: &str, hub_id: HubId, user_id: &str) -> Result<bool> {
+    let hub = Hub::load(hub_id).await?;
+    hub.check_membership(actor_id)?;
+    Ok(hub.mutes.contains(user_id))
+}
+
+/// Gets the information on a member of a hub.
+///
+/// # Arguments
+///
+/// * `actor_id` - ID of the user who is requesting the information.
+/// * `hub_id` - Hub from which to get the information.
+/// * `user_id` - ID of the user whose information is being requested.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The requesting user is not in the hub.
+/// * The user whose information is being requested is not in the hub.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+pub async fn get_hub_member(actor_id: &str, hub_id: HubId, user_id: &str) -> Result<HubMember> {
+    let hub = Hub::load(hub_id).await?;
+    hub.check_membership(actor_id)?;
+    Ok(hub.get_member(user_id)?.clone())
+}
+
+/// Adds the given user to a hub.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user to add to the hub.
+/// * `hub_id` - ID of the hub the user is to be added to.
+///
+/// # Errors
+///
+/// * The user could not be added to the hub for any of the reasons outlined by [`User::join_hub`].
+/// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The change could not be recorded for any of the reasons outlined by [`hub_changes::record`].
+pub async fn join_hub(user_id: String, hub_id: HubId) -> Result {
+    let lock = hub_changes::lock(hub_id).await;
+    let mut hub = Hub::load(hub_id).await?;
+    hub.user_join(user_id.clone())?;
+    hub.save().await?;
+    membership_log::record(
+        hub_id,
+        MembershipEvent::new(user_id.clone(), MembershipEventKind::Joined),
+    )
+    .await?;
+    hub_changes::record(&lock, &hub, HubUpdateType::UserJoined(user_id)).await?;
+    Ok(())
+}
+
+/// Removes the given user from a hub.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user to remove from the hub.
+/// * `hub_id` - ID of the hub the user is to be removed from.
+///
+/// # Errors
+///
+/// * The user owns the hub, [`Error::OwnerCannotLeave`].
+/// * The user could not be removed from the hub for any of the reasons outlined by [`User::leave_hub`].
+/// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The change could not be recorded for any of the reasons outlined by [`hub_changes::record`].
+pub async fn leave_hub(user_id: &str, hub_id: HubId) -> Result {
+    let lock = hub_changes::lock(hub_id).await;
+    let mut hub = Hub::load(hub_id).await?;
+    if hub.owner == user_id {
+        return Err(Error::OwnerCannotLeave);
+    }
+    hub.user_leave(user_id)?;
+    hub.save().await?;
+    membership_log::record(
+        hub_id,
+        MembershipEvent::new(user_id.to_string(), MembershipEventKind::Left),
+    )
+    .await?;
+    hub_changes::record(&lock, &hub, HubUpdateType::UserLeft(user_id.to_string())).await?;
+    Ok(())
+}
+
+/// Removes a user from every hub they are a member of except the excluded ones, for example before they stop using the server.
+/// Hubs the user owns are reported as failed, they have to be transferred first (see [`bulk_transfer_hubs`]).
+///
+/// # Errors
+///
+/// This function returns an error if the hubs could not be listed, see [`crate::maintenance::list_hubs`]. Hubs that could not be left are reported in the result instead.
+pub async fn leave_all_hubs(user_id: &str, exclude: &[HubId]) -> Result<BulkHubResult> {
+    let mut result = BulkHubResult::default();
+    for hub_id in crate::maintenance::list_hubs().await? {
+        if exclude.contains(&hub_id) {
+            continue;
+        }
+        if let Ok(hub) = Hub::load(hub_id).await {
+            if hub.members.contains_key(user_id) {
+                result.push(hub_id, leave_hub(user_id, hub_id).await);
+            }
+        }
+    }
+    result.succeeded.sort();
+    result.failed.sort_by_key(|failure| failure.hub_id);
+    Ok(result)
+}
+
+/// Gets the hubs a user owns, sorted by ID.
+///
+/// # Errors
+///
+/// This function returns an error if the hubs could not be listed, see [`crate::maintenance::list_hubs`].
+pub async fn get_owned_hubs(user_id: &str) -> Result<Vec<OwnedHub>> {
+    let mut owned = Vec::new();
+    for hub_id in crate::maintenance::list_hubs().await? {
+        if let Ok(hub) = Hub::load(hub_id).await {
+            if hub.owner == user_id {
+                owned.push(OwnedHub {
+                    id: hub.id,
+                    name: hub.name,
+                    members: hub.members.len(),
+                });
+            }
+        }
+    }
+    owned.sort_by_key(|hub| hub.id);
+    Ok(owned)
+}
+
+/// Gives a hub to another of its members, see [`Hub::transfer_ownership`]. The new owner's quota is charged for the hub and the previous owner's released.
+///
+/// # Arguments
+///
+/// * `actor_id` - ID of the user giving the hub away, must be its owner.
+/// * `hub_id` - ID of the hub to transfer.
+/// * `new_owner` - ID of the member that should own the hub.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The user giving the hub away does not own it, [`Error::NotOwner`].
+/// * The new owner can not own the hub for any of the reasons outlined by [`Hub::transfer_ownership`].
+/// * The new owner owns too many hubs, see [`quotas::charge_hub`].
+/// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The change could not be recorded for any of the reasons outlined by [`hub_changes::record`].
+pub async fn transfer_hub(actor_id: &str, hub_id: HubId, new_owner: &str) -> Result {
+    let lock = hub_changes::lock(hub_id).await;
+    let mut hub = Hub::load(hub_id).await?;
+    if hub.owner != actor_id {
+        return Err(Error::NotOwner);
+    }
+    hub.transfer_ownership(new_owner)?;
+    quotas::charge_hub(new_owner).await?;
+    if let Err(err) = hub.save().await {
+        let _ = quotas::release_hub(new_owner).await;
+        return Err(err);
+    }
+    if let Err(err) = quotas::release_hub(actor_id).await {
+        warn!(
+            "Could not release the quota of the previous owner of hub {}: {}",
+            hub_id,
+            err.chain()
+        );
+    }
+    crate::audit!(actor = %actor_id, hub = %hub_id, new_owner = %new_owner, "Transferred a hub.");
+    hub_changes::record(
+        &lock,
+        &hub,
+        HubUpdateType::OwnerChanged(new_owner.to_string()),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Gives many hubs to new owners at once, see [`transfer_hub`]. Each hub is transferred on its own, hubs that can not be transferred are reported in the result.
+///
+/// # Errors
+///
+/// This function returns [`Error::TooManyHubs`] if more than [`MAX_BULK_HUBS`] hubs are given.
+pub async fn bulk_transfer_hubs(
+    actor_id: &str,
+    transfers: BTreeMap<HubId, String>,
+) -> Result<BulkHubResult> {
+    if transfers.len() > MAX_BULK_HUBS {
+        return Err(Error::TooManyHubs(MAX_BULK_HUBS));
+    }
+    let mut result = BulkHubResult::default();
+    for (hub_id, new_owner) in transfers {
+        result.push(hub_id, transfer_hub(actor_id, hub_id, &new_owner).await);
+    }
+    Ok(result)
+}
+
+/// Handles kicking, banning, muting, unbanning and unmuting users in/from hubs.
+async fn hub_user_op(actor_id: &str, hub_id: HubId, user_id: &str, op: HubPermission) -> Result {
+    let lock = hub_changes::lock(hub_id).await;
+    let mut hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(actor_id)?;
+    check_permission!(member, op, hub);
+    let was_member = hub.members.contains_key(user_id);
+    let mut event = None;
+    let update = match op {
+        HubPermission::Kick => {
+            hub.kick_user(user_id)?;
+            if was_member {
+                event = Some(MembershipEventKind::Kicked);
+            }
+            HubUpdateType::UserKicked(user_id.to_string())
+        }
+        HubPermission::Ban => {
+            hub.ban_user(user_id.to_string())?;
+            event = Some(MembershipEventKind::Banned);
+            HubUpdateType::UserBanned(user_id.to_string())
+        }
+        HubPermission::Unban => {
+            hub.unban_user(user_id);
+            HubUpdateType::UserUnbanned(user_id.to_string())
+        }
+        HubPermission::Mute => {
+            hub.mute_user(user_id.to_string());
+            HubUpdateType::UserMuted(user_id.to_string())
+        }
+        HubPermission::Unmute => {
+            hub.unmute_user(user_id);
+            HubUpdateType::UserUnmuted(user_id.to_string())
+        }
+        _ => return Err(Error::UnexpectedServerArg),
+    };
+    hub.save().await?;
+    if let Some(kind) = event {
+        membership_log::record(hub_id, MembershipEvent::new(user_id.to_string(), kind)).await?;
+    }
+    hub_changes::record(&lock, &hub, update).await?;
+    crate::audit!(
+        actor = %actor_id,
+        hub = %hub_id,
+        user = %user_id,
+        action = %op,
+        kind = "moderation",
+        "Moderated hub member."
+    );
+    Ok(())
+}
+
+/// Gets the disk usage of each channel of a hub, measured at most every [`hub_storage::STORAGE_CACHE_MINUTES`] minutes.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The user does not have permission to administrate the hub.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The usage could not be measured for any of the reasons outlined by [`HubStorage::measure`].
+pub async fn get_hub_storage(user_id: &str, hub_id: HubId) -> Result<HubStorage> {
+    let hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(user_id)?;
+    check_permission!(member, HubPermission::Administrate, hub);
+    hub_storage::current(&hub).await
+}
+
+/// Compacts the message files of a channel (see [`Channel::compact`]), returning the number of bytes reclaimed.
+/// Messages can still be sent to the channel while it is compacted.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The user does not have permission to administrate the hub.
+/// * The channel could not be found in the hub.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The channel could not be compacted for any of the reasons outlined by [`Channel::compact`].
+pub async fn compact_channel(
+    user_id: &str,
+    hub_id: HubId,
+    channel_id: ChannelId,
+) -> Result<Compaction> {
+    let hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(user_id)?;
+    check_permission!(member, HubPermission::Administrate, hub);
+    let channel = hub
+        .channels
+        .get(&channel_id)
+        .ok_or(Error::ChannelNotFound)?;
+    let reclaimed = channel.compact().await?;
+    hub_storage::record_reclaimed(hub_id, channel_id, reclaimed);
+    crate::audit!(
+        actor = %user_id,
+        hub = %hub_id,
+        channel = %channel_id,
+        reclaimed,
+        "Compacted channel."
+    );
+    Ok(Compaction {
+        channel_id,
+        reclaimed,
+    })
+}
+
+/// Starts removing a user's messages from a hub in the background, used by moderators after banning a spammer. Returns the status of the purge, which can be followed with [`get_purge_status`].
+/// The user does not have to still be in the hub.
+///
+/// # Arguments
+///
+/// * `actor_id` - ID of the user starting the purge, they need the `BAN` permission.
+/// * `hub_id` - ID of the hub to remove the messages from.
+/// * `user_id` - ID of the user whose messages to remove.
+/// * `filter` - Channel and time range to remove the messages from.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The time range starts after it ends.
+/// * The user starting the purge is not in the hub or does not have the `BAN` permission.
+/// * The channel in the filter does not exist.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+pub async fn purge_user_messages(
+    actor_id: &str,
+    hub_id: HubId,
+    user_id: &str,
+    filter: PurgeFilter,
+) -> Result<PurgeStatus> {
+    if let (Some(from), Some(to)) = (filter.from, filter.to) {
+        if from > to {
+            return Err(Error::InvalidTimeRange);
+        }
+    }
+    let hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(actor_id)?;
+    check_permission!(member, HubPermission::Ban, hub);
+    let channel_ids = match filter.channel_id {
+        Some(channel_id) if !hub.channels.contains_key(&channel_id) => {
+            return Err(Error::ChannelNotFound)
+        }
+        Some(channel_id) => vec![channel_id],
+        None => hub.channels.keys().copied().collect(),
+    };
+    let status = message_purge::start(hub_id, user_id, actor_id, filter, channel_ids);
+    crate::audit!(
+        actor = %actor_id,
+        hub = %hub_id,
+        user = %user_id,
+        purge = %status.id,
+        ?filter,
+        kind = "moderation",
+        "Started purging messages."
+    );
+    Ok(status)
+}
+
+/// Gets the status of a purge started with [`purge_user_messages`].
+///
+/// # Errors
+///
+/// This function may return an error for any of the reasons outlined by [`message_purge::status`].
+pub fn get_purge_status(user_id: &str, purge_id: ID) -> Result<PurgeStatus> {
+    message_purge::status(user_id, purge_id)
+}
+
+/// Moves messages from one channel of a hub to another, see [`message_move::move_messages`].
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user moving the messages, they need the `MANAGE` permission in both channels.
+/// * `hub_id` - ID of the hub that has both channels.
+/// * `source` - ID of the channel the messages are in.
+/// * `target` - ID of the channel to move the messages to.
+/// * `message_ids` - IDs of the messages to move, at most [`MAX_MOVE_MESSAGES`].
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * More than [`MAX_MOVE_MESSAGES`] messages were given.
+/// * The source and target channel are the same.
+/// * The user is not in the hub.
+/// * Either channel does not exist.
+/// * The user does not have permission to manage either channel.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The messages could not be moved for any of the reasons outlined by [`message_move::move_messages`].
+pub async fn move_messages(
+    user_id: &str,
+    hub_id: HubId,
+    source: ChannelId,
+    target: ChannelId,
+    message_ids: Vec<MessageId>,
+) -> Result<MoveResult> {
+    if message_ids.len() > MAX_MOVE_MESSAGES {
+        return Err(Error::TooManyMessages(MAX_MOVE_MESSAGES));
+    }
+    if source == target {
+        return Err(Error::SameChannel);
+    }
+    let hub = Hub::load(hub_id).await?;
     let member = hub.get_member(user_id)?;
-    check_permission!(member, HubPermission::Administrate, hub);
-    let old_name = mem::replace(&mut hub.name, new_name);
-    hub.save().await?;
-    Ok(old_name)
+    for channel_id in [source, target].iter() {
+        if !hub.channels.contains_key(channel_id) {
+            return Err(Error::ChannelNotFound);
+        }
+        check_permission!(member, *channel_id, ChannelPermission::Manage, hub);
+    }
+    let result = message_move::move_messages(hub_id, source, target, &message_ids).await?;
+    crate::audit!(
+        user = %user_id,
+        hub = %hub_id,
+        source = %source,
+        target = %target,
+        moved = result.moved.len(),
+        "Moved messages."
+    );
+    Ok(result)
 }
 
-/// Changes the description of a hub.
+/// Gets the membership history of a hub between two times (inclusive): the number of joins, leaves, kicks and bans per day.
+/// Users that can administrate the hub also get the events themselves, at most `limit` of them starting at `offset`.
 ///
 /// # Arguments
 ///
-/// * `user_id` - ID of the user to check for permission to perform the operation.
-/// * `hub_id` - The ID of the hub whose name is to be changed.
-/// * `new_description` - The content for the hub's description
+/// * `user_id` - ID of the user requesting the history.
+/// * `hub_id` - ID of the hub to get the history of.
+/// * `from` - Start of the time range.
+/// * `to` - End of the time range.
+/// * `offset` - Number of events in the range to skip.
+/// * `limit` - Maximum number of events to return, lowered to [`membership_log::MAX_MEMBERSHIP_EVENTS_PER_REQUEST`] if it is larger.
 ///
 /// # Errors
 ///
 /// This function may return an error for any of the following reasons:
 ///
-/// * THe user is not in the hub.
-/// * The user does not have permission to change the hub's description.
-/// * The given description is bigger than [`crate::MAX_DESCRIPTION_SIZE`].
+/// * The time range starts after it ends.
+/// * The user is not in the hub.
 /// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
-/// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
-pub async fn change_hub_description<S: Into<String> + Clone>(
+/// * The membership log could not be read for any of the reasons outlined by [`membership_log::read`].
+pub async fn get_member_history(
     user_id: &str,
-    hub_id: ID,
-    new_description: S,
-) -> Result<String> {
-    let new_description: String = new_description.into();
-    if new_description.as_bytes().len() > crate::MAX_DESCRIPTION_SIZE {
-        Err(Error::TooBig)
-    } else {
-        let mut hub = Hub::load(hub_id).await?;
-        let member = hub.get_member(user_id)?;
-        check_permission!(member, HubPermission::Administrate, hub);
-        let old_name = mem::replace(&mut hub.description, new_description);
-        hub.save().await?;
-        Ok(old_name)
+    hub_id: HubId,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    offset: usize,
+    limit: usize,
+) -> Result<MemberHistory> {
+    if from > to {
+        return Err(Error::InvalidTimeRange);
     }
+    let hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(user_id)?;
+    let include_events = member.has_permission(HubPermission::Administrate, &hub);
+    let events = membership_log::read(hub_id).await?;
+    Ok(membership_log::history(
+        &events,
+        from,
+        to,
+        include_events,
+        offset,
+        limit,
+    ))
 }
 
-/// Checks if a user is banned from a hub.
-/// Returns `true` if they are and `false` if they aren't.
+/// Gets the name and description changes of a hub and its channels, newest first.
+/// Changes of channels the user can not read (including deleted channels) are left out.
 ///
 /// # Arguments
 ///
-/// * `actor_id` - ID of the user who is checking.
-/// * `hub_id` - The hub in which to check the ban status.
-/// * `user_id` - The user whose ban status is to be checked.
+/// * `user_id` - ID of the user requesting the history.
+/// * `hub_id` - ID of the hub to get the history of.
+/// * `field` - Only get changes of this field, `None` for changes of every field.
 ///
 /// # Errors
 ///
 /// This function may return an error for any of the following reasons:
 ///
-/// * The user who is checking is not in the hub.
+/// * The user is not in the hub.
+/// * The user does not have permission to administrate the hub.
 /// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
-pub async fn user_banned(actor_id: &str, hub_id: ID, user_id: &str) -> Result<bool> {
+/// * The history could not be loaded for any of the reasons outlined by [`ChangeHistory::load`].
+pub async fn get_change_history(
+    user_id: &str,
+    hub_id: HubId,
+    field: Option<ChangedField>,
+) -> Result<Vec<Change>> {
     let hub = Hub::load(hub_id).await?;
-    hub.check_membership(actor_id)?;
-    Ok(hub.bans.contains(user_id))
+    let member = hub.get_member(user_id)?;
+    check_permission!(member, HubPermission::Administrate, hub);
+    Ok(ChangeHistory::load(hub_id)
+        .await?
+        .changes
+        .into_iter()
+        .rev()
+        .filter(|change| field.is_none_or(|field| change.field == field))
+        .filter(|change| {
+            change
+                .channel_id
+                .is_none_or(|channel_id| hub.can_read_channel(member, channel_id))
+        })
+        .collect())
 }
 
-/// Checks if a user is muted in a hub.
-/// Returns `true` if they are and `false` if they aren't.
+/// Gets the activity of a hub over the last `days` days (including today), see [`hub_activity::HubActivity::report`].
 ///
 /// # Arguments
 ///
-/// * `actor_id` - ID of the user who is checking.
-/// * `hub_id` - The hub in which to check the mute status.
-/// * `user_id` - The user whose mute status is to be checked.
+/// * `user_id` - ID of the user requesting the activity.
+/// * `hub_id` - ID of the hub to get the activity of.
+/// * `days` - Number of days to get the activity of, lowered to [`hub_activity::MAX_ACTIVITY_DAYS`] if it is larger.
 ///
 /// # Errors
 ///
 /// This function may return an error for any of the following reasons:
 ///
-/// * The user who is checking is not in the hub.
+/// * The user is not in the hub.
+/// * The user does not have permission to administrate the hub.
 /// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
-pub async fn user_muted(actor_id: &str, hub_id: ID, user_id: &str) -> Result<bool> {
+/// * The activity could not be loaded for any of the reasons outlined by [`hub_activity::current`].
+pub async fn get_hub_activity(
+    user_id: &str,
+    hub_id: HubId,
+    days: u32,
+) -> Result<hub_activity::ActivityReport> {
     let hub = Hub::load(hub_id).await?;
-    hub.check_membership(actor_id)?;
-    Ok(hub.mutes.contains(user_id))
+    let member = hub.get_member(user_id)?;
+    check_permission!(member, HubPermission::Administrate, hub);
+    Ok(hub_activity::current(hub_id)
+        .await?
+        .report(Utc::now(), days.min(hub_activity::MAX_ACTIVITY_DAYS)))
 }
 
-/// Gets the information on a member of a hub.
+/// Gets the members of a hub that sent the most messages, see [`HubLeaderboard::top`]. Users that are no longer in the hub are left out.
 ///
 /// # Arguments
 ///
-/// * `actor_id` - ID of the user who is requesting the information.
-/// * `hub_id` - Hub from which to get the information.
-/// * `user_id` - ID of the user whose information is being requested.
+/// * `user_id` - ID of the user requesting the leaderboard, must be in the hub.
+/// * `hub_id` - ID of the hub to get the leaderboard of.
+/// * `period` - Time range to count messages in.
+/// * `limit` - Maximum number of members to return, lowered to [`leaderboard::MAX_LEADERBOARD_SIZE`] if it is larger.
 ///
 /// # Errors
 ///
 /// This function may return an error for any of the following reasons:
 ///
-/// * The requesting user is not in the hub.
-/// * The user whose information is being requested is not in the hub.
+/// * The user is not in the hub.
 /// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
-pub async fn get_hub_member(actor_id: &str, hub_id: ID, user_id: &str) -> Result<HubMember> {
+/// * [`Error::LeaderboardDisabled`] if the hub opted out of leaderboards.
+/// * The leaderboard could not be loaded for any of the reasons outlined by [`leaderboard::current`].
+pub async fn get_leaderboard(
+    user_id: &str,
+    hub_id: HubId,
+    period: LeaderboardPeriod,
+    limit: usize,
+) -> Result<Leaderboard> {
     let hub = Hub::load(hub_id).await?;
-    hub.check_membership(actor_id)?;
-    Ok(hub.get_member(user_id)?.clone())
+    hub.get_member(user_id)?;
+    let mut counts = leaderboard::current(hub_id).await?;
+    if counts.disabled {
+        return Err(Error::LeaderboardDisabled);
+    }
+    counts
+        .senders
+        .retain(|sender, _| hub.members.contains_key(sender));
+    Ok(counts.top(
+        Utc::now(),
+        period,
+        limit.min(leaderboard::MAX_LEADERBOARD_SIZE),
+    ))
 }
 
-/// Adds the given user to a hub.
+/// Enables or disables the leaderboard of a hub. Disabling it deletes the hub's message counts and stops counting, enabling it again starts counting from zero (see the `backfill-leaderboards` maintenance command to count the stored messages).
 ///
 /// # Arguments
 ///
-/// * `user_id` - ID of the user to add to the hub.
-/// * `hub_id` - ID of the hub the user is to be added to.
+/// * `user_id` - ID of the user to check for permission to perform the operation.
+/// * `hub_id` - The ID of the hub whose leaderboard is to be changed.
+/// * `enabled` - Whether the hub has a leaderboard.
 ///
 /// # Errors
 ///
-/// * The user could not be added to the hub for any of the reasons outlined by [`User::join_hub`].
-/// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
-pub async fn join_hub(user_id: String, hub_id: ID) -> Result {
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The user does not have permission to administrate the hub.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The leaderboard could not be loaded or saved for any of the reasons outlined by [`HubLeaderboard::load`] and [`HubLeaderboard::save`].
+/// * The change could not be recorded for any of the reasons outlined by [`hub_changes::record`].
+pub async fn set_leaderboard_enabled(user_id: &str, hub_id: HubId, enabled: bool) -> Result {
+    let lock = hub_changes::lock(hub_id).await;
     let mut hub = Hub::load(hub_id).await?;
-    hub.user_join(user_id)?;
-    hub.save().await
+    let member = hub.get_member(user_id)?;
+    check_permission!(member, HubPermission::Administrate, hub);
+    let mut counts = HubLeaderboard::load(hub_id).await?;
+    if counts.disabled != enabled {
+        return Ok(());
+    }
+    if enabled {
+        counts.disabled = false;
+    } else {
+        leaderboard::forget(hub_id);
+        counts = HubLeaderboard {
+            disabled: true,
+            ..HubLeaderboard::default()
+        };
+    }
+    counts.save(hub_id).await?;
+    hub.save().await?;
+    hub_changes::record(&lock, &hub, HubUpdateType::LeaderboardChanged).await?;
+    crate::audit!(
+        actor = %user_id,
+        hub = %hub_id,
+        enabled = enabled,
+        "Changed hub leaderboard."
+    );
+    Ok(())
 }
 
-/// Removes the given user from a hub.
+/// Lists the members of a hub, see [`Hub::list_members`].
 ///
 /// # Arguments
 ///
-/// * `user_id` - ID of the user to remove from the hub.
-/// * `hub_id` - ID of the hub the user is to be removed from.
+/// * `user_id` - ID of the user requesting the list, must be in the hub.
+/// * `hub_id` - ID of the hub whose members to list.
+/// * `sort` - Order to list the members in.
+/// * `group` - ID of a permission group to only list the members of.
+/// * `prefix` - Start of the user IDs of the members to list.
+/// * `hoisted` - Whether to group the members by hoisted groups: members are listed under the highest hoisted group they are in (see [`HubGroupDisplay::hoisted_group`]), groups ordered by position and followed by the members without a hoisted group, keeping `sort` within each group.
+/// * `offset` - Number of matching members to skip.
+/// * `limit` - Maximum number of members to return, lowered to [`MAX_MEMBERS_PER_REQUEST`] if it is larger.
 ///
 /// # Errors
 ///
-/// * The user could not be removed from the hub for any of the reasons outlined by [`User::leave_hub`].
-/// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
-pub async fn leave_hub(user_id: &str, hub_id: ID) -> Result {
-    let mut hub = Hub::load(hub_id).await?;
-    hub.user_leave(user_id)?;
-    hub.save().await
-}
-
-/// Handles kicking, banning, muting, unbanning and unmuting users in/from hubs.
-async fn hub_user_op(actor_id: &str, hub_id: ID, user_id: &str, op: HubPermission) -> Result {
-    let mut hub = Hub::load(hub_id).await?;
-    let member = hub.get_member(actor_id)?;
-    check_permission!(member, op, hub);
-    match op {
-        HubPermission::Kick => hub.kick_user(&user_id)?,
-        HubPermission::Ban => hub.ban_user(user_id.to_string())?,
-        HubPermission::Unban => hub.unban_user(user_id),
-        HubPermission::Mute => hub.mute_user(user_id.to_string()),
-        HubPermission::Unmute => hub.unmute_user(user_id),
-        _ => return Err(Error::UnexpectedServerArg),
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The group does not exist.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The group display settings could not be loaded for any of the reasons outlined by [`HubGroupDisplay::load`].
+#[allow(clippy::too_many_arguments)]
+pub async fn list_hub_members(
+    user_id: &str,
+    hub_id: HubId,
+    sort: MemberSort,
+    group: Option<ID>,
+    prefix: &str,
+    hoisted: bool,
+    offset: usize,
+    limit: usize,
+) -> Result<MemberList> {
+    let hub = Hub::load(hub_id).await?;
+    hub.get_member(user_id)?;
+    if let Some(group) = group {
+        if !hub.groups.contains_key(&group) {
+            return Err(Error::GroupNotFound);
+        }
     }
-    hub.save().await
+    let mut matching = hub.list_members(sort, group, prefix);
+    let mut sections = Vec::new();
+    if hoisted {
+        let mut display = HubGroupDisplay::load(hub_id).await?;
+        display.normalize(&hub);
+        let hoisted_group = |member: &HubMember| {
+            display
+                .hoisted_group(member)
+                .map(|group| (group.position, group.group_id))
+        };
+        // Stable, so members keep the requested order within each group.
+        matching.sort_by_key(|member| hoisted_group(member).map_or(u32::MAX, |group| group.0));
+        for member in matching
+            .iter()
+            .skip(offset)
+            .take(limit.min(MAX_MEMBERS_PER_REQUEST))
+        {
+            let group_id = hoisted_group(member).map(|group| group.1);
+            match sections.last_mut() {
+                Some(MemberSection {
+                    group_id: last,
+                    count,
+                }) if *last == group_id => *count += 1,
+                _ => sections.push(MemberSection { group_id, count: 1 }),
+            }
+        }
+    }
+    let members: Vec<HubMemberInfo> = matching
+        .iter()
+        .skip(offset)
+        .take(limit.min(MAX_MEMBERS_PER_REQUEST))
+        .map(|member| HubMemberInfo::from(*member))
+        .collect();
+    let end = offset.saturating_add(members.len());
+    Ok(MemberList {
+        next_offset: if end < matching.len() {
+            Some(end)
+        } else {
+            None
+        },
+        members,
+        sections,
+    })
 }
 
 /// Maps the different possible options for [`hub_user_op`] to separate functions.
@@ -281,7 +2473,7 @@ macro_rules! action_fns {
   ($($(#[$attr:meta])* => ($fnName:ident, $variant:ident)),*) => {
     $(
       $(#[$attr])*
-      pub async fn $fnName(actor_id: &str, hub_id: ID, user_id: &str) -> Result<()> {
+      pub async fn $fnName(actor_id: &str, hub_id: HubId, user_id: &str) -> Result<()> {
           hub_user_op(actor_id, hub_id, user_id, HubPermission::$variant).await
       }
     )*
@@ -391,20 +2583,65 @@ action_fns! {
 /// This function may return an error for any of the following reasons:
 ///
 /// * THe user is not in the hub.
-/// * The name failed to pass the checks for any of the reasons outlined in [`check_name_validity`].
+/// * The name failed to pass the checks for any of the reasons outlined in [`validate_name`].
 /// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
 /// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The change could not be recorded for any of the reasons outlined by [`hub_changes::record`].
 /// * The user does not have permission to create new channels.
+/// * Another channel in the hub has the same name ignoring case, [`Error::NameTaken`].
 /// * The channel could not be created for any of the reasons outlined by [`Hub::new_channel`].
 pub async fn create_channel<S: Into<String> + Clone>(
     user_id: &str,
-    hub_id: ID,
+    hub_id: HubId,
     name: S,
-) -> Result<ID> {
-    check_name_validity(&name.clone().into())?;
+) -> Result<ChannelId> {
+    create_channel_from(
+        user_id,
+        hub_id,
+        NewChannel {
+            name: name.into(),
+            ..NewChannel::default()
+        },
+    )
+    .await
+}
+
+/// Creates a text channel in a hub with its description and permission overrides already set, so clients never see the channel without them.
+/// Returns the ID of the new channel if successful.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user to check for permission to create the channel.
+/// * `hub_id` - ID of the hub in which the channel should be created.
+/// * `new_channel` - Name, description and permission overrides of the new channel.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The channel could not be created for any of the reasons outlined by [`Hub::new_channel_from`], in which case nothing is created.
+/// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The change could not be recorded for any of the reasons outlined by [`hub_changes::record`].
+pub async fn create_channel_from(
+    user_id: &str,
+    hub_id: HubId,
+    new_channel: NewChannel,
+) -> Result<ChannelId> {
+    let sets_permissions = new_channel.sets_permissions();
+    let lock = hub_changes::lock(hub_id).await;
     let mut hub = Hub::load(hub_id).await?;
-    let channel_id = hub.new_channel(user_id, name.into()).await?;
+    let channel_id = hub.new_channel_from(user_id, new_channel).await?;
     hub.save().await?;
+    hub_changes::record(&lock, &hub, HubUpdateType::ChannelCreated(channel_id)).await?;
+    if sets_permissions {
+        crate::audit!(
+            actor = %user_id,
+            hub = %hub_id,
+            channel = %channel_id,
+            "Created channel with permission overrides."
+        );
+    }
     Ok(channel_id)
 }
 
@@ -423,7 +2660,7 @@ pub async fn create_channel<S: Into<String> + Clone>(
 /// * The user is not in the hub.
 /// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
 /// * The channel does not exist.
-pub async fn get_channel(user_id: &str, hub_id: ID, channel_id: ID) -> Result<Channel> {
+pub async fn get_channel(user_id: &str, hub_id: HubId, channel_id: ChannelId) -> Result<Channel> {
     let hub = Hub::load(hub_id).await?;
     Ok(hub.get_channel(user_id, channel_id)?.clone())
 }
@@ -443,23 +2680,39 @@ pub async fn get_channel(user_id: &str, hub_id: ID, channel_id: ID) -> Result<Ch
 /// This function may return an error for any of the following reasons:
 ///
 /// * THe user is not in the hub.
-/// * The name failed to pass the checks for any of the reasons outlined in [`check_name_validity`].
+/// * The name failed to pass the checks for any of the reasons outlined in [`validate_name`].
 /// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
 /// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The change could not be recorded for any of the reasons outlined by [`hub_changes::record`].
 /// * The user does not have permission to rename channels.
+/// * Another channel in the hub has the new name ignoring case, [`Error::NameTaken`].
 /// * The channel could not be renamed for any of the reasons outlined by [`Hub::rename_channel`].
+/// * The change could not be added to the hub's change history for any of the reasons outlined by [`change_history::record`].
 pub async fn rename_channel<S: Into<String> + Clone>(
     user_id: &str,
-    hub_id: ID,
-    channel_id: ID,
+    hub_id: HubId,
+    channel_id: ChannelId,
     new_name: S,
 ) -> Result<String> {
-    check_name_validity(&new_name.clone().into())?;
+    let new_name = validate_name(NameKind::Channel, &new_name.into())?;
+    let lock = hub_changes::lock(hub_id).await;
     let mut hub = Hub::load(hub_id).await?;
     let old_name = hub
-        .rename_channel(user_id, channel_id, new_name.into())
+        .rename_channel(user_id, channel_id, new_name.clone())
         .await?;
     hub.save().await?;
+    hub_changes::record(&lock, &hub, HubUpdateType::ChannelRenamed(channel_id)).await?;
+    change_history::record(
+        hub_id,
+        Change::new(
+            ChangedField::Name,
+            Some(channel_id),
+            old_name.clone(),
+            new_name,
+            user_id,
+        ),
+    )
+    .await?;
     Ok(old_name)
 }
 
@@ -478,37 +2731,116 @@ pub async fn rename_channel<S: Into<String> + Clone>(
 /// This function may return an error for any of the following reasons:
 ///
 /// * THe user is not in the hub.
-/// * The name failed to pass the checks for any of the reasons outlined in [`check_name_validity`].
+/// * The name failed to pass the checks for any of the reasons outlined in [`validate_name`].
 /// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
 /// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The change could not be recorded for any of the reasons outlined by [`hub_changes::record`].
 /// * The user does not have permission to rename channels.
 /// * The channel could not be renamed for any of the reasons outlined by [`Hub::rename_channel`].
+/// * The change could not be added to the hub's change history for any of the reasons outlined by [`change_history::record`].
 pub async fn change_channel_description<S: Into<String> + Clone>(
     user_id: &str,
-    hub_id: ID,
-    channel_id: ID,
+    hub_id: HubId,
+    channel_id: ChannelId,
     new_description: S,
 ) -> Result<String> {
     let description: String = new_description.into();
-    if description.as_bytes().len() > crate::MAX_DESCRIPTION_SIZE {
-        Err(Error::TooBig)
-    } else {
-        let mut hub = Hub::load(hub_id).await?;
-        let old_name = hub
-            .change_channel_description(user_id, channel_id, description)
-            .await?;
-        hub.save().await?;
-        Ok(old_name)
+    validate_description(DescriptionKind::Short, &description)?;
+    let lock = hub_changes::lock(hub_id).await;
+    let mut hub = Hub::load(hub_id).await?;
+    let old_name = hub
+        .change_channel_description(user_id, channel_id, description.clone())
+        .await?;
+    hub.save().await?;
+    hub_changes::record(
+        &lock,
+        &hub,
+        HubUpdateType::ChannelDescriptionUpdated(channel_id),
+    )
+    .await?;
+    change_history::record(
+        hub_id,
+        Change::new(
+            ChangedField::Description,
+            Some(channel_id),
+            old_name.clone(),
+            description,
+            user_id,
+        ),
+    )
+    .await?;
+    Ok(old_name)
+}
+
+/// Changes the long (markdown) description of a channel, see [`LongDescriptions`].
+/// Returns the previous long description of the channel if successful.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user to check for permission to change the description.
+/// * `hub_id` - ID of the hub that has the channel.
+/// * `channel_id` - ID of the channel whose description is to be changed.
+/// * `new_description` - New long description for the channel, empty to remove it.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The description is longer than allowed, see [`validate_description`].
+/// * The user is not in the hub.
+/// * The channel does not exist.
+/// * The user does not have permission to manage the channel.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The descriptions could not be loaded or saved for any of the reasons outlined by [`LongDescriptions::load`] and [`LongDescriptions::save`].
+/// * The change could not be recorded for any of the reasons outlined by [`hub_changes::record`].
+/// * The change could not be added to the hub's change history for any of the reasons outlined by [`change_history::record`].
+pub async fn change_channel_long_description(
+    user_id: &str,
+    hub_id: HubId,
+    channel_id: ChannelId,
+    new_description: String,
+) -> Result<String> {
+    validate_description(DescriptionKind::Long, &new_description)?;
+    let lock = hub_changes::lock(hub_id).await;
+    let mut hub = Hub::load(hub_id).await?;
+    let member = hub.get_member(user_id)?;
+    if !hub.channels.contains_key(&channel_id) {
+        return Err(Error::ChannelNotFound);
     }
+    check_permission!(member, channel_id, ChannelPermission::Manage, hub);
+    let mut descriptions = LongDescriptions::load(hub_id).await?;
+    let old_description = descriptions.set_channel(channel_id, new_description.clone());
+    descriptions.save(hub_id).await?;
+    hub.save().await?;
+    hub_changes::record(
+        &lock,
+        &hub,
+        HubUpdateType::ChannelDescriptionUpdated(channel_id),
+    )
+    .await?;
+    change_history::record(
+        hub_id,
+        Change::new(
+            ChangedField::LongDescription,
+            Some(channel_id),
+            old_description.clone(),
+            new_description,
+            user_id,
+        ),
+    )
+    .await?;
+    Ok(old_description)
 }
 
-/// Deletes a text channel in a hub.
+/// Deletes a text channel in a hub. The channel's folder is moved aside before the deletion is recorded, so clients that see [`HubUpdateType::ChannelDeleted`] can no longer read its messages; the [`crate::server::MessageServer`] removes it once it has closed the channel's search index.
+/// A channel with more than [`crate::config::LimitsConfig::confirm_channel_deletion_above`] messages is only deleted if `confirm` is its name.
 ///
 /// # Arguments
 ///
 /// * `user_id` - ID of the user to check for permission to delete channels.
 /// * `hub_id` - ID of the hub that has the channel.
 /// * `channel_id` - ID of the channel to be deleted.
+/// * `confirm` - Name of the channel, required if it has more messages than the configured limit.
 ///
 /// # Errors
 ///
@@ -517,12 +2849,59 @@ pub async fn change_channel_description<S: Into<String> + Clone>(
 /// * THe user is not in the hub.
 /// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
 /// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The change could not be recorded for any of the reasons outlined by [`hub_changes::record`].
 /// * The user does not have permission to delete channels.
 /// * The channel could not be deleted for any of the reasons outlined by [`Hub::delete_channel`].
-pub async fn delete_channel(user_id: &str, hub_id: ID, channel_id: ID) -> Result {
+/// * The channel has too many messages and `confirm` is not its name, [`Error::ChannelDeletionNotConfirmed`].
+/// * The channel's long description could not be removed for any of the reasons outlined by [`LongDescriptions::load`] and [`LongDescriptions::save`].
+/// * The channel's webhooks could not be removed for any of the reasons outlined by [`HubWebhooks::load`] and [`HubWebhooks::save`].
+/// * The channel's folder could not be moved aside.
+pub async fn delete_channel(
+    user_id: &str,
+    hub_id: HubId,
+    channel_id: ChannelId,
+    confirm: Option<&str>,
+) -> Result {
+    let lock = hub_changes::lock(hub_id).await;
     let mut hub = Hub::load(hub_id).await?;
-    hub.delete_channel(user_id, channel_id).await?;
-    hub.save().await
+    let channel = hub.delete_channel(user_id, channel_id).await?;
+    if let Some(limit) = crate::quotas::limits().confirm_channel_deletion_above {
+        if confirm != Some(channel.name.as_str()) && channel.has_more_messages_than(limit).await {
+            return Err(Error::ChannelDeletionNotConfirmed(limit));
+        }
+    }
+    hub.save().await?;
+    let mut descriptions = LongDescriptions::load(hub_id).await?;
+    if !descriptions
+        .set_channel(channel_id, String::new())
+        .is_empty()
+    {
+        descriptions.save(hub_id).await?;
+    }
+    {
+        let _lock = webhooks::lock(hub_id).await;
+        let mut hub_webhooks = HubWebhooks::load(hub_id).await?;
+        let count = hub_webhooks.webhooks.len();
+        hub_webhooks
+            .webhooks
+            .retain(|webhook| webhook.channel_id != channel_id);
+        if hub_webhooks.webhooks.len() != count {
+            hub_webhooks.save(hub_id).await?;
+        }
+    }
+    let deleted = crate::paths::deleted_channel_dir(hub_id, channel_id);
+    match tokio::fs::rename(crate::paths::channel_dir(hub_id, channel_id), &deleted).await {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        result => result.with_path(deleted)?,
+    }
+    hub_changes::record(&lock, &hub, HubUpdateType::ChannelDeleted(channel_id)).await?;
+    crate::audit!(
+        user = %user_id,
+        hub = %hub_id,
+        channel = %channel_id,
+        "Deleted channel."
+    );
+    Ok(())
 }
 
 /// Gets a message from a text channel in a hub.
@@ -538,29 +2917,132 @@ pub async fn delete_channel(user_id: &str, hub_id: ID, channel_id: ID) -> Result
 ///
 /// This function may return an error for any of the following reasons:
 ///
-/// * The user is not in the hub.
-/// * The channel could not be found in the hub.
-/// * The message could not be found.
-/// * The channel could not be gotten for any of the reasons outlined by [`Hub::get_channel`].
+/// * The message could not be gotten for any of the reasons outlined by [`Hub::get_readable_message`].
 /// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
 pub async fn get_message(
     user_id: &str,
-    hub_id: ID,
-    channel_id: ID,
-    message_id: ID,
+    hub_id: HubId,
+    channel_id: ChannelId,
+    message_id: MessageId,
 ) -> Result<SignedMessage> {
+    Hub::load(hub_id)
+        .await?
+        .get_readable_message(user_id, channel_id, message_id)
+        .await
+}
+
+/// Gets a message with its markdown content rendered as sanitized HTML, see [`rendering::render_message`].
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user who is requesting the message.
+/// * `hub_id` - ID of the hub where the message is located.
+/// * `channel_id` - ID of the channel where the message is located.
+/// * `message_id` - ID of the message to render.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The message could not be gotten for any of the reasons outlined by [`get_message`].
+/// * The stored message could not be read for any of the reasons outlined by [`Message::from_double_signed`].
+/// * The server was built without the `markdown` feature, [`Error::RenderingDisabled`].
+pub async fn get_message_rendered(
+    user_id: &str,
+    hub_id: HubId,
+    channel_id: ChannelId,
+    message_id: MessageId,
+) -> Result<RenderedMessage> {
+    let message = Message::try_from(&get_message(user_id, hub_id, channel_id, message_id).await?)?;
+    Ok(RenderedMessage {
+        message_id,
+        html: rendering::render_message(&message)?.to_string(),
+    })
+}
+
+/// Adds the content of messages rendered as sanitized HTML to them, see [`rendering::render_message`]. Messages that can not be read are left without it.
+///
+/// # Errors
+///
+/// This function returns [`Error::RenderingDisabled`] if the server was built without the `markdown` feature.
+pub fn render_messages(messages: &mut [ExpandedMessage]) -> Result {
+    for expanded in messages.iter_mut() {
+        if let Ok(message) = Message::try_from(&expanded.message) {
+            expanded.rendered_html = Some(rendering::render_message(&message)?.to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Gets the previous versions of a message, only its sender and users that can manage its channel can see them.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user who is requesting the history.
+/// * `hub_id` - ID of the hub where the message is located.
+/// * `channel_id` - ID of the channel where the message is located.
+/// * `message_id` - ID of the message whose history to get.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not the sender of the message and does not have permission to manage the channel.
+/// * The message could not be gotten for any of the reasons outlined by [`Hub::get_readable_message`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The history could not be loaded for any of the reasons outlined by [`MessageHistory::load`].
+pub async fn get_message_history(
+    user_id: &str,
+    hub_id: HubId,
+    channel_id: ChannelId,
+    message_id: MessageId,
+) -> Result<MessageHistory> {
     let hub = Hub::load(hub_id).await?;
-    let channel = Hub::get_channel(&hub, user_id, channel_id)?;
-    if let Some(message) = channel.get_message(message_id).await {
-        Ok(message)
-    } else {
-        Err(Error::MessageNotFound)
+    let message = hub
+        .get_readable_message(user_id, channel_id, message_id)
+        .await
+        .and_then(Message::try_from)?;
+    if message.sender != user_id {
+        let member = hub.get_member(user_id)?;
+        check_permission!(member, channel_id, ChannelPermission::Manage, hub);
     }
+    MessageHistory::load(hub_id, channel_id, message_id).await
+}
+
+/// Gets the previews of the pages linked in a message, see [`crate::unfurl`].
+/// Previews are created in the background after a message is sent, a message whose previews are not ready (or that has none) has an empty list.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user who is requesting the previews.
+/// * `hub_id` - ID of the hub where the message is located.
+/// * `channel_id` - ID of the channel where the message is located.
+/// * `message_id` - ID of the message whose previews to get.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The message could not be gotten for any of the reasons outlined by [`Hub::get_readable_message`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The previews could not be loaded for any of the reasons outlined by [`MessagePreviews::load`].
+pub async fn get_link_previews(
+    user_id: &str,
+    hub_id: HubId,
+    channel_id: ChannelId,
+    message_id: MessageId,
+) -> Result<MessagePreviews> {
+    Hub::load(hub_id)
+        .await?
+        .get_readable_message(user_id, channel_id, message_id)
+        .await?;
+    MessagePreviews::load(hub_id, channel_id, message_id).await
 }
 
 /// Gets messages sent after a given message.
 /// If successful they are returned in an array. The array is orderd oldest message to newest
 /// If there are no messages after the given message or the given message is not found, an empty array is returned.
+/// Messages sent before the user can read the history of the channel are left out, see [`Hub::history_start`].
 ///
 /// # Arguments
 ///
@@ -568,7 +3050,7 @@ pub async fn get_message(
 /// * `hub_id` - ID of the hub where the message is located.
 /// * `channel_id` - ID of the channel where the message is located.
 /// * `from` - ID of the message to start from.
-/// * `max` - The maximum number of messages to retreive.
+/// * `max` - The maximum number of messages to retreive, lowered to [`crate::MAX_MESSAGES_PER_REQUEST`] if it is larger.
 ///
 /// # Errors
 ///
@@ -580,20 +3062,75 @@ pub async fn get_message(
 /// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
 pub async fn get_messages_after(
     user_id: &str,
-    hub_id: ID,
-    channel_id: ID,
-    from: ID,
+    hub_id: HubId,
+    channel_id: ChannelId,
+    from: MessageId,
     max: usize,
 ) -> Result<Vec<SignedMessage>> {
+    let max = max.min(crate::MAX_MESSAGES_PER_REQUEST);
     let hub = Hub::load(hub_id).await?;
     let channel = Hub::get_channel(&hub, user_id, channel_id)?;
-    Ok(channel.get_messages_after(from, max).await)
+    match hub.history_start(hub.get_member(user_id)?, channel_id) {
+        Some(start) => {
+            let mut messages = channel.get_all_messages_from(from).await;
+            messages.retain(|message| message.created >= start);
+            messages.truncate(max);
+            Ok(messages)
+        }
+        None => Ok(channel.get_messages_after(from, max).await),
+    }
+}
+
+/// Joins the display data of their senders (see [`types::SenderInfo`]) onto messages of a hub.
+/// The hub and its nicknames are loaded once and each sender is only looked up once, however many messages they sent.
+/// Bot and webhook messages are shown with the name and avatar they were sent with, see [`SenderInfo::for_message`].
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user who is requesting the messages, must be in the hub.
+/// * `hub_id` - ID of the hub the messages were sent in.
+/// * `messages` - The messages, already checked to be readable by the user.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user is not in the hub.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The nicknames could not be loaded for any of the reasons outlined by [`HubNicknames::load`].
+pub async fn expand_senders(
+    user_id: &str,
+    hub_id: HubId,
+    messages: Vec<SignedMessage>,
+) -> Result<Vec<ExpandedMessage>> {
+    let hub = Hub::load(hub_id).await?;
+    hub.get_member(user_id)?;
+    let nicknames = HubNicknames::load(hub_id).await?;
+    let mut senders: HashMap<String, SenderInfo> = HashMap::new();
+    Ok(messages
+        .into_iter()
+        .map(|message| {
+            let sender = Message::try_from(&message).ok().map(|content| {
+                senders
+                    .entry(content.sender.clone())
+                    .or_insert_with_key(|sender| SenderInfo::new(&hub, &nicknames, sender))
+                    .clone()
+                    .for_message(&content)
+            });
+            ExpandedMessage {
+                message,
+                sender,
+                rendered_html: None,
+            }
+        })
+        .collect())
 }
 
-/// Gets a set of messages between two times (both in milliseconds since Unix Epoch).
+/// Gets a set of messages sent between two times.
 /// If successful they are returned in an array. The array is orderd oldest message to newest
 /// unless the `invert` argument is `true` in which case the order is newest to oldest message.
 /// If there are no messages in the given time frame, an empty array is returned.
+/// The time frame starts no earlier than the time the user can read the history of the channel from, see [`Hub::history_start`].
 ///
 /// # Arguments
 ///
@@ -603,27 +3140,38 @@ pub async fn get_messages_after(
 /// * `from` - Earliest time a message can be sent to be included in the results.
 /// * `to` - Latest time a message can be sent to be included in the results.
 /// * `invert` - If true the search is done from newest message to oldest message, if false the search is done from oldest message to newest message.
-/// * `max` - The maximum number of messages to retreive.
+///   When there are more than `max` messages in the range this decides whether the newest or the oldest are returned.
+/// * `max` - The maximum number of messages to retreive, lowered to [`crate::MAX_MESSAGES_PER_REQUEST`] if it is larger.
 ///
 /// # Errors
 ///
 /// This function may return an error for any of the following reasons:
 ///
+/// * `from` is later than `to`.
 /// * The user is not in the hub.
 /// * The channel could not be found in the hub.
 /// * The channel could not be gotten for any of the reasons outlined by [`Hub::get_channel`].
 /// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
 pub async fn get_messages(
     user_id: &str,
-    hub_id: ID,
-    channel_id: ID,
+    hub_id: HubId,
+    channel_id: ChannelId,
     from: DateTime<Utc>,
     to: DateTime<Utc>,
     invert: bool,
     max: usize,
 ) -> Result<Vec<SignedMessage>> {
+    if from > to {
+        return Err(Error::InvalidTimeRange);
+    }
+    let max = max.min(crate::MAX_MESSAGES_PER_REQUEST);
     let hub = Hub::load(hub_id).await?;
     let channel = Hub::get_channel(&hub, user_id, channel_id)?;
+    let from = match hub.history_start(hub.get_member(user_id)?, channel_id) {
+        Some(start) if start > to => return Ok(Vec::new()),
+        Some(start) => from.max(start),
+        None => from,
+    };
     Ok(channel.get_messages_between(from, to, invert, max).await)
 }
 
@@ -636,6 +3184,7 @@ pub async fn get_messages(
 /// * `member_id` - The hub member whose permissions are being changed.
 /// * `permission` - The permission whose setting is being changed.
 /// * `value` - The new setting for the permission.
+/// * `expected_version` - Version the hub must be at for the change to be made (from an `If-Match` header), `None` to make it regardless.
 ///
 /// # Errors
 ///
@@ -645,22 +3194,42 @@ pub async fn get_messages(
 /// * The user whose permission is being changed is not in the hub.
 /// * The user making the change does not have permission to do so.
 /// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The change could not be recorded for any of the reasons outlined by [`hub_changes::record`].
+/// * The hub is not at `expected_version`, see [`Hub::check_version`].
 /// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
 pub async fn set_member_hub_permission(
     user_id: &str,
-    hub_id: ID,
+    hub_id: HubId,
     member_id: &str,
     permission: HubPermission,
     value: PermissionSetting,
+    expected_version: Option<u64>,
 ) -> Result {
+    let lock = hub_changes::lock(hub_id).await;
     let mut hub = Hub::load(hub_id).await?;
     {
         let member = hub.get_member(user_id)?;
         check_permission!(member, HubPermission::Administrate, hub);
     }
+    hub.check_version(expected_version)?;
     let member = hub.get_member_mut(member_id)?;
     member.set_permission(permission, value);
-    hub.save().await
+    hub.save().await?;
+    hub_changes::record(
+        &lock,
+        &hub,
+        HubUpdateType::UserHubPermissionChanged(member_id.to_string()),
+    )
+    .await?;
+    crate::audit!(
+        actor = %user_id,
+        hub = %hub_id,
+        member = %member_id,
+        permission = %permission,
+        value = ?value,
+        "Changed hub permission."
+    );
+    Ok(())
 }
 
 /// Sets a channel specific permission for a hub member.
@@ -673,6 +3242,7 @@ pub async fn set_member_hub_permission(
 /// * `channel_id` - The channel that the change should apply to.
 /// * `permission` - The permission whose setting is being changed.
 /// * `value` - The new setting for the permission.
+/// * `expected_version` - Version the hub must be at for the change to be made (from an `If-Match` header), `None` to make it regardless.
 ///
 /// # Errors
 ///
@@ -682,21 +3252,116 @@ pub async fn set_member_hub_permission(
 /// * The user whose permission is being changed is not in the hub.
 /// * The user making the change does not have permission to do so.
 /// * The hub could not be saved for any of the reasons outlined by [`Hub::save`].
+/// * The change could not be recorded for any of the reasons outlined by [`hub_changes::record`].
+/// * The hub is not at `expected_version`, see [`Hub::check_version`].
 /// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
 pub async fn set_member_channel_permission(
     user_id: &str,
-    hub_id: ID,
+    hub_id: HubId,
     member_id: &str,
-    channel_id: ID,
+    channel_id: ChannelId,
     permission: ChannelPermission,
     value: PermissionSetting,
+    expected_version: Option<u64>,
 ) -> Result {
+    let lock = hub_changes::lock(hub_id).await;
     let mut hub = Hub::load(hub_id).await?;
     {
         let member = hub.get_member(user_id)?;
         check_permission!(member, HubPermission::Administrate, hub);
     }
+    hub.check_version(expected_version)?;
     let member = hub.get_member_mut(member_id)?;
     member.set_channel_permission(channel_id, permission, value);
-    hub.save().await
+    hub.save().await?;
+    hub_changes::record(
+        &lock,
+        &hub,
+        HubUpdateType::UserChannelPermissionChanged(member_id.to_string(), channel_id),
+    )
+    .await?;
+    crate::audit!(
+        actor = %user_id,
+        hub = %hub_id,
+        member = %member_id,
+        channel = %channel_id,
+        permission = %permission,
+        value = ?value,
+        "Changed channel permission."
+    );
+    Ok(())
+}
+
+/// Copies the channel permission overrides of every member and group from one channel to others, see [`Hub::copy_channel_permissions`].
+/// Returns the channels the overrides were copied to.
+///
+/// # Arguments
+///
+/// * `user_id` - ID of the user who is making the change.
+/// * `hub_id` - The hub in which the change is being made.
+/// * `source` - The channel to copy the overrides from.
+/// * `targets` - The channels to copy the overrides to, the source channel and repeated channels are skipped.
+/// * `merge` - Whether to keep the overrides of the targets for permissions that are not set in the source channel.
+/// * `expected_version` - Version the hub must be at for the change to be made (from an `If-Match` header), `None` to make it regardless.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons.
+///
+/// * The user making the change is not in the hub.
+/// * The source channel or one of the target channels does not exist.
+/// * The user making the change does not have permission to change permissions or to manage one of the target channels.
+/// * The hub is not at `expected_version`, see [`Hub::check_version`].
+/// * The hub could not be loaded or saved for any of the reasons outlined by [`Hub::load`] and [`Hub::save`].
+/// * The changes could not be recorded for any of the reasons outlined by [`hub_changes::record`].
+pub async fn copy_channel_permissions(
+    user_id: &str,
+    hub_id: HubId,
+    source: ChannelId,
+    targets: &[ChannelId],
+    merge: bool,
+    expected_version: Option<u64>,
+) -> Result<Vec<ChannelId>> {
+    let lock = hub_changes::lock(hub_id).await;
+    let mut hub = Hub::load(hub_id).await?;
+    let mut copied = Vec::new();
+    {
+        let member = hub.get_member(user_id)?;
+        check_permission!(member, HubPermission::Administrate, hub);
+        if !hub.channels.contains_key(&source) {
+            return Err(Error::ChannelNotFound);
+        }
+        for target in targets {
+            if *target == source || copied.contains(target) {
+                continue;
+            }
+            if !hub.channels.contains_key(target) {
+                return Err(Error::ChannelNotFound);
+            }
+            check_permission!(member, *target, ChannelPermission::Manage, hub);
+            copied.push(*target);
+        }
+    }
+    hub.check_version(expected_version)?;
+    for target in copied.iter() {
+        hub.copy_channel_permissions(source, *target, merge);
+    }
+    hub.save().await?;
+    for target in copied.iter() {
+        hub_changes::record(
+            &lock,
+            &hub,
+            HubUpdateType::ChannelPermissionsChanged(*target),
+        )
+        .await?;
+    }
+    crate::audit!(
+        actor = %user_id,
+        hub = %hub_id,
+        source = %source,
+        targets = ?copied,
+        merge,
+        "Copied channel permissions."
+    );
+    Ok(copied)
 }
@@ -0,0 +1,592 @@
+//! Response types for the HTTP API.
+//!
+//! These are what clients see, the internal types in [`crate::hub`] and [`crate::channel`] can change freely as long as the conversions here keep producing the same JSON.
+//! Fields use `snake_case` names, within a major version fields are only ever added, never renamed or removed.
+//! Internal data (bans, mutes and permission settings) is deliberately left out.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    channel::{AuthorType, Channel, ForwardedFrom, Message, SignedMessage},
+    error::Error,
+    hub::{Hub, HubMember},
+    nicknames::HubNicknames,
+    permission::{ChannelPermission, HubPermission},
+    ChannelId, HubId, MessageId, Result, ID,
+};
+
+/// Public information about a hub.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct HubInfo {
+    /// ID of the hub.
+    pub id: HubId,
+    /// Name of the hub.
+    pub name: String,
+    /// Description of the hub.
+    pub description: String,
+    /// ID of the user that owns the hub.
+    pub owner: String,
+    /// ID of the permission group that all members are in.
+    pub default_group: ID,
+    /// Time the hub was created.
+    pub created: DateTime<Utc>,
+    /// Channels in the hub, sorted by ID.
+    pub channels: Vec<ChannelInfo>,
+    /// Members of the hub, sorted by user ID.
+    pub members: Vec<HubMemberInfo>,
+}
+
+impl From<&Hub> for HubInfo {
+    fn from(hub: &Hub) -> Self {
+        let mut channels: Vec<ChannelInfo> = hub.channels.values().map(Into::into).collect();
+        channels.sort_by_key(|channel| channel.id);
+        let mut members: Vec<HubMemberInfo> = hub.members.values().map(Into::into).collect();
+        members.sort_by(|a, b| a.user_id.cmp(&b.user_id));
+        Self {
+            id: hub.id,
+            name: hub.name.clone(),
+            description: hub.description.clone(),
+            owner: hub.owner.clone(),
+            default_group: hub.default_group,
+            created: hub.created,
+            channels,
+            members,
+        }
+    }
+}
+
+/// Public information about a member of a hub.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct HubMemberInfo {
+    /// ID of the user.
+    pub user_id: String,
+    /// ID of the hub the user is a member of.
+    pub hub_id: HubId,
+    /// Time the user joined the hub.
+    pub joined: DateTime<Utc>,
+    /// IDs of the permission groups the user is in.
+    pub groups: Vec<ID>,
+}
+
+impl From<&HubMember> for HubMemberInfo {
+    fn from(member: &HubMember) -> Self {
+        Self {
+            user_id: member.user_id.clone(),
+            hub_id: member.hub,
+            joined: member.joined,
+            groups: member.groups.clone(),
+        }
+    }
+}
+
+/// A page of a hub's members, see [`crate::hub::Hub::list_members`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct MemberList {
+    /// Members on this page.
+    pub members: Vec<HubMemberInfo>,
+    /// Offset of the next page, `null` if this is the last one.
+    pub next_offset: Option<usize>,
+    /// When members are grouped by hoisted groups, which members of this page are listed under which group.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sections: Vec<MemberSection>,
+}
+
+/// Members of a [`MemberList`] page that are listed under the same hoisted group, see [`crate::group_display::HubGroupDisplay::hoisted_group`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct MemberSection {
+    /// ID of the hoisted group, `null` for the members that are not in any hoisted group.
+    pub group_id: Option<ID>,
+    /// Number of consecutive members of the page in the section, sections are in the order of the members.
+    pub count: usize,
+}
+
+/// Public information about a channel.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct ChannelInfo {
+    /// ID of the channel.
+    pub id: ChannelId,
+    /// ID of the hub the channel is in.
+    pub hub_id: HubId,
+    /// Name of the channel.
+    pub name: String,
+    /// Description of the channel.
+    pub description: String,
+    /// Time the channel was created.
+    pub created: DateTime<Utc>,
+}
+
+impl From<&Channel> for ChannelInfo {
+    fn from(channel: &Channel) -> Self {
+        Self {
+            id: channel.id,
+            hub_id: channel.hub_id,
+            name: channel.name.clone(),
+            description: channel.description.clone(),
+            created: channel.created,
+        }
+    }
+}
+
+/// A chat message.
+/// Clients sign this and send it back to the server, so it must always deserialize as a [`Message`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct MessageInfo {
+    /// ID of the message.
+    pub id: MessageId,
+    /// ID of the hub the message was sent in.
+    pub hub_id: HubId,
+    /// ID of the channel the message was sent in.
+    pub channel_id: ChannelId,
+    /// ID of the user that sent the message.
+    pub sender: String,
+    /// Time the message was sent.
+    pub created: DateTime<Utc>,
+    /// Content of the message.
+    pub content: String,
+    /// IDs of the permission groups mentioned in the message.
+    pub group_mentions: Vec<ID>,
+    /// Where the message was forwarded from, `null` if it is not a forward.
+    pub forwarded_from: Option<ForwardedFrom>,
+    /// Flags of the message, see [`Message::flags`].
+    pub flags: u32,
+    /// What kind of author sent the message, see [`Message::author_type`].
+    pub author_type: AuthorType,
+    /// Name to show instead of the sender's nickname, only bot and webhook messages can have one.
+    pub override_name: Option<String>,
+    /// URL of the avatar to show instead of the sender's, only bot and webhook messages can have one.
+    pub override_avatar: Option<String>,
+}
+
+impl From<&Message> for MessageInfo {
+    fn from(message: &Message) -> Self {
+        Self {
+            id: message.id,
+            hub_id: message.hub_id,
+            channel_id: message.channel_id,
+            sender: message.sender.clone(),
+            created: message.created,
+            content: message.content.clone(),
+            group_mentions: message.group_mentions.clone(),
+            forwarded_from: message.forwarded_from.clone(),
+            flags: message.flags,
+            author_type: message.author_type,
+            override_name: message.override_name.clone(),
+            override_avatar: message.override_avatar.clone(),
+        }
+    }
+}
+
+/// What clients need to show the sender of a message, joined onto messages when they ask for it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct SenderInfo {
+    /// ID of the user.
+    pub id: String,
+    /// Nickname of the user in the hub, `null` if they have none or are no longer in the hub.
+    pub nickname: Option<String>,
+    /// False if the user has left or was removed from the hub, clients should show them as a former member.
+    pub in_hub: bool,
+    /// What kind of author sent the message, see [`Message::author_type`].
+    pub author_type: AuthorType,
+    /// Name to show instead of the nickname, set by the bot or webhook that sent the message.
+    pub override_name: Option<String>,
+    /// URL of the avatar to show, set by the bot or webhook that sent the message.
+    pub override_avatar: Option<String>,
+}
+
+impl SenderInfo {
+    /// Gets the display data of a user in a hub, users that are not in the hub get a placeholder without a nickname.
+    pub fn new(hub: &Hub, nicknames: &HubNicknames, user_id: &str) -> Self {
+        let in_hub = hub.members.contains_key(user_id);
+        Self {
+            id: user_id.to_string(),
+            nickname: if in_hub {
+                nicknames.nicknames.get(user_id).cloned()
+            } else {
+                None
+            },
+            in_hub,
+            author_type: AuthorType::User,
+            override_name: None,
+            override_avatar: None,
+        }
+    }
+
+    /// Gets the display data of the sender of one message, bot and webhook messages can override the name and avatar.
+    pub fn for_message(self, message: &Message) -> Self {
+        Self {
+            author_type: message.author_type,
+            override_name: message.override_name.clone(),
+            override_avatar: message.override_avatar.clone(),
+            ..self
+        }
+    }
+}
+
+/// A hub owned by the user that requested it, served by `/v3/owned_hubs`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct OwnedHub {
+    pub id: HubId,
+    pub name: String,
+    /// Number of members of the hub, including the owner.
+    pub members: usize,
+}
+
+/// A hub a bulk change could not be made to, see [`BulkHubResult`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct HubFailure {
+    pub hub_id: HubId,
+    /// Why the change was not made, the same text an error response would have.
+    pub error: String,
+}
+
+/// Which hubs a change was made to when it was requested for many hubs at once, served by `/v3/leave_all_hubs` and `/v3/bulk_transfer_hubs`.
+/// Each hub is changed on its own, a hub that fails does not stop the others.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct BulkHubResult {
+    pub succeeded: Vec<HubId>,
+    pub failed: Vec<HubFailure>,
+}
+
+impl BulkHubResult {
+    /// Adds the outcome of the change to one hub.
+    pub fn push(&mut self, hub_id: HubId, result: Result) {
+        match result {
+            Ok(()) => self.succeeded.push(hub_id),
+            Err(err) => self.failed.push(HubFailure {
+                hub_id,
+                error: err.to_string(),
+            }),
+        }
+    }
+}
+
+/// A member a bulk change could not be made to, see [`BulkMemberResult`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct MemberFailure {
+    pub user_id: String,
+    /// Why the change was not made, the same text an error response would have.
+    pub error: String,
+}
+
+/// Which members a change was made to when it was requested for many members at once, served by `/v3/kick_invite_members/{hub_id}/{code}`.
+/// Each member is changed on its own, a member that fails does not stop the others.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct BulkMemberResult {
+    pub succeeded: Vec<String>,
+    pub failed: Vec<MemberFailure>,
+}
+
+impl BulkMemberResult {
+    /// Adds the outcome of the change to one member.
+    pub fn push(&mut self, user_id: String, result: Result) {
+        match result {
+            Ok(()) => self.succeeded.push(user_id),
+            Err(err) => self.failed.push(MemberFailure {
+                user_id,
+                error: err.to_string(),
+            }),
+        }
+    }
+}
+
+/// A message with the display data of its sender.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct ExpandedMessage {
+    #[serde(flatten)]
+    pub message: SignedMessage,
+    /// Sender of the message, `null` if the message could not be read.
+    pub sender: Option<SenderInfo>,
+    /// Content of the message rendered as sanitized HTML, only included if it was asked for, see [`crate::rendering`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rendered_html: Option<String>,
+}
+
+/// The permissions a member has after their own settings, the settings of their groups and the hub's ownership are applied, served by `/v3/effective_permissions/{hub_id}`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct EffectivePermissions {
+    /// Hub permissions the member has, [`HubPermission::ReadHistory`] depends on the channel and is only listed per channel.
+    pub hub: Vec<HubPermission>,
+    /// Channel permissions the member has in each channel they can read.
+    pub channels: HashMap<ChannelId, Vec<ChannelPermission>>,
+}
+
+/// Selected top-level fields of a hub's [`HubInfo`], served by `/v3/hub/{hub_id}`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HubFields {
+    /// Version of the hub the fields were read at, always included so that a partial hub can still be checked against the hub's ETag.
+    pub version: u64,
+    /// The requested fields, under the same names as in [`HubInfo`].
+    #[serde(flatten)]
+    pub fields: serde_json::Map<String, serde_json::Value>,
+}
+
+impl HubFields {
+    /// Keeps only the given comma separated fields of a hub's [`HubInfo`], every field if `fields` is `None`.
+    /// Fields that are not part of [`HubInfo`] (bans, mutes and permission settings) can not be selected.
+    ///
+    /// # Errors
+    ///
+    /// This function may return an error for any of the following reasons:
+    ///
+    /// * A requested field is not a field of [`HubInfo`], [`Error::UnknownField`].
+    /// * The hub could not be serialized.
+    pub fn select(hub: &Hub, version: u64, fields: Option<&str>) -> Result<Self> {
+        let all = match serde_json::to_value(HubInfo::from(hub))? {
+            serde_json::Value::Object(all) => all,
+            _ => unreachable!("hub infos serialize to JSON objects"),
+        };
+        let fields = match fields {
+            Some(fields) => fields,
+            None => {
+                return Ok(Self {
+                    version,
+                    fields: all,
+                })
+            }
+        };
+        let mut selected = serde_json::Map::new();
+        for field in fields.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+            match all.get(field) {
+                Some(value) => {
+                    selected.insert(field.to_string(), value.clone());
+                }
+                None => {
+                    let mut valid: Vec<&str> = all.keys().map(String::as_str).collect();
+                    valid.sort_unstable();
+                    return Err(Error::UnknownField(field.to_string(), valid.join(", ")));
+                }
+            }
+        }
+        Ok(Self {
+            version,
+            fields: selected,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+    use serde_json::json;
+
+    use super::{ChannelInfo, HubFields, HubInfo, HubMemberInfo, MessageInfo, SenderInfo};
+    use crate::{
+        channel::{AuthorType, Channel, Message},
+        hub::Hub,
+        nicknames::HubNicknames,
+        ChannelId, HubId, MessageId,
+    };
+
+    const USER: &str = "0123456789ABCDEF0123456789ABCDEF01234567";
+
+    #[test]
+    fn hub_info_schema() {
+        let created = Utc.with_ymd_and_hms(2021, 4, 20, 12, 0, 0).unwrap();
+        let hub_id = HubId::from_u128(1);
+        let mut hub = Hub::new("hub".to_string(), hub_id, USER.to_string());
+        hub.created = created;
+        hub.description = "A hub.".to_string();
+        hub.bans.insert("banned".to_string());
+        let mut channel = Channel::new("chat".to_string(), ChannelId::from_u128(2), hub_id);
+        channel.created = created;
+        hub.channels.insert(channel.id, channel);
+        let member = hub.members.get_mut(USER).unwrap();
+        member.joined = created;
+        let default_group = hub.default_group;
+
+        assert_eq!(
+            serde_json::to_value(HubInfo::from(&hub)).unwrap(),
+            json!({
+                "id": "00000000-0000-0000-0000-000000000001",
+                "name": "hub",
+                "description": "A hub.",
+                "owner": USER,
+                "default_group": default_group.to_string(),
+                "created": "2021-04-20T12:00:00Z",
+                "channels": [{
+                    "id": "00000000-0000-0000-0000-000000000002",
+                    "hub_id": "00000000-0000-0000-0000-000000000001",
+                    "name": "chat",
+                    "description": "",
+                    "created": "2021-04-20T12:00:00Z"
+                }],
+                "members": [{
+                    "user_id": USER,
+                    "hub_id": "00000000-0000-0000-0000-000000000001",
+                    "joined": "2021-04-20T12:00:00Z",
+                    "groups": [default_group.to_string()]
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn channel_and_member_info_schema() {
+        let created = Utc.with_ymd_and_hms(2021, 4, 20, 12, 0, 0).unwrap();
+        let hub_id = HubId::from_u128(1);
+        let mut channel = Channel::new("chat".to_string(), ChannelId::from_u128(2), hub_id);
+        channel.created = created;
+        assert_eq!(
+            serde_json::to_value(ChannelInfo::from(&channel)).unwrap(),
+            json!({
+                "id": "00000000-0000-0000-0000-000000000002",
+                "hub_id": "00000000-0000-0000-0000-000000000001",
+                "name": "chat",
+                "description": "",
+                "created": "2021-04-20T12:00:00Z"
+            })
+        );
+
+        let mut member = crate::hub::HubMember::new(USER.to_string(), hub_id);
+        member.joined = created;
+        assert_eq!(
+            serde_json::to_value(HubMemberInfo::from(&member)).unwrap(),
+            json!({
+                "user_id": USER,
+                "hub_id": "00000000-0000-0000-0000-000000000001",
+                "joined": "2021-04-20T12:00:00Z",
+                "groups": []
+            })
+        );
+    }
+
+    #[test]
+    fn message_info_schema() {
+        let mut message = Message::new(
+            USER.to_string(),
+            "Hello.".to_string(),
+            HubId::from_u128(1),
+            ChannelId::from_u128(2),
+        );
+        message.id = MessageId::from_u128(3);
+        message.created = Utc.with_ymd_and_hms(2021, 4, 20, 12, 0, 0).unwrap();
+        // Bit 7 is not a known flag, it has to survive the round trip.
+        message.flags = Message::SILENT | 1 << 7;
+        let info = MessageInfo::from(&message);
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "id": "00000000-0000-0000-0000-000000000003",
+                "hub_id": "00000000-0000-0000-0000-000000000001",
+                "channel_id": "00000000-0000-0000-0000-000000000002",
+                "sender": USER,
+                "created": "2021-04-20T12:00:00Z",
+                "content": "Hello.",
+                "group_mentions": [],
+                "forwarded_from": null,
+                "flags": 129,
+                "author_type": "user",
+                "override_name": null,
+                "override_avatar": null
+            })
+        );
+        assert_eq!(serde_json::from_value::<Message>(json).unwrap(), message);
+    }
+
+    #[test]
+    fn sender_info_schema() {
+        let hub = Hub::new("hub".to_string(), HubId::from_u128(1), USER.to_string());
+        let mut nicknames = HubNicknames::default();
+        nicknames
+            .nicknames
+            .insert(USER.to_string(), "Owner".to_string());
+        nicknames
+            .nicknames
+            .insert("left".to_string(), "Gone".to_string());
+        assert_eq!(
+            serde_json::to_value(SenderInfo::new(&hub, &nicknames, USER)).unwrap(),
+            json!({
+                "id": USER,
+                "nickname": "Owner",
+                "in_hub": true,
+                "author_type": "user",
+                "override_name": null,
+                "override_avatar": null
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(SenderInfo::new(&hub, &nicknames, "left")).unwrap(),
+            json!({
+                "id": "left",
+                "nickname": null,
+                "in_hub": false,
+                "author_type": "user",
+                "override_name": null,
+                "override_avatar": null
+            })
+        );
+        let mut message = Message::new(
+            USER.to_string(),
+            "Deployed.".to_string(),
+            HubId::from_u128(1),
+            ChannelId::from_u128(2),
+        );
+        message
+            .set_author(
+                AuthorType::Webhook,
+                Some("  CI   bot "),
+                Some("https://example.com/ci.png"),
+            )
+            .unwrap();
+        let sender = SenderInfo::new(&hub, &nicknames, USER).for_message(&message);
+        assert_eq!(sender.author_type, AuthorType::Webhook);
+        assert_eq!(sender.override_name.as_deref(), Some("CI bot"));
+        assert!(message
+            .clone()
+            .set_author(AuthorType::Bot, None, Some("http://example.com/ci.png"))
+            .is_err());
+        assert!(message
+            .set_author(AuthorType::User, Some("Owner"), None)
+            .is_err());
+    }
+
+    #[test]
+    fn hub_fields_leave_out_unrequested_fields() {
+        let hub_id = HubId::from_u128(1);
+        let mut hub = Hub::new("hub".to_string(), hub_id, USER.to_string());
+        let channel = Channel::new("chat".to_string(), ChannelId::from_u128(2), hub_id);
+        hub.channels.insert(channel.id, channel);
+
+        let fields = HubFields::select(&hub, 7, Some("name, channels,name")).unwrap();
+        let body = serde_json::to_value(&fields).unwrap();
+        let body = body.as_object().unwrap();
+        assert_eq!(body["version"], json!(7));
+        assert_eq!(body["name"], json!("hub"));
+        assert_eq!(
+            body["channels"][0]["id"],
+            json!(ChannelId::from_u128(2).to_string())
+        );
+        assert!(!body.contains_key("members"));
+        assert!(!body.contains_key("groups"));
+        assert_eq!(body.len(), 3);
+
+        let all = HubFields::select(&hub, 7, None).unwrap();
+        assert!(all.fields.contains_key("members"));
+        assert!(!all.fields.contains_key("bans"));
+        assert!(!all.fields.contains_key("mutes"));
+        assert!(!all.fields.contains_key("groups"));
+
+        let err = HubFields::select(&hub, 7, Some("name,passwords")).unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("\"passwords\""));
+        assert!(message.contains("channels, created, default_group, description"));
+    }
+}
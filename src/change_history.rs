@@ -0,0 +1,171 @@
+use std::{collections::VecDeque, path::PathBuf};
+
+use crate::{error::IoContext, locks::KeyedLocks, ChannelId, HubId, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Folder where the name and description changes of each hub are stored.
+pub const CHANGE_HISTORY_FOLDER: &str = "data/hubs/change_history/";
+
+/// Number of changes kept for each hub, older changes are forgotten.
+pub const MAX_CHANGE_HISTORY: usize = 50;
+
+/// Makes sure only one change is added to the history of a hub at a time, see [`record`].
+static HISTORY_LOCKS: KeyedLocks<HubId> = KeyedLocks::new();
+
+/// What was changed, of the hub or of one of its channels.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangedField {
+    Name,
+    Description,
+    /// The markdown description, see [`crate::descriptions`].
+    LongDescription,
+}
+
+/// A change to the name or a description of a hub or one of its channels.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Change {
+    pub field: ChangedField,
+    /// ID of the channel that was changed, `None` if the hub itself was changed.
+    pub channel_id: Option<ChannelId>,
+    pub old: String,
+    pub new: String,
+    /// ID of the user that made the change.
+    pub actor: String,
+    pub time: DateTime<Utc>,
+}
+
+impl Change {
+    /// Creates a change made now.
+    pub fn new(
+        field: ChangedField,
+        channel_id: Option<ChannelId>,
+        old: String,
+        new: String,
+        actor: &str,
+    ) -> Self {
+        Self {
+            field,
+            channel_id,
+            old,
+            new,
+            actor: actor.to_string(),
+            time: Utc::now(),
+        }
+    }
+}
+
+/// The last (at most [`MAX_CHANGE_HISTORY`]) name and description changes of a hub and its channels, oldest first.
+/// Stored separately from the hub so that hubs saved before it existed can still be loaded.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ChangeHistory {
+    pub changes: VecDeque<Change>,
+}
+
+impl ChangeHistory {
+    /// Gets the path of the file that a hub's change history is stored in.
+    pub fn get_path(hub_id: HubId) -> PathBuf {
+        crate::paths::change_history_file(hub_id)
+    }
+
+    /// Loads the change history of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The file exists but could not be read.
+    /// * The file is encrypted and could not be decrypted, see [`crate::encryption::open`].
+    /// * The file's contents could not be deserialized.
+    pub async fn load(hub_id: HubId) -> Result<Self> {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(bincode::deserialize(&crate::encryption::open(&bytes)?)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_path(path),
+        }
+    }
+
+    /// Saves the change history of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The change history folder does not exist and could not be created.
+    /// * The data could not be encrypted, see [`crate::encryption::seal`].
+    /// * The data could not be written to the disk.
+    pub async fn save(&self, hub_id: HubId) -> Result {
+        tokio::fs::create_dir_all(CHANGE_HISTORY_FOLDER)
+            .await
+            .with_path(CHANGE_HISTORY_FOLDER)?;
+        let path = Self::get_path(hub_id);
+        tokio::fs::write(&path, crate::encryption::seal(bincode::serialize(self)?)?)
+            .await
+            .with_path(path)
+    }
+
+    /// Removes the change history of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the file exists but could not be removed.
+    pub async fn remove(hub_id: HubId) -> Result {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::remove_file(&path).await {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result.with_path(path),
+        }
+    }
+
+    /// Adds a change, forgetting the oldest ones once there are more than [`MAX_CHANGE_HISTORY`].
+    pub fn push(&mut self, change: Change) {
+        self.changes.push_back(change);
+        while self.changes.len() > MAX_CHANGE_HISTORY {
+            self.changes.pop_front();
+        }
+    }
+}
+
+/// Adds a change to the history of a hub, changes that leave the value as it was are not recorded.
+///
+/// # Errors
+///
+/// This function returns an error if the history could not be loaded or saved, see [`ChangeHistory::load`] and [`ChangeHistory::save`].
+pub async fn record(hub_id: HubId, change: Change) -> Result {
+    if change.old == change.new {
+        return Ok(());
+    }
+    let _guard = HISTORY_LOCKS.lock(hub_id).await;
+    let mut history = ChangeHistory::load(hub_id).await?;
+    history.push(change);
+    history.save(hub_id).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Change, ChangeHistory, ChangedField, MAX_CHANGE_HISTORY};
+
+    #[test]
+    fn keeps_latest_changes() {
+        let mut history = ChangeHistory::default();
+        for i in 0..MAX_CHANGE_HISTORY + 5 {
+            history.push(Change::new(
+                ChangedField::Name,
+                None,
+                i.to_string(),
+                (i + 1).to_string(),
+                "admin",
+            ));
+        }
+        assert_eq!(history.changes.len(), MAX_CHANGE_HISTORY);
+        assert_eq!(history.changes[0].old, "5");
+        assert_eq!(
+            serde_json::to_string(&ChangedField::LongDescription).unwrap(),
+            "\"long_description\""
+        );
+    }
+}
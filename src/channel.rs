@@ -1,34 +1,56 @@
-use std::{convert::TryFrom, str::FromStr};
+use std::{
+    collections::HashSet,
+    convert::TryFrom,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-use chrono::{DateTime, Utc};
-use tokio::fs;
+use chrono::{DateTime, NaiveDate, Utc};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+use tracing::warn;
 
 use serde::{Deserialize, Serialize};
 
 use fs::OpenOptions;
 
-use crate::{error::Error, hub::HUB_DATA_FOLDER, new_id, Result, ID};
+use crate::{
+    config::NameRules,
+    error::{Error, IoContext},
+    locks::KeyedLocks,
+    validation::{name_rules, validate_name_with, NameKind},
+    ChannelId, HubId, MessageId, Result, ID,
+};
 
-use async_graphql::SimpleObject;
+/// Maximum length in characters of the name a bot or webhook message is shown with, see [`Message::set_author`].
+pub const MAX_OVERRIDE_NAME_LENGTH: usize = 32;
+
+/// Maximum length in bytes of the avatar URL a bot or webhook message is shown with, see [`Message::set_author`].
+pub const MAX_OVERRIDE_AVATAR_LENGTH: usize = 512;
+
+#[cfg(feature = "graphql")]
+use async_graphql::{Enum, SimpleObject};
 
 /// Text channel, used to group a manage sets of messages.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Channel {
     /// ID of the channel.
-    pub id: ID,
+    pub id: ChannelId,
     /// ID of the Hub that the channel belongs to.
-    pub hub_id: ID,
+    pub hub_id: HubId,
     /// Description of the channel.
     pub description: String,
     /// Name of the channel.
     pub name: String,
-    /// Date the channel was created in milliseconds since Unix Epoch.
+    /// Time the channel was created.
     pub created: DateTime<Utc>,
 }
 
 impl Channel {
     /// Creates a new channel object based on parameters.
-    pub fn new(name: String, id: ID, hub_id: ID) -> Self {
+    pub fn new(name: String, id: ChannelId, hub_id: HubId) -> Self {
         Self {
             name,
             id,
@@ -39,22 +61,20 @@ impl Channel {
     }
 
     /// Get the path of the channel's data folder, used for storing message files.
-    pub fn get_folder(&self) -> String {
-        format!(
-            "{}{:x}/{:x}",
-            HUB_DATA_FOLDER,
-            self.hub_id.as_u128(),
-            self.id.as_u128()
-        )
+    pub fn get_folder(&self) -> PathBuf {
+        crate::paths::channel_dir(self.hub_id, self.id)
     }
 
     /// Creates the channel data folder.
     pub async fn create_dir(&self) -> Result {
-        tokio::fs::create_dir_all(self.get_folder()).await?;
+        let folder = self.get_folder();
+        tokio::fs::create_dir_all(&folder).await.with_path(folder)?;
         Ok(())
     }
 
-    /// Adds a message to the channel, writes it to the file corresponding to the day the message was sent, one file per day of messages, only created if a message is sent that day.
+    /// Adds a message to the channel, writes it to the file corresponding to the day the message was stored, one file per day of messages, only created if a message is sent that day.
+    /// Writes to a channel are done one at a time, so the order messages are stored in (see [`Channel::get_all_messages`]) is the order they were added in.
+    /// New files are written in the checksummed format (see [`read_message_records`]), files that are still in the legacy format are appended to in that format.
     ///
     /// # Errors
     ///
@@ -64,84 +84,234 @@ impl Channel {
     /// * The message file does not exist and could not be created.
     /// * Was unable to write to the message file.
     pub async fn add_message(&self, message: SignedMessage) -> Result {
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(self.get_current_file().await)
-            .await?;
-        bincode::serialize_into(file.into_std().await, &message)?;
+        let _guard = WRITE_LOCKS.lock((self.hub_id, self.id)).await;
+        append_message(&self.get_current_file().await, &message).await
+    }
+
+    /// Adds messages to the files of the days they were created on instead of the current file, used when messages are moved from another channel (see [`crate::message_move`]) so they are still found by [`Channel::get_messages_between`].
+    /// Each message is appended to the end of its file.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error for the same reasons as [`Channel::add_message`], the messages before the one that failed stay written.
+    pub async fn add_messages_on_created_day(&self, messages: &[SignedMessage]) -> Result {
+        let _guard = WRITE_LOCKS.lock((self.hub_id, self.id)).await;
+        for message in messages {
+            let path = crate::paths::message_file(self.hub_id, self.id, message.created);
+            append_message(&path, message).await?;
+        }
         Ok(())
     }
 
-    pub async fn write_message(hub_id: ID, channel_id: ID, message: SignedMessage) -> Result {
+    pub async fn write_message(
+        hub_id: HubId,
+        channel_id: ChannelId,
+        message: SignedMessage,
+    ) -> Result {
         Self::new("".to_string(), channel_id, hub_id)
             .add_message(message)
             .await
     }
 
-    /// Gets the last messages sent, `max` indicates the maximum number of messages to return.
-    pub async fn get_last_messages(&self, max: usize) -> Vec<Message> {
-        let mut result: Vec<Message> = Vec::new();
-        if let Ok(mut dir) = fs::read_dir(self.get_folder()).await {
-            let mut files = Vec::new();
-            while let Ok(Some(entry)) = dir.next_entry().await {
-                if entry.path().is_file() {
-                    files.push(entry)
-                }
+    /// Replaces the stored message that has the same ID as `message`, used when a message is edited. The message keeps its place in the channel.
+    /// Returns the message that was replaced.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * There is no message with that ID in the channel.
+    /// * The message file has records that could not be decrypted, see [`MessageRecords::ensure_decrypted`].
+    /// * The message file could not be rewritten.
+    pub async fn replace_message(&self, message: SignedMessage) -> Result<SignedMessage> {
+        let _guard = WRITE_LOCKS.lock((self.hub_id, self.id)).await;
+        // Edited messages are usually recent, so the newest files are searched first.
+        for path in self.get_message_files().await.into_iter().rev() {
+            let bytes = fs::read(&path).await.with_path(&path)?;
+            let records = read_message_records(&bytes);
+            if let Some(position) = records
+                .messages
+                .iter()
+                .position(|stored| stored.id == message.id)
+            {
+                records.ensure_decrypted()?;
+                let MessageRecords {
+                    mut messages, read, ..
+                } = records;
+                let replaced = std::mem::replace(&mut messages[position], message);
+                let mut new_bytes = encode_message_file(messages.iter())?;
+                // Keep anything after the last readable record as it was.
+                new_bytes.extend_from_slice(&bytes[read..]);
+                // Written to a temporary file first so the file is never left half written.
+                let mut temp_path = path.clone().into_os_string();
+                temp_path.push(".tmp");
+                fs::write(&temp_path, new_bytes)
+                    .await
+                    .with_path(&temp_path)?;
+                fs::rename(&temp_path, &path).await.with_path(&path)?;
+                return Ok(replaced);
             }
-            files.sort_by_key(|f| f.file_name());
-            files.reverse();
-            for file in files.iter() {
-                if let Ok(file) = fs::File::open(file.path()).await {
-                    let mut found = bincode::deserialize_from(file.into_std().await)
-                        .into_iter()
-                        .collect::<Vec<Message>>();
-                    found.sort_by_key(|m| m.created);
-                    found.reverse();
-                    result.append(&mut found);
-                    if result.len() >= max {
-                        result.truncate(max);
-                        return result;
-                    }
+        }
+        Err(Error::MessageNotFound)
+    }
+
+    /// Removes the messages stored between two days (inclusive, see [`message_file_day`]) for which `remove` returns true. Returns the IDs of the removed messages in the order they were stored in.
+    /// Each message file is locked only while it is rewritten, so messages can still be sent to the channel while a long removal runs.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * A message file could not be read.
+    /// * A message file has records that could not be decrypted, see [`MessageRecords::ensure_decrypted`].
+    /// * A message file could not be rewritten.
+    pub async fn remove_messages_between<F>(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        mut remove: F,
+    ) -> Result<Vec<MessageId>>
+    where
+        F: FnMut(&SignedMessage) -> bool,
+    {
+        let mut removed = Vec::new();
+        let (day_from, day_to) = (from.timestamp() / 86400, to.timestamp() / 86400);
+        for path in self.get_message_files().await {
+            let day = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(message_file_day);
+            if !matches!(day, Some(day) if day >= day_from && day <= day_to) {
+                continue;
+            }
+            let _guard = WRITE_LOCKS.lock((self.hub_id, self.id)).await;
+            let bytes = fs::read(&path).await.with_path(&path)?;
+            let records = read_message_records(&bytes);
+            let locked = records.ensure_decrypted();
+            let MessageRecords { messages, read, .. } = records;
+            let count = removed.len();
+            let mut kept = Vec::with_capacity(messages.len());
+            for message in messages {
+                if remove(&message) {
+                    removed.push(message.id);
+                } else {
+                    kept.push(message);
                 }
             }
+            if removed.len() == count {
+                continue;
+            }
+            locked?;
+            let mut new_bytes = encode_message_file(kept.iter())?;
+            new_bytes.extend_from_slice(&bytes[read..]);
+            let mut temp_path = path.clone().into_os_string();
+            temp_path.push(".tmp");
+            fs::write(&temp_path, new_bytes)
+                .await
+                .with_path(&temp_path)?;
+            fs::rename(&temp_path, &path).await.with_path(&path)?;
         }
-        result
+        Ok(removed)
     }
 
-    /// Tries to get all the messages listed by their IDs in `ids`. Not guaranteed to return all or any of the wanted messages.
-    pub async fn get_messages(&self, ids: Vec<ID>) -> Vec<SignedMessage> {
-        let mut result: Vec<SignedMessage> = Vec::new();
-        if let Ok(mut dir) = tokio::fs::read_dir(self.get_folder()).await {
-            let mut files = Vec::new();
-            while let Ok(Some(entry)) = dir.next_entry().await {
-                if entry.path().is_file() {
-                    files.push(entry)
-                }
+    /// Rewrites the message files that contain corrupt records, unreadable trailing data or messages already stored in an earlier file, files still in the legacy format are rewritten in the checksummed format.
+    /// Returns the number of bytes that were removed. Like [`Channel::remove_messages_between`] each file is locked only while it is rewritten, so this can run while the server is running.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * A message file could not be read.
+    /// * A message file has records that could not be decrypted, see [`MessageRecords::ensure_decrypted`].
+    /// * A message file could not be rewritten.
+    pub async fn compact(&self) -> Result<u64> {
+        let mut removed = 0;
+        let mut seen = HashSet::new();
+        for path in self.get_message_files().await {
+            let _guard = WRITE_LOCKS.lock((self.hub_id, self.id)).await;
+            let bytes = fs::read(&path).await.with_path(&path)?;
+            let records = read_message_records(&bytes);
+            records.ensure_decrypted()?;
+            let count = records.messages.len();
+            let messages: Vec<_> = records
+                .messages
+                .into_iter()
+                .filter(|m| seen.insert(m.id))
+                .collect();
+            if records.legacy
+                || records.corrupt != 0
+                || records.read != bytes.len()
+                || count != messages.len()
+            {
+                let compacted = encode_message_file(messages.iter())?;
+                let mut temp_path = path.clone().into_os_string();
+                temp_path.push(".tmp");
+                fs::write(&temp_path, &compacted)
+                    .await
+                    .with_path(&temp_path)?;
+                fs::rename(&temp_path, &path).await.with_path(&path)?;
+                // Migrating a legacy file adds a header to every record, so the file can grow.
+                removed += bytes.len().saturating_sub(compacted.len()) as u64;
             }
-            for file in files.iter() {
-                if let Ok(file) = tokio::fs::read(file.path()).await {
-                    let iter = bincode::deserialize::<SignedMessage>(&file).into_iter();
-                    let mut found: Vec<SignedMessage> =
-                        iter.filter(|m| ids.contains(&m.id)).collect();
-                    result.append(&mut found);
-                    if ids.len() == result.len() {
-                        return result;
-                    }
-                }
+        }
+        Ok(removed)
+    }
+
+    /// Gets the last messages stored, newest first, `max` indicates the maximum number of messages to return.
+    /// Files are read from the newest one back until enough messages have been found.
+    pub async fn get_last_messages(&self, max: usize) -> Vec<Message> {
+        let mut result = Vec::new();
+        for path in self.get_message_files().await.into_iter().rev() {
+            if result.len() >= max {
+                break;
             }
+            result.extend(
+                read_message_file(&path)
+                    .await
+                    .iter()
+                    .rev()
+                    .filter_map(|message| Message::try_from(message).ok())
+                    .take(max - result.len()),
+            );
         }
         result
     }
 
-    /// Gets a set of messages between two times given in milliseconds since Unix Epoch.
+    /// Tries to get all the messages listed by their IDs in `ids`, in the order they were stored in. Not guaranteed to return all or any of the wanted messages.
+    /// Wanted messages are usually recent, so files are read from the newest one back until all of them have been found.
+    pub async fn get_messages(&self, ids: Vec<MessageId>) -> Vec<SignedMessage> {
+        let mut missing: HashSet<MessageId> = ids.into_iter().collect();
+        let mut found = Vec::new();
+        for path in self.get_message_files().await.into_iter().rev() {
+            if missing.is_empty() {
+                break;
+            }
+            let mut messages: Vec<SignedMessage> = read_message_file(&path)
+                .await
+                .into_iter()
+                .filter(|message| missing.contains(&message.id))
+                .collect();
+            for message in messages.iter() {
+                missing.remove(&message.id);
+            }
+            messages.reverse();
+            found.append(&mut messages);
+        }
+        found.reverse();
+        found
+    }
+
+    /// Gets a set of messages sent between two times.
     ///
     /// # Arguments
     ///
     /// * `from` - The earliest send time a message can have to be included.
     /// * `to` - The latest send time a message can have to be included.
     /// * `invert` - If true messages are returned in order of newest to oldest if false, oldest to newest, search is also done in that order.
-    /// * `max` - The maximum number of messages to return.
+    /// * `max` - The maximum number of messages to return, if there are more messages in the range the newest are returned when `invert` is true and the oldest when it is false.
     pub async fn get_messages_between(
         &self,
         from: DateTime<Utc>,
@@ -150,145 +320,381 @@ impl Channel {
         max: usize,
     ) -> Vec<SignedMessage> {
         let mut result: Vec<SignedMessage> = Vec::new();
-        if let Ok(mut dir) = fs::read_dir(self.get_folder()).await {
-            let mut files = Vec::new();
-            while let Ok(Some(entry)) = dir.next_entry().await {
-                if entry.path().is_file() {
-                    files.push(entry)
-                }
-            }
-            files.sort_by_key(|f| f.file_name());
-            if invert {
-                files.reverse() // Reverse the order of the list of files to search in the correct direction if `invert` is true.
+        let mut files = self.get_message_files().await;
+        if invert {
+            files.reverse() // Reverse the order of the list of files to search in the correct direction if `invert` is true.
+        }
+        let div_from = from.timestamp() / 86400; // Get the day that `from` corresponds to.
+        let div_to = to.timestamp() / 86400; // Get the day that `to` corresponds to.
+        for file in files.iter().filter(|f| {
+            if let Some(n) = f
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(message_file_day)
+            {
+                return n >= div_from && n <= div_to; // Check that the file is of a day within the given `to` and `from` times.
             }
-            let div_from = from.timestamp() / 86400; // Get the day that `from` corresponds to.
-            let div_to = to.timestamp() / 86400; // Get the day that `to` corresponds to.
-            for file in files.iter().filter(|f| {
-                if let Ok(fname) = f.file_name().into_string() {
-                    if let Ok(n) = i64::from_str(&fname) {
-                        return n >= div_from && n <= div_to; // Check that the file is of a day within the given `to` and `from` times.
-                    }
+            false
+        }) {
+            if let Ok(bytes) = fs::read(file).await {
+                let mut filtered = read_message_records_logged(file, &bytes)
+                    .into_iter()
+                    .filter(|message: &SignedMessage| {
+                        message.created >= from && message.created <= to
+                    })
+                    .collect::<Vec<SignedMessage>>();
+                if invert {
+                    filtered.reverse() // Invert the order of found messages for that file if `invert` is true.
                 }
-                false
-            }) {
-                if let Ok(file) = fs::File::open(file.path()).await {
-                    let mut filtered = bincode::deserialize_from(file.into_std().await)
-                        .into_iter()
-                        .filter(|message: &SignedMessage| {
-                            message.created >= from && message.created <= to
-                        })
-                        .collect::<Vec<SignedMessage>>();
-                    if invert {
-                        filtered.reverse() // Invert the order of found messages for that file if `invert` is true.
-                    }
-                    filtered.truncate(max - result.len()); // Remove any extra messages if `max` has been reached.
-                    result.append(&mut filtered);
-                    let len = result.len();
-                    if len == max {
-                        return result;
-                    } else if result.len() > max {
-                        result.truncate(max);
-                        return result;
-                    }
+                filtered.truncate(max - result.len()); // Remove any extra messages if `max` has been reached.
+                result.append(&mut filtered);
+                if result.len() >= max {
+                    return result;
                 }
             }
         }
         result
     }
 
-    /// Gets all messages that were sent after the message with the given ID.
-    pub async fn get_messages_after(&self, id: ID, max: usize) -> Vec<SignedMessage> {
-        let mut result: Vec<SignedMessage> = Vec::new();
-        if let Ok(mut dir) = fs::read_dir(self.get_folder()).await {
-            let mut files = Vec::new();
-            while let Ok(Some(entry)) = dir.next_entry().await {
-                if entry.path().is_file() {
-                    files.push(entry)
-                }
+    /// Gets the messages that were stored after the message with the given ID, in the order they were stored in.
+    /// Returns nothing if there is no message with the given ID.
+    /// The file with the message is searched for from the newest file back, then the files after it are read until `max` messages have been found.
+    pub async fn get_messages_after(&self, id: MessageId, max: usize) -> Vec<SignedMessage> {
+        let files = self.get_message_files().await;
+        let mut start = None;
+        for (index, path) in files.iter().enumerate().rev() {
+            let mut messages = read_message_file(path).await;
+            if let Some(position) = messages.iter().position(|message| message.id == id) {
+                start = Some((index, messages.split_off(position + 1)));
+                break;
             }
-            for file in files.iter() {
-                if let Ok(file) = fs::File::open(file.path()).await {
-                    let mut iter = bincode::deserialize_from::<std::fs::File, SignedMessage>(
-                        file.into_std().await,
-                    )
-                    .into_iter();
-                    if iter.any(|m| m.id == id) {
-                        result.append(&mut iter.collect());
-                        let len = result.len();
-                        if len == max {
-                            return result;
-                        } else if result.len() > max {
-                            result.truncate(max);
-                            return result;
-                        }
-                    }
-                }
+        }
+        let (index, mut result) = match start {
+            Some(start) => start,
+            None => return Vec::new(),
+        };
+        for path in files[index + 1..].iter() {
+            if result.len() >= max {
+                break;
             }
+            result.append(&mut read_message_file(path).await);
         }
+        result.truncate(max);
         result
     }
 
     /// Unlimited asynchronus version of [`get_messages_after`] for internal use.
-    pub async fn get_all_messages_from(&self, id: ID) -> Vec<SignedMessage> {
-        let mut result: Vec<SignedMessage> = Vec::new();
-        if let Ok(mut dir) = tokio::fs::read_dir(self.get_folder()).await {
-            let mut files = Vec::new();
-            while let Ok(Some(entry)) = dir.next_entry().await {
-                if entry.path().is_file() {
-                    files.push(entry)
-                }
+    pub async fn get_all_messages_from(&self, id: MessageId) -> Vec<SignedMessage> {
+        self.get_messages_after(id, usize::MAX).await
+    }
+
+    /// Gets the message with the given ID, files are searched from the newest one back since most lookups are for recent messages.
+    pub async fn get_message(&self, id: MessageId) -> Option<SignedMessage> {
+        for path in self.get_message_files().await.into_iter().rev() {
+            if let Some(message) = read_message_file(&path)
+                .await
+                .into_iter()
+                .find(|message| message.id == id)
+            {
+                return Some(message);
             }
+        }
+        None
+    }
 
-            for file in files.iter() {
-                if let Ok(file) = tokio::fs::read(file.path()).await {
-                    let mut iter = bincode::deserialize::<SignedMessage>(&file).into_iter();
-                    if iter.any(|m| m.id == id) {
-                        result.append(&mut iter.collect());
+    /// Gets the paths of all of the channel's message files, sorted oldest to newest by the day in their name (see [`message_file_day`]).
+    pub async fn get_message_files(&self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        if let Ok(mut dir) = fs::read_dir(self.get_folder()).await {
+            while let Ok(Some(entry)) = dir.next_entry().await {
+                let path = entry.path();
+                if path.is_file() {
+                    if let Some(day) = entry.file_name().to_str().and_then(message_file_day) {
+                        files.push((day, path));
                     }
                 }
             }
         }
+        files.sort();
+        files.into_iter().map(|(_, path)| path).collect()
+    }
+
+    /// Gets every message stored in the channel, ordered by the file they were stored in then the order they were written in.
+    pub async fn get_all_messages(&self) -> Vec<SignedMessage> {
+        let mut result = Vec::new();
+        for path in self.get_message_files().await {
+            result.append(&mut read_message_file(&path).await);
+        }
         result
     }
 
-    /// Get the first message with the given ID.
-    pub async fn get_message(&self, id: ID) -> Option<SignedMessage> {
-        if let Ok(mut dir) = fs::read_dir(self.get_folder()).await {
-            let mut files = Vec::new();
-            while let Ok(Some(entry)) = dir.next_entry().await {
-                if entry.path().is_file() {
-                    files.push(entry)
+    /// Returns true if the channel has more than `count` messages, counting records that can not be decrypted. Stops reading files once it has found enough.
+    pub async fn has_more_messages_than(&self, count: usize) -> bool {
+        let mut found = 0;
+        for path in self.get_message_files().await {
+            if let Ok(bytes) = fs::read(&path).await {
+                let records = read_message_records(&bytes);
+                found += records.messages.len() + records.locked;
+                if found > count {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Gets the path of the current message file, the filename is the current UTC date (e.g. `2021-04-20UTC`), see [`message_file_day`].
+    pub async fn get_current_file(&self) -> PathBuf {
+        crate::paths::message_file(self.hub_id, self.id, Utc::now())
+    }
+}
+
+/// Locks that make writes to the message files of each channel happen one at a time.
+static WRITE_LOCKS: KeyedLocks<(HubId, ChannelId)> = KeyedLocks::new();
+
+/// Appends a message to a message file, the write lock of the channel has to be held.
+async fn append_message(path: &Path, message: &SignedMessage) -> Result {
+    let bytes = match fs::metadata(path).await {
+        Ok(metadata) if metadata.len() > 0 => {
+            if has_magic(path).await {
+                encode_message_record(message)?
+            } else {
+                bincode::serialize(message)?
+            }
+        }
+        _ => {
+            let mut bytes = MESSAGE_FILE_MAGIC.to_vec();
+            bytes.append(&mut encode_message_record(message)?);
+            bytes
+        }
+    };
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .with_path(path)?;
+    file.write_all(&bytes).await.with_path(path)?;
+    file.flush().await.with_path(path)?;
+    Ok(())
+}
+
+/// Gets the day (number of days since Unix Epoch) that a message file holds the messages of.
+/// Files are named after their UTC date (e.g. `2021-04-20UTC`), older versions named them after the day number itself, both are accepted.
+pub fn message_file_day(file_name: &str) -> Option<i64> {
+    if let Ok(day) = i64::from_str(file_name) {
+        return Some(day);
+    }
+    let date = NaiveDate::parse_from_str(file_name.trim_end_matches("UTC"), "%Y-%m-%d").ok()?;
+    Some(date.and_hms_opt(0, 0, 0)?.and_utc().timestamp() / 86400)
+}
+
+/// Bytes at the start of every message file in the checksummed format, see [`read_message_records`].
+pub const MESSAGE_FILE_MAGIC: &[u8; 8] = b"WICRSMF1";
+
+/// Size of the header of each record in the checksummed format: the length of the record's payload and the CRC32 of the payload, both little endian `u32`s.
+const RECORD_HEADER_SIZE: usize = 8;
+
+/// Messages read from the contents of a message file, see [`read_message_records`].
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MessageRecords {
+    /// The messages that could be read, in the order they were stored in.
+    pub messages: Vec<SignedMessage>,
+    /// Number of bytes up to the end of the last record that could be framed, equal to the length of the contents if nothing after it was left unread.
+    pub read: usize,
+    /// Number of records that were skipped because their checksum did not match or they could not be deserialized.
+    pub corrupt: usize,
+    /// Whether the file is in the legacy format without checksums.
+    pub legacy: bool,
+    /// Number of the messages that were stored encrypted, see [`crate::encryption`].
+    pub encrypted: usize,
+    /// Number of encrypted records that could not be decrypted because no key is configured or the key is not the one they were encrypted with.
+    /// Files with such records must not be rewritten, the records would be lost, see [`MessageRecords::ensure_decrypted`].
+    pub locked: usize,
+}
+
+impl MessageRecords {
+    /// Checks that every record could be decrypted, files are only rewritten if this is the case.
+    ///
+    /// # Errors
+    ///
+    /// This function returns [`Error::EncryptionKeyMissing`] or [`Error::DecryptionFailed`] if there are records that could not be decrypted.
+    pub fn ensure_decrypted(&self) -> Result {
+        if self.locked == 0 {
+            Ok(())
+        } else if crate::encryption::enabled() {
+            Err(Error::DecryptionFailed)
+        } else {
+            Err(Error::EncryptionKeyMissing)
+        }
+    }
+}
+
+/// Checks if a message file starts with [`MESSAGE_FILE_MAGIC`].
+async fn has_magic(path: &Path) -> bool {
+    let mut header = [0; MESSAGE_FILE_MAGIC.len()];
+    match fs::File::open(path).await {
+        Ok(mut file) => file.read_exact(&mut header).await.is_ok() && &header == MESSAGE_FILE_MAGIC,
+        Err(_) => false,
+    }
+}
+
+/// Encodes a message as a record of the checksummed format, encrypted if encryption is configured, see [`read_message_records`].
+///
+/// # Errors
+///
+/// This function returns an error if the message could not be serialized or encrypted.
+pub fn encode_message_record(message: &SignedMessage) -> Result<Vec<u8>> {
+    encode_message_record_as(message, crate::encryption::enabled())
+}
+
+/// Encodes a message as a record of the checksummed format, encrypted if `encrypt` is true.
+fn encode_message_record_as(message: &SignedMessage, encrypt: bool) -> Result<Vec<u8>> {
+    let payload = crate::encryption::seal_with(bincode::serialize(message)?, encrypt)?;
+    let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + payload.len());
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    record.extend_from_slice(&payload);
+    Ok(record)
+}
+
+/// Encodes the complete contents of a message file in the checksummed format, encrypted if encryption is configured.
+///
+/// # Errors
+///
+/// This function returns an error if one of the messages could not be serialized or encrypted.
+pub fn encode_message_file<'a, I: IntoIterator<Item = &'a SignedMessage>>(
+    messages: I,
+) -> Result<Vec<u8>> {
+    encode_message_file_as(messages, crate::encryption::enabled())
+}
+
+/// Encodes the complete contents of a message file in the checksummed format, encrypted if `encrypt` is true whatever the configuration, used to convert the data directory (see [`crate::maintenance::convert_encryption`]).
+///
+/// # Errors
+///
+/// This function returns an error if one of the messages could not be serialized or encrypted.
+pub fn encode_message_file_as<'a, I: IntoIterator<Item = &'a SignedMessage>>(
+    messages: I,
+    encrypt: bool,
+) -> Result<Vec<u8>> {
+    let mut bytes = MESSAGE_FILE_MAGIC.to_vec();
+    for message in messages {
+        bytes.append(&mut encode_message_record_as(message, encrypt)?);
+    }
+    Ok(bytes)
+}
+
+/// Reads all of the messages in the contents of a message file.
+///
+/// Files that start with [`MESSAGE_FILE_MAGIC`] are made of records that each have a header with the length and CRC32 of their payload, a bincode encoded [`SignedMessage`] that may be encrypted (see [`crate::encryption::seal`]).
+/// Encrypted records that can not be decrypted are counted in [`MessageRecords::locked`], not as corrupt.
+/// Records whose payload does not match its checksum are skipped. A record whose length runs past the end of the file is skipped up to the next offset where a record matches its checksum, reading stops if there is none.
+/// Other files are in the legacy format of back to back bincode encoded messages, reading stops at the first message that cannot be deserialized.
+pub fn read_message_records(bytes: &[u8]) -> MessageRecords {
+    let mut records = MessageRecords::default();
+    if let Some(mut remaining) = bytes.strip_prefix(&MESSAGE_FILE_MAGIC[..]) {
+        while remaining.len() >= RECORD_HEADER_SIZE {
+            let (header, rest) = remaining.split_at(RECORD_HEADER_SIZE);
+            let length = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+            let checksum = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+            if length > rest.len() {
+                // The length is corrupt, records after it can still be found by their checksums.
+                match next_record(remaining) {
+                    Some(offset) => {
+                        records.corrupt += 1;
+                        remaining = &remaining[offset..];
+                        continue;
+                    }
+                    None => break,
                 }
             }
-            for file in files.iter() {
-                if let Ok(file) = fs::File::open(file.path()).await {
-                    if let Some(msg) = bincode::deserialize_from(file.into_std().await)
-                        .into_iter()
-                        .find(|m: &SignedMessage| m.id == id)
-                    {
-                        return Some(msg);
+            let (payload, rest) = rest.split_at(length);
+            if crc32fast::hash(payload) != checksum {
+                records.corrupt += 1;
+            } else if let Ok(plaintext) = crate::encryption::open(payload) {
+                match bincode::deserialize::<SignedMessage>(&plaintext) {
+                    Ok(message) => {
+                        records.messages.push(message);
+                        if crate::encryption::is_encrypted(payload) {
+                            records.encrypted += 1;
+                        }
                     }
+                    Err(_) => records.corrupt += 1,
                 }
+            } else {
+                records.locked += 1;
             }
+            remaining = rest;
         }
-        None
+        records.read = bytes.len() - remaining.len();
+    } else {
+        records.legacy = true;
+        let mut remaining = bytes;
+        while !remaining.is_empty() {
+            let read = bytes.len() - remaining.len();
+            if let Ok(message) = bincode::deserialize_from::<_, SignedMessage>(&mut remaining) {
+                records.messages.push(message);
+            } else {
+                records.read = read;
+                return records;
+            }
+        }
+        records.read = bytes.len();
     }
+    records
+}
 
-    /// Gets the path of the current message file, filename is time in milliseconds from Unix Epoch divided by `86400000` (the number of milliseconds in a day).
-    pub async fn get_current_file(&self) -> String {
-        format!("{}/{}", self.get_folder(), Utc::now().date())
+/// Finds the offset of the next record after the start of `bytes` whose length fits in `bytes` and whose payload matches its checksum.
+/// Records are never empty, zeroed bytes would otherwise look like a record.
+fn next_record(bytes: &[u8]) -> Option<usize> {
+    (1..bytes.len().saturating_sub(RECORD_HEADER_SIZE - 1)).find(|&offset| {
+        let (header, rest) = bytes[offset..].split_at(RECORD_HEADER_SIZE);
+        let length = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let checksum = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        length != 0 && length <= rest.len() && crc32fast::hash(&rest[..length]) == checksum
+    })
+}
+
+/// Reads the messages of a message file, see [`read_message_records_logged`]. A file that can not be read has no messages.
+async fn read_message_file(path: &Path) -> Vec<SignedMessage> {
+    match fs::read(path).await {
+        Ok(bytes) => read_message_records_logged(path, &bytes),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Reads the messages of a message file with [`read_message_records`], logging a warning if any of its records had to be skipped.
+fn read_message_records_logged(path: &Path, bytes: &[u8]) -> Vec<SignedMessage> {
+    let records = read_message_records(bytes);
+    if records.corrupt != 0 || records.read != bytes.len() {
+        warn!(
+            "Message file {} has {} corrupt records and {} unreadable bytes at its end, run the verify command to repair it.",
+            path.display(),
+            records.corrupt,
+            bytes.len() - records.read
+        );
+    }
+    if records.locked != 0 {
+        warn!(
+            "Message file {} has {} encrypted records that could not be decrypted, check the encryption key.",
+            path.display(),
+            records.locked
+        );
     }
+    records.messages
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, SimpleObject)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "graphql", derive(SimpleObject))]
 pub struct SignedMessage {
-    pub id: ID,
+    pub id: MessageId,
     pub created: DateTime<Utc>,
     pub armoured_content: String,
 }
 
 impl SignedMessage {
-    pub fn new(id: ID, created: DateTime<Utc>, armoured_content: String) -> Self {
+    pub fn new(id: MessageId, created: DateTime<Utc>, armoured_content: String) -> Self {
         Self {
             id,
             created,
@@ -314,31 +720,382 @@ impl TryFrom<&SignedMessage> for Message {
 }
 
 /// Represents a message.
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, SimpleObject)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "graphql", derive(SimpleObject))]
 pub struct Message {
     /// ID of the message, not actually guaranteed to be unique due to the performance that could be required to check this for every message sent.
-    pub id: ID,
+    pub id: MessageId,
     /// ID of the hub the message was sent in.
-    pub hub_id: ID,
+    pub hub_id: HubId,
     /// ID of the channel the message was sent in.
-    pub channel_id: ID,
+    pub channel_id: ChannelId,
     /// ID of the user that sent the message.
     pub sender: String,
-    /// Date in milliseconds since Unix Epoch that the message was sent.
+    /// Time the message was sent.
     pub created: DateTime<Utc>,
     /// The actual text of the message.
     pub content: String,
+    /// IDs of the permission groups mentioned in the message, see [`crate::mentions::resolve_group_mentions`].
+    #[serde(default)]
+    pub group_mentions: Vec<ID>,
+    /// Where the message was forwarded from, if it is a copy of another message, see [`crate::message_pipeline::prepare_forward`].
+    #[serde(default)]
+    pub forwarded_from: Option<ForwardedFrom>,
+    /// Flags of the message (see [`Message::SILENT`] and [`Message::TTS`]), chosen by the sender when the message is prepared.
+    /// Bits this version does not know are kept as they are, so messages from newer versions keep their flags.
+    #[serde(default)]
+    pub flags: u32,
+    /// What kind of author sent the message, messages from before it existed were sent by users.
+    #[serde(default)]
+    pub author_type: AuthorType,
+    /// Name clients show instead of the sender's nickname, only bot and webhook messages can have one.
+    #[serde(default)]
+    pub override_name: Option<String>,
+    /// URL of the avatar clients show instead of the sender's, only bot and webhook messages can have one.
+    #[serde(default)]
+    pub override_avatar: Option<String>,
+}
+
+/// What kind of author sent a message, bot and webhook messages can be shown with their own name and avatar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "graphql", derive(Enum))]
+#[serde(rename_all = "snake_case")]
+pub enum AuthorType {
+    #[default]
+    User,
+    Bot,
+    Webhook,
+}
+
+/// The message a forwarded message is a copy of. Only IDs are included so that readers of the copy learn nothing about channels they can not see.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "graphql", derive(SimpleObject))]
+pub struct ForwardedFrom {
+    /// ID of the hub the original message is in.
+    pub hub_id: HubId,
+    /// ID of the channel the original message is in.
+    pub channel_id: ChannelId,
+    /// ID of the original message.
+    pub message_id: MessageId,
+    /// ID of the user that sent the original message.
+    pub original_sender: String,
 }
 
 impl Message {
-    pub fn new(sender: String, content: String, hub_id: ID, channel_id: ID) -> Self {
+    pub fn new(sender: String, content: String, hub_id: HubId, channel_id: ChannelId) -> Self {
         Self {
             sender,
             content,
             channel_id,
             hub_id,
             created: Utc::now(),
-            id: new_id(),
+            id: MessageId::random(),
+            group_mentions: Vec::new(),
+            forwarded_from: None,
+            flags: 0,
+            author_type: AuthorType::User,
+            override_name: None,
+            override_avatar: None,
+        }
+    }
+
+    /// Sets who the message is shown as sent by, used by the webhook send path (see [`crate::message_pipeline::send_webhook`]). The name follows the character rules of hub names.
+    /// Messages sent by users can not have overrides, they are rejected by [`crate::message_pipeline::check_message`].
+    ///
+    /// # Errors
+    ///
+    /// This function may return an error for any of the following reasons:
+    ///
+    /// * `author_type` is [`AuthorType::User`] and an override is given, [`Error::InvalidMessage`].
+    /// * The name is not valid for any of the reasons outlined in [`validate_override_name`].
+    /// * The avatar is not valid, see [`validate_override_avatar`].
+    pub fn set_author(
+        &mut self,
+        author_type: AuthorType,
+        override_name: Option<&str>,
+        override_avatar: Option<&str>,
+    ) -> Result {
+        if author_type == AuthorType::User && (override_name.is_some() || override_avatar.is_some())
+        {
+            return Err(Error::InvalidMessage);
+        }
+        let override_name = override_name.map(validate_override_name).transpose()?;
+        if let Some(avatar) = override_avatar {
+            validate_override_avatar(avatar)?;
         }
+        self.author_type = author_type;
+        self.override_name = override_name;
+        self.override_avatar = override_avatar.map(str::to_string);
+        Ok(())
+    }
+
+    /// Checks if the message has a name or avatar override, see [`Message::set_author`].
+    pub fn has_overrides(&self) -> bool {
+        self.override_name.is_some() || self.override_avatar.is_some()
+    }
+
+    /// The message is stored and delivered like any other, but the groups it mentions are not notified.
+    pub const SILENT: u32 = 1;
+    /// Clients may read the message out loud, only users with the [`crate::permission::ChannelPermission::SendTts`] permission can set it.
+    pub const TTS: u32 = 1 << 1;
+
+    /// Checks if all of the given flags are set.
+    pub fn has_flags(&self, flags: u32) -> bool {
+        self.flags & flags == flags
+    }
+}
+
+/// Checks a name that a message is shown as sent by and returns it with its whitespace normalized, it follows the character rules of hub names.
+///
+/// # Errors
+///
+/// This function returns an error if the name is longer than [`MAX_OVERRIDE_NAME_LENGTH`] or not valid for any of the reasons outlined in [`validate_name_with`].
+pub fn validate_override_name(name: &str) -> Result<String> {
+    let rules = NameRules {
+        min_length: 1,
+        max_length: MAX_OVERRIDE_NAME_LENGTH,
+        extended_characters: name_rules(NameKind::Hub).extended_characters,
+    };
+    validate_name_with(&rules, name)
+}
+
+/// Checks the URL of an avatar that a message is shown with.
+///
+/// # Errors
+///
+/// This function returns [`Error::InvalidAvatar`] if the avatar is not an `https` URL of at most [`MAX_OVERRIDE_AVATAR_LENGTH`] bytes.
+pub fn validate_override_avatar(avatar: &str) -> Result {
+    if avatar.len() > MAX_OVERRIDE_AVATAR_LENGTH
+        || !avatar.starts_with("https://")
+        || avatar.chars().any(|c| c.is_whitespace() || c.is_control())
+    {
+        return Err(Error::InvalidAvatar(MAX_OVERRIDE_AVATAR_LENGTH));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+
+    use super::{encode_message_file, read_message_records, Channel, SignedMessage};
+    use crate::{ChannelId, HubId, MessageId};
+
+    #[test]
+    fn corrupt_records_are_skipped() {
+        let messages: Vec<SignedMessage> = (1..=3)
+            .map(|n| SignedMessage::new(MessageId::from_u128(n), Utc::now(), format!("{}", n)))
+            .collect();
+        let mut bytes = encode_message_file(messages.iter()).unwrap();
+        let length = bytes.len();
+        let records = read_message_records(&bytes);
+        assert_eq!(records.messages, messages);
+        assert_eq!(
+            (records.read, records.corrupt, records.legacy),
+            (length, 0, false)
+        );
+        // Flip a bit in the payload of the second record and cut the third one short.
+        let second = super::MESSAGE_FILE_MAGIC.len()
+            + super::encode_message_record(&messages[0]).unwrap().len()
+            + super::RECORD_HEADER_SIZE;
+        bytes[second] ^= 1;
+        bytes.truncate(length - 1);
+        let records = read_message_records(&bytes);
+        assert_eq!(records.messages, vec![messages[0].clone()]);
+        assert_eq!(records.corrupt, 1);
+        assert_eq!(
+            records.read,
+            length - super::encode_message_record(&messages[2]).unwrap().len()
+        );
+        // Files from before the checksummed format can still be read.
+        let legacy: Vec<u8> = messages
+            .iter()
+            .flat_map(|message| bincode::serialize(message).unwrap())
+            .collect();
+        let records = read_message_records(&legacy);
+        assert_eq!(records.messages, messages);
+        assert!(records.legacy);
+    }
+
+    #[tokio::test]
+    async fn messages_between_limit() {
+        let channel = Channel::new(
+            "test_channel".to_string(),
+            ChannelId::random(),
+            HubId::random(),
+        );
+        channel.create_dir().await.unwrap();
+        let now = Utc::now();
+        for n in 1..=3 {
+            channel
+                .add_message(SignedMessage::new(
+                    MessageId::from_u128(n),
+                    now - Duration::minutes(10 - n as i64),
+                    String::new(),
+                ))
+                .await
+                .unwrap();
+        }
+        let ids = |messages: Vec<SignedMessage>| {
+            messages
+                .iter()
+                .map(|message| message.id.as_u128())
+                .collect::<Vec<u128>>()
+        };
+        let from = now - Duration::hours(1);
+        assert_eq!(
+            ids(channel.get_messages_between(from, now, false, 2).await),
+            vec![1, 2]
+        );
+        assert_eq!(
+            ids(channel.get_messages_between(from, now, true, 2).await),
+            vec![3, 2]
+        );
+        assert_eq!(
+            ids(channel
+                .get_messages_between(now - Duration::minutes(8), now, false, 10)
+                .await),
+            vec![2, 3]
+        );
+        let _ = tokio::fs::remove_dir_all(channel.get_folder()).await;
+    }
+
+    #[tokio::test]
+    async fn storage_order() {
+        let channel = Channel::new(
+            "test_channel".to_string(),
+            ChannelId::random(),
+            HubId::random(),
+        );
+        channel.create_dir().await.unwrap();
+        let now = Utc::now();
+        // Sent times are out of order, the order the messages were stored in is what matters.
+        for (n, minutes) in [(1, 1), (2, 3), (3, 2)].iter() {
+            channel
+                .add_message(SignedMessage::new(
+                    MessageId::from_u128(*n),
+                    now - Duration::minutes(*minutes),
+                    String::new(),
+                ))
+                .await
+                .unwrap();
+        }
+        let after = channel
+            .get_messages_after(MessageId::from_u128(1), 10)
+            .await;
+        assert_eq!(
+            after.iter().map(|m| m.id.as_u128()).collect::<Vec<u128>>(),
+            vec![2, 3]
+        );
+
+        let writes = (4..=50).map(|n| {
+            let channel = channel.clone();
+            tokio::spawn(async move {
+                channel
+                    .add_message(SignedMessage::new(
+                        MessageId::from_u128(n),
+                        Utc::now(),
+                        String::new(),
+                    ))
+                    .await
+            })
+        });
+        for write in futures::future::join_all(writes).await {
+            write.unwrap().unwrap();
+        }
+        let mut ids: Vec<u128> = channel
+            .get_all_messages()
+            .await
+            .iter()
+            .map(|m| m.id.as_u128())
+            .collect();
+        assert_eq!(&ids[..3], &[1, 2, 3]);
+        ids.sort();
+        assert_eq!(ids, (1..=50).collect::<Vec<u128>>());
+        let _ = tokio::fs::remove_dir_all(channel.get_folder()).await;
+    }
+
+    #[tokio::test]
+    async fn lookups_across_files() {
+        let channel = Channel::new(
+            "test_channel".to_string(),
+            ChannelId::random(),
+            HubId::random(),
+        );
+        channel.create_dir().await.unwrap();
+        let now = Utc::now();
+        let messages: Vec<SignedMessage> = (1..=6)
+            .map(|n| {
+                SignedMessage::new(
+                    MessageId::from_u128(n),
+                    now - Duration::days(3 - (n as i64 + 1) / 2),
+                    String::new(),
+                )
+            })
+            .collect();
+        channel
+            .add_messages_on_created_day(&messages)
+            .await
+            .unwrap();
+        assert_eq!(channel.get_message_files().await.len(), 3);
+        let ids = |messages: Vec<SignedMessage>| {
+            messages
+                .iter()
+                .map(|m| m.id.as_u128())
+                .collect::<Vec<u128>>()
+        };
+
+        assert_eq!(
+            ids(channel
+                .get_messages(vec![MessageId::from_u128(5), MessageId::from_u128(2)])
+                .await),
+            vec![2, 5]
+        );
+        assert_eq!(
+            channel.get_message(MessageId::from_u128(3)).await,
+            Some(messages[2].clone())
+        );
+        assert_eq!(channel.get_message(MessageId::from_u128(7)).await, None);
+        assert_eq!(
+            ids(channel.get_messages_after(MessageId::from_u128(2), 3).await),
+            vec![3, 4, 5]
+        );
+        assert_eq!(
+            ids(channel.get_all_messages_from(MessageId::from_u128(4)).await),
+            vec![5, 6]
+        );
+        assert!(channel
+            .get_messages_after(MessageId::from_u128(7), 3)
+            .await
+            .is_empty());
+        let _ = tokio::fs::remove_dir_all(channel.get_folder()).await;
+    }
+
+    #[tokio::test]
+    async fn compact_keeps_records_after_a_corrupt_length() {
+        let channel = Channel::new(
+            "test_channel".to_string(),
+            ChannelId::random(),
+            HubId::random(),
+        );
+        channel.create_dir().await.unwrap();
+        let messages: Vec<SignedMessage> = (1..=3)
+            .map(|n| SignedMessage::new(MessageId::from_u128(n), Utc::now(), format!("{}", n)))
+            .collect();
+        let mut bytes = encode_message_file(messages.iter()).unwrap();
+        // Make the length of the second record run past the end of the file.
+        let second = super::MESSAGE_FILE_MAGIC.len()
+            + super::encode_message_record(&messages[0]).unwrap().len();
+        bytes[second + 3] = 0xFF;
+        let path = channel.get_current_file().await;
+        tokio::fs::write(&path, &bytes).await.unwrap();
+        let kept = vec![messages[0].clone(), messages[2].clone()];
+        assert_eq!(channel.get_all_messages().await, kept);
+
+        assert!(channel.compact().await.unwrap() > 0);
+        let records = read_message_records(&tokio::fs::read(&path).await.unwrap());
+        assert_eq!(records.messages, kept);
+        assert_eq!(records.corrupt, 0);
+        let _ = tokio::fs::remove_dir_all(channel.get_folder()).await;
     }
 }
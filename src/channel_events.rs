@@ -0,0 +1,204 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{websocket::ServerMessage, ChannelId, HubId};
+
+/// Number of events kept for each channel, clients that are further behind have to resync.
+pub const MAX_CHANNEL_EVENTS: usize = 256;
+
+/// Maximum total size in bytes of the events kept for one channel, older events are dropped first.
+pub const MAX_CHANNEL_EVENT_BYTES: usize = 256 * 1024;
+
+/// An event sent to the subscribers of a channel, with its sequence number in the channel.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChannelEvent {
+    pub event_seq: u64,
+    pub message: ServerMessage,
+}
+
+/// Events of a channel that clients can ask for with `/v3/events_since`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EventsSince {
+    /// Epoch of the sequence numbers, see [`ChannelEvents::epoch`].
+    pub epoch: u64,
+    /// Sequence number of the latest event of the channel, `0` if there has not been one since the server started.
+    pub current_seq: u64,
+    /// If true the missed events are not known (anymore) and the client has to fetch the channel's messages again, `events` is empty.
+    pub full_resync: bool,
+    /// Events after the requested sequence number, oldest first.
+    pub events: Vec<ChannelEvent>,
+}
+
+/// Recent events of one channel and their size in bytes.
+#[derive(Debug, Default)]
+struct EventBuffer {
+    /// Sequence number after which every event is in `events`.
+    complete_since: u64,
+    events: VecDeque<(ChannelEvent, usize)>,
+    bytes: usize,
+}
+
+impl EventBuffer {
+    fn push(&mut self, event: ChannelEvent, size: usize) {
+        self.events.push_back((event, size));
+        self.bytes += size;
+        while self.events.len() > MAX_CHANNEL_EVENTS || self.bytes > MAX_CHANNEL_EVENT_BYTES {
+            match self.events.pop_front() {
+                Some((dropped, size)) => {
+                    self.complete_since = dropped.event_seq;
+                    self.bytes -= size;
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+/// Sequence numbers of the message events (new, edited and deleted messages) of every channel, and the recent events of the channels that have subscribers.
+///
+/// Each channel counts its events from `1` up, so a client that sees a number skipped knows it missed an event and can get it from the buffer.
+/// Numbers start over when the server restarts, which is why every event also has the epoch of the server that numbered it: a client that asks for events of another epoch has to resync.
+/// Buffers are only kept while a channel has subscribers, clients that were not subscribed have not missed any events.
+#[derive(Debug)]
+pub struct ChannelEvents {
+    epoch: u64,
+    seqs: HashMap<(HubId, ChannelId), u64>,
+    buffers: HashMap<(HubId, ChannelId), EventBuffer>,
+}
+
+impl Default for ChannelEvents {
+    fn default() -> Self {
+        Self {
+            epoch: rand::random(),
+            seqs: HashMap::new(),
+            buffers: HashMap::new(),
+        }
+    }
+}
+
+impl ChannelEvents {
+    /// Random number chosen when the sequence numbers start counting, sent with every event as `event_epoch`.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Gives the next event of a channel its sequence number.
+    pub fn next_seq(&mut self, key: (HubId, ChannelId)) -> u64 {
+        let seq = self.seqs.entry(key).or_insert(0);
+        *seq += 1;
+        *seq
+    }
+
+    /// Keeps an event if the channel has a buffer, see [`ChannelEvents::open`].
+    pub fn record(&mut self, key: (HubId, ChannelId), event_seq: u64, message: &ServerMessage) {
+        if let Some(buffer) = self.buffers.get_mut(&key) {
+            let size = serde_json::to_vec(message).map_or(0, |json| json.len());
+            buffer.push(
+                ChannelEvent {
+                    event_seq,
+                    message: message.clone(),
+                },
+                size,
+            );
+        }
+    }
+
+    /// Starts keeping the events of a channel, called when it gets its first subscriber.
+    pub fn open(&mut self, key: (HubId, ChannelId)) {
+        let current = self.seqs.get(&key).copied().unwrap_or(0);
+        self.buffers.entry(key).or_insert_with(|| EventBuffer {
+            complete_since: current,
+            ..EventBuffer::default()
+        });
+    }
+
+    /// Stops keeping the events of a channel, called when its last subscriber leaves.
+    pub fn close(&mut self, key: &(HubId, ChannelId)) {
+        self.buffers.remove(key);
+    }
+
+    /// Forgets everything about a deleted channel.
+    pub fn remove_channel(&mut self, key: &(HubId, ChannelId)) {
+        self.seqs.remove(key);
+        self.buffers.remove(key);
+    }
+
+    /// Forgets everything about the channels of a deleted hub.
+    pub fn remove_hub(&mut self, hub_id: HubId) {
+        self.seqs.retain(|key, _| key.0 != hub_id);
+        self.buffers.retain(|key, _| key.0 != hub_id);
+    }
+
+    /// Gets the events of a channel after the given sequence number, a sequence number of another epoch always needs a resync.
+    pub fn since(&self, key: &(HubId, ChannelId), epoch: u64, seq: u64) -> EventsSince {
+        let current_seq = self.seqs.get(key).copied().unwrap_or(0);
+        let events = if epoch != self.epoch {
+            None
+        } else if seq == current_seq {
+            Some(Vec::new())
+        } else {
+            self.buffers
+                .get(key)
+                .filter(|buffer| seq >= buffer.complete_since && seq < current_seq)
+                .map(|buffer| {
+                    buffer
+                        .events
+                        .iter()
+                        .filter(|(event, _)| event.event_seq > seq)
+                        .map(|(event, _)| event.clone())
+                        .collect()
+                })
+        };
+        EventsSince {
+            epoch: self.epoch,
+            current_seq,
+            full_resync: events.is_none(),
+            events: events.unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ChannelEvents, MAX_CHANNEL_EVENTS};
+    use crate::{websocket::ServerMessage, ChannelId, HubId};
+
+    #[test]
+    fn events_since_and_resync() {
+        let key = (HubId::from_u128(1), ChannelId::from_u128(2));
+        let mut events = ChannelEvents::default();
+        let epoch = events.epoch();
+        assert_eq!(events.next_seq(key), 1);
+        events.record(key, 1, &ServerMessage::Success);
+        events.open(key);
+        assert!(events.since(&key, epoch, 0).full_resync);
+        for _ in 0..3 {
+            let seq = events.next_seq(key);
+            events.record(key, seq, &ServerMessage::Success);
+        }
+        let since = events.since(&key, epoch, 2);
+        assert!(!since.full_resync);
+        assert_eq!(since.current_seq, 4);
+        assert_eq!(
+            since.events.iter().map(|e| e.event_seq).collect::<Vec<_>>(),
+            vec![3, 4]
+        );
+        assert!(events.since(&key, epoch, 4).events.is_empty());
+        // Sequence numbers of a previous start of the server can not be resumed from.
+        assert!(events.since(&key, epoch.wrapping_add(1), 2).full_resync);
+        assert!(events.since(&key, epoch, 9).full_resync);
+        for _ in 0..MAX_CHANNEL_EVENTS {
+            let seq = events.next_seq(key);
+            events.record(key, seq, &ServerMessage::Success);
+        }
+        assert!(events.since(&key, epoch, 3).full_resync);
+        assert_eq!(
+            events.since(&key, epoch, 4).events.len(),
+            MAX_CHANNEL_EVENTS
+        );
+        events.close(&key);
+        assert!(events.since(&key, epoch, 100).full_resync);
+        assert_eq!(events.next_seq(key), 4 + MAX_CHANNEL_EVENTS as u64 + 1);
+    }
+}
@@ -0,0 +1,444 @@
+use pgp::crypto::HashAlgorithm;
+use pgp::types::{CompressionAlgorithm, KeyTrait};
+use pgp::{Deserializable, Message as OpenPGPMessage, SignedPublicKey};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    api::types::{ChannelInfo, HubFields, HubInfo},
+    channel::Message,
+    error::{Error, Result},
+    httpapi::ServerInfo,
+    hub::{NewChannel, NewHub},
+    hub_changes::HubDelta,
+    instance_info::InstanceInfo,
+    signing::KeyPair,
+    webhooks::{NewWebhook, Webhook, WebhookMessage},
+    ChannelId, HubId, ID,
+};
+
+/// Routes of the HTTP API that [`WicrsClient`] has typed methods for, without their parameters.
+pub const ROUTES: &[&str] = &[
+    "v3/info",
+    "v3/instance",
+    "v3/graphql_schema",
+    "v3/graphql",
+    "v3/send_message_init",
+    "v3/send_message",
+    "v3/hub_delta",
+    "v3/hub",
+    "v3/hubs",
+    "v3/channels",
+    "v3/webhooks",
+    "v3/webhook_send",
+];
+
+/// Line that comes right before the armoured PGP message in a multipart response body.
+const BODY_START: &str = "Content-Type: application/octet-stream\r\n";
+/// Closing boundary of a multipart response body.
+const BODY_END: &str = "\r\n--the=boundary--";
+
+/// Client for the WICRS Server HTTP API.
+/// Every request is signed with the user's key pair and every response is checked for the server's signature.
+///
+/// Typed methods cover the routes listed in [`ROUTES`], every other route can be reached with [`WicrsClient::request_signed`] and [`WicrsClient::verify_response`].
+pub struct WicrsClient {
+    base_url: String,
+    key_pair: KeyPair,
+    fingerprint: String,
+    server_public_key: SignedPublicKey,
+    http: reqwest::Client,
+}
+
+impl WicrsClient {
+    /// Creates a new client given the base URL of the server (e.g. `http://127.0.0.1:8080`), the user's key pair and the server's public key.
+    pub fn new<S: Into<String>>(
+        base_url: S,
+        key_pair: KeyPair,
+        server_public_key: SignedPublicKey,
+    ) -> Self {
+        let fingerprint = hex::encode_upper(key_pair.public_key.fingerprint());
+        Self {
+            base_url: base_url.into(),
+            key_pair,
+            fingerprint,
+            server_public_key,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Creates a new client, getting the server's public key from the key server the server says it uses.
+    ///
+    /// # Errors
+    ///
+    /// This function may return an error for any of the following reasons:
+    ///
+    /// * The server could not be reached.
+    /// * The server's public key could not be found for any of the reasons outlined by [`crate::signing::get_or_import_public_key`].
+    /// * The server info was not signed by the key the server said it uses.
+    pub async fn connect<S: Into<String>>(base_url: S, key_pair: KeyPair) -> Result<Self> {
+        let base_url: String = base_url.into();
+        let body = reqwest::get(&format!("{}/v3/info", base_url))
+            .await?
+            .text()
+            .await?;
+        let unverified: ServerInfo = serde_json::from_str(&read_literal(
+            OpenPGPMessage::from_string(extract_armoured(&body)?)?.0,
+        )?)?;
+        let server_public_key = crate::signing::get_or_import_public_key(
+            &hex::decode(&unverified.public_key_fingerprint)
+                .map_err(|_| Error::InvalidFingerprint)?,
+            &unverified.key_server,
+        )
+        .await?;
+        let client = Self::new(base_url, key_pair, server_public_key);
+        client.verify_response(&body)?;
+        Ok(client)
+    }
+
+    /// Gets the server's information.
+    pub async fn info(&self) -> Result<ServerInfo> {
+        let body = self.get("v3/info").await?;
+        Ok(serde_json::from_str(&self.verify_response(&body)?)?)
+    }
+
+    /// Gets the description of the server that is available without signing in, it is not signed by the server.
+    pub async fn instance(&self) -> Result<InstanceInfo> {
+        let body = self.get("v3/instance").await?;
+        Ok(serde_json::from_str(&body)?)
+    }
+
+    /// Gets the server's GraphQL schema in SDL format.
+    pub async fn graphql_schema(&self) -> Result<String> {
+        let body = self.get("v3/graphql_schema").await?;
+        self.verify_response(&body)
+    }
+
+    /// Runs a GraphQL query on the server and returns the resulting data.
+    pub async fn graphql<T: DeserializeOwned>(&self, query: &str) -> Result<T> {
+        let body = self.post_signed("v3/graphql", query).await?;
+        Ok(serde_json::from_str(&self.verify_response(&body)?)?)
+    }
+
+    /// Sends a message in a channel.
+    /// This asks the server to create and sign the message, then countersigns it and sends it.
+    ///
+    /// # Errors
+    ///
+    /// This function may return an error for any of the following reasons:
+    ///
+    /// * The user does not have permission to write in the channel.
+    /// * The server's response was not signed by the server.
+    /// * The message could not be signed for any of the reasons outlined by [`Message::sign_final`].
+    pub async fn send_message<S: Into<String>>(
+        &self,
+        hub_id: HubId,
+        channel_id: ChannelId,
+        content: S,
+    ) -> Result<Message> {
+        let body = self
+            .post_signed(
+                &format!("v3/send_message_init/{}/{}", hub_id, channel_id),
+                &content.into(),
+            )
+            .await?;
+        let double_signed = Message::sign_final(
+            extract_armoured(&body)?,
+            &self.server_public_key,
+            &self.key_pair.secret_key,
+            String::new,
+        )?
+        .to_armored_string(None)?;
+        let body = self.post("v3/send_message", double_signed).await?;
+        Ok(serde_json::from_str(&self.verify_response(&body)?)?)
+    }
+
+    /// Gets the changes made to a hub since the given version, see [`crate::api::get_hub_delta`].
+    ///
+    /// # Errors
+    ///
+    /// This function may return an error for any of the following reasons:
+    ///
+    /// * The user is not in the hub.
+    /// * The server's response was not signed by the server.
+    pub async fn hub_delta(&self, hub_id: HubId, since_version: u64) -> Result<HubDelta> {
+        let body = self
+            .post_signed(
+                &format!("v3/hub_delta/{}?since_version={}", hub_id, since_version),
+                "",
+            )
+            .await?;
+        Ok(serde_json::from_str(&self.verify_response(&body)?)?)
+    }
+
+    /// Gets the given comma separated fields of a hub, every field if `fields` is `None`, see [`HubFields::select`].
+    ///
+    /// # Errors
+    ///
+    /// This function may return an error for any of the following reasons:
+    ///
+    /// * The user is not in the hub.
+    /// * A requested field is not a field of [`HubInfo`].
+    /// * The server's response was not signed by the server.
+    pub async fn hub(&self, hub_id: HubId, fields: Option<&str>) -> Result<HubFields> {
+        let path = match fields {
+            Some(fields) => format!("v3/hub/{}?fields={}", hub_id, fields),
+            None => format!("v3/hub/{}", hub_id),
+        };
+        let body = self.request_signed(reqwest::Method::GET, &path, "").await?;
+        Ok(serde_json::from_str(&self.verify_response(&body)?)?)
+    }
+
+    /// Creates a hub owned by the user, see [`crate::api::create_hub_from`].
+    ///
+    /// # Errors
+    ///
+    /// This function may return an error for any of the following reasons:
+    ///
+    /// * The hub could not be created for any of the reasons outlined by [`crate::api::create_hub_from`].
+    /// * The server's response was not signed by the server.
+    pub async fn create_hub(&self, new_hub: &NewHub) -> Result<HubInfo> {
+        let body = self
+            .post_signed("v3/hubs", &serde_json::to_string(new_hub)?)
+            .await?;
+        Ok(serde_json::from_str(&self.verify_response(&body)?)?)
+    }
+
+    /// Creates a channel in a hub, see [`crate::api::create_channel_from`].
+    ///
+    /// # Errors
+    ///
+    /// This function may return an error for any of the following reasons:
+    ///
+    /// * The channel could not be created for any of the reasons outlined by [`crate::api::create_channel_from`].
+    /// * The server's response was not signed by the server.
+    pub async fn create_channel(
+        &self,
+        hub_id: HubId,
+        new_channel: &NewChannel,
+    ) -> Result<ChannelInfo> {
+        let body = self
+            .post_signed(
+                &format!("v3/channels/{}", hub_id),
+                &serde_json::to_string(new_channel)?,
+            )
+            .await?;
+        Ok(serde_json::from_str(&self.verify_response(&body)?)?)
+    }
+
+    /// Gets the webhooks of a channel, see [`crate::api::get_webhooks`].
+    ///
+    /// # Errors
+    ///
+    /// This function may return an error for any of the following reasons:
+    ///
+    /// * The webhooks could not be gotten for any of the reasons outlined by [`crate::api::get_webhooks`].
+    /// * The server's response was not signed by the server.
+    pub async fn webhooks(&self, hub_id: HubId, channel_id: ChannelId) -> Result<Vec<Webhook>> {
+        let body = self
+            .request_signed(
+                reqwest::Method::GET,
+                &format!("v3/webhooks/{}/{}", hub_id, channel_id),
+                "",
+            )
+            .await?;
+        Ok(serde_json::from_str(&self.verify_response(&body)?)?)
+    }
+
+    /// Creates a webhook that posts in a channel, see [`crate::api::create_webhook`].
+    ///
+    /// # Errors
+    ///
+    /// This function may return an error for any of the following reasons:
+    ///
+    /// * The webhook could not be created for any of the reasons outlined by [`crate::api::create_webhook`].
+    /// * The server's response was not signed by the server.
+    pub async fn create_webhook(
+        &self,
+        hub_id: HubId,
+        channel_id: ChannelId,
+        new_webhook: &NewWebhook,
+    ) -> Result<Webhook> {
+        let body = self
+            .post_signed(
+                &format!("v3/webhooks/{}/{}", hub_id, channel_id),
+                &serde_json::to_string(new_webhook)?,
+            )
+            .await?;
+        Ok(serde_json::from_str(&self.verify_response(&body)?)?)
+    }
+
+    /// Deletes a webhook, see [`crate::api::delete_webhook`].
+    ///
+    /// # Errors
+    ///
+    /// This function may return an error for any of the following reasons:
+    ///
+    /// * The webhook could not be deleted for any of the reasons outlined by [`crate::api::delete_webhook`].
+    /// * The server's response was not signed by the server.
+    pub async fn delete_webhook(&self, hub_id: HubId, webhook_id: ID) -> Result {
+        let body = self
+            .request_signed(
+                reqwest::Method::DELETE,
+                &format!("v3/webhooks/{}/{}", hub_id, webhook_id),
+                "",
+            )
+            .await?;
+        self.verify_response(&body)?;
+        Ok(())
+    }
+
+    /// Posts a message with a webhook, see [`crate::message_pipeline::send_webhook`].
+    /// The request is not signed, the webhook's token is all that is needed.
+    ///
+    /// # Errors
+    ///
+    /// This function may return an error for any of the following reasons:
+    ///
+    /// * The message could not be sent for any of the reasons outlined by [`crate::message_pipeline::send_webhook`].
+    /// * The server's response was not signed by the server.
+    pub async fn send_webhook(
+        &self,
+        hub_id: HubId,
+        webhook_id: ID,
+        token: &str,
+        message: &WebhookMessage,
+    ) -> Result<Message> {
+        let body = self
+            .post(
+                &format!("v3/webhook_send/{}/{}/{}", hub_id, webhook_id, token),
+                serde_json::to_string(message)?,
+            )
+            .await?;
+        Ok(serde_json::from_str(&self.verify_response(&body)?)?)
+    }
+
+    /// Sends a GET request to the server and returns the body of the response.
+    async fn get(&self, path: &str) -> Result<String> {
+        let response = self
+            .http
+            .get(format!("{}/{}", self.base_url, path))
+            .header("pgp-fingerprint", &self.fingerprint)
+            .send()
+            .await?;
+        read_body(response).await
+    }
+
+    /// Sends a POST request with the given text as the body and returns the body of the response.
+    async fn post(&self, path: &str, body: String) -> Result<String> {
+        let response = self
+            .http
+            .post(format!("{}/{}", self.base_url, path))
+            .header("pgp-fingerprint", &self.fingerprint)
+            .body(body)
+            .send()
+            .await?;
+        read_body(response).await
+    }
+
+    /// Signs the given text with the user's secret key and sends it as the body of a POST request.
+    async fn post_signed(&self, path: &str, content: &str) -> Result<String> {
+        self.request_signed(reqwest::Method::POST, path, content)
+            .await
+    }
+
+    /// Signs the given text with the user's secret key and sends it as the body of a request with the given method, `path` is relative to the base URL (e.g. `v3/members/{hub_id}`).
+    /// Returns the raw body of the response, use [`WicrsClient::verify_response`] to check it and get its content.
+    /// This is how routes that are not in [`ROUTES`] are reached.
+    pub async fn request_signed(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        content: &str,
+    ) -> Result<String> {
+        let signed = OpenPGPMessage::new_literal("", content)
+            .sign(
+                &self.key_pair.secret_key,
+                String::new,
+                HashAlgorithm::SHA2_256,
+            )?
+            .compress(CompressionAlgorithm::ZIP)?
+            .to_armored_string(None)?;
+        let response = self
+            .http
+            .request(method, format!("{}/{}", self.base_url, path))
+            .header("pgp-fingerprint", &self.fingerprint)
+            .body(signed)
+            .send()
+            .await?;
+        read_body(response).await
+    }
+
+    /// Checks that a response body was signed by the server and returns its content.
+    pub fn verify_response(&self, body: &str) -> Result<String> {
+        let message = OpenPGPMessage::from_string(extract_armoured(body)?)?.0;
+        message.verify(&self.server_public_key)?;
+        read_literal(message)
+    }
+}
+
+/// Returns the body of a response, or an error containing the body if the request was not successful.
+async fn read_body(response: reqwest::Response) -> Result<String> {
+    let success = response.status().is_success();
+    let body = response.text().await?;
+    if success {
+        Ok(body)
+    } else {
+        Err(Error::Other(body))
+    }
+}
+
+/// Gets the text content of a (possibly compressed) signed PGP message.
+fn read_literal(message: OpenPGPMessage) -> Result<String> {
+    let message = message.decompress()?;
+    let literal = message.get_literal().ok_or(Error::InvalidMessage)?;
+    literal.to_string().ok_or(Error::InvalidMessage)
+}
+
+/// Gets the armoured PGP message out of the multipart body of a server response.
+fn extract_armoured(body: &str) -> Result<&str> {
+    let start = body.find(BODY_START).ok_or(Error::InvalidMessage)? + BODY_START.len();
+    let end = body.rfind(BODY_END).ok_or(Error::InvalidMessage)?;
+    body.get(start..end).ok_or(Error::InvalidMessage)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{extract_armoured, ROUTES};
+
+    #[test]
+    fn routes_are_served() {
+        let router = include_str!("httpapi.rs");
+        for route in ROUTES {
+            let pattern = format!(
+                "warp::path!({}",
+                route
+                    .split('/')
+                    .map(|segment| format!("\"{}\"", segment))
+                    .collect::<Vec<String>>()
+                    .join(" / ")
+            );
+            assert!(
+                router.contains(&format!("{})", pattern))
+                    || router.contains(&format!("{} /", pattern)),
+                "{} is not served",
+                route
+            );
+        }
+    }
+
+    #[test]
+    fn extract_from_multipart() {
+        let body = "--the=boundary\r\n\
+            Content-Type: application/pgp-encrypted\r\n\
+            Version: 1\r\n\
+            --the=boundary\r\n\
+            Content-Type: application/octet-stream\r\n\
+            -----BEGIN PGP MESSAGE-----\r\n\
+            --the=boundary--";
+        assert_eq!(
+            extract_armoured(body).expect("Failed to extract the armoured message."),
+            "-----BEGIN PGP MESSAGE-----"
+        );
+        assert!(extract_armoured("not a multipart body").is_err());
+    }
+}
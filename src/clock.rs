@@ -0,0 +1,51 @@
+use std::sync::Mutex as SyncMutex;
+
+use chrono::{DateTime, Duration, Utc};
+
+/// Source of the current time. Code whose behaviour depends on the time takes a clock so that tests can use a [`ManualClock`] and check exactly what happens at a boundary.
+pub trait Clock: Send + Sync {
+    /// Gets the current time.
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system's clock, used everywhere outside of tests.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Clock that stands still until it is moved with [`ManualClock::advance`] or [`ManualClock::set`].
+#[derive(Debug)]
+pub struct ManualClock {
+    now: SyncMutex<DateTime<Utc>>,
+}
+
+impl ManualClock {
+    /// Creates a clock that shows the given time.
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            now: SyncMutex::new(now),
+        }
+    }
+
+    /// Moves the clock forward, or back for a negative duration.
+    pub fn advance(&self, by: Duration) {
+        let mut now = self.now.lock().unwrap_or_else(|err| err.into_inner());
+        *now += by;
+    }
+
+    /// Sets the time the clock shows.
+    pub fn set(&self, to: DateTime<Utc>) {
+        *self.now.lock().unwrap_or_else(|err| err.into_inner()) = to;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
@@ -11,6 +11,54 @@ pub struct Config {
     pub show_version: bool,
     /// ID to give the generated PGP KeyPair.
     pub key_id: Option<String>,
+    /// Limits applied to queries made to the GraphQL endpoint.
+    #[serde(default)]
+    pub graphql: GraphQLConfig,
+    /// Thresholds for logging warnings about slow internal message handling.
+    #[serde(default)]
+    pub instrumentation: InstrumentationConfig,
+    /// Log output format and where to write the audit log.
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    /// Rules for the names of hubs and channels.
+    #[serde(default)]
+    pub names: NamesConfig,
+    /// Maximum lengths of the descriptions of hubs and channels.
+    #[serde(default)]
+    pub descriptions: DescriptionsConfig,
+    /// Per user quotas and the users that can change them.
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    /// Which search indexes are opened ahead of time when the server starts.
+    #[serde(default)]
+    pub search: SearchConfig,
+    /// Server-side link previews of the URLs in messages, off by default.
+    #[serde(default)]
+    pub unfurl: UnfurlConfig,
+    /// Encryption of stored messages and hubs, `None` to store them as plaintext.
+    #[serde(default)]
+    pub encryption: Option<EncryptionConfig>,
+    /// Origins (`scheme://host[:port]`) browsers may use the API and the WebSocket from, `None` allows every origin.
+    #[serde(default)]
+    pub allowed_origins: Option<Vec<String>>,
+}
+
+/// Configuration for the GraphQL endpoint.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GraphQLConfig {
+    /// Maximum depth a query can reach, stops a query from walking through every object on the server.
+    pub max_depth: usize,
+    /// Maximum complexity (roughly the number of fields resolved) a query can have.
+    pub max_complexity: usize,
+}
+
+impl Default for GraphQLConfig {
+    fn default() -> Self {
+        Self {
+            max_depth: 8,
+            max_complexity: 256,
+        }
+    }
 }
 
 impl Default for Config {
@@ -20,10 +68,250 @@ impl Default for Config {
             address: "127.0.0.1:8080".to_string(),
             show_version: true,
             key_id: None,
+            graphql: GraphQLConfig::default(),
+            instrumentation: InstrumentationConfig::default(),
+            logging: LoggingConfig::default(),
+            names: NamesConfig::default(),
+            descriptions: DescriptionsConfig::default(),
+            limits: LimitsConfig::default(),
+            search: SearchConfig::default(),
+            unfurl: UnfurlConfig::default(),
+            encryption: None,
+            allowed_origins: None,
+        }
+    }
+}
+
+/// Thresholds above which warnings about the internal actors are logged, and what `/v3/stats` includes.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct InstrumentationConfig {
+    /// Approximate number of messages waiting to be handled by an actor before a warning is logged.
+    pub warn_mailbox_depth: usize,
+    /// Time in milliseconds an actor can take to handle a message before a warning is logged.
+    pub warn_latency_ms: u64,
+    /// Whether to list the activity of each hub (labeled with the hub's ID) in `/v3/stats`.
+    #[serde(default)]
+    pub hub_activity_labels: bool,
+}
+
+impl Default for InstrumentationConfig {
+    fn default() -> Self {
+        Self {
+            warn_mailbox_depth: 100,
+            warn_latency_ms: 500,
+            hub_activity_labels: false,
+        }
+    }
+}
+
+/// Configuration for log output.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct LoggingConfig {
+    /// Whether to write logs as JSON objects (one per line) instead of human readable text.
+    pub json: bool,
+    /// Path of the file that audit events are written to as JSON, a date suffix is added and a new file is started every day.
+    pub audit_file: Option<String>,
+    /// Size-bounded copy of the audit events that server admins can query, see [`crate::security_log`].
+    #[serde(default)]
+    pub security_log: SecurityLogConfig,
+}
+
+/// Configuration of the security log, see [`crate::security_log`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct SecurityLogConfig {
+    /// Whether audit events are written to the security log.
+    pub enabled: bool,
+    /// Size in bytes a segment can grow to before a new one is started.
+    pub max_segment_bytes: u64,
+    /// Number of segments kept, including the one being written to. The oldest segment is removed when a new one is started.
+    pub segments: usize,
+    /// Number of events that can wait to be written, events that do not fit are dropped and counted.
+    pub queue_size: usize,
+}
+
+impl Default for SecurityLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_segment_bytes: 8 * 1024 * 1024,
+            segments: 4,
+            queue_size: 1024,
+        }
+    }
+}
+
+/// Rules for names, see [`crate::validation::validate_name`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct NamesConfig {
+    /// Rules for hub names.
+    #[serde(default)]
+    pub hub: NameRules,
+    /// Rules for channel names.
+    #[serde(default)]
+    pub channel: NameRules,
+    /// Whether to refuse new hubs with the same name (ignoring case) as another hub of the same owner.
+    #[serde(default)]
+    pub unique_hub_names_per_owner: bool,
+    /// Whether channels of the same hub can have the same name (ignoring case), for deployments whose hubs already have such channels.
+    /// Hubs with duplicate channel names are listed by the `verify` command.
+    #[serde(default)]
+    pub allow_duplicate_channel_names: bool,
+}
+
+/// Rules for one kind of name, lengths are counted in Unicode scalar values after whitespace is normalized.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct NameRules {
+    /// Minimum length of a name.
+    pub min_length: usize,
+    /// Maximum length of a name.
+    pub max_length: usize,
+    /// Whether to allow Unicode letters and numbers, otherwise only ASCII letters, numbers and punctuation are allowed.
+    pub extended_characters: bool,
+}
+
+impl Default for NameRules {
+    fn default() -> Self {
+        Self {
+            min_length: 1,
+            max_length: crate::MAX_NAME_SIZE,
+            extended_characters: true,
+        }
+    }
+}
+
+/// Maximum lengths of descriptions in Unicode scalar values, see [`crate::validation::validate_description`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct DescriptionsConfig {
+    /// Maximum length of the short descriptions shown in lists of hubs and channels.
+    pub max_length: usize,
+    /// Maximum length of the long (markdown) descriptions of hubs and channels.
+    pub max_long_length: usize,
+}
+
+impl Default for DescriptionsConfig {
+    fn default() -> Self {
+        Self {
+            max_length: 512,
+            max_long_length: crate::MAX_DESCRIPTION_SIZE,
+        }
+    }
+}
+
+/// Per user quotas, see [`crate::quotas`]. A limit of `null` means there is no limit.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// Maximum number of hubs a user can own.
+    pub max_hubs_per_user: Option<usize>,
+    /// Maximum total size in bytes of the content of the messages a user can send per day (UTC).
+    pub max_message_bytes_per_day: Option<u64>,
+    /// Fingerprints of the users that can view and override the quotas of other users.
+    pub admins: Vec<String>,
+    /// Maximum number of commands a WebSocket client can send per minute, clients that send more are disconnected.
+    pub max_websocket_commands_per_minute: Option<u32>,
+    /// Maximum number of hub previews that can be requested from one IP address per minute.
+    pub max_hub_previews_per_minute: Option<u32>,
+    /// Maximum number of invite codes that can be resolved from one IP address per minute.
+    pub max_code_resolves_per_minute: Option<u32>,
+    /// Number of previous versions of each edited message that are kept, the oldest are dropped first.
+    pub max_message_edits: usize,
+    /// Maximum number of WebSocket connections a user can have open at the same time.
+    pub max_connections_per_user: Option<usize>,
+    /// Maximum number of hubs one WebSocket connection can be subscribed to.
+    pub max_hub_subscriptions_per_connection: Option<usize>,
+    /// Maximum number of channels one WebSocket connection can be subscribed to.
+    pub max_channel_subscriptions_per_connection: Option<usize>,
+    /// Number of connections a single notification can be sent to before it is logged as an unusually large fan-out, only a sample of them is logged.
+    pub fanout_warning_threshold: usize,
+    /// Number of messages a channel can have before deleting it has to be confirmed with its name, `null` never asks for it.
+    pub confirm_channel_deletion_above: Option<usize>,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_hubs_per_user: Some(100),
+            max_message_bytes_per_day: Some(16 * 1024 * 1024),
+            admins: Vec::new(),
+            max_websocket_commands_per_minute: Some(600),
+            max_hub_previews_per_minute: Some(10),
+            max_code_resolves_per_minute: Some(30),
+            max_message_edits: 20,
+            max_connections_per_user: Some(10),
+            max_hub_subscriptions_per_connection: Some(100),
+            max_channel_subscriptions_per_connection: Some(500),
+            fanout_warning_threshold: 5000,
+            confirm_channel_deletion_above: Some(100),
         }
     }
 }
 
+/// Warm-up and recovery of search indexes, see [`crate::server::warm_up_indexes`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct SearchConfig {
+    /// Number of the most recently active channels whose search indexes are opened in the background when the server starts, `0` disables the warm-up.
+    pub warm_up_channels: usize,
+    /// Only channels that had messages or searches in this many hours before the server started are warmed up.
+    pub warm_up_window_hours: u32,
+    /// Whether an index that can not be opened, even after removing stale lock files, is moved aside and rebuilt from the channel's messages.
+    /// If disabled searches in the channel fail until the index is rebuilt with the `reindex` command.
+    pub auto_reindex_on_corruption: bool,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            warm_up_channels: 32,
+            warm_up_window_hours: 24,
+            auto_reindex_on_corruption: true,
+        }
+    }
+}
+
+/// Server-side link previews, see [`crate::unfurl`]. Pages are only fetched from hosts that resolve to public IP addresses, on the default HTTP and HTTPS ports.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct UnfurlConfig {
+    /// Whether the server fetches the pages linked in messages to create previews of them.
+    pub enabled: bool,
+    /// Hosts that pages can be fetched from, subdomains included, an empty list allows every host.
+    pub allowed_hosts: Vec<String>,
+    /// Maximum number of URLs of each message that are previewed, the first ones in the message are used.
+    pub max_urls_per_message: usize,
+    /// Milliseconds a page (including its redirects) has to be fetched in.
+    pub timeout_ms: u64,
+    /// Maximum number of bytes read from a page, the rest of the page is ignored.
+    pub max_page_bytes: usize,
+    /// Maximum number of redirects followed for each URL.
+    pub max_redirects: usize,
+}
+
+impl Default for UnfurlConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            allowed_hosts: Vec::new(),
+            max_urls_per_message: 3,
+            timeout_ms: 5000,
+            max_page_bytes: 256 * 1024,
+            max_redirects: 3,
+        }
+    }
+}
+
+/// Encryption at rest, see [`crate::encryption`]. Message files, hubs and edit histories are encrypted, search indexes can not be.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct EncryptionConfig {
+    /// File holding the 32 byte key as 64 hexadecimal characters.
+    pub key_file: String,
+    /// Whether messages are still added to the search indexes, which are stored as plaintext. Search is turned off if this is `false`.
+    #[serde(default)]
+    pub search_plaintext_index: bool,
+}
+
 /// Loads the configuration for wicrs_server from `./config.json`. Causes exit with code 1 if the file cannot be found or cannot be deserialized.
 pub fn load_config(path: &str) -> Config {
     if let Ok(read) = std::fs::read_to_string(path) {
@@ -0,0 +1,226 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, IoContext},
+    HubId, Result,
+};
+
+#[cfg(feature = "graphql")]
+use async_graphql::SimpleObject;
+
+/// Folder where the content policy of each hub is stored.
+pub const CONTENT_POLICY_FOLDER: &str = "data/hubs/content_policy/";
+
+/// Bytes at the start of every content policy file, the policy after them is stored in the layout of this version.
+/// Bincode ignores `#[serde(default)]`, so adding a field to [`ContentPolicy`] needs a new version and a way to read the previous layouts.
+pub const CONTENT_POLICY_MAGIC: &[u8; 8] = b"WICRSCP1";
+
+/// Restrictions a hub puts on the content of the messages sent in it, stored separately from the hub itself.
+/// The default policy allows everything the server allows.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+#[cfg_attr(feature = "graphql", derive(SimpleObject))]
+pub struct ContentPolicy {
+    /// Maximum length of a message in characters, `None` to only apply the server's limit of [`crate::MESSAGE_MAX_SIZE`] bytes.
+    pub max_message_length: Option<u32>,
+    /// Whether the server creates previews of the pages linked in messages, only if the server has link previews enabled, see [`crate::unfurl`].
+    pub allow_link_previews: bool,
+}
+
+/// Layout of the content policies stored before link previews could be turned off, files in it have no header.
+#[derive(Deserialize)]
+struct ContentPolicyWithoutPreviews {
+    max_message_length: Option<u32>,
+}
+
+impl Default for ContentPolicy {
+    fn default() -> Self {
+        Self {
+            max_message_length: None,
+            allow_link_previews: true,
+        }
+    }
+}
+
+impl ContentPolicy {
+    /// Gets the path of the file that a hub's content policy is stored in.
+    pub fn get_path(hub_id: HubId) -> PathBuf {
+        crate::paths::content_policy_file(hub_id)
+    }
+
+    /// Decodes the contents of a content policy file, also returning whether it was stored without [`CONTENT_POLICY_MAGIC`] by an older version.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the policy could not be deserialized.
+    pub fn decode(bytes: &[u8]) -> Result<(Self, bool)> {
+        match bytes.strip_prefix(&CONTENT_POLICY_MAGIC[..]) {
+            Some(policy) => Ok((bincode::deserialize(policy)?, false)),
+            None => match bincode::deserialize(bytes) {
+                Ok(policy) => Ok((policy, true)),
+                // Too short for the current layout, the missing fields get their defaults.
+                Err(_) => {
+                    let policy: ContentPolicyWithoutPreviews = bincode::deserialize(bytes)?;
+                    Ok((
+                        Self {
+                            max_message_length: policy.max_message_length,
+                            ..Self::default()
+                        },
+                        true,
+                    ))
+                }
+            },
+        }
+    }
+
+    /// Encodes the policy as the contents of a content policy file.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the policy could not be serialized.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut bytes = CONTENT_POLICY_MAGIC.to_vec();
+        bytes.append(&mut bincode::serialize(self)?);
+        Ok(bytes)
+    }
+
+    /// Loads the content policy of a hub, a hub without the file has the default policy.
+    /// Files in an older layout are read but only rewritten when the policy is saved or by [`crate::maintenance::verify`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The file exists but could not be read.
+    /// * The file's contents could not be deserialized.
+    pub async fn load(hub_id: HubId) -> Result<Self> {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(Self::decode(&bytes)?.0),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_path(path),
+        }
+    }
+
+    /// Saves the content policy of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The content policy folder does not exist and could not be created.
+    /// * The data could not be written to the disk.
+    pub async fn save(&self, hub_id: HubId) -> Result {
+        tokio::fs::create_dir_all(CONTENT_POLICY_FOLDER)
+            .await
+            .with_path(CONTENT_POLICY_FOLDER)?;
+        let path = Self::get_path(hub_id);
+        tokio::fs::write(&path, self.encode()?)
+            .await
+            .with_path(path)
+    }
+
+    /// Removes the content policy of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the file exists but could not be removed.
+    pub async fn remove(hub_id: HubId) -> Result {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::remove_file(&path).await {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result.with_path(path),
+        }
+    }
+
+    /// Checks that the policy can be applied, a message length limit must be at least one character and no more than [`crate::MESSAGE_MAX_SIZE`].
+    ///
+    /// # Errors
+    ///
+    /// This function returns [`Error::InvalidContentPolicy`] if the policy can not be applied.
+    pub fn validate(&self) -> Result {
+        if self
+            .max_message_length
+            .is_some_and(|length| length == 0 || length as usize > crate::MESSAGE_MAX_SIZE)
+        {
+            Err(Error::InvalidContentPolicy)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks that the content of a message follows the policy.
+    ///
+    /// # Errors
+    ///
+    /// This function returns [`Error::MessageTooLong`] if the content is longer than the policy's limit.
+    pub fn check(&self, content: &str) -> Result {
+        match self.max_message_length {
+            Some(length) if content.chars().count() > length as usize => {
+                Err(Error::MessageTooLong(length))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ContentPolicy, CONTENT_POLICY_MAGIC};
+    use crate::error::Error;
+
+    #[test]
+    fn stored_with_version() {
+        let policy = ContentPolicy {
+            max_message_length: Some(500),
+            allow_link_previews: false,
+        };
+        let bytes = policy.encode().unwrap();
+        assert!(bytes.starts_with(CONTENT_POLICY_MAGIC));
+        assert_eq!(ContentPolicy::decode(&bytes).unwrap(), (policy, false));
+        // Files written before the header was added.
+        let legacy = bincode::serialize(&policy).unwrap();
+        assert_eq!(ContentPolicy::decode(&legacy).unwrap(), (policy, true));
+    }
+
+    #[test]
+    fn policies_without_link_previews_load() {
+        // Stored as only the length limit, before `allow_link_previews` was added.
+        for max_message_length in [None, Some(500)].iter() {
+            let bytes = bincode::serialize(max_message_length).unwrap();
+            let expected = ContentPolicy {
+                max_message_length: *max_message_length,
+                allow_link_previews: true,
+            };
+            assert_eq!(ContentPolicy::decode(&bytes).unwrap(), (expected, true));
+        }
+    }
+
+    #[test]
+    fn message_length_limit() {
+        assert!(ContentPolicy::default().check(&"a".repeat(2000)).is_ok());
+        let policy = ContentPolicy {
+            max_message_length: Some(3),
+            ..ContentPolicy::default()
+        };
+        assert!(policy.validate().is_ok());
+        // Characters are counted, not bytes.
+        assert!(policy.check("äöü").is_ok());
+        assert!(matches!(
+            policy.check("abcd"),
+            Err(Error::MessageTooLong(3))
+        ));
+        assert!(matches!(
+            ContentPolicy {
+                max_message_length: Some(0),
+                ..ContentPolicy::default()
+            }
+            .validate(),
+            Err(Error::InvalidContentPolicy)
+        ));
+    }
+}
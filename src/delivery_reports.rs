@@ -0,0 +1,87 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ChannelId, HubId, MessageId};
+
+/// Number of messages that can wait for their first acknowledgement at the same time, the server gives up on the oldest first.
+pub const MAX_PENDING_DELIVERIES: usize = 10_000;
+
+/// Number of messages that can be acknowledged with one [`crate::websocket::ClientMessage::AckMessages`].
+pub const MAX_ACKS_PER_COMMAND: usize = 100;
+
+/// Optional notifications a WebSocket connection asks for with [`crate::websocket::ClientMessage::SetIntents`], all of them are off until it does.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Intents {
+    /// Send a [`crate::websocket::ServerMessage::Delivered`] for each message the connection's user sends, once a connection of another user has acknowledged it.
+    #[serde(default)]
+    pub delivery_reports: bool,
+}
+
+/// Messages whose sender wants a delivery report and that no other user has acknowledged yet, with the ID of their sender.
+/// Only messages sent while the sender has a connection with [`Intents::delivery_reports`] are kept, so users that do not ask for reports cost nothing.
+#[derive(Debug, Default)]
+pub struct PendingDeliveries {
+    senders: HashMap<(HubId, ChannelId, MessageId), String>,
+    /// Messages in the order they were added, may still list messages that were acknowledged since.
+    order: VecDeque<(HubId, ChannelId, MessageId)>,
+}
+
+impl PendingDeliveries {
+    /// Starts waiting for a message to be acknowledged, forgetting the oldest messages once there are more than [`MAX_PENDING_DELIVERIES`].
+    pub fn push(&mut self, key: (HubId, ChannelId, MessageId), sender: String) {
+        if self.senders.insert(key, sender).is_none() {
+            self.order.push_back(key);
+        }
+        while self.order.len() > MAX_PENDING_DELIVERIES {
+            if let Some(oldest) = self.order.pop_front() {
+                self.senders.remove(&oldest);
+            }
+        }
+    }
+
+    /// Records that a user acknowledged a message. Returns the sender of the message if this is the first acknowledgement by another user, acknowledgements by the sender do not count.
+    pub fn acknowledge(
+        &mut self,
+        key: &(HubId, ChannelId, MessageId),
+        user_id: &str,
+    ) -> Option<String> {
+        if self.senders.get(key)? == user_id {
+            return None;
+        }
+        self.senders.remove(key)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PendingDeliveries, MAX_PENDING_DELIVERIES};
+    use crate::{ChannelId, HubId, MessageId};
+
+    #[test]
+    fn first_ack_by_another_user_is_reported() {
+        let key = |n| {
+            (
+                HubId::from_u128(1),
+                ChannelId::from_u128(1),
+                MessageId::from_u128(n),
+            )
+        };
+        let mut pending = PendingDeliveries::default();
+        pending.push(key(1), "AA01".to_string());
+        assert_eq!(pending.acknowledge(&key(1), "AA01"), None);
+        assert_eq!(
+            pending.acknowledge(&key(1), "BB02"),
+            Some("AA01".to_string())
+        );
+        assert_eq!(pending.acknowledge(&key(1), "CC03"), None);
+        for n in 0..MAX_PENDING_DELIVERIES as u128 + 1 {
+            pending.push(key(10 + n), "AA01".to_string());
+        }
+        assert_eq!(pending.acknowledge(&key(10), "BB02"), None);
+        assert_eq!(
+            pending.acknowledge(&key(11), "BB02"),
+            Some("AA01".to_string())
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::IoContext, ChannelId, HubId, Result};
+
+/// Folder where the long descriptions of each hub and its channels are stored.
+pub const LONG_DESCRIPTIONS_FOLDER: &str = "data/hubs/descriptions/";
+
+/// Long (markdown) descriptions of a hub and its channels, stored separately from the hub so that the short descriptions stay cheap to load.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct LongDescriptions {
+    /// Long description of the hub.
+    pub hub: String,
+    /// Long descriptions of the channels that have one.
+    pub channels: HashMap<ChannelId, String>,
+}
+
+impl LongDescriptions {
+    /// Gets the path of the file that a hub's long descriptions are stored in.
+    pub fn get_path(hub_id: HubId) -> PathBuf {
+        crate::paths::hub_descriptions_file(hub_id)
+    }
+
+    /// Loads the long descriptions of a hub, a hub without the file has none.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The file exists but could not be read.
+    /// * The file's contents could not be deserialized.
+    pub async fn load(hub_id: HubId) -> Result<Self> {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(bincode::deserialize(&bytes)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_path(path),
+        }
+    }
+
+    /// Saves the long descriptions of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The descriptions folder does not exist and could not be created.
+    /// * The data could not be written to the disk.
+    pub async fn save(&self, hub_id: HubId) -> Result {
+        tokio::fs::create_dir_all(LONG_DESCRIPTIONS_FOLDER)
+            .await
+            .with_path(LONG_DESCRIPTIONS_FOLDER)?;
+        let path = Self::get_path(hub_id);
+        tokio::fs::write(&path, bincode::serialize(self)?)
+            .await
+            .with_path(path)
+    }
+
+    /// Removes the long descriptions of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the file exists but could not be removed.
+    pub async fn remove(hub_id: HubId) -> Result {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::remove_file(&path).await {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result.with_path(path),
+        }
+    }
+
+    /// Gets the long description of a channel, empty if it has none.
+    pub fn channel(&self, channel_id: ChannelId) -> &str {
+        self.channels
+            .get(&channel_id)
+            .map_or("", |description| description.as_str())
+    }
+
+    /// Sets the long description of a channel, returning the previous one. Empty descriptions are not stored.
+    pub fn set_channel(&mut self, channel_id: ChannelId, description: String) -> String {
+        let previous = if description.is_empty() {
+            self.channels.remove(&channel_id)
+        } else {
+            self.channels.insert(channel_id, description)
+        };
+        previous.unwrap_or_default()
+    }
+}
@@ -0,0 +1,199 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, IoContext},
+    locks::KeyedLocks,
+    server::{self, ServerNotification},
+    ChannelId, HubId, Result,
+};
+
+/// Folder where the drafts of each user are stored.
+pub const DRAFTS_FOLDER: &str = "data/users/drafts/";
+
+/// Maximum size in bytes of a draft, the same as the maximum size of a message.
+pub const MAX_DRAFT_SIZE: usize = crate::MESSAGE_MAX_SIZE;
+
+/// Number of drafts kept for each user, the drafts that were changed longest ago are dropped first.
+pub const MAX_DRAFTS: usize = 256;
+
+/// Makes sure only one change is made to the drafts of a user at a time, see [`set`] and [`clear`].
+static DRAFTS_LOCKS: KeyedLocks<String> = KeyedLocks::new();
+
+/// Text a user has typed in a channel but not sent yet.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Draft {
+    pub hub_id: HubId,
+    pub channel_id: ChannelId,
+    pub content: String,
+    /// Time the draft was last changed.
+    pub updated: DateTime<Utc>,
+}
+
+/// The drafts of one user, at most one per channel and most recently changed first.
+/// Only the user can read them, they are stored apart from hubs so that nothing else that is sent to other users can include them.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct UserDrafts {
+    pub drafts: Vec<Draft>,
+}
+
+impl UserDrafts {
+    /// Gets the path of the file that a user's drafts are stored in.
+    pub fn get_path(user_id: &str) -> PathBuf {
+        crate::paths::drafts_file(user_id)
+    }
+
+    /// Loads the drafts of a user, a user without the file has none.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The user ID is not a hex encoded fingerprint.
+    /// * The file exists but could not be read.
+    /// * The file is encrypted and could not be decrypted, see [`crate::encryption::open`].
+    /// * The file's contents could not be deserialized.
+    pub async fn load(user_id: &str) -> Result<Self> {
+        if hex::decode(user_id).is_err() {
+            return Err(Error::InvalidFingerprint);
+        }
+        let path = Self::get_path(user_id);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(bincode::deserialize(&crate::encryption::open(&bytes)?)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_path(path),
+        }
+    }
+
+    /// Saves the drafts of a user, the file is removed once the user has no drafts left.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The drafts folder does not exist and could not be created.
+    /// * The data could not be encrypted, see [`crate::encryption::seal`].
+    /// * The data could not be written to the disk or the empty file could not be removed.
+    pub async fn save(&self, user_id: &str) -> Result {
+        let path = Self::get_path(user_id);
+        if self.drafts.is_empty() {
+            return match tokio::fs::remove_file(&path).await {
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                result => result.with_path(path),
+            };
+        }
+        tokio::fs::create_dir_all(DRAFTS_FOLDER)
+            .await
+            .with_path(DRAFTS_FOLDER)?;
+        tokio::fs::write(&path, crate::encryption::seal(bincode::serialize(self)?)?)
+            .await
+            .with_path(path)
+    }
+
+    /// Sets the draft of a channel, empty content removes it. Returns true if the drafts changed.
+    /// Once there are more than [`MAX_DRAFTS`] the ones changed longest ago are forgotten.
+    pub fn set(&mut self, hub_id: HubId, channel_id: ChannelId, content: &str) -> bool {
+        let previous = self
+            .drafts
+            .iter()
+            .position(|draft| draft.hub_id == hub_id && draft.channel_id == channel_id)
+            .map(|index| self.drafts.remove(index));
+        if content.is_empty() {
+            return previous.is_some();
+        }
+        let changed = previous.is_none_or(|previous| previous.content != content);
+        self.drafts.insert(
+            0,
+            Draft {
+                hub_id,
+                channel_id,
+                content: content.to_string(),
+                updated: Utc::now(),
+            },
+        );
+        self.drafts.truncate(MAX_DRAFTS);
+        changed
+    }
+}
+
+/// Runs a change on the drafts of a user and saves them if it changed anything, the user's connections are told about the new draft of the channel.
+async fn update(
+    user_id: &str,
+    hub_id: HubId,
+    channel_id: ChannelId,
+    content: &str,
+) -> Result<bool> {
+    let _guard = DRAFTS_LOCKS.lock(user_id.to_string()).await;
+    let mut drafts = UserDrafts::load(user_id).await?;
+    if !drafts.set(hub_id, channel_id, content) {
+        return Ok(false);
+    }
+    drafts.save(user_id).await?;
+    server::publish(ServerNotification::DraftUpdated(
+        user_id.to_string(),
+        hub_id,
+        channel_id,
+        Some(content.to_string()).filter(|content| !content.is_empty()),
+    ))
+    .await;
+    Ok(true)
+}
+
+/// Sets the draft a user has in a channel, empty content removes it. Checking that the user can read the channel is up to the caller.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The draft is bigger than [`MAX_DRAFT_SIZE`], [`Error::TooBig`].
+/// * The drafts could not be loaded or saved for any of the reasons outlined in [`UserDrafts::load`] and [`UserDrafts::save`].
+pub async fn set(user_id: &str, hub_id: HubId, channel_id: ChannelId, content: &str) -> Result {
+    if content.len() > MAX_DRAFT_SIZE {
+        return Err(Error::TooBig);
+    }
+    update(user_id, hub_id, channel_id, content).await?;
+    Ok(())
+}
+
+/// Removes the draft a user has in a channel, called when the user sends a message in the channel.
+///
+/// # Errors
+///
+/// This function returns an error if the drafts could not be loaded or saved, see [`UserDrafts::load`] and [`UserDrafts::save`].
+pub async fn clear(user_id: &str, hub_id: HubId, channel_id: ChannelId) -> Result {
+    update(user_id, hub_id, channel_id, "").await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{UserDrafts, MAX_DRAFTS};
+    use crate::{ChannelId, HubId};
+
+    #[test]
+    fn one_draft_per_channel() {
+        let hub_id = HubId::from_u128(1);
+        let mut drafts = UserDrafts::default();
+        assert!(drafts.set(hub_id, ChannelId::from_u128(1), "hel"));
+        assert!(drafts.set(hub_id, ChannelId::from_u128(2), "other"));
+        assert!(drafts.set(hub_id, ChannelId::from_u128(1), "hello"));
+        assert!(!drafts.set(hub_id, ChannelId::from_u128(1), "hello"));
+        assert_eq!(drafts.drafts.len(), 2);
+        assert_eq!(drafts.drafts[0].content, "hello");
+        assert!(drafts.set(hub_id, ChannelId::from_u128(1), ""));
+        assert!(!drafts.set(hub_id, ChannelId::from_u128(1), ""));
+        assert_eq!(drafts.drafts.len(), 1);
+        for n in 0..MAX_DRAFTS as u128 + 1 {
+            drafts.set(hub_id, ChannelId::from_u128(10 + n), "draft");
+        }
+        assert_eq!(drafts.drafts.len(), MAX_DRAFTS);
+        assert!(!drafts
+            .drafts
+            .iter()
+            .any(|draft| draft.channel_id == ChannelId::from_u128(2)));
+    }
+}
@@ -0,0 +1,198 @@
+use std::{
+    borrow::Cow,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    XChaCha20Poly1305, XNonce,
+};
+use rand::RngCore;
+
+use crate::{
+    config::EncryptionConfig,
+    error::{Error, IoContext},
+    Result,
+};
+
+/// Bytes at the start of every encrypted piece of data, data without them is stored as plaintext.
+pub const ENCRYPTED_MAGIC: &[u8; 8] = b"WICRSEN1";
+
+/// Size of the random nonce stored after [`ENCRYPTED_MAGIC`], XChaCha20-Poly1305 nonces are large enough to be picked at random.
+const NONCE_SIZE: usize = 24;
+
+/// Size of the key in bytes.
+const KEY_SIZE: usize = 32;
+
+/// Key and settings set by [`init`], data is written as plaintext until it is called with a configuration.
+static ENCRYPTION: RwLock<Option<Arc<Encryption>>> = RwLock::new(None);
+
+struct Encryption {
+    cipher: XChaCha20Poly1305,
+    search_plaintext_index: bool,
+}
+
+/// Loads the key of the given configuration and encrypts all data written from then on, `None` turns encryption off.
+/// Data written before is still read whether it is encrypted or not, see [`open`].
+///
+/// # Errors
+///
+/// This function returns an error if the key file could not be read or does not hold a valid key, see [`load_key`].
+pub fn init(config: Option<&EncryptionConfig>) -> Result {
+    let encryption = match config {
+        Some(config) => Some(Arc::new(Encryption {
+            cipher: load_key(&config.key_file)?,
+            search_plaintext_index: config.search_plaintext_index,
+        })),
+        None => None,
+    };
+    *ENCRYPTION.write().unwrap_or_else(|err| err.into_inner()) = encryption;
+    Ok(())
+}
+
+fn current() -> Option<Arc<Encryption>> {
+    ENCRYPTION
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone()
+}
+
+/// Reads a key file, which must hold the 32 byte key as 64 hexadecimal characters (e.g. from `openssl rand -hex 32`).
+///
+/// # Errors
+///
+/// This function will return an error in the following situations, but is not
+/// limited to just these cases:
+///
+/// * The file could not be read.
+/// * The file does not hold a valid key, [`Error::InvalidEncryptionKey`].
+pub fn load_key<P: AsRef<Path>>(path: P) -> Result<XChaCha20Poly1305> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).with_path(path)?;
+    let key = hex::decode(text.trim()).map_err(|_| Error::InvalidEncryptionKey)?;
+    if key.len() != KEY_SIZE {
+        return Err(Error::InvalidEncryptionKey);
+    }
+    XChaCha20Poly1305::new_from_slice(&key).map_err(|_| Error::InvalidEncryptionKey)
+}
+
+/// Whether data is encrypted when it is written.
+pub fn enabled() -> bool {
+    current().is_some()
+}
+
+/// Whether messages are added to the search indexes, which are stored as plaintext. Search is turned off when data is encrypted unless the configuration allows a plaintext index.
+pub fn search_enabled() -> bool {
+    current().is_none_or(|encryption| encryption.search_plaintext_index)
+}
+
+/// Checks if data was encrypted by [`seal`].
+pub fn is_encrypted(bytes: &[u8]) -> bool {
+    bytes.starts_with(ENCRYPTED_MAGIC)
+}
+
+/// Encrypts data with a random nonce, the result starts with [`ENCRYPTED_MAGIC`] followed by the nonce.
+///
+/// # Errors
+///
+/// This function returns an error if the data is too large to be encrypted.
+pub fn encrypt_with(cipher: &XChaCha20Poly1305, bytes: &[u8]) -> Result<Vec<u8>> {
+    let mut nonce = [0; NONCE_SIZE];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let ciphertext = cipher
+        .encrypt(XNonce::from_slice(&nonce), bytes)
+        .map_err(|_| Error::Other("Unable to encrypt data.".to_string()))?;
+    let mut sealed = Vec::with_capacity(ENCRYPTED_MAGIC.len() + NONCE_SIZE + ciphertext.len());
+    sealed.extend_from_slice(ENCRYPTED_MAGIC);
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+/// Decrypts data encrypted by [`encrypt_with`].
+///
+/// # Errors
+///
+/// This function returns [`Error::DecryptionFailed`] if the data is not encrypted, was encrypted with another key or was changed after it was encrypted.
+pub fn decrypt_with(cipher: &XChaCha20Poly1305, bytes: &[u8]) -> Result<Vec<u8>> {
+    let sealed = bytes
+        .strip_prefix(&ENCRYPTED_MAGIC[..])
+        .filter(|sealed| sealed.len() >= NONCE_SIZE)
+        .ok_or(Error::DecryptionFailed)?;
+    let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+    cipher
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| Error::DecryptionFailed)
+}
+
+/// Encrypts data if `encrypt` is true, otherwise returns it as it is. Used by the maintenance commands that convert the data directory.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The data is to be encrypted but no key is configured, [`Error::EncryptionKeyMissing`].
+/// * The data could not be encrypted for any of the reasons outlined by [`encrypt_with`].
+pub fn seal_with(bytes: Vec<u8>, encrypt: bool) -> Result<Vec<u8>> {
+    if !encrypt {
+        return Ok(bytes);
+    }
+    let encryption = current().ok_or(Error::EncryptionKeyMissing)?;
+    encrypt_with(&encryption.cipher, &bytes)
+}
+
+/// Prepares data to be written: encrypts it if encryption is configured, otherwise returns it as it is.
+///
+/// # Errors
+///
+/// This function returns an error if the data could not be encrypted, see [`encrypt_with`].
+pub fn seal(bytes: Vec<u8>) -> Result<Vec<u8>> {
+    match current() {
+        Some(encryption) => encrypt_with(&encryption.cipher, &bytes),
+        None => Ok(bytes),
+    }
+}
+
+/// Reads data written by [`seal`]: encrypted data is decrypted, plaintext is returned as it is whether or not encryption is configured.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The data is encrypted but no key is configured, [`Error::EncryptionKeyMissing`].
+/// * The data could not be decrypted for any of the reasons outlined by [`decrypt_with`].
+pub fn open(bytes: &[u8]) -> Result<Cow<'_, [u8]>> {
+    if !is_encrypted(bytes) {
+        return Ok(Cow::Borrowed(bytes));
+    }
+    let encryption = current().ok_or(Error::EncryptionKeyMissing)?;
+    decrypt_with(&encryption.cipher, bytes).map(Cow::Owned)
+}
+
+#[cfg(test)]
+mod test {
+    use chacha20poly1305::{aead::KeyInit, XChaCha20Poly1305};
+
+    use super::{decrypt_with, encrypt_with, is_encrypted, open};
+    use crate::error::Error;
+
+    #[test]
+    fn round_trip_and_detection() {
+        let cipher = XChaCha20Poly1305::new_from_slice(&[7; 32]).unwrap();
+        let sealed = encrypt_with(&cipher, b"hello").unwrap();
+        assert!(is_encrypted(&sealed));
+        assert_ne!(encrypt_with(&cipher, b"hello").unwrap(), sealed);
+        assert_eq!(decrypt_with(&cipher, &sealed).unwrap(), b"hello");
+        let other = XChaCha20Poly1305::new_from_slice(&[8; 32]).unwrap();
+        assert!(matches!(
+            decrypt_with(&other, &sealed),
+            Err(Error::DecryptionFailed)
+        ));
+        let mut tampered = sealed;
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(decrypt_with(&cipher, &tampered).is_err());
+        // Plaintext is passed through, so directories with both kinds of data can be read.
+        assert_eq!(&*open(b"plain").unwrap(), b"plain");
+    }
+}
@@ -1,4 +1,4 @@
-use std::string::FromUtf8Error;
+use std::{error::Error as StdError, path::PathBuf, string::FromUtf8Error};
 
 use crate::permission::{ChannelPermission, HubPermission};
 use reqwest::StatusCode;
@@ -32,12 +32,12 @@ pub enum Error {
     #[error("permission group does not exist")]
     GroupNotFound,
     #[error("invalid name")]
-    InvalidName,
+    InvalidName(#[source] crate::validation::InvalidNameReason),
     #[error("something strange happened")]
     UnexpectedServerArg,
     #[error("text object to big")]
     TooBig,
-    #[error("not utf-8 bytes")]
+    #[error("text is not valid utf-8 or is empty")]
     InvalidText,
     #[error("bad message format")]
     InvalidMessage,
@@ -49,30 +49,136 @@ pub enum Error {
     InternalMessageFailed,
     #[error("internal handler servers failed to start")]
     ServerStartFailed,
+    #[error("data directory is in use by another instance")]
+    DataLocked,
     #[error("IO serror")]
     Io(#[from] std::io::Error),
+    #[error("IO serror")]
+    IoPath {
+        path: PathBuf,
+        source: std::io::Error,
+    },
     #[error("JSON error")]
     Json(#[from] serde_json::Error),
     #[error("Bincode error")]
     Bincode(#[from] bincode::Error),
+    #[cfg(feature = "search")]
     #[error("Tantivy error")]
     Tantivy(#[from] tantivy::error::TantivyError),
+    #[cfg(feature = "search")]
     #[error("Tantivy error")]
     TantivyOpenDirectory(#[from] tantivy::directory::error::OpenDirectoryError),
+    #[cfg(feature = "search")]
     #[error("Tantivy error")]
     TantivyOpenRead(#[from] tantivy::directory::error::OpenReadError),
+    #[cfg(feature = "search")]
     #[error("Tantivy error")]
     TantivyOpenWrite(#[from] tantivy::directory::error::OpenWriteError),
+    #[cfg(feature = "search")]
     #[error("Tantivy error")]
     TantivyQueryParse(#[from] tantivy::query::QueryParserError),
+    #[error("search is disabled on this server")]
+    SearchDisabled,
+    #[error("markdown rendering is not enabled on this server")]
+    RenderingDisabled,
     #[error("could not get a Tantivy index writer")]
     GetIndexWriter,
     #[error("could not get a Tantivy index reader")]
     GetIndexReader,
+    #[error("time range starts after it ends")]
+    InvalidTimeRange,
+    #[error("image is not a PNG, JPEG, GIF or WebP image")]
+    InvalidImage,
+    #[error("image file is too large")]
+    ImageTooLarge,
+    #[error("image dimensions are too large")]
+    ImageDimensionsTooLarge,
+    #[error("hub does not have that image")]
+    ImageNotFound,
+    #[error("the \"{0}\" quota has been reached")]
+    LimitExceeded(crate::quotas::Quota),
+    #[error("user is not a server admin")]
+    NotAdmin,
+    #[error("this key has been revoked, ask a server admin to link a new key to your account")]
+    KeyRevoked,
+    #[error("key already belongs to a user, only keys that were never used can be linked")]
+    KeyInUse,
+    #[error("too many requests, try again later")]
+    RateLimited,
+    #[error("description is longer than the limit of {0} characters")]
+    DescriptionTooLong(usize),
+    #[error("hub was changed, it is now at version {0}")]
+    VersionMismatch(u64),
+    #[error("If-Match header is not a hub version")]
+    InvalidIfMatch,
+    #[error("at most {0} messages can be moved at once")]
+    TooManyMessages(usize),
+    #[error("at most {0} messages can be acknowledged at once")]
+    TooManyAcks(usize),
+    #[error("messages can not be moved to the channel they are in")]
+    SameChannel,
+    #[error("hub deletion token is not valid, request a new one")]
+    InvalidDeletionToken,
+    #[error("channel has more than {0} messages, confirm its deletion with its name")]
+    ChannelDeletionNotConfirmed(usize),
+    #[error("avatar must be an https URL of at most {0} bytes")]
+    InvalidAvatar(usize),
+    #[error("name is already taken")]
+    NameTaken,
+    #[error("user does not own the hub")]
+    NotOwner,
+    #[error("user already owns the hub")]
+    AlreadyOwner,
+    #[error("the owner of a hub can not leave it, transfer the hub to another member first")]
+    OwnerCannotLeave,
+    #[error("at most {0} hubs can be changed at once")]
+    TooManyHubs(usize),
+    #[error("a subscription profile can have at most {0} hubs and channels")]
+    TooManyProfileEntries(usize),
+    #[error("no account export is ready")]
+    ExportNotReady,
+    #[error("purge not found")]
+    PurgeNotFound,
+    #[error("invite not found")]
+    InviteNotFound,
+    #[error("webhook not found")]
+    WebhookNotFound,
+    #[error("channel can not have more than {0} webhooks")]
+    TooManyWebhooks(usize),
+    #[error("origin is not allowed")]
+    OriginNotAllowed,
+    #[error("the \"wicrs\" WebSocket subprotocol was not requested")]
+    SubprotocolMissing,
+    #[error("invalid allowed origin \"{0}\"")]
+    InvalidOrigin(String),
+    #[error("group color must be between 0x000000 and 0xFFFFFF")]
+    InvalidGroupColor,
+    #[error("hub has disabled its leaderboard")]
+    LeaderboardDisabled,
+    #[error("message is longer than the hub's limit of {0} characters")]
+    MessageTooLong(u32),
+    #[error("message length limit must be between 1 and the server's limit")]
+    InvalidContentPolicy,
+    #[error("URL is not allowed for link previews")]
+    UnfurlBlocked,
+    #[error("data is encrypted but no encryption key is configured")]
+    EncryptionKeyMissing,
+    #[error("encryption key file must hold a 32 byte key as 64 hexadecimal characters")]
+    InvalidEncryptionKey,
+    #[error("data could not be decrypted with the configured key")]
+    DecryptionFailed,
     #[error("request expired")]
     Expired,
     #[error("not authenticated for websocket")]
     WsNotAuthenticated,
+    #[error("server is overloaded, try again later")]
+    Overloaded,
+    #[error("user can not have more than {0} connections open")]
+    TooManyConnections(usize),
+    #[error("connection can not be subscribed to more than {0} hubs")]
+    TooManyHubSubscriptions(usize),
+    #[error("connection can not be subscribed to more than {0} channels")]
+    TooManyChannelSubscriptions(usize),
     #[error("Warp error")]
     Warp(#[from] warp::Error),
     #[error("Reqwest error")]
@@ -83,6 +189,10 @@ pub enum Error {
     #[error("ID error")]
     #[allow(clippy::upper_case_acronyms)]
     ID(#[from] uuid::Error),
+    #[error("unknown value \"{0}\"")]
+    UnknownValue(String),
+    #[error("unknown hub field \"{0}\", valid fields are: {1}")]
+    UnknownField(String, String),
     #[error("could not find a pgp public key with that ID")]
     PublicKeyNotFound,
     #[error("invalid PGP fingerprint")]
@@ -93,6 +203,40 @@ pub enum Error {
     Other(String),
 }
 
+impl Error {
+    /// Formats the error followed by each of its sources, for example `Tantivy error: <the Tantivy error>`.
+    /// The [`std::fmt::Display`] output of an error on its own only ever names the variant so that clients can match on it, the chain also includes the path of an [`Error::IoPath`].
+    /// It is meant for the server's logs, clients are only sent the [`std::fmt::Display`] output.
+    pub fn chain(&self) -> String {
+        let mut result = self.to_string();
+        if let Error::IoPath { path, .. } = self {
+            result.push_str(&format!(" at {}", path.display()));
+        }
+        let mut source = self.source();
+        while let Some(error) = source {
+            result.push_str(": ");
+            result.push_str(&error.to_string());
+            source = error.source();
+        }
+        result
+    }
+}
+
+/// Adds the path that an IO operation was working on to its error.
+pub trait IoContext<T> {
+    /// Converts the IO error into an [`Error::IoPath`] holding `path`.
+    fn with_path<P: Into<PathBuf>>(self, path: P) -> Result<T>;
+}
+
+impl<T> IoContext<T> for std::io::Result<T> {
+    fn with_path<P: Into<PathBuf>>(self, path: P) -> Result<T> {
+        self.map_err(|source| Error::IoPath {
+            path: path.into(),
+            source,
+        })
+    }
+}
+
 impl From<String> for Error {
     fn from(s: String) -> Self {
         Self::Other(s)
@@ -113,11 +257,24 @@ impl From<&Error> for StatusCode {
             Error::Banned
             | Error::Muted
             | Error::MissingChannelPermission(_)
-            | Error::MissingHubPermission(_) => Self::FORBIDDEN,
-            Error::ChannelNotFound
+            | Error::MissingHubPermission(_)
+            | Error::NotAdmin
+            | Error::NotOwner
+            | Error::KeyRevoked
+            | Error::LeaderboardDisabled
+            | Error::InvalidDeletionToken
+            | Error::OriginNotAllowed
+            | Error::SubprotocolMissing => Self::FORBIDDEN,
+            Error::HubNotFound
+            | Error::ChannelNotFound
             | Error::GroupNotFound
             | Error::MemberNotFound
             | Error::MessageNotFound
+            | Error::ImageNotFound
+            | Error::ExportNotReady
+            | Error::PurgeNotFound
+            | Error::InviteNotFound
+            | Error::WebhookNotFound
             | Error::NotInHub => Self::NOT_FOUND,
             Error::ID(_)
             | Error::Http(_)
@@ -125,8 +282,41 @@ impl From<&Error> for StatusCode {
             | Error::InvalidText
             | Error::TooBig
             | Error::InvalidFingerprint
-            | Error::InvalidName => Self::BAD_REQUEST,
-            Error::AlreadyTyping | Error::NotTyping => Self::CONFLICT,
+            | Error::InvalidTimeRange
+            | Error::InvalidImage
+            | Error::ImageDimensionsTooLarge
+            | Error::InvalidName(_)
+            | Error::DescriptionTooLong(_)
+            | Error::InvalidGroupColor
+            | Error::MessageTooLong(_)
+            | Error::InvalidContentPolicy
+            | Error::UnfurlBlocked
+            | Error::UnknownValue(_)
+            | Error::UnknownField(_, _)
+            | Error::TooManyMessages(_)
+            | Error::TooManyAcks(_)
+            | Error::TooManyHubs(_)
+            | Error::TooManyProfileEntries(_)
+            | Error::TooManyWebhooks(_)
+            | Error::ChannelDeletionNotConfirmed(_)
+            | Error::SameChannel
+            | Error::InvalidAvatar(_)
+            | Error::InvalidIfMatch => Self::BAD_REQUEST,
+            Error::ImageTooLarge => Self::PAYLOAD_TOO_LARGE,
+            Error::LimitExceeded(_)
+            | Error::RateLimited
+            | Error::TooManyConnections(_)
+            | Error::TooManyHubSubscriptions(_)
+            | Error::TooManyChannelSubscriptions(_) => Self::TOO_MANY_REQUESTS,
+            Error::AlreadyTyping
+            | Error::NotTyping
+            | Error::VersionMismatch(_)
+            | Error::AlreadyOwner
+            | Error::OwnerCannotLeave
+            | Error::KeyInUse
+            | Error::NameTaken => Self::CONFLICT,
+            Error::SearchDisabled | Error::RenderingDisabled => Self::NOT_IMPLEMENTED,
+            Error::Overloaded => Self::SERVICE_UNAVAILABLE,
             _ => Self::INTERNAL_SERVER_ERROR,
         }
     }
@@ -134,8 +324,85 @@ impl From<&Error> for StatusCode {
 
 impl warp::reply::Reply for Error {
     fn into_response(self) -> warp::reply::Response {
+        let status: StatusCode = (&self).into();
+        if status.is_server_error() {
+            error!("Request failed: {}", self.chain());
+        }
         let mut response = warp::reply::Response::new(warp::hyper::Body::from(self.to_string()));
-        *response.status_mut() = (&self).into();
+        *response.status_mut() = status;
+        match self {
+            Error::VersionMismatch(version) => {
+                if let Ok(value) = format!("\"{}\"", version).parse() {
+                    response.headers_mut().insert("etag", value);
+                }
+            }
+            Error::Overloaded => {
+                response
+                    .headers_mut()
+                    .insert("retry-after", warp::http::HeaderValue::from_static("1"));
+            }
+            _ => {}
+        }
         response
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Error, IoContext};
+    use crate::{
+        permission::{ChannelPermission, HubPermission},
+        quotas::Quota,
+    };
+
+    #[test]
+    fn chain_includes_sources() {
+        let error = Err::<(), _>(std::io::Error::new(std::io::ErrorKind::NotFound, "missing"))
+            .with_path("data/hubs/info/1")
+            .unwrap_err();
+        assert_eq!(error.to_string(), "IO serror");
+        assert_eq!(error.chain(), "IO serror at data/hubs/info/1: missing");
+        assert_eq!(Error::HubNotFound.chain(), "hub does not exist");
+    }
+
+    #[tokio::test]
+    async fn responses_leave_out_paths() {
+        use warp::{hyper::body::to_bytes, Reply};
+
+        let error = Err::<(), _>(std::io::Error::new(std::io::ErrorKind::NotFound, "missing"))
+            .with_path("data/hubs/info/1")
+            .unwrap_err();
+        let body = to_bytes(error.into_response().into_body()).await.unwrap();
+        assert_eq!(&body[..], b"IO serror");
+    }
+
+    #[test]
+    fn nested_error_strings() {
+        // WebSocket clients match on these, so they must not change.
+        assert_eq!(
+            Error::MissingHubPermission(HubPermission::ReadChannels).to_string(),
+            "user does not have the \"READ_CHANNELS\" hub permission"
+        );
+        assert_eq!(
+            Error::MissingChannelPermission(ChannelPermission::SendTts).to_string(),
+            "user does not have the \"SEND_TTS\" channel permission"
+        );
+        assert_eq!(
+            Error::LimitExceeded(Quota::MessageBytesPerDay).to_string(),
+            "the \"MESSAGE_BYTES_PER_DAY\" quota has been reached"
+        );
+        for quota in Quota::VARIANTS {
+            assert_eq!(quota.to_string().parse::<Quota>().unwrap(), *quota);
+        }
+        for permission in HubPermission::VARIANTS {
+            let message = Error::MissingHubPermission(*permission).to_string();
+            let name = message.split('"').nth(1).unwrap();
+            assert_eq!(name.parse::<HubPermission>().unwrap(), *permission);
+        }
+        for permission in ChannelPermission::VARIANTS {
+            let message = Error::MissingChannelPermission(*permission).to_string();
+            let name = message.split('"').nth(1).unwrap();
+            assert_eq!(name.parse::<ChannelPermission>().unwrap(), *permission);
+        }
+    }
+}
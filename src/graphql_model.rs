@@ -2,15 +2,19 @@ use std::{collections::HashSet, sync::Arc};
 
 use crate::{
     api,
-    channel::Channel,
-    hub::{Hub, HubMember, PermissionGroup},
+    channel::{Channel, SignedMessage},
+    hub::{
+        GroupChannelPermission, Hub, HubMember, MemberChannelPermission, NewChannel,
+        PermissionGroup,
+    },
+    hub_deletion::DeletionToken,
+    instrumentation::InstrumentedAddr,
     permission::{ChannelPermission, ChannelPermissionSet, HubPermission, HubPermissionSet},
     server::Server,
-    ID,
+    ChannelId, HubId, MessageId, ID,
 };
 use async_graphql::*;
 use chrono::{DateTime, Utc};
-use xactor::Addr;
 
 pub struct QueryRoot;
 
@@ -23,16 +27,24 @@ impl QueryRoot {
     async fn hub(
         &self,
         ctx: &Context<'_>,
-        #[graphql(desc = "ID of a hub.")] id: ID,
+        #[graphql(desc = "ID of a hub.")] id: HubId,
+        #[graphql(
+            desc = "Get the hub without stripping the channels the requester can not see, only for server admins.",
+            default
+        )]
+        raw: bool,
     ) -> Result<Hub> {
-        let hub = Hub::load(id).await?;
-        Ok(hub.strip(self.requester(ctx).await?)?)
+        let requester = self.requester(ctx).await?;
+        if raw {
+            return Ok(api::get_raw_hub(requester, id).await?);
+        }
+        Ok(api::get_hub(requester, id).await?)
     }
 
     async fn hubs(
         &self,
         ctx: &Context<'_>,
-        #[graphql(desc = "List of the IDs of the hubs to get.")] ids: Vec<ID>,
+        #[graphql(desc = "List of the IDs of the hubs to get.")] ids: Vec<HubId>,
     ) -> Result<Vec<Hub>> {
         let mut result = Vec::new();
         for id in ids {
@@ -45,14 +57,19 @@ impl QueryRoot {
 
 pub struct MutationRoot;
 
+/// Version from the `If-Match` header of a GraphQL request, hub mutations are only made if the hub is at this version.
+/// Every mutation checks it separately, so a request should only make one change to a hub when it is set.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IfMatch(pub Option<u64>);
+
 struct ChannelMutator {
     user_id: String,
-    hub_id: ID,
-    channel_id: ID,
+    hub_id: HubId,
+    channel_id: ChannelId,
 }
 
 impl ChannelMutator {
-    fn new(user_id: String, hub_id: ID, channel_id: ID) -> Self {
+    fn new(user_id: String, hub_id: HubId, channel_id: ChannelId) -> Self {
         Self {
             user_id,
             hub_id,
@@ -78,52 +95,152 @@ impl ChannelMutator {
                 .await?,
         )
     }
+    async fn description_long(
+        &self,
+        #[graphql(desc = "New long (markdown) description for the channel, empty to remove it.")]
+        new: String,
+    ) -> Result<String> {
+        Ok(
+            api::change_channel_long_description(&self.user_id, self.hub_id, self.channel_id, new)
+                .await?,
+        )
+    }
 }
 
 struct HubMutator {
     user_id: String,
-    hub_id: ID,
+    hub_id: HubId,
+    expected_version: Option<u64>,
 }
 
 impl HubMutator {
-    fn new(user_id: String, hub_id: ID) -> Self {
-        Self { user_id, hub_id }
+    fn new(user_id: String, hub_id: HubId, expected_version: Option<u64>) -> Self {
+        Self {
+            user_id,
+            hub_id,
+            expected_version,
+        }
     }
 }
 
 #[Object]
 impl HubMutator {
     async fn name(&self, #[graphql(desc = "New name for the hub.")] new: String) -> Result<String> {
-        Ok(api::rename_hub(&self.user_id, self.hub_id, new).await?)
+        Ok(api::rename_hub(&self.user_id, self.hub_id, new, self.expected_version).await?)
     }
     async fn description(
         &self,
         #[graphql(desc = "New description for the hub.")] new: String,
     ) -> Result<String> {
-        Ok(api::change_hub_description(&self.user_id, self.hub_id, new).await?)
+        Ok(
+            api::change_hub_description(&self.user_id, self.hub_id, new, self.expected_version)
+                .await?,
+        )
+    }
+    async fn description_long(
+        &self,
+        #[graphql(desc = "New long (markdown) description for the hub, empty to remove it.")]
+        new: String,
+    ) -> Result<String> {
+        Ok(
+            api::change_hub_long_description(
+                &self.user_id,
+                self.hub_id,
+                new,
+                self.expected_version,
+            )
+            .await?,
+        )
     }
     async fn channel(
         &self,
-        #[graphql(desc = "ID of the channel to get.")] id: ID,
+        #[graphql(desc = "ID of the channel to get.")] id: ChannelId,
     ) -> ChannelMutator {
         ChannelMutator::new(self.user_id.clone(), self.hub_id, id)
     }
     async fn delete_channel(
         &self,
-        #[graphql(desc = "ID of the channel to delete.")] id: ID,
-    ) -> Result<ID> {
-        Ok(api::delete_channel(&self.user_id, self.hub_id, id)
-            .await
-            .and(Ok(id))?)
+        #[graphql(desc = "ID of the channel to delete.")] id: ChannelId,
+        #[graphql(
+            desc = "Name of the channel, required if it has more messages than the server allows deleting without confirmation."
+        )]
+        confirm: Option<String>,
+    ) -> Result<ChannelId> {
+        Ok(
+            api::delete_channel(&self.user_id, self.hub_id, id, confirm.as_deref())
+                .await
+                .and(Ok(id))?,
+        )
     }
     async fn create_channel(
         &self,
         #[graphql(desc = "Name for the new channel.")] name: String,
+        #[graphql(desc = "Short description of the new channel.")] description: Option<String>,
+        #[graphql(
+            desc = "Whether only members and groups given access by an override can read the channel."
+        )]
+        private: Option<bool>,
+        #[graphql(desc = "Channel permission overrides of permission groups.")]
+        group_permissions: Option<Vec<GroupChannelPermission>>,
+        #[graphql(desc = "Channel permission overrides of hub members.")]
+        member_permissions: Option<Vec<MemberChannelPermission>>,
     ) -> Result<Channel> {
+        let new_channel = NewChannel {
+            name,
+            description,
+            private: private.unwrap_or_default(),
+            group_permissions: group_permissions.unwrap_or_default(),
+            member_permissions: member_permissions.unwrap_or_default(),
+        };
         Ok(api::get_channel(
             &self.user_id,
             self.hub_id,
-            api::create_channel(&self.user_id, self.hub_id, name).await?,
+            api::create_channel_from(&self.user_id, self.hub_id, new_channel).await?,
+        )
+        .await?)
+    }
+    async fn group_mentionable(
+        &self,
+        #[graphql(desc = "ID of the permission group.")] id: ID,
+        #[graphql(desc = "Whether mentioning the group notifies its members.")] mentionable: bool,
+    ) -> Result<bool> {
+        Ok(api::set_group_mentionable(
+            &self.user_id,
+            self.hub_id,
+            id,
+            mentionable,
+            self.expected_version,
+        )
+        .await
+        .and(Ok(mentionable))?)
+    }
+    async fn group_display(
+        &self,
+        #[graphql(desc = "ID of the permission group.")] id: ID,
+        #[graphql(desc = "Color of the group as 0xRRGGBB, null to remove it.")] color: Option<u32>,
+        #[graphql(desc = "Whether members of the group are listed separately.")] hoist: bool,
+    ) -> Result<crate::group_display::GroupDisplay> {
+        Ok(api::set_group_display(
+            &self.user_id,
+            self.hub_id,
+            id,
+            color,
+            hoist,
+            self.expected_version,
+        )
+        .await?)
+    }
+    async fn group_position(
+        &self,
+        #[graphql(desc = "ID of the permission group.")] id: ID,
+        #[graphql(desc = "New position of the group, 0 is the highest.")] position: u32,
+    ) -> Result<Vec<crate::group_display::GroupDisplay>> {
+        Ok(api::set_group_position(
+            &self.user_id,
+            self.hub_id,
+            id,
+            position,
+            self.expected_version,
         )
         .await?)
     }
@@ -175,19 +292,34 @@ impl MutationRoot {
     async fn hub(
         &self,
         ctx: &Context<'_>,
-        #[graphql(desc = "ID of the hub to get.")] id: ID,
+        #[graphql(desc = "ID of the hub to get.")] id: HubId,
     ) -> Result<HubMutator> {
-        Ok(HubMutator::new(self.requester(ctx).await?.clone(), id))
+        Ok(HubMutator::new(
+            self.requester(ctx).await?.clone(),
+            id,
+            ctx.data_opt::<IfMatch>().and_then(|if_match| if_match.0),
+        ))
+    }
+
+    async fn request_hub_deletion(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "ID of the hub to delete.")] id: HubId,
+    ) -> Result<DeletionToken> {
+        Ok(api::request_hub_deletion(self.requester(ctx).await?, id).await?)
     }
 
     async fn delete_hub(
         &self,
         ctx: &Context<'_>,
-        #[graphql(desc = "ID of the hub to delete.")] id: ID,
-    ) -> Result<ID> {
-        Ok(api::delete_hub(self.requester(ctx).await?, id)
-            .await
-            .and(Ok(id))?)
+        #[graphql(desc = "ID of the hub to delete.")] id: HubId,
+        #[graphql(desc = "Token from `requestHubDeletion`.")] confirmation_token: String,
+    ) -> Result<HubId> {
+        Ok(
+            api::delete_hub(self.requester(ctx).await?, id, &confirmation_token)
+                .await
+                .and(Ok(id))?,
+        )
     }
 
     async fn create_hub(
@@ -201,7 +333,7 @@ impl MutationRoot {
 
 #[Object]
 impl Channel {
-    async fn id(&self) -> &ID {
+    async fn id(&self) -> &ChannelId {
         &self.id
     }
 
@@ -217,14 +349,76 @@ impl Channel {
         &self.description
     }
 
+    async fn description_long(&self, ctx: &Context<'_>) -> Result<String> {
+        Hub::load(self.hub_id)
+            .await?
+            .check_can_read_channel(ctx.data_unchecked::<String>(), self.id)?;
+        Ok(crate::descriptions::LongDescriptions::load(self.hub_id)
+            .await?
+            .channel(self.id)
+            .to_string())
+    }
+
+    async fn message(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "ID of the message to get.")] id: MessageId,
+    ) -> Result<SignedMessage> {
+        Ok(api::get_message(ctx.data_unchecked::<String>(), self.hub_id, self.id, id).await?)
+    }
+
+    async fn messages(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "Earliest time a message can have been sent.")] from: DateTime<Utc>,
+        #[graphql(desc = "Latest time a message can have been sent.")] to: DateTime<Utc>,
+        #[graphql(desc = "Whether to get messages from newest to oldest.")] invert: bool,
+        #[graphql(desc = "Maximum number of messages to get.")] limit: u8,
+    ) -> Result<Vec<SignedMessage>> {
+        Ok(api::get_messages(
+            ctx.data_unchecked::<String>(),
+            self.hub_id,
+            self.id,
+            from,
+            to,
+            invert,
+            limit as usize,
+        )
+        .await?)
+    }
+
+    async fn messages_after(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "ID of the message to start from.")] from: MessageId,
+        #[graphql(desc = "Maximum number of messages to get.")] limit: u8,
+    ) -> Result<Vec<SignedMessage>> {
+        Ok(api::get_messages_after(
+            ctx.data_unchecked::<String>(),
+            self.hub_id,
+            self.id,
+            from,
+            limit as usize,
+        )
+        .await?)
+    }
+
     async fn search_messages(
         &self,
         ctx: &Context<'_>,
         #[graphql(desc = "Query that messages should match.")] query: String,
         #[graphql(desc = "Maximum number of messages to get.")] limit: u8,
-    ) -> Vec<ID> {
+    ) -> Vec<MessageId> {
+        let user_id = ctx.data_unchecked::<String>();
+        let not_before = match Hub::load(self.hub_id).await {
+            Ok(hub) if hub.check_can_read_channel(user_id, self.id).is_ok() => hub
+                .get_member(user_id)
+                .ok()
+                .and_then(|member| hub.history_start(member, self.id)),
+            _ => return Vec::new(),
+        };
         if let Ok(ms_addr) = ctx
-            .data_unchecked::<Arc<Addr<Server>>>()
+            .data_unchecked::<Arc<InstrumentedAddr<Server>>>()
             .call(crate::server::GetMessageServer)
             .await
         {
@@ -234,6 +428,7 @@ impl Channel {
                     channel_id: self.id,
                     limit: limit as usize,
                     query,
+                    not_before,
                 })
                 .await
                 .map_or(Vec::new(), |r| r.unwrap_or_default())
@@ -245,7 +440,7 @@ impl Channel {
 
 #[Object]
 impl Hub {
-    async fn id(&self) -> &ID {
+    async fn id(&self) -> &HubId {
         &self.id
     }
 
@@ -265,10 +460,52 @@ impl Hub {
         &self.created
     }
 
+    async fn version(&self) -> u64 {
+        self.version
+    }
+
+    async fn group_display(&self) -> Result<Vec<crate::group_display::GroupDisplay>> {
+        let mut display = crate::group_display::HubGroupDisplay::load(self.id).await?;
+        display.normalize(self);
+        Ok(display.groups)
+    }
+
+    async fn content_policy(&self) -> Result<crate::content_policy::ContentPolicy> {
+        Ok(crate::content_policy::ContentPolicy::load(self.id).await?)
+    }
+
+    async fn mentionable_groups(&self) -> Result<Vec<ID>> {
+        let mut groups: Vec<ID> = crate::mentions::MentionableGroups::load(self.id)
+            .await?
+            .groups
+            .into_iter()
+            .collect();
+        groups.sort();
+        Ok(groups)
+    }
+
+    async fn icon_version(&self) -> Result<u64> {
+        Ok(crate::hub_images::HubImages::load(self.id)
+            .await?
+            .version(crate::hub_images::ImageKind::Icon))
+    }
+
+    async fn banner_version(&self) -> Result<u64> {
+        Ok(crate::hub_images::HubImages::load(self.id)
+            .await?
+            .version(crate::hub_images::ImageKind::Banner))
+    }
+
     async fn description(&self) -> &String {
         &self.description
     }
 
+    async fn description_long(&self) -> Result<String> {
+        Ok(crate::descriptions::LongDescriptions::load(self.id)
+            .await?
+            .hub)
+    }
+
     async fn is_banned(
         &self,
         #[graphql(desc = "ID of user hub to check the ban status of.")] id: String,
@@ -293,14 +530,14 @@ impl Hub {
 
     async fn channel(
         &self,
-        #[graphql(desc = "ID of the channel to get.")] id: ID,
+        #[graphql(desc = "ID of the channel to get.")] id: ChannelId,
     ) -> Option<&Channel> {
         self.channels.get(&id)
     }
 
     async fn channels(
         &self,
-        #[graphql(desc = "IDs of the channels to get.")] ids: Vec<ID>,
+        #[graphql(desc = "IDs of the channels to get.")] ids: Vec<ChannelId>,
     ) -> Vec<&Channel> {
         self.channels
             .iter()
@@ -390,7 +627,7 @@ impl Hub {
         #[graphql(
             desc = "ID of the channel to check in which to check the setting of the permission."
         )]
-        channel: ID,
+        channel: ChannelId,
         #[graphql(desc = "Permission to check for.")] permission: ChannelPermission,
     ) -> bool {
         self.members.get(&id).map_or(false, |m| {
@@ -446,7 +683,7 @@ impl PermissionGroup {
 
     async fn channel_permission(
         &self,
-        #[graphql(desc = "Channel in which to check for the permission.")] channel: ID,
+        #[graphql(desc = "Channel in which to check for the permission.")] channel: ChannelId,
         #[graphql(desc = "Permission to check for.")] permission: ChannelPermission,
     ) -> Option<ChannelPermissionSet> {
         if let Some(setting) = self.channel_permissions.get(&channel) {
@@ -527,7 +764,7 @@ impl HubMember {
     async fn channel_permission(
         &self,
         #[graphql(desc = "Permission to check for.")] permission: ChannelPermission,
-        channel: ID,
+        channel: ChannelId,
     ) -> Option<ChannelPermissionSet> {
         if let Some(setting) = self.channel_permissions.get(&channel) {
             setting.get(&permission).map(|s| ChannelPermissionSet {
@@ -0,0 +1,247 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, IoContext},
+    hub::{Hub, HubMember},
+    HubId, Result, ID,
+};
+
+#[cfg(feature = "graphql")]
+use async_graphql::SimpleObject;
+
+/// Folder where the display settings of the permission groups of each hub are stored.
+pub const GROUP_DISPLAY_FOLDER: &str = "data/hubs/group_display/";
+
+/// Largest color a group can have, colors are `0xRRGGBB`.
+pub const MAX_GROUP_COLOR: u32 = 0xFF_FF_FF;
+
+/// How clients should show a permission group.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+#[cfg_attr(feature = "graphql", derive(SimpleObject))]
+pub struct GroupDisplay {
+    /// ID of the group.
+    pub group_id: ID,
+    /// Color of the group as `0xRRGGBB`, `None` for the client's default color.
+    pub color: Option<u32>,
+    /// Whether members of the group are listed separately from other members, see [`HubGroupDisplay::hoisted_group`].
+    pub hoist: bool,
+    /// Place of the group in the hub's list of groups, starting at `0` for the highest group.
+    pub position: u32,
+}
+
+impl Default for GroupDisplay {
+    fn default() -> Self {
+        Self {
+            group_id: ID::nil(),
+            color: None,
+            hoist: false,
+            position: 0,
+        }
+    }
+}
+
+/// Display settings of the permission groups of a hub, stored separately from the hub itself.
+/// Groups without settings are shown without a color, are not hoisted and come after the groups with settings.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct HubGroupDisplay {
+    /// Settings of each group, ordered by position.
+    pub groups: Vec<GroupDisplay>,
+}
+
+impl HubGroupDisplay {
+    /// Gets the path of the file that a hub's group display settings are stored in.
+    pub fn get_path(hub_id: HubId) -> PathBuf {
+        crate::paths::group_display_file(hub_id)
+    }
+
+    /// Loads the group display settings of a hub, a hub without the file has none.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The file exists but could not be read.
+    /// * The file's contents could not be deserialized.
+    pub async fn load(hub_id: HubId) -> Result<Self> {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(bincode::deserialize(&bytes)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_path(path),
+        }
+    }
+
+    /// Saves the group display settings of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The group display folder does not exist and could not be created.
+    /// * The data could not be written to the disk.
+    pub async fn save(&self, hub_id: HubId) -> Result {
+        tokio::fs::create_dir_all(GROUP_DISPLAY_FOLDER)
+            .await
+            .with_path(GROUP_DISPLAY_FOLDER)?;
+        let path = Self::get_path(hub_id);
+        tokio::fs::write(&path, bincode::serialize(self)?)
+            .await
+            .with_path(path)
+    }
+
+    /// Removes the group display settings of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the file exists but could not be removed.
+    pub async fn remove(hub_id: HubId) -> Result {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::remove_file(&path).await {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result.with_path(path),
+        }
+    }
+
+    /// Makes the settings match the groups of `hub`: settings of groups that no longer exist are dropped, groups without settings are added after the others (oldest first) and every group is numbered by its place in the list.
+    pub fn normalize(&mut self, hub: &Hub) {
+        self.groups
+            .retain(|display| hub.groups.contains_key(&display.group_id));
+        self.groups.sort_by_key(|display| display.position);
+        let mut missing: Vec<_> = hub
+            .groups
+            .values()
+            .filter(|group| {
+                !self
+                    .groups
+                    .iter()
+                    .any(|display| display.group_id == group.id)
+            })
+            .collect();
+        missing.sort_by(|a, b| a.created.cmp(&b.created).then(a.id.cmp(&b.id)));
+        self.groups
+            .extend(missing.into_iter().map(|group| GroupDisplay {
+                group_id: group.id,
+                ..GroupDisplay::default()
+            }));
+        for (position, display) in self.groups.iter_mut().enumerate() {
+            display.position = position as u32;
+        }
+    }
+
+    /// Gets the settings of a group, the settings must have been normalized (see [`HubGroupDisplay::normalize`]) with the group in the hub.
+    ///
+    /// # Errors
+    ///
+    /// This function returns [`Error::GroupNotFound`] if the group has no settings.
+    pub fn get_mut(&mut self, group_id: ID) -> Result<&mut GroupDisplay> {
+        self.groups
+            .iter_mut()
+            .find(|display| display.group_id == group_id)
+            .ok_or(Error::GroupNotFound)
+    }
+
+    /// Moves a group to `position` (the last position if it is larger) and renumbers the other groups, the settings must have been normalized.
+    ///
+    /// # Errors
+    ///
+    /// This function returns [`Error::GroupNotFound`] if the group has no settings.
+    pub fn set_position(&mut self, group_id: ID, position: u32) -> Result {
+        let index = self
+            .groups
+            .iter()
+            .position(|display| display.group_id == group_id)
+            .ok_or(Error::GroupNotFound)?;
+        let display = self.groups.remove(index);
+        let position = (position as usize).min(self.groups.len());
+        self.groups.insert(position, display);
+        for (position, display) in self.groups.iter_mut().enumerate() {
+            display.position = position as u32;
+        }
+        Ok(())
+    }
+
+    /// Gets the highest hoisted group that a member is in, members are listed under this group when members are grouped by hoisted groups.
+    pub fn hoisted_group(&self, member: &HubMember) -> Option<&GroupDisplay> {
+        self.groups
+            .iter()
+            .filter(|display| display.hoist && member.groups.contains(&display.group_id))
+            .min_by_key(|display| display.position)
+    }
+}
+
+/// Checks that a group color fits in `0xRRGGBB`.
+///
+/// # Errors
+///
+/// This function returns [`Error::InvalidGroupColor`] if the color is larger than [`MAX_GROUP_COLOR`].
+pub fn validate_color(color: Option<u32>) -> Result {
+    if color.is_some_and(|color| color > MAX_GROUP_COLOR) {
+        Err(Error::InvalidGroupColor)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::HubGroupDisplay;
+    use crate::{
+        hub::{Hub, PermissionGroup},
+        HubId, ID,
+    };
+
+    #[test]
+    fn positions_stay_unique() {
+        let mut hub = Hub::new(
+            "hub".to_string(),
+            HubId::from_u128(1),
+            "0123456789ABCDEF0123456789ABCDEF01234567".to_string(),
+        );
+        let everyone = hub.default_group;
+        for n in 1..=3 {
+            let mut group = PermissionGroup::new(format!("group{}", n), ID::from_u128(n));
+            group.created = hub.groups[&everyone].created + chrono::Duration::seconds(n as i64);
+            hub.groups.insert(group.id, group);
+        }
+        let mut display = HubGroupDisplay::default();
+        display.normalize(&hub);
+        let order = |display: &HubGroupDisplay| {
+            display
+                .groups
+                .iter()
+                .map(|display| (display.group_id, display.position))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            order(&display),
+            vec![
+                (everyone, 0),
+                (ID::from_u128(1), 1),
+                (ID::from_u128(2), 2),
+                (ID::from_u128(3), 3)
+            ]
+        );
+        display.set_position(ID::from_u128(3), 0).unwrap();
+        display.set_position(everyone, 100).unwrap();
+        assert_eq!(
+            order(&display),
+            vec![
+                (ID::from_u128(3), 0),
+                (ID::from_u128(1), 1),
+                (ID::from_u128(2), 2),
+                (everyone, 3)
+            ]
+        );
+        hub.groups.remove(&ID::from_u128(1));
+        display.normalize(&hub);
+        assert_eq!(
+            order(&display),
+            vec![(ID::from_u128(3), 0), (ID::from_u128(2), 1), (everyone, 2)]
+        );
+    }
+}
@@ -1,206 +1,2839 @@
+#[cfg(feature = "graphql")]
 use async_graphql::{EmptySubscription, Request as GraphQLRequest, Schema};
 
+use chrono::{DateTime, Utc};
 use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use xactor::Actor;
 
 use std::convert::Infallible;
+#[cfg(feature = "graphql")]
 use std::convert::TryInto;
-use std::net::SocketAddr;
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
 
 use warp::hyper::body::Bytes;
+#[cfg(feature = "websocket")]
 use warp::ws::Ws;
+use warp::Rejection;
 use warp::Reply;
 use warp::{http::Response as HttpResponse, Filter};
 
+use flate2::{write::GzEncoder, Compression};
+
 use pgp::crypto::HashAlgorithm;
 use pgp::types::{CompressionAlgorithm, KeyTrait, SecretKeyTrait};
 use pgp::Message as OpenPGPMessage;
 use pgp::SignedPublicKey;
 
-use crate::server::Server;
-use crate::signing::KeyPair;
-use crate::signing::{PUBLIC_KEY_PATH, SECRET_KEY_PATH};
-use crate::ID;
-use crate::{channel::Message, config::Config};
-use crate::{
-    error::{Error, Result},
-    hub::Hub,
-    permission::ChannelPermission,
-};
-use crate::{
-    graphql_model::{MutationRoot, QueryRoot},
-    server::ServerNotification,
-};
+use crate::api::types::{ChannelInfo, HubInfo, MessageInfo};
+use crate::change_history::ChangedField;
+use crate::config::Config;
+#[cfg(feature = "graphql")]
+use crate::config::GraphQLConfig;
+use crate::error::{Error, Result};
+#[cfg(feature = "graphql")]
+use crate::graphql_model::{IfMatch, MutationRoot, QueryRoot};
+use crate::hub::{Hub, LeaveAllHubs, MemberSort};
+use crate::hub_images::{ImageKind, MAX_BANNER_SIZE};
+use crate::instrumentation::{Instrumentation, InstrumentedAddr};
+use crate::leaderboard::LeaderboardPeriod;
+use crate::message_purge::PurgeFilter;
+use crate::permission_hook::PermissionHook;
+use crate::security_log::SecurityLogQuery;
+use crate::server::{client_command, CloseConnections, Server};
+use crate::signing::KeyPair;
+use crate::signing::{PUBLIC_KEY_PATH, SECRET_KEY_PATH};
+use crate::subscription_profiles::SubscriptionProfile;
+use crate::websocket::CloseCode;
+use crate::{ChannelId, HubId, MessageId, ID};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ServerInfo {
+    pub version: String,
+    pub public_key_fingerprint: String,
+    pub key_server: String,
+}
+
+/// Query parameters of `/v3/send_message_init/{hub_id}/{channel_id}`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct SendMessageInitQuery {
+    /// Flags of the message, see [`crate::channel::Message::flags`].
+    #[serde(default)]
+    pub flags: u32,
+}
+
+/// Query parameters of `/v3/hub/{hub_id}`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct HubQuery {
+    /// Comma separated fields of the hub to include, for example `name,channels`, all fields if it is left out.
+    pub fields: Option<String>,
+}
+
+/// Query parameters of `/v3/hub_delta/{hub_id}`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct HubDeltaQuery {
+    /// Version of the hub the client already has.
+    pub since_version: u64,
+}
+
+/// Query parameters of `/v3/events_since/{hub_id}/{channel_id}/{seq}`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct EventsSinceQuery {
+    /// Epoch of `seq`, the `event_epoch` of the event it was taken from.
+    pub epoch: u64,
+}
+
+/// Body of `/v3/hub_description/{hub_id}` and `/v3/channel_description/{hub_id}/{channel_id}`, fields that are not given are left unchanged.
+/// The response has the same shape, with the previous values of the fields that were changed.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct DescriptionUpdate {
+    /// Short plain text description.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Long markdown description, empty to remove it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description_long: Option<String>,
+}
+
+/// Body and response of `/v3/nickname/{hub_id}`, the response has the previous nickname.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NicknameUpdate {
+    /// Nickname in the hub, empty for none.
+    pub nickname: String,
+}
+
+/// Body and response of `/v3/draft/{hub_id}/{channel_id}`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DraftUpdate {
+    /// Text of the draft, empty to remove it.
+    pub content: String,
+}
+
+/// Body of `/v3/forward_message_init/{hub_id}/{channel_id}/{message_id}`, the channel to forward the message to.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct ForwardTarget {
+    /// ID of the hub to forward the message to.
+    pub hub_id: HubId,
+    /// ID of the channel to forward the message to.
+    pub channel_id: ChannelId,
+}
+
+/// Query parameters of `/v3/member_history/{hub_id}`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MemberHistoryQuery {
+    /// Start of the time range.
+    pub from: DateTime<Utc>,
+    /// End of the time range.
+    pub to: DateTime<Utc>,
+    /// Number of events in the range to skip.
+    #[serde(default)]
+    pub offset: usize,
+    /// Maximum number of events to return.
+    #[serde(default = "default_member_history_limit")]
+    pub limit: usize,
+}
+
+/// Query parameters of `/v3/change_history/{hub_id}`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct ChangeHistoryQuery {
+    /// Only get changes of this field (`name`, `description` or `long_description`), all changes if it is left out.
+    pub field: Option<ChangedField>,
+}
+
+/// Query parameters of `/v3/hub_activity/{hub_id}`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct HubActivityQuery {
+    /// Number of days to get the activity of, including today.
+    #[serde(default = "default_hub_activity_days")]
+    pub days: u32,
+}
+
+/// Query parameters of `/v3/leaderboard/{hub_id}`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct LeaderboardQuery {
+    /// Time range to count messages in, `all` or `30d`.
+    #[serde(default)]
+    pub period: LeaderboardPeriod,
+    /// Maximum number of members to return.
+    #[serde(default = "default_leaderboard_limit")]
+    pub limit: usize,
+}
+
+/// Query parameters of a DELETE to `/v3/channels/{hub_id}/{channel_id}`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct DeleteChannelQuery {
+    /// Name of the channel, required if it has more messages than [`crate::config::LimitsConfig::confirm_channel_deletion_above`].
+    pub confirm: Option<String>,
+}
+
+/// Query parameters of `/v3/copy_channel_permissions/{hub_id}/{source_channel}` and `/v3/copy_channel_permissions/{hub_id}/{source_channel}/{target_channel}`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct CopyPermissionsQuery {
+    /// Keep the overrides of the target channels for permissions that are not set in the source channel.
+    #[serde(default)]
+    pub merge: bool,
+}
+
+/// Body of a POST to `/v3/group_display/{hub_id}/{group_id}`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct GroupDisplayUpdate {
+    /// Color of the group as `0xRRGGBB`, `null` to remove it.
+    #[serde(default)]
+    pub color: Option<u32>,
+    /// Whether members of the group are listed separately.
+    #[serde(default)]
+    pub hoist: bool,
+}
+
+/// Query parameters of `/v3/members/{hub_id}`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MemberListQuery {
+    /// Order to list the members in.
+    #[serde(default)]
+    pub sort: MemberSort,
+    /// Only list the members of this permission group.
+    pub group: Option<ID>,
+    /// Only list the members whose user ID starts with this.
+    #[serde(default)]
+    pub prefix: String,
+    /// Group the members by hoisted groups, see [`crate::api::list_hub_members`].
+    #[serde(default)]
+    pub hoisted: bool,
+    /// Number of matching members to skip.
+    #[serde(default)]
+    pub offset: usize,
+    /// Maximum number of members to return.
+    #[serde(default = "default_member_list_limit")]
+    pub limit: usize,
+}
+
+/// Query parameters of `/v3/member_map/{hub_id}`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MemberMapQuery {
+    /// Version of the hub the client's map is at, leave it out to get the full map.
+    pub since_version: Option<u64>,
+    /// Continuation token of the previous page.
+    pub after: Option<String>,
+    /// Maximum number of members to return.
+    #[serde(default = "default_member_map_limit")]
+    pub limit: usize,
+}
+
+/// Checks that a configured allowed origin is a valid origin (`scheme://host` with an optional port), see [`Config::allowed_origins`].
+///
+/// # Errors
+///
+/// This function returns [`Error::InvalidOrigin`] if it is not.
+pub fn check_origin(origin: &str) -> Result {
+    let valid = reqwest::Url::parse(origin).is_ok_and(|url| {
+        url.origin().is_tuple()
+            && url
+                .origin()
+                .ascii_serialization()
+                .eq_ignore_ascii_case(origin.trim_end_matches('/'))
+    });
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidOrigin(origin.to_string()))
+    }
+}
+
+/// Parses the value of an `If-Match` header as a hub version, the version may be quoted like an `ETag`.
+///
+/// # Errors
+///
+/// This function returns [`Error::InvalidIfMatch`] if the value is not a version.
+pub fn parse_if_match(value: Option<String>) -> Result<Option<u64>> {
+    value
+        .map(|value| {
+            value
+                .trim()
+                .trim_matches('"')
+                .parse()
+                .map_err(|_| Error::InvalidIfMatch)
+        })
+        .transpose()
+}
+
+fn default_member_list_limit() -> usize {
+    crate::hub::MAX_MEMBERS_PER_REQUEST
+}
+
+fn default_member_history_limit() -> usize {
+    crate::membership_log::MAX_MEMBERSHIP_EVENTS_PER_REQUEST
+}
+
+fn default_hub_activity_days() -> u32 {
+    30
+}
+
+fn default_leaderboard_limit() -> usize {
+    10
+}
+
+fn default_member_map_limit() -> usize {
+    crate::member_map::MAX_MEMBER_MAP_ENTRIES
+}
+
+/// `Cache-Control` header of hub images, clients can add the image's version to the URL to get a changed image sooner.
+pub const HUB_IMAGE_CACHE_CONTROL: &str = "public, max-age=3600";
+
+/// Starts the HTTP API with the given configuration, see [`ServerBuilder`] for embedding the API in another application.
+pub async fn start(config: Config) -> Result {
+    ServerBuilder::new(config).build().await?.serve().await
+}
+
+/// Builds the parts of WICRS Server so that they can be used separately, for example to mount the API in another warp application.
+///
+/// ```no_run
+/// use warp::Filter;
+/// use wicrs_server::{config::Config, httpapi::ServerBuilder};
+///
+/// # async fn run() -> wicrs_server::error::Result {
+/// let wicrs = ServerBuilder::new(Config::default()).build().await?;
+/// let app = warp::path!("hello")
+///     .map(|| "Hello from the host application!")
+///     .or(warp::path("chat").and(wicrs.routes()));
+/// warp::serve(app).run(([127, 0, 0, 1], 8080)).await;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ServerBuilder {
+    config: Config,
+    key_pair: Option<KeyPair>,
+    instrumentation: Option<Arc<Instrumentation>>,
+    permission_hook: Option<(Arc<dyn PermissionHook>, bool)>,
+}
+
+impl ServerBuilder {
+    /// Creates a new builder using the given configuration.
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            key_pair: None,
+            instrumentation: None,
+            permission_hook: None,
+        }
+    }
+
+    /// Sets the key pair the server uses to sign responses, by default it is loaded from [`SECRET_KEY_PATH`] and [`PUBLIC_KEY_PATH`] or generated if those files do not exist.
+    pub fn key_pair(mut self, key_pair: KeyPair) -> Self {
+        self.key_pair = Some(key_pair);
+        self
+    }
+
+    /// Sets the instrumentation handle the server actors report to, by default one is created from the configuration.
+    pub fn instrumentation(mut self, instrumentation: Arc<Instrumentation>) -> Self {
+        self.instrumentation = Some(instrumentation);
+        self
+    }
+
+    /// Sets the hook that every permission check is passed through, see [`PermissionHook`]. If `can_grant` is false the hook can only deny permissions the hub's settings give.
+    /// By default the hub's settings decide alone. The hook is shared by every server in the process and stays registered after the server stops.
+    pub fn permission_hook(mut self, hook: Arc<dyn PermissionHook>, can_grant: bool) -> Self {
+        self.permission_hook = Some((hook, can_grant));
+        self
+    }
+
+    /// Gets the key pair, uploads the public key to the configured key server and starts the server actors.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * A new key pair had to be generated and it could not be generated or saved.
+    /// * The [`Server`] actor could not be started.
+    pub async fn build(self) -> Result<WicrsServer> {
+        crate::validation::set_name_rules(self.config.names.clone());
+        crate::validation::set_description_rules(self.config.descriptions.clone());
+        crate::quotas::set_limits(self.config.limits.clone());
+        crate::unfurl::set_config(self.config.unfurl.clone());
+        crate::encryption::init(self.config.encryption.as_ref())?;
+        if let Some((hook, can_grant)) = self.permission_hook {
+            crate::permission_hook::set(hook, can_grant);
+        }
+        for origin in self.config.allowed_origins.iter().flatten() {
+            check_origin(origin)?;
+        }
+        let key_pair = if let Some(key_pair) = self.key_pair {
+            key_pair
+        } else {
+            load_or_generate_key_pair(&self.config).await?
+        };
+        if let Err(err) = upload_public_key(&key_pair, &self.config.key_server).await {
+            warn!("Unable to upload public key to key server: {}", err.chain());
+        }
+        let instrumentation = match self.instrumentation {
+            Some(instrumentation) => instrumentation,
+            None => Arc::new(Instrumentation::new(&self.config.instrumentation)),
+        };
+        let server = Arc::new(InstrumentedAddr::new(
+            Server::new(key_pair.secret_key.clone(), instrumentation.clone())
+                .await?
+                .start()
+                .await
+                .map_err(|_| Error::ServerStartFailed)?,
+            instrumentation.server.clone(),
+        ));
+        #[cfg(feature = "search")]
+        {
+            crate::server::set_auto_reindex_on_corruption(
+                self.config.search.auto_reindex_on_corruption,
+            );
+            tokio::spawn(crate::server::warm_up_indexes(self.config.search.clone()));
+        }
+        Ok(WicrsServer {
+            config: self.config,
+            key_pair: Arc::new(key_pair),
+            server,
+            instrumentation,
+        })
+    }
+}
+
+/// A started WICRS Server, created using a [`ServerBuilder`].
+pub struct WicrsServer {
+    /// Configuration the server was built with.
+    pub config: Config,
+    /// Key pair used to sign responses.
+    pub key_pair: Arc<KeyPair>,
+    /// Address of the [`Server`] actor.
+    pub server: Arc<InstrumentedAddr<Server>>,
+    /// Statistics of the server actors.
+    pub instrumentation: Arc<Instrumentation>,
+}
+
+impl WicrsServer {
+    /// Creates the filter for all of the API routes (`/v3/...`), it can be mounted under a path of another warp application.
+    // The GraphQL routes are only added when the feature is enabled, so the routes can not be returned directly.
+    #[allow(clippy::let_and_return)]
+    pub fn routes(&self) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        let key_pair = self.key_pair.clone();
+        let server = self.server.clone();
+        let instrumentation = self.instrumentation.clone();
+        let server_fingerprint = hex::encode_upper(key_pair.secret_key.fingerprint());
+        #[cfg(feature = "websocket")]
+        let key_pair_ws = key_pair.clone();
+        let send_message_server_arc = server.clone();
+        let send_message_instrumentation = instrumentation.clone();
+        let webhook_send_server_arc = server.clone();
+        let webhook_send_instrumentation = instrumentation.clone();
+        let key_pair_webhook_send = key_pair.clone();
+        #[cfg(feature = "websocket")]
+        let ws_instrumentation = instrumentation.clone();
+        #[cfg(feature = "websocket")]
+        let show_version = self.config.show_version;
+        #[cfg(feature = "websocket")]
+        let allowed_origins = self.config.allowed_origins.clone();
+        #[cfg(feature = "graphql")]
+        let graphql_server_arc = server.clone();
+        let key_pair_send = key_pair.clone();
+        let key_pair_send_init = key_pair.clone();
+        let key_server_url = self.config.key_server.clone();
+        let public_key_filter =
+            warp::any()
+                .and(warp::header("pgp-fingerprint"))
+                .and_then(move |header: String| {
+                    let key_server_url = key_server_url.clone();
+                    async move {
+                        crate::signing::get_or_import_public_key(
+                            &hex::decode(header).map_err(|_| Error::InvalidFingerprint)?,
+                            &key_server_url,
+                        )
+                        .await
+                        .and_then(|key| {
+                            key.verify()?;
+                            Ok(key)
+                        })
+                        .map_err(warp::reject::custom)
+                    }
+                });
+
+        let signed_body_pub_key = public_key_filter.clone();
+
+        let signed_body = signed_body_pub_key.and(warp::body::bytes()).and_then(
+            |requester_public_key: SignedPublicKey, body: Bytes| async move {
+                let text = String::from_utf8(body.to_vec())
+                    .map_err(|e| warp::reject::custom(Error::from(e)))?;
+                crate::signing::verify_message_extract(&requester_public_key, &text).map_err(
+                    |err| {
+                        crate::audit!(
+                            user = %hex::encode_upper(requester_public_key.fingerprint()),
+                            kind = "auth_failure",
+                            "Request signature could not be verified."
+                        );
+                        warp::reject::custom(err)
+                    },
+                )
+            },
+        );
+
+        let hub_icon = hub_image_routes(
+            "hub_icon",
+            ImageKind::Icon,
+            key_pair.clone(),
+            public_key_filter.clone(),
+        );
+        let hub_banner = hub_image_routes(
+            "hub_banner",
+            ImageKind::Banner,
+            key_pair.clone(),
+            public_key_filter.clone(),
+        );
+
+        let key_pair_preview = key_pair.clone();
+        let signed_body_preview_set = signed_body.clone();
+        let key_pair_preview_set = key_pair.clone();
+        let signed_body_history = signed_body.clone();
+        let key_pair_history = key_pair.clone();
+        let signed_body_change_history = signed_body.clone();
+        let key_pair_change_history = key_pair.clone();
+        let signed_body_activity = signed_body.clone();
+        let key_pair_activity = key_pair.clone();
+        let signed_body_hub_storage = signed_body.clone();
+        let key_pair_hub_storage = key_pair.clone();
+        let signed_body_compact_channel = signed_body.clone();
+        let key_pair_compact_channel = key_pair.clone();
+        let signed_body_leaderboard = signed_body.clone();
+        let key_pair_leaderboard = key_pair.clone();
+        let signed_body_leaderboard_set = signed_body.clone();
+        let key_pair_leaderboard_set = key_pair.clone();
+        let signed_body_quota = signed_body.clone();
+        let key_pair_quota = key_pair.clone();
+        let signed_body_quota_set = signed_body.clone();
+        let signed_body_stats = signed_body.clone();
+        let signed_body_link_identity = signed_body.clone();
+        let key_pair_link_identity = key_pair.clone();
+        let signed_body_security_log = signed_body.clone();
+        let key_pair_security_log = key_pair.clone();
+        let key_pair_quota_set = key_pair.clone();
+        let signed_body_members = signed_body.clone();
+        let key_pair_members = key_pair.clone();
+        let signed_body_member_map = signed_body.clone();
+        let key_pair_member_map = key_pair.clone();
+        let signed_body_export = signed_body.clone();
+        let key_pair_export = key_pair.clone();
+        let signed_body_export_status = signed_body.clone();
+        let key_pair_export_status = key_pair.clone();
+        let signed_body_export_download = signed_body.clone();
+        let key_pair_export_download = key_pair.clone();
+        let signed_body_hub_description = signed_body.clone();
+        let key_pair_hub_description = key_pair.clone();
+        let signed_body_channel_description = signed_body.clone();
+        let key_pair_channel_description = key_pair.clone();
+        let signed_body_nicknames = signed_body.clone();
+        let key_pair_nicknames = key_pair.clone();
+        let signed_body_nickname = signed_body.clone();
+        let key_pair_nickname = key_pair.clone();
+        let signed_body_nickname_policy = signed_body.clone();
+        let key_pair_nickname_policy = key_pair.clone();
+        let signed_body_content_policy = signed_body.clone();
+        let key_pair_content_policy = key_pair.clone();
+        let signed_body_set_content_policy = signed_body.clone();
+        let key_pair_set_content_policy = key_pair.clone();
+        let signed_body_copy_permissions = signed_body.clone();
+        let key_pair_copy_permissions = key_pair.clone();
+        let signed_body_copy_permissions_many = signed_body.clone();
+        let key_pair_copy_permissions_many = key_pair.clone();
+        let signed_body_group_display = signed_body.clone();
+        let key_pair_group_display = key_pair.clone();
+        let signed_body_set_group_display = signed_body.clone();
+        let key_pair_set_group_display = key_pair.clone();
+        let signed_body_group_position = signed_body.clone();
+        let key_pair_group_position = key_pair.clone();
+        let signed_body_group_preset = signed_body.clone();
+        let key_pair_group_preset = key_pair.clone();
+        let signed_body_effective_permissions = signed_body.clone();
+        let key_pair_effective_permissions = key_pair.clone();
+        let key_pair_resolve = key_pair.clone();
+        let signed_body_invites = signed_body.clone();
+        let key_pair_invites = key_pair.clone();
+        let signed_body_invite_stats = signed_body.clone();
+        let key_pair_invite_stats = key_pair.clone();
+        let signed_body_kick_invite_members = signed_body.clone();
+        let key_pair_kick_invite_members = key_pair.clone();
+        let signed_body_create_hub = signed_body.clone();
+        let key_pair_create_hub = key_pair.clone();
+        let signed_body_create_channel = signed_body.clone();
+        let key_pair_create_channel = key_pair.clone();
+        let signed_body_delete_channel = signed_body.clone();
+        let key_pair_delete_channel = key_pair.clone();
+        let signed_body_webhooks = signed_body.clone();
+        let key_pair_webhooks = key_pair.clone();
+        let signed_body_create_webhook = signed_body.clone();
+        let key_pair_create_webhook = key_pair.clone();
+        let signed_body_delete_webhook = signed_body.clone();
+        let key_pair_delete_webhook = key_pair.clone();
+        let signed_body_create_invite = signed_body.clone();
+        let key_pair_create_invite = key_pair.clone();
+        let signed_body_revoke_invite = signed_body.clone();
+        let key_pair_revoke_invite = key_pair.clone();
+        let signed_body_join = signed_body.clone();
+        let key_pair_join = key_pair.clone();
+        let signed_body_purge = signed_body.clone();
+        let key_pair_purge = key_pair.clone();
+        let signed_body_purge_status = signed_body.clone();
+        let key_pair_purge_status = key_pair.clone();
+        let signed_body_move_messages = signed_body.clone();
+        let key_pair_move_messages = key_pair.clone();
+        let signed_body_request_delete_hub = signed_body.clone();
+        let key_pair_request_delete_hub = key_pair.clone();
+        let signed_body_delete_hub = signed_body.clone();
+        let key_pair_delete_hub = key_pair.clone();
+        let signed_body_owned_hubs = signed_body.clone();
+        let key_pair_owned_hubs = key_pair.clone();
+        let signed_body_leave_all = signed_body.clone();
+        let key_pair_leave_all = key_pair.clone();
+        let signed_body_bulk_transfer = signed_body.clone();
+        let key_pair_bulk_transfer = key_pair.clone();
+        let signed_body_drafts = signed_body.clone();
+        let key_pair_drafts = key_pair.clone();
+        let signed_body_set_draft = signed_body.clone();
+        let key_pair_set_draft = key_pair.clone();
+        let signed_body_subscription_profile = signed_body.clone();
+        let key_pair_subscription_profile = key_pair.clone();
+        let signed_body_set_subscription_profile = signed_body.clone();
+        let key_pair_set_subscription_profile = key_pair.clone();
+
+        let signed_body_smi = signed_body.clone();
+        let signed_body_hub = signed_body.clone();
+        let key_pair_hub = key_pair.clone();
+        let signed_body_delta = signed_body.clone();
+        let key_pair_delta = key_pair.clone();
+        let signed_body_events = signed_body.clone();
+        let key_pair_events = key_pair.clone();
+        let events_server = server.clone();
+
+        let send_message_init = warp::any()
+            .and(warp::path!("v3" / "send_message_init" / String / String))
+            .and(warp::query::<SendMessageInitQuery>())
+            .and(signed_body_smi)
+            .and_then(
+                move |hub_id: String,
+                      channel_id: String,
+                      query: SendMessageInitQuery,
+                      (content, sender): (String, String)| {
+                    let key_pair = key_pair_send_init.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let hub_id = HubId::parse_str(&hub_id)?;
+                                let channel_id = ChannelId::parse_str(&channel_id)?;
+                                let msg = crate::message_pipeline::prepare(
+                                    sender,
+                                    hub_id,
+                                    channel_id,
+                                    content,
+                                    query.flags,
+                                )
+                                .await?;
+                                create_response(
+                                    &serde_json::to_string(&MessageInfo::from(&msg))?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let signed_body_emi = signed_body.clone();
+        let key_pair_edit_init = key_pair.clone();
+        let edit_message_init = warp::any()
+            .and(warp::path!(
+                "v3" / "edit_message_init" / String / String / String
+            ))
+            .and(signed_body_emi)
+            .and_then(
+                move |hub_id: String,
+                      channel_id: String,
+                      message_id: String,
+                      (content, sender): (String, String)| {
+                    let key_pair = key_pair_edit_init.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let hub_id = HubId::parse_str(&hub_id)?;
+                                let channel_id = ChannelId::parse_str(&channel_id)?;
+                                let message_id = MessageId::parse_str(&message_id)?;
+                                let msg = crate::message_pipeline::prepare_edit(
+                                    sender, hub_id, channel_id, message_id, content,
+                                )
+                                .await?;
+                                create_response(
+                                    &serde_json::to_string(&MessageInfo::from(&msg))?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let signed_body_fmi = signed_body.clone();
+        let key_pair_forward_init = key_pair.clone();
+        let forward_message_init =
+            warp::path!("v3" / "forward_message_init" / String / String / String)
+                .and(warp::post())
+                .and(signed_body_fmi)
+                .and_then(
+                    move |hub_id: String,
+                          channel_id: String,
+                          message_id: String,
+                          (target, sender): (String, String)| {
+                        let key_pair = key_pair_forward_init.clone();
+                        async move {
+                            Ok::<_, Infallible>(
+                                async {
+                                    let hub_id = HubId::parse_str(&hub_id)?;
+                                    let channel_id = ChannelId::parse_str(&channel_id)?;
+                                    let message_id = MessageId::parse_str(&message_id)?;
+                                    let target: ForwardTarget = serde_json::from_str(&target)?;
+                                    let msg = crate::message_pipeline::prepare_forward(
+                                        sender,
+                                        hub_id,
+                                        channel_id,
+                                        message_id,
+                                        target.hub_id,
+                                        target.channel_id,
+                                    )
+                                    .await?;
+                                    create_response(
+                                        &serde_json::to_string(&MessageInfo::from(&msg))?,
+                                        &key_pair.secret_key,
+                                    )
+                                }
+                                .await
+                                .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                            )
+                        }
+                    },
+                );
+
+        let key_pair_edit = key_pair.clone();
+        let edit_message_server = server.clone();
+        let edit_message = warp::any()
+            .and(warp::path!("v3" / "edit_message"))
+            .and(public_key_filter.clone())
+            .and(warp::body::bytes())
+            .and_then(move |client_public_key: SignedPublicKey, body: Bytes| {
+                let key_pair = key_pair_edit.clone();
+                let server = edit_message_server.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let body = String::from_utf8(body.to_vec())?;
+                            let message = crate::message_pipeline::edit(
+                                body,
+                                &client_public_key,
+                                &key_pair.public_key,
+                                &server,
+                            )
+                            .await?;
+                            create_response(
+                                &serde_json::to_string(&MessageInfo::from(&message))?,
+                                &key_pair.secret_key,
+                            )
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
+
+        let signed_body_message_history = signed_body.clone();
+        let key_pair_message_history = key_pair.clone();
+        let message_history = warp::path!("v3" / "message_history" / String / String / String)
+            .and(warp::get())
+            .and(signed_body_message_history)
+            .and_then(
+                move |hub_id: String,
+                      channel_id: String,
+                      message_id: String,
+                      (_, sender): (String, String)| {
+                    let key_pair = key_pair_message_history.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let hub_id = HubId::parse_str(&hub_id)?;
+                                let channel_id = ChannelId::parse_str(&channel_id)?;
+                                let message_id = MessageId::parse_str(&message_id)?;
+                                let history = crate::api::get_message_history(
+                                    &sender, hub_id, channel_id, message_id,
+                                )
+                                .await?;
+                                create_response(
+                                    &serde_json::to_string(&history)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let signed_body_link_previews = signed_body.clone();
+        let key_pair_link_previews = key_pair.clone();
+        let link_previews = warp::path!("v3" / "link_previews" / String / String / String)
+            .and(warp::get())
+            .and(signed_body_link_previews)
+            .and_then(
+                move |hub_id: String,
+                      channel_id: String,
+                      message_id: String,
+                      (_, sender): (String, String)| {
+                    let key_pair = key_pair_link_previews.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let hub_id = HubId::parse_str(&hub_id)?;
+                                let channel_id = ChannelId::parse_str(&channel_id)?;
+                                let message_id = MessageId::parse_str(&message_id)?;
+                                let previews = crate::api::get_link_previews(
+                                    &sender, hub_id, channel_id, message_id,
+                                )
+                                .await?;
+                                create_response(
+                                    &serde_json::to_string(&previews)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let signed_body_message_rendered = signed_body.clone();
+        let key_pair_message_rendered = key_pair.clone();
+        let message_rendered = warp::path!("v3" / "message_rendered" / String / String / String)
+            .and(warp::get())
+            .and(signed_body_message_rendered)
+            .and_then(
+                move |hub_id: String,
+                      channel_id: String,
+                      message_id: String,
+                      (_, sender): (String, String)| {
+                    let key_pair = key_pair_message_rendered.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let hub_id = HubId::parse_str(&hub_id)?;
+                                let channel_id = ChannelId::parse_str(&channel_id)?;
+                                let message_id = MessageId::parse_str(&message_id)?;
+                                let rendered = crate::api::get_message_rendered(
+                                    &sender, hub_id, channel_id, message_id,
+                                )
+                                .await?;
+                                create_response(
+                                    &serde_json::to_string(&rendered)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let send_message_pub_key = public_key_filter.clone();
+
+        let send_message = warp::any()
+            .and(warp::path!("v3" / "send_message"))
+            .and(send_message_pub_key)
+            .and(warp::body::bytes())
+            .and_then(move |client_public_key: SignedPublicKey, body: Bytes| {
+                let key_pair = key_pair_send.clone();
+                let server = send_message_server_arc.clone();
+                let instrumentation = send_message_instrumentation.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let body = String::from_utf8(body.to_vec())?;
+                            let message = crate::message_pipeline::send(
+                                body,
+                                &client_public_key,
+                                &key_pair.public_key,
+                                &server,
+                                &instrumentation,
+                            )
+                            .await?;
+                            create_response(
+                                &serde_json::to_string(&MessageInfo::from(&message))?,
+                                &key_pair.secret_key,
+                            )
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
+
+        // Services posting with a webhook have no key pair, the webhook's token takes the place of the signature.
+        let webhook_send = warp::path!("v3" / "webhook_send" / String / String / String)
+            .and(warp::post())
+            .and(warp::body::bytes())
+            .and_then(
+                move |hub_id: String, webhook_id: String, token: String, body: Bytes| {
+                    let key_pair = key_pair_webhook_send.clone();
+                    let server = webhook_send_server_arc.clone();
+                    let instrumentation = webhook_send_instrumentation.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let message = crate::message_pipeline::send_webhook(
+                                    HubId::parse_str(&hub_id)?,
+                                    ID::parse_str(&webhook_id)?,
+                                    &token,
+                                    serde_json::from_slice(&body)?,
+                                    &key_pair,
+                                    &server,
+                                    &instrumentation,
+                                )
+                                .await?;
+                                create_response(
+                                    &serde_json::to_string(&MessageInfo::from(&message))?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        #[cfg(feature = "websocket")]
+        let web_socket = warp::path!("v3" / "websocket")
+            .and(public_key_filter)
+            .and(crate::websocket::handshake())
+            .map(
+                move |public_key: SignedPublicKey,
+                      ws: Ws,
+                      origin: Option<String>,
+                      protocols: Option<String>| {
+                    let key_pair = key_pair_ws.clone();
+                    let server = server.clone();
+                    let instrumentation = ws_instrumentation.clone();
+                    crate::websocket::accept(
+                        ws,
+                        origin,
+                        protocols,
+                        allowed_origins.as_deref(),
+                        move |websocket| async move {
+                            let _ = crate::websocket::handle_connection(
+                                websocket,
+                                public_key,
+                                key_pair,
+                                server,
+                                instrumentation,
+                                crate::websocket::ConnectionLimits::configured(show_version),
+                            )
+                            .await;
+                        },
+                    )
+                },
+            );
+
+        let server_info_struct = ServerInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            public_key_fingerprint: server_fingerprint,
+            key_server: self.config.key_server.clone(),
+        };
+        let server_info_str = serde_json::to_string(&server_info_struct).unwrap();
+
+        let server_info_secret = key_pair.secret_key.clone();
+
+        let server_info = warp::path!("v3" / "info").map(move || {
+            create_response(server_info_str.clone().as_str(), &server_info_secret)
+                .map_or_else(|e| e.into_response(), |r| r.into_response())
+        });
+
+        let instance_info_str = serde_json::to_string(&crate::instance_info::InstanceInfo::new(
+            &self.config,
+            server_info_struct.public_key_fingerprint.clone(),
+        ))
+        .unwrap();
+
+        let instance_info = warp::path!("v3" / "instance")
+            .and(warp::get())
+            .map(move || {
+                warp::reply::with_header(
+                    warp::reply::with_header(
+                        instance_info_str.clone(),
+                        "Content-Type",
+                        "application/json",
+                    ),
+                    "Cache-Control",
+                    "public, max-age=300",
+                )
+            });
+
+        let stats_secret = key_pair.secret_key.clone();
+        let stats = warp::path!("v3" / "stats").and(signed_body_stats).map(
+            move |(_, sender): (String, String)| {
+                if crate::quotas::is_admin(&sender) {
+                    serde_json::to_string(&instrumentation.snapshot())
+                        .map_err(Error::from)
+                        .and_then(|stats| create_response(&stats, &stats_secret))
+                } else {
+                    Err(Error::NotAdmin)
+                }
+                .map_or_else(|e| e.into_response(), |r| r.into_response())
+            },
+        );
+
+        let hub_fields = warp::path!("v3" / "hub" / String)
+            .and(warp::get())
+            .and(warp::query::<HubQuery>())
+            .and(warp::header::optional::<String>("if-none-match"))
+            .and(signed_body_hub)
+            .and_then(
+                move |hub_id: String,
+                      query: HubQuery,
+                      if_none_match: Option<String>,
+                      (_, sender): (String, String)| {
+                    let key_pair = key_pair_hub.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let hub_id = HubId::parse_str(&hub_id)?;
+                                let hub = crate::api::get_hub_fields(
+                                    &sender,
+                                    hub_id,
+                                    query.fields.as_deref(),
+                                )
+                                .await?;
+                                let etag = format!("\"{}\"", hub.version);
+                                let mut response = if if_none_match.as_deref() == Some(&etag) {
+                                    HttpResponse::builder()
+                                        .status(StatusCode::NOT_MODIFIED)
+                                        .body(String::new())?
+                                } else {
+                                    create_response(
+                                        &serde_json::to_string(&hub)?,
+                                        &key_pair.secret_key,
+                                    )?
+                                };
+                                if let Ok(value) = etag.parse() {
+                                    response.headers_mut().insert("etag", value);
+                                }
+                                Ok::<_, Error>(response)
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let hub_delta = warp::path!("v3" / "hub_delta" / String)
+            .and(warp::query::<HubDeltaQuery>())
+            .and(signed_body_delta)
+            .and_then(
+                move |hub_id: String, query: HubDeltaQuery, (_, sender): (String, String)| {
+                    let key_pair = key_pair_delta.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let hub_id = HubId::parse_str(&hub_id)?;
+                                let delta =
+                                    crate::api::get_hub_delta(&sender, hub_id, query.since_version)
+                                        .await?;
+                                create_response(
+                                    &serde_json::to_string(&delta)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let events_since = warp::path!("v3" / "events_since" / String / String / u64)
+            .and(warp::get())
+            .and(warp::query::<EventsSinceQuery>())
+            .and(signed_body_events)
+            .and_then(
+                move |hub_id: String,
+                      channel_id: String,
+                      seq: u64,
+                      query: EventsSinceQuery,
+                      (_, sender): (String, String)| {
+                    let key_pair = key_pair_events.clone();
+                    let server = events_server.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let hub_id = HubId::parse_str(&hub_id)?;
+                                let channel_id = ChannelId::parse_str(&channel_id)?;
+                                let events = server
+                                    .call(client_command::EventsSince {
+                                        user_id: sender,
+                                        hub_id,
+                                        channel_id,
+                                        epoch: query.epoch,
+                                        seq,
+                                    })
+                                    .await
+                                    .map_err(|_| Error::InternalMessageFailed)??;
+                                create_response(
+                                    &serde_json::to_string(&events)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let hub_preview = warp::path!("v3" / "hub_preview" / String)
+            .and(warp::get())
+            .and(warp::addr::remote())
+            .and_then(move |hub_id: String, address: Option<SocketAddr>| {
+                let key_pair = key_pair_preview.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            // Requests whose address is unknown share one limit.
+                            let address = address
+                                .map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |address| address.ip());
+                            if !crate::hub_preview::allow_request(address) {
+                                return Err(Error::RateLimited);
+                            }
+                            let hub_id = HubId::parse_str(&hub_id)?;
+                            let preview = crate::api::get_hub_preview(hub_id).await?;
+                            create_response(&serde_json::to_string(&preview)?, &key_pair.secret_key)
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
+
+        let set_hub_preview = warp::path!("v3" / "hub_preview" / String)
+            .and(warp::put())
+            .and(signed_body_preview_set)
+            .and_then(move |hub_id: String, (enabled, sender): (String, String)| {
+                let key_pair = key_pair_preview_set.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let hub_id = HubId::parse_str(&hub_id)?;
+                            let enabled: bool = serde_json::from_str(&enabled)?;
+                            crate::api::set_hub_preview_enabled(&sender, hub_id, enabled).await?;
+                            create_response(&serde_json::to_string(&enabled)?, &key_pair.secret_key)
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
+
+        let resolve_code = warp::path!("v3" / "resolve" / String)
+            .and(warp::get())
+            .and(warp::addr::remote())
+            .and_then(move |code: String, address: Option<SocketAddr>| {
+                let key_pair = key_pair_resolve.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            // Requests whose address is unknown share one limit.
+                            let address = address
+                                .map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |address| address.ip());
+                            if !crate::invites::allow_request(address) {
+                                return Err(Error::RateLimited);
+                            }
+                            let resolved = crate::api::resolve_code(&code).await?;
+                            create_response(
+                                &serde_json::to_string(&resolved)?,
+                                &key_pair.secret_key,
+                            )
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
+
+        let invites = warp::path!("v3" / "invites" / String)
+            .and(warp::get())
+            .and(signed_body_invites)
+            .and_then(move |hub_id: String, (_, sender): (String, String)| {
+                let key_pair = key_pair_invites.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let hub_id = HubId::parse_str(&hub_id)?;
+                            let invites = crate::api::get_invites(&sender, hub_id).await?;
+                            create_response(&serde_json::to_string(&invites)?, &key_pair.secret_key)
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
+
+        let invite_stats = warp::path!("v3" / "invite_stats" / String)
+            .and(warp::get())
+            .and(signed_body_invite_stats)
+            .and_then(move |hub_id: String, (_, sender): (String, String)| {
+                let key_pair = key_pair_invite_stats.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let hub_id = HubId::parse_str(&hub_id)?;
+                            let stats = crate::api::get_invite_stats(&sender, hub_id).await?;
+                            create_response(&serde_json::to_string(&stats)?, &key_pair.secret_key)
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
+
+        let kick_invite_members = warp::path!("v3" / "kick_invite_members" / String / String)
+            .and(warp::post())
+            .and(signed_body_kick_invite_members)
+            .and_then(
+                move |hub_id: String, code: String, (_, sender): (String, String)| {
+                    let key_pair = key_pair_kick_invite_members.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let hub_id = HubId::parse_str(&hub_id)?;
+                                let result =
+                                    crate::api::kick_invite_members(&sender, hub_id, &code).await?;
+                                create_response(
+                                    &serde_json::to_string(&result)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let create_hub = warp::path!("v3" / "hubs")
+            .and(warp::post())
+            .and(signed_body_create_hub)
+            .and_then(move |(new_hub, sender): (String, String)| {
+                let key_pair = key_pair_create_hub.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let hub_id = crate::api::create_hub_from(
+                                sender,
+                                serde_json::from_str(&new_hub)?,
+                            )
+                            .await?;
+                            let hub = HubInfo::from(&Hub::load(hub_id).await?);
+                            create_response(&serde_json::to_string(&hub)?, &key_pair.secret_key)
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
+
+        let create_channel = warp::path!("v3" / "channels" / String)
+            .and(warp::post())
+            .and(signed_body_create_channel)
+            .and_then(
+                move |hub_id: String, (new_channel, sender): (String, String)| {
+                    let key_pair = key_pair_create_channel.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let hub_id = HubId::parse_str(&hub_id)?;
+                                let channel_id = crate::api::create_channel_from(
+                                    &sender,
+                                    hub_id,
+                                    serde_json::from_str(&new_channel)?,
+                                )
+                                .await?;
+                                let channel = ChannelInfo::from(
+                                    &crate::api::get_channel(&sender, hub_id, channel_id).await?,
+                                );
+                                create_response(
+                                    &serde_json::to_string(&channel)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let delete_channel = warp::path!("v3" / "channels" / String / String)
+            .and(warp::delete())
+            .and(warp::query::<DeleteChannelQuery>())
+            .and(signed_body_delete_channel)
+            .and_then(
+                move |hub_id: String,
+                      channel_id: String,
+                      query: DeleteChannelQuery,
+                      (_, sender): (String, String)| {
+                    let key_pair = key_pair_delete_channel.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let channel_id = ChannelId::parse_str(&channel_id)?;
+                                crate::api::delete_channel(
+                                    &sender,
+                                    HubId::parse_str(&hub_id)?,
+                                    channel_id,
+                                    query.confirm.as_deref(),
+                                )
+                                .await?;
+                                create_response(
+                                    &serde_json::to_string(&channel_id)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let webhooks = warp::path!("v3" / "webhooks" / String / String)
+            .and(warp::get())
+            .and(signed_body_webhooks)
+            .and_then(
+                move |hub_id: String, channel_id: String, (_, sender): (String, String)| {
+                    let key_pair = key_pair_webhooks.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let webhooks = crate::api::get_webhooks(
+                                    &sender,
+                                    HubId::parse_str(&hub_id)?,
+                                    ChannelId::parse_str(&channel_id)?,
+                                )
+                                .await?;
+                                create_response(
+                                    &serde_json::to_string(&webhooks)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let create_webhook = warp::path!("v3" / "webhooks" / String / String)
+            .and(warp::post())
+            .and(signed_body_create_webhook)
+            .and_then(
+                move |hub_id: String,
+                      channel_id: String,
+                      (new_webhook, sender): (String, String)| {
+                    let key_pair = key_pair_create_webhook.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let webhook = crate::api::create_webhook(
+                                    &sender,
+                                    HubId::parse_str(&hub_id)?,
+                                    ChannelId::parse_str(&channel_id)?,
+                                    serde_json::from_str(&new_webhook)?,
+                                )
+                                .await?;
+                                create_response(
+                                    &serde_json::to_string(&webhook)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let delete_webhook = warp::path!("v3" / "webhooks" / String / String)
+            .and(warp::delete())
+            .and(signed_body_delete_webhook)
+            .and_then(
+                move |hub_id: String, webhook_id: String, (_, sender): (String, String)| {
+                    let key_pair = key_pair_delete_webhook.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let webhook_id = ID::parse_str(&webhook_id)?;
+                                crate::api::delete_webhook(
+                                    &sender,
+                                    HubId::parse_str(&hub_id)?,
+                                    webhook_id,
+                                )
+                                .await?;
+                                create_response(
+                                    &serde_json::to_string(&webhook_id)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let create_invite = warp::path!("v3" / "invites" / String)
+            .and(warp::post())
+            .and(signed_body_create_invite)
+            .and_then(move |hub_id: String, (options, sender): (String, String)| {
+                let key_pair = key_pair_create_invite.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let hub_id = HubId::parse_str(&hub_id)?;
+                            let invite = crate::api::create_invite(
+                                &sender,
+                                hub_id,
+                                serde_json::from_str(&options)?,
+                            )
+                            .await?;
+                            create_response(&serde_json::to_string(&invite)?, &key_pair.secret_key)
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
+
+        let revoke_invite = warp::path!("v3" / "revoke_invite" / String / String)
+            .and(warp::post())
+            .and(signed_body_revoke_invite)
+            .and_then(
+                move |hub_id: String, code: String, (_, sender): (String, String)| {
+                    let key_pair = key_pair_revoke_invite.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let hub_id = HubId::parse_str(&hub_id)?;
+                                crate::api::revoke_invite(&sender, hub_id, &code).await?;
+                                create_response(
+                                    &serde_json::to_string(&code)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let join = warp::path!("v3" / "join" / String)
+            .and(warp::post())
+            .and(signed_body_join)
+            .and_then(move |code: String, (_, sender): (String, String)| {
+                let key_pair = key_pair_join.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let hub_id = crate::api::join_with_invite(sender, &code).await?;
+                            create_response(&serde_json::to_string(&hub_id)?, &key_pair.secret_key)
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
+
+        let export_account = warp::path!("v3" / "export_account")
+            .and(warp::post())
+            .and(signed_body_export)
+            .and_then(move |(_, sender): (String, String)| {
+                let key_pair = key_pair_export.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let status = crate::api::start_account_export(&sender).await?;
+                            create_response(&serde_json::to_string(&status)?, &key_pair.secret_key)
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
+
+        let export_account_status = warp::path!("v3" / "export_account" / "status")
+            .and(warp::get())
+            .and(signed_body_export_status)
+            .and_then(move |(_, sender): (String, String)| {
+                let key_pair = key_pair_export_status.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let status = crate::api::get_account_export_status(&sender).await?;
+                            create_response(&serde_json::to_string(&status)?, &key_pair.secret_key)
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
+
+        let export_account_download = warp::path!("v3" / "export_account" / "download")
+            .and(warp::get())
+            .and(signed_body_export_download)
+            .and_then(move |(_, sender): (String, String)| {
+                let key_pair = key_pair_export_download.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let export = crate::api::get_account_export(&sender).await?;
+                            create_response(&export, &key_pair.secret_key)
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
+
+        let hub_description = warp::path!("v3" / "hub_description" / String)
+            .and(warp::put())
+            .and(warp::header::optional::<String>("if-match"))
+            .and(signed_body_hub_description)
+            .and_then(
+                move |hub_id: String,
+                      if_match: Option<String>,
+                      (update, sender): (String, String)| {
+                    let key_pair = key_pair_hub_description.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let hub_id = HubId::parse_str(&hub_id)?;
+                                let mut expected_version = parse_if_match(if_match)?;
+                                let update: DescriptionUpdate = serde_json::from_str(&update)?;
+                                let mut previous = DescriptionUpdate::default();
+                                if let Some(description) = update.description {
+                                    previous.description = Some(
+                                        crate::api::change_hub_description(
+                                            &sender,
+                                            hub_id,
+                                            description,
+                                            expected_version,
+                                        )
+                                        .await?,
+                                    );
+                                    // The first change moved the hub to the next version.
+                                    expected_version = expected_version.map(|version| version + 1);
+                                }
+                                if let Some(description) = update.description_long {
+                                    previous.description_long = Some(
+                                        crate::api::change_hub_long_description(
+                                            &sender,
+                                            hub_id,
+                                            description,
+                                            expected_version,
+                                        )
+                                        .await?,
+                                    );
+                                }
+                                create_response(
+                                    &serde_json::to_string(&previous)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let channel_description = warp::path!("v3" / "channel_description" / String / String)
+            .and(warp::put())
+            .and(signed_body_channel_description)
+            .and_then(
+                move |hub_id: String, channel_id: String, (update, sender): (String, String)| {
+                    let key_pair = key_pair_channel_description.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let hub_id = HubId::parse_str(&hub_id)?;
+                                let channel_id = ChannelId::parse_str(&channel_id)?;
+                                let update: DescriptionUpdate = serde_json::from_str(&update)?;
+                                let mut previous = DescriptionUpdate::default();
+                                if let Some(description) = update.description {
+                                    previous.description = Some(
+                                        crate::api::change_channel_description(
+                                            &sender,
+                                            hub_id,
+                                            channel_id,
+                                            description,
+                                        )
+                                        .await?,
+                                    );
+                                }
+                                if let Some(description) = update.description_long {
+                                    previous.description_long = Some(
+                                        crate::api::change_channel_long_description(
+                                            &sender,
+                                            hub_id,
+                                            channel_id,
+                                            description,
+                                        )
+                                        .await?,
+                                    );
+                                }
+                                create_response(
+                                    &serde_json::to_string(&previous)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let nicknames = warp::path!("v3" / "nicknames" / String)
+            .and(warp::get())
+            .and(signed_body_nicknames)
+            .and_then(move |hub_id: String, (_, sender): (String, String)| {
+                let key_pair = key_pair_nicknames.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let hub_id = HubId::parse_str(&hub_id)?;
+                            let nicknames = crate::api::get_nicknames(&sender, hub_id).await?;
+                            create_response(
+                                &serde_json::to_string(&nicknames)?,
+                                &key_pair.secret_key,
+                            )
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
+
+        let nickname = warp::path!("v3" / "nickname" / String)
+            .and(warp::put())
+            .and(signed_body_nickname)
+            .and_then(move |hub_id: String, (update, sender): (String, String)| {
+                let key_pair = key_pair_nickname.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let hub_id = HubId::parse_str(&hub_id)?;
+                            let update: NicknameUpdate = serde_json::from_str(&update)?;
+                            let previous = NicknameUpdate {
+                                nickname: crate::api::change_nickname(
+                                    &sender,
+                                    hub_id,
+                                    &update.nickname,
+                                )
+                                .await?,
+                            };
+                            create_response(
+                                &serde_json::to_string(&previous)?,
+                                &key_pair.secret_key,
+                            )
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
+
+        let nickname_policy = warp::path!("v3" / "nickname_policy" / String)
+            .and(warp::put())
+            .and(warp::header::optional::<String>("if-match"))
+            .and(signed_body_nickname_policy)
+            .and_then(
+                move |hub_id: String,
+                      if_match: Option<String>,
+                      (policy, sender): (String, String)| {
+                    let key_pair = key_pair_nickname_policy.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let hub_id = HubId::parse_str(&hub_id)?;
+                                let previous = crate::api::set_nickname_policy(
+                                    &sender,
+                                    hub_id,
+                                    serde_json::from_str(&policy)?,
+                                    parse_if_match(if_match)?,
+                                )
+                                .await?;
+                                create_response(
+                                    &serde_json::to_string(&previous)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let content_policy = warp::path!("v3" / "content_policy" / String)
+            .and(warp::get())
+            .and(signed_body_content_policy)
+            .and_then(move |hub_id: String, (_, sender): (String, String)| {
+                let key_pair = key_pair_content_policy.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let hub_id = HubId::parse_str(&hub_id)?;
+                            let policy = crate::api::get_content_policy(&sender, hub_id).await?;
+                            create_response(&serde_json::to_string(&policy)?, &key_pair.secret_key)
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
+
+        let set_content_policy = warp::path!("v3" / "content_policy" / String)
+            .and(warp::put())
+            .and(warp::header::optional::<String>("if-match"))
+            .and(signed_body_set_content_policy)
+            .and_then(
+                move |hub_id: String,
+                      if_match: Option<String>,
+                      (policy, sender): (String, String)| {
+                    let key_pair = key_pair_set_content_policy.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let hub_id = HubId::parse_str(&hub_id)?;
+                                let previous = crate::api::set_content_policy(
+                                    &sender,
+                                    hub_id,
+                                    serde_json::from_str(&policy)?,
+                                    parse_if_match(if_match)?,
+                                )
+                                .await?;
+                                create_response(
+                                    &serde_json::to_string(&previous)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let copy_permissions =
+            warp::path!("v3" / "copy_channel_permissions" / String / String / String)
+                .and(warp::post())
+                .and(warp::query::<CopyPermissionsQuery>())
+                .and(warp::header::optional::<String>("if-match"))
+                .and(signed_body_copy_permissions)
+                .and_then(
+                    move |hub_id: String,
+                          source: String,
+                          target: String,
+                          query: CopyPermissionsQuery,
+                          if_match: Option<String>,
+                          (_, sender): (String, String)| {
+                        let key_pair = key_pair_copy_permissions.clone();
+                        async move {
+                            Ok::<_, Infallible>(
+                                async {
+                                    let hub_id = HubId::parse_str(&hub_id)?;
+                                    let copied = crate::api::copy_channel_permissions(
+                                        &sender,
+                                        hub_id,
+                                        ChannelId::parse_str(&source)?,
+                                        &[ChannelId::parse_str(&target)?],
+                                        query.merge,
+                                        parse_if_match(if_match)?,
+                                    )
+                                    .await?;
+                                    create_response(
+                                        &serde_json::to_string(&copied)?,
+                                        &key_pair.secret_key,
+                                    )
+                                }
+                                .await
+                                .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                            )
+                        }
+                    },
+                );
+
+        let copy_permissions_many =
+            warp::path!("v3" / "copy_channel_permissions" / String / String)
+                .and(warp::post())
+                .and(warp::query::<CopyPermissionsQuery>())
+                .and(warp::header::optional::<String>("if-match"))
+                .and(signed_body_copy_permissions_many)
+                .and_then(
+                    move |hub_id: String,
+                          source: String,
+                          query: CopyPermissionsQuery,
+                          if_match: Option<String>,
+                          (targets, sender): (String, String)| {
+                        let key_pair = key_pair_copy_permissions_many.clone();
+                        async move {
+                            Ok::<_, Infallible>(
+                                async {
+                                    let hub_id = HubId::parse_str(&hub_id)?;
+                                    let targets: Vec<ChannelId> = serde_json::from_str(&targets)?;
+                                    let copied = crate::api::copy_channel_permissions(
+                                        &sender,
+                                        hub_id,
+                                        ChannelId::parse_str(&source)?,
+                                        &targets,
+                                        query.merge,
+                                        parse_if_match(if_match)?,
+                                    )
+                                    .await?;
+                                    create_response(
+                                        &serde_json::to_string(&copied)?,
+                                        &key_pair.secret_key,
+                                    )
+                                }
+                                .await
+                                .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                            )
+                        }
+                    },
+                );
+
+        let group_display = warp::path!("v3" / "group_display" / String)
+            .and(warp::get())
+            .and(signed_body_group_display)
+            .and_then(move |hub_id: String, (_, sender): (String, String)| {
+                let key_pair = key_pair_group_display.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let display =
+                                crate::api::get_group_display(&sender, HubId::parse_str(&hub_id)?)
+                                    .await?;
+                            create_response(&serde_json::to_string(&display)?, &key_pair.secret_key)
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
+
+        let set_group_display = warp::path!("v3" / "group_display" / String / String)
+            .and(warp::post())
+            .and(warp::header::optional::<String>("if-match"))
+            .and(signed_body_set_group_display)
+            .and_then(
+                move |hub_id: String,
+                      group_id: String,
+                      if_match: Option<String>,
+                      (update, sender): (String, String)| {
+                    let key_pair = key_pair_set_group_display.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let update: GroupDisplayUpdate = serde_json::from_str(&update)?;
+                                let display = crate::api::set_group_display(
+                                    &sender,
+                                    HubId::parse_str(&hub_id)?,
+                                    ID::parse_str(&group_id)?,
+                                    update.color,
+                                    update.hoist,
+                                    parse_if_match(if_match)?,
+                                )
+                                .await?;
+                                create_response(
+                                    &serde_json::to_string(&display)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let group_position = warp::path!("v3" / "group_position" / String / String / u32)
+            .and(warp::post())
+            .and(warp::header::optional::<String>("if-match"))
+            .and(signed_body_group_position)
+            .and_then(
+                move |hub_id: String,
+                      group_id: String,
+                      position: u32,
+                      if_match: Option<String>,
+                      (_, sender): (String, String)| {
+                    let key_pair = key_pair_group_position.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let display = crate::api::set_group_position(
+                                    &sender,
+                                    HubId::parse_str(&hub_id)?,
+                                    ID::parse_str(&group_id)?,
+                                    position,
+                                    parse_if_match(if_match)?,
+                                )
+                                .await?;
+                                create_response(
+                                    &serde_json::to_string(&display)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let group_preset = warp::path!("v3" / "group_preset" / String / String / String)
+            .and(warp::post())
+            .and(warp::header::optional::<String>("if-match"))
+            .and(signed_body_group_preset)
+            .and_then(
+                move |hub_id: String,
+                      group_id: String,
+                      preset: String,
+                      if_match: Option<String>,
+                      (_, sender): (String, String)| {
+                    let key_pair = key_pair_group_preset.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let group_id = ID::parse_str(&group_id)?;
+                                crate::api::apply_group_preset(
+                                    &sender,
+                                    HubId::parse_str(&hub_id)?,
+                                    group_id,
+                                    preset.parse()?,
+                                    parse_if_match(if_match)?,
+                                )
+                                .await?;
+                                create_response(
+                                    &serde_json::to_string(&group_id)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let effective_permissions = warp::path!("v3" / "effective_permissions" / String)
+            .and(warp::get())
+            .and(signed_body_effective_permissions)
+            .and_then(move |hub_id: String, (_, sender): (String, String)| {
+                let key_pair = key_pair_effective_permissions.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let permissions = crate::api::get_effective_permissions(
+                                &sender,
+                                HubId::parse_str(&hub_id)?,
+                            )
+                            .await?;
+                            create_response(
+                                &serde_json::to_string(&permissions)?,
+                                &key_pair.secret_key,
+                            )
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
+
+        let purge = warp::path!("v3" / "purge_user_messages" / String / String)
+            .and(warp::post())
+            .and(warp::query::<PurgeFilter>())
+            .and(signed_body_purge)
+            .and_then(
+                move |hub_id: String,
+                      user_id: String,
+                      filter: PurgeFilter,
+                      (_, sender): (String, String)| {
+                    let key_pair = key_pair_purge.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let hub_id = HubId::parse_str(&hub_id)?;
+                                let status = crate::api::purge_user_messages(
+                                    &sender, hub_id, &user_id, filter,
+                                )
+                                .await?;
+                                create_response(
+                                    &serde_json::to_string(&status)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let purge_status = warp::path!("v3" / "purge_status" / String)
+            .and(warp::get())
+            .and(signed_body_purge_status)
+            .and_then(move |purge_id: String, (_, sender): (String, String)| {
+                let key_pair = key_pair_purge_status.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let status =
+                                crate::api::get_purge_status(&sender, ID::parse_str(&purge_id)?)?;
+                            create_response(&serde_json::to_string(&status)?, &key_pair.secret_key)
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct ServerInfo {
-    pub version: String,
-    pub public_key_fingerprint: String,
-    pub key_server: String,
-}
+        let move_messages = warp::path!("v3" / "move_messages" / String / String / String)
+            .and(warp::post())
+            .and(signed_body_move_messages)
+            .and_then(
+                move |hub_id: String,
+                      source: String,
+                      target: String,
+                      (message_ids, sender): (String, String)| {
+                    let key_pair = key_pair_move_messages.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let result = crate::api::move_messages(
+                                    &sender,
+                                    HubId::parse_str(&hub_id)?,
+                                    ChannelId::parse_str(&source)?,
+                                    ChannelId::parse_str(&target)?,
+                                    serde_json::from_str(&message_ids)?,
+                                )
+                                .await?;
+                                create_response(
+                                    &serde_json::to_string(&result)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
 
-pub async fn start(config: Config) -> Result {
-    let key_pair = if let Ok(key_pair) = KeyPair::load(SECRET_KEY_PATH, PUBLIC_KEY_PATH).await {
-        key_pair
-    } else {
-        warn!(
-            "Failed to load secret key from {}, generating a new one.",
-            SECRET_KEY_PATH
-        );
-        let key_id = if let Some(key_id) = config.key_id.clone() {
-            key_id
-        } else {
-            println!(
-                "Enter an ID for the server's PGP key (e.g. WICRS Server <wicrs@examle.com>):"
+        let request_delete_hub = warp::path!("v3" / "request_delete_hub" / String)
+            .and(warp::post())
+            .and(signed_body_request_delete_hub)
+            .and_then(move |hub_id: String, (_, sender): (String, String)| {
+                let key_pair = key_pair_request_delete_hub.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let hub_id = HubId::parse_str(&hub_id)?;
+                            let token = crate::api::request_hub_deletion(&sender, hub_id).await?;
+                            create_response(&serde_json::to_string(&token)?, &key_pair.secret_key)
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
+
+        let delete_hub = warp::path!("v3" / "delete_hub" / String / String)
+            .and(warp::delete())
+            .and(signed_body_delete_hub)
+            .and_then(
+                move |hub_id: String, token: String, (_, sender): (String, String)| {
+                    let key_pair = key_pair_delete_hub.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let hub_id = HubId::parse_str(&hub_id)?;
+                                crate::api::delete_hub(&sender, hub_id, &token).await?;
+                                create_response(
+                                    &serde_json::to_string(&hub_id)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
             );
-            let mut buf = String::new();
-            std::io::stdin().read_line(&mut buf)?;
-            buf
-        };
-        info!("Generating new PGP key pair for the server...");
-        let key_pair = KeyPair::new(key_id)?;
-        key_pair.save(SECRET_KEY_PATH, PUBLIC_KEY_PATH).await?;
-        info!(
-            "Server key pair generated, private key saved to {}, public key saved to {}.",
-            SECRET_KEY_PATH, PUBLIC_KEY_PATH
-        );
-        key_pair
-    };
-    let key_pair = Arc::new(key_pair);
-    let upload_key = async {
-        let armoured_pub_key = key_pair.public_key.to_armored_string(None)?;
-        let form = reqwest::multipart::Form::new().text("keytext", armoured_pub_key);
-        let url = format!("{}/pks/add", config.key_server);
-
-        let response = reqwest::Client::builder()
-            .build()?
-            .post(&url)
-            .multipart(form)
-            .send()
-            .await?;
-        if response.status() == StatusCode::OK {
-            Ok(())
-        } else {
-            Err(Error::Other(
-                "Failed to upload the server's public PGP key to the selected key server."
-                    .to_string(),
-            ))
-        }
-    }
-    .await;
-    if upload_key.is_err() {
-        warn!("Unable to upload public key to key server.");
-    }
-    let server_fingerprint = hex::encode_upper(key_pair.secret_key.fingerprint());
-    let key_pair_ws = key_pair.clone();
-    let schema = Schema::build(QueryRoot, MutationRoot, EmptySubscription).finish();
-    let server = Arc::new(
-        Server::new(key_pair.secret_key.clone())
-            .await?
-            .start()
-            .await
-            .map_err(|_| Error::ServerStartFailed)?,
-    );
-    let send_message_server_arc = server.clone();
-    let key_pair_send = key_pair.clone();
-    let key_pair_send_init = key_pair.clone();
-    let graphql_server_arc = server.clone();
-    let key_server_url = config.key_server.clone();
-    let public_key_filter =
-        warp::any()
-            .and(warp::header("pgp-fingerprint"))
-            .and_then(move |header: String| {
-                let key_server_url = key_server_url.clone();
+
+        let owned_hubs = warp::path!("v3" / "owned_hubs")
+            .and(warp::get())
+            .and(signed_body_owned_hubs)
+            .and_then(move |(_, sender): (String, String)| {
+                let key_pair = key_pair_owned_hubs.clone();
                 async move {
-                    crate::signing::get_or_import_public_key(
-                        &hex::decode(header).map_err(|_| Error::InvalidFingerprint)?,
-                        &key_server_url,
+                    Ok::<_, Infallible>(
+                        async {
+                            let hubs = crate::api::get_owned_hubs(&sender).await?;
+                            create_response(&serde_json::to_string(&hubs)?, &key_pair.secret_key)
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
                     )
-                    .await
-                    .and_then(|key| {
-                        key.verify()?;
-                        Ok(key)
-                    })
-                    .map_err(warp::reject::custom)
                 }
             });
 
-    let signed_body_pub_key = public_key_filter.clone();
+        let leave_all_hubs = warp::path!("v3" / "leave_all_hubs")
+            .and(warp::post())
+            .and(signed_body_leave_all)
+            .and_then(move |(request, sender): (String, String)| {
+                let key_pair = key_pair_leave_all.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let request: LeaveAllHubs = if request.trim().is_empty() {
+                                LeaveAllHubs::default()
+                            } else {
+                                serde_json::from_str(&request)?
+                            };
+                            let result =
+                                crate::api::leave_all_hubs(&sender, &request.exclude).await?;
+                            create_response(&serde_json::to_string(&result)?, &key_pair.secret_key)
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
 
-    let signed_body = signed_body_pub_key.and(warp::body::bytes()).and_then(
-        |requester_public_key: SignedPublicKey, body: Bytes| async move {
-            let text = String::from_utf8(body.to_vec())
-                .map_err(|e| warp::reject::custom(Error::from(e)))?;
-            crate::signing::verify_message_extract(&requester_public_key, &text)
-                .map_err(warp::reject::custom)
-        },
-    );
+        let bulk_transfer_hubs = warp::path!("v3" / "bulk_transfer_hubs")
+            .and(warp::post())
+            .and(signed_body_bulk_transfer)
+            .and_then(move |(transfers, sender): (String, String)| {
+                let key_pair = key_pair_bulk_transfer.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let result = crate::api::bulk_transfer_hubs(
+                                &sender,
+                                serde_json::from_str(&transfers)?,
+                            )
+                            .await?;
+                            create_response(&serde_json::to_string(&result)?, &key_pair.secret_key)
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
 
-    let schema_sdl = schema.sdl();
-    let signed_body_graphql = signed_body.clone();
-    let graphql_key_pair = key_pair.clone();
-    let graphql_post = warp::any()
-        .and(warp::path!("v3" / "graphql"))
-        .and(signed_body_graphql)
-        .and_then(move |(content, fingerprint): (String, String)| {
-            let server = graphql_server_arc.clone();
-            let schema = schema.clone();
-            let key_pair = graphql_key_pair.clone();
-            async move {
-                Ok::<_, Infallible>(
-                    async {
-                        let request = GraphQLRequest::new(content);
-                        let resp = schema
-                            .execute(request.data(server).data(hex::encode_upper(fingerprint)))
-                            .await;
+        let drafts = warp::path!("v3" / "drafts")
+            .and(warp::get())
+            .and(signed_body_drafts)
+            .and_then(move |(_, sender): (String, String)| {
+                let key_pair = key_pair_drafts.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let drafts = crate::api::get_drafts(&sender).await?;
+                            create_response(&serde_json::to_string(&drafts)?, &key_pair.secret_key)
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
 
-                        let mut response =
-                            create_response(resp.data.to_string().as_str(), &key_pair.secret_key)?;
-                        if let Some(value) = resp.cache_control.value() {
-                            if let Ok(value) = value.try_into() {
-                                response.headers_mut().insert("cache-control", value);
+        let set_draft = warp::path!("v3" / "draft" / String / String)
+            .and(warp::put())
+            .and(signed_body_set_draft)
+            .and_then(
+                move |hub_id: String, channel_id: String, (update, sender): (String, String)| {
+                    let key_pair = key_pair_set_draft.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let update: DraftUpdate = serde_json::from_str(&update)?;
+                                crate::api::set_draft(
+                                    &sender,
+                                    HubId::parse_str(&hub_id)?,
+                                    ChannelId::parse_str(&channel_id)?,
+                                    &update.content,
+                                )
+                                .await?;
+                                create_response(
+                                    &serde_json::to_string(&update)?,
+                                    &key_pair.secret_key,
+                                )
                             }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let subscription_profile = warp::path!("v3" / "subscription_profile")
+            .and(warp::get())
+            .and(signed_body_subscription_profile)
+            .and_then(move |(_, sender): (String, String)| {
+                let key_pair = key_pair_subscription_profile.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let profile = crate::api::get_subscription_profile(&sender).await?;
+                            create_response(&serde_json::to_string(&profile)?, &key_pair.secret_key)
                         }
-                        for (name, value) in resp.http_headers {
-                            if let Some(name) = name {
-                                if let Ok(value) = value.try_into() {
-                                    response.headers_mut().append(name, value);
-                                }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
+
+        let set_subscription_profile = warp::path!("v3" / "subscription_profile")
+            .and(warp::put())
+            .and(signed_body_set_subscription_profile)
+            .and_then(move |(profile, sender): (String, String)| {
+                let key_pair = key_pair_set_subscription_profile.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let profile: SubscriptionProfile = serde_json::from_str(&profile)?;
+                            crate::api::set_subscription_profile(&sender, &profile).await?;
+                            create_response(&serde_json::to_string(&profile)?, &key_pair.secret_key)
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
+
+        let members = warp::path!("v3" / "members" / String)
+            .and(warp::get())
+            .and(warp::query::<MemberListQuery>())
+            .and(signed_body_members)
+            .and_then(
+                move |hub_id: String, query: MemberListQuery, (_, sender): (String, String)| {
+                    let key_pair = key_pair_members.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let hub_id = HubId::parse_str(&hub_id)?;
+                                let members = crate::api::list_hub_members(
+                                    &sender,
+                                    hub_id,
+                                    query.sort,
+                                    query.group,
+                                    &query.prefix,
+                                    query.hoisted,
+                                    query.offset,
+                                    query.limit,
+                                )
+                                .await?;
+                                create_response(
+                                    &serde_json::to_string(&members)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let member_map = warp::path!("v3" / "member_map" / String)
+            .and(warp::get())
+            .and(warp::query::<MemberMapQuery>())
+            .and(warp::header::optional::<String>("accept-encoding"))
+            .and(signed_body_member_map)
+            .and_then(
+                move |hub_id: String,
+                      query: MemberMapQuery,
+                      accept_encoding: Option<String>,
+                      (_, sender): (String, String)| {
+                    let key_pair = key_pair_member_map.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let hub_id = HubId::parse_str(&hub_id)?;
+                                let map = crate::api::get_member_map(
+                                    &sender,
+                                    hub_id,
+                                    query.since_version,
+                                    query.after.as_deref(),
+                                    query.limit,
+                                )
+                                .await?;
+                                gzip_response(
+                                    create_response(
+                                        &serde_json::to_string(&map)?,
+                                        &key_pair.secret_key,
+                                    )?,
+                                    accept_encoding.as_deref(),
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let member_history = warp::path!("v3" / "member_history" / String)
+            .and(warp::query::<MemberHistoryQuery>())
+            .and(signed_body_history)
+            .and_then(
+                move |hub_id: String, query: MemberHistoryQuery, (_, sender): (String, String)| {
+                    let key_pair = key_pair_history.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let hub_id = HubId::parse_str(&hub_id)?;
+                                let history = crate::api::get_member_history(
+                                    &sender,
+                                    hub_id,
+                                    query.from,
+                                    query.to,
+                                    query.offset,
+                                    query.limit,
+                                )
+                                .await?;
+                                create_response(
+                                    &serde_json::to_string(&history)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let change_history = warp::path!("v3" / "change_history" / String)
+            .and(warp::get())
+            .and(warp::query::<ChangeHistoryQuery>())
+            .and(signed_body_change_history)
+            .and_then(
+                move |hub_id: String, query: ChangeHistoryQuery, (_, sender): (String, String)| {
+                    let key_pair = key_pair_change_history.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let hub_id = HubId::parse_str(&hub_id)?;
+                                let history =
+                                    crate::api::get_change_history(&sender, hub_id, query.field)
+                                        .await?;
+                                create_response(
+                                    &serde_json::to_string(&history)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let hub_activity = warp::path!("v3" / "hub_activity" / String)
+            .and(warp::get())
+            .and(warp::query::<HubActivityQuery>())
+            .and(signed_body_activity)
+            .and_then(
+                move |hub_id: String, query: HubActivityQuery, (_, sender): (String, String)| {
+                    let key_pair = key_pair_activity.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let hub_id = HubId::parse_str(&hub_id)?;
+                                let activity =
+                                    crate::api::get_hub_activity(&sender, hub_id, query.days)
+                                        .await?;
+                                create_response(
+                                    &serde_json::to_string(&activity)?,
+                                    &key_pair.secret_key,
+                                )
                             }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let hub_storage = warp::path!("v3" / "hub_storage" / String)
+            .and(warp::get())
+            .and(signed_body_hub_storage)
+            .and_then(move |hub_id: String, (_, sender): (String, String)| {
+                let key_pair = key_pair_hub_storage.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let hub_id = HubId::parse_str(&hub_id)?;
+                            let storage = crate::api::get_hub_storage(&sender, hub_id).await?;
+                            create_response(&serde_json::to_string(&storage)?, &key_pair.secret_key)
                         }
-                        Ok::<_, Error>(response)
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
+
+        let compact_channel = warp::path!("v3" / "compact_channel" / String / String)
+            .and(warp::post())
+            .and(signed_body_compact_channel)
+            .and_then(
+                move |hub_id: String, channel_id: String, (_, sender): (String, String)| {
+                    let key_pair = key_pair_compact_channel.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let hub_id = HubId::parse_str(&hub_id)?;
+                                let channel_id = ChannelId::parse_str(&channel_id)?;
+                                let compaction =
+                                    crate::api::compact_channel(&sender, hub_id, channel_id)
+                                        .await?;
+                                create_response(
+                                    &serde_json::to_string(&compaction)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
                     }
-                    .await
-                    .map_or_else(|e| e.into_response(), |r| r.into_response()),
-                )
+                },
+            );
+
+        let leaderboard = warp::path!("v3" / "leaderboard" / String)
+            .and(warp::get())
+            .and(warp::query::<LeaderboardQuery>())
+            .and(signed_body_leaderboard)
+            .and_then(
+                move |hub_id: String, query: LeaderboardQuery, (_, sender): (String, String)| {
+                    let key_pair = key_pair_leaderboard.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let hub_id = HubId::parse_str(&hub_id)?;
+                                let leaderboard = crate::api::get_leaderboard(
+                                    &sender,
+                                    hub_id,
+                                    query.period,
+                                    query.limit,
+                                )
+                                .await?;
+                                create_response(
+                                    &serde_json::to_string(&leaderboard)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let set_leaderboard = warp::path!("v3" / "leaderboard" / String)
+            .and(warp::put())
+            .and(signed_body_leaderboard_set)
+            .and_then(move |hub_id: String, (enabled, sender): (String, String)| {
+                let key_pair = key_pair_leaderboard_set.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let hub_id = HubId::parse_str(&hub_id)?;
+                            let enabled: bool = serde_json::from_str(&enabled)?;
+                            crate::api::set_leaderboard_enabled(&sender, hub_id, enabled).await?;
+                            create_response(&serde_json::to_string(&enabled)?, &key_pair.secret_key)
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
+
+        let user_quota = warp::path!("v3" / "user_quota" / String)
+            .and(warp::get())
+            .and(signed_body_quota)
+            .and_then(move |user_id: String, (_, sender): (String, String)| {
+                let key_pair = key_pair_quota.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let status = crate::api::get_user_quota(&sender, &user_id).await?;
+                            create_response(&serde_json::to_string(&status)?, &key_pair.secret_key)
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            });
+
+        let set_user_quota = warp::path!("v3" / "user_quota" / String)
+            .and(warp::put())
+            .and(signed_body_quota_set)
+            .and_then(
+                move |user_id: String, (overrides, sender): (String, String)| {
+                    let key_pair = key_pair_quota_set.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let status = crate::api::set_user_quota_overrides(
+                                    &sender,
+                                    &user_id,
+                                    serde_json::from_str(&overrides)?,
+                                )
+                                .await?;
+                                create_response(
+                                    &serde_json::to_string(&status)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let link_identity = warp::path!("v3" / "link_identity" / String)
+            .and(warp::post())
+            .and(signed_body_link_identity)
+            .and_then(
+                move |user_id: String, (request, sender): (String, String)| {
+                    let key_pair = key_pair_link_identity.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let result = crate::api::link_identity(
+                                    &sender,
+                                    &user_id,
+                                    serde_json::from_str(&request)?,
+                                )
+                                .await?;
+                                create_response(
+                                    &serde_json::to_string(&result)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        let security_log = warp::path!("v3" / "admin" / "security_log")
+            .and(warp::get())
+            .and(warp::query::<SecurityLogQuery>())
+            .and(signed_body_security_log)
+            .and_then(
+                move |query: SecurityLogQuery, (_, sender): (String, String)| {
+                    let key_pair = key_pair_security_log.clone();
+                    async move {
+                        Ok::<_, Infallible>(
+                            async {
+                                let page = crate::api::get_security_log(&sender, &query).await?;
+                                create_response(
+                                    &serde_json::to_string(&page)?,
+                                    &key_pair.secret_key,
+                                )
+                            }
+                            .await
+                            .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                        )
+                    }
+                },
+            );
+
+        // The routes are boxed in groups, one long chain of `or` filters is too deep for the type checker.
+        // Server information and routes that can be used without being in a hub.
+        let info_routes = server_info
+            .or(instance_info)
+            .or(stats)
+            .or(hub_icon)
+            .or(hub_banner)
+            .or(hub_preview)
+            .or(set_hub_preview)
+            .or(resolve_code)
+            .boxed();
+        // Sending, editing, reading and moderating messages.
+        let messages_routes = send_message_init
+            .or(send_message)
+            .or(webhook_send)
+            .or(edit_message_init)
+            .or(edit_message)
+            .or(forward_message_init)
+            .or(message_history)
+            .or(link_previews)
+            .or(message_rendered)
+            .or(purge)
+            .or(purge_status)
+            .or(move_messages)
+            .or(compact_channel)
+            .boxed();
+        // Hubs as a whole: their state, settings, statistics and deletion.
+        let hubs_routes = hub_fields
+            .or(hub_delta)
+            .or(events_since)
+            .or(create_hub)
+            .or(hub_description)
+            .or(content_policy)
+            .or(set_content_policy)
+            .or(request_delete_hub)
+            .or(delete_hub)
+            .or(owned_hubs)
+            .or(leave_all_hubs)
+            .or(bulk_transfer_hubs)
+            .or(hub_activity)
+            .or(hub_storage)
+            .or(leaderboard)
+            .or(set_leaderboard)
+            .boxed();
+        // Channels and permission groups.
+        let channels_routes = create_channel
+            .or(delete_channel)
+            .or(channel_description)
+            .or(copy_permissions)
+            .or(copy_permissions_many)
+            .or(group_display)
+            .or(set_group_display)
+            .or(group_position)
+            .or(group_preset)
+            .or(effective_permissions)
+            .or(webhooks)
+            .or(create_webhook)
+            .or(delete_webhook)
+            .boxed();
+        // Invites, members and their history.
+        let members_routes = invites
+            .or(create_invite)
+            .or(revoke_invite)
+            .or(invite_stats)
+            .or(kick_invite_members)
+            .or(join)
+            .or(nicknames)
+            .or(nickname)
+            .or(nickname_policy)
+            .or(members)
+            .or(member_map)
+            .or(member_history)
+            .or(change_history)
+            .boxed();
+        // Data of the signed in user.
+        let user_routes = export_account
+            .or(export_account_status)
+            .or(export_account_download)
+            .or(drafts)
+            .or(set_draft)
+            .or(subscription_profile)
+            .or(set_subscription_profile)
+            .boxed();
+        // Routes for server admins.
+        let admin_routes = user_quota
+            .or(set_user_quota)
+            .or(link_identity)
+            .or(security_log)
+            .boxed();
+        let routes = info_routes
+            .or(messages_routes)
+            .or(hubs_routes)
+            .or(channels_routes)
+            .or(members_routes)
+            .or(user_routes)
+            .or(admin_routes);
+        #[cfg(feature = "websocket")]
+        let routes = routes.or(web_socket);
+        #[cfg(feature = "graphql")]
+        let routes = routes.or(graphql_routes(
+            &self.config.graphql,
+            graphql_server_arc,
+            key_pair,
+            signed_body,
+        ));
+        routes
+    }
+
+    /// Serves the API on the configured address until the server is stopped.
+    /// With the `systemd` feature a socket passed by systemd is used instead if there is one, and systemd is notified when the server is ready and when it is stopping.
+    pub async fn serve(self) -> Result {
+        let cors = match &self.config.allowed_origins {
+            Some(origins) => warp::cors().allow_origins(origins.iter().map(String::as_str)),
+            None => warp::cors().allow_any_origin(),
+        };
+        let routes = self
+            .routes()
+            .with(cors)
+            .with(warp::log("wicrs_server::http"));
+        let address = self
+            .config
+            .address
+            .parse::<SocketAddr>()
+            .expect("Invalid bind address");
+        crate::identity_links::init().await?;
+        crate::hub_activity::spawn_flusher();
+        crate::leaderboard::spawn_flusher();
+
+        #[cfg(not(feature = "systemd"))]
+        {
+            let (_, server) = warp::serve(routes)
+                .bind_with_graceful_shutdown(address, shutdown(self.server.clone()));
+            server.await;
+        }
+
+        #[cfg(feature = "systemd")]
+        {
+            use crate::systemd;
+            let service = warp::serve(routes);
+            if let Some(listener) = systemd::take_listener()? {
+                let server = service
+                    .serve_incoming_with_graceful_shutdown(listener, shutdown(self.server.clone()));
+                systemd::notify_ready();
+                systemd::spawn_watchdog(self.server.clone());
+                server.await;
+            } else {
+                let (_, server) =
+                    service.bind_with_graceful_shutdown(address, shutdown(self.server.clone()));
+                systemd::notify_ready();
+                systemd::spawn_watchdog(self.server.clone());
+                server.await;
             }
-        });
+        }
+
+        let leaderboard_flushed = crate::leaderboard::flush().await;
+        crate::hub_activity::flush().await.and(leaderboard_flushed)
+    }
+}
+
+/// Completes when the server is told to stop, after closing the WebSocket connections with [`CloseCode::ServerShutdown`].
+async fn shutdown(server: Arc<InstrumentedAddr<Server>>) {
+    #[cfg(feature = "systemd")]
+    crate::systemd::shutdown_signal().await;
+    #[cfg(not(feature = "systemd"))]
+    let _ = tokio::signal::ctrl_c().await;
+    let _ = server
+        .call(CloseConnections(CloseCode::ServerShutdown))
+        .await;
+}
+
+/// Loads the server's key pair, generating and saving a new one if it could not be loaded.
+async fn load_or_generate_key_pair(config: &Config) -> Result<KeyPair> {
+    if let Ok(key_pair) = KeyPair::load(SECRET_KEY_PATH, PUBLIC_KEY_PATH).await {
+        return Ok(key_pair);
+    }
+    warn!(
+        "Failed to load secret key from {}, generating a new one.",
+        SECRET_KEY_PATH
+    );
+    let key_id = if let Some(key_id) = config.key_id.clone() {
+        key_id
+    } else {
+        println!("Enter an ID for the server's PGP key (e.g. WICRS Server <wicrs@examle.com>):");
+        let mut buf = String::new();
+        std::io::stdin().read_line(&mut buf)?;
+        buf
+    };
+    info!("Generating new PGP key pair for the server...");
+    let key_pair = KeyPair::new(key_id)?;
+    key_pair.save(SECRET_KEY_PATH, PUBLIC_KEY_PATH).await?;
+    info!(
+        "Server key pair generated, private key saved to {}, public key saved to {}.",
+        SECRET_KEY_PATH, PUBLIC_KEY_PATH
+    );
+    Ok(key_pair)
+}
+
+/// Uploads the server's public key to the given key server.
+async fn upload_public_key(key_pair: &KeyPair, key_server: &str) -> Result {
+    let armoured_pub_key = key_pair.public_key.to_armored_string(None)?;
+    let form = reqwest::multipart::Form::new().text("keytext", armoured_pub_key);
+    let url = format!("{}/pks/add", key_server);
+
+    let response = reqwest::Client::builder()
+        .build()?
+        .post(&url)
+        .multipart(form)
+        .send()
+        .await?;
+    if response.status() == StatusCode::OK {
+        Ok(())
+    } else {
+        Err(Error::Other(
+            "Failed to upload the server's public PGP key to the selected key server.".to_string(),
+        ))
+    }
+}
 
-    let signed_body_smi = signed_body.clone();
+/// Creates the routes for one kind of hub image under `/v3/{name}/{hub_id}`.
+/// `PUT` changes the image, the body must be signed and contain the image as literal data, the response is the new version of the image.
+/// `GET` returns the image itself, unsigned so that it can be used directly, with an `ETag` of its version.
+fn hub_image_routes<F>(
+    name: &'static str,
+    kind: ImageKind,
+    key_pair: Arc<KeyPair>,
+    public_key: F,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = (SignedPublicKey,), Error = Rejection> + Clone + Send + Sync + 'static,
+{
+    let path = warp::path("v3")
+        .and(warp::path(name))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end());
 
-    let send_message_init = warp::any()
-        .and(warp::path!("v3" / "send_message_init" / String / String))
-        .and(signed_body_smi)
+    let put = path
+        .and(warp::put())
+        .and(public_key)
+        // Armoured messages are about a third larger than their content.
+        .and(warp::body::content_length_limit(MAX_BANNER_SIZE as u64 * 2))
+        .and(warp::body::bytes())
         .and_then(
-            move |hub_id: String, channel_id: String, (content, sender): (String, String)| {
-                let key_pair = key_pair_send_init.clone();
+            move |hub_id: String, requester_public_key: SignedPublicKey, body: Bytes| {
+                let key_pair = key_pair.clone();
                 async move {
                     Ok::<_, Infallible>(
                         async {
-                            let hub_id = ID::parse_str(&hub_id)?;
-                            let hub = Hub::load(hub_id).await?;
-                            let channel_id = ID::parse_str(&channel_id)?;
-                            let member = hub.get_member(&sender)?;
-                            crate::check_permission!(
-                                &member,
-                                channel_id,
-                                ChannelPermission::Write,
-                                &hub
-                            );
-                            let msg = Message::new(sender, content, hub_id, channel_id);
-                            create_response(&serde_json::to_string(&msg)?, &key_pair.secret_key)
+                            let hub_id = HubId::parse_str(&hub_id)?;
+                            let body = String::from_utf8(body.to_vec())?;
+                            let (image, sender) = crate::signing::verify_message_extract_bytes(
+                                &requester_public_key,
+                                &body,
+                            )?;
+                            let version =
+                                crate::api::set_hub_image(&sender, hub_id, kind, &image).await?;
+                            create_response(&serde_json::to_string(&version)?, &key_pair.secret_key)
                         }
                         .await
                         .map_or_else(|e| e.into_response(), |r| r.into_response()),
@@ -209,95 +2842,104 @@ pub async fn start(config: Config) -> Result {
             },
         );
 
-    let send_message_pub_key = public_key_filter.clone();
-
-    let send_message = warp::any()
-        .and(warp::path!("v3" / "send_message"))
-        .and(send_message_pub_key)
-        .and(warp::body::bytes())
-        .and_then(move |client_public_key: SignedPublicKey, body: Bytes| {
-            let key_pair = key_pair_send.clone();
-            let server = send_message_server_arc.clone();
-            async move {
+    let get = path
+        .and(warp::get())
+        .and(warp::header::optional::<String>("if-none-match"))
+        .and_then(
+            move |hub_id: String, if_none_match: Option<String>| async move {
                 Ok::<_, Infallible>(
                     async {
-                        let body = String::from_utf8(body.to_vec())?;
-                        let message = Message::from_double_signed_verify(
-                            &body,
-                            &key_pair.public_key,
-                            &client_public_key,
-                        )?;
-                        let response = create_response(
-                            &serde_json::to_string(&message)?,
-                            &key_pair.secret_key,
-                        );
-                        let _ = server.send(ServerNotification::NewMessage(
-                            message.hub_id,
-                            message.channel_id,
-                            message.id,
-                            body,
-                            message,
-                        ));
-                        response
+                        let hub_id = HubId::parse_str(&hub_id)?;
+                        let (image, bytes) = crate::api::get_hub_image(hub_id, kind).await?;
+                        let etag = format!("\"{}\"", image.version);
+                        let response = warp::http::response::Builder::new()
+                            .header("Cache-Control", HUB_IMAGE_CACHE_CONTROL)
+                            .header("ETag", &etag);
+                        if if_none_match.as_deref() == Some(etag.as_str()) {
+                            Ok::<_, Error>(
+                                response.status(StatusCode::NOT_MODIFIED).body(Vec::new())?,
+                            )
+                        } else {
+                            Ok(response
+                                .header("Content-Type", image.format.content_type())
+                                .body(bytes)?)
+                        }
                     }
                     .await
                     .map_or_else(|e| e.into_response(), |r| r.into_response()),
                 )
-            }
-        });
+            },
+        );
 
-    let web_socket = warp::path!("v3" / "websocket")
-        .and(public_key_filter)
-        .and(warp::ws())
-        .map(move |public_key: SignedPublicKey, ws: Ws| {
-            let key_pair = key_pair_ws.clone();
-            let server = server.clone();
-            ws.on_upgrade(move |websocket| async move {
-                let _ =
-                    crate::websocket::handle_connection(websocket, public_key, key_pair, server)
-                        .await;
-            })
-        });
+    put.or(get)
+}
 
-    let server_info_struct = ServerInfo {
-        version: env!("CARGO_PKG_VERSION").to_string(),
-        public_key_fingerprint: server_fingerprint,
-        key_server: config.key_server,
-    };
-    let server_info_str = serde_json::to_string(&server_info_struct).unwrap();
+/// Creates the GraphQL routes (`/v3/graphql` and `/v3/graphql_schema`), queries are rejected if they go over the depth or complexity limits in the given configuration.
+#[cfg(feature = "graphql")]
+fn graphql_routes<F>(
+    config: &GraphQLConfig,
+    server: Arc<InstrumentedAddr<Server>>,
+    key_pair: Arc<KeyPair>,
+    signed_body: F,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    F: Filter<Extract = ((String, String),), Error = Rejection> + Clone + Send + Sync + 'static,
+{
+    let schema = Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+        .limit_depth(config.max_depth)
+        .limit_complexity(config.max_complexity)
+        .finish();
+    let schema_sdl = schema.sdl();
+    let graphql_key_pair = key_pair.clone();
+    let graphql_post = warp::any()
+        .and(warp::path!("v3" / "graphql"))
+        .and(warp::header::optional::<String>("if-match"))
+        .and(signed_body)
+        .and_then(
+            move |if_match: Option<String>, (content, fingerprint): (String, String)| {
+                let server = server.clone();
+                let schema = schema.clone();
+                let key_pair = graphql_key_pair.clone();
+                async move {
+                    Ok::<_, Infallible>(
+                        async {
+                            let if_match = IfMatch(parse_if_match(if_match)?);
+                            let request = GraphQLRequest::new(content);
+                            let resp = schema
+                                .execute(request.data(server).data(fingerprint).data(if_match))
+                                .await;
 
-    let server_info_secret = key_pair.secret_key.clone();
+                            let mut response = create_response(
+                                resp.data.to_string().as_str(),
+                                &key_pair.secret_key,
+                            )?;
+                            if let Some(value) = resp.cache_control.value() {
+                                if let Ok(value) = value.try_into() {
+                                    response.headers_mut().insert("cache-control", value);
+                                }
+                            }
+                            for (name, value) in resp.http_headers {
+                                if let Some(name) = name {
+                                    if let Ok(value) = value.try_into() {
+                                        response.headers_mut().append(name, value);
+                                    }
+                                }
+                            }
+                            Ok::<_, Error>(response)
+                        }
+                        .await
+                        .map_or_else(|e| e.into_response(), |r| r.into_response()),
+                    )
+                }
+            },
+        );
 
-    let server_info = warp::path!("v3" / "info").map(move || {
-        create_response(server_info_str.clone().as_str(), &server_info_secret)
-            .map_or_else(|e| e.into_response(), |r| r.into_response())
-    });
     let graphql_schema = warp::path!("v3" / "graphql_schema").map(move || {
         create_response(&schema_sdl, &key_pair.secret_key)
             .map_or_else(|e| e.into_response(), |r| r.into_response())
     });
 
-    let cors = warp::cors().allow_any_origin();
-    let log = warp::log("wicrs_server::http");
-
-    let routes = graphql_post
-        .or(server_info)
-        .or(graphql_schema)
-        .or(web_socket)
-        .or(send_message_init)
-        .or(send_message)
-        .with(cors)
-        .with(log);
-    let server = warp::serve(routes).run(
-        config
-            .address
-            .parse::<SocketAddr>()
-            .expect("Invalid bind address"),
-    );
-
-    server.await;
-
-    Ok(())
+    graphql_post.or(graphql_schema)
 }
 
 fn create_response(response: &str, key: &impl SecretKeyTrait) -> Result<HttpResponse<String>> {
@@ -333,3 +2975,30 @@ fn create_response(response: &str, key: &impl SecretKeyTrait) -> Result<HttpResp
         )
         .body(body)?)
 }
+
+/// Compresses a response with gzip if the client accepts it (from its `Accept-Encoding` header), for responses that can be large.
+///
+/// # Errors
+///
+/// This function returns an error if the response could not be compressed.
+fn gzip_response(
+    response: HttpResponse<String>,
+    accept_encoding: Option<&str>,
+) -> Result<HttpResponse<Vec<u8>>> {
+    let accepts_gzip = accept_encoding.is_some_and(|accept_encoding| {
+        accept_encoding
+            .split(',')
+            .any(|encoding| encoding.split(';').next().map(str::trim) == Some("gzip"))
+    });
+    let (mut parts, body) = response.into_parts();
+    if !accepts_gzip {
+        return Ok(HttpResponse::from_parts(parts, body.into_bytes()));
+    }
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(body.as_bytes())?;
+    parts.headers.insert(
+        warp::http::header::CONTENT_ENCODING,
+        warp::http::HeaderValue::from_static("gzip"),
+    );
+    Ok(HttpResponse::from_parts(parts, encoder.finish()?))
+}
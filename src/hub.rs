@@ -1,6 +1,7 @@
 use std::{
     collections::{HashMap, HashSet},
     mem,
+    path::PathBuf,
 };
 
 use chrono::{DateTime, Utc};
@@ -8,42 +9,66 @@ use serde::{Deserialize, Serialize};
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
 
+#[cfg(feature = "graphql")]
+use async_graphql::InputObject;
+
 use crate::{
-    channel::Channel,
-    check_name_validity, check_permission,
-    error::Error,
+    channel::{Channel, SignedMessage},
+    check_permission,
+    error::{Error, IoContext},
     new_id,
     permission::{
         ChannelPermission, ChannelPermissions, HubPermission, HubPermissions, PermissionSetting,
     },
-    Result, ID,
+    validation::{
+        fold_name, unique_channel_names, validate_description, validate_name, DescriptionKind,
+        NameKind,
+    },
+    ChannelId, HubId, MessageId, Result, ID,
 };
 
 /// Relative path of the folder in which Hub information files (`${ID}`) files are stored.
 pub const HUB_INFO_FOLDER: &str = "data/hubs/info/";
 /// Relative path of the folder in which Hub data files are stored (channel directories and messages).
 pub const HUB_DATA_FOLDER: &str = "data/hubs/data/";
+/// Maximum number of members returned by a single request for a hub's members, larger limits are lowered to this.
+pub const MAX_MEMBERS_PER_REQUEST: usize = 256;
+/// Maximum number of hubs that can be transferred with one request.
+pub const MAX_BULK_HUBS: usize = 100;
+
+/// Orders that [`Hub::list_members`] can list members in.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MemberSort {
+    /// By user ID.
+    #[default]
+    UserId,
+    /// Members that joined first come first.
+    Joined,
+    /// Members that joined last come first.
+    JoinedDesc,
+}
 
 /// Represents a member of a hub that maps to a user.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct HubMember {
     /// ID of the user that the hub member represents.
     pub user_id: String,
-    /// Time in milliseconds since Unix Epoch that the user became a member of the hub.
+    /// Time the user became a member of the hub.
     pub joined: DateTime<Utc>,
     /// ID of the hub that this hub member is in.
-    pub hub: ID,
+    pub hub: HubId,
     /// Groups that the hub member is part of.
     pub groups: Vec<ID>,
     /// Hub permission settings that the hub member has.
     pub hub_permissions: HubPermissions,
     /// Mapping of channel permission settings the hub member has to the channel they apply to.
-    pub channel_permissions: HashMap<ID, ChannelPermissions>,
+    pub channel_permissions: HashMap<ChannelId, ChannelPermissions>,
 }
 
 impl HubMember {
     /// Creates a new hub member based on a user and the ID of the hub they are part of.
-    pub fn new(user_id: String, hub: ID) -> Self {
+    pub fn new(user_id: String, hub: HubId) -> Self {
         Self {
             user_id,
             hub,
@@ -82,7 +107,7 @@ impl HubMember {
     /// Sets a channel permission for the hub member in the specified channel.
     pub fn set_channel_permission(
         &mut self,
-        channel: ID,
+        channel: ChannelId,
         permission: ChannelPermission,
         value: PermissionSetting,
     ) {
@@ -104,7 +129,26 @@ impl HubMember {
     }
 
     /// Checks if the hub member has the given hub permission or if they inherit it from a permission group they are in.
+    /// The decision can be changed by the registered [`crate::permission_hook::PermissionHook`].
     pub fn has_permission(&self, permission: HubPermission, hub: &Hub) -> bool {
+        let default = self.hub_grants_permission(permission, hub);
+        crate::permission_hook::hub_permission(&self.user_id, hub, permission, default)
+    }
+
+    /// Checks if the hub member has the given channel permission in the given channel or if they inherit it from a permission group they are in.
+    /// The decision can be changed by the registered [`crate::permission_hook::PermissionHook`].
+    pub fn has_channel_permission(
+        &self,
+        channel: ChannelId,
+        permission: ChannelPermission,
+        hub: &Hub,
+    ) -> bool {
+        let default = self.hub_grants_channel_permission(channel, permission, hub);
+        crate::permission_hook::channel_permission(&self.user_id, hub, channel, permission, default)
+    }
+
+    /// Checks the hub's own permission settings for [`HubMember::has_permission`].
+    fn hub_grants_permission(&self, permission: HubPermission, hub: &Hub) -> bool {
         if hub.owner == self.user_id {
             // If the user is the owner of the hub they are all powerful.
             return true;
@@ -135,10 +179,10 @@ impl HubMember {
         false
     }
 
-    /// Checks if the hub member has the given channel permission in the given channel or if they inherit it from a permission group they are in.
-    pub fn has_channel_permission(
+    /// Checks the hub's own permission settings for [`HubMember::has_channel_permission`].
+    fn hub_grants_channel_permission(
         &self,
-        channel: ID,
+        channel: ChannelId,
         permission: ChannelPermission,
         hub: &Hub,
     ) -> bool {
@@ -165,7 +209,7 @@ impl HubMember {
                         return false;
                     }
                     None => {
-                        if self.has_permission(permission.into(), hub) {
+                        if self.hub_grants_permission(permission.into(), hub) {
                             return true;
                         }
                     }
@@ -184,6 +228,19 @@ impl HubMember {
     }
 }
 
+/// Checks if permission settings explicitly deny [`ChannelPermission::ReadHistory`] for a channel, see [`Hub::history_start`].
+fn denies_history(
+    hub_permissions: &HubPermissions,
+    channel_permissions: &HashMap<ChannelId, ChannelPermissions>,
+    channel_id: ChannelId,
+) -> bool {
+    hub_permissions.get(&HubPermission::ReadHistory) == Some(&Some(false))
+        || channel_permissions
+            .get(&channel_id)
+            .and_then(|permissions| permissions.get(&ChannelPermission::ReadHistory))
+            == Some(&Some(false))
+}
+
 /// Represents a set of permissions that can be easily given to any hub member.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct PermissionGroup {
@@ -196,8 +253,8 @@ pub struct PermissionGroup {
     /// Hub permission settings that the group has.
     pub hub_permissions: HubPermissions,
     /// Mapping of channel permission settings the group has to the channel they apply to.
-    pub channel_permissions: HashMap<ID, ChannelPermissions>,
-    /// Time in milliseconds since Unix Epoch that the group was created.
+    pub channel_permissions: HashMap<ChannelId, ChannelPermissions>,
+    /// Time the group was created.
     pub created: DateTime<Utc>,
 }
 
@@ -232,7 +289,7 @@ impl PermissionGroup {
     /// Changes the setting of a channel permission for a specific channel for the group.
     pub fn set_channel_permission(
         &mut self,
-        channel_id: ID,
+        channel_id: ChannelId,
         permission: ChannelPermission,
         value: PermissionSetting,
     ) {
@@ -267,7 +324,11 @@ impl PermissionGroup {
     }
 
     /// Checks if the group has a permission in a specific channel.
-    pub fn has_channel_permission(&self, channel_id: ID, permission: ChannelPermission) -> bool {
+    pub fn has_channel_permission(
+        &self,
+        channel_id: ChannelId,
+        permission: ChannelPermission,
+    ) -> bool {
         if self.has_all_permissions() {
             return true;
         }
@@ -289,11 +350,71 @@ impl PermissionGroup {
     }
 }
 
+/// A hub to be created, as given by its owner.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct NewHub {
+    pub name: String,
+    /// Short description of the hub, empty if `None`.
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+/// Channel permission override of a permission group, set on a channel as it is created, see [`NewChannel`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "graphql", derive(InputObject))]
+pub struct GroupChannelPermission {
+    /// ID of the permission group.
+    pub group: ID,
+    pub permission: ChannelPermission,
+    pub setting: PermissionSetting,
+}
+
+/// Channel permission override of a hub member, set on a channel as it is created, see [`NewChannel`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "graphql", derive(InputObject))]
+pub struct MemberChannelPermission {
+    /// ID of the hub member.
+    pub member: String,
+    pub permission: ChannelPermission,
+    pub setting: PermissionSetting,
+}
+
+/// A channel to be created, as given by the member creating it.
+/// Everything in it is set before the channel is saved, so no client sees the channel without its description or permissions.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct NewChannel {
+    pub name: String,
+    /// Short description of the channel, empty if `None`.
+    #[serde(default)]
+    pub description: Option<String>,
+    /// If true the hub's default group can not read or write in the channel, only members and groups given access by an override can.
+    #[serde(default)]
+    pub private: bool,
+    #[serde(default)]
+    pub group_permissions: Vec<GroupChannelPermission>,
+    #[serde(default)]
+    pub member_permissions: Vec<MemberChannelPermission>,
+}
+
+impl NewChannel {
+    /// Returns true if creating the channel changes permissions, which needs [`HubPermission::Administrate`].
+    pub fn sets_permissions(&self) -> bool {
+        self.private || !self.group_permissions.is_empty() || !self.member_permissions.is_empty()
+    }
+}
+
+/// Hubs to stay in when leaving all hubs, as given by the user leaving them.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct LeaveAllHubs {
+    #[serde(default)]
+    pub exclude: Vec<HubId>,
+}
+
 /// Represents a group of users, permission groups and channels.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Hub {
     /// Map of channels to their IDs.
-    pub channels: HashMap<ID, Channel>,
+    pub channels: HashMap<ChannelId, Channel>,
     /// Map of hub members to their corresponding user's IDs.
     pub members: HashMap<String, HubMember>,
     /// List of IDs of all users that are banned from the hub.
@@ -311,14 +432,18 @@ pub struct Hub {
     /// Name of the hub.
     pub name: String,
     /// ID of the hub.
-    pub id: ID,
-    /// Time the hub was created in milliseconds since Unix Epoch.
+    pub id: HubId,
+    /// Time the hub was created.
     pub created: DateTime<Utc>,
+    /// Version of the hub, increased by one every time it is saved, see [`crate::hub_changes`].
+    /// It is stored after the rest of the hub so that hubs saved before it existed can still be loaded.
+    #[serde(skip)]
+    pub version: u64,
 }
 
 impl Hub {
     /// Creates a new hub given the ID of the user who should be the owner, the name and the ID the hub should have.
-    pub fn new(name: String, id: ID, creator: String) -> Self {
+    pub fn new(name: String, id: HubId, creator: String) -> Self {
         let mut everyone = PermissionGroup::new(String::from("everyone"), new_id());
         let mut owner = HubMember::new(creator.clone(), id);
         let mut members = HashMap::new();
@@ -339,6 +464,7 @@ impl Hub {
             channels: HashMap::new(),
             members,
             created: Utc::now(),
+            version: 0,
         }
     }
 
@@ -349,55 +475,242 @@ impl Hub {
     /// This function will return an error in the following situations, but is not
     /// limited to just these cases:
     ///
-    /// * Failed to pass [`check_name_validity`].
+    /// * Failed to pass [`validate_name`].
     /// * The user it not in the hub.
     /// * The user does not have permission create new channels.
+    /// * Another channel of the hub has the same name, see [`Hub::check_channel_name_free`].
     /// * Any of the reasons outlined in [`Channel::create_dir`].
-    pub async fn new_channel(&mut self, member_id: &str, name: String) -> Result<ID> {
-        check_name_validity(&name)?;
+    pub async fn new_channel(&mut self, member_id: &str, name: String) -> Result<ChannelId> {
+        self.new_channel_from(
+            member_id,
+            NewChannel {
+                name,
+                ..NewChannel::default()
+            },
+        )
+        .await
+    }
+
+    /// Creates a channel with the description and permission overrides given by the member creating it, see [`NewChannel`].
+    /// Everything is checked before the channel is created, so if anything is wrong the hub is left unchanged.
+    /// The creator is given permission to read the channel before the overrides are applied.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The member is not in the hub or does not have permission to create channels.
+    /// * The name or description is not valid, see [`validate_name`] and [`validate_description`].
+    /// * Another channel has the same name, [`Error::NameTaken`].
+    /// * The channel sets permissions and the member does not have permission to administrate the hub.
+    /// * A group or member an override is for is not in the hub.
+    /// * The channel's data folder could not be created.
+    pub async fn new_channel_from(
+        &mut self,
+        member_id: &str,
+        new_channel: NewChannel,
+    ) -> Result<ChannelId> {
+        let name = validate_name(NameKind::Channel, &new_channel.name)?;
+        let description = new_channel.description.clone().unwrap_or_default();
+        validate_description(DescriptionKind::Short, &description)?;
         let member = self.get_member(member_id)?;
         check_permission!(member, HubPermission::ManageChannels, self);
-        let mut id = new_id();
+        if new_channel.sets_permissions() {
+            check_permission!(member, HubPermission::Administrate, self);
+        }
+        self.check_channel_name_free(&name, None)?;
+        let default_group = new_channel.private.then_some(self.default_group);
+        for group_id in new_channel
+            .group_permissions
+            .iter()
+            .map(|over| over.group)
+            .chain(default_group)
+        {
+            if !self.groups.contains_key(&group_id) {
+                return Err(Error::GroupNotFound);
+            }
+        }
+        for over in &new_channel.member_permissions {
+            self.get_member(&over.member)?;
+        }
+        let mut id = ChannelId::random();
         while self.channels.contains_key(&id) {
-            id = new_id();
+            id = ChannelId::random();
         }
-        let channel = Channel::new(name, id, self.id);
+        let mut channel = Channel::new(name, id, self.id);
+        channel.description = description;
         channel.create_dir().await?;
-        {
-            self.get_member_mut(member_id)?.set_channel_permission(
-                channel.id,
-                ChannelPermission::Read,
-                Some(true),
+        self.get_member_mut(member_id)?.set_channel_permission(
+            id,
+            ChannelPermission::Read,
+            Some(true),
+        );
+        if let Some(group) = default_group.and_then(|group| self.groups.get_mut(&group)) {
+            group.set_channel_permission(id, ChannelPermission::Read, Some(false));
+            group.set_channel_permission(id, ChannelPermission::Write, Some(false));
+        }
+        for over in new_channel.group_permissions {
+            if let Some(group) = self.groups.get_mut(&over.group) {
+                group.set_channel_permission(id, over.permission, over.setting);
+            }
+        }
+        for over in new_channel.member_permissions {
+            self.get_member_mut(&over.member)?.set_channel_permission(
+                id,
+                over.permission,
+                over.setting,
             );
         }
         self.channels.insert(id, channel);
         Ok(id)
     }
 
-    /// Gets a reference to the channel.
-    /// Returns an error if the channel could not be found or the user did not have permission to view the channel.
-    pub fn get_channel(&self, member_id: &str, channel_id: ID) -> Result<&Channel> {
-        let member = self.get_member(member_id)?;
-        check_permission!(member, channel_id, ChannelPermission::Read, self);
-        if let Some(channel) = self.channels.get(&channel_id) {
-            Ok(channel)
+    /// Checks that no channel of the hub other than `except` has a name that only differs from `name` in case (see [`fold_name`]).
+    /// Always succeeds if duplicate channel names are allowed, see [`crate::config::NamesConfig::allow_duplicate_channel_names`].
+    ///
+    /// # Errors
+    ///
+    /// This function returns [`Error::NameTaken`] if another channel has the name.
+    pub fn check_channel_name_free(&self, name: &str, except: Option<ChannelId>) -> Result {
+        if !unique_channel_names() {
+            return Ok(());
+        }
+        let folded = fold_name(name);
+        let taken = self
+            .channels
+            .values()
+            .any(|channel| Some(channel.id) != except && fold_name(&channel.name) == folded);
+        if taken {
+            Err(Error::NameTaken)
         } else {
-            Err(Error::ChannelNotFound)
+            Ok(())
         }
     }
 
-    /// Gets a mutable reference to the channel.
-    /// Returns an error if the channel could not be found or the user did not have permission to view the channel.
-    pub fn get_channel_mut(&mut self, member_id: &str, channel_id: ID) -> Result<&mut Channel> {
+    /// Gets the groups of channels whose names only differ in case, for hubs from before channel names had to be unique. Each group is sorted by ID.
+    pub fn duplicate_channel_names(&self) -> Vec<Vec<&Channel>> {
+        let mut by_name: HashMap<String, Vec<&Channel>> = HashMap::new();
+        for channel in self.channels.values() {
+            by_name
+                .entry(fold_name(&channel.name))
+                .or_default()
+                .push(channel);
+        }
+        let mut duplicates: Vec<Vec<&Channel>> = by_name
+            .into_values()
+            .filter(|channels| channels.len() > 1)
+            .map(|mut channels| {
+                channels.sort_by_key(|channel| channel.id);
+                channels
+            })
+            .collect();
+        duplicates.sort_by_key(|channels| channels[0].id);
+        duplicates
+    }
+
+    /// Checks if a member can read a channel: the channel has to exist and the member needs the [`ChannelPermission::Read`] permission for it.
+    /// Everything that gives access to the messages of a channel (getting messages, searching, subscribing) goes through this check.
+    pub fn can_read_channel(&self, member: &HubMember, channel_id: ChannelId) -> bool {
+        self.channels.contains_key(&channel_id)
+            && member.has_channel_permission(channel_id, ChannelPermission::Read, self)
+    }
+
+    /// Checks that the user with the given ID can read a channel, see [`Hub::can_read_channel`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations:
+    ///
+    /// * The user is not in the hub.
+    /// * The channel could not be found.
+    /// * The user does not have permission to read the channel.
+    pub fn check_can_read_channel(&self, member_id: &str, channel_id: ChannelId) -> Result {
         let member = self.get_member(member_id)?;
-        check_permission!(member, channel_id, ChannelPermission::Read, self);
-        if let Some(channel) = self.channels.get_mut(&channel_id) {
-            Ok(channel)
+        if !self.channels.contains_key(&channel_id) {
+            return Err(Error::ChannelNotFound);
+        }
+        if !self.can_read_channel(member, channel_id) {
+            return Err(Error::MissingChannelPermission(ChannelPermission::Read));
+        }
+        Ok(())
+    }
+
+    /// Gets the time from which a member can read the messages of a channel, `None` if they can read its whole history.
+    /// Members that are denied [`ChannelPermission::ReadHistory`] (hub wide or for the channel, directly or through one of their groups) and are not granted it only see messages sent after they joined the hub.
+    /// Every read of stored messages (getting messages, context, search) is limited by this, new messages are delivered to subscribers as usual.
+    pub fn history_start(
+        &self,
+        member: &HubMember,
+        channel_id: ChannelId,
+    ) -> Option<DateTime<Utc>> {
+        let denied = denies_history(
+            &member.hub_permissions,
+            &member.channel_permissions,
+            channel_id,
+        ) || member
+            .groups
+            .iter()
+            .filter_map(|group| self.groups.get(group))
+            .any(|group| {
+                denies_history(
+                    &group.hub_permissions,
+                    &group.channel_permissions,
+                    channel_id,
+                )
+            });
+        if denied
+            && !member.has_channel_permission(channel_id, ChannelPermission::ReadHistory, self)
+        {
+            Some(member.joined)
         } else {
-            Err(Error::ChannelNotFound)
+            None
         }
     }
 
+    /// Gets a reference to the channel.
+    /// Returns an error if the channel could not be found or the user did not have permission to view the channel.
+    pub fn get_channel(&self, member_id: &str, channel_id: ChannelId) -> Result<&Channel> {
+        self.check_can_read_channel(member_id, channel_id)?;
+        self.channels.get(&channel_id).ok_or(Error::ChannelNotFound)
+    }
+
+    /// Gets a stored message of a channel that the user with the given ID can read, messages sent before their [`Hub::history_start`] are not found.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations:
+    ///
+    /// * The user can not read the channel for any of the reasons outlined by [`Hub::check_can_read_channel`].
+    /// * The message does not exist or was sent before the user can read the history of the channel.
+    pub async fn get_readable_message(
+        &self,
+        member_id: &str,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) -> Result<SignedMessage> {
+        let channel = self.get_channel(member_id, channel_id)?;
+        let start = self.history_start(self.get_member(member_id)?, channel_id);
+        channel
+            .get_message(message_id)
+            .await
+            .filter(|message| start.is_none_or(|start| message.created >= start))
+            .ok_or(Error::MessageNotFound)
+    }
+
+    /// Gets a mutable reference to the channel.
+    /// Returns an error if the channel could not be found or the user did not have permission to view the channel.
+    pub fn get_channel_mut(
+        &mut self,
+        member_id: &str,
+        channel_id: ChannelId,
+    ) -> Result<&mut Channel> {
+        self.check_can_read_channel(member_id, channel_id)?;
+        self.channels
+            .get_mut(&channel_id)
+            .ok_or(Error::ChannelNotFound)
+    }
+
     /// Checks if the user with the given ID is in the hub.
     pub fn is_member(&self, member_id: &str) -> bool {
         self.members.contains_key(member_id)
@@ -434,6 +747,33 @@ impl Hub {
         }
     }
 
+    /// Lists the members of the hub in the given order, members that joined at the same time are ordered by user ID.
+    /// Only members in `group` (if given) whose user ID starts with `prefix` (ignoring case) are included.
+    pub fn list_members(
+        &self,
+        sort: MemberSort,
+        group: Option<ID>,
+        prefix: &str,
+    ) -> Vec<&HubMember> {
+        let prefix = prefix.to_uppercase();
+        let mut members: Vec<&HubMember> = self
+            .members
+            .values()
+            .filter(|member| group.is_none_or(|group| member.groups.contains(&group)))
+            .filter(|member| member.user_id.to_uppercase().starts_with(&prefix))
+            .collect();
+        match sort {
+            MemberSort::UserId => members.sort_by(|a, b| a.user_id.cmp(&b.user_id)),
+            MemberSort::Joined => {
+                members.sort_by(|a, b| a.joined.cmp(&b.joined).then(a.user_id.cmp(&b.user_id)))
+            }
+            MemberSort::JoinedDesc => {
+                members.sort_by(|a, b| b.joined.cmp(&a.joined).then(a.user_id.cmp(&b.user_id)))
+            }
+        }
+        members
+    }
+
     /// Changes the description of a channel while checking that the given user has permission to do so.
     ///
     /// # Errors
@@ -441,7 +781,7 @@ impl Hub {
     /// This function will return an error in the following situations, but is not
     /// limited to just these cases:
     ///
-    /// * Description is bigger than [`crate::MAX_DESCRIPTION_SIZE`].
+    /// * The description is longer than allowed, see [`validate_description`].
     /// * The user it not in the hub.
     /// * The user does not have permission to view the channel.
     /// * The user does not have permission to configure the channel.
@@ -449,12 +789,11 @@ impl Hub {
     pub async fn change_channel_description(
         &mut self,
         user_id: &str,
-        channel_id: ID,
+        channel_id: ChannelId,
         new_description: String,
     ) -> Result<String> {
-        if new_description.as_bytes().len() > crate::MAX_DESCRIPTION_SIZE {
-            Err(Error::TooBig)
-        } else if let Some(user) = self.members.get(user_id) {
+        validate_description(DescriptionKind::Short, &new_description)?;
+        if let Some(user) = self.members.get(user_id) {
             check_permission!(user, channel_id, ChannelPermission::Manage, self);
             if let Some(channel) = self.channels.get_mut(&channel_id) {
                 Ok(mem::replace(&mut channel.description, new_description))
@@ -473,20 +812,22 @@ impl Hub {
     /// This function will return an error in the following situations, but is not
     /// limited to just these cases:
     ///
-    /// * Failed to pass [`check_name_validity`].
+    /// * Failed to pass [`validate_name`].
     /// * The user it not in the hub.
     /// * The user does not have permission to view the channel.
     /// * The user does not have permission to configure the channel.
     /// * The channel does not exist.
+    /// * Another channel of the hub has the same name, see [`Hub::check_channel_name_free`].
     pub async fn rename_channel(
         &mut self,
         user_id: &str,
-        channel_id: ID,
+        channel_id: ChannelId,
         new_name: String,
     ) -> Result<String> {
-        check_name_validity(&new_name)?;
+        let new_name = validate_name(NameKind::Channel, &new_name)?;
         if let Some(user) = self.members.get(user_id) {
             check_permission!(user, channel_id, ChannelPermission::Manage, self);
+            self.check_channel_name_free(&new_name, Some(channel_id))?;
             if let Some(channel) = self.channels.get_mut(&channel_id) {
                 Ok(mem::replace(&mut channel.name, new_name))
             } else {
@@ -497,7 +838,8 @@ impl Hub {
         }
     }
 
-    /// Deletes a channel while checking that the given user has permission to do so.
+    /// Deletes a channel while checking that the given user has permission to do so, returning the deleted channel.
+    /// The channel permission overrides of every member and group for the channel are removed with it, so a channel that later gets the same ID does not inherit them.
     ///
     /// # Errors
     ///
@@ -508,50 +850,69 @@ impl Hub {
     /// * The channel does not exist.
     /// * THe user does not have permission to view the channel.
     /// * The user does not have permission to delete the channel.
-    pub async fn delete_channel(&mut self, user_id: &str, channel_id: ID) -> Result {
+    pub async fn delete_channel(
+        &mut self,
+        user_id: &str,
+        channel_id: ChannelId,
+    ) -> Result<Channel> {
         if let Some(user) = self.members.get(user_id) {
             check_permission!(user, HubPermission::ManageChannels, self);
-            if self.channels.remove(&channel_id).is_some() {
-                Ok(())
-            } else {
-                Err(Error::ChannelNotFound)
+            let channel = self
+                .channels
+                .remove(&channel_id)
+                .ok_or(Error::ChannelNotFound)?;
+            for member in self.members.values_mut() {
+                member.channel_permissions.remove(&channel_id);
+            }
+            for group in self.groups.values_mut() {
+                group.channel_permissions.remove(&channel_id);
             }
+            Ok(channel)
         } else {
             Err(Error::NotInHub)
         }
     }
 
     /// Gets the file path to be used for storing the hub's data.
-    pub fn get_info_path(&self) -> String {
-        format!("{}{:x}", HUB_INFO_FOLDER, self.id.as_u128())
+    pub fn get_info_path(&self) -> PathBuf {
+        crate::paths::hub_info_file(self.id)
     }
 
     /// Gets the path of the directory in which channel folders should be stored.
-    pub fn get_data_path(&self) -> String {
-        format!("{}{:x}/", HUB_DATA_FOLDER, self.id.as_u128())
+    pub fn get_data_path(&self) -> PathBuf {
+        crate::paths::hub_data_dir(self.id)
     }
 
-    /// Saves the hub's data to disk.
+    /// Saves the hub's data to disk, increasing its version by one.
+    /// Changes to a hub have to be made while its lock is held (see [`crate::hub_changes::lock`]), from loading the hub until it is saved.
     ///
     /// # Errors
     ///
     /// This function will return an error in the following situations, but is not
     /// limited to just these cases:
     ///
-    /// * The hub data could not be serialized.
+    /// * The hub data could not be serialized or encrypted.
     /// * The hub info folder does not exist and could not be created.
     /// * The data could not be written to the disk.
-    pub async fn save(&self) -> Result {
-        tokio::fs::create_dir_all(HUB_INFO_FOLDER).await?;
+    pub async fn save(&mut self) -> Result {
+        tokio::fs::create_dir_all(HUB_INFO_FOLDER)
+            .await
+            .with_path(HUB_INFO_FOLDER)?;
+        let path = self.get_info_path();
         let mut file = tokio::fs::OpenOptions::new()
             .create(true)
             .write(true)
-            .open(self.get_info_path())
-            .await?;
-        let bytes = bincode::serialize(self)?;
+            .truncate(true)
+            .open(&path)
+            .await
+            .with_path(&path)?;
+        self.version += 1;
+        let mut bytes = bincode::serialize(self)?;
+        bytes.append(&mut bincode::serialize(&self.version)?);
+        let bytes = crate::encryption::seal(bytes)?;
         let mut buf: &[u8] = bytes.as_slice();
-        file.write_buf(&mut buf).await?;
-        file.flush().await?;
+        file.write_buf(&mut buf).await.with_path(&path)?;
+        file.flush().await.with_path(path)?;
         Ok(())
     }
 
@@ -563,17 +924,41 @@ impl Hub {
     /// limited to just these cases:
     ///
     /// * There is no hub with that ID.
+    /// * The hub's data file is encrypted and could not be decrypted, see [`crate::encryption::open`].
     /// * The hub's data file was corrupt and could not be deserialized.
-    pub async fn load(id: ID) -> Result<Self> {
-        let filename = format!("{}{:x}", HUB_INFO_FOLDER, id.as_u128());
-        let path = std::path::Path::new(&filename);
+    pub async fn load(id: HubId) -> Result<Self> {
+        let path = &crate::paths::hub_info_file(id);
         if !path.exists() {
             return Err(Error::HubNotFound);
         }
-        let mut file = tokio::fs::OpenOptions::new().read(true).open(path).await?;
+        let mut file = tokio::fs::OpenOptions::new()
+            .read(true)
+            .open(path)
+            .await
+            .with_path(path)?;
         let mut buf = Vec::new();
-        file.read_to_end(&mut buf).await?;
-        Ok(bincode::deserialize(&buf)?)
+        file.read_to_end(&mut buf).await.with_path(path)?;
+        let bytes = crate::encryption::open(&buf)?;
+        let mut remaining = &bytes[..];
+        let mut hub: Hub = bincode::deserialize_from(&mut remaining)?;
+        hub.version = if remaining.is_empty() {
+            crate::hub_changes::HubChanges::load(id).await?.version
+        } else {
+            bincode::deserialize(remaining)?
+        };
+        Ok(hub)
+    }
+
+    /// Checks that the hub is at the version a client expects it to be at (for example from an `If-Match` header), any version is accepted if `expected` is `None`.
+    ///
+    /// # Errors
+    ///
+    /// This function returns [`Error::VersionMismatch`] with the current version if it is not the expected one.
+    pub fn check_version(&self, expected: Option<u64>) -> Result {
+        match expected {
+            Some(expected) if expected != self.version => Err(Error::VersionMismatch(self.version)),
+            _ => Ok(()),
+        }
     }
 
     /// Adds a user to a hub, creating and returning the resulting hub member.
@@ -595,6 +980,32 @@ impl Hub {
         }
     }
 
+    /// Makes another member the owner of the hub. The previous owner stays a member but loses the `All` permission they were given when they became the owner.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The user already owns the hub, [`Error::AlreadyOwner`].
+    /// * The user is banned from the hub, [`Error::Banned`].
+    /// * The user is not a member of the hub, [`Error::MemberNotFound`].
+    pub fn transfer_ownership(&mut self, new_owner: &str) -> Result {
+        if self.owner == new_owner {
+            return Err(Error::AlreadyOwner);
+        }
+        if self.bans.contains(new_owner) {
+            return Err(Error::Banned);
+        }
+        self.get_member_mut(new_owner)?
+            .set_permission(HubPermission::All, Some(true));
+        let previous = std::mem::replace(&mut self.owner, new_owner.to_string());
+        if let Ok(member) = self.get_member_mut(&previous) {
+            member.set_permission(HubPermission::All, None);
+        }
+        Ok(())
+    }
+
     /// Removes the given user from the hub.
     ///
     /// # Errors
@@ -667,21 +1078,30 @@ impl Hub {
     /// # Errors
     ///
     /// This function will only return an error if the given user is not in the hub.
-    pub fn get_channels_for_user(&self, user_id: &str) -> Result<HashMap<ID, Channel>> {
-        let hub_im = self.clone();
+    pub fn get_channels_for_user(&self, user_id: &str) -> Result<HashMap<ChannelId, Channel>> {
         if let Some(user) = self.members.get(user_id) {
-            let mut result = HashMap::new();
-            for channel in self.channels.clone() {
-                if user.has_channel_permission(channel.0, ChannelPermission::Read, &hub_im) {
-                    result.insert(channel.0, channel.1.clone());
-                }
-            }
-            Ok(result)
+            Ok(self
+                .channels
+                .iter()
+                .filter(|(id, _)| self.can_read_channel(user, **id))
+                .map(|(id, channel)| (*id, channel.clone()))
+                .collect())
         } else {
             Err(Error::MemberNotFound)
         }
     }
 
+    /// Copies the channel permission overrides of every member and group from one channel to another.
+    /// The overrides of the target channel are replaced, unless `merge` is true, then only the permissions set in the source channel are replaced.
+    pub fn copy_channel_permissions(&mut self, source: ChannelId, target: ChannelId, merge: bool) {
+        for member in self.members.values_mut() {
+            copy_overrides(&mut member.channel_permissions, source, target, merge);
+        }
+        for group in self.groups.values_mut() {
+            copy_overrides(&mut group.channel_permissions, source, target, merge);
+        }
+    }
+
     /// Returns a hub object with only the items that the given user is allowed to view.
     /// Only hides channels that the user does not have permission to view.
     ///
@@ -695,19 +1115,284 @@ impl Hub {
     }
 }
 
+/// Copies the overrides of one channel to another in the channel permissions of a member or group, see [`Hub::copy_channel_permissions`].
+fn copy_overrides(
+    channel_permissions: &mut HashMap<ChannelId, ChannelPermissions>,
+    source: ChannelId,
+    target: ChannelId,
+    merge: bool,
+) {
+    let overrides = channel_permissions
+        .get(&source)
+        .cloned()
+        .unwrap_or_default();
+    if merge {
+        if !overrides.is_empty() {
+            channel_permissions
+                .entry(target)
+                .or_default()
+                .extend(overrides);
+        }
+    } else if overrides.is_empty() {
+        channel_permissions.remove(&target);
+    } else {
+        channel_permissions.insert(target, overrides);
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::{Hub, ID};
+    use chrono::Duration;
+
+    use super::{Channel, Hub, HubId, HubMember, MemberChannelPermission, MemberSort, NewChannel};
+    use crate::{
+        error::Error,
+        permission::{ChannelPermission, HubPermission},
+        ChannelId,
+    };
 
     #[tokio::test]
     async fn save_load() {
-        let id = ID::nil();
+        let id = HubId::nil();
         let mut hub = Hub::new("test_hub".to_string(), id, id.to_string());
         let _ = tokio::fs::remove_file(&hub.get_info_path()).await;
         hub.new_channel(&id.to_string(), "test_channel".to_string())
             .await
             .expect("Failed to add a channel to the test hub.");
         hub.save().await.expect("Failed to save the hub.");
-        Hub::load(hub.id).await.expect("Failed to load the hub.");
+        assert_eq!(hub.version, 1);
+        let loaded = Hub::load(hub.id).await.expect("Failed to load the hub.");
+        assert_eq!(loaded.version, 1);
+
+        // Hubs saved before the version was stored with them are at the version of their recorded changes.
+        tokio::fs::write(hub.get_info_path(), bincode::serialize(&hub).unwrap())
+            .await
+            .unwrap();
+        let legacy = Hub::load(hub.id).await.expect("Failed to load the hub.");
+        assert_eq!(legacy.name, hub.name);
+        assert_eq!(
+            legacy.version,
+            crate::hub_changes::HubChanges::load(hub.id)
+                .await
+                .unwrap()
+                .version
+        );
+    }
+
+    #[tokio::test]
+    async fn new_channel_is_configured_before_it_exists() {
+        let owner = "AA01".to_string();
+        let mut hub = Hub::new("hub".to_string(), HubId::from_u128(0xc4a), owner.clone());
+        hub.user_join("AB02".to_string()).unwrap();
+        hub.user_join("BB03".to_string()).unwrap();
+        let grant = |member: &str| MemberChannelPermission {
+            member: member.to_string(),
+            permission: ChannelPermission::Read,
+            setting: Some(true),
+        };
+        let invalid = NewChannel {
+            name: "staff".to_string(),
+            private: true,
+            member_permissions: vec![grant("AB02"), grant("CC04")],
+            ..NewChannel::default()
+        };
+        assert!(matches!(
+            hub.new_channel_from(&owner, invalid).await,
+            Err(Error::MemberNotFound)
+        ));
+        assert!(hub.channels.is_empty());
+
+        let channel_id = hub
+            .new_channel_from(
+                &owner,
+                NewChannel {
+                    name: "staff".to_string(),
+                    description: Some("Staff only.".to_string()),
+                    private: true,
+                    member_permissions: vec![grant("AB02")],
+                    ..NewChannel::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(hub.channels[&channel_id].description, "Staff only.");
+        assert!(hub.check_can_read_channel(&owner, channel_id).is_ok());
+        assert!(hub.check_can_read_channel("AB02", channel_id).is_ok());
+        assert!(hub.check_can_read_channel("BB03", channel_id).is_err());
+        // Only administrators can set permissions while creating a channel.
+        assert!(hub
+            .new_channel_from(
+                "AB02",
+                NewChannel {
+                    name: "mine".to_string(),
+                    private: true,
+                    ..NewChannel::default()
+                },
+            )
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn list_members() {
+        let mut hub = Hub::new("hub".to_string(), HubId::from_u128(1), "AA01".to_string());
+        let group = crate::new_id();
+        let owner_joined = hub.members["AA01"].joined;
+        for (n, user_id) in ["AB02", "BB03"].iter().enumerate() {
+            let mut member = HubMember::new(user_id.to_string(), hub.id);
+            member.joined = owner_joined + Duration::minutes(n as i64 + 1);
+            hub.members.insert(user_id.to_string(), member);
+        }
+        hub.members.get_mut("BB03").unwrap().groups.push(group);
+        let ids = |sort, group, prefix| {
+            hub.list_members(sort, group, prefix)
+                .iter()
+                .map(|member| member.user_id.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ids(MemberSort::JoinedDesc, None, ""),
+            vec!["BB03", "AB02", "AA01"]
+        );
+        assert_eq!(ids(MemberSort::UserId, None, "a"), vec!["AA01", "AB02"]);
+        assert_eq!(ids(MemberSort::Joined, Some(group), ""), vec!["BB03"]);
+    }
+
+    #[test]
+    fn copy_channel_permissions() {
+        let mut hub = Hub::new("hub".to_string(), HubId::from_u128(1), "AA01".to_string());
+        let (source, target) = (ChannelId::from_u128(1), ChannelId::from_u128(2));
+        let member = hub.members.get_mut("AA01").unwrap();
+        member.set_channel_permission(source, ChannelPermission::Write, Some(false));
+        member.set_channel_permission(target, ChannelPermission::Write, Some(true));
+        member.set_channel_permission(target, ChannelPermission::Read, Some(true));
+        let default_group = hub.default_group;
+        hub.groups
+            .get_mut(&default_group)
+            .unwrap()
+            .set_channel_permission(target, ChannelPermission::Read, Some(false));
+        let overrides = |hub: &Hub| {
+            let member = &hub.members["AA01"].channel_permissions;
+            let group = &hub.groups[&default_group].channel_permissions;
+            (member.get(&target).cloned(), group.get(&target).cloned())
+        };
+
+        let mut merged = hub.clone();
+        merged.copy_channel_permissions(source, target, true);
+        let (member, group) = overrides(&merged);
+        let member = member.unwrap();
+        assert_eq!(member[&ChannelPermission::Write], Some(false));
+        assert_eq!(member[&ChannelPermission::Read], Some(true));
+        // The group has nothing set in the source channel, so its own override is kept.
+        assert_eq!(group.unwrap()[&ChannelPermission::Read], Some(false));
+
+        hub.copy_channel_permissions(source, target, false);
+        let (member, group) = overrides(&hub);
+        assert_eq!(member.unwrap().len(), 1);
+        assert!(group.is_none());
+    }
+
+    #[tokio::test]
+    async fn delete_channel_removes_its_overrides() {
+        let mut hub = Hub::new("hub".to_string(), HubId::from_u128(1), "AA01".to_string());
+        let (deleted, kept) = (ChannelId::from_u128(1), ChannelId::from_u128(2));
+        for id in [deleted, kept].iter() {
+            hub.channels
+                .insert(*id, Channel::new("channel".to_string(), *id, hub.id));
+        }
+        let default_group = hub.default_group;
+        for id in [deleted, kept].iter() {
+            hub.members.get_mut("AA01").unwrap().set_channel_permission(
+                *id,
+                ChannelPermission::Write,
+                Some(false),
+            );
+            hub.groups
+                .get_mut(&default_group)
+                .unwrap()
+                .set_channel_permission(*id, ChannelPermission::Read, Some(false));
+        }
+        hub.delete_channel("AA01", deleted).await.unwrap();
+        let member = &hub.members["AA01"].channel_permissions;
+        let group = &hub.groups[&default_group].channel_permissions;
+        assert!(!member.contains_key(&deleted));
+        assert!(!group.contains_key(&deleted));
+        assert!(member.contains_key(&kept));
+        assert!(group.contains_key(&kept));
+    }
+    #[test]
+    fn history_start_when_denied() {
+        let mut hub = Hub::new("hub".to_string(), HubId::from_u128(1), "AA01".to_string());
+        let channel = ChannelId::from_u128(1);
+        let joined = hub.user_join("BB02".to_string()).unwrap().joined;
+        let start = |hub: &Hub, user_id| hub.history_start(&hub.members[user_id], channel);
+        assert_eq!(start(&hub, "BB02"), None);
+        let default_group = hub.default_group;
+        hub.groups
+            .get_mut(&default_group)
+            .unwrap()
+            .set_channel_permission(channel, ChannelPermission::ReadHistory, Some(false));
+        assert_eq!(start(&hub, "BB02"), Some(joined));
+        // The owner can read everything.
+        assert_eq!(start(&hub, "AA01"), None);
+        hub.get_member_mut("BB02").unwrap().set_channel_permission(
+            channel,
+            ChannelPermission::ReadHistory,
+            Some(true),
+        );
+        assert_eq!(start(&hub, "BB02"), None);
+    }
+
+    #[tokio::test]
+    async fn channel_names_are_unique_ignoring_case() {
+        let owner = "AA01".to_string();
+        let mut hub = Hub::new("hub".to_string(), HubId::from_u128(0xca5e), owner.clone());
+        let general = hub
+            .new_channel(&owner, "General".to_string())
+            .await
+            .unwrap();
+        for name in ["general", "GENERAL"].iter() {
+            assert!(matches!(
+                hub.new_channel(&owner, name.to_string()).await,
+                Err(Error::NameTaken)
+            ));
+        }
+        let other = hub.new_channel(&owner, "other".to_string()).await.unwrap();
+        assert!(matches!(
+            hub.rename_channel(&owner, other, "GENERAL".to_string())
+                .await,
+            Err(Error::NameTaken)
+        ));
+        // Changing the case of a channel's own name is fine.
+        hub.rename_channel(&owner, general, "GENERAL".to_string())
+            .await
+            .unwrap();
+        assert!(hub.duplicate_channel_names().is_empty());
+        hub.channels.get_mut(&other).unwrap().name = "general".to_string();
+        assert_eq!(hub.duplicate_channel_names().len(), 1);
+    }
+
+    #[test]
+    fn ownership_goes_to_members_only() {
+        let mut hub = Hub::new(
+            "hub".to_string(),
+            HubId::from_u128(0x0ee),
+            "AA01".to_string(),
+        );
+        hub.user_join("BB02".to_string()).unwrap();
+        assert!(matches!(
+            hub.transfer_ownership("AA01"),
+            Err(Error::AlreadyOwner)
+        ));
+        assert!(matches!(
+            hub.transfer_ownership("CC03"),
+            Err(Error::MemberNotFound)
+        ));
+        hub.transfer_ownership("BB02").unwrap();
+        assert_eq!(hub.owner, "BB02");
+        let previous = hub.get_member("AA01").unwrap();
+        assert!(!previous.has_permission(HubPermission::ManageChannels, &hub));
+        let owner = hub.get_member("BB02").unwrap();
+        assert!(owner.has_permission(HubPermission::ManageChannels, &hub));
     }
 }
@@ -0,0 +1,444 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::IoContext, membership_log::MembershipEventKind, HubId, Result};
+
+/// Folder where the activity statistics of each hub are stored.
+pub const HUB_ACTIVITY_FOLDER: &str = "data/hubs/activity/";
+
+/// Number of days of activity kept for each hub.
+pub const MAX_ACTIVITY_DAYS: u32 = 90;
+
+/// Seconds between flushes of the in memory counters to the activity files.
+pub const ACTIVITY_FLUSH_INTERVAL: u64 = 60;
+
+/// Counters of each hub that had activity since the server started.
+static COUNTERS: Mutex<Option<HashMap<HubId, Arc<HubCounters>>>> = Mutex::new(None);
+
+/// In memory activity counters of a hub, the pending counts are moved to the hub's [`HubActivity`] by [`flush`] while the totals only ever grow.
+#[derive(Default)]
+struct HubCounters {
+    messages: AtomicU64,
+    joins: AtomicU64,
+    leaves: AtomicU64,
+    senders: Mutex<HashSet<String>>,
+    total_messages: AtomicU64,
+    total_joins: AtomicU64,
+    total_leaves: AtomicU64,
+}
+
+impl HubCounters {
+    /// Takes the pending counts, leaving them at zero.
+    fn take(&self) -> PendingActivity {
+        PendingActivity {
+            messages: self.messages.swap(0, Ordering::Relaxed),
+            joins: self.joins.swap(0, Ordering::Relaxed),
+            leaves: self.leaves.swap(0, Ordering::Relaxed),
+            senders: std::mem::take(
+                &mut *self.senders.lock().unwrap_or_else(|err| err.into_inner()),
+            ),
+        }
+    }
+
+    /// Copies the pending counts without resetting them.
+    fn peek(&self) -> PendingActivity {
+        PendingActivity {
+            messages: self.messages.load(Ordering::Relaxed),
+            joins: self.joins.load(Ordering::Relaxed),
+            leaves: self.leaves.load(Ordering::Relaxed),
+            senders: self
+                .senders
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .clone(),
+        }
+    }
+}
+
+/// Activity of a hub that has not been written to its activity file yet.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PendingActivity {
+    pub messages: u64,
+    pub joins: u64,
+    pub leaves: u64,
+    /// IDs of the users that sent messages.
+    pub senders: HashSet<String>,
+}
+
+impl PendingActivity {
+    fn is_empty(&self) -> bool {
+        self.messages == 0 && self.joins == 0 && self.leaves == 0 && self.senders.is_empty()
+    }
+}
+
+/// Gets the counters of a hub, creating them if the hub had no activity yet.
+fn counters(hub_id: HubId) -> Arc<HubCounters> {
+    COUNTERS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .get_or_insert_with(HashMap::new)
+        .entry(hub_id)
+        .or_default()
+        .clone()
+}
+
+/// Counts a message sent in a hub.
+pub fn record_message(hub_id: HubId, sender: &str) {
+    let counters = counters(hub_id);
+    counters.messages.fetch_add(1, Ordering::Relaxed);
+    counters.total_messages.fetch_add(1, Ordering::Relaxed);
+    counters
+        .senders
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .insert(sender.to_string());
+}
+
+/// Counts a change to the membership of a hub, kicks and bans count as leaves.
+pub fn record_membership(hub_id: HubId, kind: &MembershipEventKind) {
+    let counters = counters(hub_id);
+    if *kind == MembershipEventKind::Joined {
+        counters.joins.fetch_add(1, Ordering::Relaxed);
+        counters.total_joins.fetch_add(1, Ordering::Relaxed);
+    } else {
+        counters.leaves.fetch_add(1, Ordering::Relaxed);
+        counters.total_leaves.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Forgets the in memory counters of a hub, used when the hub is deleted.
+pub fn forget(hub_id: HubId) {
+    if let Some(counters) = COUNTERS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .as_mut()
+    {
+        counters.remove(&hub_id);
+    }
+}
+
+/// Activity of a hub in one hour.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HourActivity {
+    /// Start of the hour.
+    pub hour: DateTime<Utc>,
+    pub messages: u64,
+    pub joins: u64,
+    pub leaves: u64,
+}
+
+/// Number of users that sent messages in a hub in one UTC day.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DaySenders {
+    /// Start of the day.
+    pub day: DateTime<Utc>,
+    pub active_senders: u64,
+}
+
+/// Activity statistics of a hub, stored separately from the hub itself.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct HubActivity {
+    /// Activity per hour, oldest first, hours without activity are left out.
+    pub hours: Vec<HourActivity>,
+    /// Active senders per day, oldest first, days without messages are left out.
+    pub days: Vec<DaySenders>,
+    /// IDs of the users that sent messages on the last day in `days`, so that they are only counted once.
+    today_senders: HashSet<String>,
+}
+
+/// Truncates a time to the start of its hour.
+fn start_of_hour(time: DateTime<Utc>) -> DateTime<Utc> {
+    let seconds = time.timestamp();
+    DateTime::<Utc>::from(std::time::UNIX_EPOCH)
+        + chrono::Duration::seconds(seconds - seconds.rem_euclid(3600))
+}
+
+/// Truncates a time to the start of its UTC day.
+fn start_of_day(time: DateTime<Utc>) -> DateTime<Utc> {
+    let seconds = time.timestamp();
+    DateTime::<Utc>::from(std::time::UNIX_EPOCH)
+        + chrono::Duration::seconds(seconds - seconds.rem_euclid(86400))
+}
+
+impl HubActivity {
+    /// Gets the path of the file that a hub's activity is stored in.
+    pub fn get_path(hub_id: HubId) -> PathBuf {
+        crate::paths::hub_activity_file(hub_id)
+    }
+
+    /// Loads the activity of a hub, a hub without the file had no activity.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The file exists but could not be read.
+    /// * The file's contents could not be deserialized.
+    pub async fn load(hub_id: HubId) -> Result<Self> {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(bincode::deserialize(&bytes)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_path(path),
+        }
+    }
+
+    /// Saves the activity of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The activity folder does not exist and could not be created.
+    /// * The data could not be written to the disk.
+    pub async fn save(&self, hub_id: HubId) -> Result {
+        tokio::fs::create_dir_all(HUB_ACTIVITY_FOLDER)
+            .await
+            .with_path(HUB_ACTIVITY_FOLDER)?;
+        let path = Self::get_path(hub_id);
+        tokio::fs::write(&path, bincode::serialize(self)?)
+            .await
+            .with_path(path)
+    }
+
+    /// Removes the activity of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the file exists but could not be removed.
+    pub async fn remove(hub_id: HubId) -> Result {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::remove_file(&path).await {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result.with_path(path),
+        }
+    }
+
+    /// Adds pending activity to the hour and day of `now`, dropping activity older than [`MAX_ACTIVITY_DAYS`].
+    pub fn merge(&mut self, now: DateTime<Utc>, pending: PendingActivity) {
+        let hour = start_of_hour(now);
+        if pending.messages != 0 || pending.joins != 0 || pending.leaves != 0 {
+            if self.hours.last().is_none_or(|last| last.hour != hour) {
+                self.hours.push(HourActivity {
+                    hour,
+                    messages: 0,
+                    joins: 0,
+                    leaves: 0,
+                });
+            }
+            if let Some(last) = self.hours.last_mut() {
+                last.messages += pending.messages;
+                last.joins += pending.joins;
+                last.leaves += pending.leaves;
+            }
+        }
+        let day = start_of_day(now);
+        if !pending.senders.is_empty() {
+            if self.days.last().is_none_or(|last| last.day != day) {
+                self.days.push(DaySenders {
+                    day,
+                    active_senders: 0,
+                });
+                self.today_senders.clear();
+            }
+            self.today_senders.extend(pending.senders);
+            if let Some(last) = self.days.last_mut() {
+                last.active_senders = self.today_senders.len() as u64;
+            }
+        }
+        let oldest = day - chrono::Duration::days(MAX_ACTIVITY_DAYS.into());
+        self.hours.retain(|hour| hour.hour >= oldest);
+        self.days.retain(|day| day.day >= oldest);
+    }
+
+    /// Gets the activity of the last `days` days (including today).
+    pub fn report(&self, now: DateTime<Utc>, days: u32) -> ActivityReport {
+        let since = start_of_day(now) - chrono::Duration::days(i64::from(days.max(1)) - 1);
+        ActivityReport {
+            hours: self
+                .hours
+                .iter()
+                .filter(|hour| hour.hour >= since)
+                .cloned()
+                .collect(),
+            days: self
+                .days
+                .iter()
+                .filter(|day| day.day >= since)
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// Activity of a hub in a range of days, served by `/v3/hub_activity/{hub_id}`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ActivityReport {
+    /// Activity per hour, oldest first, hours without activity are left out.
+    pub hours: Vec<HourActivity>,
+    /// Active senders per day, oldest first, days without messages are left out.
+    pub days: Vec<DaySenders>,
+}
+
+/// Gets the stored activity of a hub together with the activity that has not been flushed yet.
+///
+/// # Errors
+///
+/// This function may return an error for any of the reasons outlined in [`HubActivity::load`].
+pub async fn current(hub_id: HubId) -> Result<HubActivity> {
+    let mut activity = HubActivity::load(hub_id).await?;
+    let counters = COUNTERS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .as_ref()
+        .and_then(|counters| counters.get(&hub_id).cloned());
+    if let Some(counters) = counters {
+        activity.merge(Utc::now(), counters.peek());
+    }
+    Ok(activity)
+}
+
+/// Writes the pending activity of every hub to its activity file. Activity of hubs that no longer exist is dropped.
+///
+/// # Errors
+///
+/// This function may return an error for any of the reasons outlined in [`HubActivity::load`] and [`HubActivity::save`], the other hubs are still flushed.
+pub async fn flush() -> Result {
+    let counters: Vec<(HubId, Arc<HubCounters>)> = COUNTERS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .as_ref()
+        .map(|counters| {
+            counters
+                .iter()
+                .map(|(hub_id, counters)| (*hub_id, counters.clone()))
+                .collect()
+        })
+        .unwrap_or_default();
+    let now = Utc::now();
+    let mut result = Ok(());
+    for (hub_id, counters) in counters {
+        let pending = counters.take();
+        if pending.is_empty() {
+            continue;
+        }
+        if !crate::paths::hub_info_file(hub_id).is_file() {
+            forget(hub_id);
+            continue;
+        }
+        let flushed = async {
+            let mut activity = HubActivity::load(hub_id).await?;
+            activity.merge(now, pending);
+            activity.save(hub_id).await
+        }
+        .await;
+        if flushed.is_err() {
+            result = flushed;
+        }
+    }
+    result
+}
+
+/// Starts flushing the activity counters every [`ACTIVITY_FLUSH_INTERVAL`] seconds.
+pub fn spawn_flusher() {
+    tokio::spawn(async {
+        loop {
+            tokio::time::sleep(Duration::from_secs(ACTIVITY_FLUSH_INTERVAL)).await;
+            if let Err(err) = flush().await {
+                error!("Unable to save hub activity: {}", err.chain());
+            }
+        }
+    });
+}
+
+/// Activity of a hub since the server started, listed in `/v3/stats` if enabled in the configuration.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HubActivityTotals {
+    pub hub_id: HubId,
+    pub messages: u64,
+    pub joins: u64,
+    pub leaves: u64,
+}
+
+/// Gets the activity of every hub since the server started, sorted by hub ID.
+pub fn totals() -> Vec<HubActivityTotals> {
+    let mut totals: Vec<HubActivityTotals> = COUNTERS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .as_ref()
+        .map(|counters| {
+            counters
+                .iter()
+                .map(|(hub_id, counters)| HubActivityTotals {
+                    hub_id: *hub_id,
+                    messages: counters.total_messages.load(Ordering::Relaxed),
+                    joins: counters.total_joins.load(Ordering::Relaxed),
+                    leaves: counters.total_leaves.load(Ordering::Relaxed),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    totals.sort_by_key(|totals| totals.hub_id);
+    totals
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{HubActivity, PendingActivity, MAX_ACTIVITY_DAYS};
+
+    fn pending(messages: u64, senders: &[&str]) -> PendingActivity {
+        PendingActivity {
+            messages,
+            joins: 1,
+            leaves: 0,
+            senders: senders.iter().map(|sender| sender.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn merge_and_report() {
+        let now = Utc.with_ymd_and_hms(2021, 5, 1, 10, 15, 0).unwrap();
+        let mut activity = HubActivity::default();
+        activity.merge(now, pending(2, &["a", "b"]));
+        activity.merge(now + Duration::minutes(30), pending(1, &["a"]));
+        activity.merge(now + Duration::hours(1), pending(1, &["c"]));
+        assert_eq!(activity.hours.len(), 2);
+        assert_eq!(
+            activity.hours[0].hour,
+            Utc.with_ymd_and_hms(2021, 5, 1, 10, 0, 0).unwrap()
+        );
+        assert_eq!(activity.hours[0].messages, 3);
+        assert_eq!(activity.hours[0].joins, 2);
+        // Senders are counted once per day.
+        assert_eq!(activity.days.len(), 1);
+        assert_eq!(activity.days[0].active_senders, 3);
+        activity.merge(now + Duration::days(1), pending(1, &["a"]));
+        assert_eq!(activity.days[1].active_senders, 1);
+
+        let later = now + Duration::days(1);
+        assert_eq!(activity.report(later, 1).days.len(), 1);
+        assert_eq!(activity.report(later, 2).days.len(), 2);
+        assert_eq!(activity.report(later, 2).hours.len(), 3);
+
+        activity.merge(
+            now + Duration::days(i64::from(MAX_ACTIVITY_DAYS) + 2),
+            pending(1, &[]),
+        );
+        assert_eq!(activity.hours.len(), 1);
+        assert!(activity.days.is_empty());
+    }
+}
@@ -0,0 +1,195 @@
+use std::{collections::VecDeque, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::IoContext,
+    hub::Hub,
+    locks::{KeyedLock, KeyedLocks},
+    member_map::MemberChanges,
+    server::{self, HubUpdateType, ServerNotification},
+    HubId, Result,
+};
+
+/// Folder where the recent changes of each hub are stored.
+pub const HUB_CHANGES_FOLDER: &str = "data/hubs/changes/";
+
+/// Number of changes kept for each hub, clients that are further behind have to download the whole hub again.
+pub const MAX_HUB_CHANGES: usize = 64;
+
+/// Locks that make changes to the version of each hub happen one at a time, see [`lock`].
+static HUB_LOCKS: KeyedLocks<HubId> = KeyedLocks::new();
+
+/// Guard of a hub's lock, no other change to the hub can be recorded while it is held.
+pub type HubLock = KeyedLock<'static, HubId>;
+
+/// A change made to a hub.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HubChange {
+    /// Version of the hub after the change was made.
+    pub version: u64,
+    /// What was changed.
+    pub update: HubUpdateType,
+}
+
+/// Changes that a client has to apply to get from the version it has to the current version of a hub.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HubDelta {
+    /// Current version of the hub.
+    pub version: u64,
+    /// If true the changes are not known (anymore) and the client has to download the whole hub, `changes` is empty.
+    pub full_resync: bool,
+    /// Changes since the client's version, oldest first.
+    pub changes: Vec<HubChange>,
+}
+
+/// Most recent changes of a hub, stored separately from the hub itself.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct HubChanges {
+    /// Version of the hub after the last recorded change, only used for hubs that were saved before their version was stored with them, see [`Hub::version`].
+    pub version: u64,
+    /// The last (at most [`MAX_HUB_CHANGES`]) changes, oldest first.
+    pub recent: VecDeque<HubChange>,
+}
+
+impl HubChanges {
+    /// Gets the path of the file that a hub's changes are stored in.
+    pub fn get_path(hub_id: HubId) -> PathBuf {
+        crate::paths::hub_changes_file(hub_id)
+    }
+
+    /// Loads the changes of a hub, a hub that has never been changed is at version 0.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The file exists but could not be read.
+    /// * The file's contents could not be deserialized.
+    pub async fn load(hub_id: HubId) -> Result<Self> {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(bincode::deserialize(&bytes)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_path(path),
+        }
+    }
+
+    /// Saves the changes of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The changes folder does not exist and could not be created.
+    /// * The data could not be written to the disk.
+    pub async fn save(&self, hub_id: HubId) -> Result {
+        tokio::fs::create_dir_all(HUB_CHANGES_FOLDER)
+            .await
+            .with_path(HUB_CHANGES_FOLDER)?;
+        let path = Self::get_path(hub_id);
+        tokio::fs::write(&path, bincode::serialize(self)?)
+            .await
+            .with_path(path)
+    }
+
+    /// Records a change that brought the hub to the given version.
+    pub fn push(&mut self, version: u64, update: HubUpdateType) {
+        self.version = version;
+        self.recent.push_back(HubChange { version, update });
+        while self.recent.len() > MAX_HUB_CHANGES {
+            self.recent.pop_front();
+        }
+    }
+
+    /// Gets the changes that brought the hub from the given version to its current version, changes recorded after the current version was read are left out.
+    /// A full resync is needed if some of those changes are not known, because they are too old or the hub was saved without recording a change, or if the given version is newer than the current one.
+    pub fn since(&self, current: u64, version: u64) -> HubDelta {
+        let changes: Vec<HubChange> = self
+            .recent
+            .iter()
+            .filter(|change| change.version > version && change.version <= current)
+            .cloned()
+            .collect();
+        // Every version in between has to have a recorded change, otherwise the changes are incomplete.
+        let full_resync = version > current || changes.len() as u64 != current - version;
+        HubDelta {
+            version: current,
+            full_resync,
+            changes: if full_resync { Vec::new() } else { changes },
+        }
+    }
+}
+
+/// Locks a hub so that no other change can be made to it until the guard is dropped.
+/// Every change to a hub holds the lock from loading the hub until the change is recorded with [`record`], so that no two changes are made to the same copy of the hub and a version check (see [`Hub::check_version`]) and the new version are atomic.
+pub async fn lock(hub_id: HubId) -> HubLock {
+    HUB_LOCKS.lock(hub_id).await
+}
+
+/// Records a change made to a hub and notifies clients subscribed to the hub, returning the new version of the hub.
+/// The hub has to have been saved with the change (see [`Hub::save`]) while its lock was held, the change gets the version it was saved with.
+/// Changes to members are also added to the hub's [`MemberChanges`].
+///
+/// # Errors
+///
+/// This function may return an error for any of the reasons outlined in [`HubChanges::load`], [`HubChanges::save`], [`MemberChanges::load`] and [`MemberChanges::save`].
+pub async fn record(_lock: &HubLock, hub: &Hub, update: HubUpdateType) -> Result<u64> {
+    let (hub_id, version) = (hub.id, hub.version);
+    let mut changes = HubChanges::load(hub_id).await?;
+    changes.push(version, update.clone());
+    changes.save(hub_id).await?;
+    if let Some(user_id) = update.changed_member() {
+        let mut member_changes = MemberChanges::load(hub_id).await?;
+        member_changes.push(version, user_id.to_string());
+        member_changes.save(hub_id).await?;
+    }
+    server::publish(ServerNotification::HubUpdated(hub_id, update, version)).await;
+    Ok(version)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HubChanges, MAX_HUB_CHANGES};
+    use crate::server::HubUpdateType;
+
+    #[test]
+    fn delta_since() {
+        let mut changes = HubChanges::default();
+        assert!(!changes.since(0, 0).full_resync);
+        for version in 1..=3 {
+            changes.push(version, HubUpdateType::HubRenamed);
+        }
+        let delta = changes.since(3, 1);
+        assert_eq!(delta.version, 3);
+        assert!(!delta.full_resync);
+        assert_eq!(
+            delta
+                .changes
+                .iter()
+                .map(|change| change.version)
+                .collect::<Vec<u64>>(),
+            vec![2, 3]
+        );
+        assert!(changes.since(3, 3).changes.is_empty());
+        assert!(changes.since(3, 4).full_resync);
+
+        // The hub was saved without recording a change.
+        assert!(changes.since(4, 3).full_resync);
+        changes.push(5, HubUpdateType::HubRenamed);
+        assert!(changes.since(5, 3).full_resync);
+        assert!(!changes.since(5, 4).full_resync);
+        // Changes recorded after the current version was read are left out.
+        assert_eq!(changes.since(3, 2).changes.len(), 1);
+
+        for version in 6..6 + MAX_HUB_CHANGES as u64 {
+            changes.push(version, HubUpdateType::HubDescriptionUpdated);
+        }
+        assert_eq!(changes.recent.len(), MAX_HUB_CHANGES);
+        let current = 5 + MAX_HUB_CHANGES as u64;
+        assert!(changes.since(current, 4).full_resync);
+        assert_eq!(changes.since(current, 5).changes.len(), MAX_HUB_CHANGES);
+    }
+}
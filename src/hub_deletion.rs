@@ -0,0 +1,91 @@
+use std::{collections::HashMap, sync::Mutex as SyncMutex};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "graphql")]
+use async_graphql::SimpleObject;
+
+use crate::{error::Error, HubId, Result};
+
+/// Minutes a hub deletion token can be used for, see [`issue`].
+pub const DELETION_TOKEN_MINUTES: i64 = 10;
+
+/// Unused deletion tokens by the hub and the user that requested them, only kept in memory so that a restart cancels every pending deletion.
+static DELETION_TOKENS: SyncMutex<Option<HashMap<(HubId, String), DeletionToken>>> =
+    SyncMutex::new(None);
+
+/// Token that confirms the deletion of a hub, served by `/v3/request_delete_hub`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "graphql", derive(SimpleObject))]
+pub struct DeletionToken {
+    /// Secret that has to be given to `/v3/delete_hub` to delete the hub.
+    pub token: String,
+    /// Time after which the token can no longer be used.
+    pub expires: DateTime<Utc>,
+}
+
+/// Creates the token a user needs to delete a hub, replacing any earlier token of the user for the hub. Checking that the user can delete the hub is up to the caller.
+pub fn issue(hub_id: HubId, user_id: &str, now: DateTime<Utc>) -> DeletionToken {
+    let token = DeletionToken {
+        token: format!("{:032x}", rand::random::<u128>()),
+        expires: now + Duration::minutes(DELETION_TOKEN_MINUTES),
+    };
+    let mut tokens = DELETION_TOKENS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    let tokens = tokens.get_or_insert_with(HashMap::new);
+    tokens.retain(|_, token| token.expires > now);
+    tokens.insert((hub_id, user_id.to_string()), token.clone());
+    token
+}
+
+/// Uses up the deletion token of a user for a hub. The token is forgotten even if the given one is wrong, so a token can not be guessed and the user has to request a new one.
+///
+/// # Errors
+///
+/// This function returns [`Error::InvalidDeletionToken`] if the user has no token for the hub, it has expired or it is not `token`.
+pub fn redeem(hub_id: HubId, user_id: &str, token: &str, now: DateTime<Utc>) -> Result {
+    let stored = DELETION_TOKENS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .get_or_insert_with(HashMap::new)
+        .remove(&(hub_id, user_id.to_string()));
+    match stored {
+        Some(stored) if stored.expires > now && constant_time_eq(&stored.token, token) => Ok(()),
+        _ => Err(Error::InvalidDeletionToken),
+    }
+}
+
+/// Compares two tokens without stopping at the first difference.
+pub(crate) fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+
+    use super::{issue, redeem, DELETION_TOKEN_MINUTES};
+    use crate::HubId;
+
+    #[test]
+    fn tokens_are_single_use_and_expire() {
+        let hub_id = HubId::from_u128(0xde1e7e);
+        let now = Utc::now();
+        let token = issue(hub_id, "owner", now);
+        assert!(redeem(hub_id, "other", &token.token, now).is_err());
+        assert!(redeem(hub_id, "owner", &token.token, now).is_ok());
+        assert!(redeem(hub_id, "owner", &token.token, now).is_err());
+        let token = issue(hub_id, "owner", now);
+        assert!(redeem(hub_id, "owner", "wrong", now).is_err());
+        assert!(redeem(hub_id, "owner", &token.token, now).is_err());
+        let token = issue(hub_id, "owner", now);
+        let later = now + Duration::minutes(DELETION_TOKEN_MINUTES);
+        assert!(redeem(hub_id, "owner", &token.token, later).is_err());
+    }
+}
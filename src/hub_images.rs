@@ -0,0 +1,411 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, IoContext},
+    HubId, Result,
+};
+
+/// Folder where the icons and banners of hubs are stored, each hub has its own folder in it.
+pub const HUB_IMAGES_FOLDER: &str = "data/hubs/images/";
+
+/// Maximum size of a hub icon in bytes.
+pub const MAX_ICON_SIZE: usize = 256 * 1024;
+
+/// Maximum width and height of a hub icon in pixels.
+pub const MAX_ICON_DIMENSIONS: (u32, u32) = (512, 512);
+
+/// Maximum size of a hub banner in bytes.
+pub const MAX_BANNER_SIZE: usize = 1024 * 1024;
+
+/// Maximum width and height of a hub banner in pixels.
+pub const MAX_BANNER_DIMENSIONS: (u32, u32) = (1920, 1080);
+
+/// Images a hub can have.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageKind {
+    Icon,
+    Banner,
+}
+
+impl ImageKind {
+    /// Name of the file the image is stored in.
+    pub fn file_name(self) -> &'static str {
+        match self {
+            ImageKind::Icon => "icon",
+            ImageKind::Banner => "banner",
+        }
+    }
+
+    /// Maximum size of the image in bytes.
+    pub fn max_size(self) -> usize {
+        match self {
+            ImageKind::Icon => MAX_ICON_SIZE,
+            ImageKind::Banner => MAX_BANNER_SIZE,
+        }
+    }
+
+    /// Maximum width and height of the image in pixels.
+    pub fn max_dimensions(self) -> (u32, u32) {
+        match self {
+            ImageKind::Icon => MAX_ICON_DIMENSIONS,
+            ImageKind::Banner => MAX_BANNER_DIMENSIONS,
+        }
+    }
+}
+
+/// Image formats that are accepted for hub images.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+}
+
+impl ImageFormat {
+    /// MIME type of the format, used as the `Content-Type` when serving the image.
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "image/png",
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Gif => "image/gif",
+            ImageFormat::Webp => "image/webp",
+        }
+    }
+}
+
+/// Format and size (width, height) of an image, read from its header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageHeader {
+    pub format: ImageFormat,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ImageHeader {
+    /// Reads the format and size of an image without decoding the image itself, so images that would take up a lot of memory once decoded are rejected cheaply.
+    /// Returns `None` if the format is not supported or the header is malformed.
+    pub fn read(bytes: &[u8]) -> Option<Self> {
+        let (format, width, height) = if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            if bytes.get(12..16)? != b"IHDR" {
+                return None;
+            }
+            (ImageFormat::Png, be_u32(bytes, 16)?, be_u32(bytes, 20)?)
+        } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+            (
+                ImageFormat::Gif,
+                u32::from(le_u16(bytes, 6)?),
+                u32::from(le_u16(bytes, 8)?),
+            )
+        } else if bytes.starts_with(&[0xFF, 0xD8]) {
+            let (width, height) = jpeg_dimensions(bytes)?;
+            (ImageFormat::Jpeg, width, height)
+        } else if bytes.starts_with(b"RIFF") && bytes.get(8..12)? == b"WEBP" {
+            let (width, height) = webp_dimensions(bytes)?;
+            (ImageFormat::Webp, width, height)
+        } else {
+            return None;
+        };
+        if width == 0 || height == 0 {
+            return None;
+        }
+        Some(Self {
+            format,
+            width,
+            height,
+        })
+    }
+}
+
+fn be_u16(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*bytes.get(at)?, *bytes.get(at + 1)?]))
+}
+
+fn le_u16(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes([*bytes.get(at)?, *bytes.get(at + 1)?]))
+}
+
+fn be_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(bytes.get(at..at + 4)?);
+    Some(u32::from_be_bytes(buf))
+}
+
+fn le_u24(bytes: &[u8], at: usize) -> Option<u32> {
+    let b = bytes.get(at..at + 3)?;
+    Some(u32::from(b[0]) | u32::from(b[1]) << 8 | u32::from(b[2]) << 16)
+}
+
+/// Finds the start of frame segment of a JPEG and reads the size from it.
+fn jpeg_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let mut at = 2;
+    loop {
+        if *bytes.get(at)? != 0xFF {
+            return None;
+        }
+        let marker = *bytes.get(at + 1)?;
+        match marker {
+            // Fill byte, the marker starts at the next byte.
+            0xFF => at += 1,
+            // Markers without a length.
+            0x01 | 0xD0..=0xD7 => at += 2,
+            // Start of frame, except for DHT (C4), JPG (C8) and DAC (CC) which share the range.
+            0xC0..=0xCF if marker != 0xC4 && marker != 0xC8 && marker != 0xCC => {
+                let height = be_u16(bytes, at + 5)?;
+                let width = be_u16(bytes, at + 7)?;
+                return Some((u32::from(width), u32::from(height)));
+            }
+            // Start of scan or end of image before any frame.
+            0xDA | 0xD9 => return None,
+            _ => at += 2 + usize::from(be_u16(bytes, at + 2)?),
+        }
+    }
+}
+
+/// Reads the size of a WebP image from its first chunk.
+fn webp_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    match bytes.get(12..16)? {
+        b"VP8 " => Some((
+            u32::from(le_u16(bytes, 26)? & 0x3FFF),
+            u32::from(le_u16(bytes, 28)? & 0x3FFF),
+        )),
+        b"VP8L" => {
+            let b = bytes.get(21..25)?;
+            let (b0, b1, b2, b3) = (
+                u32::from(b[0]),
+                u32::from(b[1]),
+                u32::from(b[2]),
+                u32::from(b[3]),
+            );
+            Some((
+                1 + (b0 | (b1 & 0x3F) << 8),
+                1 + (b1 >> 6 | b2 << 2 | (b3 & 0x0F) << 10),
+            ))
+        }
+        b"VP8X" => Some((1 + le_u24(bytes, 24)?, 1 + le_u24(bytes, 27)?)),
+        _ => None,
+    }
+}
+
+/// Checks that an image can be used as the given kind of hub image, returning its header.
+///
+/// # Errors
+///
+/// This function returns an error for any of the following reasons:
+///
+/// * [`Error::ImageTooLarge`] if the image is bigger than [`ImageKind::max_size`].
+/// * [`Error::InvalidImage`] if the image is not in one of the [`ImageFormat`]s or its header is malformed.
+/// * [`Error::ImageDimensionsTooLarge`] if the image is wider or taller than [`ImageKind::max_dimensions`].
+pub fn validate_image(kind: ImageKind, bytes: &[u8]) -> Result<ImageHeader> {
+    if bytes.len() > kind.max_size() {
+        return Err(Error::ImageTooLarge);
+    }
+    let header = ImageHeader::read(bytes).ok_or(Error::InvalidImage)?;
+    let (max_width, max_height) = kind.max_dimensions();
+    if header.width > max_width || header.height > max_height {
+        return Err(Error::ImageDimensionsTooLarge);
+    }
+    Ok(header)
+}
+
+/// Format and version of a stored hub image.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct StoredImage {
+    /// Format of the image.
+    pub format: ImageFormat,
+    /// Increased every time the image is changed, clients can add it to the image URL to bypass their cache.
+    pub version: u64,
+}
+
+/// The images of a hub, stored separately from the hub itself.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct HubImages {
+    /// The hub's icon, if it has one.
+    pub icon: Option<StoredImage>,
+    /// The hub's banner, if it has one.
+    pub banner: Option<StoredImage>,
+}
+
+impl HubImages {
+    /// Gets the path of the folder a hub's images are stored in.
+    pub fn get_folder(hub_id: HubId) -> PathBuf {
+        crate::paths::hub_images_dir(hub_id)
+    }
+
+    /// Gets the path of the file that the image information of a hub is stored in.
+    pub fn get_path(hub_id: HubId) -> PathBuf {
+        crate::paths::hub_images_file(hub_id)
+    }
+
+    /// Gets the path of the file that an image is stored in.
+    pub fn get_image_path(hub_id: HubId, kind: ImageKind) -> PathBuf {
+        crate::paths::hub_image_file(hub_id, kind)
+    }
+
+    /// Gets an image's information.
+    pub fn get(&self, kind: ImageKind) -> Option<StoredImage> {
+        match kind {
+            ImageKind::Icon => self.icon,
+            ImageKind::Banner => self.banner,
+        }
+    }
+
+    /// Version of an image, `0` if the hub does not have the image.
+    pub fn version(&self, kind: ImageKind) -> u64 {
+        self.get(kind).map_or(0, |image| image.version)
+    }
+
+    /// Loads the image information of a hub, a hub without images has none.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The file exists but could not be read.
+    /// * The file's contents could not be deserialized.
+    pub async fn load(hub_id: HubId) -> Result<Self> {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(bincode::deserialize(&bytes)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_path(path),
+        }
+    }
+
+    /// Saves the image information of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The hub's image folder does not exist and could not be created.
+    /// * The data could not be written to the disk.
+    pub async fn save(&self, hub_id: HubId) -> Result {
+        let folder = Self::get_folder(hub_id);
+        tokio::fs::create_dir_all(&folder).await.with_path(folder)?;
+        let path = Self::get_path(hub_id);
+        tokio::fs::write(&path, bincode::serialize(self)?)
+            .await
+            .with_path(path)
+    }
+
+    /// Validates and stores an image, returning its new version.
+    ///
+    /// # Errors
+    ///
+    /// This function may return an error for any of the reasons outlined in [`validate_image`], [`HubImages::load`] and [`HubImages::save`] or if the image could not be written to the disk.
+    pub async fn set(hub_id: HubId, kind: ImageKind, bytes: &[u8]) -> Result<u64> {
+        let header = validate_image(kind, bytes)?;
+        let mut images = Self::load(hub_id).await?;
+        let image = StoredImage {
+            format: header.format,
+            version: images.version(kind) + 1,
+        };
+        let folder = Self::get_folder(hub_id);
+        tokio::fs::create_dir_all(&folder).await.with_path(folder)?;
+        let path = Self::get_image_path(hub_id, kind);
+        tokio::fs::write(&path, bytes).await.with_path(path)?;
+        match kind {
+            ImageKind::Icon => images.icon = Some(image),
+            ImageKind::Banner => images.banner = Some(image),
+        }
+        images.save(hub_id).await?;
+        Ok(image.version)
+    }
+
+    /// Reads an image, returning its information and contents.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The hub does not have the image.
+    /// * The image information could not be loaded for any of the reasons outlined in [`HubImages::load`].
+    /// * The image could not be read.
+    pub async fn read(hub_id: HubId, kind: ImageKind) -> Result<(StoredImage, Vec<u8>)> {
+        let image = Self::load(hub_id)
+            .await?
+            .get(kind)
+            .ok_or(Error::ImageNotFound)?;
+        let path = Self::get_image_path(hub_id, kind);
+        let bytes = tokio::fs::read(&path).await.with_path(path)?;
+        Ok((image, bytes))
+    }
+
+    /// Removes all of the images of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the hub's image folder exists but could not be removed.
+    pub async fn remove(hub_id: HubId) -> Result {
+        let folder = Self::get_folder(hub_id);
+        match tokio::fs::remove_dir_all(&folder).await {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result.with_path(folder),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{validate_image, ImageFormat, ImageHeader, ImageKind};
+    use crate::error::Error;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        bytes.extend_from_slice(&width.to_be_bytes());
+        bytes.extend_from_slice(&height.to_be_bytes());
+        bytes.extend_from_slice(&[8, 6, 0, 0, 0]);
+        bytes
+    }
+
+    #[test]
+    fn reads_headers() {
+        assert_eq!(
+            ImageHeader::read(&png(64, 32)),
+            Some(ImageHeader {
+                format: ImageFormat::Png,
+                width: 64,
+                height: 32
+            })
+        );
+        let gif = b"GIF89a\x40\x00\x20\x00";
+        assert_eq!(
+            ImageHeader::read(gif).map(|header| (header.width, header.height)),
+            Some((64, 32))
+        );
+        // SOI, an APP0 segment with no data, then a baseline SOF0 segment.
+        let jpeg = [
+            0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x02, 0xFF, 0xC0, 0x00, 0x11, 0x08, 0x00, 0x20, 0x00,
+            0x40,
+        ];
+        assert_eq!(
+            ImageHeader::read(&jpeg).map(|header| (header.format, header.width, header.height)),
+            Some((ImageFormat::Jpeg, 64, 32))
+        );
+        assert_eq!(ImageHeader::read(b"not an image"), None);
+        assert_eq!(ImageHeader::read(&png(0, 32)), None);
+        assert_eq!(ImageHeader::read(&png(64, 32)[..20]), None);
+    }
+
+    #[test]
+    fn limits() {
+        assert!(validate_image(ImageKind::Icon, &png(512, 512)).is_ok());
+        assert!(matches!(
+            validate_image(ImageKind::Icon, &png(100_000, 100_000)),
+            Err(Error::ImageDimensionsTooLarge)
+        ));
+        assert!(validate_image(ImageKind::Banner, &png(1920, 1080)).is_ok());
+        let mut big = png(16, 16);
+        big.resize(ImageKind::Icon.max_size() + 1, 0);
+        assert!(matches!(
+            validate_image(ImageKind::Icon, &big),
+            Err(Error::ImageTooLarge)
+        ));
+    }
+}
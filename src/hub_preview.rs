@@ -0,0 +1,197 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::IoContext,
+    hub::Hub,
+    hub_images::{HubImages, ImageKind},
+    HubId, Result,
+};
+
+/// Folder where the preview settings of each hub are stored.
+pub const HUB_PREVIEW_FOLDER: &str = "data/hubs/preview/";
+
+/// Number of addresses the preview rate limiter tracks before it forgets the ones whose window is over.
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// Counts the preview requests made from each address, see [`allow_request`].
+static PREVIEW_REQUESTS: Mutex<Option<RateLimiter>> = Mutex::new(None);
+
+/// Whether users that are not in a hub can preview it, stored separately from the hub itself.
+/// Hubs can not be previewed until a user that can administrate them enables it.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct PreviewSettings {
+    pub enabled: bool,
+}
+
+impl PreviewSettings {
+    /// Gets the path of the file that a hub's preview settings are stored in.
+    pub fn get_path(hub_id: HubId) -> PathBuf {
+        crate::paths::hub_preview_file(hub_id)
+    }
+
+    /// Loads the preview settings of a hub, a hub without settings can not be previewed.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The file exists but could not be read.
+    /// * The file's contents could not be deserialized.
+    pub async fn load(hub_id: HubId) -> Result<Self> {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(bincode::deserialize(&bytes)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_path(path),
+        }
+    }
+
+    /// Saves the preview settings of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The preview folder does not exist and could not be created.
+    /// * The data could not be written to the disk.
+    pub async fn save(&self, hub_id: HubId) -> Result {
+        tokio::fs::create_dir_all(HUB_PREVIEW_FOLDER)
+            .await
+            .with_path(HUB_PREVIEW_FOLDER)?;
+        let path = Self::get_path(hub_id);
+        tokio::fs::write(&path, bincode::serialize(self)?)
+            .await
+            .with_path(path)
+    }
+
+    /// Removes the preview settings of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the file exists but could not be removed.
+    pub async fn remove(hub_id: HubId) -> Result {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::remove_file(&path).await {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result.with_path(path),
+        }
+    }
+}
+
+/// What users that are not in a hub can see of it, never includes channels or members.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HubPreview {
+    pub id: HubId,
+    pub name: String,
+    pub description: String,
+    /// Version of the hub's icon, `0` if it has none. The icon itself is served by `/v3/hub_icon/{hub_id}`.
+    pub icon_version: u64,
+    pub member_count: usize,
+    /// Time the hub was created.
+    pub created: DateTime<Utc>,
+}
+
+impl HubPreview {
+    /// Creates the preview of a hub.
+    pub fn new(hub: &Hub, images: &HubImages) -> Self {
+        Self {
+            id: hub.id,
+            name: hub.name.clone(),
+            description: hub.description.clone(),
+            icon_version: images.version(ImageKind::Icon),
+            member_count: hub.members.len(),
+            created: hub.created,
+        }
+    }
+}
+
+/// Counts requests per address in windows of a minute.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    requests: HashMap<IpAddr, (Instant, u32)>,
+}
+
+impl RateLimiter {
+    /// Counts a request from an address, returns false if the address has made more than `limit` requests in the current window.
+    pub fn allow(&mut self, address: IpAddr, now: Instant, limit: Option<u32>) -> bool {
+        let window = Duration::from_secs(60);
+        if self.requests.len() >= PRUNE_THRESHOLD {
+            self.requests
+                .retain(|_, (start, _)| now.duration_since(*start) < window);
+        }
+        let (start, count) = self.requests.entry(address).or_insert((now, 0));
+        if now.duration_since(*start) >= window {
+            *start = now;
+            *count = 0;
+        }
+        *count = count.saturating_add(1);
+        limit.is_none_or(|limit| *count <= limit)
+    }
+}
+
+/// Counts a preview request from an address against the configured limit (see [`crate::config::LimitsConfig`]), returns false if it goes over it.
+pub fn allow_request(address: IpAddr) -> bool {
+    let limit = crate::quotas::limits().max_hub_previews_per_minute;
+    PREVIEW_REQUESTS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .get_or_insert_with(RateLimiter::default)
+        .allow(address, Instant::now(), limit)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::{Duration, Instant},
+    };
+
+    use super::{HubPreview, RateLimiter};
+    use crate::{hub::Hub, hub_images::HubImages, HubId};
+
+    #[test]
+    fn preview_hides_channels_and_members() {
+        let hub = Hub::new("hub".to_string(), HubId::from_u128(1), "owner".to_string());
+        let json = serde_json::to_value(HubPreview::new(&hub, &HubImages::default())).unwrap();
+        let mut keys: Vec<&String> = json.as_object().unwrap().keys().collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            vec![
+                "created",
+                "description",
+                "icon_version",
+                "id",
+                "member_count",
+                "name"
+            ]
+        );
+        assert_eq!(json["member_count"], 1);
+    }
+
+    #[test]
+    fn rate_limit_per_address() {
+        let mut limiter = RateLimiter::default();
+        let now = Instant::now();
+        let first = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let second = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+        assert!(limiter.allow(first, now, Some(2)));
+        assert!(limiter.allow(first, now, Some(2)));
+        assert!(!limiter.allow(first, now, Some(2)));
+        assert!(limiter.allow(second, now, Some(2)));
+        let later = now + Duration::from_secs(60);
+        assert!(limiter.allow(first, later, Some(2)));
+        assert!(limiter.allow(first, later, None));
+    }
+}
@@ -0,0 +1,247 @@
+use std::{collections::HashMap, path::Path, sync::Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{channel::message_file_day, error::IoContext, hub::Hub, ChannelId, HubId, Result};
+
+/// Minutes the measured disk usage of a hub is reused for before its data folder is walked again.
+pub const STORAGE_CACHE_MINUTES: i64 = 10;
+
+/// Last measured disk usage of each hub whose usage was requested since the server started.
+static CACHE: Mutex<Option<HashMap<HubId, HubStorage>>> = Mutex::new(None);
+
+/// Bytes a channel uses on disk, by what they are used for.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChannelStorage {
+    pub channel_id: ChannelId,
+    /// Bytes of the channel's message files.
+    pub messages: u64,
+    /// Bytes of the channel's search index.
+    pub index: u64,
+    /// Bytes of the previous versions of edited messages, see [`crate::message_edits`].
+    pub edits: u64,
+    /// Bytes of the link previews of messages, see [`crate::unfurl`].
+    pub previews: u64,
+    /// Bytes of anything else in the channel's folder.
+    pub other: u64,
+}
+
+impl Default for ChannelStorage {
+    fn default() -> Self {
+        Self {
+            channel_id: ChannelId::nil(),
+            messages: 0,
+            index: 0,
+            edits: 0,
+            previews: 0,
+            other: 0,
+        }
+    }
+}
+
+impl ChannelStorage {
+    /// Gets the total number of bytes the channel uses.
+    pub fn total(&self) -> u64 {
+        self.messages + self.index + self.edits + self.previews + self.other
+    }
+}
+
+/// Disk usage of the channels of a hub, largest channel first.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct HubStorage {
+    /// When the hub's data folder was walked, usage is measured again once this is [`STORAGE_CACHE_MINUTES`] old.
+    pub measured: DateTime<Utc>,
+    /// Total number of bytes used by the hub's channels.
+    pub total: u64,
+    pub channels: Vec<ChannelStorage>,
+}
+
+impl HubStorage {
+    /// Measures the disk usage of every channel of a hub by walking their folders.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if a channel folder exists but could not be read.
+    pub async fn measure(hub: &Hub) -> Result<Self> {
+        let mut channels = Vec::with_capacity(hub.channels.len());
+        for channel_id in hub.channels.keys() {
+            channels.push(measure_channel(hub.id, *channel_id).await?);
+        }
+        let mut storage = Self {
+            measured: Utc::now(),
+            total: 0,
+            channels,
+        };
+        storage.sort();
+        Ok(storage)
+    }
+
+    /// Removes bytes reclaimed by compacting a channel (see [`crate::channel::Channel::compact`]) from its message files.
+    pub fn reclaim(&mut self, channel_id: ChannelId, bytes: u64) {
+        if let Some(channel) = self
+            .channels
+            .iter_mut()
+            .find(|channel| channel.channel_id == channel_id)
+        {
+            channel.messages = channel.messages.saturating_sub(bytes);
+        }
+        self.sort();
+    }
+
+    /// Sorts the channels largest first and recomputes the total.
+    fn sort(&mut self) {
+        self.channels.sort_by(|a, b| {
+            b.total()
+                .cmp(&a.total())
+                .then(a.channel_id.cmp(&b.channel_id))
+        });
+        self.total = self.channels.iter().map(ChannelStorage::total).sum();
+    }
+}
+
+/// Result of compacting a channel on request.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Compaction {
+    pub channel_id: ChannelId,
+    /// Number of bytes removed from the channel's message files.
+    pub reclaimed: u64,
+}
+
+/// Adds up the size of every file in a folder and its subfolders, a missing folder is empty.
+async fn folder_size(path: &Path) -> Result<u64> {
+    let mut size = 0;
+    let mut folders = vec![path.to_path_buf()];
+    while let Some(folder) = folders.pop() {
+        let mut entries = match tokio::fs::read_dir(&folder).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err).with_path(folder),
+        };
+        while let Some(entry) = entries.next_entry().await.with_path(&folder)? {
+            let metadata = entry.metadata().await.with_path(entry.path())?;
+            if metadata.is_dir() {
+                folders.push(entry.path());
+            } else {
+                size += metadata.len();
+            }
+        }
+    }
+    Ok(size)
+}
+
+/// Measures the disk usage of a channel, see [`crate::paths`] for the layout of its folder.
+async fn measure_channel(hub_id: HubId, channel_id: ChannelId) -> Result<ChannelStorage> {
+    let mut storage = ChannelStorage {
+        channel_id,
+        ..ChannelStorage::default()
+    };
+    let folder = crate::paths::channel_dir(hub_id, channel_id);
+    let mut entries = match tokio::fs::read_dir(&folder).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(storage),
+        Err(err) => return Err(err).with_path(folder),
+    };
+    while let Some(entry) = entries.next_entry().await.with_path(&folder)? {
+        let path = entry.path();
+        let metadata = entry.metadata().await.with_path(&path)?;
+        let size = if metadata.is_dir() {
+            folder_size(&path).await?
+        } else {
+            metadata.len()
+        };
+        if path == crate::paths::channel_index_dir(hub_id, channel_id) {
+            storage.index += size;
+        } else if path == crate::paths::channel_edits_dir(hub_id, channel_id) {
+            storage.edits += size;
+        } else if path == crate::paths::channel_previews_dir(hub_id, channel_id) {
+            storage.previews += size;
+        } else if metadata.is_file()
+            && entry
+                .file_name()
+                .to_str()
+                .and_then(message_file_day)
+                .is_some()
+        {
+            storage.messages += size;
+        } else {
+            storage.other += size;
+        }
+    }
+    Ok(storage)
+}
+
+/// Gets the disk usage of a hub, measuring it only if the last measurement is older than [`STORAGE_CACHE_MINUTES`].
+///
+/// # Errors
+///
+/// This function returns an error if the usage had to be measured and could not be, see [`HubStorage::measure`].
+pub async fn current(hub: &Hub) -> Result<HubStorage> {
+    let cached = CACHE
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .as_ref()
+        .and_then(|cache| cache.get(&hub.id).cloned());
+    if let Some(storage) = cached {
+        if Utc::now() - storage.measured < Duration::minutes(STORAGE_CACHE_MINUTES) {
+            return Ok(storage);
+        }
+    }
+    let storage = HubStorage::measure(hub).await?;
+    CACHE
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .get_or_insert_with(HashMap::new)
+        .insert(hub.id, storage.clone());
+    Ok(storage)
+}
+
+/// Updates the cached usage of a hub after one of its channels was compacted, see [`HubStorage::reclaim`].
+pub fn record_reclaimed(hub_id: HubId, channel_id: ChannelId, bytes: u64) {
+    if let Some(storage) = CACHE
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .as_mut()
+        .and_then(|cache| cache.get_mut(&hub_id))
+    {
+        storage.reclaim(channel_id, bytes);
+    }
+}
+
+/// Drops the cached usage of a hub, used when the hub is deleted.
+pub fn forget(hub_id: HubId) {
+    if let Some(cache) = CACHE.lock().unwrap_or_else(|err| err.into_inner()).as_mut() {
+        cache.remove(&hub_id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use super::{ChannelStorage, HubStorage};
+    use crate::ChannelId;
+
+    #[test]
+    fn reclaimed_bytes_reorder_channels() {
+        let channel = |id, messages, index| ChannelStorage {
+            channel_id: ChannelId::from_u128(id),
+            messages,
+            index,
+            ..ChannelStorage::default()
+        };
+        let mut storage = HubStorage {
+            measured: Utc::now(),
+            total: 0,
+            channels: vec![channel(1, 100, 10), channel(2, 300, 0)],
+        };
+        storage.sort();
+        assert_eq!(storage.total, 410);
+        assert_eq!(storage.channels[0].channel_id, ChannelId::from_u128(2));
+        storage.reclaim(ChannelId::from_u128(2), 250);
+        assert_eq!(storage.total, 160);
+        assert_eq!(storage.channels[0].channel_id, ChannelId::from_u128(1));
+        storage.reclaim(ChannelId::from_u128(1), 1000);
+        assert_eq!(storage.channels[1].messages, 0);
+    }
+}
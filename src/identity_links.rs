@@ -0,0 +1,344 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex as SyncMutex,
+};
+
+use chrono::{DateTime, Utc};
+use pgp::{types::KeyTrait, SignedPublicKey};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, IoContext},
+    hub::Hub,
+    locks::KeyedLocks,
+    quotas::UserQuota,
+    Result,
+};
+
+/// Folder the identity links are stored in.
+pub const IDENTITY_LINKS_FOLDER: &str = "data/users/";
+
+/// File that maps linked keys to the users they sign in as.
+pub const IDENTITY_LINKS_FILE: &str = "data/users/identity_links";
+
+/// Makes sure only one change is made to the identity links at a time, see [`link`].
+static LINKS_LOCK: KeyedLocks<()> = KeyedLocks::new();
+
+/// The identity links as they were last loaded or saved, so that requests can be resolved without reading the file, see [`init`].
+static LINKS: SyncMutex<Option<IdentityLinks>> = SyncMutex::new(None);
+
+/// A key that signs in as a user other than its own fingerprint.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct IdentityLink {
+    /// ID of the user the key signs in as.
+    pub user_id: String,
+    /// ID of the server admin that linked the key.
+    pub linked_by: String,
+    pub linked: DateTime<Utc>,
+}
+
+/// Options for linking a key to a user, given by the admin that links it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LinkRequest {
+    /// Fingerprint of the new key, hex encoded.
+    pub fingerprint: String,
+    /// If true every other key of the user stops working, including the key whose fingerprint is the user's ID.
+    #[serde(default)]
+    pub revoke_others: bool,
+}
+
+/// Result of linking a key, served by `/v3/link_identity/{user_id}`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LinkResult {
+    /// ID of the user the key now signs in as.
+    pub user_id: String,
+    pub fingerprint: String,
+    /// Fingerprints of the keys that stopped working.
+    pub revoked: Vec<String>,
+}
+
+/// Keys that sign in as another user, by fingerprint, and keys that can no longer be used at all.
+/// Users are identified by the fingerprint of their key, so a user that loses their key would otherwise lose access to everything they are a member of.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct IdentityLinks {
+    pub links: HashMap<String, IdentityLink>,
+    pub revoked: HashSet<String>,
+}
+
+impl IdentityLinks {
+    /// Loads the identity links, if there is no file no keys are linked.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The file exists but could not be read.
+    /// * The file's contents could not be deserialized.
+    pub async fn load() -> Result<Self> {
+        match tokio::fs::read(IDENTITY_LINKS_FILE).await {
+            Ok(bytes) => Ok(bincode::deserialize(&bytes)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_path(IDENTITY_LINKS_FILE),
+        }
+    }
+
+    /// Saves the identity links.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The users folder does not exist and could not be created.
+    /// * The data could not be written to the disk.
+    pub async fn save(&self) -> Result {
+        tokio::fs::create_dir_all(IDENTITY_LINKS_FOLDER)
+            .await
+            .with_path(IDENTITY_LINKS_FOLDER)?;
+        tokio::fs::write(IDENTITY_LINKS_FILE, bincode::serialize(self)?)
+            .await
+            .with_path(IDENTITY_LINKS_FILE)
+    }
+
+    /// Gets the ID of the user a key signs in as, which is its own fingerprint unless it was linked to another user.
+    ///
+    /// # Errors
+    ///
+    /// This function returns [`Error::KeyRevoked`] if the key can no longer be used.
+    pub fn user_id(&self, fingerprint: &str) -> Result<String> {
+        if self.revoked.contains(fingerprint) {
+            return Err(Error::KeyRevoked);
+        }
+        Ok(self
+            .links
+            .get(fingerprint)
+            .map_or(fingerprint, |link| &link.user_id)
+            .to_string())
+    }
+
+    /// Gets the user a key or user ID stands for: the user a linked key signs in as, otherwise the ID itself.
+    pub fn resolve<'a>(&'a self, user_id: &'a str) -> &'a str {
+        self.links
+            .get(user_id)
+            .map_or(user_id, |link| link.user_id.as_str())
+    }
+
+    /// Makes a key sign in as a user. A key that was revoked can be used again once it is linked, linking a user's own fingerprint to them only does that.
+    /// If `user_id` is itself a linked key the key is linked to the user that key signs in as.
+    ///
+    /// # Errors
+    ///
+    /// This function returns [`Error::KeyInUse`] if the key already signs in as another user, or if other keys are linked to the user whose ID is the key's fingerprint.
+    pub fn link(
+        &mut self,
+        fingerprint: &str,
+        user_id: &str,
+        linked_by: &str,
+        revoke_others: bool,
+        now: DateTime<Utc>,
+    ) -> Result<LinkResult> {
+        let user_id = self.resolve(user_id).to_string();
+        let linked_elsewhere = self
+            .links
+            .get(fingerprint)
+            .is_some_and(|link| link.user_id != user_id);
+        let has_links = self.links.values().any(|link| link.user_id == fingerprint);
+        if fingerprint != user_id && (linked_elsewhere || has_links) {
+            return Err(Error::KeyInUse);
+        }
+        self.revoked.remove(fingerprint);
+        if fingerprint == user_id {
+            self.links.remove(fingerprint);
+        } else {
+            self.links.insert(
+                fingerprint.to_string(),
+                IdentityLink {
+                    user_id: user_id.clone(),
+                    linked_by: linked_by.to_string(),
+                    linked: now,
+                },
+            );
+        }
+        let mut revoked = Vec::new();
+        if revoke_others {
+            let others: Vec<String> = self
+                .links
+                .iter()
+                .filter(|(other, link)| link.user_id == user_id && *other != fingerprint)
+                .map(|(other, _)| other.clone())
+                .collect();
+            for other in others {
+                self.links.remove(&other);
+                self.revoked.insert(other.clone());
+                revoked.push(other);
+            }
+            if fingerprint != user_id && self.revoked.insert(user_id.clone()) {
+                revoked.push(user_id.clone());
+            }
+            revoked.sort();
+        }
+        Ok(LinkResult {
+            user_id,
+            fingerprint: fingerprint.to_string(),
+            revoked,
+        })
+    }
+}
+
+/// Loads the identity links into memory, called once when the server starts. Until then every key signs in as its own fingerprint.
+///
+/// # Errors
+///
+/// This function returns an error if the links could not be loaded, see [`IdentityLinks::load`].
+pub async fn init() -> Result {
+    let links = IdentityLinks::load().await?;
+    *LINKS.lock().unwrap_or_else(|err| err.into_inner()) = Some(links);
+    Ok(())
+}
+
+/// Gets the ID of the user that signs in with a key, see [`IdentityLinks::user_id`].
+///
+/// # Errors
+///
+/// This function returns [`Error::KeyRevoked`] if the key can no longer be used.
+pub fn user_id(public_key: &SignedPublicKey) -> Result<String> {
+    let fingerprint = hex::encode_upper(public_key.fingerprint());
+    match LINKS.lock().unwrap_or_else(|err| err.into_inner()).as_ref() {
+        Some(links) => links.user_id(&fingerprint),
+        None => Ok(fingerprint),
+    }
+}
+
+/// Checks if a user has data on the server: a quota, which every user that created a hub or sent a message has, or a membership of a hub.
+///
+/// # Errors
+///
+/// This function returns an error if the hubs could not be listed for any of the reasons outlined in [`crate::maintenance::list_hubs`].
+pub async fn has_user_data(user_id: &str) -> Result<bool> {
+    if tokio::fs::metadata(UserQuota::get_path(user_id))
+        .await
+        .is_ok()
+    {
+        return Ok(true);
+    }
+    for hub_id in crate::maintenance::list_hubs().await? {
+        if let Ok(hub) = Hub::load(hub_id).await {
+            if hub.members.contains_key(user_id) {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Makes a key sign in as a user and saves the change, see [`IdentityLinks::link`]. Checking that the actor is a server admin is up to the caller.
+/// Only keys that were never used as an account can be linked to another user, so that nobody loses access to their own data.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The fingerprint or the user ID is not a hex encoded fingerprint, [`Error::InvalidFingerprint`].
+/// * [`Error::KeyInUse`] if the key belongs to another user, see [`IdentityLinks::link`], or has data of its own, see [`has_user_data`].
+/// * The links could not be loaded or saved for any of the reasons outlined in [`IdentityLinks::load`] and [`IdentityLinks::save`].
+pub async fn link(actor_id: &str, user_id: &str, request: &LinkRequest) -> Result<LinkResult> {
+    let fingerprint = request.fingerprint.to_uppercase();
+    let user_id = user_id.to_uppercase();
+    for id in [&fingerprint, &user_id].iter() {
+        if id.len() != 40 || hex::decode(id).is_err() {
+            return Err(Error::InvalidFingerprint);
+        }
+    }
+    let _guard = LINKS_LOCK.lock(()).await;
+    let mut links = IdentityLinks::load().await?;
+    if fingerprint != links.resolve(&user_id) && has_user_data(&fingerprint).await? {
+        return Err(Error::KeyInUse);
+    }
+    let result = links.link(
+        &fingerprint,
+        &user_id,
+        actor_id,
+        request.revoke_others,
+        Utc::now(),
+    )?;
+    links.save().await?;
+    *LINKS.lock().unwrap_or_else(|err| err.into_inner()) = Some(links);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use super::{has_user_data, IdentityLinks};
+    use crate::{
+        error::Error,
+        quotas::{UserQuota, USER_QUOTAS_FOLDER},
+    };
+
+    const USER: &str = "0123456789ABCDEF0123456789ABCDEF01234567";
+    const NEW_KEY: &str = "89ABCDEF0123456789ABCDEF0123456789ABCDEF";
+    const OTHER_KEY: &str = "FEDCBA9876543210FEDCBA9876543210FEDCBA98";
+
+    #[test]
+    fn linked_keys_sign_in_as_the_user() {
+        let mut links = IdentityLinks::default();
+        assert_eq!(links.user_id(NEW_KEY).unwrap(), NEW_KEY);
+        let result = links
+            .link(NEW_KEY, USER, "admin", false, Utc::now())
+            .unwrap();
+        assert!(result.revoked.is_empty());
+        assert_eq!(links.user_id(NEW_KEY).unwrap(), USER);
+        assert_eq!(links.user_id(USER).unwrap(), USER);
+
+        // Linking to a linked key links to its user, revoking the others locks out the old keys.
+        let result = links
+            .link(OTHER_KEY, NEW_KEY, "admin", true, Utc::now())
+            .unwrap();
+        assert_eq!(result.user_id, USER);
+        assert_eq!(result.revoked, vec![USER.to_string(), NEW_KEY.to_string()]);
+        assert_eq!(links.user_id(OTHER_KEY).unwrap(), USER);
+        assert!(links.user_id(USER).is_err());
+        assert!(links.user_id(NEW_KEY).is_err());
+
+        // Linking the user's own key again restores it.
+        links.link(USER, USER, "admin", false, Utc::now()).unwrap();
+        assert_eq!(links.user_id(USER).unwrap(), USER);
+    }
+
+    #[test]
+    fn keys_of_other_users_can_not_be_linked() {
+        let mut links = IdentityLinks::default();
+        links
+            .link(NEW_KEY, USER, "admin", false, Utc::now())
+            .unwrap();
+
+        // A key that signs in as a user can not be moved to another one, linking it to the same user again is fine.
+        assert!(matches!(
+            links.link(NEW_KEY, OTHER_KEY, "admin", false, Utc::now()),
+            Err(Error::KeyInUse)
+        ));
+        links
+            .link(NEW_KEY, USER, "admin", false, Utc::now())
+            .unwrap();
+
+        // Neither can the key of a user that other keys sign in as.
+        assert!(matches!(
+            links.link(USER, OTHER_KEY, "admin", false, Utc::now()),
+            Err(Error::KeyInUse)
+        ));
+        assert_eq!(links.user_id(NEW_KEY).unwrap(), USER);
+        assert_eq!(links.user_id(USER).unwrap(), USER);
+    }
+
+    #[tokio::test]
+    async fn keys_with_data_can_not_be_linked() {
+        let _ = std::fs::remove_file(UserQuota::get_path(OTHER_KEY));
+        assert!(!has_user_data(OTHER_KEY).await.unwrap());
+        std::fs::create_dir_all(USER_QUOTAS_FOLDER).unwrap();
+        std::fs::write(UserQuota::get_path(OTHER_KEY), b"").unwrap();
+        assert!(has_user_data(OTHER_KEY).await.unwrap());
+        std::fs::remove_file(UserQuota::get_path(OTHER_KEY)).unwrap();
+    }
+}
@@ -0,0 +1,173 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    hub_images::{MAX_BANNER_SIZE, MAX_ICON_SIZE},
+    websocket::{MAX_FRAME_SIZE, PROTOCOL_VERSION, SUBPROTOCOL},
+    MAX_MESSAGES_PER_REQUEST, MESSAGE_MAX_SIZE,
+};
+
+/// Versions of the HTTP API served by this server, as used in the first segment of route paths.
+pub const API_VERSIONS: &[&str] = &["v3"];
+
+/// How users sign in, the server has no accounts of its own.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    /// Requests are signed with the user's PGP key, which is looked up on the key server.
+    PgpSignature,
+}
+
+/// Who can use the server and how users get into hubs.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RegistrationPolicy {
+    /// True if any user with a PGP key can use the server without being registered first.
+    pub open: bool,
+    /// True if hubs can be joined with invite codes, see [`crate::invites`].
+    pub invites: bool,
+    /// Number of hubs a user can own, `None` if there is no limit.
+    pub max_hubs_per_user: Option<usize>,
+}
+
+/// Limits clients should respect so their requests are not rejected.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct InstanceLimits {
+    /// Maximum size in bytes of a message.
+    pub max_message_length: usize,
+    /// Maximum number of messages returned by one request.
+    pub max_messages_per_request: usize,
+    /// Maximum size in bytes of a hub icon.
+    pub max_icon_size: usize,
+    /// Maximum size in bytes of an upload, currently the largest hub image.
+    pub max_upload_size: usize,
+    /// Maximum total size in bytes of the messages a user can send per UTC day, `None` if there is no limit.
+    pub max_message_bytes_per_day: Option<u64>,
+}
+
+/// How to connect to the WebSocket API.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct WebSocketInfo {
+    /// Path of the WebSocket endpoint, relative to the address the document was fetched from.
+    pub path: String,
+    /// Subprotocol clients have to request in the handshake.
+    pub subprotocol: String,
+    /// WebSocket protocol versions the server speaks.
+    pub protocol_versions: Vec<u32>,
+    /// Maximum size in bytes of a WebSocket frame.
+    pub max_frame_size: usize,
+    /// Number of commands a connection can send per minute, `None` if there is no limit.
+    pub commands_per_minute: Option<u32>,
+    /// Number of connections a user can have open at the same time, `None` if there is no limit.
+    pub max_connections_per_user: Option<usize>,
+    /// Number of hubs a connection can be subscribed to, `None` if there is no limit.
+    pub max_hub_subscriptions: Option<usize>,
+    /// Number of channels a connection can be subscribed to, `None` if there is no limit.
+    pub max_channel_subscriptions: Option<usize>,
+}
+
+/// Description of the server that clients can read before signing in, served at `/v3/instance`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct InstanceInfo {
+    /// Version of the server, `None` unless `show_version` is set in the configuration.
+    pub server_version: Option<String>,
+    /// Versions of the HTTP API, see [`API_VERSIONS`].
+    pub api_versions: Vec<String>,
+    pub auth_methods: Vec<AuthMethod>,
+    /// Fingerprint of the key the server signs its responses with.
+    pub public_key_fingerprint: String,
+    /// Key server that the keys of users are looked up on.
+    pub key_server: String,
+    pub registration: RegistrationPolicy,
+    pub limits: InstanceLimits,
+    /// `None` if the server was built without the WebSocket API.
+    pub websocket: Option<WebSocketInfo>,
+    /// Optional parts of the server that it was built with, for example `search`, `graphql` and `markdown`.
+    pub features: Vec<String>,
+}
+
+impl InstanceInfo {
+    /// Creates the description of a server from its configuration and the features it was built with.
+    pub fn new(config: &Config, public_key_fingerprint: String) -> Self {
+        let features = [
+            ("graphql", cfg!(feature = "graphql")),
+            ("markdown", cfg!(feature = "markdown")),
+            ("search", cfg!(feature = "search")),
+            ("websocket", cfg!(feature = "websocket")),
+        ];
+        Self {
+            server_version: if config.show_version {
+                Some(env!("CARGO_PKG_VERSION").to_string())
+            } else {
+                None
+            },
+            api_versions: API_VERSIONS.iter().map(|v| v.to_string()).collect(),
+            auth_methods: vec![AuthMethod::PgpSignature],
+            public_key_fingerprint,
+            key_server: config.key_server.clone(),
+            registration: RegistrationPolicy {
+                open: true,
+                invites: true,
+                max_hubs_per_user: config.limits.max_hubs_per_user,
+            },
+            limits: InstanceLimits {
+                max_message_length: MESSAGE_MAX_SIZE,
+                max_messages_per_request: MAX_MESSAGES_PER_REQUEST,
+                max_icon_size: MAX_ICON_SIZE,
+                max_upload_size: MAX_BANNER_SIZE.max(MAX_ICON_SIZE),
+                max_message_bytes_per_day: config.limits.max_message_bytes_per_day,
+            },
+            websocket: if cfg!(feature = "websocket") {
+                Some(WebSocketInfo {
+                    path: "/v3/websocket".to_string(),
+                    subprotocol: SUBPROTOCOL.to_string(),
+                    protocol_versions: vec![PROTOCOL_VERSION],
+                    max_frame_size: MAX_FRAME_SIZE,
+                    commands_per_minute: config.limits.max_websocket_commands_per_minute,
+                    max_connections_per_user: config.limits.max_connections_per_user,
+                    max_hub_subscriptions: config.limits.max_hub_subscriptions_per_connection,
+                    max_channel_subscriptions: config
+                        .limits
+                        .max_channel_subscriptions_per_connection,
+                })
+            } else {
+                None
+            },
+            features: features
+                .iter()
+                .filter(|(_, enabled)| *enabled)
+                .map(|(name, _)| name.to_string())
+                .collect(),
+        }
+    }
+}
+
+#[cfg(all(
+    test,
+    feature = "graphql",
+    feature = "markdown",
+    feature = "search",
+    feature = "websocket"
+))]
+mod test {
+    use super::InstanceInfo;
+    use crate::config::{Config, LimitsConfig};
+
+    #[test]
+    fn instance_snapshot() {
+        let config = Config {
+            show_version: false,
+            limits: LimitsConfig {
+                max_hubs_per_user: Some(10),
+                max_message_bytes_per_day: None,
+                max_websocket_commands_per_minute: Some(120),
+                ..LimitsConfig::default()
+            },
+            ..Config::default()
+        };
+        let info = InstanceInfo::new(&config, "0123456789ABCDEF".to_string());
+        assert_eq!(
+            serde_json::to_string(&info).unwrap(),
+            r#"{"server_version":null,"api_versions":["v3"],"auth_methods":["pgp_signature"],"public_key_fingerprint":"0123456789ABCDEF","key_server":"https://keys.openpgp.org","registration":{"open":true,"invites":true,"max_hubs_per_user":10},"limits":{"max_message_length":8192,"max_messages_per_request":256,"max_icon_size":262144,"max_upload_size":1048576,"max_message_bytes_per_day":null},"websocket":{"path":"/v3/websocket","subprotocol":"wicrs","protocol_versions":[3],"max_frame_size":65536,"commands_per_minute":120,"max_connections_per_user":10,"max_hub_subscriptions":100,"max_channel_subscriptions":500},"features":["graphql","markdown","search","websocket"]}"#
+        );
+    }
+}
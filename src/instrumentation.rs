@@ -0,0 +1,340 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use xactor::{Actor, Addr, Handler, Message};
+
+use crate::{config::InstrumentationConfig, hub_activity::HubActivityTotals};
+
+/// Number of handling latency samples kept per actor for calculating percentiles.
+pub const LATENCY_SAMPLES: usize = 1024;
+
+/// Number of search indexes that had to be recovered since the server started, see [`record_index_recovery`].
+static INDEX_RECOVERIES: AtomicU64 = AtomicU64::new(0);
+
+/// Counts a search index that could not be opened and was recovered by removing stale lock files or rebuilding it.
+pub fn record_index_recovery() {
+    INDEX_RECOVERIES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Shared handle to the statistics of the server actors.
+pub struct Instrumentation {
+    /// Statistics for the [`crate::server::Server`] actor.
+    pub server: Arc<ActorStats>,
+    /// Statistics for the [`crate::server::MessageServer`] actor.
+    pub message_server: Arc<ActorStats>,
+    /// Sizes and durations of the notifications the [`crate::server::Server`] sends to its connections.
+    pub fanout: Arc<FanoutStats>,
+    /// Whether snapshots list the activity of each hub.
+    hub_activity_labels: bool,
+}
+
+impl Instrumentation {
+    /// Creates a new instrumentation handle, warnings are logged when the limits in the given configuration are exceeded.
+    pub fn new(config: &InstrumentationConfig) -> Self {
+        Self {
+            server: Arc::new(ActorStats::new("server", config)),
+            message_server: Arc::new(ActorStats::new("message_server", config)),
+            fanout: Arc::new(FanoutStats::default()),
+            hub_activity_labels: config.hub_activity_labels,
+        }
+    }
+
+    /// Gets the current statistics of all of the actors.
+    pub fn snapshot(&self) -> InstrumentationSnapshot {
+        InstrumentationSnapshot {
+            server: self.server.snapshot(),
+            message_server: self.message_server.snapshot(),
+            fanout: self.fanout.snapshot(),
+            index_recoveries: INDEX_RECOVERIES.load(Ordering::Relaxed),
+            hubs: if self.hub_activity_labels {
+                crate::hub_activity::totals()
+            } else {
+                Vec::new()
+            },
+        }
+    }
+}
+
+impl Default for Instrumentation {
+    fn default() -> Self {
+        Self::new(&InstrumentationConfig::default())
+    }
+}
+
+/// Mailbox and handling latency statistics for a single actor.
+pub struct ActorStats {
+    name: &'static str,
+    warn_mailbox_depth: i64,
+    warn_latency: Duration,
+    pending: AtomicI64,
+    handled: AtomicU64,
+    failed: AtomicU64,
+    samples: Mutex<VecDeque<u64>>,
+}
+
+impl ActorStats {
+    /// Creates a new set of statistics for the actor with the given name.
+    pub fn new(name: &'static str, config: &InstrumentationConfig) -> Self {
+        Self {
+            name,
+            warn_mailbox_depth: config.warn_mailbox_depth as i64,
+            warn_latency: Duration::from_millis(config.warn_latency_ms),
+            pending: AtomicI64::new(0),
+            handled: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            samples: Mutex::new(VecDeque::with_capacity(LATENCY_SAMPLES)),
+        }
+    }
+
+    /// Marks a message as sent to the actor, should be called right before the message is sent. Messages sent through an [`InstrumentedAddr`] are marked automatically.
+    pub fn sent(&self) {
+        let depth = self.pending.fetch_add(1, Ordering::Relaxed) + 1;
+        if depth > self.warn_mailbox_depth {
+            warn!(
+                "The {} actor has approximately {} messages waiting to be handled.",
+                self.name, depth
+            );
+        }
+    }
+
+    /// Takes back a message marked with [`ActorStats::sent`] that could not be sent.
+    pub fn unsent(&self) {
+        let _ = self
+            .pending
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |pending| {
+                Some((pending - 1).max(0))
+            });
+    }
+
+    /// Gets the approximate number of messages waiting to be handled by the actor.
+    pub fn mailbox_depth(&self) -> u64 {
+        self.pending.load(Ordering::Relaxed).max(0) as u64
+    }
+
+    /// Counts a message that was sent without waiting for it to be handled and could not be handled.
+    pub fn failed(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Starts timing the handling of a message, the time is recorded when the returned timer is dropped.
+    pub fn start(self: Arc<Self>) -> HandlerTimer {
+        HandlerTimer {
+            stats: self,
+            started: Instant::now(),
+        }
+    }
+
+    /// Records that a message was handled in the given amount of time.
+    fn record(&self, elapsed: Duration) {
+        self.unsent();
+        self.handled.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut samples) = self.samples.lock() {
+            if samples.len() == LATENCY_SAMPLES {
+                samples.pop_front();
+            }
+            samples.push_back(elapsed.as_micros() as u64);
+        }
+        if elapsed > self.warn_latency {
+            warn!(
+                "The {} actor took {}ms to handle a message.",
+                self.name,
+                elapsed.as_millis()
+            );
+        }
+    }
+
+    /// Gets the current statistics of the actor.
+    pub fn snapshot(&self) -> ActorStatsSnapshot {
+        let mut samples: Vec<u64> = self
+            .samples
+            .lock()
+            .map(|samples| samples.iter().copied().collect())
+            .unwrap_or_default();
+        samples.sort_unstable();
+        ActorStatsSnapshot {
+            mailbox_depth: self.mailbox_depth(),
+            handled: self.handled.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            p50_latency_us: percentile(&samples, 50),
+            p99_latency_us: percentile(&samples, 99),
+        }
+    }
+}
+
+/// Number of connections and time taken for each notification the [`crate::server::Server`] sends, notifications without any subscribers are not counted.
+#[derive(Default)]
+pub struct FanoutStats {
+    fanouts: AtomicU64,
+    connections: AtomicU64,
+    largest: AtomicU64,
+    samples: Mutex<VecDeque<u64>>,
+}
+
+impl FanoutStats {
+    /// Records that a notification was sent to the given number of connections in the given amount of time.
+    pub fn record(&self, connections: usize, elapsed: Duration) {
+        self.fanouts.fetch_add(1, Ordering::Relaxed);
+        self.connections
+            .fetch_add(connections as u64, Ordering::Relaxed);
+        self.largest
+            .fetch_max(connections as u64, Ordering::Relaxed);
+        if let Ok(mut samples) = self.samples.lock() {
+            if samples.len() == LATENCY_SAMPLES {
+                samples.pop_front();
+            }
+            samples.push_back(elapsed.as_micros() as u64);
+        }
+    }
+
+    /// Gets the current fan-out statistics.
+    pub fn snapshot(&self) -> FanoutSnapshot {
+        let mut samples: Vec<u64> = self
+            .samples
+            .lock()
+            .map(|samples| samples.iter().copied().collect())
+            .unwrap_or_default();
+        samples.sort_unstable();
+        FanoutSnapshot {
+            notifications: self.fanouts.load(Ordering::Relaxed),
+            connections: self.connections.load(Ordering::Relaxed),
+            largest: self.largest.load(Ordering::Relaxed),
+            p50_duration_us: percentile(&samples, 50),
+            p99_duration_us: percentile(&samples, 99),
+        }
+    }
+}
+
+/// Gets the given percentile of a sorted list of samples, zero if there are no samples.
+fn percentile(sorted: &[u64], percentile: usize) -> u64 {
+    if sorted.is_empty() {
+        0
+    } else {
+        sorted[(sorted.len() - 1) * percentile / 100]
+    }
+}
+
+/// Address of an actor that marks every message sent through it as waiting in the actor's mailbox (see [`ActorStats::sent`]), the actor's handlers mark them as handled with [`ActorStats::start`].
+pub struct InstrumentedAddr<A> {
+    addr: Addr<A>,
+    stats: Arc<ActorStats>,
+}
+
+impl<A: Actor> InstrumentedAddr<A> {
+    /// Wraps the address of an actor whose handlers record their messages in `stats`.
+    pub fn new(addr: Addr<A>, stats: Arc<ActorStats>) -> Self {
+        Self { addr, stats }
+    }
+
+    /// Gets the address of the actor, messages sent through it directly are not counted.
+    pub fn addr(&self) -> &Addr<A> {
+        &self.addr
+    }
+
+    /// Sends a message to the actor without waiting for it to be handled, see [`Addr::send`].
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the actor has stopped.
+    pub fn send<T: Message<Result = ()>>(&self, msg: T) -> xactor::Result<()>
+    where
+        A: Handler<T>,
+    {
+        self.stats.sent();
+        let result = self.addr.send(msg);
+        if result.is_err() {
+            self.stats.unsent();
+        }
+        result
+    }
+
+    /// Sends a message to the actor and waits for it to be handled, see [`Addr::call`].
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the actor has stopped.
+    pub async fn call<T: Message>(&self, msg: T) -> xactor::Result<T::Result>
+    where
+        A: Handler<T>,
+    {
+        self.stats.sent();
+        let result = self.addr.call(msg).await;
+        if result.is_err() {
+            self.stats.unsent();
+        }
+        result
+    }
+}
+
+impl<A> Clone for InstrumentedAddr<A> {
+    fn clone(&self) -> Self {
+        Self {
+            addr: self.addr.clone(),
+            stats: self.stats.clone(),
+        }
+    }
+}
+
+/// Times the handling of a message by an actor, see [`ActorStats::start`].
+pub struct HandlerTimer {
+    stats: Arc<ActorStats>,
+    started: Instant,
+}
+
+impl Drop for HandlerTimer {
+    fn drop(&mut self) {
+        self.stats.record(self.started.elapsed());
+    }
+}
+
+/// Statistics of a single actor at a point in time.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ActorStatsSnapshot {
+    /// Approximate number of messages waiting to be handled by the actor.
+    pub mailbox_depth: u64,
+    /// Number of messages the actor has handled.
+    pub handled: u64,
+    /// Number of messages that were sent without waiting for them and could not be handled.
+    #[serde(default)]
+    pub failed: u64,
+    /// Median message handling time in microseconds.
+    pub p50_latency_us: u64,
+    /// 99th percentile message handling time in microseconds.
+    pub p99_latency_us: u64,
+}
+
+/// Fan-out statistics at a point in time, see [`FanoutStats`].
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct FanoutSnapshot {
+    /// Number of notifications that were sent to at least one connection.
+    pub notifications: u64,
+    /// Total number of connections the notifications were sent to.
+    pub connections: u64,
+    /// Largest number of connections one notification was sent to.
+    pub largest: u64,
+    /// Median time in microseconds taken to send a notification to all of its connections.
+    pub p50_duration_us: u64,
+    /// 99th percentile time in microseconds taken to send a notification to all of its connections.
+    pub p99_duration_us: u64,
+}
+
+/// Statistics of all of the server actors at a point in time.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct InstrumentationSnapshot {
+    pub server: ActorStatsSnapshot,
+    pub message_server: ActorStatsSnapshot,
+    #[serde(default)]
+    pub fanout: FanoutSnapshot,
+    /// Number of search indexes that were recovered since the server started.
+    #[serde(default)]
+    pub index_recoveries: u64,
+    /// Activity of each hub since the server started, only listed if enabled in the configuration.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hubs: Vec<HubActivityTotals>,
+}
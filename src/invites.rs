@@ -0,0 +1,562 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    net::IpAddr,
+    path::PathBuf,
+    sync::Mutex as SyncMutex,
+    time::Instant,
+};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::IoContext,
+    hub_preview::{HubPreview, RateLimiter},
+    locks::{KeyedLock, KeyedLocks},
+    HubId, Result,
+};
+
+/// Folder the invite index is stored in.
+pub const INVITES_FOLDER: &str = "data/";
+
+/// File that maps the codes of all invites of the server to the invites, so codes can be resolved without looking at every hub.
+pub const INVITES_FILE: &str = "data/invites";
+
+/// Bytes at the start of the invite index file, the index after them is stored in the layout of this version.
+pub const INVITES_MAGIC: &[u8; 8] = b"WICRSIV1";
+
+/// Folder where the joins made with the invites of each hub are stored, see [`InviteJoins`].
+pub const INVITE_JOINS_FOLDER: &str = "data/hubs/invite_joins/";
+
+/// Characters codes are made of: digits and uppercase letters without `0`, `1`, `I` and `O`, which are easily confused with each other.
+pub const CODE_ALPHABET: &[u8; 32] = b"23456789ABCDEFGHJKLMNPQRSTUVWXYZ";
+
+/// Length of new codes, codes only get longer when many codes of this length are taken.
+pub const MIN_CODE_LENGTH: usize = 6;
+
+/// Length of the longest codes.
+pub const MAX_CODE_LENGTH: usize = 10;
+
+/// Number of random codes tried at each length before trying a longer one.
+const ATTEMPTS_PER_LENGTH: usize = 8;
+
+/// Makes sure only one change is made to the invite index at a time, see [`lock`].
+static INDEX_LOCK: KeyedLocks<()> = KeyedLocks::new();
+
+/// Counts the code resolutions made from each address, see [`allow_request`].
+static RESOLVE_REQUESTS: SyncMutex<Option<RateLimiter>> = SyncMutex::new(None);
+
+/// An invite to a hub, users that have its code can join the hub.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Invite {
+    pub code: String,
+    pub hub_id: HubId,
+    /// ID of the user that created the invite.
+    pub created_by: String,
+    pub created: DateTime<Utc>,
+    /// Time after which the invite can no longer be used, `None` if it does not expire.
+    pub expires: Option<DateTime<Utc>>,
+    /// Number of times the invite can be used, `None` if there is no limit.
+    pub max_uses: Option<u32>,
+    /// Number of users that joined the hub with the invite.
+    pub uses: u32,
+    /// Last time a user joined the hub with the invite, `None` if it was never used.
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+/// Layout of the invites in index files without [`INVITES_MAGIC`], from before the last use of invites was recorded.
+#[derive(Deserialize)]
+struct InviteWithoutLastUse {
+    code: String,
+    hub_id: HubId,
+    created_by: String,
+    created: DateTime<Utc>,
+    expires: Option<DateTime<Utc>>,
+    max_uses: Option<u32>,
+    uses: u32,
+}
+
+impl From<InviteWithoutLastUse> for Invite {
+    fn from(invite: InviteWithoutLastUse) -> Self {
+        Self {
+            code: invite.code,
+            hub_id: invite.hub_id,
+            created_by: invite.created_by,
+            created: invite.created,
+            expires: invite.expires,
+            max_uses: invite.max_uses,
+            uses: invite.uses,
+            last_used: None,
+        }
+    }
+}
+
+impl Invite {
+    /// Checks if the invite has not expired and has uses left.
+    pub fn is_usable(&self, now: DateTime<Utc>) -> bool {
+        self.expires.is_none_or(|expires| now < expires)
+            && self.max_uses.is_none_or(|max_uses| self.uses < max_uses)
+    }
+}
+
+/// Options for a new invite, given by the user that creates it.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct InviteOptions {
+    /// Number of hours the invite can be used for, `None` if it does not expire.
+    pub expires_in_hours: Option<u32>,
+    /// Number of times the invite can be used, `None` if there is no limit.
+    pub max_uses: Option<u32>,
+}
+
+/// What a code points at, returned when a code is resolved.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ResolvedCode {
+    /// An invite to a hub, with a preview of the hub.
+    HubInvite {
+        code: String,
+        hub: HubPreview,
+        expires: Option<DateTime<Utc>>,
+    },
+}
+
+/// The invites of all hubs of the server, by code.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct InviteIndex {
+    pub invites: HashMap<String, Invite>,
+}
+
+impl InviteIndex {
+    /// Loads the invite index, if there is no index there are no invites.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The file exists but could not be read.
+    /// * The file's contents could not be deserialized.
+    pub async fn load() -> Result<Self> {
+        match tokio::fs::read(INVITES_FILE).await {
+            Ok(bytes) => Self::decode(&bytes),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_path(INVITES_FILE),
+        }
+    }
+
+    /// Saves the invite index, callers should hold the lock from [`lock`] since loading the index.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The data folder does not exist and could not be created.
+    /// * The data could not be written to the disk.
+    pub async fn save(&self) -> Result {
+        tokio::fs::create_dir_all(INVITES_FOLDER)
+            .await
+            .with_path(INVITES_FOLDER)?;
+        tokio::fs::write(INVITES_FILE, self.encode()?)
+            .await
+            .with_path(INVITES_FILE)
+    }
+
+    /// Decodes the contents of the index file, files without [`INVITES_MAGIC`] are read in the layout they were written in.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the index could not be deserialized.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        match bytes.strip_prefix(&INVITES_MAGIC[..]) {
+            Some(index) => Ok(bincode::deserialize(index)?),
+            None => {
+                let invites: HashMap<String, InviteWithoutLastUse> = bincode::deserialize(bytes)?;
+                Ok(Self {
+                    invites: invites
+                        .into_iter()
+                        .map(|(code, invite)| (code, invite.into()))
+                        .collect(),
+                })
+            }
+        }
+    }
+
+    /// Encodes the index as the contents of the index file.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the index could not be serialized.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let mut bytes = INVITES_MAGIC.to_vec();
+        bytes.append(&mut bincode::serialize(self)?);
+        Ok(bytes)
+    }
+
+    /// Generates a random code that is not used by any invite, see [`CODE_ALPHABET`].
+    /// Returns `None` if no free code was found, which only happens if nearly every code is taken.
+    pub fn generate_code<R: Rng>(&self, rng: &mut R) -> Option<String> {
+        (MIN_CODE_LENGTH..=MAX_CODE_LENGTH)
+            .flat_map(|length| std::iter::repeat_n(length, ATTEMPTS_PER_LENGTH))
+            .map(|length| {
+                (0..length)
+                    .map(|_| CODE_ALPHABET[rng.gen_range(0..CODE_ALPHABET.len())] as char)
+                    .collect::<String>()
+            })
+            .find(|code| !self.invites.contains_key(code))
+    }
+
+    /// Gets an invite that can still be used, expired and used up invites are treated as if they never existed.
+    pub fn get_usable(&self, code: &str, now: DateTime<Utc>) -> Option<&Invite> {
+        normalize_code(code)
+            .and_then(|code| self.invites.get(&code))
+            .filter(|invite| invite.is_usable(now))
+    }
+
+    /// Removes the invites that can no longer be used, returns true if any were removed.
+    pub fn prune(&mut self, now: DateTime<Utc>) -> bool {
+        let count = self.invites.len();
+        self.invites.retain(|_, invite| invite.is_usable(now));
+        self.invites.len() != count
+    }
+
+    /// Gets the invites of a hub, oldest first.
+    pub fn hub_invites(&self, hub_id: HubId) -> Vec<Invite> {
+        let mut invites: Vec<Invite> = self
+            .invites
+            .values()
+            .filter(|invite| invite.hub_id == hub_id)
+            .cloned()
+            .collect();
+        invites.sort_by(|a, b| a.created.cmp(&b.created).then(a.code.cmp(&b.code)));
+        invites
+    }
+}
+
+/// A user joining a hub with an invite.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct InviteJoin {
+    pub user_id: String,
+    /// Code of the invite, kept after the invite is revoked or can no longer be used.
+    pub code: String,
+    pub joined: DateTime<Utc>,
+}
+
+/// How a hub's members joined with one of its invites, served by `/v3/invite_stats/{hub_id}`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct InviteStats {
+    pub code: String,
+    /// Number of times a user joined the hub with the invite.
+    pub joins: u32,
+    /// Number of users still in the hub whose last join was with the invite.
+    pub members: u32,
+    /// Number of joins made on each day (UTC), days without joins are left out.
+    pub days: BTreeMap<NaiveDate, u32>,
+    /// The invite, `None` if it was revoked or can no longer be used.
+    pub invite: Option<Invite>,
+}
+
+/// The joins made with the invites of a hub, stored separately from the invite index so that members stay attributed to invites that were removed.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct InviteJoins {
+    /// Every join, oldest first.
+    pub joins: Vec<InviteJoin>,
+}
+
+impl InviteJoins {
+    /// Gets the path of the file that the joins of a hub's invites are stored in.
+    pub fn get_path(hub_id: HubId) -> PathBuf {
+        crate::paths::invite_joins_file(hub_id)
+    }
+
+    /// Loads the joins made with the invites of a hub, a hub without the file has none.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The file exists but could not be read.
+    /// * The file's contents could not be deserialized.
+    pub async fn load(hub_id: HubId) -> Result<Self> {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(bincode::deserialize(&bytes)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_path(path),
+        }
+    }
+
+    /// Saves the joins made with the invites of a hub, callers should hold the lock from [`lock`] since loading them.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The invite joins folder does not exist and could not be created.
+    /// * The data could not be written to the disk.
+    pub async fn save(&self, hub_id: HubId) -> Result {
+        tokio::fs::create_dir_all(INVITE_JOINS_FOLDER)
+            .await
+            .with_path(INVITE_JOINS_FOLDER)?;
+        let path = Self::get_path(hub_id);
+        tokio::fs::write(&path, bincode::serialize(self)?)
+            .await
+            .with_path(path)
+    }
+
+    /// Removes the joins made with the invites of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the file exists but could not be removed.
+    pub async fn remove(hub_id: HubId) -> Result {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::remove_file(&path).await {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result.with_path(path),
+        }
+    }
+
+    /// Gets the code of the invite a user last joined the hub with, `None` if they never joined it with an invite.
+    pub fn invited_by(&self, user_id: &str) -> Option<&str> {
+        self.joins
+            .iter()
+            .rev()
+            .find(|join| join.user_id == user_id)
+            .map(|join| join.code.as_str())
+    }
+
+    /// Counts the joins made with each invite, ordered by code. `invites` are the usable invites of the hub, they are included even if nobody joined with them yet.
+    /// `is_member` tells if a user is still in the hub.
+    pub fn stats(
+        &self,
+        invites: Vec<Invite>,
+        is_member: impl Fn(&str) -> bool,
+    ) -> Vec<InviteStats> {
+        let empty = |code: &str| InviteStats {
+            code: code.to_string(),
+            joins: 0,
+            members: 0,
+            days: BTreeMap::new(),
+            invite: None,
+        };
+        let mut stats: BTreeMap<String, InviteStats> = BTreeMap::new();
+        for invite in invites {
+            let entry = stats
+                .entry(invite.code.clone())
+                .or_insert_with(|| empty(&invite.code));
+            entry.invite = Some(invite);
+        }
+        for join in self.joins.iter() {
+            let entry = stats
+                .entry(join.code.clone())
+                .or_insert_with(|| empty(&join.code));
+            entry.joins = entry.joins.saturating_add(1);
+            *entry.days.entry(join.joined.date_naive()).or_default() += 1;
+        }
+        let users: HashSet<&str> = self
+            .joins
+            .iter()
+            .map(|join| join.user_id.as_str())
+            .collect();
+        for user_id in users.into_iter().filter(|user_id| is_member(user_id)) {
+            if let Some(entry) = self
+                .invited_by(user_id)
+                .and_then(|code| stats.get_mut(code))
+            {
+                entry.members += 1;
+            }
+        }
+        stats.into_values().collect()
+    }
+}
+
+/// Converts a code typed by a user to the form it is stored in: surrounding whitespace is removed and letters are made uppercase.
+/// Returns `None` if it can not be a code, so that lookups of malformed codes fail the same way as lookups of unknown codes.
+pub fn normalize_code(code: &str) -> Option<String> {
+    let code = code.trim().to_ascii_uppercase();
+    if (MIN_CODE_LENGTH..=MAX_CODE_LENGTH).contains(&code.len())
+        && code.bytes().all(|byte| CODE_ALPHABET.contains(&byte))
+    {
+        Some(code)
+    } else {
+        None
+    }
+}
+
+/// Locks the invite index so that no other change is made to it until the guard is dropped.
+pub async fn lock() -> KeyedLock<'static, ()> {
+    INDEX_LOCK.lock(()).await
+}
+
+/// Removes all invites of a hub and the joins made with them, used when the hub is deleted.
+///
+/// # Errors
+///
+/// This function returns an error if the index could not be loaded or saved or the joins could not be removed, see [`InviteIndex::load`], [`InviteIndex::save`] and [`InviteJoins::remove`].
+pub async fn remove_hub(hub_id: HubId) -> Result {
+    let _lock = lock().await;
+    let mut index = InviteIndex::load().await?;
+    let count = index.invites.len();
+    index.invites.retain(|_, invite| invite.hub_id != hub_id);
+    if index.invites.len() != count {
+        index.save().await?;
+    }
+    InviteJoins::remove(hub_id).await
+}
+
+/// Counts a code resolution from an address against the configured limit (see [`crate::config::LimitsConfig`]), returns false if it goes over it.
+pub fn allow_request(address: IpAddr) -> bool {
+    let limit = crate::quotas::limits().max_code_resolves_per_minute;
+    RESOLVE_REQUESTS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .get_or_insert_with(RateLimiter::default)
+        .allow(address, Instant::now(), limit)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{
+        normalize_code, Invite, InviteIndex, InviteJoin, InviteJoins, CODE_ALPHABET, INVITES_MAGIC,
+        MIN_CODE_LENGTH,
+    };
+    use crate::HubId;
+
+    fn invite(code: &str) -> Invite {
+        Invite {
+            code: code.to_string(),
+            hub_id: HubId::from_u128(1),
+            created_by: "owner".to_string(),
+            created: Utc::now(),
+            expires: None,
+            max_uses: None,
+            uses: 0,
+            last_used: None,
+        }
+    }
+
+    #[test]
+    fn codes_avoid_ambiguous_characters_and_collisions() {
+        for ambiguous in b"01IO" {
+            assert!(!CODE_ALPHABET.contains(ambiguous));
+        }
+        let mut index = InviteIndex::default();
+        let mut rng = StdRng::seed_from_u64(1);
+        let first = index.generate_code(&mut rng).unwrap();
+        assert_eq!(first.len(), MIN_CODE_LENGTH);
+        index.invites.insert(first.clone(), invite(&first));
+        // The same sequence of codes is generated again, so the first one has to be skipped.
+        let second = index.generate_code(&mut StdRng::seed_from_u64(1)).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(
+            normalize_code(&format!(" {} ", first.to_lowercase())),
+            Some(first)
+        );
+        assert_eq!(normalize_code("ABC0EF"), None);
+    }
+
+    #[test]
+    fn unusable_invites_look_like_missing_ones() {
+        let now = Utc::now();
+        let mut index = InviteIndex::default();
+        let mut expired = invite("XPRD2345");
+        expired.expires = Some(now - Duration::hours(1));
+        let mut used = invite("USEDUP22");
+        used.max_uses = Some(1);
+        used.uses = 1;
+        for invite in [expired, used, invite("VALD2345")] {
+            index.invites.insert(invite.code.clone(), invite);
+        }
+        assert!(index.get_usable("XPRD2345", now).is_none());
+        assert!(index.get_usable("USEDUP22", now).is_none());
+        assert!(index.get_usable("MSSNG234", now).is_none());
+        assert!(index.get_usable("vald2345", now).is_some());
+        assert!(index.prune(now));
+        assert_eq!(index.invites.len(), 1);
+    }
+
+    #[test]
+    fn indexes_without_last_use_load() {
+        let mut index = InviteIndex::default();
+        let mut used = invite("USED2345");
+        used.uses = 3;
+        index.invites.insert(used.code.clone(), used.clone());
+        let bytes = index.encode().unwrap();
+        assert!(bytes.starts_with(INVITES_MAGIC));
+        assert_eq!(InviteIndex::decode(&bytes).unwrap(), index);
+        // Written before `last_used` was added: the same fields without it and no header.
+        let mut legacy = 1u64.to_le_bytes().to_vec();
+        legacy.append(&mut bincode::serialize(&used.code).unwrap());
+        legacy.append(
+            &mut bincode::serialize(&(
+                &used.code,
+                used.hub_id,
+                &used.created_by,
+                used.created,
+                used.expires,
+                used.max_uses,
+                used.uses,
+            ))
+            .unwrap(),
+        );
+        assert_eq!(InviteIndex::decode(&legacy).unwrap(), index);
+    }
+
+    #[test]
+    fn members_are_attributed_to_their_last_invite() {
+        let join = |user_id: &str, code: &str| InviteJoin {
+            user_id: user_id.to_string(),
+            code: code.to_string(),
+            joined: Utc::now(),
+        };
+        let joins = InviteJoins {
+            joins: vec![
+                join("alice", "FIRST234"),
+                join("bob", "FIRST234"),
+                join("alice", "SECND234"),
+            ],
+        };
+        assert_eq!(joins.invited_by("alice"), Some("SECND234"));
+        assert_eq!(joins.invited_by("bob"), Some("FIRST234"));
+        assert_eq!(joins.invited_by("carol"), None);
+    }
+
+    #[test]
+    fn stats_keep_joins_of_revoked_invites() {
+        let today = Utc::now();
+        let yesterday = today - Duration::days(1);
+        let join = |user_id: &str, code: &str, joined| InviteJoin {
+            user_id: user_id.to_string(),
+            code: code.to_string(),
+            joined,
+        };
+        let joins = InviteJoins {
+            joins: vec![
+                join("alice", "REVOKED2", yesterday),
+                join("bob", "REVOKED2", today),
+                join("carol", "REVOKED2", today),
+                join("alice", "USABLE23", today),
+            ],
+        };
+        let stats = joins.stats(vec![invite("USABLE23"), invite("UNUSED23")], |user_id| {
+            user_id != "carol"
+        });
+        let codes: Vec<&str> = stats.iter().map(|stats| stats.code.as_str()).collect();
+        assert_eq!(codes, ["REVOKED2", "UNUSED23", "USABLE23"]);
+        let revoked = &stats[0];
+        assert_eq!((revoked.joins, revoked.members), (3, 1));
+        assert_eq!(revoked.invite, None);
+        assert_eq!(revoked.days[&yesterday.date_naive()], 1);
+        assert_eq!(revoked.days[&today.date_naive()], 2);
+        assert_eq!((stats[1].joins, stats[1].members), (0, 0));
+        assert_eq!((stats[2].joins, stats[2].members), (1, 1));
+        assert!(stats[2].invite.is_some());
+    }
+}
@@ -0,0 +1,356 @@
+use std::{collections::HashMap, path::PathBuf, sync::Mutex, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::IoContext, HubId, Result};
+
+/// Folder where the message counts of the senders of each hub are stored.
+pub const LEADERBOARD_FOLDER: &str = "data/hubs/leaderboard/";
+
+/// Number of days counted by [`LeaderboardPeriod::Recent`], including today.
+pub const RECENT_DAYS: u32 = 30;
+
+/// Maximum number of senders returned by a single request for a leaderboard, larger limits are lowered to this.
+pub const MAX_LEADERBOARD_SIZE: usize = 100;
+
+/// Seconds between flushes of the pending message counts to the leaderboard files.
+pub const LEADERBOARD_FLUSH_INTERVAL: u64 = 60;
+
+/// Changes to the message counts of each hub that have not been written to its leaderboard file yet, by sender and day.
+static PENDING: Mutex<Option<HashMap<HubId, PendingCounts>>> = Mutex::new(None);
+
+/// Changes to the message counts of a hub's senders, keyed by sender and the start of the UTC day the messages were sent on.
+type PendingCounts = HashMap<(String, DateTime<Utc>), i64>;
+
+/// Truncates a time to the start of its UTC day.
+fn start_of_day(time: DateTime<Utc>) -> DateTime<Utc> {
+    let seconds = time.timestamp();
+    DateTime::<Utc>::from(std::time::UNIX_EPOCH)
+        + chrono::Duration::seconds(seconds - seconds.rem_euclid(86400))
+}
+
+/// Adds `change` to the pending count of a sender.
+fn record(hub_id: HubId, sender: &str, sent: DateTime<Utc>, change: i64) {
+    *PENDING
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .get_or_insert_with(HashMap::new)
+        .entry(hub_id)
+        .or_default()
+        .entry((sender.to_string(), start_of_day(sent)))
+        .or_default() += change;
+}
+
+/// Counts a message sent in a hub.
+pub fn record_message(hub_id: HubId, sender: &str, sent: DateTime<Utc>) {
+    record(hub_id, sender, sent, 1);
+}
+
+/// Uncounts a message that was removed from a hub, `sent` is the time the message was sent at.
+pub fn record_removed(hub_id: HubId, sender: &str, sent: DateTime<Utc>) {
+    record(hub_id, sender, sent, -1);
+}
+
+/// Forgets the pending counts of a hub, used when the hub is deleted or its leaderboard is disabled.
+pub fn forget(hub_id: HubId) {
+    if let Some(pending) = PENDING
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .as_mut()
+    {
+        pending.remove(&hub_id);
+    }
+}
+
+/// Time range that a leaderboard counts messages in.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LeaderboardPeriod {
+    /// Every message still stored in the hub.
+    #[default]
+    All,
+    /// Messages sent in the last [`RECENT_DAYS`] days.
+    #[serde(rename = "30d")]
+    Recent,
+}
+
+/// Number of messages a user has sent in a hub.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct SenderCounts {
+    /// Messages sent since the hub was created (or its leaderboard was last enabled) that have not been removed.
+    pub total: u64,
+    /// Messages sent on each of the last [`RECENT_DAYS`] UTC days, oldest first, days without messages are left out.
+    pub days: Vec<(DateTime<Utc>, u64)>,
+}
+
+/// Message counts of the senders of a hub, stored separately from the hub itself.
+/// Leaderboards are enabled by default, hubs can opt out with [`crate::api::set_leaderboard_enabled`].
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct HubLeaderboard {
+    /// Whether the hub opted out, messages of hubs that opted out are not counted.
+    pub disabled: bool,
+    /// Counts of each user that sent messages.
+    pub senders: HashMap<String, SenderCounts>,
+}
+
+impl HubLeaderboard {
+    /// Gets the path of the file that a hub's leaderboard is stored in.
+    pub fn get_path(hub_id: HubId) -> PathBuf {
+        crate::paths::hub_leaderboard_file(hub_id)
+    }
+
+    /// Loads the leaderboard of a hub, a hub without the file has no counts.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The file exists but could not be read.
+    /// * The file's contents could not be deserialized.
+    pub async fn load(hub_id: HubId) -> Result<Self> {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(bincode::deserialize(&bytes)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_path(path),
+        }
+    }
+
+    /// Saves the leaderboard of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The leaderboard folder does not exist and could not be created.
+    /// * The data could not be written to the disk.
+    pub async fn save(&self, hub_id: HubId) -> Result {
+        tokio::fs::create_dir_all(LEADERBOARD_FOLDER)
+            .await
+            .with_path(LEADERBOARD_FOLDER)?;
+        let path = Self::get_path(hub_id);
+        tokio::fs::write(&path, bincode::serialize(self)?)
+            .await
+            .with_path(path)
+    }
+
+    /// Removes the leaderboard of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the file exists but could not be removed.
+    pub async fn remove(hub_id: HubId) -> Result {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::remove_file(&path).await {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result.with_path(path),
+        }
+    }
+
+    /// Adds `change` to the count of a sender for the UTC day of `sent`, counts never go below zero.
+    /// Days older than [`RECENT_DAYS`] before `now` are dropped and senders left without messages are removed.
+    pub fn apply(&mut self, now: DateTime<Utc>, sender: &str, sent: DateTime<Utc>, change: i64) {
+        let day = start_of_day(sent);
+        let oldest = start_of_day(now) - chrono::Duration::days(i64::from(RECENT_DAYS) - 1);
+        let counts = self.senders.entry(sender.to_string()).or_default();
+        let add = |count: u64| {
+            if change < 0 {
+                count.saturating_sub(change.unsigned_abs())
+            } else {
+                count.saturating_add(change as u64)
+            }
+        };
+        counts.total = add(counts.total);
+        if day >= oldest {
+            let index = match counts.days.binary_search_by_key(&day, |(day, _)| *day) {
+                Ok(index) => index,
+                Err(index) => {
+                    counts.days.insert(index, (day, 0));
+                    index
+                }
+            };
+            counts.days[index].1 = add(counts.days[index].1);
+        }
+        counts
+            .days
+            .retain(|(day, messages)| *day >= oldest && *messages > 0);
+        if counts.total == 0 {
+            self.senders.remove(sender);
+        }
+    }
+
+    /// Gets the `limit` users that sent the most messages in `period`, most messages first, users with the same count are ordered by ID.
+    pub fn top(&self, now: DateTime<Utc>, period: LeaderboardPeriod, limit: usize) -> Leaderboard {
+        let oldest = start_of_day(now) - chrono::Duration::days(i64::from(RECENT_DAYS) - 1);
+        let mut entries: Vec<LeaderboardEntry> = self
+            .senders
+            .iter()
+            .map(|(user_id, counts)| LeaderboardEntry {
+                user_id: user_id.clone(),
+                messages: match period {
+                    LeaderboardPeriod::All => counts.total,
+                    LeaderboardPeriod::Recent => counts
+                        .days
+                        .iter()
+                        .filter(|(day, _)| *day >= oldest)
+                        .map(|(_, messages)| messages)
+                        .sum(),
+                },
+            })
+            .filter(|entry| entry.messages > 0)
+            .collect();
+        entries.sort_by(|a, b| {
+            b.messages
+                .cmp(&a.messages)
+                .then_with(|| a.user_id.cmp(&b.user_id))
+        });
+        entries.truncate(limit);
+        Leaderboard { period, entries }
+    }
+}
+
+/// A user and the number of messages they sent.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LeaderboardEntry {
+    pub user_id: String,
+    pub messages: u64,
+}
+
+/// Users that sent the most messages in a hub, served by `/v3/leaderboard/{hub_id}`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Leaderboard {
+    pub period: LeaderboardPeriod,
+    /// Most messages first.
+    pub entries: Vec<LeaderboardEntry>,
+}
+
+/// Gets the stored leaderboard of a hub together with the counts that have not been flushed yet.
+///
+/// # Errors
+///
+/// This function may return an error for any of the reasons outlined in [`HubLeaderboard::load`].
+pub async fn current(hub_id: HubId) -> Result<HubLeaderboard> {
+    let mut leaderboard = HubLeaderboard::load(hub_id).await?;
+    if !leaderboard.disabled {
+        let pending = PENDING
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .as_ref()
+            .and_then(|pending| pending.get(&hub_id).cloned());
+        let now = Utc::now();
+        for ((sender, day), change) in pending.unwrap_or_default() {
+            leaderboard.apply(now, &sender, day, change);
+        }
+    }
+    Ok(leaderboard)
+}
+
+/// Writes the pending counts of every hub to its leaderboard file. Counts of hubs that no longer exist or opted out are dropped.
+///
+/// # Errors
+///
+/// This function may return an error for any of the reasons outlined in [`HubLeaderboard::load`] and [`HubLeaderboard::save`], the other hubs are still flushed.
+pub async fn flush() -> Result {
+    let pending = PENDING
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .take()
+        .unwrap_or_default();
+    let now = Utc::now();
+    let mut result = Ok(());
+    for (hub_id, counts) in pending {
+        if !crate::paths::hub_info_file(hub_id).is_file() {
+            continue;
+        }
+        let flushed = async {
+            let mut leaderboard = HubLeaderboard::load(hub_id).await?;
+            if leaderboard.disabled {
+                return Ok(());
+            }
+            for ((sender, day), change) in counts {
+                leaderboard.apply(now, &sender, day, change);
+            }
+            leaderboard.save(hub_id).await
+        }
+        .await;
+        if flushed.is_err() {
+            result = flushed;
+        }
+    }
+    result
+}
+
+/// Starts flushing the pending message counts every [`LEADERBOARD_FLUSH_INTERVAL`] seconds.
+pub fn spawn_flusher() {
+    tokio::spawn(async {
+        loop {
+            tokio::time::sleep(Duration::from_secs(LEADERBOARD_FLUSH_INTERVAL)).await;
+            if let Err(err) = flush().await {
+                error!("Unable to save hub leaderboards: {}", err.chain());
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{HubLeaderboard, LeaderboardPeriod, RECENT_DAYS};
+
+    #[test]
+    fn counts_and_ranking() {
+        let now = Utc.with_ymd_and_hms(2021, 5, 1, 10, 15, 0).unwrap();
+        let today = Utc.with_ymd_and_hms(2021, 5, 1, 0, 0, 0).unwrap();
+        let old = today - Duration::days(i64::from(RECENT_DAYS));
+        let mut leaderboard = HubLeaderboard::default();
+        leaderboard.apply(now, "a", today, 2);
+        leaderboard.apply(now, "b", today, 1);
+        leaderboard.apply(now, "c", old, 5);
+        leaderboard.apply(now, "b", today - Duration::days(1), 1);
+
+        let ranking = |leaderboard: &HubLeaderboard, period| {
+            leaderboard
+                .top(now, period, 10)
+                .entries
+                .into_iter()
+                .map(|entry| (entry.user_id, entry.messages))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ranking(&leaderboard, LeaderboardPeriod::All),
+            vec![
+                ("c".to_string(), 5),
+                ("a".to_string(), 2),
+                ("b".to_string(), 2)
+            ]
+        );
+        // Messages older than the recent period only count towards the total.
+        assert_eq!(
+            ranking(&leaderboard, LeaderboardPeriod::Recent),
+            vec![("a".to_string(), 2), ("b".to_string(), 2)]
+        );
+        assert_eq!(
+            leaderboard
+                .top(now, LeaderboardPeriod::All, 1)
+                .entries
+                .len(),
+            1
+        );
+
+        // Removed messages are uncounted, senders without messages are dropped.
+        leaderboard.apply(now, "a", today, -2);
+        leaderboard.apply(now, "b", today, -1);
+        leaderboard.apply(now, "c", old, -10);
+        assert!(!leaderboard.senders.contains_key("a"));
+        assert!(!leaderboard.senders.contains_key("c"));
+        assert_eq!(leaderboard.senders["b"].total, 1);
+        assert_eq!(
+            ranking(&leaderboard, LeaderboardPeriod::Recent),
+            vec![("b".to_string(), 1)]
+        );
+    }
+}
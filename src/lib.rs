@@ -1,79 +1,186 @@
 #[macro_use]
-extern crate log;
+extern crate tracing;
 
 use error::{Error, Result};
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+#[cfg(feature = "http-api")]
+pub use httpapi::{ServerBuilder, WicrsServer};
 pub use pgp;
 
+/// Background generation of the data exports users can request of their accounts.
+pub mod account_export;
 /// Public API for performing user actions, should be used for creating API implementations like the HTTP API or similar.
 pub mod api;
+/// History of the name and description changes of hubs and their channels.
+pub mod change_history;
 /// Message storage and retreival for channels.
 pub mod channel;
+/// Sequence numbers of the message events of channels, so clients can notice and fill gaps.
+pub mod channel_events;
+/// Client for the HTTP API, with typed methods for the routes in [`client::ROUTES`] and signed requests for the others.
+#[cfg(feature = "client")]
+pub mod client;
+/// Source of the current time that tests can control.
+pub mod clock;
 /// Various objects for storing configuration.
 pub mod config;
+/// Restrictions hubs put on the content of their messages.
+pub mod content_policy;
+/// Reports to senders that their messages reached another member.
+pub mod delivery_reports;
+/// Long descriptions of hubs and channels.
+pub mod descriptions;
+/// Unsent messages of users, synced between their devices.
+pub mod drafts;
+/// Encryption of stored data at rest.
+pub mod encryption;
 /// Errors
 pub mod error;
 /// GraphQL model definition.
+#[cfg(feature = "graphql")]
 pub mod graphql_model;
+/// Colors, hoisting and order of the permission groups of hubs.
+pub mod group_display;
 /// Definition of the HTTP API.
+#[cfg(feature = "http-api")]
 pub mod httpapi;
 /// Hubs, permission management, channel management and member management.
 pub mod hub;
+/// Rolling activity statistics of hubs for their administrators.
+pub mod hub_activity;
+/// Versions and recent changes of hubs, lets clients catch up without downloading whole hubs.
+pub mod hub_changes;
+/// Confirmation tokens that hubs can only be deleted with.
+pub mod hub_deletion;
+/// Icons and banners of hubs.
+pub mod hub_images;
+/// Public previews of hubs for users that have not joined them.
+pub mod hub_preview;
+/// Disk usage of the channels of each hub, for hub administrators.
+pub mod hub_storage;
+/// Replacement keys that sign in as an existing user, for users that lost their key.
+pub mod identity_links;
+/// Machine-readable description of the server for clients that have not signed in yet.
+pub mod instance_info;
+/// Latency and mailbox statistics for the server actors.
+pub mod instrumentation;
+/// Short codes of hub invites that can be shared in messages and QR codes.
+pub mod invites;
+/// Message counts of the senders of each hub, for leaderboards.
+pub mod leaderboard;
+/// Locks that make changes to the same file happen one at a time.
+pub mod locks;
+/// Log output setup, including the separate audit log.
+pub mod logging;
+/// Offline maintenance tasks for the data directory.
+pub mod maintenance;
+/// Compact maps of member IDs to nicknames that clients keep up to date with deltas.
+pub mod member_map;
+/// Log of the joins, leaves, kicks and bans in each hub.
+pub mod membership_log;
+/// Mentions of permission groups in messages.
+pub mod mentions;
+/// Previous versions of edited messages.
+pub mod message_edits;
+/// Moving messages from one channel of a hub to another.
+pub mod message_move;
+/// The path every sent message takes: checks, quotas, storage, indexing and notifying clients.
+pub mod message_pipeline;
+/// Background removal of all of a user's messages in a hub.
+pub mod message_purge;
+/// Nicknames of hub members and the rules for them.
+pub mod nicknames;
+/// Counts of the mentions users missed while they had no WebSocket connection open.
+pub mod offline_summaries;
+/// Locations of hub and channel data in the data directory.
+pub mod paths;
 /// Permissions are defined here.
 pub mod permission;
+/// Hook for applications embedding the server to add their own authorization to hub permissions.
+pub mod permission_hook;
+/// Per user quotas on what users can create and send.
+pub mod quotas;
+/// Checks that no route gives users content of channels they can not read.
+#[cfg(all(test, feature = "testing", feature = "graphql"))]
+mod redaction;
+/// Rendering of message markdown as sanitized HTML for embedding.
+pub mod rendering;
+/// Size-bounded log of security events that server admins can query.
+pub mod security_log;
 /// Server implementation.
 pub mod server;
+/// Hubs and channels users want to be subscribed to, restored at once when they reconnect.
+pub mod subscription_profiles;
+/// Socket activation, readiness and watchdog notifications for running under systemd.
+#[cfg(feature = "systemd")]
+pub mod systemd;
+/// Local test servers with ready made users, for integration tests of bots and clients.
+#[cfg(feature = "testing")]
+pub mod testing;
+/// Server-side previews of the pages linked in messages.
+pub mod unfurl;
+/// Validation of user provided names.
+pub mod validation;
+/// Webhooks that post messages in channels for services without a key pair.
+pub mod webhooks;
 /// Definition of the WebSocket API.
 pub mod websocket;
 
 pub mod signing;
 
-/// Maximum size of a username in bytes. Clients should be able to accept larger and smaller values.
+/// Default maximum length of a hub or channel name in Unicode scalar values, see [`config::NameRules`]. Clients should be able to accept larger and smaller values.
 pub const MAX_NAME_SIZE: usize = 128;
 
 /// Maximum size of a user status in bytes. Clients should be able to accept larger and smaller values.
 pub const MAX_STATUS_SIZE: usize = 128;
 
-/// Maximum size of a description in bytes. Clients should be able to accept larger and smaller values.
+/// Default maximum length of a long description in characters (see [`config::DescriptionsConfig`]). Clients should be able to accept larger and smaller values.
 pub const MAX_DESCRIPTION_SIZE: usize = 8192;
 
 /// Maximum size of a message in bytes. Clients should be able to accept larger and smaller values.
 pub const MESSAGE_MAX_SIZE: usize = 8192;
 
-/// How long to wait before commiting new messages to the tantivy search engine in milliseconds, this takes a lot of time, which is why it should be done only periodically.
-pub const TANTIVY_COMMIT_THRESHOLD: u8 = 10;
+/// Maximum number of messages returned by a single request for messages, larger limits are lowered to this.
+pub const MAX_MESSAGES_PER_REQUEST: usize = 256;
 
-/// Checks if a name is valid (not too long and only allowed characters).
-pub fn is_valid_name(name: &str) -> bool {
-    name.as_bytes().len() <= MAX_NAME_SIZE
-}
+/// Number of messages in the snapshot of a channel that WebSocket clients can ask for when subscribing to it.
+pub const SNAPSHOT_MESSAGES: usize = 50;
+
+/// Number of new messages to wait for before commiting them to the tantivy search engine, commiting takes a lot of time, which is why it should be done only periodically.
+/// See [`server::PendingMessages::push`] for how the threshold is applied.
+pub const TANTIVY_COMMIT_THRESHOLD: usize = 10;
+
+/// Number of seconds between commits of messages that are still waiting to be commited to the tantivy search engine, so messages in quiet channels do not wait for [`TANTIVY_COMMIT_THRESHOLD`] forever.
+pub const TANTIVY_COMMIT_INTERVAL: u64 = 5;
+
+/// Number of messages that can be waiting to be indexed before new messages are refused with [`error::Error::Overloaded`] until the search engine catches up.
+pub const MAX_INDEX_QUEUE_DEPTH: u64 = 10_000;
+
+/// Number of seconds between saves of the list of recently active channels, used to pick the search indexes that are warmed up when the server starts.
+pub const RECENT_CHANNELS_SAVE_INTERVAL: u64 = 60;
+
+/// Maximum number of search indexes that are opened at the same time while warming up indexes when the server starts.
+pub const INDEX_WARM_UP_CONCURRENCY: usize = 4;
 
 /// Starts WICRS Server in the current directory loading the configuration from `config.json`.
+#[cfg(feature = "http-api")]
 pub async fn start() -> Result {
-    let config = config::load_config("config.json");
+    run(config::load_config("config.json")).await
+}
+
+/// Starts WICRS Server in the current directory with the given configuration.
+#[cfg(feature = "http-api")]
+pub async fn run(config: config::Config) -> Result {
     if std::fs::create_dir_all("data").is_err() {
         Err(Error::Other("Failed to create data directory.".to_string()))
     } else {
+        let _lock = maintenance::DataLock::acquire()?;
         httpapi::start(config).await
     }
 }
 
-/// Wraps `is_valid_name` to return a `Result<()>`.
-///
-/// # Errors
-///
-/// This function returns an error for any of the following reasons:
-///
-/// * The name is too big (maximum in bytes defined by [`MAX_NAME_SIZE`]).
-pub fn check_name_validity(name: &str) -> Result {
-    if is_valid_name(name) {
-        Ok(())
-    } else {
-        Err(Error::InvalidName)
-    }
-}
-
 /// Checks that a hub member has a given permission and returns an error if it doesn't.
 #[macro_export]
 macro_rules! check_permission {
@@ -89,6 +196,45 @@ macro_rules! check_permission {
     };
 }
 
+/// Implements [`std::fmt::Display`] and [`std::str::FromStr`] for an enum of unit variants from a single table of the strings clients see, so the two always agree.
+/// Also adds a `VARIANTS` constant listing every variant in the order of the table, which should be the order they are declared in.
+#[macro_export]
+macro_rules! wire_strings {
+    ($name:ident { $($variant:ident => $string:literal),+ $(,)? }) => {
+        impl $name {
+            /// Every variant, in the order they are declared in.
+            pub const VARIANTS: &'static [Self] = &[$(Self::$variant),+];
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(match self {
+                    $(Self::$variant => $string),+
+                })
+            }
+        }
+
+        impl std::str::FromStr for $name {
+            type Err = $crate::error::Error;
+
+            fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+                match s {
+                    $($string => Ok(Self::$variant),)+
+                    _ => Err($crate::error::Error::UnknownValue(s.to_string())),
+                }
+            }
+        }
+    };
+}
+
+/// Emits an event on the audit target ([`logging::AUDIT_TARGET`]), used for security relevant events.
+#[macro_export]
+macro_rules! audit {
+    ($($arg:tt)+) => {
+        ::tracing::info!(target: "audit", $($arg)+)
+    };
+}
+
 /// Type used to represent IDs of non user objects throughout wicrs.
 #[allow(clippy::upper_case_acronyms)]
 pub type ID = Uuid;
@@ -97,3 +243,97 @@ pub type ID = Uuid;
 pub fn new_id() -> ID {
     uuid::Uuid::new_v4()
 }
+
+/// Defines a newtype around [`Uuid`] for the IDs of one kind of object, it is serialized exactly like the [`Uuid`] it wraps.
+macro_rules! id_type {
+    ($(#[$attr:meta])* $name:ident, $graphql_name:literal) => {
+        $(#[$attr])*
+        #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        #[serde(transparent)]
+        pub struct $name(pub Uuid);
+
+        impl $name {
+            /// Generates a new random ID.
+            pub fn random() -> Self {
+                Self(Uuid::new_v4())
+            }
+
+            /// Gets the ID with all bits set to zero.
+            pub fn nil() -> Self {
+                Self(Uuid::nil())
+            }
+
+            /// Creates an ID from its integer representation, used for file and directory names.
+            pub fn from_u128(value: u128) -> Self {
+                Self(Uuid::from_u128(value))
+            }
+
+            /// Gets the integer representation of the ID, used for file and directory names.
+            pub fn as_u128(&self) -> u128 {
+                self.0.as_u128()
+            }
+
+            /// Parses an ID from any of the string formats accepted by [`Uuid::parse_str`].
+            pub fn parse_str(input: &str) -> std::result::Result<Self, uuid::Error> {
+                Uuid::parse_str(input).map(Self)
+            }
+        }
+
+        impl From<Uuid> for $name {
+            fn from(id: Uuid) -> Self {
+                Self(id)
+            }
+        }
+
+        impl From<$name> for Uuid {
+            fn from(id: $name) -> Self {
+                id.0
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        impl std::str::FromStr for $name {
+            type Err = uuid::Error;
+
+            fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+                Self::parse_str(s)
+            }
+        }
+
+        #[cfg(feature = "graphql")]
+        #[async_graphql::Scalar(name = $graphql_name)]
+        impl async_graphql::ScalarType for $name {
+            fn parse(value: async_graphql::Value) -> async_graphql::InputValueResult<Self> {
+                match value {
+                    async_graphql::Value::String(s) => Ok(Self::parse_str(&s)?),
+                    _ => Err(async_graphql::InputValueError::expected_type(value)),
+                }
+            }
+
+            fn to_value(&self) -> async_graphql::Value {
+                async_graphql::Value::String(self.0.to_string())
+            }
+        }
+    };
+}
+
+id_type!(
+    /// ID of a hub.
+    HubId,
+    "HubID"
+);
+id_type!(
+    /// ID of a channel, only unique within the hub the channel is in.
+    ChannelId,
+    "ChannelID"
+);
+id_type!(
+    /// ID of a message.
+    MessageId,
+    "MessageID"
+);
@@ -0,0 +1,115 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Mutex as SyncMutex, MutexGuard as SyncMutexGuard},
+};
+
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+/// Locks that make changes to something identified by a key (for example the file of a user) happen one at a time.
+/// The lock of a key is created when it is first needed and removed again once nothing holds or waits for it, so the number of locks stays at the number of keys that are in use.
+pub struct KeyedLocks<K> {
+    locks: SyncMutex<Option<HashMap<K, Arc<Mutex<()>>>>>,
+}
+
+impl<K: Eq + Hash + Clone> KeyedLocks<K> {
+    /// Creates an empty set of locks, usable in a `static`.
+    pub const fn new() -> Self {
+        Self {
+            locks: SyncMutex::new(None),
+        }
+    }
+
+    fn entries(&self) -> SyncMutexGuard<'_, Option<HashMap<K, Arc<Mutex<()>>>>> {
+        self.locks.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Waits until nothing else holds the lock of a key and takes it, it is released when the guard is dropped.
+    pub async fn lock(&self, key: K) -> KeyedLock<'_, K> {
+        // The guard is created first so that the lock is also removed if this future is dropped while it waits.
+        let mut guard = KeyedLock {
+            locks: self,
+            key,
+            guard: None,
+        };
+        let lock = self
+            .entries()
+            .get_or_insert_with(HashMap::new)
+            .entry(guard.key.clone())
+            .or_default()
+            .clone();
+        guard.guard = Some(lock.lock_owned().await);
+        guard
+    }
+
+    /// Number of keys that currently have a lock.
+    pub fn len(&self) -> usize {
+        self.entries().as_ref().map_or(0, HashMap::len)
+    }
+
+    /// Returns true if nothing holds or waits for any of the locks.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K: Eq + Hash + Clone> Default for KeyedLocks<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Guard of the lock of one key, see [`KeyedLocks::lock`].
+pub struct KeyedLock<'a, K: Eq + Hash + Clone> {
+    locks: &'a KeyedLocks<K>,
+    key: K,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl<'a, K: Eq + Hash + Clone> Drop for KeyedLock<'a, K> {
+    fn drop(&mut self) {
+        let mut entries = self.locks.entries();
+        self.guard = None;
+        if let Some(locks) = entries.as_mut() {
+            // The map holds the only reference once no other guard or waiting task has the lock.
+            if locks
+                .get(&self.key)
+                .is_some_and(|lock| Arc::strong_count(lock) == 1)
+            {
+                locks.remove(&self.key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::KeyedLocks;
+
+    #[tokio::test]
+    async fn idle_locks_are_removed() {
+        let locks = KeyedLocks::new();
+        let first = locks.lock(1).await;
+        let other = locks.lock(2).await;
+        assert_eq!(locks.len(), 2);
+        drop(other);
+        assert_eq!(locks.len(), 1);
+
+        // A task that gave up waiting does not leave its lock behind.
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), locks.lock(1))
+                .await
+                .is_err()
+        );
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), locks.lock(3))
+                .await
+                .is_ok()
+        );
+        assert_eq!(locks.len(), 1);
+        drop(first);
+        assert!(locks.is_empty());
+    }
+}
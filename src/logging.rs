@@ -0,0 +1,100 @@
+use std::path::Path;
+
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{
+    filter::{filter_fn, EnvFilter},
+    fmt,
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    Layer,
+};
+
+use crate::{
+    config::LoggingConfig,
+    error::{Error, Result},
+    security_log,
+};
+
+/// Target of security relevant events (logins, permission changes, moderation and admin actions), see [`crate::audit`].
+pub const AUDIT_TARGET: &str = "audit";
+
+/// Sets up log output, logs are written to stdout filtered by the `RUST_LOG` environment variable (`info` by default).
+/// If an audit file is configured audit events are also written to it regardless of `RUST_LOG`, and to the security log if it is enabled (see [`security_log`]).
+/// The returned guard must be kept alive for as long as audit events should be written.
+///
+/// # Errors
+///
+/// This function will return an error in the following situations, but is not
+/// limited to just these cases:
+///
+/// * Logging has already been set up.
+/// * The security log could not be opened.
+pub fn init(config: &LoggingConfig) -> Result<Option<WorkerGuard>> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let stdout = if config.json {
+        fmt::layer().json().boxed()
+    } else {
+        fmt::layer().boxed()
+    };
+    let (audit, guard) = if let Some(audit_file) = &config.audit_file {
+        let path = Path::new(audit_file);
+        let directory = path.parent().unwrap_or_else(|| Path::new("."));
+        let file_name = path.file_name().unwrap_or_else(|| "audit.log".as_ref());
+        let (writer, guard) =
+            tracing_appender::non_blocking(tracing_appender::rolling::daily(directory, file_name));
+        let layer = fmt::layer()
+            .json()
+            .with_writer(writer)
+            .with_filter(filter_fn(|metadata| metadata.target() == AUDIT_TARGET));
+        (Some(layer), Some(guard))
+    } else {
+        (None, None)
+    };
+    let security_log = security_log::start(&config.security_log)?;
+    tracing_subscriber::registry()
+        .with(stdout.with_filter(filter))
+        .with(audit)
+        .with(security_log)
+        .try_init()
+        .map_err(|err| Error::Other(err.to_string()))?;
+    Ok(guard)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use tracing::{Event, Subscriber};
+    use tracing_subscriber::{
+        layer::{Context, SubscriberExt},
+        Layer,
+    };
+
+    use super::AUDIT_TARGET;
+
+    /// Layer that keeps the targets of all events it sees.
+    struct CaptureTargets(Arc<Mutex<Vec<String>>>);
+
+    impl<S: Subscriber> Layer<S> for CaptureTargets {
+        fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(event.metadata().target().to_string());
+        }
+    }
+
+    #[test]
+    fn audit_events_use_audit_target() {
+        let targets = Arc::new(Mutex::new(Vec::new()));
+        let subscriber = tracing_subscriber::registry().with(CaptureTargets(targets.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            crate::audit!(actor = "ABCD", "Banned user.");
+            info!("Not an audit event.");
+        });
+        let targets = targets.lock().unwrap();
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets[0], AUDIT_TARGET);
+        assert_ne!(targets[1], AUDIT_TARGET);
+    }
+}
@@ -1,20 +1,237 @@
 #[macro_use]
-extern crate log;
-extern crate pretty_env_logger;
+extern crate tracing;
 
-use log::LevelFilter;
+use clap::{App, Arg, SubCommand};
+use wicrs_server::{
+    config, encryption,
+    error::{Error, Result},
+    logging, maintenance, ChannelId, HubId,
+};
+#[cfg(feature = "testing")]
+use wicrs_server::{
+    signing::{KeyPair, PUBLIC_KEY_PATH, SECRET_KEY_PATH},
+    testing,
+};
 
-/// Main function, loads config and starts a server for the HTTP API.
+/// Main function, loads config and starts a server for the HTTP API or runs a maintenance subcommand.
 #[tokio::main]
 async fn main() {
-    let mut builder = pretty_env_logger::formatted_timed_builder();
-    builder.filter_level(LevelFilter::Info);
-    builder.parse_filters("RUST_LOG");
-    builder.init();
+    let config = config::load_config("config.json");
+    let audit_guard = match logging::init(&config.logging) {
+        Ok(guard) => guard,
+        Err(err) => {
+            eprintln!("Failed to set up logging: {}", err);
+            std::process::exit(1);
+        }
+    };
+    if let Err(err) = encryption::init(config.encryption.as_ref()) {
+        error!("Failed to load the encryption key: {}", err.chain());
+        std::process::exit(1);
+    }
+
+    let app = App::new("wicrs_server")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about(env!("CARGO_PKG_DESCRIPTION"))
+        .subcommand(
+            SubCommand::with_name("reindex")
+                .about("Rebuilds search indexes from the stored messages.")
+                .arg(
+                    Arg::with_name("hub")
+                        .long("hub")
+                        .takes_value(true)
+                        .help("Only rebuild the indexes of channels in this hub."),
+                )
+                .arg(
+                    Arg::with_name("channel")
+                        .long("channel")
+                        .takes_value(true)
+                        .requires("hub")
+                        .help("Only rebuild the index of this channel."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("compact")
+                .about("Rewrites message files, dropping unreadable data and duplicate messages."),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Checks hubs against the channel directories, index logs and user quotas.")
+                .arg(Arg::with_name("repair").long("repair").help(
+                    "Correct user quotas and truncate unreadable data at the end of message files.",
+                )),
+        )
+        .subcommand(SubCommand::with_name("encrypt-data").about(
+            "Encrypts the stored hubs, messages and edit histories with the configured key.",
+        ))
+        .subcommand(SubCommand::with_name("decrypt-data").about(
+            "Decrypts the stored hubs, messages and edit histories with the configured key.",
+        ))
+        .subcommand(
+            SubCommand::with_name("backfill-leaderboards")
+                .about("Recounts the messages of each hub's senders from the stored messages.")
+                .arg(
+                    Arg::with_name("hub")
+                        .long("hub")
+                        .takes_value(true)
+                        .help("Only recount the messages of this hub."),
+                ),
+        );
+    #[cfg(feature = "testing")]
+    let app = app.subcommand(
+        SubCommand::with_name("seed")
+            .about("Generates a hub with members and messages for measuring performance.")
+            .arg(
+                Arg::with_name("channels")
+                    .long("channels")
+                    .takes_value(true)
+                    .default_value("4")
+                    .help("Number of channels."),
+            )
+            .arg(
+                Arg::with_name("members")
+                    .long("members")
+                    .takes_value(true)
+                    .default_value("100")
+                    .help("Number of members besides the owner."),
+            )
+            .arg(
+                Arg::with_name("messages")
+                    .long("messages")
+                    .takes_value(true)
+                    .default_value("1000")
+                    .help("Number of messages in each channel."),
+            )
+            .arg(
+                Arg::with_name("days")
+                    .long("days")
+                    .takes_value(true)
+                    .default_value("30")
+                    .help("Number of days the messages are spread over."),
+            )
+            .arg(
+                Arg::with_name("seed")
+                    .long("seed")
+                    .takes_value(true)
+                    .default_value("0")
+                    .help("Seed of the generated data, the same seed always gives the same hub."),
+            )
+            .arg(
+                Arg::with_name("unsigned")
+                    .long("unsigned")
+                    .help("Store messages without signatures, which is much faster."),
+            ),
+    );
+    let matches = app.get_matches();
 
-    if let Err(err) = wicrs_server::start().await {
-        error!("{}", err);
-    } else {
-        info!("WICRS Server stopped.")
+    let result = match matches.subcommand() {
+        ("reindex", Some(args)) => {
+            run_maintenance(async move {
+                let hub_id = args.value_of("hub").map(HubId::parse_str).transpose()?;
+                let channel_id = args
+                    .value_of("channel")
+                    .map(ChannelId::parse_str)
+                    .transpose()?;
+                wicrs_server::audit!(?hub_id, ?channel_id, "Reindexing search indexes.");
+                let count = maintenance::reindex(hub_id, channel_id).await?;
+                info!("Reindexed {} messages.", count);
+                Ok(true)
+            })
+            .await
+        }
+        ("compact", _) => {
+            run_maintenance(async {
+                wicrs_server::audit!("Compacting message files.");
+                let removed = maintenance::compact().await?;
+                info!("Compaction removed {} bytes.", removed);
+                Ok(true)
+            })
+            .await
+        }
+        ("verify", args) => {
+            let repair = args.is_some_and(|args| args.is_present("repair"));
+            run_maintenance(async move {
+                let problems = maintenance::verify(repair).await?;
+                for problem in problems.iter() {
+                    println!("{}", problem);
+                }
+                info!("Verification found {} problems.", problems.len());
+                Ok::<_, Error>(problems.is_empty())
+            })
+            .await
+        }
+        ("encrypt-data", _) | ("decrypt-data", _) => {
+            let encrypt = matches.subcommand_name() == Some("encrypt-data");
+            run_maintenance(async move {
+                wicrs_server::audit!(encrypt, "Converting the encryption of the data directory.");
+                let count = maintenance::convert_encryption(encrypt).await?;
+                info!("Rewrote {} files.", count);
+                Ok(true)
+            })
+            .await
+        }
+        ("backfill-leaderboards", Some(args)) => {
+            run_maintenance(async move {
+                let hub_id = args.value_of("hub").map(HubId::parse_str).transpose()?;
+                wicrs_server::audit!(?hub_id, "Backfilling hub leaderboards.");
+                let count = maintenance::backfill_leaderboards(hub_id).await?;
+                info!("Counted {} messages.", count);
+                Ok(true)
+            })
+            .await
+        }
+        #[cfg(feature = "testing")]
+        ("seed", Some(args)) => {
+            run_maintenance(async move {
+                let number = |name: &str| {
+                    args.value_of(name)
+                        .unwrap_or_default()
+                        .parse::<u64>()
+                        .map_err(|_| Error::Other(format!("--{} must be a number", name)))
+                };
+                let options = testing::SeedOptions {
+                    channels: number("channels")? as usize,
+                    members: number("members")? as usize,
+                    messages_per_channel: number("messages")? as usize,
+                    days: number("days")? as u32,
+                    seed: number("seed")?,
+                    ..testing::SeedOptions::default()
+                };
+                let key_pair = if args.is_present("unsigned") {
+                    None
+                } else {
+                    Some(KeyPair::load(SECRET_KEY_PATH, PUBLIC_KEY_PATH).await?)
+                };
+                wicrs_server::audit!(seed = options.seed, "Seeding a hub.");
+                let seeded = testing::seed_hub(&options, key_pair.as_ref()).await?;
+                info!(
+                    "Seeded hub {} with {} messages, {} of them indexed.",
+                    seeded.hub_id, seeded.messages, seeded.indexed
+                );
+                Ok(true)
+            })
+            .await
+        }
+        _ => wicrs_server::run(config).await.map(|_| {
+            info!("WICRS Server stopped.");
+            true
+        }),
+    };
+    let success = match result {
+        Ok(success) => success,
+        Err(err) => {
+            error!("{}", err.chain());
+            false
+        }
+    };
+    // Flush the audit log before exiting.
+    drop(audit_guard);
+    if !success {
+        std::process::exit(1);
     }
 }
+
+/// Runs a maintenance task while holding the data directory lock so that it cannot run alongside a server.
+async fn run_maintenance<F: std::future::Future<Output = Result<bool>>>(task: F) -> Result<bool> {
+    let _lock = maintenance::DataLock::acquire()?;
+    task.await
+}
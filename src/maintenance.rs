@@ -0,0 +1,473 @@
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    io::{Read, Write},
+};
+
+use chrono::Utc;
+
+use crate::{
+    account_export::ACCOUNT_EXPORTS_FOLDER,
+    change_history::ChangeHistory,
+    channel::{encode_message_file_as, read_message_records, Channel, Message},
+    content_policy::ContentPolicy,
+    drafts::DRAFTS_FOLDER,
+    encryption,
+    error::{Error, IoContext, Result},
+    hub::{Hub, HUB_DATA_FOLDER, HUB_INFO_FOLDER},
+    leaderboard::HubLeaderboard,
+    offline_summaries::OFFLINE_SUMMARIES_FOLDER,
+    quotas::{UserQuota, USER_QUOTAS_FOLDER},
+    server::rebuild_index,
+    ChannelId, HubId,
+};
+
+/// Relative path of the lock file that marks the data directory as in use.
+pub const LOCK_FILE: &str = "data/wicrs.lock";
+
+/// Guard that holds the data directory lock, the lock is released when it is dropped.
+pub struct DataLock;
+
+impl DataLock {
+    /// Takes the data directory lock, the lock file contains the ID of the process that holds it.
+    /// A lock left behind by a process that no longer exists is replaced.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * Another running process holds the lock.
+    /// * The lock file could not be created.
+    pub fn acquire() -> Result<Self> {
+        std::fs::create_dir_all("data")?;
+        loop {
+            match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(LOCK_FILE)
+            {
+                Ok(mut file) => {
+                    write!(file, "{}", std::process::id())?;
+                    return Ok(Self);
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                    if lock_is_stale() {
+                        warn!("Removing stale lock file {}.", LOCK_FILE);
+                        std::fs::remove_file(LOCK_FILE)?;
+                    } else {
+                        return Err(Error::DataLocked);
+                    }
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+impl Drop for DataLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(LOCK_FILE);
+    }
+}
+
+/// Checks if the process that created the lock file is gone, only possible on systems with `/proc`.
+fn lock_is_stale() -> bool {
+    let mut pid = String::new();
+    if let Ok(mut file) = std::fs::File::open(LOCK_FILE) {
+        if file.read_to_string(&mut pid).is_err() {
+            return false;
+        }
+    }
+    std::path::Path::new("/proc").is_dir()
+        && !std::path::Path::new(&format!("/proc/{}", pid.trim())).exists()
+}
+
+/// Parses a hex encoded ID as used in file and directory names.
+fn parse_hex_id(name: &str) -> Option<u128> {
+    u128::from_str_radix(name, 16).ok()
+}
+
+/// Lists the IDs of all the hubs that have an info file.
+pub async fn list_hubs() -> Result<Vec<HubId>> {
+    let mut result = Vec::new();
+    if let Ok(mut dir) = tokio::fs::read_dir(HUB_INFO_FOLDER).await {
+        while let Some(entry) = dir.next_entry().await? {
+            if let Some(id) = entry.file_name().to_str().and_then(parse_hex_id) {
+                result.push(HubId::from_u128(id));
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Rebuilds the search indexes of every channel, every channel in a hub or a single channel.
+/// Returns the number of messages that were indexed.
+///
+/// # Errors
+///
+/// This function will return an error in the following situations, but is not
+/// limited to just these cases:
+///
+/// * A hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The given channel is not in the given hub.
+/// * An index could not be rebuilt for any of the reasons outlined by [`rebuild_index`].
+pub async fn reindex(hub_id: Option<HubId>, channel_id: Option<ChannelId>) -> Result<usize> {
+    let hubs = if let Some(hub_id) = hub_id {
+        vec![hub_id]
+    } else {
+        list_hubs().await?
+    };
+    let mut count = 0;
+    for hub_id in hubs {
+        let hub = Hub::load(hub_id).await?;
+        if let Some(channel_id) = channel_id {
+            let channel = hub
+                .channels
+                .get(&channel_id)
+                .ok_or(Error::ChannelNotFound)?;
+            count += rebuild_index(channel).await?;
+        } else {
+            for channel in hub.channels.values() {
+                info!("Reindexing channel {} in hub {}...", channel.id, hub.id);
+                count += rebuild_index(channel).await?;
+            }
+        }
+    }
+    Ok(count)
+}
+
+/// Rewrites message files that contain corrupt records, unreadable trailing data or duplicated messages, files still in the legacy format are rewritten in the checksummed format.
+/// Returns the number of bytes that were removed.
+///
+/// # Errors
+///
+/// This function will return an error in the following situations, but is not
+/// limited to just these cases:
+///
+/// * A hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * A message file could not be read or written.
+pub async fn compact() -> Result<u64> {
+    let mut removed = 0;
+    for hub_id in list_hubs().await? {
+        let hub = Hub::load(hub_id).await?;
+        for channel in hub.channels.values() {
+            removed += compact_channel(channel).await?;
+        }
+    }
+    Ok(removed)
+}
+
+/// Rewrites the message files of a single channel, see [`compact`] and [`Channel::compact`].
+pub async fn compact_channel(channel: &Channel) -> Result<u64> {
+    channel.compact().await
+}
+
+/// Writes a file in place by writing a temporary file next to it and renaming it over the file.
+async fn replace_file(path: &std::path::Path, bytes: Vec<u8>) -> Result {
+    let mut temp_path = path.to_path_buf().into_os_string();
+    temp_path.push(".tmp");
+    tokio::fs::write(&temp_path, bytes)
+        .await
+        .with_path(&temp_path)?;
+    tokio::fs::rename(&temp_path, path).await.with_path(path)
+}
+
+/// Rewrites a file that was stored with [`encryption::seal`] so that it is encrypted if `encrypt` is true and plaintext otherwise. Returns whether the file had to be rewritten.
+async fn convert_sealed_file(path: &std::path::Path, encrypt: bool) -> Result<bool> {
+    let bytes = tokio::fs::read(path).await.with_path(path)?;
+    if encryption::is_encrypted(&bytes) == encrypt {
+        return Ok(false);
+    }
+    let plaintext = encryption::open(&bytes)?.into_owned();
+    replace_file(path, encryption::seal_with(plaintext, encrypt)?).await?;
+    Ok(true)
+}
+
+/// Rewrites the files in a folder of user data whose names are a fingerprint followed by `extension`, see [`convert_sealed_file`]. Returns the number of files that had to be rewritten.
+async fn convert_user_files(folder: &str, extension: &str, encrypt: bool) -> Result<usize> {
+    let mut converted = 0;
+    if let Ok(mut dir) = tokio::fs::read_dir(folder).await {
+        while let Some(entry) = dir.next_entry().await.with_path(folder)? {
+            let is_user_file = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_suffix(extension))
+                .is_some_and(|user_id| hex::decode(user_id).is_ok());
+            if is_user_file && convert_sealed_file(&entry.path(), encrypt).await? {
+                converted += 1;
+            }
+        }
+    }
+    Ok(converted)
+}
+
+/// Converts the data directory in place so that hubs, message files, edit histories, change histories, drafts, offline summaries and account exports are all encrypted (`encrypt` is true) or all plaintext, see [`crate::encryption`].
+/// Both directions need the key to be configured. Corrupt records in message files are dropped like [`compact`] does, unreadable data at their end is kept.
+/// Returns the number of files that were rewritten, running it again after an interruption converts the files that are left.
+///
+/// # Errors
+///
+/// This function will return an error in the following situations, but is not
+/// limited to just these cases:
+///
+/// * No key is configured, [`Error::EncryptionKeyMissing`].
+/// * A hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * A file could not be decrypted, see [`encryption::open`].
+/// * A file could not be read or written.
+pub async fn convert_encryption(encrypt: bool) -> Result<usize> {
+    if !encryption::enabled() {
+        return Err(Error::EncryptionKeyMissing);
+    }
+    let mut converted = convert_user_files(DRAFTS_FOLDER, "", encrypt).await?
+        + convert_user_files(OFFLINE_SUMMARIES_FOLDER, "", encrypt).await?
+        + convert_user_files(ACCOUNT_EXPORTS_FOLDER, ".json", encrypt).await?;
+    for hub_id in list_hubs().await? {
+        if convert_sealed_file(&crate::paths::hub_info_file(hub_id), encrypt).await? {
+            converted += 1;
+        }
+        let history = ChangeHistory::get_path(hub_id);
+        if tokio::fs::metadata(&history).await.is_ok()
+            && convert_sealed_file(&history, encrypt).await?
+        {
+            converted += 1;
+        }
+        let hub = Hub::load(hub_id).await?;
+        for channel in hub.channels.values() {
+            for path in channel.get_message_files().await {
+                let bytes = tokio::fs::read(&path).await.with_path(&path)?;
+                let records = read_message_records(&bytes);
+                records.ensure_decrypted()?;
+                let target = if encrypt { records.messages.len() } else { 0 };
+                if !records.legacy && records.corrupt == 0 && records.encrypted == target {
+                    continue;
+                }
+                let mut new_bytes = encode_message_file_as(records.messages.iter(), encrypt)?;
+                new_bytes.extend_from_slice(&bytes[records.read..]);
+                replace_file(&path, new_bytes).await?;
+                converted += 1;
+            }
+            let edits = crate::paths::channel_edits_dir(hub_id, channel.id);
+            if let Ok(mut dir) = tokio::fs::read_dir(&edits).await {
+                while let Some(entry) = dir.next_entry().await.with_path(&edits)? {
+                    if entry.file_name().to_str().and_then(parse_hex_id).is_some()
+                        && convert_sealed_file(&entry.path(), encrypt).await?
+                    {
+                        converted += 1;
+                    }
+                }
+            }
+        }
+    }
+    Ok(converted)
+}
+
+/// Recounts the messages of the senders of every hub or of a single hub from the stored messages, replacing their leaderboards (see [`crate::leaderboard`]).
+/// Hubs that opted out of leaderboards are skipped. Returns the number of messages that were counted.
+///
+/// # Errors
+///
+/// This function will return an error in the following situations, but is not
+/// limited to just these cases:
+///
+/// * A hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * A leaderboard could not be loaded or saved for any of the reasons outlined by [`HubLeaderboard::load`] and [`HubLeaderboard::save`].
+pub async fn backfill_leaderboards(hub_id: Option<HubId>) -> Result<usize> {
+    let hubs = if let Some(hub_id) = hub_id {
+        vec![hub_id]
+    } else {
+        list_hubs().await?
+    };
+    let now = Utc::now();
+    let mut count = 0;
+    for hub_id in hubs {
+        let hub = Hub::load(hub_id).await?;
+        if HubLeaderboard::load(hub_id).await?.disabled {
+            continue;
+        }
+        let mut leaderboard = HubLeaderboard::default();
+        for channel in hub.channels.values() {
+            info!(
+                "Counting messages of channel {} in hub {}...",
+                channel.id, hub.id
+            );
+            for signed in channel.get_all_messages().await {
+                if let Ok(message) = Message::try_from(&signed) {
+                    leaderboard.apply(now, &message.sender, message.created, 1);
+                    count += 1;
+                }
+            }
+        }
+        leaderboard.save(hub_id).await?;
+    }
+    Ok(count)
+}
+
+/// Cross-checks hub info files against the channel directories and search index logs and the owned hub counters of user quotas, and checks every message file for corrupt records.
+/// If `repair` is true, quotas are corrected to the number of hubs their users own, content policies stored by older versions are rewritten in the current layout and unreadable data at the end of message files is truncated, corrupt records in the middle of a file are left for [`compact`] to drop.
+/// Returns a description of every problem that was found.
+pub async fn verify(repair: bool) -> Result<Vec<String>> {
+    let mut problems = Vec::new();
+    let mut owned_hubs: HashMap<String, usize> = HashMap::new();
+    let hubs = list_hubs().await?;
+    for hub_id in hubs.iter() {
+        let hub = match Hub::load(*hub_id).await {
+            Ok(hub) => hub,
+            Err(err) => {
+                problems.push(format!("hub {} could not be loaded: {}", hub_id, err));
+                continue;
+            }
+        };
+        if hub.id != *hub_id {
+            problems.push(format!("hub {} is stored under the ID {}", hub.id, hub_id));
+        }
+        *owned_hubs.entry(hub.owner.clone()).or_default() += 1;
+        let policy_path = ContentPolicy::get_path(hub.id);
+        if let Ok(bytes) = tokio::fs::read(&policy_path).await {
+            match ContentPolicy::decode(&bytes) {
+                Ok((policy, true)) => {
+                    problems.push(format!(
+                        "content policy of hub {} is stored in an older layout",
+                        hub.id
+                    ));
+                    if repair {
+                        policy.save(hub.id).await?;
+                        problems.push(format!("content policy of hub {} was rewritten", hub.id));
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => problems.push(format!(
+                    "content policy of hub {} could not be read: {}",
+                    hub.id, err
+                )),
+            }
+        }
+        // Hubs from before channel names had to be unique can still have duplicates, they are reported but not renamed.
+        for channels in hub.duplicate_channel_names() {
+            problems.push(format!(
+                "hub {} has channels with the name \"{}\" differing only in case: {}",
+                hub.id,
+                channels[0].name,
+                channels
+                    .iter()
+                    .map(|channel| channel.id.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ));
+        }
+        for channel in hub.channels.values() {
+            if !channel.get_folder().is_dir() {
+                problems.push(format!(
+                    "channel {} in hub {} has no data directory",
+                    channel.id, hub.id
+                ));
+                continue;
+            }
+            let log_path = crate::paths::channel_log_file(hub.id, channel.id);
+            if let Ok(log) = tokio::fs::read(&log_path).await {
+                if log.len() != 16 {
+                    problems.push(format!(
+                        "channel {} in hub {} has an invalid index log",
+                        channel.id, hub.id
+                    ));
+                }
+            }
+            for path in channel.get_message_files().await {
+                let bytes = tokio::fs::read(&path).await?;
+                let records = read_message_records(&bytes);
+                if records.corrupt != 0 {
+                    problems.push(format!(
+                        "message file {} contains {} corrupt records",
+                        path.display(),
+                        records.corrupt
+                    ));
+                }
+                if records.locked != 0 {
+                    problems.push(format!(
+                        "message file {} contains {} encrypted records that can not be decrypted with the configured key",
+                        path.display(),
+                        records.locked
+                    ));
+                }
+                if records.read != bytes.len() {
+                    problems.push(format!(
+                        "message file {} has {} unreadable bytes at its end",
+                        path.display(),
+                        bytes.len() - records.read
+                    ));
+                    if repair {
+                        let file = std::fs::OpenOptions::new().write(true).open(&path)?;
+                        file.set_len(records.read as u64)?;
+                        problems.push(format!(
+                            "message file {} was truncated to {} bytes",
+                            path.display(),
+                            records.read
+                        ));
+                    }
+                }
+            }
+        }
+        if let Ok(mut dir) = tokio::fs::read_dir(hub.get_data_path()).await {
+            while let Some(entry) = dir.next_entry().await? {
+                let channel_id = entry
+                    .file_name()
+                    .to_str()
+                    .and_then(parse_hex_id)
+                    .map(ChannelId::from_u128);
+                if !channel_id.is_some_and(|id| hub.channels.contains_key(&id)) {
+                    problems.push(format!(
+                        "{} does not belong to a channel in hub {}",
+                        entry.path().display(),
+                        hub.id
+                    ));
+                }
+            }
+        }
+    }
+    if let Ok(mut dir) = tokio::fs::read_dir(HUB_DATA_FOLDER).await {
+        while let Some(entry) = dir.next_entry().await? {
+            let hub_id = entry
+                .file_name()
+                .to_str()
+                .and_then(parse_hex_id)
+                .map(HubId::from_u128);
+            if !hub_id.is_some_and(|id| hubs.contains(&id)) {
+                problems.push(format!(
+                    "{} does not belong to a hub",
+                    entry.path().display()
+                ));
+            }
+        }
+    }
+    if let Ok(mut dir) = tokio::fs::read_dir(USER_QUOTAS_FOLDER).await {
+        while let Some(entry) = dir.next_entry().await? {
+            let user_id = match entry.file_name().into_string() {
+                Ok(user_id) => user_id,
+                Err(_) => continue,
+            };
+            let mut quota = match UserQuota::load(&user_id).await {
+                Ok(quota) => quota,
+                Err(err) => {
+                    problems.push(format!(
+                        "quota of user {} could not be loaded: {}",
+                        user_id, err
+                    ));
+                    continue;
+                }
+            };
+            let counted = quota.owned_hubs;
+            let owned = owned_hubs.get(&user_id).copied().unwrap_or(0);
+            if quota.reconcile_hubs(owned) {
+                problems.push(format!(
+                    "quota of user {} counts {} owned hubs but they own {}",
+                    user_id, counted, owned
+                ));
+                if repair {
+                    quota.save(&user_id).await?;
+                    problems.push(format!("quota of user {} was corrected", user_id));
+                }
+            }
+        }
+    }
+    Ok(problems)
+}
@@ -0,0 +1,250 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::IoContext, hub::Hub, nicknames::HubNicknames, HubId, Result};
+
+/// Folder where the recently changed members of each hub are stored.
+pub const MEMBER_CHANGES_FOLDER: &str = "data/hubs/member_changes/";
+
+/// Number of member changes kept for each hub, clients that are further behind get the full member map.
+pub const MAX_MEMBER_CHANGES: usize = 1024;
+
+/// Maximum number of members in one member map response, the rest is fetched with the continuation token.
+pub const MAX_MEMBER_MAP_ENTRIES: usize = 5000;
+
+/// A member that joined, left or changed their nickname.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MemberChange {
+    /// Version of the hub after the change was made, see [`crate::hub_changes`].
+    pub version: u64,
+    pub user_id: String,
+}
+
+/// The members of a hub that changed recently, stored separately from the hub's other changes so that many more of them can be kept.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct MemberChanges {
+    /// Hub version after which every member change is known, `None` if no change has been recorded yet.
+    pub complete_since: Option<u64>,
+    /// The last (at most [`MAX_MEMBER_CHANGES`]) changes, oldest first.
+    pub recent: VecDeque<MemberChange>,
+}
+
+impl MemberChanges {
+    /// Gets the path of the file that a hub's member changes are stored in.
+    pub fn get_path(hub_id: HubId) -> PathBuf {
+        crate::paths::member_changes_file(hub_id)
+    }
+
+    /// Loads the member changes of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The file exists but could not be read.
+    /// * The file's contents could not be deserialized.
+    pub async fn load(hub_id: HubId) -> Result<Self> {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(bincode::deserialize(&bytes)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_path(path),
+        }
+    }
+
+    /// Saves the member changes of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The member changes folder does not exist and could not be created.
+    /// * The data could not be written to the disk.
+    pub async fn save(&self, hub_id: HubId) -> Result {
+        tokio::fs::create_dir_all(MEMBER_CHANGES_FOLDER)
+            .await
+            .with_path(MEMBER_CHANGES_FOLDER)?;
+        let path = Self::get_path(hub_id);
+        tokio::fs::write(&path, bincode::serialize(self)?)
+            .await
+            .with_path(path)
+    }
+
+    /// Removes the member changes of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the file exists but could not be removed.
+    pub async fn remove(hub_id: HubId) -> Result {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::remove_file(&path).await {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result.with_path(path),
+        }
+    }
+
+    /// Records that a member changed in the given version of the hub.
+    /// Changes made before the first recorded one are not known, so the history starts just before it.
+    pub fn push(&mut self, version: u64, user_id: String) {
+        if self.complete_since.is_none() {
+            self.complete_since = Some(version.saturating_sub(1));
+        }
+        self.recent.push_back(MemberChange { version, user_id });
+        while self.recent.len() > MAX_MEMBER_CHANGES {
+            if let Some(dropped) = self.recent.pop_front() {
+                self.complete_since = Some(dropped.version);
+            }
+        }
+    }
+
+    /// Gets the IDs of the members that changed after the given version, sorted.
+    /// Returns `None` if those changes are no longer known or the given version is newer than `current`, the client then needs the full member map.
+    pub fn since(&self, version: u64, current: u64) -> Option<Vec<String>> {
+        if version == current {
+            return Some(Vec::new());
+        }
+        match self.complete_since {
+            Some(complete_since) if version >= complete_since && version < current => Some(
+                self.recent
+                    .iter()
+                    .filter(|change| change.version > version)
+                    .map(|change| change.user_id.clone())
+                    .collect::<BTreeSet<String>>()
+                    .into_iter()
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// Nicknames of the members of a hub by member ID, for clients that only need to show the senders of messages.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MemberMap {
+    /// Version of the hub the map is for, clients should keep the version of the first page they get.
+    pub version: u64,
+    /// If true `members` is (a page of) every member of the hub, otherwise only members that changed since the requested version.
+    pub full: bool,
+    /// Nickname of each member, `null` for members without one.
+    pub members: BTreeMap<String, Option<String>>,
+    /// Members that changed since the requested version and are no longer in the hub, always empty if `full` is true.
+    pub removed: Vec<String>,
+    /// Token to pass as `after` to get the next page, `None` if this is the last page.
+    pub continuation: Option<String>,
+}
+
+impl MemberMap {
+    /// Creates a page of the member map of a hub, listing the `changed` members or every member if it is `None`.
+    /// Members are sorted by ID and only members after `after` are included.
+    pub fn new(
+        hub: &Hub,
+        nicknames: &HubNicknames,
+        version: u64,
+        changed: Option<Vec<String>>,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Self {
+        let full = changed.is_none();
+        let ids: Vec<String> = match changed {
+            Some(changed) => changed,
+            None => {
+                let mut ids: Vec<String> = hub.members.keys().cloned().collect();
+                ids.sort();
+                ids
+            }
+        };
+        let mut remaining = ids
+            .into_iter()
+            .filter(|id| after.is_none_or(|after| id.as_str() > after))
+            .peekable();
+        let mut map = Self {
+            version,
+            full,
+            members: BTreeMap::new(),
+            removed: Vec::new(),
+            continuation: None,
+        };
+        let mut last = None;
+        for id in remaining.by_ref().take(limit) {
+            if hub.members.contains_key(&id) {
+                map.members
+                    .insert(id.clone(), nicknames.nicknames.get(&id).cloned());
+            } else {
+                map.removed.push(id.clone());
+            }
+            last = Some(id);
+        }
+        if remaining.peek().is_some() {
+            map.continuation = last;
+        }
+        map
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MemberChanges, MemberMap, MAX_MEMBER_CHANGES};
+    use crate::{hub::Hub, nicknames::HubNicknames, HubId};
+
+    #[test]
+    fn changes_since_version() {
+        let mut changes = MemberChanges::default();
+        assert_eq!(changes.since(0, 3), None);
+        assert_eq!(changes.since(3, 3), Some(Vec::new()));
+        changes.push(4, "b".to_string());
+        changes.push(5, "a".to_string());
+        changes.push(6, "b".to_string());
+        assert_eq!(changes.since(2, 6), None);
+        assert_eq!(
+            changes.since(3, 6),
+            Some(vec!["a".to_string(), "b".to_string()])
+        );
+        assert_eq!(changes.since(5, 6), Some(vec!["b".to_string()]));
+        assert_eq!(changes.since(7, 6), None);
+        for version in 7..7 + MAX_MEMBER_CHANGES as u64 {
+            changes.push(version, "c".to_string());
+        }
+        assert_eq!(changes.since(5, 6 + MAX_MEMBER_CHANGES as u64), None);
+        assert_eq!(
+            changes.since(6, 6 + MAX_MEMBER_CHANGES as u64),
+            Some(vec!["c".to_string()])
+        );
+    }
+
+    #[test]
+    fn pages_and_removed_members() {
+        let mut hub = Hub::new("hub".to_string(), HubId::from_u128(1), "a".to_string());
+        for member in &["b", "c", "d"] {
+            hub.user_join(member.to_string()).unwrap();
+        }
+        let mut nicknames = HubNicknames::default();
+        nicknames
+            .nicknames
+            .insert("b".to_string(), "Bee".to_string());
+        let first = MemberMap::new(&hub, &nicknames, 4, None, None, 2);
+        assert!(first.full);
+        assert_eq!(first.members.len(), 2);
+        assert_eq!(first.members["b"], Some("Bee".to_string()));
+        assert_eq!(first.continuation.as_deref(), Some("b"));
+        let second = MemberMap::new(&hub, &nicknames, 4, None, Some("b"), 2);
+        assert_eq!(second.members.keys().collect::<Vec<_>>(), vec!["c", "d"]);
+        assert_eq!(second.continuation, None);
+        let delta = MemberMap::new(
+            &hub,
+            &nicknames,
+            5,
+            Some(vec!["b".to_string(), "e".to_string()]),
+            None,
+            10,
+        );
+        assert!(!delta.full);
+        assert_eq!(delta.members.len(), 1);
+        assert_eq!(delta.removed, vec!["e".to_string()]);
+    }
+}
@@ -0,0 +1,233 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use crate::{error::IoContext, HubId, Result};
+
+/// Folder where the membership events of each hub are stored, one file per hub that events are appended to.
+pub const MEMBERSHIP_LOG_FOLDER: &str = "data/hubs/members/";
+
+/// Maximum number of raw events returned by a single request for member history, larger limits are lowered to this.
+pub const MAX_MEMBERSHIP_EVENTS_PER_REQUEST: usize = 256;
+
+/// Changes to the membership of a hub.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MembershipEventKind {
+    Joined,
+    Left,
+    Kicked,
+    Banned,
+}
+
+/// A change to the membership of a hub.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MembershipEvent {
+    /// ID of the user whose membership changed.
+    pub user_id: String,
+    /// What happened.
+    pub kind: MembershipEventKind,
+    /// When it happened.
+    pub time: DateTime<Utc>,
+}
+
+impl MembershipEvent {
+    /// Creates an event that happened now.
+    pub fn new(user_id: String, kind: MembershipEventKind) -> Self {
+        Self {
+            user_id,
+            kind,
+            time: Utc::now(),
+        }
+    }
+}
+
+/// Number of events of each kind on a day (UTC).
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct DailyMembershipCounts {
+    pub joined: usize,
+    pub left: usize,
+    pub kicked: usize,
+    pub banned: usize,
+}
+
+/// Membership history of a hub over a time range.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MemberHistory {
+    /// Counts of the events in the range for each day that had any events.
+    pub days: BTreeMap<NaiveDate, DailyMembershipCounts>,
+    /// Events in the range starting at the requested offset, oldest first. Only given to users that can administrate the hub.
+    pub events: Option<Vec<MembershipEvent>>,
+    /// Offset to request to get the next events, if there are more.
+    pub next_offset: Option<usize>,
+}
+
+/// Gets the path of the file that a hub's membership events are stored in.
+pub fn get_path(hub_id: HubId) -> PathBuf {
+    crate::paths::membership_log_file(hub_id)
+}
+
+/// Appends an event to a hub's membership log.
+///
+/// # Errors
+///
+/// This function will return an error in the following situations, but is not
+/// limited to just these cases:
+///
+/// * The membership log folder does not exist and could not be created.
+/// * The event could not be written to the disk.
+pub async fn record(hub_id: HubId, event: MembershipEvent) -> Result {
+    tokio::fs::create_dir_all(MEMBERSHIP_LOG_FOLDER)
+        .await
+        .with_path(MEMBERSHIP_LOG_FOLDER)?;
+    // Written with a single append so concurrent events do not interleave.
+    let bytes = bincode::serialize(&event)?;
+    let path = get_path(hub_id);
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .await
+        .with_path(&path)?;
+    file.write_all(&bytes).await.with_path(&path)?;
+    file.flush().await.with_path(path)?;
+    crate::hub_activity::record_membership(hub_id, &event.kind);
+    Ok(())
+}
+
+/// Reads all of a hub's membership events, oldest first, stopping at the first event that cannot be read.
+///
+/// # Errors
+///
+/// This function returns an error if the log exists but could not be read.
+pub async fn read(hub_id: HubId) -> Result<Vec<MembershipEvent>> {
+    let path = get_path(hub_id);
+    let bytes = match tokio::fs::read(&path).await {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_path(path),
+    };
+    let mut events = Vec::new();
+    let mut remaining = bytes.as_slice();
+    while !remaining.is_empty() {
+        match bincode::deserialize_from::<_, MembershipEvent>(&mut remaining) {
+            Ok(event) => events.push(event),
+            Err(_) => break,
+        }
+    }
+    Ok(events)
+}
+
+/// Removes a hub's membership log.
+///
+/// # Errors
+///
+/// This function returns an error if the log exists but could not be removed.
+pub async fn remove(hub_id: HubId) -> Result {
+    let path = get_path(hub_id);
+    match tokio::fs::remove_file(&path).await {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result.with_path(path),
+    }
+}
+
+/// Summarizes the events between `from` and `to` (inclusive), the raw events are only included if `include_events` is true.
+/// At most `limit` raw events are included, starting at `offset`.
+pub fn history(
+    events: &[MembershipEvent],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    include_events: bool,
+    offset: usize,
+    limit: usize,
+) -> MemberHistory {
+    let in_range: Vec<&MembershipEvent> = events
+        .iter()
+        .filter(|event| event.time >= from && event.time <= to)
+        .collect();
+    let mut days: BTreeMap<NaiveDate, DailyMembershipCounts> = BTreeMap::new();
+    for event in in_range.iter() {
+        let counts = days.entry(event.time.date_naive()).or_default();
+        match event.kind {
+            MembershipEventKind::Joined => counts.joined += 1,
+            MembershipEventKind::Left => counts.left += 1,
+            MembershipEventKind::Kicked => counts.kicked += 1,
+            MembershipEventKind::Banned => counts.banned += 1,
+        }
+    }
+    let (events, next_offset) = if include_events {
+        let limit = limit.min(MAX_MEMBERSHIP_EVENTS_PER_REQUEST);
+        let page: Vec<MembershipEvent> = in_range
+            .iter()
+            .skip(offset)
+            .take(limit)
+            .map(|event| (*event).clone())
+            .collect();
+        let end = offset.saturating_add(page.len());
+        (
+            Some(page),
+            if end < in_range.len() {
+                Some(end)
+            } else {
+                None
+            },
+        )
+    } else {
+        (None, None)
+    };
+    MemberHistory {
+        days,
+        events,
+        next_offset,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{history, MembershipEvent, MembershipEventKind, MAX_MEMBERSHIP_EVENTS_PER_REQUEST};
+
+    #[test]
+    fn counts_and_pages() {
+        let start = Utc.with_ymd_and_hms(2021, 4, 20, 12, 0, 0).unwrap();
+        let mut events = Vec::new();
+        for n in 0..300 {
+            events.push(MembershipEvent {
+                user_id: n.to_string(),
+                kind: MembershipEventKind::Joined,
+                time: start + Duration::hours(n),
+            });
+        }
+        events.push(MembershipEvent {
+            user_id: "0".to_string(),
+            kind: MembershipEventKind::Banned,
+            time: start + Duration::hours(1),
+        });
+        let end = start + Duration::days(1000);
+
+        let summary = history(&events, start, end, false, 0, 10);
+        assert!(summary.events.is_none());
+        let first_day = summary.days[&start.date_naive()];
+        assert_eq!(first_day.joined, 12);
+        assert_eq!(first_day.banned, 1);
+        assert_eq!(
+            summary.days.values().map(|day| day.joined).sum::<usize>(),
+            300
+        );
+
+        let page = history(&events, start, end, true, 0, usize::MAX);
+        assert_eq!(
+            page.events.unwrap().len(),
+            MAX_MEMBERSHIP_EVENTS_PER_REQUEST
+        );
+        assert_eq!(page.next_offset, Some(MAX_MEMBERSHIP_EVENTS_PER_REQUEST));
+        let last = history(&events, start, end, true, 290, 100);
+        assert_eq!(last.events.unwrap().len(), 11);
+        assert_eq!(last.next_offset, None);
+
+        let narrow = history(&events, start, start + Duration::hours(1), true, 0, 10);
+        assert_eq!(narrow.events.unwrap().len(), 3);
+    }
+}
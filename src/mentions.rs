@@ -0,0 +1,154 @@
+use std::{collections::HashSet, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::IoContext, hub::Hub, permission::ChannelPermission, ChannelId, HubId, Result, ID,
+};
+
+/// Folder where the IDs of the mentionable groups of each hub are stored.
+pub const MENTIONABLE_GROUPS_FOLDER: &str = "data/hubs/mentionable/";
+
+/// Permission groups of a hub that can be mentioned, stored separately from the hub itself.
+/// Groups can not be mentioned until a user that can administrate the hub makes them mentionable, so `@everyone` does nothing by default.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct MentionableGroups {
+    pub groups: HashSet<ID>,
+}
+
+impl MentionableGroups {
+    /// Gets the path of the file that a hub's mentionable groups are stored in.
+    pub fn get_path(hub_id: HubId) -> PathBuf {
+        crate::paths::mentionable_groups_file(hub_id)
+    }
+
+    /// Loads the mentionable groups of a hub, a hub without the file has none.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The file exists but could not be read.
+    /// * The file's contents could not be deserialized.
+    pub async fn load(hub_id: HubId) -> Result<Self> {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(bincode::deserialize(&bytes)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_path(path),
+        }
+    }
+
+    /// Saves the mentionable groups of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The mentionable groups folder does not exist and could not be created.
+    /// * The data could not be written to the disk.
+    pub async fn save(&self, hub_id: HubId) -> Result {
+        tokio::fs::create_dir_all(MENTIONABLE_GROUPS_FOLDER)
+            .await
+            .with_path(MENTIONABLE_GROUPS_FOLDER)?;
+        let path = Self::get_path(hub_id);
+        tokio::fs::write(&path, bincode::serialize(self)?)
+            .await
+            .with_path(path)
+    }
+
+    /// Removes the mentionable groups of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the file exists but could not be removed.
+    pub async fn remove(hub_id: HubId) -> Result {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::remove_file(&path).await {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result.with_path(path),
+        }
+    }
+}
+
+/// Checks if `content` mentions `name`, as `@name` (ignoring case) not preceded or followed by a letter or number.
+fn mentions_name(content: &str, name: &str) -> bool {
+    let content = content.to_lowercase();
+    let mention = format!("@{}", name.to_lowercase());
+    content.match_indices(&mention).any(|(start, _)| {
+        let before = content[..start].chars().next_back();
+        let after = content[start + mention.len()..].chars().next();
+        !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+    })
+}
+
+/// Finds the permission groups that a message mentions, sorted by ID.
+/// Only mentionable groups are resolved, and only if the sender has the [`ChannelPermission::MentionGroups`] permission in the channel, other mentions are left as plain text.
+pub fn resolve_group_mentions(
+    hub: &Hub,
+    mentionable: &MentionableGroups,
+    sender_id: &str,
+    channel_id: ChannelId,
+    content: &str,
+) -> Vec<ID> {
+    let can_mention = hub.members.get(sender_id).is_some_and(|member| {
+        member.has_channel_permission(channel_id, ChannelPermission::MentionGroups, hub)
+    });
+    if !can_mention {
+        return Vec::new();
+    }
+    let mut mentions: Vec<ID> = hub
+        .groups
+        .values()
+        .filter(|group| mentionable.groups.contains(&group.id))
+        .filter(|group| mentions_name(content, &group.name))
+        .map(|group| group.id)
+        .collect();
+    mentions.sort();
+    mentions
+}
+
+#[cfg(test)]
+mod test {
+    use super::{resolve_group_mentions, MentionableGroups};
+    use crate::{hub::Hub, permission::ChannelPermission, ChannelId, HubId};
+
+    const OWNER: &str = "0123456789ABCDEF0123456789ABCDEF01234567";
+    const MEMBER: &str = "89ABCDEF0123456789ABCDEF0123456789ABCDEF";
+
+    #[test]
+    fn resolves_mentionable_groups() {
+        let channel_id = ChannelId::from_u128(2);
+        let mut hub = Hub::new("hub".to_string(), HubId::from_u128(1), OWNER.to_string());
+        let everyone = hub.default_group;
+        let mut member = crate::hub::HubMember::new(MEMBER.to_string(), hub.id);
+        member.set_channel_permission(channel_id, ChannelPermission::MentionGroups, Some(true));
+        hub.members.insert(MEMBER.to_string(), member);
+        let mut mentionable = MentionableGroups::default();
+        let resolve = |hub: &Hub, mentionable: &MentionableGroups, sender: &str, content: &str| {
+            resolve_group_mentions(hub, mentionable, sender, channel_id, content)
+        };
+
+        assert!(resolve(&hub, &mentionable, OWNER, "Hi @everyone.").is_empty());
+        mentionable.groups.insert(everyone);
+        assert_eq!(
+            resolve(&hub, &mentionable, OWNER, "Hi @Everyone."),
+            vec![everyone]
+        );
+        assert_eq!(
+            resolve(&hub, &mentionable, MEMBER, "@everyone"),
+            vec![everyone]
+        );
+        assert!(resolve(&hub, &mentionable, OWNER, "mail@everyone.example").is_empty());
+        assert!(resolve(&hub, &mentionable, OWNER, "@everyones").is_empty());
+        // Users without the permission can still write the text, it just does not notify anyone.
+        hub.members.get_mut(MEMBER).unwrap().set_channel_permission(
+            channel_id,
+            ChannelPermission::MentionGroups,
+            Some(false),
+        );
+        assert!(resolve(&hub, &mentionable, MEMBER, "@everyone").is_empty());
+    }
+}
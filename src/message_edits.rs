@@ -0,0 +1,150 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::IoContext, ChannelId, HubId, MessageId, Result};
+
+/// Content a message had before it was edited.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MessageEdit {
+    /// Content of the message before the edit.
+    pub content: String,
+    /// Time the content was replaced.
+    pub edited_at: DateTime<Utc>,
+}
+
+/// Previous versions of an edited message, only served by `/v3/message_history` and never included with the message itself.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct MessageHistory {
+    /// Previous contents of the message, oldest first.
+    pub edits: Vec<MessageEdit>,
+}
+
+impl MessageHistory {
+    /// Gets the path of the file that a message's history is stored in, next to the channel's message files.
+    pub fn get_path(hub_id: HubId, channel_id: ChannelId, message_id: MessageId) -> PathBuf {
+        crate::paths::message_edits_file(hub_id, channel_id, message_id)
+    }
+
+    /// Gets the folder that the histories of a channel's messages are stored in.
+    pub fn get_folder(hub_id: HubId, channel_id: ChannelId) -> PathBuf {
+        crate::paths::channel_edits_dir(hub_id, channel_id)
+    }
+
+    /// Loads the history of a message, a message that was never edited has an empty history.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The file exists but could not be read.
+    /// * The file is encrypted and could not be decrypted, see [`crate::encryption::open`].
+    /// * The file's contents could not be deserialized.
+    pub async fn load(hub_id: HubId, channel_id: ChannelId, message_id: MessageId) -> Result<Self> {
+        let path = Self::get_path(hub_id, channel_id, message_id);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(bincode::deserialize(&crate::encryption::open(&bytes)?)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_path(path),
+        }
+    }
+
+    /// Saves the history of a message.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The history folder does not exist and could not be created.
+    /// * The data could not be encrypted, see [`crate::encryption::seal`].
+    /// * The data could not be written to the disk.
+    pub async fn save(
+        &self,
+        hub_id: HubId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) -> Result {
+        let folder = Self::get_folder(hub_id, channel_id);
+        tokio::fs::create_dir_all(&folder).await.with_path(folder)?;
+        let path = Self::get_path(hub_id, channel_id, message_id);
+        tokio::fs::write(&path, crate::encryption::seal(bincode::serialize(self)?)?)
+            .await
+            .with_path(path)
+    }
+
+    /// Removes the history of a message, used when the message is deleted.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the file exists but could not be removed.
+    pub async fn remove(hub_id: HubId, channel_id: ChannelId, message_id: MessageId) -> Result {
+        let path = Self::get_path(hub_id, channel_id, message_id);
+        match tokio::fs::remove_file(&path).await {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result.with_path(path),
+        }
+    }
+
+    /// Adds a previous version of the message, dropping the oldest versions so that at most `depth` are kept.
+    pub fn push(&mut self, edit: MessageEdit, depth: usize) {
+        self.edits.push(edit);
+        if self.edits.len() > depth {
+            let excess = self.edits.len() - depth;
+            self.edits.drain(..excess);
+        }
+    }
+}
+
+/// Records the content a message had before it was edited, see [`MessageHistory::push`].
+///
+/// # Errors
+///
+/// This function may return an error for any of the reasons outlined in [`MessageHistory::load`] and [`MessageHistory::save`].
+pub async fn record(
+    hub_id: HubId,
+    channel_id: ChannelId,
+    message_id: MessageId,
+    edit: MessageEdit,
+) -> Result {
+    let mut history = MessageHistory::load(hub_id, channel_id, message_id).await?;
+    history.push(edit, crate::quotas::limits().max_message_edits);
+    history.save(hub_id, channel_id, message_id).await
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use super::{MessageEdit, MessageHistory};
+
+    #[test]
+    fn history_is_capped() {
+        let mut history = MessageHistory::default();
+        for n in 0..5 {
+            history.push(
+                MessageEdit {
+                    content: n.to_string(),
+                    edited_at: Utc::now(),
+                },
+                3,
+            );
+        }
+        let contents: Vec<&str> = history
+            .edits
+            .iter()
+            .map(|edit| edit.content.as_str())
+            .collect();
+        assert_eq!(contents, vec!["2", "3", "4"]);
+        history.push(
+            MessageEdit {
+                content: "5".to_string(),
+                edited_at: Utc::now(),
+            },
+            0,
+        );
+        assert!(history.edits.is_empty());
+    }
+}
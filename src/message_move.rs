@@ -0,0 +1,194 @@
+use std::{collections::HashSet, convert::TryFrom};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::{
+    channel::{Channel, Message, SignedMessage},
+    message_edits::MessageHistory,
+    server::{self, ServerNotification},
+    unfurl::MessagePreviews,
+    ChannelId, HubId, MessageId, Result,
+};
+
+/// Maximum number of messages that can be moved with one request.
+pub const MAX_MOVE_MESSAGES: usize = 100;
+
+/// Which of the requested messages were moved, served by `/v3/move_messages`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct MoveResult {
+    /// Messages that are now in the target channel and no longer in the source channel, in the order they were stored in.
+    pub moved: Vec<MessageId>,
+    /// Messages that are not in the source channel, nothing was done with them.
+    pub not_found: Vec<MessageId>,
+    /// Messages that were changed or removed while they were being moved, or could not be removed from the source channel. They are still where they were.
+    pub failed: Vec<MessageId>,
+}
+
+/// Moves the edit history and link previews of a moved message to its new channel, failures are only logged since the message itself has already moved.
+async fn move_extras(hub_id: HubId, source: ChannelId, target: ChannelId, message_id: MessageId) {
+    let result: Result = async {
+        let history = MessageHistory::load(hub_id, source, message_id).await?;
+        if !history.edits.is_empty() {
+            history.save(hub_id, target, message_id).await?;
+            MessageHistory::remove(hub_id, source, message_id).await?;
+        }
+        let previews = MessagePreviews::load(hub_id, source, message_id).await?;
+        if !previews.previews.is_empty() {
+            previews.save(hub_id, target, message_id).await?;
+            MessagePreviews::remove(hub_id, source, message_id).await?;
+        }
+        Ok(())
+    }
+    .await;
+    if let Err(err) = result {
+        warn!(
+            "Unable to move the history or previews of message {} in hub {}: {}",
+            message_id,
+            hub_id,
+            err.chain()
+        );
+    }
+}
+
+/// Removes copies of messages from the target channel again, after they could not be removed from the source channel.
+/// If this fails the messages are in both channels, which is logged.
+async fn roll_back(target: &Channel, copies: &[SignedMessage]) -> Result {
+    let from = match copies.iter().map(|copy| copy.created).min() {
+        Some(from) => from,
+        None => return Ok(()),
+    };
+    target
+        .remove_messages_between(from, Utc::now(), |stored| copies.contains(stored))
+        .await
+        .map(|_| ())
+}
+
+/// Moves messages from one channel of a hub to another. Messages keep their ID, sender and time and are stored in the target channel's file of the day they were created on (see [`Channel::add_messages_on_created_day`]).
+/// The signed content of a moved message still names the channel it was sent in, so moved messages can no longer be edited.
+///
+/// Messages are first copied to the target channel and then removed from the source channel, copies of messages that could not be removed are taken out of the target channel again.
+/// Afterwards the [`crate::server::Server`] is told that the messages were deleted from the source channel and moved into the target channel, which updates both search indexes and notifies subscribers.
+///
+/// # Errors
+///
+/// This function returns an error if the messages could not be written to the target channel, see [`Channel::add_messages_on_created_day`]; in that case no message was moved.
+pub async fn move_messages(
+    hub_id: HubId,
+    source: ChannelId,
+    target: ChannelId,
+    message_ids: &[MessageId],
+) -> Result<MoveResult> {
+    let source_channel = Channel::new(String::new(), source, hub_id);
+    let target_channel = Channel::new(String::new(), target, hub_id);
+    let mut seen = HashSet::new();
+    let copies: Vec<SignedMessage> = source_channel
+        .get_messages(message_ids.to_vec())
+        .await
+        .into_iter()
+        .filter(|message| seen.insert(message.id))
+        .collect();
+    let mut result = MoveResult {
+        not_found: message_ids
+            .iter()
+            .filter(|id| !seen.contains(id))
+            .copied()
+            .collect(),
+        ..MoveResult::default()
+    };
+    let from = match copies.iter().map(|copy| copy.created).min() {
+        Some(from) => from,
+        None => return Ok(result),
+    };
+    if let Err(err) = target_channel.add_messages_on_created_day(&copies).await {
+        if let Err(rollback) = roll_back(&target_channel, &copies).await {
+            error!(
+                "Unable to remove partly moved messages from channel {} in hub {}: {}",
+                target,
+                hub_id,
+                rollback.chain()
+            );
+        }
+        return Err(err);
+    }
+    // Only copies that are still exactly as they were read are removed, a message edited in the meantime stays in the source channel.
+    let removed: HashSet<MessageId> = match source_channel
+        .remove_messages_between(from, Utc::now(), |stored| copies.contains(stored))
+        .await
+    {
+        Ok(removed) => removed.into_iter().collect(),
+        Err(err) => {
+            warn!(
+                "Unable to remove all moved messages from channel {} in hub {}: {}",
+                source,
+                hub_id,
+                err.chain()
+            );
+            // Some files may have been rewritten before the error, whatever is no longer in the source channel was moved.
+            let remaining: HashSet<MessageId> = source_channel
+                .get_messages(seen.iter().copied().collect())
+                .await
+                .into_iter()
+                .map(|message| message.id)
+                .collect();
+            seen.difference(&remaining).copied().collect()
+        }
+    };
+    let (moved, failed): (Vec<SignedMessage>, Vec<SignedMessage>) = copies
+        .into_iter()
+        .partition(|copy| removed.contains(&copy.id));
+    if let Err(err) = roll_back(&target_channel, &failed).await {
+        error!(
+            "Unable to remove messages that were not moved from channel {} in hub {}: {}",
+            target,
+            hub_id,
+            err.chain()
+        );
+    }
+    result.failed = failed.iter().map(|message| message.id).collect();
+    result.moved = moved.iter().map(|message| message.id).collect();
+    for message_id in result.moved.iter() {
+        move_extras(hub_id, source, target, *message_id).await;
+    }
+    if !result.moved.is_empty() {
+        server::publish(ServerNotification::MessagesDeleted(
+            hub_id,
+            source,
+            result.moved.clone(),
+        ))
+        .await;
+    }
+    for signed in moved {
+        if let Ok(message) = Message::try_from(&signed) {
+            server::publish(ServerNotification::MessageMoved(
+                hub_id,
+                target,
+                signed.id,
+                signed.armoured_content,
+                message,
+            ))
+            .await;
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::MoveResult;
+    use crate::MessageId;
+
+    #[test]
+    fn result_lists_every_requested_message() {
+        let result = MoveResult {
+            moved: vec![MessageId::from_u128(1)],
+            not_found: vec![MessageId::from_u128(2)],
+            failed: Vec::new(),
+        };
+        assert_eq!(
+            serde_json::to_string(&result).unwrap(),
+            r#"{"moved":["00000000-0000-0000-0000-000000000001"],"not_found":["00000000-0000-0000-0000-000000000002"],"failed":[]}"#
+        );
+    }
+}
@@ -0,0 +1,599 @@
+use std::convert::TryFrom;
+
+use chrono::Utc;
+use pgp::SignedPublicKey;
+use tracing::warn;
+
+use crate::{
+    channel::{AuthorType, Channel, ForwardedFrom, Message, SignedMessage},
+    check_permission,
+    content_policy::ContentPolicy,
+    drafts,
+    error::{Error, Result},
+    hub::Hub,
+    hub_activity,
+    instrumentation::{Instrumentation, InstrumentedAddr},
+    leaderboard,
+    mentions::{resolve_group_mentions, MentionableGroups},
+    message_edits::{self, MessageEdit},
+    permission::ChannelPermission,
+    quotas,
+    server::{Server, ServerNotification},
+    signing::KeyPair,
+    unfurl::{self, MessagePreviews},
+    validation::normalize_message_content,
+    webhooks::{HubWebhooks, WebhookMessage},
+    ChannelId, HubId, MessageId, ID,
+};
+
+/// Checks that a user can send a message, `sender_id` is the ID of the user whose key signed the message.
+///
+/// # Errors
+///
+/// This function returns an error for any of the following reasons:
+///
+/// * The message was prepared for another user or for another hub.
+/// * The message is marked as sent by a bot or webhook or has a name or avatar override, see [`Message::set_author`].
+/// * The message is bigger than [`crate::MESSAGE_MAX_SIZE`].
+/// * The content was not normalized by [`normalize_message_content`].
+/// * The user is not in the hub or is muted in it.
+/// * The channel does not exist.
+/// * The user does not have permission to write in the channel.
+/// * The message has the [`Message::TTS`] flag and the user does not have the [`ChannelPermission::SendTts`] permission in the channel.
+pub fn check_message(hub: &Hub, sender_id: &str, message: &Message) -> Result {
+    if message.sender != sender_id || message.hub_id != hub.id {
+        return Err(Error::InvalidMessage);
+    }
+    // Users sign their own messages, only the bot and webhook send paths can set who a message is shown as sent by.
+    if message.author_type != AuthorType::User || message.has_overrides() {
+        return Err(Error::InvalidMessage);
+    }
+    check_content_and_sender(hub, message)
+}
+
+/// Checks the content of a message and that its sender can send it, the checks of [`check_message`] that apply to every author type.
+///
+/// # Errors
+///
+/// This function returns an error for any of the reasons outlined by [`check_message`] except the first two.
+fn check_content_and_sender(hub: &Hub, message: &Message) -> Result {
+    let sender_id = message.sender.as_str();
+    if message.content.len() > crate::MESSAGE_MAX_SIZE {
+        return Err(Error::TooBig);
+    }
+    if normalize_message_content(&message.content)? != message.content {
+        return Err(Error::InvalidText);
+    }
+    let member = hub.get_member(sender_id)?;
+    if hub.mutes.contains(sender_id) {
+        return Err(Error::Muted);
+    }
+    if !hub.channels.contains_key(&message.channel_id) {
+        return Err(Error::ChannelNotFound);
+    }
+    check_permission!(member, message.channel_id, ChannelPermission::Write, hub);
+    if message.has_flags(Message::TTS) {
+        check_permission!(member, message.channel_id, ChannelPermission::SendTts, hub);
+    }
+    Ok(())
+}
+
+/// Checks that the group mentions of a message are the ones [`resolve_group_mentions`] finds in its content.
+///
+/// # Errors
+///
+/// This function returns [`Error::InvalidMessage`] if the mentions do not match, the hub's groups or the sender's permissions may have changed since the message was prepared.
+pub fn check_group_mentions(
+    hub: &Hub,
+    mentionable: &MentionableGroups,
+    message: &Message,
+) -> Result {
+    let expected = resolve_group_mentions(
+        hub,
+        mentionable,
+        &message.sender,
+        message.channel_id,
+        &message.content,
+    );
+    if message.group_mentions != expected {
+        return Err(Error::InvalidMessage);
+    }
+    Ok(())
+}
+
+/// Loads a message that a user wants to forward, checking that the user can read it.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The user can not read the message for any of the reasons outlined by [`Hub::get_readable_message`].
+/// * The message could not be read.
+async fn load_forward_source(
+    sender_id: &str,
+    hub_id: HubId,
+    channel_id: ChannelId,
+    message_id: MessageId,
+) -> Result<Message> {
+    Hub::load(hub_id)
+        .await?
+        .get_readable_message(sender_id, channel_id, message_id)
+        .await
+        .and_then(Message::try_from)
+}
+
+/// Checks that a forwarded message is still a copy of a message its sender can read, messages that are not forwards always pass.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The sender can no longer read the original message for any of the reasons outlined by [`load_forward_source`].
+/// * The content or original sender does not match the original message.
+pub async fn check_forward(message: &Message) -> Result {
+    if let Some(from) = &message.forwarded_from {
+        let source = load_forward_source(
+            &message.sender,
+            from.hub_id,
+            from.channel_id,
+            from.message_id,
+        )
+        .await?;
+        if source.content != message.content || source.sender != from.original_sender {
+            return Err(Error::InvalidMessage);
+        }
+    }
+    Ok(())
+}
+
+/// Creates a message for the sender to sign, used by every API that prepares messages (see the `send_message_init` routes).
+/// The content is normalized with [`normalize_message_content`] first, so the content that is signed is the content that is stored and shown.
+/// `flags` are the [`Message::flags`] the sender chose, unknown bits are kept.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The content is empty after being normalized.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The hub's mentionable groups could not be loaded for any of the reasons outlined by [`MentionableGroups::load`].
+/// * The user can not send the message for any of the reasons outlined by [`check_message`].
+/// * The hub's content policy could not be loaded or the message does not follow it, see [`ContentPolicy::check`].
+pub async fn prepare(
+    sender_id: String,
+    hub_id: HubId,
+    channel_id: ChannelId,
+    content: String,
+    flags: u32,
+) -> Result<Message> {
+    let content = normalize_message_content(&content)?;
+    let hub = Hub::load(hub_id).await?;
+    let mentionable = MentionableGroups::load(hub_id).await?;
+    let mut message = Message::new(sender_id, content, hub_id, channel_id);
+    message.flags = flags;
+    message.group_mentions = resolve_group_mentions(
+        &hub,
+        &mentionable,
+        &message.sender,
+        channel_id,
+        &message.content,
+    );
+    check_message(&hub, &message.sender, &message)?;
+    ContentPolicy::load(hub_id).await?.check(&message.content)?;
+    Ok(message)
+}
+
+/// Creates a copy of a message in another channel for the sender to sign, used by every API that forwards messages (see the `forward_message_init` routes).
+/// The copy is sent like any other message (see [`send`]) and is marked with the IDs of the original message, the original channel does not need to be visible to the readers of the copy.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The user can not read the original message for any of the reasons outlined by [`load_forward_source`].
+/// * The target hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The target hub's mentionable groups could not be loaded for any of the reasons outlined by [`MentionableGroups::load`].
+/// * The user can not send the copy for any of the reasons outlined by [`check_message`].
+/// * The target hub's content policy could not be loaded or the copy does not follow it, see [`ContentPolicy::check`].
+pub async fn prepare_forward(
+    sender_id: String,
+    hub_id: HubId,
+    channel_id: ChannelId,
+    message_id: MessageId,
+    target_hub_id: HubId,
+    target_channel_id: ChannelId,
+) -> Result<Message> {
+    let source = load_forward_source(&sender_id, hub_id, channel_id, message_id).await?;
+    let hub = Hub::load(target_hub_id).await?;
+    let mut message = Message::new(sender_id, source.content, target_hub_id, target_channel_id);
+    message.forwarded_from = Some(ForwardedFrom {
+        hub_id,
+        channel_id,
+        message_id,
+        original_sender: source.sender,
+    });
+    message.group_mentions = resolve_group_mentions(
+        &hub,
+        &MentionableGroups::load(target_hub_id).await?,
+        &message.sender,
+        target_channel_id,
+        &message.content,
+    );
+    check_message(&hub, &message.sender, &message)?;
+    ContentPolicy::load(target_hub_id)
+        .await?
+        .check(&message.content)?;
+    Ok(message)
+}
+
+/// Sends a message signed by both the server (see the `send_message_init` routes) and the sender, used by every API that can send messages.
+/// The message is checked, counted against the sender's quota and stored, then the [`Server`] indexes it and sends it to the clients subscribed to its channel.
+/// Previews of the pages it links to are created in the background afterwards, see [`unfurl::spawn`]. The sender's draft in the channel is removed, see [`drafts::clear`].
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * Too many messages are waiting to be indexed, [`Error::Overloaded`], see [`crate::MAX_INDEX_QUEUE_DEPTH`].
+/// * Either of the signatures is missing or invalid.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The user can not send the message for any of the reasons outlined by [`check_message`].
+/// * The hub's mentionable groups could not be loaded or the message's group mentions are wrong, see [`check_group_mentions`].
+/// * The hub's content policy could not be loaded or the message does not follow it, see [`ContentPolicy::check`].
+/// * The message is a forward that is no longer valid, see [`check_forward`].
+/// * The user has sent too much today, see [`quotas::charge_message`].
+/// * The message could not be delivered for any of the reasons outlined by [`deliver`].
+pub async fn send(
+    signed_message: String,
+    sender_key: &SignedPublicKey,
+    server_key: &SignedPublicKey,
+    server: &InstrumentedAddr<Server>,
+    instrumentation: &Instrumentation,
+) -> Result<Message> {
+    // Indexing happens in the background, refuse new messages only once the index queue is far behind.
+    if instrumentation.message_server.mailbox_depth() >= crate::MAX_INDEX_QUEUE_DEPTH {
+        return Err(Error::Overloaded);
+    }
+    let message = Message::from_double_signed_verify(&signed_message, server_key, sender_key)?;
+    let sender_id = crate::identity_links::user_id(sender_key)?;
+    let hub = Hub::load(message.hub_id).await?;
+    check_message(&hub, &sender_id, &message)?;
+    check_group_mentions(
+        &hub,
+        &MentionableGroups::load(message.hub_id).await?,
+        &message,
+    )?;
+    ContentPolicy::load(message.hub_id)
+        .await?
+        .check(&message.content)?;
+    check_forward(&message).await?;
+    quotas::charge_message(&sender_id, message.content.len()).await?;
+    deliver(&message, signed_message, server).await?;
+    hub_activity::record_message(message.hub_id, &sender_id);
+    leaderboard::record_message(message.hub_id, &sender_id, message.created);
+    // The message is sent, a draft that could not be removed is only logged.
+    if let Err(err) = drafts::clear(&sender_id, message.hub_id, message.channel_id).await {
+        warn!(
+            "Unable to remove the draft of user {} in channel {}: {}",
+            sender_id,
+            message.channel_id,
+            err.chain()
+        );
+    }
+    Ok(message)
+}
+
+/// Stores a checked and signed message, then the [`Server`] indexes it and sends it to the clients subscribed to its channel and previews of the pages it links to are created in the background, see [`unfurl::spawn`].
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The message could not be stored for any of the reasons outlined by [`Channel::write_message`].
+/// * The [`Server`] could not be notified of the message.
+async fn deliver(
+    message: &Message,
+    signed_message: String,
+    server: &InstrumentedAddr<Server>,
+) -> Result {
+    Channel::write_message(
+        message.hub_id,
+        message.channel_id,
+        SignedMessage::new(message.id, message.created, signed_message.clone()),
+    )
+    .await?;
+    server
+        .call(ServerNotification::NewMessage(
+            message.hub_id,
+            message.channel_id,
+            message.id,
+            signed_message,
+            message.clone(),
+        ))
+        .await
+        .map_err(|_| Error::InternalMessageFailed)?;
+    unfurl::spawn(message);
+    Ok(())
+}
+
+/// Sends a message with a webhook, used by every API that lets services post with a webhook's token (see the `webhook_send` route).
+/// The message is shown as sent by a [`AuthorType::Webhook`] author with the name and avatar of the webhook, unless the message overrides them (see [`Message::set_author`]).
+/// It is sent in the name of the user that created the webhook, who has to still be able to write in the channel, and counts against their quota. The server signs it as both the server and the sender.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * Too many messages are waiting to be indexed, [`Error::Overloaded`], see [`crate::MAX_INDEX_QUEUE_DEPTH`].
+/// * The webhooks could not be loaded for any of the reasons outlined by [`HubWebhooks::load`].
+/// * The webhook does not exist or the token is wrong, see [`HubWebhooks::authenticate`].
+/// * The content is empty after being normalized.
+/// * The name or avatar override is not valid, see [`Message::set_author`].
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The hub's mentionable groups could not be loaded for any of the reasons outlined by [`MentionableGroups::load`].
+/// * The webhook's creator can not send the message for any of the reasons outlined by [`check_message`] that are not about its author.
+/// * The hub's content policy could not be loaded or the message does not follow it, see [`ContentPolicy::check`].
+/// * The webhook's creator has sent too much today, see [`quotas::charge_message`].
+/// * The message could not be signed.
+/// * The message could not be delivered for any of the reasons outlined by [`deliver`].
+pub async fn send_webhook(
+    hub_id: HubId,
+    webhook_id: ID,
+    token: &str,
+    webhook_message: WebhookMessage,
+    server_keys: &KeyPair,
+    server: &InstrumentedAddr<Server>,
+    instrumentation: &Instrumentation,
+) -> Result<Message> {
+    if instrumentation.message_server.mailbox_depth() >= crate::MAX_INDEX_QUEUE_DEPTH {
+        return Err(Error::Overloaded);
+    }
+    let webhook = HubWebhooks::load(hub_id)
+        .await?
+        .authenticate(webhook_id, token)?
+        .clone();
+    let content = normalize_message_content(&webhook_message.content)?;
+    let mut message = Message::new(
+        webhook.created_by.clone(),
+        content,
+        hub_id,
+        webhook.channel_id,
+    );
+    message.set_author(
+        AuthorType::Webhook,
+        Some(webhook_message.name.as_deref().unwrap_or(&webhook.name)),
+        webhook_message
+            .avatar
+            .as_deref()
+            .or(webhook.avatar.as_deref()),
+    )?;
+    let hub = Hub::load(hub_id).await?;
+    message.group_mentions = resolve_group_mentions(
+        &hub,
+        &MentionableGroups::load(hub_id).await?,
+        &message.sender,
+        message.channel_id,
+        &message.content,
+    );
+    check_content_and_sender(&hub, &message)?;
+    ContentPolicy::load(hub_id).await?.check(&message.content)?;
+    quotas::charge_message(&message.sender, message.content.len()).await?;
+    let server_signed = message
+        .sign(&server_keys.secret_key, String::new)?
+        .to_armored_string(None)?;
+    let signed_message = Message::sign_final(
+        &server_signed,
+        &server_keys.public_key,
+        &server_keys.secret_key,
+        String::new,
+    )?
+    .to_armored_string(None)?;
+    deliver(&message, signed_message, server).await?;
+    Ok(message)
+}
+
+/// Creates a new version of a message for its sender to sign, used by every API that prepares edits (see the `edit_message_init` routes).
+/// The new version keeps the ID and send time of the message, only its content changes.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The content is empty after being normalized.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The user can not read the channel for any of the reasons outlined by [`Hub::get_channel`].
+/// * The message does not exist, was not sent by the user or is a forward, forwards are copies and can not be edited.
+/// * The message was moved from another channel, see [`crate::message_move`].
+/// * The hub's mentionable groups could not be loaded for any of the reasons outlined by [`MentionableGroups::load`].
+/// * The user can not send the new version for any of the reasons outlined by [`check_message`].
+/// * The hub's content policy could not be loaded or the new version does not follow it, see [`ContentPolicy::check`].
+pub async fn prepare_edit(
+    sender_id: String,
+    hub_id: HubId,
+    channel_id: ChannelId,
+    message_id: MessageId,
+    content: String,
+) -> Result<Message> {
+    let content = normalize_message_content(&content)?;
+    let hub = Hub::load(hub_id).await?;
+    let channel = hub.get_channel(&sender_id, channel_id)?;
+    let mut message = channel
+        .get_message(message_id)
+        .await
+        .ok_or(Error::MessageNotFound)
+        .and_then(Message::try_from)?;
+    if message.sender != sender_id {
+        return Err(Error::MessageNotFound);
+    }
+    // Moved messages are signed for the channel they were sent in, see `message_move`.
+    if message.forwarded_from.is_some() || message.channel_id != channel_id {
+        return Err(Error::InvalidMessage);
+    }
+    message.group_mentions = resolve_group_mentions(
+        &hub,
+        &MentionableGroups::load(hub_id).await?,
+        &sender_id,
+        channel_id,
+        &content,
+    );
+    message.content = content;
+    check_message(&hub, &sender_id, &message)?;
+    ContentPolicy::load(hub_id).await?.check(&message.content)?;
+    Ok(message)
+}
+
+/// Edits a message, the new version must be signed by both the server (see the `edit_message_init` routes) and the sender, used by every API that can edit messages.
+/// The new version replaces the stored message and the previous content is added to the message's history (see [`message_edits`]), then the [`Server`] updates its index and sends the new version to the clients subscribed to its channel.
+/// The link previews of the previous content are removed and new ones are created in the background, see [`unfurl::spawn`].
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * Either of the signatures is missing or invalid.
+/// * The hub could not be loaded for any of the reasons outlined by [`Hub::load`].
+/// * The user can not send the new version for any of the reasons outlined by [`check_message`].
+/// * The hub's mentionable groups could not be loaded or the new version's group mentions are wrong, see [`check_group_mentions`].
+/// * The hub's content policy could not be loaded or the new version does not follow it, see [`ContentPolicy::check`].
+/// * The message does not exist, was not sent by the user or was sent at another time than the new version says.
+/// * Either version is a forward, forwards can not be edited.
+/// * The user has sent too much today, see [`quotas::charge_message`].
+/// * The message could not be replaced for any of the reasons outlined by [`Channel::replace_message`].
+/// * The previous content could not be recorded for any of the reasons outlined by [`message_edits::record`].
+/// * The link previews of the previous content could not be removed, see [`MessagePreviews::remove`].
+/// * The [`Server`] could not be notified of the edit.
+pub async fn edit(
+    signed_message: String,
+    sender_key: &SignedPublicKey,
+    server_key: &SignedPublicKey,
+    server: &InstrumentedAddr<Server>,
+) -> Result<Message> {
+    let message = Message::from_double_signed_verify(&signed_message, server_key, sender_key)?;
+    let sender_id = crate::identity_links::user_id(sender_key)?;
+    let hub = Hub::load(message.hub_id).await?;
+    check_message(&hub, &sender_id, &message)?;
+    check_group_mentions(
+        &hub,
+        &MentionableGroups::load(message.hub_id).await?,
+        &message,
+    )?;
+    ContentPolicy::load(message.hub_id)
+        .await?
+        .check(&message.content)?;
+    let channel = Channel::new(String::new(), message.channel_id, message.hub_id);
+    let previous = channel
+        .get_message(message.id)
+        .await
+        .ok_or(Error::MessageNotFound)
+        .and_then(Message::try_from)?;
+    if previous.sender != sender_id {
+        return Err(Error::MessageNotFound);
+    }
+    if previous.created != message.created
+        || previous.forwarded_from.is_some()
+        || message.forwarded_from.is_some()
+    {
+        return Err(Error::InvalidMessage);
+    }
+    quotas::charge_message(&sender_id, message.content.len()).await?;
+    channel
+        .replace_message(SignedMessage::new(
+            message.id,
+            message.created,
+            signed_message.clone(),
+        ))
+        .await?;
+    message_edits::record(
+        message.hub_id,
+        message.channel_id,
+        message.id,
+        MessageEdit {
+            content: previous.content,
+            edited_at: Utc::now(),
+        },
+    )
+    .await?;
+    MessagePreviews::remove(message.hub_id, message.channel_id, message.id).await?;
+    server
+        .call(ServerNotification::MessageEdited(
+            message.hub_id,
+            message.channel_id,
+            message.id,
+            signed_message,
+            message.clone(),
+        ))
+        .await
+        .map_err(|_| Error::InternalMessageFailed)?;
+    unfurl::spawn(&message);
+    Ok(message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::check_message;
+    use crate::{
+        channel::{Channel, Message},
+        error::Error,
+        hub::Hub,
+        ChannelId, HubId,
+    };
+
+    const OWNER: &str = "0123456789ABCDEF0123456789ABCDEF01234567";
+    const OTHER: &str = "89ABCDEF0123456789ABCDEF0123456789ABCDEF";
+
+    #[test]
+    fn checks_sender() {
+        let hub_id = HubId::from_u128(1);
+        let channel_id = ChannelId::from_u128(2);
+        let mut hub = Hub::new("hub".to_string(), hub_id, OWNER.to_string());
+        hub.channels.insert(
+            channel_id,
+            Channel::new("chat".to_string(), channel_id, hub_id),
+        );
+        let message = Message::new(OWNER.to_string(), "Hi.".to_string(), hub_id, channel_id);
+        assert!(check_message(&hub, OWNER, &message).is_ok());
+        // Signed by another user than the one it was prepared for.
+        assert!(matches!(
+            check_message(&hub, OTHER, &message),
+            Err(Error::InvalidMessage)
+        ));
+        let outsider = Message::new(OTHER.to_string(), "Hi.".to_string(), hub_id, channel_id);
+        assert!(check_message(&hub, OTHER, &outsider).is_err());
+        let elsewhere = Message::new(
+            OWNER.to_string(),
+            "Hi.".to_string(),
+            hub_id,
+            ChannelId::from_u128(3),
+        );
+        assert!(matches!(
+            check_message(&hub, OWNER, &elsewhere),
+            Err(Error::ChannelNotFound)
+        ));
+        let big = Message::new(
+            OWNER.to_string(),
+            "a".repeat(crate::MESSAGE_MAX_SIZE + 1),
+            hub_id,
+            channel_id,
+        );
+        assert!(matches!(
+            check_message(&hub, OWNER, &big),
+            Err(Error::TooBig)
+        ));
+        let unnormalized = Message::new(
+            OWNER.to_string(),
+            "e\u{301}\u{202E}".to_string(),
+            hub_id,
+            channel_id,
+        );
+        assert!(matches!(
+            check_message(&hub, OWNER, &unnormalized),
+            Err(Error::InvalidText)
+        ));
+        hub.mutes.insert(OWNER.to_string());
+        assert!(matches!(
+            check_message(&hub, OWNER, &message),
+            Err(Error::Muted)
+        ));
+    }
+}
@@ -0,0 +1,256 @@
+use std::{collections::HashMap, convert::TryFrom, sync::Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::{
+    channel::{Channel, Message},
+    error::Error,
+    leaderboard,
+    message_edits::MessageHistory,
+    server::{self, ServerNotification},
+    unfurl::MessagePreviews,
+    ChannelId, HubId, MessageId, Result, ID,
+};
+
+/// Maximum number of message IDs in each [`crate::websocket::ServerMessage::MessagesDeleted`] sent while purging.
+pub const PURGE_BATCH_SIZE: usize = 100;
+
+/// Purges started by this process. Jobs are only kept in memory, a purge interrupted by a restart has to be started again.
+static PURGES: Mutex<Option<HashMap<ID, PurgeStatus>>> = Mutex::new(None);
+
+/// State of a purge.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PurgeState {
+    /// Messages are being removed.
+    Running,
+    /// Every matching message was removed.
+    Finished,
+    /// The purge stopped early, the messages counted in [`PurgeStatus::removed`] were still removed.
+    Failed,
+}
+
+/// Which of a user's messages a purge removes.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct PurgeFilter {
+    /// Only remove messages from this channel, all channels of the hub if `None`.
+    pub channel_id: Option<ChannelId>,
+    /// Only remove messages sent at or after this time.
+    pub from: Option<DateTime<Utc>>,
+    /// Only remove messages sent at or before this time.
+    pub to: Option<DateTime<Utc>>,
+}
+
+/// Status of a purge, served by `/v3/purge_status/{job_id}`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PurgeStatus {
+    pub id: ID,
+    pub hub_id: HubId,
+    /// ID of the user whose messages are removed.
+    pub user_id: String,
+    /// ID of the user that started the purge, the only user that can see its status.
+    pub requested_by: String,
+    pub filter: PurgeFilter,
+    pub state: PurgeState,
+    /// Number of messages removed so far.
+    pub removed: usize,
+    /// Time the purge was started.
+    pub started: DateTime<Utc>,
+    /// Time the purge finished or failed.
+    pub finished: Option<DateTime<Utc>>,
+}
+
+/// Changes the status of a purge.
+fn update<F: FnOnce(&mut PurgeStatus)>(id: ID, change: F) {
+    let mut purges = PURGES.lock().unwrap_or_else(|err| err.into_inner());
+    if let Some(status) = purges.get_or_insert_with(HashMap::new).get_mut(&id) {
+        change(status);
+    }
+}
+
+/// Gets the status of a purge, only the user that started it can see it.
+///
+/// # Errors
+///
+/// This function returns [`Error::PurgeNotFound`] if there is no purge with that ID or `user_id` did not start it.
+pub fn status(user_id: &str, id: ID) -> Result<PurgeStatus> {
+    PURGES
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .as_ref()
+        .and_then(|purges| purges.get(&id))
+        .filter(|status| status.requested_by == user_id)
+        .cloned()
+        .ok_or(Error::PurgeNotFound)
+}
+
+/// Removes the messages of one channel that match a purge, then removes their edit history and link previews, uncounts them from the hub's leaderboard and tells the [`crate::server::Server`] about them in batches.
+async fn purge_channel(
+    hub_id: HubId,
+    channel_id: ChannelId,
+    user_id: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<MessageId>> {
+    let mut sent = HashMap::new();
+    let removed = Channel::new(String::new(), channel_id, hub_id)
+        .remove_messages_between(from, to, |signed| {
+            let matches = signed.created >= from
+                && signed.created <= to
+                && Message::try_from(signed).is_ok_and(|message| message.sender == user_id);
+            if matches {
+                sent.insert(signed.id, signed.created);
+            }
+            matches
+        })
+        .await?;
+    for message_id in removed.iter() {
+        MessageHistory::remove(hub_id, channel_id, *message_id).await?;
+        MessagePreviews::remove(hub_id, channel_id, *message_id).await?;
+        if let Some(created) = sent.get(message_id) {
+            leaderboard::record_removed(hub_id, user_id, *created);
+        }
+    }
+    for batch in removed.chunks(PURGE_BATCH_SIZE) {
+        server::publish(ServerNotification::MessagesDeleted(
+            hub_id,
+            channel_id,
+            batch.to_vec(),
+        ))
+        .await;
+    }
+    Ok(removed)
+}
+
+/// Removes the messages of a purge from each of its channels, recording progress after each channel.
+async fn run(id: ID, channel_ids: Vec<ChannelId>, status: PurgeStatus) {
+    let from = status.filter.from.unwrap_or(DateTime::UNIX_EPOCH);
+    let to = status.filter.to.unwrap_or(status.started);
+    let mut result = Ok(());
+    for channel_id in channel_ids {
+        match purge_channel(status.hub_id, channel_id, &status.user_id, from, to).await {
+            Ok(removed) => update(id, |status| status.removed += removed.len()),
+            Err(err) => {
+                result = Err(err);
+                break;
+            }
+        }
+    }
+    let state = match result {
+        Ok(()) => PurgeState::Finished,
+        Err(err) => {
+            error!(
+                "Purge of the messages of {} in hub {} failed: {}",
+                status.user_id,
+                status.hub_id,
+                err.chain()
+            );
+            PurgeState::Failed
+        }
+    };
+    update(id, |status| {
+        status.state = state;
+        status.finished = Some(Utc::now());
+    });
+    crate::audit!(
+        hub = %status.hub_id,
+        user = %status.user_id,
+        actor = %status.requested_by,
+        purge = %id,
+        ?state,
+        kind = "moderation",
+        "Finished purging messages."
+    );
+}
+
+/// Starts removing a user's messages from the given channels of a hub in the background. Purges that finished more than a day ago are forgotten.
+/// Permissions are not checked here, see [`crate::api::purge_user_messages`].
+pub fn start(
+    hub_id: HubId,
+    user_id: &str,
+    requested_by: &str,
+    filter: PurgeFilter,
+    channel_ids: Vec<ChannelId>,
+) -> PurgeStatus {
+    let now = Utc::now();
+    let status = PurgeStatus {
+        id: crate::new_id(),
+        hub_id,
+        user_id: user_id.to_string(),
+        requested_by: requested_by.to_string(),
+        filter,
+        state: PurgeState::Running,
+        removed: 0,
+        started: now,
+        finished: None,
+    };
+    {
+        let mut purges = PURGES.lock().unwrap_or_else(|err| err.into_inner());
+        let purges = purges.get_or_insert_with(HashMap::new);
+        purges.retain(|_, purge| {
+            purge
+                .finished
+                .is_none_or(|finished| now - finished < Duration::days(1))
+        });
+        purges.insert(status.id, status.clone());
+    }
+    tokio::spawn(run(status.id, channel_ids, status.clone()));
+    status
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+
+    use super::purge_channel;
+    use crate::{
+        channel::{Channel, SignedMessage},
+        ChannelId, HubId, MessageId,
+    };
+
+    #[tokio::test]
+    async fn purge_keeps_other_messages() {
+        let channel = Channel::new(String::new(), ChannelId::random(), HubId::random());
+        channel.create_dir().await.unwrap();
+        let now = Utc::now();
+        // Messages that are not double signed can not be read, so they are never purged.
+        for n in 1..=3 {
+            channel
+                .add_message(SignedMessage::new(
+                    MessageId::from_u128(n),
+                    now - Duration::minutes(5),
+                    String::new(),
+                ))
+                .await
+                .unwrap();
+        }
+        let removed = purge_channel(
+            channel.hub_id,
+            channel.id,
+            "0123456789ABCDEF0123456789ABCDEF01234567",
+            now - Duration::hours(1),
+            now,
+        )
+        .await
+        .unwrap();
+        assert!(removed.is_empty());
+        assert_eq!(channel.get_all_messages().await.len(), 3);
+        let removed = channel
+            .remove_messages_between(now - Duration::hours(1), now, |message| {
+                message.id == MessageId::from_u128(2)
+            })
+            .await
+            .unwrap();
+        assert_eq!(removed, vec![MessageId::from_u128(2)]);
+        let ids: Vec<u128> = channel
+            .get_all_messages()
+            .await
+            .iter()
+            .map(|message| message.id.as_u128())
+            .collect();
+        assert_eq!(ids, vec![1, 3]);
+        let _ = tokio::fs::remove_dir_all(crate::paths::hub_data_dir(channel.hub_id)).await;
+    }
+}
@@ -0,0 +1,180 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::NameRules,
+    error::{Error, IoContext},
+    hub::Hub,
+    validation::{name_rules, validate_name_with, NameKind},
+    HubId, Result,
+};
+
+/// Folder where the nicknames and the nickname policy of each hub are stored.
+pub const NICKNAMES_FOLDER: &str = "data/hubs/nicknames/";
+
+/// Rules for the nicknames members can give themselves in a hub.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct NicknamePolicy {
+    /// Whether members can set their nickname, members with the `ADMINISTRATE` permission always can.
+    pub allow: bool,
+    /// Whether two members can have the same nickname (ignoring case).
+    pub unique: bool,
+    /// Maximum length of a nickname in characters.
+    pub max_length: u8,
+}
+
+impl Default for NicknamePolicy {
+    fn default() -> Self {
+        Self {
+            allow: true,
+            unique: false,
+            max_length: 32,
+        }
+    }
+}
+
+/// Nicknames of the members of a hub and the rules for them, stored separately from the hub itself.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct HubNicknames {
+    pub policy: NicknamePolicy,
+    /// Nickname of each member that has one.
+    pub nicknames: HashMap<String, String>,
+}
+
+impl HubNicknames {
+    /// Gets the path of the file that a hub's nicknames are stored in.
+    pub fn get_path(hub_id: HubId) -> PathBuf {
+        crate::paths::nicknames_file(hub_id)
+    }
+
+    /// Loads the nicknames of a hub, a hub without the file has none and the default policy.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The file exists but could not be read.
+    /// * The file's contents could not be deserialized.
+    pub async fn load(hub_id: HubId) -> Result<Self> {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(bincode::deserialize(&bytes)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_path(path),
+        }
+    }
+
+    /// Saves the nicknames of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The nicknames folder does not exist and could not be created.
+    /// * The data could not be written to the disk.
+    pub async fn save(&self, hub_id: HubId) -> Result {
+        tokio::fs::create_dir_all(NICKNAMES_FOLDER)
+            .await
+            .with_path(NICKNAMES_FOLDER)?;
+        let path = Self::get_path(hub_id);
+        tokio::fs::write(&path, bincode::serialize(self)?)
+            .await
+            .with_path(path)
+    }
+
+    /// Removes the nicknames of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the file exists but could not be removed.
+    pub async fn remove(hub_id: HubId) -> Result {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::remove_file(&path).await {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result.with_path(path),
+        }
+    }
+
+    /// Sets the nickname of a member, an empty nickname removes it. Returns the previous and the new (normalized) nickname.
+    /// Nicknames follow the same character rules as hub names, with the length limit of the policy.
+    ///
+    /// # Errors
+    ///
+    /// This function may return an error for any of the following reasons:
+    ///
+    /// * The nickname is not valid for any of the reasons outlined in [`validate_name_with`].
+    /// * The policy requires unique nicknames and another member of `hub` already has it, [`Error::NameTaken`].
+    pub fn set(
+        &mut self,
+        hub: &Hub,
+        user_id: &str,
+        nickname: &str,
+    ) -> Result<(Option<String>, Option<String>)> {
+        if nickname.is_empty() {
+            return Ok((self.nicknames.remove(user_id), None));
+        }
+        let rules = NameRules {
+            min_length: 1,
+            max_length: self.policy.max_length.into(),
+            extended_characters: name_rules(NameKind::Hub).extended_characters,
+        };
+        let nickname = validate_name_with(&rules, nickname)?;
+        if self.policy.unique {
+            let lowercase = nickname.to_lowercase();
+            let taken = self.nicknames.iter().any(|(member, other)| {
+                member != user_id
+                    && hub.members.contains_key(member)
+                    && other.to_lowercase() == lowercase
+            });
+            if taken {
+                return Err(Error::NameTaken);
+            }
+        }
+        let previous = self.nicknames.insert(user_id.to_string(), nickname.clone());
+        Ok((previous, Some(nickname)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{HubNicknames, NicknamePolicy};
+    use crate::{error::Error, hub::Hub, HubId};
+
+    const OWNER: &str = "0123456789ABCDEF0123456789ABCDEF01234567";
+    const MEMBER: &str = "89ABCDEF0123456789ABCDEF0123456789ABCDEF";
+
+    #[test]
+    fn unique_nicknames() {
+        let mut hub = Hub::new("hub".to_string(), HubId::from_u128(1), OWNER.to_string());
+        hub.user_join(MEMBER.to_string()).unwrap();
+        let mut nicknames = HubNicknames::default();
+        assert_eq!(
+            nicknames.set(&hub, OWNER, "  Sam  ").unwrap(),
+            (None, Some("Sam".to_string()))
+        );
+        // Duplicates are fine until the policy asks for unique nicknames.
+        assert!(nicknames.set(&hub, MEMBER, "sam").is_ok());
+        nicknames.policy = NicknamePolicy {
+            unique: true,
+            ..NicknamePolicy::default()
+        };
+        assert!(matches!(
+            nicknames.set(&hub, MEMBER, "SAM"),
+            Err(Error::NameTaken)
+        ));
+        // Nicknames of users that left the hub do not count.
+        hub.members.remove(OWNER);
+        assert!(nicknames.set(&hub, MEMBER, "Sam").is_ok());
+        assert_eq!(
+            nicknames.set(&hub, MEMBER, "").unwrap(),
+            (Some("Sam".to_string()), None)
+        );
+        assert!(matches!(
+            nicknames.set(&hub, MEMBER, &"a".repeat(33)),
+            Err(Error::InvalidName(_))
+        ));
+    }
+}
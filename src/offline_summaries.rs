@@ -0,0 +1,370 @@
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    path::PathBuf,
+    sync::{Mutex as SyncMutex, MutexGuard as SyncMutexGuard},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    error::{Error, IoContext},
+    hub::Hub,
+    locks::KeyedLocks,
+    ChannelId, HubId, MessageId, Result,
+};
+
+/// Folder where the offline summary of each user is stored.
+pub const OFFLINE_SUMMARIES_FOLDER: &str = "data/users/offline_summaries/";
+
+/// Maximum number of channels an offline summary keeps mentions for, the channel with the oldest mention is dropped to make room for a new one.
+pub const MAX_SUMMARY_CHANNELS: usize = 100;
+
+/// Mentions in a channel are counted up to this number.
+pub const MAX_MENTION_COUNT: u32 = 999;
+
+/// Number of users whose mentions can wait in memory to be written to their summary files, see [`record_mention`].
+pub const MAX_PENDING_USERS: usize = 100_000;
+
+/// Mentions that have not been written to the summary files yet.
+static PENDING: SyncMutex<Option<Pending>> = SyncMutex::new(None);
+
+/// Makes the summary of a user change one at a time.
+static SUMMARY_LOCKS: KeyedLocks<String> = KeyedLocks::new();
+
+/// Mentions of a user in one channel while they had no WebSocket connection open.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MissedMentions {
+    pub hub_id: HubId,
+    pub channel_id: ChannelId,
+    /// Number of messages that mentioned the user, at most [`MAX_MENTION_COUNT`].
+    pub count: u32,
+    pub last_message_id: MessageId,
+    /// When the last of the messages was sent.
+    pub last_mentioned: DateTime<Utc>,
+}
+
+/// What a user missed while they had no WebSocket connection open, sent to the first connection they open afterwards as a [`crate::websocket::ServerMessage::OfflineSummary`] and then cleared.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct OfflineSummary {
+    /// Channels the user was mentioned in, at most [`MAX_SUMMARY_CHANNELS`].
+    #[serde(default)]
+    pub mentions: Vec<MissedMentions>,
+}
+
+impl OfflineSummary {
+    /// Gets the path of the file that a user's offline summary is stored in.
+    pub fn get_path(user_id: &str) -> PathBuf {
+        crate::paths::offline_summary_file(user_id)
+    }
+
+    /// Loads the offline summary of a user, a user without the file has an empty one.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The user ID is not a hex encoded fingerprint.
+    /// * The file exists but could not be read.
+    /// * The file is encrypted and could not be decrypted, see [`crate::encryption::open`].
+    /// * The file's contents could not be deserialized.
+    pub async fn load(user_id: &str) -> Result<Self> {
+        if hex::decode(user_id).is_err() {
+            return Err(Error::InvalidFingerprint);
+        }
+        let path = Self::get_path(user_id);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(bincode::deserialize(&crate::encryption::open(&bytes)?)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_path(path),
+        }
+    }
+
+    /// Saves the offline summary of a user, the file is removed if the summary is empty.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The offline summaries folder does not exist and could not be created.
+    /// * The data could not be encrypted, see [`crate::encryption::seal`].
+    /// * The data could not be written to the disk or the empty file could not be removed.
+    pub async fn save(&self, user_id: &str) -> Result {
+        let path = Self::get_path(user_id);
+        if self.is_empty() {
+            return match tokio::fs::remove_file(&path).await {
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                result => result.with_path(path),
+            };
+        }
+        tokio::fs::create_dir_all(OFFLINE_SUMMARIES_FOLDER)
+            .await
+            .with_path(OFFLINE_SUMMARIES_FOLDER)?;
+        tokio::fs::write(&path, crate::encryption::seal(bincode::serialize(self)?)?)
+            .await
+            .with_path(path)
+    }
+
+    /// Returns true if the user missed nothing.
+    pub fn is_empty(&self) -> bool {
+        self.mentions.is_empty()
+    }
+
+    /// Counts a message that mentioned the user, the count of its channel stops at [`MAX_MENTION_COUNT`].
+    /// If the summary already has [`MAX_SUMMARY_CHANNELS`] channels the one with the oldest mention is dropped.
+    pub fn add_mention(
+        &mut self,
+        hub_id: HubId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        now: DateTime<Utc>,
+    ) {
+        self.add(MissedMentions {
+            hub_id,
+            channel_id,
+            count: 1,
+            last_message_id: message_id,
+            last_mentioned: now,
+        });
+    }
+
+    /// Adds the mentions of a newer summary to this one, see [`OfflineSummary::add_mention`].
+    pub fn merge(&mut self, newer: OfflineSummary) {
+        for mentions in newer.mentions {
+            self.add(mentions);
+        }
+    }
+
+    fn add(&mut self, new: MissedMentions) {
+        if let Some(mentions) = self
+            .mentions
+            .iter_mut()
+            .find(|mentions| mentions.hub_id == new.hub_id && mentions.channel_id == new.channel_id)
+        {
+            mentions.count = mentions
+                .count
+                .saturating_add(new.count)
+                .min(MAX_MENTION_COUNT);
+            mentions.last_message_id = new.last_message_id;
+            mentions.last_mentioned = new.last_mentioned;
+            return;
+        }
+        if self.mentions.len() >= MAX_SUMMARY_CHANNELS {
+            if let Some(oldest) = self
+                .mentions
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, mentions)| mentions.last_mentioned)
+                .map(|(index, _)| index)
+            {
+                self.mentions.remove(oldest);
+            }
+        }
+        self.mentions.push(new);
+    }
+}
+
+/// Mentions that have not been written to the summary files yet, see [`record_mention`].
+#[derive(Default)]
+struct Pending {
+    summaries: HashMap<String, OfflineSummary>,
+    /// Whether a task started by [`record_mention`] is writing the summaries.
+    flushing: bool,
+}
+
+/// Returns the pending mentions, see [`Pending`].
+fn pending() -> SyncMutexGuard<'static, Option<Pending>> {
+    PENDING.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Lets the next call to [`record_mention`] start a new task when the one writing the summaries stops, also if it is dropped with the runtime it was started on.
+struct FlushingGuard;
+
+impl Drop for FlushingGuard {
+    fn drop(&mut self) {
+        if let Some(pending) = pending().as_mut() {
+            pending.flushing = false;
+        }
+    }
+}
+
+/// Adds the pending mentions of a user to their summary file, the lock of the user has to be held.
+async fn write_pending(user_id: &str) -> Result {
+    let pending = pending()
+        .as_mut()
+        .and_then(|pending| pending.summaries.remove(user_id));
+    if let Some(pending) = pending {
+        let mut summary = OfflineSummary::load(user_id).await?;
+        summary.merge(pending);
+        summary.save(user_id).await?;
+    }
+    Ok(())
+}
+
+/// Writes the pending mentions one user at a time until there are none left.
+async fn flush() {
+    let _flushing = FlushingGuard;
+    loop {
+        let user_ids: Vec<String> = match pending().as_ref() {
+            Some(pending) if !pending.summaries.is_empty() => {
+                pending.summaries.keys().cloned().collect()
+            }
+            _ => return,
+        };
+        for user_id in user_ids {
+            let _guard = SUMMARY_LOCKS.lock(user_id.clone()).await;
+            if let Err(err) = write_pending(&user_id).await {
+                warn!(
+                    "Unable to count mentions in the offline summary of {}: {}",
+                    user_id,
+                    err.chain()
+                );
+            }
+        }
+    }
+}
+
+/// Counts a message in the offline summaries of the users it mentioned that had no WebSocket connection open, see [`OfflineSummary::add_mention`].
+/// The mention is kept in memory until a background task writes it, [`take`] includes the mentions that are not written yet, so a connection opened after this is called always gets it.
+/// Mentions of users that are not among the [`MAX_PENDING_USERS`] users with unwritten mentions are dropped while the summaries can not be written fast enough.
+pub fn record_mention(
+    user_ids: HashSet<String>,
+    hub_id: HubId,
+    channel_id: ChannelId,
+    message_id: MessageId,
+) {
+    let now = Utc::now();
+    let mut pending = pending();
+    let pending = pending.get_or_insert_with(Pending::default);
+    let mut dropped = 0;
+    for user_id in user_ids {
+        let count = pending.summaries.len();
+        match pending.summaries.entry(user_id) {
+            Entry::Occupied(mut entry) => entry
+                .get_mut()
+                .add_mention(hub_id, channel_id, message_id, now),
+            Entry::Vacant(_) if count >= MAX_PENDING_USERS => dropped += 1,
+            Entry::Vacant(entry) => entry
+                .insert(OfflineSummary::default())
+                .add_mention(hub_id, channel_id, message_id, now),
+        }
+    }
+    if dropped > 0 {
+        warn!(
+            "Dropped the mentions of {} offline users, too many mentions are waiting to be written",
+            dropped
+        );
+    }
+    if !pending.flushing && !pending.summaries.is_empty() {
+        pending.flushing = true;
+        tokio::spawn(flush());
+    }
+}
+
+/// Gets the offline summary of a user and clears it, so that only one of the connections they open gets it.
+/// Mentions in channels the user can no longer read, or that no longer exist, are left out.
+///
+/// # Errors
+///
+/// This function may return an error for any of the reasons outlined in [`OfflineSummary::load`] and [`OfflineSummary::save`].
+pub async fn take(user_id: &str) -> Result<OfflineSummary> {
+    if hex::decode(user_id).is_err() {
+        return Err(Error::InvalidFingerprint);
+    }
+    let mut summary = {
+        let _guard = SUMMARY_LOCKS.lock(user_id.to_string()).await;
+        let mut summary = OfflineSummary::load(user_id).await?;
+        if !summary.is_empty() {
+            OfflineSummary::default().save(user_id).await?;
+        }
+        let pending = pending()
+            .as_mut()
+            .and_then(|pending| pending.summaries.remove(user_id));
+        if let Some(pending) = pending {
+            summary.merge(pending);
+        }
+        summary
+    };
+    let mut hubs: HashMap<HubId, Option<Hub>> = HashMap::new();
+    for mentions in summary.mentions.iter() {
+        if let Entry::Vacant(entry) = hubs.entry(mentions.hub_id) {
+            entry.insert(Hub::load(mentions.hub_id).await.ok());
+        }
+    }
+    summary.mentions.retain(|mentions| {
+        hubs[&mentions.hub_id].as_ref().is_some_and(|hub| {
+            hub.check_can_read_channel(user_id, mentions.channel_id)
+                .is_ok()
+        })
+    });
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+
+    use super::{OfflineSummary, MAX_MENTION_COUNT, MAX_SUMMARY_CHANNELS};
+    use crate::{hub::Hub, ChannelId, HubId, MessageId};
+
+    #[test]
+    fn mentions_are_capped() {
+        let hub_id = HubId::from_u128(1);
+        let start = Utc::now();
+        let mut summary = OfflineSummary::default();
+        for n in 0..=MAX_MENTION_COUNT {
+            summary.add_mention(
+                hub_id,
+                ChannelId::from_u128(0),
+                MessageId::from_u128(n.into()),
+                start,
+            );
+        }
+        assert_eq!(summary.mentions.len(), 1);
+        assert_eq!(summary.mentions[0].count, MAX_MENTION_COUNT);
+        assert_eq!(
+            summary.mentions[0].last_message_id,
+            MessageId::from_u128(MAX_MENTION_COUNT.into())
+        );
+
+        // Channel 0 has the oldest mention, it makes room for the last channel.
+        for n in 1..=MAX_SUMMARY_CHANNELS as u128 {
+            summary.add_mention(
+                hub_id,
+                ChannelId::from_u128(n),
+                MessageId::from_u128(n),
+                start + Duration::seconds(n as i64),
+            );
+        }
+        assert_eq!(summary.mentions.len(), MAX_SUMMARY_CHANNELS);
+        assert!(summary
+            .mentions
+            .iter()
+            .all(|mentions| mentions.channel_id != ChannelId::from_u128(0)));
+    }
+
+    #[tokio::test]
+    async fn take_includes_unwritten_mentions() {
+        let user_id = format!("{:040X}", rand::random::<u128>());
+        let mut hub = Hub::new("hub".to_string(), HubId::random(), user_id.clone());
+        let channel_id = hub.new_channel(&user_id, "chat".to_string()).await.unwrap();
+        hub.save().await.unwrap();
+        for n in 1..=2 {
+            super::record_mention(
+                vec![user_id.clone()].into_iter().collect(),
+                hub.id,
+                channel_id,
+                MessageId::from_u128(n),
+            );
+        }
+
+        let summary = super::take(&user_id).await.unwrap();
+        assert_eq!(summary.mentions.len(), 1);
+        assert_eq!(summary.mentions[0].count, 2);
+        assert_eq!(summary.mentions[0].last_message_id, MessageId::from_u128(2));
+        assert!(super::take(&user_id).await.unwrap().is_empty());
+    }
+}
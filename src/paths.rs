@@ -0,0 +1,366 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    account_export::ACCOUNT_EXPORTS_FOLDER,
+    change_history::CHANGE_HISTORY_FOLDER,
+    content_policy::CONTENT_POLICY_FOLDER,
+    descriptions::LONG_DESCRIPTIONS_FOLDER,
+    drafts::DRAFTS_FOLDER,
+    group_display::GROUP_DISPLAY_FOLDER,
+    hub::{HUB_DATA_FOLDER, HUB_INFO_FOLDER},
+    hub_activity::HUB_ACTIVITY_FOLDER,
+    hub_changes::HUB_CHANGES_FOLDER,
+    hub_images::{ImageKind, HUB_IMAGES_FOLDER},
+    hub_preview::HUB_PREVIEW_FOLDER,
+    invites::INVITE_JOINS_FOLDER,
+    leaderboard::LEADERBOARD_FOLDER,
+    member_map::MEMBER_CHANGES_FOLDER,
+    membership_log::MEMBERSHIP_LOG_FOLDER,
+    mentions::MENTIONABLE_GROUPS_FOLDER,
+    nicknames::NICKNAMES_FOLDER,
+    offline_summaries::OFFLINE_SUMMARIES_FOLDER,
+    quotas::USER_QUOTAS_FOLDER,
+    signing::USER_PUBLIC_KEY_FOLDER,
+    subscription_profiles::SUBSCRIPTION_PROFILES_FOLDER,
+    webhooks::WEBHOOKS_FOLDER,
+    ChannelId, HubId, MessageId,
+};
+
+/// Formats an ID the way it is used in file and folder names, as lowercase hex without leading zeros.
+pub fn hex_id(id: u128) -> String {
+    format!("{:x}", id)
+}
+
+/// File that a hub's information (see [`crate::hub::Hub::save`]) is stored in.
+pub fn hub_info_file(hub_id: HubId) -> PathBuf {
+    PathBuf::from(HUB_INFO_FOLDER).join(hex_id(hub_id.as_u128()))
+}
+
+/// File or folder named after a hub in one of the folders that hold a kind of data for every hub.
+fn hub_entry(folder: &str, hub_id: HubId) -> PathBuf {
+    PathBuf::from(folder).join(hex_id(hub_id.as_u128()))
+}
+
+/// File holding the long descriptions of a hub and its channels, see [`crate::descriptions`].
+pub fn hub_descriptions_file(hub_id: HubId) -> PathBuf {
+    hub_entry(LONG_DESCRIPTIONS_FOLDER, hub_id)
+}
+
+/// File holding the display settings of a hub's permission groups, see [`crate::group_display`].
+pub fn group_display_file(hub_id: HubId) -> PathBuf {
+    hub_entry(GROUP_DISPLAY_FOLDER, hub_id)
+}
+
+/// File holding the activity statistics of a hub, see [`crate::hub_activity`].
+pub fn hub_activity_file(hub_id: HubId) -> PathBuf {
+    hub_entry(HUB_ACTIVITY_FOLDER, hub_id)
+}
+
+/// File holding the recent changes of a hub, see [`crate::hub_changes`].
+pub fn hub_changes_file(hub_id: HubId) -> PathBuf {
+    hub_entry(HUB_CHANGES_FOLDER, hub_id)
+}
+
+/// Folder of a hub's icon and banner, see [`crate::hub_images`].
+pub fn hub_images_dir(hub_id: HubId) -> PathBuf {
+    hub_entry(HUB_IMAGES_FOLDER, hub_id)
+}
+
+/// File holding the information about a hub's images.
+pub fn hub_images_file(hub_id: HubId) -> PathBuf {
+    hub_images_dir(hub_id).join("images")
+}
+
+/// File holding one of a hub's images.
+pub fn hub_image_file(hub_id: HubId, kind: ImageKind) -> PathBuf {
+    hub_images_dir(hub_id).join(kind.file_name())
+}
+
+/// File holding the preview settings of a hub, see [`crate::hub_preview`].
+pub fn hub_preview_file(hub_id: HubId) -> PathBuf {
+    hub_entry(HUB_PREVIEW_FOLDER, hub_id)
+}
+
+/// File that the membership events of a hub are appended to, see [`crate::membership_log`].
+pub fn membership_log_file(hub_id: HubId) -> PathBuf {
+    hub_entry(MEMBERSHIP_LOG_FOLDER, hub_id)
+}
+
+/// File holding the IDs of a hub's mentionable groups, see [`crate::mentions`].
+pub fn mentionable_groups_file(hub_id: HubId) -> PathBuf {
+    hub_entry(MENTIONABLE_GROUPS_FOLDER, hub_id)
+}
+
+/// File holding the nicknames of a hub's members, see [`crate::nicknames`].
+pub fn nicknames_file(hub_id: HubId) -> PathBuf {
+    hub_entry(NICKNAMES_FOLDER, hub_id)
+}
+
+/// File holding the message counts of a hub's senders, see [`crate::leaderboard`].
+pub fn hub_leaderboard_file(hub_id: HubId) -> PathBuf {
+    hub_entry(LEADERBOARD_FOLDER, hub_id)
+}
+
+/// File holding the recently changed members of a hub, see [`crate::member_map`].
+pub fn member_changes_file(hub_id: HubId) -> PathBuf {
+    hub_entry(MEMBER_CHANGES_FOLDER, hub_id)
+}
+
+/// File holding the name and description changes of a hub, see [`crate::change_history`].
+pub fn change_history_file(hub_id: HubId) -> PathBuf {
+    hub_entry(CHANGE_HISTORY_FOLDER, hub_id)
+}
+
+/// File holding the content policy of a hub, see [`crate::content_policy`].
+pub fn content_policy_file(hub_id: HubId) -> PathBuf {
+    hub_entry(CONTENT_POLICY_FOLDER, hub_id)
+}
+
+/// File holding the joins made with the invites of a hub, see [`crate::invites::InviteJoins`].
+pub fn invite_joins_file(hub_id: HubId) -> PathBuf {
+    hub_entry(INVITE_JOINS_FOLDER, hub_id)
+}
+
+/// File holding the webhooks of a hub, see [`crate::webhooks`].
+pub fn webhooks_file(hub_id: HubId) -> PathBuf {
+    hub_entry(WEBHOOKS_FOLDER, hub_id)
+}
+
+/// File named after a user in one of the folders that hold a kind of data for every user.
+fn user_entry(folder: &str, user_id: &str) -> PathBuf {
+    PathBuf::from(folder).join(user_id)
+}
+
+/// File holding the armored public key of a user, named after its fingerprint.
+pub fn user_public_key_file(user_id: &str) -> PathBuf {
+    user_entry(USER_PUBLIC_KEY_FOLDER, &format!("{}.asc", user_id))
+}
+
+/// File holding the quota usage of a user, see [`crate::quotas`].
+pub fn user_quota_file(user_id: &str) -> PathBuf {
+    user_entry(USER_QUOTAS_FOLDER, user_id)
+}
+
+/// File holding the message drafts of a user, see [`crate::drafts`].
+pub fn drafts_file(user_id: &str) -> PathBuf {
+    user_entry(DRAFTS_FOLDER, user_id)
+}
+
+/// File holding what a user missed while offline, see [`crate::offline_summaries`].
+pub fn offline_summary_file(user_id: &str) -> PathBuf {
+    user_entry(OFFLINE_SUMMARIES_FOLDER, user_id)
+}
+
+/// File holding the subscription profiles of a user, see [`crate::subscription_profiles`].
+pub fn subscription_profiles_file(user_id: &str) -> PathBuf {
+    user_entry(SUBSCRIPTION_PROFILES_FOLDER, user_id)
+}
+
+/// File holding the status of a user's latest account export, see [`crate::account_export`].
+pub fn export_status_file(user_id: &str) -> PathBuf {
+    user_entry(ACCOUNT_EXPORTS_FOLDER, &format!("{}.status", user_id))
+}
+
+/// File holding a user's finished account export.
+pub fn export_file(user_id: &str) -> PathBuf {
+    user_entry(ACCOUNT_EXPORTS_FOLDER, &format!("{}.json", user_id))
+}
+
+/// Folder that holds the folders of all of a hub's channels.
+pub fn hub_data_dir(hub_id: HubId) -> PathBuf {
+    PathBuf::from(HUB_DATA_FOLDER).join(hex_id(hub_id.as_u128()))
+}
+
+/// Folder of a channel, holds its message files (one per day) and the folders and files below.
+pub fn channel_dir(hub_id: HubId, channel_id: ChannelId) -> PathBuf {
+    hub_data_dir(hub_id).join(hex_id(channel_id.as_u128()))
+}
+
+/// File holding the messages sent in a channel on the UTC day of `time`, named after the date (e.g. `2021-04-20UTC`), see [`crate::channel::message_file_day`].
+pub fn message_file(hub_id: HubId, channel_id: ChannelId, time: DateTime<Utc>) -> PathBuf {
+    channel_dir(hub_id, channel_id).join(format!("{}UTC", time.date_naive()))
+}
+
+/// Folder a deleted channel's folder is moved to until its search index is closed and it can be removed, see [`crate::api::delete_channel`].
+pub fn deleted_channel_dir(hub_id: HubId, channel_id: ChannelId) -> PathBuf {
+    hub_data_dir(hub_id).join(format!("{}.deleted", hex_id(channel_id.as_u128())))
+}
+
+/// Folder of the Tantivy search index of a channel.
+pub fn channel_index_dir(hub_id: HubId, channel_id: ChannelId) -> PathBuf {
+    channel_dir(hub_id, channel_id).join("index")
+}
+
+/// File holding the ID of the last message of a channel that was commited to its search index.
+pub fn channel_log_file(hub_id: HubId, channel_id: ChannelId) -> PathBuf {
+    channel_dir(hub_id, channel_id).join("log")
+}
+
+/// Folder of the histories of a channel's edited messages, see [`crate::message_edits`].
+pub fn channel_edits_dir(hub_id: HubId, channel_id: ChannelId) -> PathBuf {
+    channel_dir(hub_id, channel_id).join("edits")
+}
+
+/// File holding the history of an edited message.
+pub fn message_edits_file(hub_id: HubId, channel_id: ChannelId, message_id: MessageId) -> PathBuf {
+    channel_edits_dir(hub_id, channel_id).join(hex_id(message_id.as_u128()))
+}
+
+/// Folder of the link previews of a channel's messages, see [`crate::unfurl`].
+pub fn channel_previews_dir(hub_id: HubId, channel_id: ChannelId) -> PathBuf {
+    channel_dir(hub_id, channel_id).join("previews")
+}
+
+/// File holding the link previews of a message.
+pub fn message_previews_file(
+    hub_id: HubId,
+    channel_id: ChannelId,
+    message_id: MessageId,
+) -> PathBuf {
+    channel_previews_dir(hub_id, channel_id).join(hex_id(message_id.as_u128()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use chrono::{TimeZone, Utc};
+
+    use crate::{hub_images::ImageKind, ChannelId, HubId, MessageId};
+
+    #[test]
+    fn layout_is_stable() {
+        let hub_id = HubId::from_u128(0xab);
+        let channel_id = ChannelId::from_u128(0x0c);
+        let message_id = MessageId::from_u128(0x10);
+        assert_eq!(super::hub_info_file(hub_id), Path::new("data/hubs/info/ab"));
+        assert_eq!(super::hub_data_dir(hub_id), Path::new("data/hubs/data/ab"));
+        assert_eq!(
+            super::channel_dir(hub_id, channel_id),
+            Path::new("data/hubs/data/ab/c")
+        );
+        assert_eq!(
+            super::deleted_channel_dir(hub_id, channel_id),
+            Path::new("data/hubs/data/ab/c.deleted")
+        );
+        assert_eq!(
+            super::channel_index_dir(hub_id, channel_id),
+            Path::new("data/hubs/data/ab/c/index")
+        );
+        assert_eq!(
+            super::channel_log_file(hub_id, channel_id),
+            Path::new("data/hubs/data/ab/c/log")
+        );
+        assert_eq!(
+            super::message_file(
+                hub_id,
+                channel_id,
+                Utc.with_ymd_and_hms(2021, 4, 20, 23, 59, 0).unwrap()
+            ),
+            Path::new("data/hubs/data/ab/c/2021-04-20UTC")
+        );
+        assert_eq!(
+            super::message_edits_file(hub_id, channel_id, message_id),
+            Path::new("data/hubs/data/ab/c/edits/10")
+        );
+        assert_eq!(
+            super::message_previews_file(hub_id, channel_id, message_id),
+            Path::new("data/hubs/data/ab/c/previews/10")
+        );
+        assert_eq!(
+            super::hub_descriptions_file(hub_id),
+            Path::new("data/hubs/descriptions/ab")
+        );
+        assert_eq!(
+            super::group_display_file(hub_id),
+            Path::new("data/hubs/group_display/ab")
+        );
+        assert_eq!(
+            super::hub_activity_file(hub_id),
+            Path::new("data/hubs/activity/ab")
+        );
+        assert_eq!(
+            super::hub_changes_file(hub_id),
+            Path::new("data/hubs/changes/ab")
+        );
+        assert_eq!(
+            super::hub_images_file(hub_id),
+            Path::new("data/hubs/images/ab/images")
+        );
+        assert_eq!(
+            super::hub_image_file(hub_id, ImageKind::Banner),
+            Path::new("data/hubs/images/ab/banner")
+        );
+        assert_eq!(
+            super::hub_preview_file(hub_id),
+            Path::new("data/hubs/preview/ab")
+        );
+        assert_eq!(
+            super::membership_log_file(hub_id),
+            Path::new("data/hubs/members/ab")
+        );
+        assert_eq!(
+            super::mentionable_groups_file(hub_id),
+            Path::new("data/hubs/mentionable/ab")
+        );
+        assert_eq!(
+            super::nicknames_file(hub_id),
+            Path::new("data/hubs/nicknames/ab")
+        );
+        assert_eq!(
+            super::hub_leaderboard_file(hub_id),
+            Path::new("data/hubs/leaderboard/ab")
+        );
+        assert_eq!(
+            super::member_changes_file(hub_id),
+            Path::new("data/hubs/member_changes/ab")
+        );
+        assert_eq!(
+            super::change_history_file(hub_id),
+            Path::new("data/hubs/change_history/ab")
+        );
+        assert_eq!(
+            super::content_policy_file(hub_id),
+            Path::new("data/hubs/content_policy/ab")
+        );
+        assert_eq!(
+            super::invite_joins_file(hub_id),
+            Path::new("data/hubs/invite_joins/ab")
+        );
+        assert_eq!(
+            super::webhooks_file(hub_id),
+            Path::new("data/hubs/webhooks/ab")
+        );
+        let user_id = "0123ABCD";
+        assert_eq!(
+            super::user_public_key_file(user_id),
+            Path::new("data/user_public_keys/0123ABCD.asc")
+        );
+        assert_eq!(
+            super::user_quota_file(user_id),
+            Path::new("data/users/quotas/0123ABCD")
+        );
+        assert_eq!(
+            super::drafts_file(user_id),
+            Path::new("data/users/drafts/0123ABCD")
+        );
+        assert_eq!(
+            super::offline_summary_file(user_id),
+            Path::new("data/users/offline_summaries/0123ABCD")
+        );
+        assert_eq!(
+            super::subscription_profiles_file(user_id),
+            Path::new("data/users/subscription_profiles/0123ABCD")
+        );
+        assert_eq!(
+            super::export_status_file(user_id),
+            Path::new("data/users/exports/0123ABCD.status")
+        );
+        assert_eq!(
+            super::export_file(user_id),
+            Path::new("data/users/exports/0123ABCD.json")
+        );
+    }
+}
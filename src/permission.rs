@@ -1,13 +1,15 @@
-use crate::ID;
+use crate::ChannelId;
+#[cfg(feature = "graphql")]
 use async_graphql::{Enum, SimpleObject};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fmt::Display};
+use std::collections::HashMap;
 
 /// Setting for a permission. If all user has permission set to None and it is set to None in all permission groups they are part of it maps to false.
 pub type PermissionSetting = Option<bool>;
 
 /// Struct that groups a hub permission with a permission setting.
-#[derive(PartialEq, Hash, Eq, Serialize, Deserialize, Clone, Copy, Debug, SimpleObject)]
+#[derive(PartialEq, Hash, Eq, Serialize, Deserialize, Clone, Copy, Debug)]
+#[cfg_attr(feature = "graphql", derive(SimpleObject))]
 pub struct HubPermissionSet {
     /// Permission that this permission set is for.
     pub permission: HubPermission,
@@ -25,18 +27,19 @@ impl From<(HubPermission, PermissionSetting)> for HubPermissionSet {
 }
 
 /// Datastructure that groups a channel permission setting with the channel ID that it is valid in and a permission setting.
-#[derive(PartialEq, Hash, Eq, Serialize, Deserialize, Clone, Copy, Debug, SimpleObject)]
+#[derive(PartialEq, Hash, Eq, Serialize, Deserialize, Clone, Copy, Debug)]
+#[cfg_attr(feature = "graphql", derive(SimpleObject))]
 pub struct ChannelPermissionSet {
     /// Permission that this permission set is for.
     pub permission: ChannelPermission,
     /// Setting for the permission.
     pub setting: Option<bool>,
     /// ID of the channel that this permission setting is for.
-    pub channel: ID,
+    pub channel: ChannelId,
 }
 
-impl From<(ChannelPermission, PermissionSetting, ID)> for ChannelPermissionSet {
-    fn from(tup: (ChannelPermission, PermissionSetting, ID)) -> Self {
+impl From<(ChannelPermission, PermissionSetting, ChannelId)> for ChannelPermissionSet {
+    fn from(tup: (ChannelPermission, PermissionSetting, ChannelId)) -> Self {
         Self {
             permission: tup.0,
             setting: tup.1,
@@ -46,7 +49,9 @@ impl From<(ChannelPermission, PermissionSetting, ID)> for ChannelPermissionSet {
 }
 
 /// Hub-wide permission, can be all of these except for the `All` permission can be overridden by channel permissions.
-#[derive(PartialEq, Hash, Eq, Serialize, Deserialize, Clone, Copy, Debug, Enum)]
+/// Hubs are stored with the position of each variant, so new variants must be added at the end.
+#[derive(PartialEq, Hash, Eq, Serialize, Deserialize, Clone, Copy, Debug)]
+#[cfg_attr(feature = "graphql", derive(Enum))]
 pub enum HubPermission {
     All,
     ReadChannels,
@@ -58,47 +63,71 @@ pub enum HubPermission {
     Kick,
     Ban,
     Unban,
+    MentionGroups,
+    SendTts,
+    /// Hub wide setting of [`ChannelPermission::ReadHistory`].
+    ReadHistory,
+    /// Creating invites to the hub, see [`crate::invites`]. Members that can administrate the hub can always create invites.
+    CreateInvite,
+    /// Listing and revoking the hub's invites, whoever created them. Members that can administrate the hub can always manage invites.
+    ManageInvites,
+    /// Hub wide setting of [`ChannelPermission::ManageWebhooks`].
+    ManageWebhooks,
 }
 
-impl Display for HubPermission {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(match self {
-            HubPermission::All => "ALL",
-            HubPermission::ReadChannels => "READ_CHANNELS",
-            HubPermission::WriteChannels => "WRITE_CHANNELS",
-            HubPermission::Administrate => "ADMINISTRATE",
-            HubPermission::ManageChannels => "MANAGE_CHANNELS",
-            HubPermission::Mute => "MUTE",
-            HubPermission::Unmute => "UNMUTE",
-            HubPermission::Kick => "KICK",
-            HubPermission::Ban => "BAN",
-            HubPermission::Unban => "UNBAN",
-        })
-    }
-}
+crate::wire_strings!(HubPermission {
+    All => "ALL",
+    ReadChannels => "READ_CHANNELS",
+    WriteChannels => "WRITE_CHANNELS",
+    Administrate => "ADMINISTRATE",
+    ManageChannels => "MANAGE_CHANNELS",
+    Mute => "MUTE",
+    Unmute => "UNMUTE",
+    Kick => "KICK",
+    Ban => "BAN",
+    Unban => "UNBAN",
+    MentionGroups => "MENTION_GROUPS",
+    SendTts => "SEND_TTS",
+    ReadHistory => "READ_HISTORY",
+    CreateInvite => "CREATE_INVITE",
+    ManageInvites => "MANAGE_INVITES",
+    ManageWebhooks => "MANAGE_WEBHOOKS",
+});
 
 /// Map of hub permissions to permission settings.
 pub type HubPermissions = HashMap<HubPermission, PermissionSetting>;
 
 /// Permissions that only apply to channels, override hub permissions.
-#[derive(PartialEq, Hash, Eq, Serialize, Deserialize, Clone, Copy, Debug, Enum)]
+/// Hubs are stored with the position of each variant, so new variants must be added at the end.
+#[derive(PartialEq, Hash, Eq, Serialize, Deserialize, Clone, Copy, Debug)]
+#[cfg_attr(feature = "graphql", derive(Enum))]
 pub enum ChannelPermission {
     Write,
     Read,
     Manage,
     All,
+    /// Mentioning permission groups notifies their members, see [`crate::mentions`].
+    MentionGroups,
+    /// Sending messages with the [`crate::channel::Message::TTS`] flag.
+    SendTts,
+    /// Reading the messages sent before the member joined the hub, see [`crate::hub::Hub::history_start`].
+    /// Unlike other permissions it is granted unless it is denied, so channels keep their history readable until it is restricted.
+    ReadHistory,
+    /// Managing the webhooks that post messages in the channel, shown as sent by [`crate::channel::AuthorType::Webhook`] authors.
+    ManageWebhooks,
 }
 
-impl Display for ChannelPermission {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(match self {
-            ChannelPermission::Write => "WRITE",
-            ChannelPermission::Read => "READ",
-            ChannelPermission::Manage => "MANAGE",
-            ChannelPermission::All => "ALL",
-        })
-    }
-}
+crate::wire_strings!(ChannelPermission {
+    Write => "WRITE",
+    Read => "READ",
+    Manage => "MANAGE",
+    All => "ALL",
+    MentionGroups => "MENTION_GROUPS",
+    SendTts => "SEND_TTS",
+    ReadHistory => "READ_HISTORY",
+    ManageWebhooks => "MANAGE_WEBHOOKS",
+});
+
 impl From<ChannelPermission> for HubPermission {
     fn from(channel_perm: ChannelPermission) -> Self {
         match channel_perm {
@@ -106,9 +135,188 @@ impl From<ChannelPermission> for HubPermission {
             ChannelPermission::Read => HubPermission::ReadChannels,
             ChannelPermission::Manage => HubPermission::ManageChannels,
             ChannelPermission::All => HubPermission::All,
+            ChannelPermission::MentionGroups => HubPermission::MentionGroups,
+            ChannelPermission::SendTts => HubPermission::SendTts,
+            ChannelPermission::ReadHistory => HubPermission::ReadHistory,
+            ChannelPermission::ManageWebhooks => HubPermission::ManageWebhooks,
         }
     }
 }
 
 /// Map of channel permissions to permission settings.
 pub type ChannelPermissions = HashMap<ChannelPermission, PermissionSetting>;
+
+/// Common sets of hub permissions that can be given to a permission group at once, see [`crate::api::apply_group_preset`].
+#[derive(PartialEq, Hash, Eq, Serialize, Deserialize, Clone, Copy, Debug)]
+#[cfg_attr(feature = "graphql", derive(Enum))]
+pub enum PermissionPreset {
+    /// Reading and writing in channels and inviting others.
+    Member,
+    /// A member that can also mute, kick and ban members and manage the hub's invites.
+    Moderator,
+    /// A moderator that can also administrate the hub and manage its channels and webhooks.
+    Administrator,
+}
+
+crate::wire_strings!(PermissionPreset {
+    Member => "MEMBER",
+    Moderator => "MODERATOR",
+    Administrator => "ADMINISTRATOR",
+});
+
+impl PermissionPreset {
+    /// Gets the permissions the preset grants, each preset includes the permissions of the ones before it.
+    pub fn permissions(self) -> &'static [HubPermission] {
+        use HubPermission::*;
+        match self {
+            PermissionPreset::Member => &[ReadChannels, WriteChannels, CreateInvite],
+            PermissionPreset::Moderator => &[
+                ReadChannels,
+                WriteChannels,
+                CreateInvite,
+                MentionGroups,
+                Mute,
+                Unmute,
+                Kick,
+                Ban,
+                Unban,
+                ManageInvites,
+            ],
+            PermissionPreset::Administrator => &[
+                ReadChannels,
+                WriteChannels,
+                CreateInvite,
+                MentionGroups,
+                Mute,
+                Unmute,
+                Kick,
+                Ban,
+                Unban,
+                ManageInvites,
+                Administrate,
+                ManageChannels,
+                ManageWebhooks,
+            ],
+        }
+    }
+
+    /// Gets the hub permission settings of the preset, every permission it does not grant is left unset so it can still be granted by another group.
+    pub fn hub_permissions(self) -> HubPermissions {
+        self.permissions()
+            .iter()
+            .map(|permission| (*permission, Some(true)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{ChannelPermission, HubPermission, PermissionPreset};
+
+    #[test]
+    fn stored_permissions_keep_their_meaning() {
+        // Permissions in the order they were stored in by earlier versions.
+        let hub_permissions = [
+            HubPermission::All,
+            HubPermission::ReadChannels,
+            HubPermission::WriteChannels,
+            HubPermission::Administrate,
+            HubPermission::ManageChannels,
+            HubPermission::Mute,
+            HubPermission::Unmute,
+            HubPermission::Kick,
+            HubPermission::Ban,
+            HubPermission::Unban,
+            HubPermission::MentionGroups,
+            HubPermission::SendTts,
+            HubPermission::ReadHistory,
+            HubPermission::CreateInvite,
+            HubPermission::ManageInvites,
+            HubPermission::ManageWebhooks,
+        ];
+        assert_eq!(&hub_permissions[..], HubPermission::VARIANTS);
+        for (index, permission) in hub_permissions.iter().enumerate() {
+            let stored = (index as u32).to_le_bytes();
+            assert_eq!(
+                bincode::deserialize::<HubPermission>(&stored).unwrap(),
+                *permission
+            );
+        }
+        let channel_permissions = [
+            ChannelPermission::Write,
+            ChannelPermission::Read,
+            ChannelPermission::Manage,
+            ChannelPermission::All,
+            ChannelPermission::MentionGroups,
+            ChannelPermission::SendTts,
+            ChannelPermission::ReadHistory,
+            ChannelPermission::ManageWebhooks,
+        ];
+        assert_eq!(&channel_permissions[..], ChannelPermission::VARIANTS);
+        for (index, permission) in channel_permissions.iter().enumerate() {
+            let stored = (index as u32).to_le_bytes();
+            assert_eq!(
+                bincode::deserialize::<ChannelPermission>(&stored).unwrap(),
+                *permission
+            );
+        }
+        // A permission map as saved in a hub: one entry, `Ban` (8) set to `Some(true)`.
+        let mut stored_map = 1u64.to_le_bytes().to_vec();
+        stored_map.extend_from_slice(&8u32.to_le_bytes());
+        stored_map.extend_from_slice(&[1, 1]);
+        let mut expected = HashMap::new();
+        expected.insert(HubPermission::Ban, Some(true));
+        assert_eq!(
+            bincode::deserialize::<HashMap<HubPermission, Option<bool>>>(&stored_map).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn strings_round_trip() {
+        let hub_strings: Vec<String> = HubPermission::VARIANTS
+            .iter()
+            .map(|permission| {
+                let string = permission.to_string();
+                assert_eq!(string.parse::<HubPermission>().unwrap(), *permission);
+                string
+            })
+            .collect();
+        assert_eq!(
+            hub_strings.join(","),
+            "ALL,READ_CHANNELS,WRITE_CHANNELS,ADMINISTRATE,MANAGE_CHANNELS,MUTE,UNMUTE,KICK,BAN,UNBAN,MENTION_GROUPS,SEND_TTS,READ_HISTORY,CREATE_INVITE,MANAGE_INVITES,MANAGE_WEBHOOKS"
+        );
+        let channel_strings: Vec<String> = ChannelPermission::VARIANTS
+            .iter()
+            .map(|permission| {
+                let string = permission.to_string();
+                assert_eq!(string.parse::<ChannelPermission>().unwrap(), *permission);
+                string
+            })
+            .collect();
+        assert_eq!(
+            channel_strings.join(","),
+            "WRITE,READ,MANAGE,ALL,MENTION_GROUPS,SEND_TTS,READ_HISTORY,MANAGE_WEBHOOKS"
+        );
+        assert!("read".parse::<ChannelPermission>().is_err());
+        assert_eq!(
+            "MODERATOR".parse::<PermissionPreset>().unwrap(),
+            PermissionPreset::Moderator
+        );
+    }
+
+    #[test]
+    fn presets_include_the_ones_before_them() {
+        let presets = PermissionPreset::VARIANTS;
+        for (smaller, larger) in presets.iter().zip(presets.iter().skip(1)) {
+            for permission in smaller.permissions() {
+                assert!(larger.permissions().contains(permission));
+            }
+        }
+        assert!(!PermissionPreset::Administrator
+            .permissions()
+            .contains(&HubPermission::All));
+    }
+}
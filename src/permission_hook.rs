@@ -0,0 +1,79 @@
+use std::sync::{Arc, RwLock};
+
+use crate::{
+    hub::Hub,
+    permission::{ChannelPermission, HubPermission},
+    ChannelId,
+};
+
+/// The hook registered with [`set`] and whether it may grant permissions.
+static HOOK: RwLock<Option<(Arc<dyn PermissionHook>, bool)>> = RwLock::new(None);
+
+/// Authorization logic of an application that embeds the server, for example permissions that come from LDAP groups, see [`crate::httpapi::ServerBuilder::permission_hook`].
+/// It is asked about every hub and channel permission check after the hub's own permissions have been checked, whether the request came through HTTP, GraphQL or a WebSocket, and gets their decision as `default`.
+/// The checks are synchronous and are often made for many channels or members at once, so a hook that uses an external source should answer from a cache it keeps up to date itself.
+/// Unless it was registered with `can_grant` a hook can only take permissions away, answering `true` when `default` is `false` has no effect.
+pub trait PermissionHook: Send + Sync {
+    /// Decides if a member of a hub has a hub permission.
+    fn hub_permission(
+        &self,
+        _user_id: &str,
+        _hub: &Hub,
+        _permission: HubPermission,
+        default: bool,
+    ) -> bool {
+        default
+    }
+
+    /// Decides if a member of a hub has a permission in one of its channels, including [`ChannelPermission::Read`] for everything that reads messages and [`ChannelPermission::Write`] for sending them.
+    fn channel_permission(
+        &self,
+        _user_id: &str,
+        _hub: &Hub,
+        _channel_id: ChannelId,
+        _permission: ChannelPermission,
+        default: bool,
+    ) -> bool {
+        default
+    }
+}
+
+/// Hook that keeps every decision of the hub's permissions, the same as having no hook.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultPermissionHook;
+
+impl PermissionHook for DefaultPermissionHook {}
+
+/// Registers the hook that permission checks are passed through, replacing the previous one. If `can_grant` is true the hook can also give permissions the hub's settings do not.
+pub fn set(hook: Arc<dyn PermissionHook>, can_grant: bool) {
+    *HOOK.write().unwrap_or_else(|err| err.into_inner()) = Some((hook, can_grant));
+}
+
+/// Passes a decision through the registered hook, a hook that is not allowed to grant permissions can only turn `true` into `false`.
+fn decide(default: bool, ask: impl FnOnce(&dyn PermissionHook) -> bool) -> bool {
+    let registered = HOOK.read().unwrap_or_else(|err| err.into_inner()).clone();
+    match registered {
+        Some((hook, can_grant)) => ask(hook.as_ref()) && (default || can_grant),
+        None => default,
+    }
+}
+
+/// Asks the registered hook if a member has a hub permission, see [`PermissionHook::hub_permission`].
+pub fn hub_permission(user_id: &str, hub: &Hub, permission: HubPermission, default: bool) -> bool {
+    decide(default, |hook| {
+        hook.hub_permission(user_id, hub, permission, default)
+    })
+}
+
+/// Asks the registered hook if a member has a channel permission, see [`PermissionHook::channel_permission`].
+pub fn channel_permission(
+    user_id: &str,
+    hub: &Hub,
+    channel_id: ChannelId,
+    permission: ChannelPermission,
+    default: bool,
+) -> bool {
+    decide(default, |hook| {
+        hook.channel_permission(user_id, hub, channel_id, permission, default)
+    })
+}
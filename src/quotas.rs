@@ -0,0 +1,384 @@
+use std::{path::PathBuf, sync::RwLock};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    config::LimitsConfig,
+    error::{Error, IoContext},
+    hub::Hub,
+    locks::{KeyedLock, KeyedLocks},
+    Result,
+};
+use serde::{Deserialize, Serialize};
+
+/// Folder where the usage counters and quota overrides of each user are stored.
+pub const USER_QUOTAS_FOLDER: &str = "data/users/quotas/";
+
+/// Limits set by [`set_limits`], the defaults are used until it is called.
+static LIMITS: RwLock<Option<LimitsConfig>> = RwLock::new(None);
+
+/// Locks that make changes to the quota file of each user happen one at a time.
+static QUOTA_LOCKS: KeyedLocks<String> = KeyedLocks::new();
+
+/// Sets the limits used by the quota checks, called by the server on startup with the limits from its configuration.
+pub fn set_limits(limits: LimitsConfig) {
+    *LIMITS.write().unwrap_or_else(|err| err.into_inner()) = Some(limits);
+}
+
+/// Gets the configured limits.
+pub fn limits() -> LimitsConfig {
+    LIMITS
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone()
+        .unwrap_or_default()
+}
+
+/// Checks if a user can view and change the quotas of other users.
+pub fn is_admin(user_id: &str) -> bool {
+    limits().admins.iter().any(|admin| admin == user_id)
+}
+
+/// Quotas that limit what a single user can do.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quota {
+    /// Number of hubs a user owns.
+    Hubs,
+    /// Total size of the content of the messages a user sends in a day (UTC).
+    MessageBytesPerDay,
+}
+
+crate::wire_strings!(Quota {
+    Hubs => "HUBS",
+    MessageBytesPerDay => "MESSAGE_BYTES_PER_DAY",
+});
+
+/// Limits for a single user that replace the configured ones, `None` uses the configured limit.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct QuotaOverrides {
+    /// Maximum number of hubs the user can own.
+    pub max_hubs: Option<usize>,
+    /// Maximum number of message bytes the user can send per day.
+    pub max_message_bytes_per_day: Option<u64>,
+}
+
+/// Usage counters and quota overrides of a user.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct UserQuota {
+    /// Number of hubs the user owns.
+    pub owned_hubs: usize,
+    /// Day (number of days since Unix Epoch) that `message_bytes` was counted on.
+    pub day: i64,
+    /// Number of message bytes the user sent on `day`.
+    pub message_bytes: u64,
+    /// Limits that apply to this user instead of the configured ones.
+    pub overrides: QuotaOverrides,
+}
+
+/// Usage and effective limits of a user, `None` means there is no limit.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct QuotaStatus {
+    pub owned_hubs: usize,
+    pub max_hubs: Option<usize>,
+    pub message_bytes_today: u64,
+    pub max_message_bytes_per_day: Option<u64>,
+    pub overrides: QuotaOverrides,
+}
+
+/// Gets the current day of a clock as used by [`UserQuota::day`], days start at midnight UTC.
+fn today(clock: &dyn Clock) -> i64 {
+    clock.now().timestamp().div_euclid(86400)
+}
+
+impl UserQuota {
+    /// Gets the path of the file that a user's quota is stored in.
+    pub fn get_path(user_id: &str) -> PathBuf {
+        crate::paths::user_quota_file(user_id)
+    }
+
+    /// Loads a user's quota. A user without a quota file gets a new one, with the number of hubs they already own counted.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The user ID is not a hex encoded fingerprint.
+    /// * The file exists but could not be read.
+    /// * The file's contents could not be deserialized.
+    /// * The hubs could not be counted for any of the reasons outlined in [`count_owned_hubs`].
+    pub async fn load(user_id: &str) -> Result<Self> {
+        if hex::decode(user_id).is_err() {
+            return Err(Error::InvalidFingerprint);
+        }
+        let path = Self::get_path(user_id);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(bincode::deserialize(&bytes)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self {
+                owned_hubs: count_owned_hubs(user_id).await?,
+                ..Self::default()
+            }),
+            Err(err) => Err(err).with_path(path),
+        }
+    }
+
+    /// Saves a user's quota.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The quota folder does not exist and could not be created.
+    /// * The data could not be written to the disk.
+    pub async fn save(&self, user_id: &str) -> Result {
+        tokio::fs::create_dir_all(USER_QUOTAS_FOLDER)
+            .await
+            .with_path(USER_QUOTAS_FOLDER)?;
+        let path = Self::get_path(user_id);
+        tokio::fs::write(&path, bincode::serialize(self)?)
+            .await
+            .with_path(path)
+    }
+
+    /// Maximum number of hubs the user can own.
+    pub fn max_hubs(&self, limits: &LimitsConfig) -> Option<usize> {
+        self.overrides.max_hubs.or(limits.max_hubs_per_user)
+    }
+
+    /// Maximum number of message bytes the user can send per day.
+    pub fn max_message_bytes_per_day(&self, limits: &LimitsConfig) -> Option<u64> {
+        self.overrides
+            .max_message_bytes_per_day
+            .or(limits.max_message_bytes_per_day)
+    }
+
+    /// Number of message bytes sent on the given day, the counter is only kept for one day.
+    pub fn message_bytes_on(&self, day: i64) -> u64 {
+        if self.day == day {
+            self.message_bytes
+        } else {
+            0
+        }
+    }
+
+    /// Counts a new hub owned by the user, fails without counting it if the user already owns as many hubs as they can.
+    pub fn add_hub(&mut self, limits: &LimitsConfig) -> Result {
+        if let Some(max) = self.max_hubs(limits) {
+            if self.owned_hubs >= max {
+                return Err(Error::LimitExceeded(Quota::Hubs));
+            }
+        }
+        self.owned_hubs += 1;
+        Ok(())
+    }
+
+    /// Replaces the counted number of owned hubs with the number of hubs the user really owns, returns true if they differed.
+    pub fn reconcile_hubs(&mut self, owned_hubs: usize) -> bool {
+        let changed = self.owned_hubs != owned_hubs;
+        self.owned_hubs = owned_hubs;
+        changed
+    }
+
+    /// Counts message bytes sent on the given day, fails without counting them if they would go over the user's daily limit.
+    pub fn add_message_bytes(&mut self, bytes: u64, day: i64, limits: &LimitsConfig) -> Result {
+        let total = self.message_bytes_on(day).saturating_add(bytes);
+        if let Some(max) = self.max_message_bytes_per_day(limits) {
+            if total > max {
+                return Err(Error::LimitExceeded(Quota::MessageBytesPerDay));
+            }
+        }
+        self.day = day;
+        self.message_bytes = total;
+        Ok(())
+    }
+
+    /// Gets the usage and effective limits of the user.
+    pub fn status(&self, limits: &LimitsConfig) -> QuotaStatus {
+        QuotaStatus {
+            owned_hubs: self.owned_hubs,
+            max_hubs: self.max_hubs(limits),
+            message_bytes_today: self.message_bytes_on(today(&SystemClock)),
+            max_message_bytes_per_day: self.max_message_bytes_per_day(limits),
+            overrides: self.overrides.clone(),
+        }
+    }
+}
+
+/// Locks the quota file of a user so that no other change is made to it until the guard is dropped.
+async fn lock(user_id: &str) -> KeyedLock<'static, String> {
+    QUOTA_LOCKS.lock(user_id.to_string()).await
+}
+
+/// Counts the hubs a user owns. Hubs are the source of truth for ownership, [`UserQuota::owned_hubs`] is only a counter that is kept next to them.
+///
+/// # Errors
+///
+/// This function returns an error if the hubs could not be listed for any of the reasons outlined in [`crate::maintenance::list_hubs`].
+pub async fn count_owned_hubs(user_id: &str) -> Result<usize> {
+    let mut owned_hubs = 0;
+    for hub_id in crate::maintenance::list_hubs().await? {
+        if let Ok(hub) = Hub::load(hub_id).await {
+            if hub.owner == user_id {
+                owned_hubs += 1;
+            }
+        }
+    }
+    Ok(owned_hubs)
+}
+
+/// Recounts the hubs a user owns and corrects their quota if the counter drifted, for example because a hub was deleted but the quota could not be saved afterwards.
+/// Returns true if the quota was corrected.
+/// A hub that is being created while this runs may not be counted yet, the next reconciliation counts it.
+///
+/// # Errors
+///
+/// This function may return an error for any of the reasons outlined in [`count_owned_hubs`], [`UserQuota::load`] and [`UserQuota::save`].
+pub async fn reconcile(user_id: &str) -> Result<bool> {
+    let _guard = lock(user_id).await;
+    let mut quota = UserQuota::load(user_id).await?;
+    let changed = quota.reconcile_hubs(count_owned_hubs(user_id).await?);
+    if changed {
+        warn!(
+            "Corrected the number of hubs owned by {} to {}.",
+            user_id, quota.owned_hubs
+        );
+        quota.save(user_id).await?;
+    }
+    Ok(changed)
+}
+
+/// Loads a user's quota, changes it and saves it if the change succeeded, while no other change to the user's quota is made.
+///
+/// # Errors
+///
+/// This function may return an error for any of the reasons outlined in [`UserQuota::load`] and [`UserQuota::save`] or the error returned by `change`.
+pub async fn update<T>(
+    user_id: &str,
+    change: impl FnOnce(&mut UserQuota, &LimitsConfig) -> Result<T>,
+) -> Result<T> {
+    let _guard = lock(user_id).await;
+    let mut quota = UserQuota::load(user_id).await?;
+    let result = change(&mut quota, &limits())?;
+    quota.save(user_id).await?;
+    Ok(result)
+}
+
+/// Counts a new hub owned by a user, see [`UserQuota::add_hub`].
+///
+/// # Errors
+///
+/// This function returns [`Error::LimitExceeded`] if the user owns too many hubs and may return an error for any of the reasons outlined in [`update`] and [`reconcile`].
+pub async fn charge_hub(user_id: &str) -> Result {
+    match update(user_id, |quota, limits| quota.add_hub(limits)).await {
+        // The counter may be too high if an earlier deletion could not update it, so check before refusing.
+        Err(Error::LimitExceeded(Quota::Hubs)) if reconcile(user_id).await? => {
+            update(user_id, |quota, limits| quota.add_hub(limits)).await
+        }
+        result => result,
+    }
+}
+
+/// Stops counting a hub that a user owned, used when it is deleted.
+///
+/// # Errors
+///
+/// This function may return an error for any of the reasons outlined in [`update`].
+pub async fn release_hub(user_id: &str) -> Result {
+    update(user_id, |quota, _| {
+        quota.owned_hubs = quota.owned_hubs.saturating_sub(1);
+        Ok(())
+    })
+    .await
+}
+
+/// Counts the content of a message sent by a user against their daily limit, see [`UserQuota::add_message_bytes`].
+///
+/// # Errors
+///
+/// This function returns [`Error::LimitExceeded`] if the user has sent too many bytes today and may return an error for any of the reasons outlined in [`update`].
+pub async fn charge_message(user_id: &str, bytes: usize) -> Result {
+    update(user_id, |quota, limits| {
+        quota.add_message_bytes(bytes as u64, today(&SystemClock), limits)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{today, Quota, UserQuota};
+    use crate::{clock::ManualClock, config::LimitsConfig, error::Error};
+
+    #[test]
+    fn days_start_at_midnight() {
+        let clock = ManualClock::new(
+            Utc.with_ymd_and_hms(2021, 3, 1, 23, 59, 59).unwrap() + Duration::milliseconds(999),
+        );
+        let day = today(&clock);
+        clock.advance(Duration::milliseconds(1));
+        assert_eq!(today(&clock), day + 1);
+        clock.advance(Duration::hours(24) - Duration::milliseconds(1));
+        assert_eq!(today(&clock), day + 1);
+    }
+
+    #[test]
+    fn daily_message_bytes() {
+        let limits = LimitsConfig {
+            max_message_bytes_per_day: Some(10),
+            ..LimitsConfig::default()
+        };
+        let mut quota = UserQuota::default();
+        quota.add_message_bytes(6, 1, &limits).unwrap();
+        assert!(matches!(
+            quota.add_message_bytes(5, 1, &limits),
+            Err(Error::LimitExceeded(Quota::MessageBytesPerDay))
+        ));
+        assert_eq!(quota.message_bytes_on(1), 6);
+        // The counter starts again on the next day.
+        quota.add_message_bytes(10, 2, &limits).unwrap();
+        assert_eq!(quota.message_bytes_on(2), 10);
+        quota.overrides.max_message_bytes_per_day = Some(20);
+        quota.add_message_bytes(10, 2, &limits).unwrap();
+    }
+
+    #[test]
+    fn hub_limit() {
+        let limits = LimitsConfig {
+            max_hubs_per_user: Some(1),
+            ..LimitsConfig::default()
+        };
+        let mut quota = UserQuota::default();
+        quota.add_hub(&limits).unwrap();
+        assert!(matches!(
+            quota.add_hub(&limits),
+            Err(Error::LimitExceeded(Quota::Hubs))
+        ));
+        assert_eq!(quota.owned_hubs, 1);
+        quota.overrides.max_hubs = Some(2);
+        quota.add_hub(&limits).unwrap();
+    }
+
+    #[test]
+    fn reconcile_owned_hubs() {
+        let limits = LimitsConfig {
+            max_hubs_per_user: Some(2),
+            ..LimitsConfig::default()
+        };
+        // A hub was deleted (or failed to be created after it was charged) but the quota was not released.
+        let mut quota = UserQuota::default();
+        quota.add_hub(&limits).unwrap();
+        quota.add_hub(&limits).unwrap();
+        assert!(quota.add_hub(&limits).is_err());
+        assert!(quota.reconcile_hubs(1));
+        quota.add_hub(&limits).unwrap();
+        // A hub was created but the charge was lost, for example by restoring an old quota file.
+        let mut quota = UserQuota::default();
+        assert!(quota.reconcile_hubs(2));
+        assert!(matches!(
+            quota.add_hub(&limits),
+            Err(Error::LimitExceeded(Quota::Hubs))
+        ));
+        assert!(!quota.reconcile_hubs(2));
+    }
+}
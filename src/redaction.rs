@@ -0,0 +1,804 @@
+use std::collections::BTreeSet;
+
+use chrono::{Duration, Utc};
+use reqwest::Method;
+
+use crate::{
+    account_export::{ExportState, ExportStatus},
+    api,
+    client::WicrsClient,
+    invites::InviteOptions,
+    permission::ChannelPermission,
+    rendering::RenderFormat,
+    testing::{spawn_test_server, TestServer},
+    webhooks::NewWebhook,
+    websocket::{answer_read, ReadQuery},
+    ChannelId, HubId, MessageId, ID,
+};
+
+/// Name of the channel the low-permission user can not read.
+const SEALED_NAME: &str = "sealedname";
+const SEALED_DESCRIPTION: &str = "sealeddescription";
+const SEALED_LONG_DESCRIPTION: &str = "sealedlongdescription";
+/// Content of a message sent in the sealed channel by the hub owner.
+const SEALED_MESSAGE: &str = "sealedmessage";
+/// Content of a message the low-permission user sent in the sealed channel before they lost access to it.
+const SEALED_OWN_MESSAGE: &str = "sealedownmessage";
+
+/// Text that must not appear in any response the low-permission user gets.
+const SECRETS: [&str; 5] = [
+    SEALED_NAME,
+    SEALED_DESCRIPTION,
+    SEALED_LONG_DESCRIPTION,
+    SEALED_MESSAGE,
+    SEALED_OWN_MESSAGE,
+];
+
+/// A hub with a channel the low-permission user can read and one they can not.
+struct Fixture {
+    hub_id: HubId,
+    lobby: ChannelId,
+    sealed: ChannelId,
+    sealed_message: MessageId,
+    own_message: MessageId,
+    invite_code: String,
+    /// Webhook of the sealed channel.
+    webhook: ID,
+}
+
+impl Fixture {
+    async fn new(server: &TestServer) -> Self {
+        let owner = server.admin.user_id.as_str();
+        let user = server.user.user_id.as_str();
+        let hub_id = api::create_hub(owner, "redaction").await.unwrap();
+        let lobby = api::create_channel(owner, hub_id, "lobby").await.unwrap();
+        let sealed = api::create_channel(owner, hub_id, SEALED_NAME)
+            .await
+            .unwrap();
+        api::change_channel_description(owner, hub_id, sealed, SEALED_DESCRIPTION)
+            .await
+            .unwrap();
+        api::change_channel_long_description(
+            owner,
+            hub_id,
+            sealed,
+            SEALED_LONG_DESCRIPTION.to_string(),
+        )
+        .await
+        .unwrap();
+        api::set_hub_preview_enabled(owner, hub_id, true)
+            .await
+            .unwrap();
+        api::join_hub(user.to_string(), hub_id).await.unwrap();
+        for channel in [lobby, sealed].iter().copied() {
+            for permission in [ChannelPermission::Read, ChannelPermission::Write]
+                .iter()
+                .copied()
+            {
+                api::set_member_channel_permission(
+                    owner,
+                    hub_id,
+                    user,
+                    channel,
+                    permission,
+                    Some(true),
+                    None,
+                )
+                .await
+                .unwrap();
+            }
+        }
+        let sealed_message = server
+            .client(&server.admin)
+            .send_message(hub_id, sealed, SEALED_MESSAGE)
+            .await
+            .unwrap()
+            .id;
+        let client = server.client(&server.user);
+        let own_message = client
+            .send_message(hub_id, sealed, SEALED_OWN_MESSAGE)
+            .await
+            .unwrap()
+            .id;
+        client.send_message(hub_id, lobby, "hello").await.unwrap();
+        api::set_member_channel_permission(
+            owner,
+            hub_id,
+            user,
+            sealed,
+            ChannelPermission::Read,
+            Some(false),
+            None,
+        )
+        .await
+        .unwrap();
+        let invite_code = api::create_invite(owner, hub_id, InviteOptions::default())
+            .await
+            .unwrap()
+            .code;
+        let webhook = api::create_webhook(
+            owner,
+            hub_id,
+            sealed,
+            NewWebhook {
+                name: "alerts".to_string(),
+                avatar: None,
+            },
+        )
+        .await
+        .unwrap()
+        .id;
+        Self {
+            hub_id,
+            lobby,
+            sealed,
+            sealed_message,
+            own_message,
+            invite_code,
+            webhook,
+        }
+    }
+
+    /// Requests to every HTTP route, as (route, method, path, signed content).
+    /// The route is the path without its parameters, as found by [`registered_routes`].
+    fn requests(&self, server: &TestServer) -> Vec<(&'static str, Method, String, String)> {
+        let hub = self.hub_id;
+        let sealed = self.sealed;
+        let owner = &server.admin.user_id;
+        let user = &server.user.user_id;
+        let forward_target = format!(
+            "{{\"hub_id\":\"{}\",\"channel_id\":\"{}\"}}",
+            hub, self.lobby
+        );
+        let channel_query = format!(
+            "{{ hub(id: \"{hub}\") {{ name description descriptionLong channel(id: \"{sealed}\") {{ name }} allChannels {{ name description descriptionLong searchMessages(query: \"{message}\", limit: 10) }} }} hubs(ids: [\"{hub}\"]) {{ allChannels {{ name }} }} }}",
+            hub = hub,
+            sealed = sealed,
+            message = SEALED_MESSAGE,
+        );
+        let raw_query = format!(
+            "{{ hub(id: \"{}\", raw: true) {{ allChannels {{ name description }} }} }}",
+            hub
+        );
+        vec![
+            ("info", Method::GET, "v3/info".to_string(), String::new()),
+            (
+                "instance",
+                Method::GET,
+                "v3/instance".to_string(),
+                String::new(),
+            ),
+            ("stats", Method::GET, "v3/stats".to_string(), String::new()),
+            (
+                "send_message_init",
+                Method::POST,
+                format!("v3/send_message_init/{}/{}", hub, sealed),
+                "hello".to_string(),
+            ),
+            (
+                "send_message",
+                Method::POST,
+                "v3/send_message".to_string(),
+                "hello".to_string(),
+            ),
+            (
+                "edit_message_init",
+                Method::POST,
+                format!(
+                    "v3/edit_message_init/{}/{}/{}",
+                    hub, sealed, self.own_message
+                ),
+                "edited".to_string(),
+            ),
+            (
+                "edit_message",
+                Method::POST,
+                "v3/edit_message".to_string(),
+                "edited".to_string(),
+            ),
+            (
+                "forward_message_init",
+                Method::POST,
+                format!(
+                    "v3/forward_message_init/{}/{}/{}",
+                    hub, sealed, self.sealed_message
+                ),
+                forward_target,
+            ),
+            (
+                "message_history",
+                Method::GET,
+                format!("v3/message_history/{}/{}/{}", hub, sealed, self.own_message),
+                String::new(),
+            ),
+            (
+                "message_rendered",
+                Method::GET,
+                format!(
+                    "v3/message_rendered/{}/{}/{}",
+                    hub, sealed, self.sealed_message
+                ),
+                String::new(),
+            ),
+            (
+                "link_previews",
+                Method::GET,
+                format!(
+                    "v3/link_previews/{}/{}/{}",
+                    hub, sealed, self.sealed_message
+                ),
+                String::new(),
+            ),
+            (
+                "hub",
+                Method::GET,
+                format!("v3/hub/{}?fields=name,channels", hub),
+                String::new(),
+            ),
+            (
+                "hub_delta",
+                Method::GET,
+                format!("v3/hub_delta/{}?since_version=0", hub),
+                String::new(),
+            ),
+            (
+                "events_since",
+                Method::GET,
+                format!("v3/events_since/{}/{}/0?epoch=0", hub, sealed),
+                String::new(),
+            ),
+            (
+                "hub_preview",
+                Method::GET,
+                format!("v3/hub_preview/{}", hub),
+                String::new(),
+            ),
+            (
+                "hub_preview",
+                Method::PUT,
+                format!("v3/hub_preview/{}", hub),
+                "true".to_string(),
+            ),
+            (
+                "hub_icon",
+                Method::GET,
+                format!("v3/hub_icon/{}", hub),
+                String::new(),
+            ),
+            (
+                "hub_banner",
+                Method::GET,
+                format!("v3/hub_banner/{}", hub),
+                String::new(),
+            ),
+            (
+                "hubs",
+                Method::POST,
+                "v3/hubs".to_string(),
+                "{\"name\":\"new hub\"}".to_string(),
+            ),
+            (
+                "resolve",
+                Method::GET,
+                format!("v3/resolve/{}", self.invite_code),
+                String::new(),
+            ),
+            (
+                "invites",
+                Method::GET,
+                format!("v3/invites/{}", hub),
+                String::new(),
+            ),
+            (
+                "invites",
+                Method::POST,
+                format!("v3/invites/{}", hub),
+                "{}".to_string(),
+            ),
+            (
+                "invite_stats",
+                Method::GET,
+                format!("v3/invite_stats/{}", hub),
+                String::new(),
+            ),
+            (
+                "kick_invite_members",
+                Method::POST,
+                format!("v3/kick_invite_members/{}/{}", hub, self.invite_code),
+                String::new(),
+            ),
+            (
+                "webhooks",
+                Method::GET,
+                format!("v3/webhooks/{}/{}", hub, sealed),
+                String::new(),
+            ),
+            (
+                "webhooks",
+                Method::POST,
+                format!("v3/webhooks/{}/{}", hub, sealed),
+                "{\"name\":\"hook\"}".to_string(),
+            ),
+            (
+                "webhooks",
+                Method::DELETE,
+                format!("v3/webhooks/{}/{}", hub, self.webhook),
+                String::new(),
+            ),
+            (
+                "webhook_send",
+                Method::POST,
+                format!(
+                    "v3/webhook_send/{}/{}/{}",
+                    hub,
+                    self.webhook,
+                    "0".repeat(32)
+                ),
+                "{\"content\":\"hook\"}".to_string(),
+            ),
+            (
+                "revoke_invite",
+                Method::POST,
+                format!("v3/revoke_invite/{}/{}", hub, self.invite_code),
+                String::new(),
+            ),
+            (
+                "join",
+                Method::POST,
+                format!("v3/join/{}", self.invite_code),
+                String::new(),
+            ),
+            (
+                "hub_description",
+                Method::PUT,
+                format!("v3/hub_description/{}", hub),
+                "changed".to_string(),
+            ),
+            (
+                "channel_description",
+                Method::PUT,
+                format!("v3/channel_description/{}/{}", hub, sealed),
+                "changed".to_string(),
+            ),
+            (
+                "nicknames",
+                Method::GET,
+                format!("v3/nicknames/{}", hub),
+                String::new(),
+            ),
+            (
+                "nickname",
+                Method::PUT,
+                format!("v3/nickname/{}", hub),
+                "nick".to_string(),
+            ),
+            (
+                "nickname_policy",
+                Method::PUT,
+                format!("v3/nickname_policy/{}", hub),
+                "{}".to_string(),
+            ),
+            (
+                "content_policy",
+                Method::GET,
+                format!("v3/content_policy/{}", hub),
+                String::new(),
+            ),
+            (
+                "content_policy",
+                Method::PUT,
+                format!("v3/content_policy/{}", hub),
+                "{}".to_string(),
+            ),
+            (
+                "copy_channel_permissions",
+                Method::POST,
+                format!(
+                    "v3/copy_channel_permissions/{}/{}/{}",
+                    hub, sealed, self.lobby
+                ),
+                String::new(),
+            ),
+            (
+                "copy_channel_permissions",
+                Method::POST,
+                format!("v3/copy_channel_permissions/{}/{}", hub, sealed),
+                String::new(),
+            ),
+            (
+                "group_display",
+                Method::GET,
+                format!("v3/group_display/{}", hub),
+                String::new(),
+            ),
+            (
+                "group_display",
+                Method::POST,
+                format!("v3/group_display/{}/{}", hub, hub),
+                "{}".to_string(),
+            ),
+            (
+                "group_preset",
+                Method::POST,
+                format!("v3/group_preset/{}/{}/MODERATOR", hub, hub),
+                String::new(),
+            ),
+            (
+                "effective_permissions",
+                Method::GET,
+                format!("v3/effective_permissions/{}", hub),
+                String::new(),
+            ),
+            (
+                "group_position",
+                Method::POST,
+                format!("v3/group_position/{}/{}/0", hub, hub),
+                String::new(),
+            ),
+            (
+                "purge_user_messages",
+                Method::POST,
+                format!("v3/purge_user_messages/{}/{}", hub, owner),
+                String::new(),
+            ),
+            (
+                "purge_status",
+                Method::GET,
+                format!("v3/purge_status/{}", hub),
+                String::new(),
+            ),
+            (
+                "move_messages",
+                Method::POST,
+                format!("v3/move_messages/{}/{}/{}", hub, sealed, self.lobby),
+                "[]".to_string(),
+            ),
+            (
+                "request_delete_hub",
+                Method::POST,
+                format!("v3/request_delete_hub/{}", hub),
+                String::new(),
+            ),
+            (
+                "delete_hub",
+                Method::DELETE,
+                format!("v3/delete_hub/{}/0", hub),
+                String::new(),
+            ),
+            (
+                "channels",
+                Method::POST,
+                format!("v3/channels/{}", hub),
+                format!(
+                    "{{\"name\": \"copy\", \"member_permissions\": [{{\"member\": \"{}\", \"permission\": \"Read\", \"setting\": true}}]}}",
+                    user
+                ),
+            ),
+            (
+                "drafts",
+                Method::GET,
+                "v3/drafts".to_string(),
+                String::new(),
+            ),
+            (
+                "owned_hubs",
+                Method::GET,
+                "v3/owned_hubs".to_string(),
+                String::new(),
+            ),
+            (
+                "bulk_transfer_hubs",
+                Method::POST,
+                "v3/bulk_transfer_hubs".to_string(),
+                format!("{{\"{}\": \"{}\"}}", hub, user),
+            ),
+            (
+                "leave_all_hubs",
+                Method::POST,
+                "v3/leave_all_hubs".to_string(),
+                format!("{{\"exclude\": [\"{}\"]}}", hub),
+            ),
+            (
+                "subscription_profile",
+                Method::GET,
+                "v3/subscription_profile".to_string(),
+                String::new(),
+            ),
+            (
+                "set_subscription_profile",
+                Method::PUT,
+                "v3/subscription_profile".to_string(),
+                format!("{{\"channels\": [[\"{}\", \"{}\"]]}}", hub, sealed),
+            ),
+            (
+                "draft",
+                Method::PUT,
+                format!("v3/draft/{}/{}", hub, sealed),
+                "{\"content\":\"\"}".to_string(),
+            ),
+            (
+                "members",
+                Method::GET,
+                format!("v3/members/{}", hub),
+                String::new(),
+            ),
+            (
+                "member_map",
+                Method::GET,
+                format!("v3/member_map/{}", hub),
+                String::new(),
+            ),
+            (
+                "member_history",
+                Method::GET,
+                format!("v3/member_history/{}", hub),
+                String::new(),
+            ),
+            (
+                "change_history",
+                Method::GET,
+                format!("v3/change_history/{}", hub),
+                String::new(),
+            ),
+            (
+                "hub_activity",
+                Method::GET,
+                format!("v3/hub_activity/{}", hub),
+                String::new(),
+            ),
+            (
+                "hub_storage",
+                Method::GET,
+                format!("v3/hub_storage/{}", hub),
+                String::new(),
+            ),
+            (
+                "compact_channel",
+                Method::POST,
+                format!("v3/compact_channel/{}/{}", hub, sealed),
+                String::new(),
+            ),
+            (
+                "leaderboard",
+                Method::GET,
+                format!("v3/leaderboard/{}", hub),
+                String::new(),
+            ),
+            (
+                "leaderboard",
+                Method::PUT,
+                format!("v3/leaderboard/{}", hub),
+                "true".to_string(),
+            ),
+            (
+                "user_quota",
+                Method::GET,
+                format!("v3/user_quota/{}", user),
+                String::new(),
+            ),
+            (
+                "user_quota",
+                Method::PUT,
+                format!("v3/user_quota/{}", user),
+                "{}".to_string(),
+            ),
+            (
+                "link_identity",
+                Method::POST,
+                format!("v3/link_identity/{}", user),
+                format!("{{\"fingerprint\": \"{}\"}}", user),
+            ),
+            (
+                "admin/security_log",
+                Method::GET,
+                format!("v3/admin/security_log?user={}", user),
+                String::new(),
+            ),
+            (
+                "graphql",
+                Method::POST,
+                "v3/graphql".to_string(),
+                channel_query,
+            ),
+            ("graphql", Method::POST, "v3/graphql".to_string(), raw_query),
+            (
+                "graphql_schema",
+                Method::GET,
+                "v3/graphql_schema".to_string(),
+                String::new(),
+            ),
+        ]
+    }
+
+    /// Queries that can be sent over the WebSocket API, see [`answer_read`].
+    fn read_queries(&self, server: &TestServer) -> Vec<ReadQuery> {
+        vec![
+            ReadQuery::GetHub {
+                hub_id: self.hub_id,
+            },
+            ReadQuery::GetChannel {
+                hub_id: self.hub_id,
+                channel_id: self.sealed,
+            },
+            ReadQuery::GetMessage {
+                hub_id: self.hub_id,
+                channel_id: self.sealed,
+                message_id: self.sealed_message,
+                expand_sender: true,
+                render: None,
+            },
+            ReadQuery::GetMessages {
+                hub_id: self.hub_id,
+                channel_id: self.sealed,
+                from: Utc::now() - Duration::days(1),
+                to: Utc::now() + Duration::days(1),
+                invert: false,
+                max: 100,
+                expand_sender: false,
+                render: Some(RenderFormat::Html),
+            },
+            ReadQuery::GetMessagesAfter {
+                hub_id: self.hub_id,
+                channel_id: self.sealed,
+                from: MessageId::nil(),
+                max: 100,
+                expand_sender: true,
+                render: None,
+            },
+            ReadQuery::GetHubMember {
+                hub_id: self.hub_id,
+                user_id: server.user.user_id.clone(),
+            },
+        ]
+    }
+}
+
+/// Gets the routes registered under `/v3`, as their path without parameters (e.g. `export_account/status`).
+/// Read from the source of the HTTP API so that routes added later are covered without changing this module.
+fn registered_routes() -> BTreeSet<String> {
+    let source = include_str!("httpapi.rs");
+    let mut routes: BTreeSet<String> = source
+        .match_indices("\"v3\" /")
+        .map(|(start, pattern)| {
+            let rest = &source[start + pattern.len()..];
+            rest[..rest.find(')').unwrap_or(rest.len())]
+                .split('/')
+                .map(str::trim)
+                .filter(|segment| segment.starts_with('"'))
+                .map(|segment| segment.trim_matches('"'))
+                .collect::<Vec<&str>>()
+                .join("/")
+        })
+        .collect();
+    routes.extend(
+        source
+            .match_indices("hub_image_routes(")
+            .filter_map(|(start, pattern)| {
+                let rest = source[start + pattern.len()..].trim_start();
+                let name = rest.strip_prefix('"')?;
+                Some(name[..name.find('"')?].to_string())
+            }),
+    );
+    routes
+}
+
+/// Sends a signed request and returns what the user gets to see: the content of the response if it was signed by the server, otherwise the body itself.
+async fn fetch(client: &WicrsClient, method: Method, path: &str, content: &str) -> String {
+    match client.request_signed(method, path, content).await {
+        Ok(body) => client.verify_response(&body).unwrap_or(body),
+        Err(err) => err.to_string(),
+    }
+}
+
+/// Gets the state of an export from the response of `/v3/export_account/status`.
+fn export_state(status: &str) -> Option<ExportState> {
+    serde_json::from_str::<ExportStatus>(status)
+        .ok()
+        .map(|status| status.state)
+}
+
+fn assert_redacted(route: &str, response: &str) {
+    for secret in SECRETS.iter() {
+        assert!(
+            !response.contains(secret),
+            "{} gave content of a channel the user can not read: {}",
+            route,
+            response
+        );
+    }
+}
+
+#[tokio::test]
+async fn unreadable_channels_are_redacted_on_every_route() {
+    let server = spawn_test_server().await.unwrap();
+    let fixture = Fixture::new(&server).await;
+    let client = server.client(&server.user);
+    let mut audited = BTreeSet::new();
+    for (route, method, path, content) in fixture.requests(&server) {
+        let response = fetch(&client, method, &path, &content).await;
+        assert_redacted(&path, &response);
+        audited.insert(route.to_string());
+    }
+
+    // Exports are made in the background, the download is only there once the export is ready.
+    let status = fetch(&client, Method::POST, "v3/export_account", "").await;
+    assert_redacted("v3/export_account", &status);
+    let mut status = String::new();
+    for _ in 0..100 {
+        status = fetch(&client, Method::GET, "v3/export_account/status", "").await;
+        if export_state(&status) != Some(ExportState::Running) {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert_eq!(
+        export_state(&status),
+        Some(ExportState::Ready),
+        "export did not finish: {}",
+        status
+    );
+    assert_redacted("v3/export_account/status", &status);
+    let export = fetch(&client, Method::GET, "v3/export_account/download", "").await;
+    assert!(
+        export.contains("hello"),
+        "export is missing messages: {}",
+        export
+    );
+    assert_redacted("v3/export_account/download", &export);
+    audited.extend(
+        [
+            "export_account",
+            "export_account/status",
+            "export_account/download",
+        ]
+        .iter()
+        .map(|route| route.to_string()),
+    );
+
+    for query in fixture.read_queries(&server) {
+        let answer = answer_read(&server.user.user_id, 1, query).await;
+        assert_redacted("websocket", &serde_json::to_string(&answer).unwrap());
+    }
+    audited.insert("websocket".to_string());
+
+    let missing: Vec<String> = registered_routes().difference(&audited).cloned().collect();
+    assert!(
+        missing.is_empty(),
+        "routes missing from the redaction audit: {:?}",
+        missing
+    );
+}
+
+#[tokio::test]
+async fn hub_fields_leave_out_bans() {
+    let server = spawn_test_server().await.unwrap();
+    let owner = server.admin.user_id.as_str();
+    let hub_id = api::create_hub(owner, "fields").await.unwrap();
+    api::join_hub(server.user.user_id.clone(), hub_id)
+        .await
+        .unwrap();
+    let banned = "0BADC0FFEE0BADC0FFEE0BADC0FFEE0BADC0FFEE";
+    api::ban_user(owner, hub_id, banned).await.unwrap();
+    let client = server.client(&server.user);
+
+    let response = fetch(
+        &client,
+        Method::GET,
+        &format!("v3/hub/{}?fields=bans", hub_id),
+        "",
+    )
+    .await;
+    assert!(
+        response.contains("unknown hub field") && response.contains("bans"),
+        "bans were not refused as an unknown field: {}",
+        response
+    );
+    assert!(!response.contains(banned));
+    let all = fetch(&client, Method::GET, &format!("v3/hub/{}", hub_id), "").await;
+    assert!(all.contains("default_group"), "hub was not sent: {}", all);
+    assert!(
+        !all.contains(banned) && !all.contains("\"bans\""),
+        "hub has its bans: {}",
+        all
+    );
+}
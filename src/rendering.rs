@@ -0,0 +1,251 @@
+#[cfg(feature = "markdown")]
+use std::collections::HashSet;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex as SyncMutex},
+};
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(not(feature = "markdown"))]
+use crate::error::Error;
+use crate::{channel::Message, MessageId, Result};
+
+/// Number of rendered messages kept in memory, the cache is emptied once it is full.
+pub const MAX_CACHED_RENDERINGS: usize = 4096;
+
+/// HTML tags that rendered messages can contain, everything else is removed.
+pub const ALLOWED_TAGS: &[&str] = &[
+    "a",
+    "blockquote",
+    "br",
+    "code",
+    "del",
+    "em",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "li",
+    "ol",
+    "p",
+    "pre",
+    "strong",
+    "table",
+    "tbody",
+    "td",
+    "th",
+    "thead",
+    "tr",
+    "ul",
+];
+
+/// URL schemes that links in rendered messages can have, links with any other scheme lose their target.
+pub const ALLOWED_URL_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+/// `rel` attribute given to every link in a rendered message.
+pub const LINK_REL: &str = "noopener noreferrer nofollow";
+
+/// Rendered HTML by message ID and content checksum.
+type RenderedMap = HashMap<(MessageId, u32), Arc<String>>;
+
+/// Rendered messages by message ID and the checksum of the content they were rendered from, so an edited message is rendered again.
+static RENDERED: SyncMutex<Option<RenderedMap>> = SyncMutex::new(None);
+
+/// Formats messages can be rendered in besides their markdown source.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RenderFormat {
+    /// Sanitized HTML, see [`render_message`].
+    Html,
+}
+
+/// A message rendered as HTML, served by `/v3/message_rendered`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RenderedMessage {
+    pub message_id: MessageId,
+    pub html: String,
+}
+
+/// Renders the markdown content of a message as HTML that is safe to embed in other pages, see [`render_html`].
+/// Renderings are cached until the message is edited.
+///
+/// # Errors
+///
+/// This function returns [`crate::error::Error::RenderingDisabled`] if the server was built without the `markdown` feature.
+pub fn render_message(message: &Message) -> Result<Arc<String>> {
+    let key = (message.id, crc32fast::hash(message.content.as_bytes()));
+    if let Some(html) = RENDERED
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .get_or_insert_with(HashMap::new)
+        .get(&key)
+    {
+        return Ok(html.clone());
+    }
+    let html = Arc::new(render_html(&message.content)?);
+    let mut rendered = RENDERED.lock().unwrap_or_else(|err| err.into_inner());
+    let rendered = rendered.get_or_insert_with(HashMap::new);
+    if rendered.len() >= MAX_CACHED_RENDERINGS {
+        rendered.clear();
+    }
+    rendered.insert(key, html.clone());
+    Ok(html)
+}
+
+/// Renders CommonMark as HTML and sanitizes it: raw HTML in the markdown is shown as text, only [`ALLOWED_TAGS`] are kept, links can only use [`ALLOWED_URL_SCHEMES`] with an absolute URL and get [`LINK_REL`]. Images are removed so that embedding a message can not load anything.
+///
+/// # Errors
+///
+/// This function returns [`crate::error::Error::RenderingDisabled`] if the server was built without the `markdown` feature.
+#[cfg(feature = "markdown")]
+pub fn render_html(markdown: &str) -> Result<String> {
+    use pulldown_cmark::{Event, Options, Parser};
+
+    let parser = Parser::new_ext(
+        markdown,
+        Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TABLES,
+    )
+    .map(|event| match event {
+        Event::Html(html) => Event::Text(html),
+        event => event,
+    });
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, parser);
+    Ok(ammonia::Builder::default()
+        .tags(ALLOWED_TAGS.iter().copied().collect::<HashSet<&str>>())
+        .url_schemes(
+            ALLOWED_URL_SCHEMES
+                .iter()
+                .copied()
+                .collect::<HashSet<&str>>(),
+        )
+        .url_relative(ammonia::UrlRelative::Deny)
+        .link_rel(Some(LINK_REL))
+        .clean(&html)
+        .to_string())
+}
+
+/// Renders CommonMark as HTML, always fails because the server was built without the `markdown` feature.
+///
+/// # Errors
+///
+/// This function always returns [`Error::RenderingDisabled`].
+#[cfg(not(feature = "markdown"))]
+pub fn render_html(_markdown: &str) -> Result<String> {
+    Err(Error::RenderingDisabled)
+}
+
+#[cfg(all(test, feature = "markdown"))]
+mod test {
+    use super::{render_html, ALLOWED_TAGS, ALLOWED_URL_SCHEMES, LINK_REL};
+
+    /// Gets the start tags of sanitized HTML with their lowercase names and attributes. The sanitizer escapes every `<` in text, so each one that is left starts a tag.
+    fn start_tags(html: &str) -> Vec<(String, Vec<(String, String)>)> {
+        let mut tags = Vec::new();
+        let mut rest = html;
+        while let Some(start) = rest.find('<') {
+            rest = &rest[start + 1..];
+            if rest.starts_with('/') {
+                continue;
+            }
+            let end = rest
+                .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+                .unwrap_or(rest.len());
+            let name = rest[..end].to_lowercase();
+            rest = &rest[end..];
+            let mut attributes = Vec::new();
+            loop {
+                rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+                if rest.is_empty() || rest.starts_with('>') {
+                    break;
+                }
+                let end = rest
+                    .find(|c: char| c == '=' || c.is_whitespace() || c == '>')
+                    .unwrap_or(rest.len());
+                let attribute = rest[..end].to_lowercase();
+                rest = &rest[end..];
+                let mut value = String::new();
+                if let Some(quoted) = rest.strip_prefix("=\"") {
+                    let end = quoted.find('"').unwrap_or(quoted.len());
+                    value = quoted[..end].to_string();
+                    rest = quoted.get(end + 1..).unwrap_or_default();
+                }
+                attributes.push((attribute, value));
+            }
+            tags.push((name, attributes));
+        }
+        tags
+    }
+
+    #[test]
+    fn renders_markdown() {
+        assert_eq!(
+            render_html("**hi** _there_").unwrap(),
+            "<p><strong>hi</strong> <em>there</em></p>\n"
+        );
+        let link = render_html("[site](https://example.com)").unwrap();
+        assert!(link.contains(r#"href="https://example.com""#));
+        assert!(link.contains(&format!(r#"rel="{}""#, LINK_REL)));
+    }
+
+    #[test]
+    fn xss_payloads_are_neutralized() {
+        let payloads = [
+            "<script>alert(1)</script>",
+            "<img src=x onerror=alert(1)>",
+            "<a href=\"javascript:alert(1)\">x</a>",
+            "<iframe src=\"https://example.com\"></iframe>",
+            "<div onmouseover=\"alert(1)\">x</div>",
+            "[x](javascript:alert(1))",
+            "[x](JaVaScRiPt:alert(1))",
+            "[x](  javascript:alert(1)  )",
+            "[x](data:text/html;base64,PHNjcmlwdD5hbGVydCgxKTwvc2NyaXB0Pg==)",
+            "[x](vbscript:msgbox(1))",
+            "[x](/relative)",
+            "<javascript:alert(1)>",
+            "![x](https://example.com/pixel.png)",
+            "![x](javascript:alert(1))",
+            "[x]: javascript:alert(1)\n\n[x]",
+            "`<script>`<script>alert(1)</script>",
+            "> <svg onload=alert(1)>",
+            "| a |\n|---|\n| <script>alert(1)</script> |",
+        ];
+        let link = start_tags(&render_html("[x](https://example.com)").unwrap());
+        assert!(link.iter().any(|(tag, attributes)| tag == "a"
+            && attributes.contains(&("href".to_string(), "https://example.com".to_string()))));
+        for payload in payloads.iter() {
+            let html = render_html(payload).unwrap();
+            for (tag, attributes) in start_tags(&html) {
+                assert!(
+                    ALLOWED_TAGS.contains(&tag.as_str()),
+                    "{:?} rendered as {:?}",
+                    payload,
+                    html
+                );
+                for (attribute, value) in attributes {
+                    assert!(
+                        !attribute.starts_with("on") && attribute != "src",
+                        "{:?} rendered as {:?}",
+                        payload,
+                        html
+                    );
+                    if attribute == "href" {
+                        let value = value.trim().to_lowercase();
+                        assert!(
+                            ALLOWED_URL_SCHEMES
+                                .iter()
+                                .any(|scheme| value.starts_with(&format!("{}:", scheme))),
+                            "{:?} rendered as {:?}",
+                            payload,
+                            html
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
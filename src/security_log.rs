@@ -0,0 +1,461 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, SyncSender},
+    },
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{
+    field::{Field, Visit},
+    Event, Subscriber,
+};
+use tracing_subscriber::{layer::Context, Layer};
+
+use crate::{
+    config::SecurityLogConfig,
+    error::{Error, IoContext},
+    logging::AUDIT_TARGET,
+    Result,
+};
+
+/// Folder the segments of the security log are stored in.
+pub const SECURITY_LOG_FOLDER: &str = "data/security_log/";
+
+/// Name of the segment being written to, older segments have a number added (`security.log.1` is the newest of them).
+pub const SEGMENT_NAME: &str = "security.log";
+
+/// Maximum number of entries returned by one query, larger limits are lowered to this.
+pub const MAX_SECURITY_LOG_ENTRIES: usize = 500;
+
+/// Number of events that were dropped because the writer fell behind or could not write them, since the server started.
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// What kind of security event an entry is, set with a `kind` field on the audit event (see [`crate::audit`]).
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SecurityEventKind {
+    /// A client signed in.
+    Auth,
+    /// A client failed to sign in, for example because its signature was wrong or it took too long.
+    AuthFailure,
+    /// A client was disconnected for sending too much or opening too many connections.
+    RateLimited,
+    /// A server admin used their admin rights.
+    Admin,
+    /// A hub member was moderated or had their messages purged.
+    Moderation,
+    /// A confirmation token that was wrong, expired or already used was given.
+    TokenRejected,
+    /// Any other audit event, for example a change to a hub's settings.
+    #[default]
+    Other,
+}
+
+/// One line of the security log. Entries are written as JSON objects, one per line, and their fields are not renamed or removed so that tools reading the log keep working.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SecurityLogEntry {
+    /// Number of the entry, one higher than the entry before it. Used to page through the log.
+    pub seq: u64,
+    pub time: DateTime<Utc>,
+    pub kind: SecurityEventKind,
+    pub message: String,
+    /// ID of the user that did what the entry is about, if it was not `user`.
+    pub actor: Option<String>,
+    /// ID of the user the entry is about.
+    pub user: Option<String>,
+    /// The other fields of the audit event, formatted as text.
+    pub fields: BTreeMap<String, String>,
+}
+
+impl SecurityLogEntry {
+    /// Creates an empty entry, the writer numbers it.
+    fn new(time: DateTime<Utc>) -> Self {
+        Self {
+            seq: 0,
+            time,
+            kind: SecurityEventKind::Other,
+            message: String::new(),
+            actor: None,
+            user: None,
+            fields: BTreeMap::new(),
+        }
+    }
+
+    /// Sets a field of the entry from a field of an audit event.
+    fn record(&mut self, name: &str, value: String) {
+        match name {
+            "message" => self.message = value,
+            "kind" => {
+                self.kind =
+                    serde_json::from_value(serde_json::Value::String(value)).unwrap_or_default()
+            }
+            "actor" => self.actor = Some(value),
+            "user" => self.user = Some(value),
+            _ => {
+                self.fields.insert(name.to_string(), value);
+            }
+        }
+    }
+
+    /// Returns true if the entry is one that the query asks for, ignoring the page.
+    fn matches(&self, query: &SecurityLogQuery) -> bool {
+        query.from.is_none_or(|from| self.time >= from)
+            && query.to.is_none_or(|to| self.time <= to)
+            && query.kind.is_none_or(|kind| self.kind == kind)
+            && query.user.as_ref().is_none_or(|user| {
+                self.user.as_ref() == Some(user) || self.actor.as_ref() == Some(user)
+            })
+    }
+}
+
+/// Collects the fields of an audit event into a [`SecurityLogEntry`].
+struct EntryVisitor<'a>(&'a mut SecurityLogEntry);
+
+impl Visit for EntryVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.record(field.name(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0.record(field.name(), format!("{:?}", value));
+    }
+}
+
+/// Layer that sends audit events to the security log writer. It never waits for the writer, events that do not fit in its queue are dropped and counted.
+pub struct SecurityLogLayer {
+    sender: SyncSender<SecurityLogEntry>,
+}
+
+impl<S: Subscriber> Layer<S> for SecurityLogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if event.metadata().target() != AUDIT_TARGET {
+            return;
+        }
+        let mut entry = SecurityLogEntry::new(Utc::now());
+        event.record(&mut EntryVisitor(&mut entry));
+        if self.sender.try_send(entry).is_err() {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Appends entries to the newest segment of the security log, starting a new segment once it is full.
+pub struct SegmentWriter {
+    folder: PathBuf,
+    max_segment_bytes: u64,
+    segments: usize,
+    file: File,
+    size: u64,
+    next_seq: u64,
+}
+
+impl SegmentWriter {
+    /// Opens the newest segment in `folder`, creating the folder if needed. Numbering continues after the last entry in the log.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The folder does not exist and could not be created.
+    /// * The newest segment could not be read or opened.
+    pub fn open(folder: &Path, config: &SecurityLogConfig) -> Result<Self> {
+        std::fs::create_dir_all(folder).with_path(folder)?;
+        let mut next_seq = 0;
+        for (_, path) in segment_paths(folder)?.iter().rev() {
+            let content = std::fs::read_to_string(path).with_path(path)?;
+            if let Some(last) = content
+                .lines()
+                .rev()
+                .find_map(|line| serde_json::from_str::<SecurityLogEntry>(line).ok())
+            {
+                next_seq = last.seq + 1;
+                break;
+            }
+        }
+        let path = folder.join(SEGMENT_NAME);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_path(&path)?;
+        let mut size = file.metadata().with_path(&path)?.len();
+        // A line cut off when the server stopped is ended, so that the next entry starts on a line of its own.
+        if size > 0 && !std::fs::read(&path).with_path(&path)?.ends_with(b"\n") {
+            (&file).write_all(b"\n").with_path(&path)?;
+            size += 1;
+        }
+        Ok(Self {
+            folder: folder.to_path_buf(),
+            max_segment_bytes: config.max_segment_bytes,
+            segments: config.segments.max(1),
+            file,
+            size,
+            next_seq,
+        })
+    }
+
+    /// Numbers an entry and appends it to the log.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the entry could not be written or a new segment could not be started.
+    pub fn write(&mut self, mut entry: SecurityLogEntry) -> Result {
+        entry.seq = self.next_seq;
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        if self.size > 0 && self.size + line.len() as u64 > self.max_segment_bytes {
+            self.rotate()?;
+        }
+        let path = self.folder.join(SEGMENT_NAME);
+        self.file.write_all(line.as_bytes()).with_path(&path)?;
+        self.size += line.len() as u64;
+        self.next_seq += 1;
+        Ok(())
+    }
+
+    /// Starts a new segment, removing the oldest one if there are already [`SecurityLogConfig::segments`].
+    fn rotate(&mut self) -> Result {
+        for (number, path) in segment_paths(&self.folder)?.into_iter() {
+            if number + 1 >= self.segments {
+                std::fs::remove_file(&path).with_path(&path)?;
+            } else {
+                let to = self.folder.join(format!("{}.{}", SEGMENT_NAME, number + 1));
+                std::fs::rename(&path, &to).with_path(&path)?;
+            }
+        }
+        let path = self.folder.join(SEGMENT_NAME);
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_path(&path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Gets the segments in a folder with their numbers (0 for the one being written to), oldest first.
+fn segment_paths(folder: &Path) -> Result<Vec<(usize, PathBuf)>> {
+    let entries = match std::fs::read_dir(folder) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_path(folder),
+    };
+    let mut segments = Vec::new();
+    for entry in entries {
+        let path = entry.with_path(folder)?.path();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let number = if name == SEGMENT_NAME {
+            Some(0)
+        } else {
+            name.strip_prefix(SEGMENT_NAME)
+                .and_then(|suffix| suffix.strip_prefix('.'))
+                .and_then(|number| number.parse().ok())
+        };
+        if let Some(number) = number {
+            segments.push((number, path));
+        }
+    }
+    segments.sort_by_key(|(number, _)| std::cmp::Reverse(*number));
+    Ok(segments)
+}
+
+/// Starts the security log writer if it is enabled, returning the layer that feeds it audit events.
+/// The writer runs on its own thread so that nothing that logs an audit event waits for the disk.
+///
+/// # Errors
+///
+/// This function returns an error if the log could not be opened, see [`SegmentWriter::open`].
+pub fn start(config: &SecurityLogConfig) -> Result<Option<SecurityLogLayer>> {
+    if !config.enabled {
+        return Ok(None);
+    }
+    let mut writer = SegmentWriter::open(Path::new(SECURITY_LOG_FOLDER), config)?;
+    let (sender, receiver) = mpsc::sync_channel(config.queue_size.max(1));
+    std::thread::Builder::new()
+        .name("security-log".to_string())
+        .spawn(move || {
+            for entry in receiver {
+                if let Err(err) = writer.write(entry) {
+                    DROPPED.fetch_add(1, Ordering::Relaxed);
+                    tracing::error!("Failed to write to the security log: {}", err.chain());
+                }
+            }
+        })
+        .map_err(|err| Error::Other(err.to_string()))?;
+    Ok(Some(SecurityLogLayer { sender }))
+}
+
+/// Gets the number of events that were not written to the security log since the server started.
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// Filters for the entries of the security log, query parameters of `/v3/admin/security_log`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SecurityLogQuery {
+    /// Only entries at or after this time.
+    pub from: Option<DateTime<Utc>>,
+    /// Only entries at or before this time.
+    pub to: Option<DateTime<Utc>>,
+    /// Only entries of this kind.
+    pub kind: Option<SecurityEventKind>,
+    /// Only entries about or by this user.
+    pub user: Option<String>,
+    /// Only entries after the one with this number, the `next` of the previous page.
+    pub after: Option<u64>,
+    /// Maximum number of entries to return.
+    pub limit: Option<usize>,
+}
+
+/// Entries of the security log that match a query, oldest first.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SecurityLogPage {
+    pub entries: Vec<SecurityLogEntry>,
+    /// Number of the last entry if there are more, to be given as `after` to get the next page.
+    pub next: Option<u64>,
+    /// Number of events that were not written to the log since the server started, see [`dropped`].
+    pub dropped: u64,
+}
+
+/// Reads the entries of the security log in `folder` that match a query, one segment at a time so that reading stops once the page is full.
+/// Lines that can not be parsed, such as a line that was being written when the server stopped, are skipped.
+///
+/// # Errors
+///
+/// This function returns an error if a segment could not be read.
+pub async fn read(folder: &Path, query: &SecurityLogQuery) -> Result<SecurityLogPage> {
+    let limit = query
+        .limit
+        .unwrap_or(MAX_SECURITY_LOG_ENTRIES)
+        .min(MAX_SECURITY_LOG_ENTRIES);
+    let mut entries = Vec::new();
+    let mut more = false;
+    'segments: for (_, path) in segment_paths(folder)? {
+        let content = match tokio::fs::read_to_string(&path).await {
+            Ok(content) => content,
+            // The segment was rotated away while the log was being read.
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err).with_path(path),
+        };
+        for entry in content
+            .lines()
+            .filter_map(|line| serde_json::from_str::<SecurityLogEntry>(line).ok())
+        {
+            if query.after.is_some_and(|after| entry.seq <= after) || !entry.matches(query) {
+                continue;
+            }
+            if entries.len() == limit {
+                more = true;
+                break 'segments;
+            }
+            entries.push(entry);
+        }
+    }
+    Ok(SecurityLogPage {
+        next: if more {
+            entries.last().map(|entry| entry.seq)
+        } else {
+            None
+        },
+        entries,
+        dropped: dropped(),
+    })
+}
+
+/// Reads the entries of the server's security log that match a query, see [`read`]. Checking that the user is a server admin is up to the caller.
+///
+/// # Errors
+///
+/// This function returns an error if a segment could not be read.
+pub async fn query(query: &SecurityLogQuery) -> Result<SecurityLogPage> {
+    read(Path::new(SECURITY_LOG_FOLDER), query).await
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+
+    use super::{
+        read, segment_paths, SecurityEventKind, SecurityLogEntry, SecurityLogQuery, SegmentWriter,
+    };
+    use crate::config::SecurityLogConfig;
+
+    #[test]
+    fn entry_snapshot() {
+        let mut entry = SecurityLogEntry::new(Utc.with_ymd_and_hms(2021, 6, 1, 12, 0, 0).unwrap());
+        entry.record(
+            "message",
+            "WebSocket client failed to authenticate.".to_string(),
+        );
+        entry.record("kind", "auth_failure".to_string());
+        entry.record("user", "ABCD".to_string());
+        entry.record("connection", "42".to_string());
+        assert_eq!(
+            serde_json::to_string(&entry).unwrap(),
+            r#"{"seq":0,"time":"2021-06-01T12:00:00Z","kind":"auth_failure","message":"WebSocket client failed to authenticate.","actor":null,"user":"ABCD","fields":{"connection":"42"}}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn segments_rotate_and_pages_continue() {
+        let folder = std::env::temp_dir().join(format!("wicrs-security-log-{}", crate::new_id()));
+        let config = SecurityLogConfig {
+            max_segment_bytes: 1024,
+            segments: 3,
+            ..SecurityLogConfig::default()
+        };
+        let mut writer = SegmentWriter::open(&folder, &config).unwrap();
+        for n in 0..40 {
+            let mut entry = SecurityLogEntry::new(Utc::now());
+            entry.record("message", "Moderated hub member.".to_string());
+            if n % 2 == 0 {
+                entry.record("kind", "moderation".to_string());
+                entry.record("actor", "ADMIN".to_string());
+            }
+            writer.write(entry).unwrap();
+        }
+        assert_eq!(segment_paths(&folder).unwrap().len(), 3);
+
+        // Older segments were removed, the remaining entries are still numbered in order.
+        let query = SecurityLogQuery {
+            kind: Some(SecurityEventKind::Moderation),
+            user: Some("ADMIN".to_string()),
+            limit: Some(3),
+            ..SecurityLogQuery::default()
+        };
+        let first = read(&folder, &query).await.unwrap();
+        assert_eq!(first.entries.len(), 3);
+        assert!(first.entries[0].seq > 0);
+        assert!(first.entries.iter().all(|entry| entry.seq % 2 == 0));
+        let second = read(
+            &folder,
+            &SecurityLogQuery {
+                after: first.next,
+                ..query
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(second.entries[0].seq, first.entries[2].seq + 2);
+
+        // Numbering continues after the log is reopened.
+        drop(writer);
+        let mut writer = SegmentWriter::open(&folder, &config).unwrap();
+        writer.write(SecurityLogEntry::new(Utc::now())).unwrap();
+        let all = read(&folder, &SecurityLogQuery::default()).await.unwrap();
+        assert_eq!(all.entries.last().unwrap().seq, 40);
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+}
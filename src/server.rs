@@ -1,11 +1,20 @@
+#[cfg(feature = "search")]
+use crate::channel::Message;
 use crate::{
-    channel::{self, Message},
+    channel,
+    channel_events::{ChannelEvents, EventsSince},
     check_permission,
+    delivery_reports::{Intents, PendingDeliveries, MAX_ACKS_PER_COMMAND},
+    error::IoContext,
     hub::Hub,
-    websocket::ServerMessage,
-    Error, Result, ID,
+    instrumentation::{ActorStats, Instrumentation, InstrumentedAddr},
+    subscription_profiles::SubscriptionProfile,
+    unfurl::LinkPreview,
+    websocket::{CloseCode, ServerMessage},
+    ChannelId, Error, HubId, MessageId, Result, ID,
 };
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use futures::stream::SplitSink;
 use futures::SinkExt;
 use pgp::Message as OpenPGPMessage;
@@ -13,33 +22,48 @@ use pgp::SignedSecretKey;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::{HashMap, HashSet},
-    convert::TryFrom,
-    io::Read,
-    sync::Arc,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex as SyncMutex, MutexGuard as SyncMutexGuard,
+    },
+    time::{Duration, Instant},
 };
+#[cfg(feature = "search")]
+use std::{convert::TryFrom, io::Read};
+#[cfg(feature = "search")]
 use tantivy::{
     collector::TopDocs,
     directory::MmapDirectory,
     doc,
     query::QueryParser,
-    schema::{Field, Schema, FAST, STORED, TEXT},
-    Index, IndexReader, IndexWriter, LeasedItem, ReloadPolicy, Searcher,
+    schema::{Field, Schema, FAST, INDEXED, STORED, TEXT},
+    Index, IndexReader, IndexWriter, LeasedItem, ReloadPolicy, Searcher, Term,
 };
+#[cfg(feature = "search")]
 use tokio::io::AsyncWriteExt;
 use tokio::sync::{Mutex, RwLock};
+#[cfg(feature = "search")]
+use tracing::info;
+use tracing::{error, warn};
 use warp::ws::Message as WebSocketMessage;
 use warp::ws::WebSocket;
 use xactor::*;
 
+#[cfg(feature = "search")]
 use lazy_static::lazy_static;
 
 pub mod client_command {
-    use super::{message, Arc, Mutex, Result, SplitSink, WebSocket, WebSocketMessage, ID};
+    use super::{
+        message, Arc, ChannelId, HubId, Mutex, Result, SplitSink, WebSocket, WebSocketMessage,
+    };
 
-    /// Disconnects the client by unsubscribing them from everything (does not drop connection).
-    #[message(result = "u128")]
+    /// Registers a client connection for the given user, returns the ID of the connection.
+    /// Fails with [`crate::error::Error::TooManyConnections`] if the user already has as many connections as [`crate::config::LimitsConfig::max_connections_per_user`] allows.
+    #[message(result = "Result<u128>")]
     #[derive(Clone, Debug)]
     pub struct Connect {
+        pub user_id: String,
         pub websocket_writer: Arc<Mutex<SplitSink<WebSocket, WebSocketMessage>>>,
     }
     /// Disconnects the client by unsubscribing them from everything (does not drop connection).
@@ -49,56 +73,114 @@ pub mod client_command {
         pub connection_id: u128,
     }
     /// Subscribes the client to notifications on a hub (everything except for messages sent in channels in the hub).
+    /// A connection can be subscribed to at most [`crate::config::LimitsConfig::max_hub_subscriptions_per_connection`] hubs.
     #[message(result = "Result")]
     #[derive(Clone, Debug)]
     pub struct SubscribeHub {
         pub user_id: String,
-        pub hub_id: ID,
+        pub hub_id: HubId,
         pub connection_id: u128,
     }
     /// Unsubscribes the client from notifications in a hub, does not change channel subscriptions.
     #[message(result = "()")]
     #[derive(Debug, Clone)]
     pub struct UnsubscribeHub {
-        pub hub_id: ID,
+        pub hub_id: HubId,
         pub connection_id: u128,
     }
     /// Subscribes the client to notifications of new messages in the given channel.
+    /// With `with_snapshot` the connection is first sent a [`crate::websocket::ServerMessage::ChannelSnapshot`], by the server so that it arrives before any notification about the channel.
+    /// A connection can be subscribed to at most [`crate::config::LimitsConfig::max_channel_subscriptions_per_connection`] channels.
     #[message(result = "Result")]
     #[derive(Debug, Clone)]
     pub struct SubscribeChannel {
         pub user_id: String,
-        pub hub_id: ID,
-        pub channel_id: ID,
+        pub hub_id: HubId,
+        pub channel_id: ChannelId,
         pub connection_id: u128,
+        pub with_snapshot: bool,
     }
     /// Unsubscribes the client to notifications of new messages in the given channel.
     #[message(result = "()")]
     #[derive(Debug, Clone)]
     pub struct UnsubscribeChannel {
-        pub hub_id: ID,
-        pub channel_id: ID,
+        pub hub_id: HubId,
+        pub channel_id: ChannelId,
         pub connection_id: u128,
     }
     /// Notifies other clients subscribed to the given channel that the given user has started typing.
+    /// The user is considered to be typing until they stop typing or the connection is closed.
     #[message(result = "Result")]
     #[derive(Debug, Clone)]
     pub struct StartTyping {
         pub user_id: String,
-        pub hub_id: ID,
-        pub channel_id: ID,
+        pub hub_id: HubId,
+        pub channel_id: ChannelId,
+        pub connection_id: u128,
     }
     /// Notifies other clients subscribed to the given channel that the given user has stopped typing.
     #[message(result = "Result")]
     #[derive(Debug, Clone)]
     pub struct StopTyping {
         pub user_id: String,
-        pub hub_id: ID,
-        pub channel_id: ID,
+        pub hub_id: HubId,
+        pub channel_id: ChannelId,
+    }
+    /// Gets the hubs and the channels (as `(hub, channel)` pairs) that the client is subscribed to, both sorted.
+    #[message(result = "(Vec<HubId>, Vec<(HubId, ChannelId)>)")]
+    #[derive(Debug, Clone)]
+    pub struct QuerySubscriptions {
+        pub connection_id: u128,
+    }
+    /// Subscribes the client to the hubs and channels in the subscription profile of its user, see [`crate::subscription_profiles`].
+    /// Each hub is loaded once for all of its entries. Returns the entries that were not subscribed to, because the user can no longer read them or because the connection reached its subscription limits.
+    #[message(result = "Result<crate::subscription_profiles::SubscriptionProfile>")]
+    #[derive(Debug, Clone)]
+    pub struct ResumeProfile {
+        pub user_id: String,
+        pub connection_id: u128,
+    }
+    /// Gets the sorted IDs of the users that are currently typing in the given channel, the user asking needs to be able to read the channel.
+    #[message(result = "Result<Vec<String>>")]
+    #[derive(Debug, Clone)]
+    pub struct QueryTyping {
+        pub user_id: String,
+        pub hub_id: HubId,
+        pub channel_id: ChannelId,
+    }
+    /// Sets the optional notifications the connection gets, see [`crate::delivery_reports::Intents`].
+    #[message(result = "()")]
+    #[derive(Debug, Clone)]
+    pub struct SetIntents {
+        pub connection_id: u128,
+        pub intents: crate::delivery_reports::Intents,
+    }
+    /// Acknowledges that messages in a channel were received, the first acknowledgement of a message by a user other than its sender is reported to the sender, see [`crate::delivery_reports`].
+    /// Acknowledgements from connections that are not subscribed to the channel are ignored.
+    /// Fails with [`crate::error::Error::TooManyAcks`] if more than [`crate::delivery_reports::MAX_ACKS_PER_COMMAND`] messages are given.
+    #[message(result = "Result")]
+    #[derive(Debug, Clone)]
+    pub struct AckMessages {
+        pub user_id: String,
+        pub connection_id: u128,
+        pub hub_id: HubId,
+        pub channel_id: ChannelId,
+        pub message_ids: Vec<crate::MessageId>,
+    }
+    /// Gets the message events of a channel after the given sequence number, the user asking needs to be able to read the channel, see [`crate::channel_events`].
+    #[message(result = "Result<crate::channel_events::EventsSince>")]
+    #[derive(Debug, Clone)]
+    pub struct EventsSince {
+        pub user_id: String,
+        pub hub_id: HubId,
+        pub channel_id: ChannelId,
+        pub epoch: u64,
+        pub seq: u64,
     }
 }
 
 /// Fields for the Tantivy message schema.
+#[cfg(feature = "search")]
 #[derive(Clone)]
 pub struct MessageSchemaFields {
     pub content: Field,
@@ -109,65 +191,196 @@ pub struct MessageSchemaFields {
 #[message(result = "Result")]
 #[derive(Clone, Debug)]
 pub struct NewMessageForIndex {
-    pub hub_id: ID,
-    pub channel_id: ID,
+    pub hub_id: HubId,
+    pub channel_id: ChannelId,
+    pub message: channel::Message,
+}
+
+/// A [`NewMessageForIndex`] that is sent without waiting for the message to be indexed, the [`Server`] uses it so that indexing can fall behind without slowing down delivery.
+/// Messages that could not be indexed are logged and counted as failed in the message server's [`ActorStats`].
+#[message]
+#[derive(Clone, Debug)]
+pub struct QueueMessageForIndex(pub NewMessageForIndex);
+
+/// Message to tell the message server that a message in a channel was edited, the indexed content of the message is replaced.
+#[message(result = "Result")]
+#[derive(Clone, Debug)]
+pub struct EditedMessageForIndex {
+    pub hub_id: HubId,
+    pub channel_id: ChannelId,
     pub message: channel::Message,
 }
 
+/// Message to tell the message server that messages were removed from a channel, they are removed from its index.
+#[message(result = "Result")]
+#[derive(Clone, Debug)]
+pub struct DeletedMessagesForIndex {
+    pub hub_id: HubId,
+    pub channel_id: ChannelId,
+    pub message_ids: Vec<MessageId>,
+}
+
+/// Message to tell the message server that a channel was deleted, its index is closed and its data folder is removed, both where it was and where [`crate::api::delete_channel`] moved it to.
+#[message(result = "Result")]
+#[derive(Clone, Debug)]
+pub struct ChannelDeletedForIndex {
+    pub hub_id: HubId,
+    pub channel_id: ChannelId,
+}
+
+/// Message to tell the message server that a hub was deleted, the indexes of its channels are closed and its data folder is removed.
+#[message(result = "Result")]
+#[derive(Clone, Debug)]
+pub struct HubDeletedForIndex {
+    pub hub_id: HubId,
+}
+
+/// Sent to a [`MessageServer`] by itself every [`crate::TANTIVY_COMMIT_INTERVAL`] seconds to commit the messages that are still waiting in the index writers.
+#[cfg(feature = "search")]
+#[message]
+#[derive(Clone, Debug)]
+struct CommitPendingMessages;
+
+/// Sent to a [`MessageServer`] by itself every [`crate::RECENT_CHANNELS_SAVE_INTERVAL`] seconds to save its [`RecentChannels`].
+#[cfg(feature = "search")]
+#[message]
+#[derive(Clone, Debug)]
+struct SaveRecentChannels;
+
 /// Command for a [`MessageServer`] to search the given channel with a query.
-#[message(result = "Result<Vec<ID>>")]
+#[message(result = "Result<Vec<MessageId>>")]
 #[derive(Clone, Debug)]
 pub struct SearchMessageIndex {
     /// ID of the hub the channel is in.
-    pub hub_id: ID,
+    pub hub_id: HubId,
     /// ID of the channel in which to perform the search.
-    pub channel_id: ID,
+    pub channel_id: ChannelId,
     /// Maximum number of results to return.
     pub limit: usize,
     /// Query string.
     pub query: String,
+    /// Only messages sent at or after this time are returned, for users that can not read the whole history of the channel (see [`Hub::history_start`]).
+    pub not_before: Option<DateTime<Utc>>,
 }
 
+/// Number of results looked at when a search is limited by [`SearchMessageIndex::not_before`], the index does not know when messages were sent so older results are left out afterwards.
+pub const MAX_LIMITED_SEARCH_CANDIDATES: usize = 1000;
+
 /// Types of updates that trigger [`ServerNotification::HubUpdated`]
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum HubUpdateType {
     HubDeleted,
     HubRenamed,
     HubDescriptionUpdated,
-    UserJoined(ID),
-    UserLeft(ID),
-    UserBanned(ID),
-    UserMuted(ID),
-    UserUnmuted(ID),
-    UserUnbanned(ID),
-    UserKicked(ID),
-    UserHubPermissionChanged(ID),
-    UserChannelPermissionChanged(ID, ID),
-    MemberNicknameChanged(ID),
-    ChannelCreated(ID),
-    ChannelDeleted(ID),
-    ChannelRenamed(ID),
-    ChannelDescriptionUpdated(ID),
+    UserJoined(String),
+    UserLeft(String),
+    UserBanned(String),
+    UserMuted(String),
+    UserUnmuted(String),
+    UserUnbanned(String),
+    UserKicked(String),
+    UserHubPermissionChanged(String),
+    UserChannelPermissionChanged(String, ChannelId),
+    /// The member's nickname changed from the first to the second value, `None` for no nickname.
+    MemberNicknameChanged(String, Option<String>, Option<String>),
+    ChannelCreated(ChannelId),
+    ChannelDeleted(ChannelId),
+    ChannelRenamed(ChannelId),
+    ChannelDescriptionUpdated(ChannelId),
+    HubIconUpdated,
+    HubBannerUpdated,
+    HubPreviewUpdated,
+    GroupMentionableChanged(ID),
+    NicknamePolicyChanged,
+    /// The channel permission overrides of every member and group in the channel were replaced, see [`crate::api::copy_channel_permissions`].
+    ChannelPermissionsChanged(ChannelId),
+    /// The color, hoisting or position of a group changed, positions of other groups may have changed with it, see [`crate::group_display`].
+    GroupDisplayChanged(ID),
+    /// The hub's leaderboard was enabled or disabled, see [`crate::leaderboard`].
+    LeaderboardChanged,
+    /// The restrictions on the content of the hub's messages changed, see [`crate::content_policy`].
+    ContentPolicyChanged,
+    /// The hub was given to the member with this ID, the previous owner is still a member.
+    OwnerChanged(String),
+    /// The hub permissions of a group were replaced, see [`crate::api::apply_group_preset`].
+    GroupPermissionsChanged(ID),
+}
+
+impl HubUpdateType {
+    /// Gets the ID of the member whose membership or nickname the update changed, see [`crate::member_map`].
+    pub fn changed_member(&self) -> Option<&str> {
+        match self {
+            HubUpdateType::UserJoined(user_id)
+            | HubUpdateType::UserLeft(user_id)
+            | HubUpdateType::UserBanned(user_id)
+            | HubUpdateType::UserKicked(user_id)
+            | HubUpdateType::MemberNicknameChanged(user_id, _, _) => Some(user_id),
+            _ => None,
+        }
+    }
+
+    /// Checks if the update can take away a member's access to channels, after these updates the [`Server`] checks the channel subscriptions in the hub again.
+    pub fn may_revoke_access(&self) -> bool {
+        matches!(
+            self,
+            HubUpdateType::HubDeleted
+                | HubUpdateType::UserLeft(_)
+                | HubUpdateType::UserBanned(_)
+                | HubUpdateType::UserKicked(_)
+                | HubUpdateType::UserHubPermissionChanged(_)
+                | HubUpdateType::UserChannelPermissionChanged(_, _)
+                | HubUpdateType::ChannelDeleted(_)
+                | HubUpdateType::ChannelPermissionsChanged(_)
+                | HubUpdateType::OwnerChanged(_)
+                | HubUpdateType::GroupPermissionsChanged(_)
+        )
+    }
 }
 
 /// Message to notify the server of a change made externally, usually used so the server can notify clients.
+/// The [`Server`] also receives the notifications given to [`publish`].
 #[message(result = "()")]
 #[derive(Debug, Clone)]
 pub enum ServerNotification {
-    NewMessage(ID, ID, ID, String, channel::Message),
-    HubUpdated(ID, HubUpdateType),
+    NewMessage(HubId, ChannelId, MessageId, String, channel::Message),
+    /// A message was edited, holds the new version of the message.
+    MessageEdited(HubId, ChannelId, MessageId, String, channel::Message),
+    /// A hub was changed, the last field is the version of the hub after the change.
+    HubUpdated(HubId, HubUpdateType, u64),
+    /// Messages were removed from a channel, see [`crate::message_purge`].
+    MessagesDeleted(HubId, ChannelId, Vec<MessageId>),
+    /// A message was moved into a channel, see [`crate::message_move`]. Its removal from the channel it was in is sent as [`ServerNotification::MessagesDeleted`].
+    MessageMoved(HubId, ChannelId, MessageId, String, channel::Message),
+    /// Previews of the links in a message were created, see [`crate::unfurl`].
+    LinkPreviews(HubId, ChannelId, MessageId, Vec<LinkPreview>),
+    /// The draft of a user in a channel changed, `None` if it was removed, see [`crate::drafts`].
+    DraftUpdated(String, HubId, ChannelId, Option<String>),
+    /// A user asked to delete a hub, holds the time the deletion token expires, see [`crate::hub_deletion`].
+    HubDeletionRequested(String, HubId, DateTime<Utc>),
 }
 
 /// Tells the [`Server`] to get an address to it's [`MessageServer`].
-#[message(result = "Addr<MessageServer>")]
+#[message(result = "InstrumentedAddr<MessageServer>")]
 #[derive(Clone, Copy)]
 pub struct GetMessageServer;
 
+/// Closes every client connection with the given code, used when the server is shutting down.
+#[message(result = "()")]
+#[derive(Clone, Copy, Debug)]
+pub struct CloseConnections(pub CloseCode);
+
+/// Checks that the [`Server`] is still handling messages.
+#[message(result = "()")]
+#[derive(Clone, Copy)]
+pub struct Ping;
+
+#[cfg(feature = "search")]
 lazy_static! {
     static ref MESSAGE_SCHEMA: Schema = {
         let mut schema_builder = Schema::builder();
         schema_builder.add_text_field("content", TEXT);
-        schema_builder.add_bytes_field("id", STORED | FAST);
+        // Indexed so that the document of an edited message can be deleted.
+        schema_builder.add_bytes_field("id", STORED | FAST | INDEXED);
         schema_builder.build()
     };
     static ref MESSAGE_SCHEMA_FIELDS: MessageSchemaFields = MessageSchemaFields {
@@ -181,6 +394,7 @@ lazy_static! {
 }
 
 /// Adds a message to a Tantivy [`IndexWriter`].
+#[cfg(feature = "search")]
 pub fn add_message_to_writer(writer: &mut IndexWriter, message: channel::Message) -> Result {
     writer.add_document(doc!(
         MESSAGE_SCHEMA_FIELDS.id => bincode::serialize(&message.id)?,
@@ -190,121 +404,515 @@ pub fn add_message_to_writer(writer: &mut IndexWriter, message: channel::Message
 }
 
 /// Logs the given message ID to a file, should be called after any Tantivy commits.
-async fn log_last_message(hub_id: ID, channel_id: ID, message_id: ID) -> Result {
-    let log_path_string = format!(
-        "{}/{:x}/{:x}/log",
-        crate::hub::HUB_DATA_FOLDER,
-        hub_id.as_u128(),
-        channel_id.as_u128()
-    );
-    tokio::fs::write(log_path_string, &message_id.as_u128().to_ne_bytes()).await?;
+#[cfg(feature = "search")]
+async fn log_last_message(hub_id: HubId, channel_id: ChannelId, message_id: MessageId) -> Result {
+    let log_path = crate::paths::channel_log_file(hub_id, channel_id);
+    tokio::fs::write(&log_path, &message_id.as_u128().to_ne_bytes())
+        .await
+        .with_path(log_path)?;
     Ok(())
 }
 
-async fn log_if_nologs(hub_id: ID, channel_id: ID, message_id: ID) -> Result {
-    let mut file = tokio::fs::OpenOptions::new()
+#[cfg(feature = "search")]
+async fn log_if_nologs(hub_id: HubId, channel_id: ChannelId, message_id: MessageId) -> Result {
+    let log_path = crate::paths::channel_log_file(hub_id, channel_id);
+    let mut file = match tokio::fs::OpenOptions::new()
         .write(true)
         .create_new(true)
-        .open(format!(
-            "{}/{:x}/{:x}/log",
-            crate::hub::HUB_DATA_FOLDER,
-            hub_id.as_u128(),
-            channel_id.as_u128()
-        ))
-        .await?;
-    file.write(&message_id.as_u128().to_ne_bytes()).await?;
+        .open(&log_path)
+        .await
+    {
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => return Ok(()),
+        result => result.with_path(&log_path)?,
+    };
+    file.write(&message_id.as_u128().to_ne_bytes())
+        .await
+        .with_path(log_path)?;
+    Ok(())
+}
+
+/// Rebuilds the Tantivy index of a channel from its message files, returns the number of messages that were indexed.
+/// Must not be used while a [`MessageServer`] has the channel's index open. Fails with [`Error::SearchDisabled`] if search is turned off because data is encrypted, see [`crate::encryption::search_enabled`].
+#[cfg(feature = "search")]
+pub async fn rebuild_index(channel: &channel::Channel) -> Result<usize> {
+    if !crate::encryption::search_enabled() {
+        return Err(Error::SearchDisabled);
+    }
+    let dir_path = &crate::paths::channel_index_dir(channel.hub_id, channel.id);
+    if dir_path.is_dir() {
+        tokio::fs::remove_dir_all(dir_path)
+            .await
+            .with_path(dir_path)?;
+    }
+    tokio::fs::create_dir_all(dir_path)
+        .await
+        .with_path(dir_path)?;
+    let index = Index::open_or_create(MmapDirectory::open(dir_path)?, MESSAGE_SCHEMA.clone())?;
+    let mut writer = index.writer(50_000_000)?;
+    let messages: Vec<Message> = channel
+        .get_all_messages()
+        .await
+        .iter()
+        .filter_map(|signed_message| Message::try_from(signed_message).ok())
+        .collect();
+    let count = messages.len();
+    let last_id = messages.last().map(|last| last.id);
+    for message in messages {
+        add_message_to_writer(&mut writer, message)?;
+    }
+    writer.commit()?;
+    if let Some(last_id) = last_id {
+        log_last_message(channel.hub_id, channel.id, last_id).await?;
+    }
+    Ok(count)
+}
+
+/// Rebuilding search indexes is not possible without the `search` feature.
+#[cfg(not(feature = "search"))]
+pub async fn rebuild_index(_channel: &channel::Channel) -> Result<usize> {
+    Err(Error::SearchDisabled)
+}
+
+#[cfg(feature = "search")]
+pub type IndexMap = HashMap<(HubId, ChannelId), Index>;
+#[cfg(feature = "search")]
+pub type IndexWriterMap = HashMap<(HubId, ChannelId), IndexWriter>;
+#[cfg(feature = "search")]
+pub type IndexReaderMap = HashMap<(HubId, ChannelId), IndexReader>;
+#[cfg(feature = "search")]
+pub type PendingMessageMap = HashMap<(HubId, ChannelId), PendingMessages>;
+
+/// Messages that were added to the index writer of a channel but have not been commited yet.
+#[cfg(feature = "search")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PendingMessages {
+    /// Number of messages waiting to be commited, saturates instead of overflowing.
+    pub count: usize,
+    /// ID of the last message added to the index writer.
+    pub last: MessageId,
+}
+
+#[cfg(feature = "search")]
+impl PendingMessages {
+    /// Creates an empty set of pending messages, `last` is the ID of the last message that was added to the index.
+    pub fn new(last: MessageId) -> Self {
+        Self { count: 0, last }
+    }
+
+    /// Records that a message was added to the index writer, returns true if the writer should be commited.
+    /// The writer should be commited as soon as `threshold` messages are pending, so thresholds of `0` and `1` both commit every message.
+    /// The count saturates at `usize::MAX`, so with that threshold messages are only commited when the channel is searched or the server stops.
+    pub fn push(&mut self, id: MessageId, threshold: usize) -> bool {
+        self.count = self.count.saturating_add(1);
+        self.last = id;
+        self.count >= threshold
+    }
+
+    /// Marks all of the pending messages as commited.
+    pub fn commited(&mut self) {
+        self.count = 0;
+    }
+}
+
+/// Path of the file that the list of recently active channels is saved to, see [`RecentChannels`].
+#[cfg(feature = "search")]
+pub const RECENT_CHANNELS_PATH: &str = "data/recent_channels";
+
+/// Maximum number of channels kept in the saved list of recently active channels, the least recently active are dropped first.
+#[cfg(feature = "search")]
+pub const MAX_RECENT_CHANNELS: usize = 1024;
+
+/// Channels that recently had messages or searches and when they last did, saved by the [`MessageServer`] so that [`warm_up_indexes`] knows which indexes to open after a restart.
+#[cfg(feature = "search")]
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct RecentChannels(pub HashMap<(HubId, ChannelId), DateTime<Utc>>);
+
+#[cfg(feature = "search")]
+impl RecentChannels {
+    /// Loads the saved list, a list that does not exist or can not be read is empty.
+    pub async fn load() -> Self {
+        tokio::fs::read(RECENT_CHANNELS_PATH)
+            .await
+            .ok()
+            .and_then(|bytes| bincode::deserialize(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    /// Saves the list, keeping only the [`MAX_RECENT_CHANNELS`] most recently active channels.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the list could not be serialized or written.
+    pub async fn save(&mut self) -> Result {
+        if self.0.len() > MAX_RECENT_CHANNELS {
+            let kept: HashSet<(HubId, ChannelId)> = self
+                .most_recent(MAX_RECENT_CHANNELS, DateTime::UNIX_EPOCH)
+                .into_iter()
+                .collect();
+            self.0.retain(|key, _| kept.contains(key));
+        }
+        let bytes = bincode::serialize(self)?;
+        tokio::fs::write(RECENT_CHANNELS_PATH, bytes)
+            .await
+            .with_path(RECENT_CHANNELS_PATH)
+    }
+
+    /// Records that a channel is active now.
+    pub fn touch(&mut self, hub_id: HubId, channel_id: ChannelId) {
+        self.0.insert((hub_id, channel_id), Utc::now());
+    }
+
+    /// Gets up to `count` channels that were active at or after `since`, the most recently active first.
+    pub fn most_recent(&self, count: usize, since: DateTime<Utc>) -> Vec<(HubId, ChannelId)> {
+        let mut channels: Vec<_> = self.0.iter().filter(|(_, last)| **last >= since).collect();
+        channels.sort_by(|a, b| b.1.cmp(a.1));
+        channels
+            .into_iter()
+            .take(count)
+            .map(|(key, _)| *key)
+            .collect()
+    }
+}
+
+/// Index of a channel that has been opened and caught up with the channel's messages, see [`open_index`].
+#[cfg(feature = "search")]
+struct OpenedIndex {
+    index: Index,
+    reader: IndexReader,
+    writer: IndexWriter,
+}
+
+/// How far the index of a channel has been set up, see [`index_setup_slot`].
+#[cfg(feature = "search")]
+enum IndexSetup {
+    /// Nothing has opened the index yet.
+    Pending,
+    /// Opened by [`warm_up_indexes`], waiting for the [`MessageServer`] to take it.
+    Warmed(Box<OpenedIndex>),
+    /// The [`MessageServer`] has the index.
+    Taken,
+}
+
+/// Index setup states by hub and channel ID.
+#[cfg(feature = "search")]
+type IndexSetupMap = HashMap<(HubId, ChannelId), Arc<Mutex<IndexSetup>>>;
+
+/// Setup state of the index of each channel, see [`index_setup_slot`].
+#[cfg(feature = "search")]
+static INDEX_SETUP: std::sync::Mutex<Option<IndexSetupMap>> = std::sync::Mutex::new(None);
+
+/// Gets the setup state of a channel's index. It is locked while the index is opened, so [`warm_up_indexes`] and the [`MessageServer`] never open the same index at the same time.
+#[cfg(feature = "search")]
+fn index_setup_slot(hub_id: HubId, channel_id: ChannelId) -> Arc<Mutex<IndexSetup>> {
+    INDEX_SETUP
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .get_or_insert_with(HashMap::new)
+        .entry((hub_id, channel_id))
+        .or_insert_with(|| Arc::new(Mutex::new(IndexSetup::Pending)))
+        .clone()
+}
+
+/// Forgets the setup state of the indexes for which `forget` returns true, dropping any index that was warmed up but not taken yet.
+#[cfg(feature = "search")]
+fn forget_index_setup<F: Fn(&(HubId, ChannelId)) -> bool>(forget: F) {
+    if let Some(slots) = INDEX_SETUP
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .as_mut()
+    {
+        slots.retain(|key, _| !forget(key));
+    }
+}
+
+/// Whether unreadable indexes are rebuilt when they are opened, see [`set_auto_reindex_on_corruption`].
+#[cfg(feature = "search")]
+static AUTO_REINDEX_ON_CORRUPTION: std::sync::atomic::AtomicBool =
+    std::sync::atomic::AtomicBool::new(true);
+
+/// Sets whether an index that can not be opened, even after removing stale lock files, is moved aside and rebuilt from the channel's messages. Called by the server on startup with its configuration.
+#[cfg(feature = "search")]
+pub fn set_auto_reindex_on_corruption(enabled: bool) {
+    AUTO_REINDEX_ON_CORRUPTION.store(enabled, Ordering::Relaxed);
+}
+
+/// Names of the lock files Tantivy keeps in an index directory.
+#[cfg(feature = "search")]
+const TANTIVY_LOCK_FILES: &[&str] = &[".tantivy-writer.lock", ".tantivy-meta.lock"];
+
+/// Removes the lock files left in an index directory by a process that was killed, returns true if there were any.
+/// Only one process can use the data directory (see [`crate::maintenance::DataLock`]) and it opens each index once (see [`index_setup_slot`]), so lock files found while opening an index are stale.
+#[cfg(feature = "search")]
+async fn remove_stale_locks(dir_path: &std::path::Path) -> Result<bool> {
+    let mut removed = false;
+    for name in TANTIVY_LOCK_FILES {
+        let path = dir_path.join(name);
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => {
+                warn!("Removed stale lock file {}.", path.display());
+                removed = true;
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err).with_path(path),
+        }
+    }
+    Ok(removed)
+}
+
+/// Opens the index in a directory, creating it if the directory is empty.
+#[cfg(feature = "search")]
+fn open_index_dir(dir_path: &std::path::Path) -> Result<Index> {
+    Ok(Index::open_or_create(
+        MmapDirectory::open(dir_path)?,
+        MESSAGE_SCHEMA.clone(),
+    )?)
+}
+
+/// Recovers the index of a channel that could not be opened, for example because the server was killed while commiting or the index has an older schema (before message IDs were indexed).
+/// Stale lock files are removed first. If the index still can not be opened and [`set_auto_reindex_on_corruption`] is enabled, it is moved aside (to a directory next to it named after the time) and rebuilt from the channel's message files.
+/// Each recovery is counted in the instrumentation, see [`crate::instrumentation::record_index_recovery`].
+#[cfg(feature = "search")]
+async fn recover_index(
+    hub_id: HubId,
+    channel_id: ChannelId,
+    dir_path: &std::path::Path,
+    open_error: Error,
+) -> Result<Index> {
+    warn!(
+        "Unable to open the search index of channel {} in hub {}: {}",
+        channel_id,
+        hub_id,
+        open_error.chain()
+    );
+    if remove_stale_locks(dir_path).await? {
+        if let Ok(index) = open_index_dir(dir_path) {
+            info!(
+                "Opened the search index of channel {} in hub {} after removing stale lock files.",
+                channel_id, hub_id
+            );
+            crate::instrumentation::record_index_recovery();
+            return Ok(index);
+        }
+    }
+    if !AUTO_REINDEX_ON_CORRUPTION.load(Ordering::Relaxed) {
+        error!(
+            "The search index of channel {} in hub {} can not be opened, move {} away or run `reindex --hub {} --channel {}` to rebuild it.",
+            channel_id,
+            hub_id,
+            dir_path.display(),
+            hub_id,
+            channel_id
+        );
+        return Err(open_error);
+    }
+    let mut quarantine = dir_path.as_os_str().to_owned();
+    quarantine.push(format!(".corrupt-{}", Utc::now().timestamp()));
+    tokio::fs::rename(dir_path, &quarantine)
+        .await
+        .with_path(&quarantine)?;
+    warn!(
+        "Moved the search index of channel {} in hub {} to {}, rebuilding it from the channel's messages.",
+        channel_id,
+        hub_id,
+        std::path::Path::new(&quarantine).display()
+    );
+    let count = rebuild_index(&channel::Channel::new(String::new(), channel_id, hub_id)).await?;
+    info!(
+        "Rebuilt the search index of channel {} in hub {} from {} messages.",
+        channel_id, hub_id, count
+    );
+    crate::instrumentation::record_index_recovery();
+    open_index_dir(dir_path)
+}
+
+/// Opens the Tantivy index for a given channel, also makes sure that the index is up to date by commiting any messages sent after the last message sent (logged by [`log_last_message`]).
+/// Indexes that can not be opened are recovered, see [`recover_index`].
+#[cfg(feature = "search")]
+async fn open_index(hub_id: HubId, channel_id: ChannelId) -> Result<OpenedIndex> {
+    let dir_path = &crate::paths::channel_index_dir(hub_id, channel_id);
+    if !dir_path.is_dir() {
+        tokio::fs::create_dir_all(dir_path)
+            .await
+            .with_path(dir_path)?;
+    }
+    let index = match open_index_dir(dir_path) {
+        Ok(index) => index,
+        Err(err) => recover_index(hub_id, channel_id, dir_path, err).await?,
+    };
+    let reader = index
+        .reader_builder()
+        .reload_policy(ReloadPolicy::OnCommit)
+        .try_into()?;
+    let mut writer = match index.writer(50_000_000) {
+        Ok(writer) => writer,
+        Err(tantivy::TantivyError::LockFailure(..)) if remove_stale_locks(dir_path).await? => {
+            crate::instrumentation::record_index_recovery();
+            index.writer(50_000_000)?
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let log_path = &crate::paths::channel_log_file(hub_id, channel_id);
+    if log_path.is_file() {
+        let mut buf: [u8; 16] = [0; 16];
+        tokio::fs::read(log_path)
+            .await
+            .with_path(log_path)?
+            .as_slice()
+            .read_exact(&mut buf)
+            .with_path(log_path)?;
+        let last_id = MessageId::from_u128(u128::from_le_bytes(buf));
+        let hub = Hub::load(hub_id).await?;
+        if let Some(channel) = hub.channels.get(&channel_id) {
+            let messages: Vec<Message> = channel
+                .get_all_messages_from(last_id)
+                .await
+                .iter()
+                .filter_map(|signed_message| Message::try_from(signed_message).ok())
+                .collect();
+            let last_id = messages.last().map(|last| last.id);
+            for message in messages {
+                add_message_to_writer(&mut writer, message)?;
+            }
+            writer.commit()?;
+            if let Some(last_id) = last_id {
+                log_last_message(hub_id, channel_id, last_id).await?;
+            }
+            reader.reload()?;
+        }
+    }
+    Ok(OpenedIndex {
+        index,
+        reader,
+        writer,
+    })
+}
+
+/// Opens the index of a channel for [`warm_up_indexes`], unless the [`MessageServer`] already set it up. Channels that no longer exist are skipped.
+#[cfg(feature = "search")]
+async fn warm_up_index(hub_id: HubId, channel_id: ChannelId) -> Result {
+    if !Hub::load(hub_id).await?.channels.contains_key(&channel_id) {
+        return Ok(());
+    }
+    let slot = index_setup_slot(hub_id, channel_id);
+    let mut setup = slot.lock().await;
+    if let IndexSetup::Pending = *setup {
+        *setup = IndexSetup::Warmed(Box::new(open_index(hub_id, channel_id).await?));
+    }
     Ok(())
 }
 
-pub type IndexMap = HashMap<(ID, ID), Index>;
-pub type IndexWriterMap = HashMap<(ID, ID), IndexWriter>;
-pub type IndexReaderMap = HashMap<(ID, ID), IndexReader>;
-pub type PendingMessageMap = HashMap<(ID, ID), (u8, ID)>;
+/// Opens the search indexes of the channels that were most recently active (see [`RecentChannels`]) before they are needed, at most [`crate::INDEX_WARM_UP_CONCURRENCY`] at a time.
+/// The opened indexes are taken by the [`MessageServer`] the first time it needs them, a channel that gets a message or search while its index is being opened waits for it instead of opening it again.
+#[cfg(feature = "search")]
+pub async fn warm_up_indexes(config: crate::config::SearchConfig) {
+    use futures::StreamExt;
+
+    if config.warm_up_channels == 0 || !crate::encryption::search_enabled() {
+        return;
+    }
+    let since = Utc::now() - chrono::Duration::hours(config.warm_up_window_hours.into());
+    let channels = RecentChannels::load()
+        .await
+        .most_recent(config.warm_up_channels, since);
+    let count = channels.len();
+    futures::stream::iter(channels)
+        .for_each_concurrent(
+            crate::INDEX_WARM_UP_CONCURRENCY,
+            |(hub_id, channel_id)| async move {
+                if let Err(err) = warm_up_index(hub_id, channel_id).await {
+                    warn!(
+                        "Unable to warm up the index of channel {:x} in hub {:x}: {}",
+                        channel_id.as_u128(),
+                        hub_id.as_u128(),
+                        err.chain()
+                    );
+                }
+            },
+        )
+        .await;
+    info!("Warmed up the search indexes of {} channels.", count);
+}
 
+/// Server that manages the Tantivy search indexes of channels, without the `search` feature it only rejects searches.
 pub struct MessageServer {
+    #[cfg(feature = "search")]
     indexes: IndexMap,
+    #[cfg(feature = "search")]
     index_writers: IndexWriterMap,
+    #[cfg(feature = "search")]
     index_readers: IndexReaderMap,
+    #[cfg(feature = "search")]
     pending_messages: PendingMessageMap,
+    #[cfg(feature = "search")]
+    recent_channels: RecentChannels,
+    /// Whether [`MessageServer::recent_channels`] changed since it was last saved.
+    #[cfg(feature = "search")]
+    recent_channels_changed: bool,
+    stats: Arc<ActorStats>,
 }
 
 impl MessageServer {
-    pub fn new() -> Self {
+    /// Creates a new message server that records how long it takes to handle messages in `stats`.
+    pub fn new(stats: Arc<ActorStats>) -> Self {
         Self {
+            #[cfg(feature = "search")]
             indexes: HashMap::new(),
+            #[cfg(feature = "search")]
             index_writers: HashMap::new(),
+            #[cfg(feature = "search")]
             index_readers: HashMap::new(),
+            #[cfg(feature = "search")]
             pending_messages: HashMap::new(),
+            #[cfg(feature = "search")]
+            recent_channels: RecentChannels::default(),
+            #[cfg(feature = "search")]
+            recent_channels_changed: false,
+            stats,
         }
     }
+}
 
-    /// Sets up the Tantivy index for a given channel, also makes sure that the index is up to date by commiting any messages sent after the last message sent (logged by [`log_last_message`]).
-    async fn setup_index(&mut self, hub_id: ID, channel_id: ID) -> Result {
-        let dir_string = format!(
-            "{}/{:x}/{:x}/index",
-            crate::hub::HUB_DATA_FOLDER,
-            hub_id.as_u128(),
-            channel_id.as_u128()
-        );
-        let dir_path = std::path::Path::new(&dir_string);
-        if !dir_path.is_dir() {
-            tokio::fs::create_dir_all(dir_path).await?;
-        }
-        let dir = MmapDirectory::open(dir_path)?;
-        let index = Index::open_or_create(dir, MESSAGE_SCHEMA.clone())?;
-        let reader = index
-            .reader_builder()
-            .reload_policy(ReloadPolicy::OnCommit)
-            .try_into()?;
-        let mut writer = index.writer(50_000_000)?;
+#[cfg(feature = "search")]
+impl MessageServer {
+    /// Commits the messages waiting in the index writer of a channel, if there are any.
+    async fn commit_pending(&mut self, hub_id: HubId, channel_id: ChannelId) -> Result {
         let key = (hub_id, channel_id);
-        let log_path_string = format!(
-            "{}/{:x}/{:x}/log",
-            crate::hub::HUB_DATA_FOLDER,
-            hub_id.as_u128(),
-            channel_id.as_u128()
-        );
-        let log_path = std::path::Path::new(&log_path_string);
-        if log_path.is_file() {
-            let mut buf: [u8; 16] = [0; 16];
-            tokio::fs::read(log_path)
-                .await?
-                .as_slice()
-                .read_exact(&mut buf)?;
-            let last_id = ID::from_u128(u128::from_le_bytes(buf));
-            let filename = format!("{}{:x}.json", crate::hub::HUB_INFO_FOLDER, hub_id.as_u128());
-            let path = std::path::Path::new(&filename);
-            if !path.exists() {
-                return Err(Error::HubNotFound);
-            }
-            let json = tokio::fs::read_to_string(path).await?;
-            let hub = serde_json::from_str::<Hub>(&json)?;
-            if let Some(channel) = hub.channels.get(&channel_id) {
-                let messages: Vec<Message> = channel
-                    .get_all_messages_from(last_id)
-                    .await
-                    .iter()
-                    .filter_map(|signed_message| Message::try_from(signed_message).ok())
-                    .collect();
-                let last_id = messages.last().map(|last| last.id);
-                for message in messages {
-                    add_message_to_writer(&mut writer, message)?;
-                }
-                writer.commit()?;
-                if let Some(last_id) = last_id {
-                    log_last_message(hub_id, channel_id, last_id).await?;
+        if let Some(pending) = self.pending_messages.get(&key).cloned() {
+            if pending.count != 0 {
+                self.get_writer(hub_id, channel_id).await?.commit()?;
+                log_last_message(hub_id, channel_id, pending.last).await?;
+                if let Some(pending) = self.pending_messages.get_mut(&key) {
+                    pending.commited();
                 }
-                reader.reload()?;
             }
         }
-        self.indexes.insert(key, index);
-        self.index_readers.insert(key, reader);
-        self.index_writers.insert(key, writer);
         Ok(())
     }
 
+    /// Sets up the Tantivy index for a given channel, taking it from [`warm_up_indexes`] if it was already opened, see [`open_index`].
+    async fn setup_index(&mut self, hub_id: HubId, channel_id: ChannelId) -> Result {
+        let slot = index_setup_slot(hub_id, channel_id);
+        let mut setup = slot.lock().await;
+        let opened = match std::mem::replace(&mut *setup, IndexSetup::Pending) {
+            IndexSetup::Warmed(opened) => *opened,
+            _ => open_index(hub_id, channel_id).await?,
+        };
+        *setup = IndexSetup::Taken;
+        let key = (hub_id, channel_id);
+        self.indexes.insert(key, opened.index);
+        self.index_readers.insert(key, opened.reader);
+        self.index_writers.insert(key, opened.writer);
+        Ok(())
+    }
+
+    /// Records that a channel is active, see [`RecentChannels`].
+    fn touch_channel(&mut self, hub_id: HubId, channel_id: ChannelId) {
+        self.recent_channels.touch(hub_id, channel_id);
+        self.recent_channels_changed = true;
+    }
+
     /// Gets a reader for a Tantivy index, also runs [`setup_index`] if it hasn't already been run for the given channel.
-    async fn get_reader(&mut self, hub_id: ID, channel_id: ID) -> Result<&IndexReader> {
+    async fn get_reader(&mut self, hub_id: HubId, channel_id: ChannelId) -> Result<&IndexReader> {
         let key = (hub_id, channel_id);
         if !self.index_readers.contains_key(&key) {
             self.setup_index(hub_id, channel_id).await?;
@@ -317,14 +925,22 @@ impl MessageServer {
     }
 
     /// Gets a searcher for the Tantivy index for a channel, uses [`get_reader`].
-    async fn get_searcher(&mut self, hub_id: ID, channel_id: ID) -> Result<LeasedItem<Searcher>> {
+    async fn get_searcher(
+        &mut self,
+        hub_id: HubId,
+        channel_id: ChannelId,
+    ) -> Result<LeasedItem<Searcher>> {
         let reader = self.get_reader(hub_id, channel_id).await?;
         let _ = reader.reload();
         Ok(reader.searcher())
     }
 
     /// Gets a writer for a Tantivy index, also runs [`setup_index`] if it hasn't already been run for the given channel.
-    async fn get_writer(&mut self, hub_id: ID, channel_id: ID) -> Result<&mut IndexWriter> {
+    async fn get_writer(
+        &mut self,
+        hub_id: HubId,
+        channel_id: ChannelId,
+    ) -> Result<&mut IndexWriter> {
         let key = (hub_id, channel_id);
         if !self.index_writers.contains_key(&key) {
             self.setup_index(hub_id, channel_id).await?;
@@ -337,211 +953,1074 @@ impl MessageServer {
     }
 }
 
+/// Removes a data folder and everything in it, a folder that does not exist is already removed.
+async fn remove_data_folder(path: std::path::PathBuf) -> Result {
+    match tokio::fs::remove_dir_all(&path).await {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result.with_path(path),
+    }
+}
+
+/// Removes the search index of a channel that was written before search was disabled, used when one of its messages is edited or deleted so that the old content does not stay in the index as plaintext.
+/// The index can be rebuilt with [`rebuild_index`] once search is enabled again.
+#[cfg(feature = "search")]
+async fn remove_stale_index(hub_id: HubId, channel_id: ChannelId) -> Result {
+    remove_data_folder(crate::paths::channel_index_dir(hub_id, channel_id)).await?;
+    let log_path = crate::paths::channel_log_file(hub_id, channel_id);
+    match tokio::fs::remove_file(&log_path).await {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result.with_path(log_path),
+    }
+}
+
 impl Default for MessageServer {
     fn default() -> Self {
-        Self::new()
+        Self::new(Instrumentation::default().message_server)
     }
 }
 
+#[cfg(not(feature = "search"))]
+impl Actor for MessageServer {}
+
+#[cfg(feature = "search")]
 #[async_trait]
 impl Actor for MessageServer {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> xactor::Result<()> {
+        ctx.send_interval(
+            CommitPendingMessages,
+            std::time::Duration::from_secs(crate::TANTIVY_COMMIT_INTERVAL),
+        );
+        // Channels that were active before the restart stay in the list until newer channels push them out.
+        self.recent_channels = RecentChannels::load().await;
+        ctx.send_interval(
+            SaveRecentChannels,
+            std::time::Duration::from_secs(crate::RECENT_CHANNELS_SAVE_INTERVAL),
+        );
+        Ok(())
+    }
+
     async fn stopped(&mut self, _ctx: &mut xactor::Context<Self>) {
+        if let Err(err) = self.recent_channels.save().await {
+            error!(
+                "Unable to save the recently active channels: {}",
+                err.chain()
+            );
+        }
         for (hc_id, writer) in self.index_writers.iter_mut() {
-            if let Some((_, id)) = self.pending_messages.get(&hc_id) {
-                let _ = log_last_message(hc_id.0, hc_id.1, *id);
+            if writer.commit().is_ok() {
+                if let Some(pending) = self.pending_messages.get(hc_id) {
+                    let _ = log_last_message(hc_id.0, hc_id.1, pending.last).await;
+                }
             }
-            let _ = writer.commit();
         }
     }
 }
 
+#[cfg(not(feature = "search"))]
 #[async_trait]
 impl Handler<SearchMessageIndex> for MessageServer {
     async fn handle(
         &mut self,
         _ctx: &mut Context<Self>,
-        msg: SearchMessageIndex,
-    ) -> Result<Vec<ID>> {
-        {
-            let pending = {
-                self.pending_messages
-                    .get(&(msg.hub_id, msg.channel_id))
-                    .cloned()
-            };
-            if let Some(pending) = pending {
-                if pending.0 != 0 {
-                    let _ = self.get_writer(msg.hub_id, msg.channel_id).await?.commit();
-                    log_last_message(msg.hub_id, msg.channel_id, pending.1).await?;
+        _msg: SearchMessageIndex,
+    ) -> Result<Vec<MessageId>> {
+        let _timer = self.stats.clone().start();
+        Err(Error::SearchDisabled)
+    }
+}
 
-                    self.pending_messages
-                        .insert((msg.hub_id, msg.channel_id), (0, pending.1));
-                }
-            }
+#[cfg(not(feature = "search"))]
+#[async_trait]
+impl Handler<NewMessageForIndex> for MessageServer {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: NewMessageForIndex) -> Result {
+        let _timer = self.stats.clone().start();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Handler<QueueMessageForIndex> for MessageServer {
+    async fn handle(&mut self, ctx: &mut Context<Self>, msg: QueueMessageForIndex) {
+        let (hub_id, channel_id) = (msg.0.hub_id, msg.0.channel_id);
+        if let Err(err) = Handler::<NewMessageForIndex>::handle(self, ctx, msg.0).await {
+            self.stats.failed();
+            error!(
+                "Unable to index a new message in channel {} of hub {}: {}",
+                channel_id,
+                hub_id,
+                err.chain()
+            );
+        }
+    }
+}
+
+#[cfg(not(feature = "search"))]
+#[async_trait]
+impl Handler<EditedMessageForIndex> for MessageServer {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: EditedMessageForIndex) -> Result {
+        let _timer = self.stats.clone().start();
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "search"))]
+#[async_trait]
+impl Handler<DeletedMessagesForIndex> for MessageServer {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: DeletedMessagesForIndex) -> Result {
+        let _timer = self.stats.clone().start();
+        Ok(())
+    }
+}
+
+#[cfg(feature = "search")]
+#[async_trait]
+impl Handler<SearchMessageIndex> for MessageServer {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        msg: SearchMessageIndex,
+    ) -> Result<Vec<MessageId>> {
+        let _timer = self.stats.clone().start();
+        if !crate::encryption::search_enabled() {
+            return Err(Error::SearchDisabled);
         }
+        self.touch_channel(msg.hub_id, msg.channel_id);
+        self.commit_pending(msg.hub_id, msg.channel_id).await?;
         let searcher = self.get_searcher(msg.hub_id, msg.channel_id).await?;
         let query_parser =
             QueryParser::for_index(searcher.index(), vec![MESSAGE_SCHEMA_FIELDS.content]);
         let query = query_parser.parse_query(&msg.query)?;
-        let top_docs = searcher.search(&query, &TopDocs::with_limit(msg.limit))?;
+        let candidates = if msg.not_before.is_some() {
+            MAX_LIMITED_SEARCH_CANDIDATES.max(msg.limit)
+        } else {
+            msg.limit
+        };
+        let top_docs = searcher.search(&query, &TopDocs::with_limit(candidates))?;
         let mut result = Vec::new();
         for (_score, doc_address) in top_docs {
             let retrieved_doc = searcher.doc(doc_address)?;
             if let Some(value) = retrieved_doc.get_first(MESSAGE_SCHEMA_FIELDS.id) {
                 if let Some(bytes) = value.bytes_value() {
-                    if let Ok(id) = bincode::deserialize::<ID>(bytes) {
+                    if let Ok(id) = bincode::deserialize::<MessageId>(bytes) {
                         result.push(id);
                     }
                 }
             }
         }
+        if let Some(not_before) = msg.not_before {
+            let readable: HashSet<MessageId> =
+                channel::Channel::new(String::new(), msg.channel_id, msg.hub_id)
+                    .get_messages(result.clone())
+                    .await
+                    .into_iter()
+                    .filter(|message| message.created >= not_before)
+                    .map(|message| message.id)
+                    .collect();
+            result.retain(|id| readable.contains(id));
+            result.truncate(msg.limit);
+        }
         Ok(result)
     }
 }
 
+#[cfg(feature = "search")]
 #[async_trait]
 impl Handler<NewMessageForIndex> for MessageServer {
     async fn handle(&mut self, _ctx: &mut Context<Self>, msg: NewMessageForIndex) -> Result {
-        let mut new_pending: u8;
+        let _timer = self.stats.clone().start();
+        // The index would hold the plaintext of encrypted messages.
+        if !crate::encryption::search_enabled() {
+            return Ok(());
+        }
+        let key = (msg.hub_id, msg.channel_id);
         let message_id = msg.message.id;
-        if let Some((pending, _)) = self
+        self.touch_channel(msg.hub_id, msg.channel_id);
+        add_message_to_writer(
+            self.get_writer(msg.hub_id, msg.channel_id).await?,
+            msg.message,
+        )?;
+        let commit = self
             .pending_messages
-            .get(&(msg.hub_id, msg.channel_id))
-            .cloned()
-        {
-            new_pending = pending + 1;
-            if pending >= crate::TANTIVY_COMMIT_THRESHOLD {
-                let mut writer = self.get_writer(msg.hub_id, msg.channel_id).await?;
-                add_message_to_writer(&mut writer, msg.message)?;
-                writer.commit()?;
-                log_last_message(msg.hub_id, msg.channel_id, message_id).await?;
-                new_pending = 0;
-            } else {
-                log_if_nologs(msg.hub_id, msg.channel_id, message_id).await?;
-            }
+            .entry(key)
+            .or_insert_with(|| PendingMessages::new(message_id))
+            .push(message_id, crate::TANTIVY_COMMIT_THRESHOLD);
+        if commit {
+            self.commit_pending(msg.hub_id, msg.channel_id).await?;
         } else {
-            new_pending = 1;
             log_if_nologs(msg.hub_id, msg.channel_id, message_id).await?;
         }
-        let _ = self
-            .pending_messages
-            .insert((msg.hub_id, msg.channel_id), (new_pending, message_id));
         Ok(())
     }
 }
 
-pub type SubscribedChannelMap = Arc<RwLock<HashMap<(ID, ID), Arc<RwLock<HashSet<u128>>>>>>;
-pub type SubscribedHubMap = Arc<RwLock<HashMap<ID, Arc<RwLock<HashSet<u128>>>>>>;
-pub type SubscribedMap = Arc<RwLock<HashMap<u128, Arc<RwLock<(HashSet<(ID, ID)>, HashSet<ID>)>>>>>;
-pub type ConnectedMap =
-    Arc<RwLock<HashMap<u128, Arc<Mutex<SplitSink<WebSocket, WebSocketMessage>>>>>>;
+#[cfg(feature = "search")]
+#[async_trait]
+impl Handler<CommitPendingMessages> for MessageServer {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: CommitPendingMessages) {
+        // Not timed, these are not counted as sent so they would lower the mailbox depth.
+        let keys: Vec<(HubId, ChannelId)> = self
+            .pending_messages
+            .iter()
+            .filter(|(_, pending)| pending.count != 0)
+            .map(|(key, _)| *key)
+            .collect();
+        for (hub_id, channel_id) in keys {
+            if let Err(err) = self.commit_pending(hub_id, channel_id).await {
+                error!(
+                    "Unable to commit the index of channel {:x} in hub {:x}: {}",
+                    channel_id.as_u128(),
+                    hub_id.as_u128(),
+                    err.chain()
+                );
+            }
+        }
+    }
+}
 
-/// Server that handles socket clients and manages notifying them of new messages/changes as well as sending messages to be indexed by Tantivy.
-pub struct Server {
-    subscribed_channels: SubscribedChannelMap,
-    subscribed_hubs: SubscribedHubMap,
-    subscribed: SubscribedMap,
-    connected: ConnectedMap,
-    message_server: Addr<MessageServer>,
-    secret_key: SignedSecretKey,
+#[cfg(feature = "search")]
+#[async_trait]
+impl Handler<SaveRecentChannels> for MessageServer {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: SaveRecentChannels) {
+        // Not timed for the same reason as commits of pending messages.
+        if !self.recent_channels_changed {
+            return;
+        }
+        match self.recent_channels.save().await {
+            Ok(()) => self.recent_channels_changed = false,
+            Err(err) => error!(
+                "Unable to save the recently active channels: {}",
+                err.chain()
+            ),
+        }
+    }
 }
 
-impl Server {
-    /// Creates a new server with default options, also creates a [`MessageServer`] with the given `commit_threshold` (how many messages should be added to the search index before commiting to the index).
-    pub async fn new(secret_key: SignedSecretKey) -> Result<Self> {
-        Ok(Self {
-            subscribed_channels: Arc::new(RwLock::new(HashMap::new())),
-            subscribed_hubs: Arc::new(RwLock::new(HashMap::new())),
-            subscribed: Arc::new(RwLock::new(HashMap::new())),
-            connected: Arc::new(RwLock::new(HashMap::new())),
-            secret_key,
-            message_server: MessageServer::new()
-                .start()
-                .await
-                .map_err(|_| Error::ServerStartFailed)?,
-        })
+#[cfg(feature = "search")]
+#[async_trait]
+impl Handler<EditedMessageForIndex> for MessageServer {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: EditedMessageForIndex) -> Result {
+        let _timer = self.stats.clone().start();
+        if !crate::encryption::search_enabled() {
+            return remove_stale_index(msg.hub_id, msg.channel_id).await;
+        }
+        let key = (msg.hub_id, msg.channel_id);
+        let id_term = Term::from_field_bytes(
+            MESSAGE_SCHEMA_FIELDS.id,
+            &bincode::serialize(&msg.message.id)?,
+        );
+        let writer = self.get_writer(msg.hub_id, msg.channel_id).await?;
+        writer.delete_term(id_term);
+        add_message_to_writer(writer, msg.message)?;
+        // Commited right away, the log of the last indexed message only covers new messages so an edit could not be replayed.
+        writer.commit()?;
+        if let Some(pending) = self.pending_messages.get_mut(&key) {
+            if pending.count != 0 {
+                log_last_message(msg.hub_id, msg.channel_id, pending.last).await?;
+                pending.commited();
+            }
+        }
+        Ok(())
     }
+}
 
-    /// Sends a [`ServreMessage`] to all clients subscribed to notifications for the given hub.
-    async fn send_hub(&self, message: ServerMessage, hub_id: &ID) -> Result {
-        if let Some(subscribed_arc) = self.subscribed_hubs.read().await.get(hub_id) {
-            let signed_message =
-                OpenPGPMessage::new_literal("", serde_json::to_string(&message)?.as_str()).sign(
-                    &self.secret_key,
-                    String::new,
-                    pgp::crypto::HashAlgorithm::SHA2_256,
-                )?;
-            let signed_message_string = signed_message.to_armored_string(None)?;
-            let message = WebSocketMessage::text(signed_message_string);
-            for connection_id in subscribed_arc.read().await.iter() {
-                if let Some(connection) = self.connected.read().await.get(connection_id) {
-                    let _ = connection.lock().await.send(message.clone()).await;
-                }
+#[cfg(feature = "search")]
+#[async_trait]
+impl Handler<DeletedMessagesForIndex> for MessageServer {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: DeletedMessagesForIndex) -> Result {
+        let _timer = self.stats.clone().start();
+        if !crate::encryption::search_enabled() {
+            return remove_stale_index(msg.hub_id, msg.channel_id).await;
+        }
+        let key = (msg.hub_id, msg.channel_id);
+        let writer = self.get_writer(msg.hub_id, msg.channel_id).await?;
+        for message_id in msg.message_ids.iter() {
+            writer.delete_term(Term::from_field_bytes(
+                MESSAGE_SCHEMA_FIELDS.id,
+                &bincode::serialize(message_id)?,
+            ));
+        }
+        // Commited right away for the same reason as edits.
+        writer.commit()?;
+        if let Some(pending) = self.pending_messages.get_mut(&key) {
+            if pending.count != 0 {
+                log_last_message(msg.hub_id, msg.channel_id, pending.last).await?;
+                pending.commited();
             }
         }
         Ok(())
     }
+}
+
+#[cfg(not(feature = "search"))]
+#[async_trait]
+impl Handler<ChannelDeletedForIndex> for MessageServer {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: ChannelDeletedForIndex) -> Result {
+        let _timer = self.stats.clone().start();
+        remove_data_folder(crate::paths::deleted_channel_dir(
+            msg.hub_id,
+            msg.channel_id,
+        ))
+        .await?;
+        remove_data_folder(crate::paths::channel_dir(msg.hub_id, msg.channel_id)).await
+    }
+}
+
+#[cfg(not(feature = "search"))]
+#[async_trait]
+impl Handler<HubDeletedForIndex> for MessageServer {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: HubDeletedForIndex) -> Result {
+        let _timer = self.stats.clone().start();
+        remove_data_folder(crate::paths::hub_data_dir(msg.hub_id)).await
+    }
+}
+
+#[cfg(feature = "search")]
+#[async_trait]
+impl Handler<ChannelDeletedForIndex> for MessageServer {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: ChannelDeletedForIndex) -> Result {
+        let _timer = self.stats.clone().start();
+        let key = (msg.hub_id, msg.channel_id);
+        // The writer is dropped first so that its memory maps are released before the files are removed, pending messages are discarded with it.
+        self.index_writers.remove(&key);
+        self.index_readers.remove(&key);
+        self.indexes.remove(&key);
+        self.pending_messages.remove(&key);
+        self.recent_channels.0.remove(&key);
+        forget_index_setup(|setup_key| *setup_key == key);
+        remove_data_folder(crate::paths::deleted_channel_dir(
+            msg.hub_id,
+            msg.channel_id,
+        ))
+        .await?;
+        remove_data_folder(crate::paths::channel_dir(msg.hub_id, msg.channel_id)).await
+    }
+}
+
+#[cfg(feature = "search")]
+#[async_trait]
+impl Handler<HubDeletedForIndex> for MessageServer {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: HubDeletedForIndex) -> Result {
+        let _timer = self.stats.clone().start();
+        let hub_id = msg.hub_id;
+        self.index_writers.retain(|key, _| key.0 != hub_id);
+        self.index_readers.retain(|key, _| key.0 != hub_id);
+        self.indexes.retain(|key, _| key.0 != hub_id);
+        self.pending_messages.retain(|key, _| key.0 != hub_id);
+        self.recent_channels.0.retain(|key, _| key.0 != hub_id);
+        forget_index_setup(|key| key.0 == hub_id);
+        remove_data_folder(crate::paths::hub_data_dir(hub_id)).await
+    }
+}
+
+pub type SubscribedChannelMap =
+    Arc<RwLock<HashMap<(HubId, ChannelId), Arc<RwLock<HashSet<u128>>>>>>;
+pub type SubscribedHubMap = Arc<RwLock<HashMap<HubId, Arc<RwLock<HashSet<u128>>>>>>;
+pub type SubscribedMap =
+    Arc<RwLock<HashMap<u128, Arc<RwLock<(HashSet<(HubId, ChannelId)>, HashSet<HubId>)>>>>>;
+pub type ConnectedMap = Arc<RwLock<HashMap<u128, Arc<Mutex<ConnectionOutbox>>>>>;
+
+/// Events delivered to a connection. Every [`ServerMessage`] the [`Server`] sends gets a new, larger event ID and the server sends one event at a time, so an event whose ID is not larger than the last delivered one has already been delivered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeliveredEvents {
+    last: u64,
+}
+
+impl DeliveredEvents {
+    /// Records that an event is about to be delivered, returns false if it was already delivered.
+    pub fn admit(&mut self, event_id: u64) -> bool {
+        if event_id <= self.last {
+            false
+        } else {
+            self.last = event_id;
+            true
+        }
+    }
+}
+
+/// The way out to a client connection for everything the [`Server`] sends it. It is locked for the whole time a frame is written, so frames arrive in the order they were sent.
+pub struct ConnectionOutbox {
+    writer: Arc<Mutex<SplitSink<WebSocket, WebSocketMessage>>>,
+    delivered: DeliveredEvents,
+}
+
+impl ConnectionOutbox {
+    /// Creates an outbox that writes to the given half of a WebSocket.
+    pub fn new(writer: Arc<Mutex<SplitSink<WebSocket, WebSocketMessage>>>) -> Self {
+        Self {
+            writer,
+            delivered: DeliveredEvents::default(),
+        }
+    }
+
+    /// Writes an event to the connection unless it was already delivered. Returns false if the connection could not be written to.
+    async fn send_event(&mut self, event_id: u64, frame: WebSocketMessage) -> bool {
+        !self.delivered.admit(event_id) || self.send_frame(frame).await
+    }
+
+    /// Writes a frame to the connection without giving it an event ID, for frames whose senders make sure each connection gets them once. Returns false if the connection could not be written to.
+    async fn send_frame(&mut self, frame: WebSocketMessage) -> bool {
+        self.writer.lock().await.send(frame).await.is_ok()
+    }
+}
+/// ID of the user that each connection belongs to.
+pub type ConnectionUserMap = Arc<RwLock<HashMap<u128, String>>>;
+/// Users that are typing in each channel, with the ID of the connection they started typing on.
+pub type TypingMap = Arc<RwLock<HashMap<(HubId, ChannelId), HashMap<String, u128>>>>;
+
+/// Removes the users that started typing on the given connection, returns the channels they were typing in and their IDs.
+fn remove_typing_connection(
+    typing: &mut HashMap<(HubId, ChannelId), HashMap<String, u128>>,
+    connection_id: u128,
+) -> Vec<(HubId, ChannelId, String)> {
+    let mut removed = Vec::new();
+    typing.retain(|(hub_id, channel_id), users| {
+        users.retain(|user_id, typing_connection| {
+            if *typing_connection == connection_id {
+                removed.push((*hub_id, *channel_id, user_id.clone()));
+                false
+            } else {
+                true
+            }
+        });
+        !users.is_empty()
+    });
+    removed
+}
+
+/// Removes a user from the typing users of every channel of a hub, returns the channels they were typing in.
+fn remove_typing_user(
+    typing: &mut HashMap<(HubId, ChannelId), HashMap<String, u128>>,
+    hub_id: HubId,
+    user_id: &str,
+) -> Vec<ChannelId> {
+    let mut removed = Vec::new();
+    typing.retain(|(typing_hub_id, channel_id), users| {
+        if *typing_hub_id == hub_id && users.remove(user_id).is_some() {
+            removed.push(*channel_id);
+        }
+        !users.is_empty()
+    });
+    removed
+}
+
+/// Finds the channel subscriptions (`(channel, connection)` pairs) whose user can no longer read the channel, using the hub as it is now.
+/// `hub` is `None` if the hub could not be loaded, all of its subscriptions are dropped then, as are the subscriptions of connections without a known user.
+fn unreadable_subscriptions(
+    hub: Option<&Hub>,
+    subscriptions: Vec<((HubId, ChannelId), u128)>,
+    connection_users: &HashMap<u128, String>,
+) -> Vec<((HubId, ChannelId), u128)> {
+    subscriptions
+        .into_iter()
+        .filter(|((_, channel_id), connection_id)| {
+            let readable = hub.is_some_and(|hub| {
+                connection_users
+                    .get(connection_id)
+                    .and_then(|user_id| hub.members.get(user_id))
+                    .is_some_and(|member| hub.can_read_channel(member, *channel_id))
+            });
+            !readable
+        })
+        .collect()
+}
+
+/// Adds a connection to the subscribers of `key`.
+/// The map is always locked before the set of subscribers in it, the same order as in [`remove_subscriber`].
+async fn add_subscriber<K: Hash + Eq>(
+    map: &RwLock<HashMap<K, Arc<RwLock<HashSet<u128>>>>>,
+    key: K,
+    connection_id: u128,
+) {
+    map.write()
+        .await
+        .entry(key)
+        .or_default()
+        .write()
+        .await
+        .insert(connection_id);
+}
+
+/// Removes a connection from the subscribers of `key`, removing the entry once it has no subscribers left so that the map does not keep growing.
+/// Returns true if the entry was removed.
+async fn remove_subscriber<K: Hash + Eq>(
+    map: &RwLock<HashMap<K, Arc<RwLock<HashSet<u128>>>>>,
+    key: &K,
+    connection_id: u128,
+) -> bool {
+    let mut map = map.write().await;
+    let empty = match map.get(key) {
+        Some(subscribers) => {
+            let mut subscribers = subscribers.write().await;
+            subscribers.remove(&connection_id);
+            subscribers.is_empty()
+        }
+        None => false,
+    };
+    if empty {
+        map.remove(key);
+    }
+    empty
+}
+
+/// Milliseconds that the [`ServerNotification::HubUpdated`] notifications of a hub are collected for before they are sent to the hub's subscribers as one frame, see [`PendingHubUpdates`].
+pub const HUB_UPDATE_COALESCE_MS: u64 = 50;
+
+/// Number of connections a hub update is written to at the same time, see [`deliver`].
+pub const HUB_UPDATE_FANOUT_CONCURRENCY: usize = 64;
+
+/// Sent to the [`Server`] by itself [`HUB_UPDATE_COALESCE_MS`] milliseconds after the first update of a hub in a burst, to send the updates that were collected for it.
+#[message]
+#[derive(Clone, Debug)]
+struct FlushHubUpdates(HubId);
+
+/// Updates of each hub that have not been sent to its subscribers yet, so that a burst of updates reaches each connection as one frame.
+#[derive(Debug, Default)]
+pub struct PendingHubUpdates(HashMap<HubId, (Vec<HubUpdateType>, u64)>);
+
+impl PendingHubUpdates {
+    /// Adds an update made to a hub, an update that is already pending is not added again. Returns true if it is the first pending update of the hub, which is when a [`FlushHubUpdates`] has to be scheduled.
+    pub fn push(&mut self, hub_id: HubId, update_type: HubUpdateType, version: u64) -> bool {
+        let mut first = false;
+        let (update_types, latest) = self.0.entry(hub_id).or_insert_with(|| {
+            first = true;
+            (Vec::new(), version)
+        });
+        if !update_types.contains(&update_type) {
+            update_types.push(update_type);
+        }
+        *latest = version.max(*latest);
+        first
+    }
+
+    /// Takes the pending updates of a hub and turns them into the message sent to its subscribers, [`ServerMessage::HubUpdated`] if there is only one.
+    pub fn take(&mut self, hub_id: HubId) -> Option<ServerMessage> {
+        let (mut update_types, version) = self.0.remove(&hub_id)?;
+        Some(if update_types.len() == 1 {
+            ServerMessage::HubUpdated {
+                hub_id,
+                update_type: update_types.remove(0),
+                version,
+            }
+        } else {
+            ServerMessage::HubUpdates {
+                hub_id,
+                update_types,
+                version,
+            }
+        })
+    }
+}
+
+/// Writes a frame to connections, at most `concurrency` at a time. Returns the connections that could not be written to, including connections that are already gone (`None`).
+async fn deliver(
+    connections: Vec<(u128, Option<Arc<Mutex<ConnectionOutbox>>>)>,
+    frame: WebSocketMessage,
+    concurrency: usize,
+) -> Vec<u128> {
+    use futures::StreamExt;
+    futures::stream::iter(connections)
+        .map(|(connection_id, connection)| {
+            let frame = frame.clone();
+            async move {
+                let sent = match connection {
+                    Some(connection) => connection.lock().await.send_frame(frame).await,
+                    None => false,
+                };
+                (connection_id, sent)
+            }
+        })
+        .buffer_unordered(concurrency)
+        .filter_map(|(connection_id, sent)| async move {
+            if sent {
+                None
+            } else {
+                Some(connection_id)
+            }
+        })
+        .collect()
+        .await
+}
+
+/// One in this many notifications sent to more connections than [`crate::config::LimitsConfig::fanout_warning_threshold`] is logged, see [`Server::check_fanout`].
+pub const FANOUT_LOG_SAMPLE: u64 = 100;
+
+/// Server that handles socket clients and manages notifying them of new messages/changes as well as sending messages to be indexed by Tantivy.
+pub struct Server {
+    subscribed_channels: SubscribedChannelMap,
+    subscribed_hubs: SubscribedHubMap,
+    subscribed: SubscribedMap,
+    connected: ConnectedMap,
+    connection_users: ConnectionUserMap,
+    typing: TypingMap,
+    message_server: InstrumentedAddr<MessageServer>,
+    secret_key: SignedSecretKey,
+    instrumentation: Arc<Instrumentation>,
+    /// ID of the last event sent to clients, see [`DeliveredEvents`].
+    last_event_id: AtomicU64,
+    /// Sequence numbers and recent message events of channels, see [`ChannelEvents`].
+    channel_events: RwLock<ChannelEvents>,
+    /// Number of notifications sent to more connections than [`crate::config::LimitsConfig::fanout_warning_threshold`], see [`Server::check_fanout`].
+    large_fanouts: AtomicU64,
+    /// Hub updates waiting to be sent, see [`HUB_UPDATE_COALESCE_MS`].
+    pending_hub_updates: PendingHubUpdates,
+    /// Intents of the connections that set any, see [`client_command::SetIntents`].
+    intents: RwLock<HashMap<u128, Intents>>,
+    /// Messages waiting for their first acknowledgement, see [`crate::delivery_reports`].
+    pending_deliveries: PendingDeliveries,
+}
+
+impl Server {
+    /// Creates a new server with default options, also creates a [`MessageServer`] with the given `commit_threshold` (how many messages should be added to the search index before commiting to the index).
+    /// Message handling statistics for both servers are recorded in `instrumentation`.
+    pub async fn new(
+        secret_key: SignedSecretKey,
+        instrumentation: Arc<Instrumentation>,
+    ) -> Result<Self> {
+        Ok(Self {
+            subscribed_channels: Arc::new(RwLock::new(HashMap::new())),
+            subscribed_hubs: Arc::new(RwLock::new(HashMap::new())),
+            subscribed: Arc::new(RwLock::new(HashMap::new())),
+            connected: Arc::new(RwLock::new(HashMap::new())),
+            connection_users: Arc::new(RwLock::new(HashMap::new())),
+            typing: Arc::new(RwLock::new(HashMap::new())),
+            secret_key,
+            message_server: InstrumentedAddr::new(
+                MessageServer::new(instrumentation.message_server.clone())
+                    .start()
+                    .await
+                    .map_err(|_| Error::ServerStartFailed)?,
+                instrumentation.message_server.clone(),
+            ),
+            instrumentation,
+            last_event_id: AtomicU64::new(0),
+            channel_events: RwLock::new(ChannelEvents::default()),
+            large_fanouts: AtomicU64::new(0),
+            pending_hub_updates: PendingHubUpdates::default(),
+            intents: RwLock::new(HashMap::new()),
+            pending_deliveries: PendingDeliveries::default(),
+        })
+    }
 
     /// Sends a [`ServreMessage`] to all clients subscribed to notifications for the given channel.
-    async fn send_channel(&self, message: ServerMessage, hub_id: ID, channel_id: ID) -> Result {
-        if let Some(subscribed_arc) = self
-            .subscribed_channels
+    async fn send_channel(
+        &self,
+        message: ServerMessage,
+        hub_id: HubId,
+        channel_id: ChannelId,
+    ) -> Result {
+        self.send_scopes(message, &[], &[(hub_id, channel_id)])
+            .await
+    }
+
+    /// Sends a message event to all clients subscribed to the given channel with the channel's next sequence number, keeping it for clients that miss it, see [`ChannelEvents`].
+    async fn send_channel_event(
+        &self,
+        hub_id: HubId,
+        channel_id: ChannelId,
+        event: impl FnOnce(u64, u64) -> ServerMessage,
+    ) -> Result {
+        let key = (hub_id, channel_id);
+        let message = {
+            let mut events = self.channel_events.write().await;
+            let event_seq = events.next_seq(key);
+            let message = event(events.epoch(), event_seq);
+            events.record(key, event_seq, &message);
+            message
+        };
+        self.send_channel(message, hub_id, channel_id).await
+    }
+
+    /// Removes a connection from the subscribers of a channel, the channel's events are no longer kept once it has no subscribers left.
+    async fn remove_channel_subscriber(&self, key: &(HubId, ChannelId), connection_id: u128) {
+        if remove_subscriber(&self.subscribed_channels, key, connection_id).await {
+            self.channel_events.write().await.close(key);
+        }
+    }
+
+    /// Sends a [`ServerMessage`] to all clients subscribed to any of the given hubs or channels, a client with several matching subscriptions gets it once.
+    async fn send_scopes(
+        &self,
+        message: ServerMessage,
+        hub_ids: &[HubId],
+        channels: &[(HubId, ChannelId)],
+    ) -> Result {
+        let mut subscribers = Vec::new();
+        for hub_id in hub_ids {
+            if let Some(hub_subscribers) = self.subscribed_hubs.read().await.get(hub_id) {
+                subscribers.extend(hub_subscribers.read().await.iter().copied());
+            }
+        }
+        for channel in channels {
+            if let Some(channel_subscribers) = self.subscribed_channels.read().await.get(channel) {
+                subscribers.extend(channel_subscribers.read().await.iter().copied());
+            }
+        }
+        if subscribers.is_empty() {
+            return Ok(());
+        }
+        self.check_fanout(hub_ids, channels, subscribers.len());
+        self.send_to(message, subscribers).await
+    }
+
+    /// Logs notifications that are sent to more connections than [`crate::config::LimitsConfig::fanout_warning_threshold`], they are still sent.
+    /// Only one in [`FANOUT_LOG_SAMPLE`] is logged so that a busy channel with many subscribers does not flood the log.
+    fn check_fanout(&self, hub_ids: &[HubId], channels: &[(HubId, ChannelId)], connections: usize) {
+        if connections <= crate::quotas::limits().fanout_warning_threshold {
+            return;
+        }
+        let count = self.large_fanouts.fetch_add(1, Ordering::Relaxed);
+        if count.is_multiple_of(FANOUT_LOG_SAMPLE) {
+            warn!(
+                "Notification for hubs {:?} and channels {:?} is sent to {} connections ({} such notifications so far).",
+                hub_ids,
+                channels,
+                connections,
+                count + 1
+            );
+        }
+    }
+
+    /// Gets the sorted IDs of the users that are typing in a channel.
+    async fn typing_users(&self, key: (HubId, ChannelId)) -> Vec<String> {
+        let mut users: Vec<String> = self
+            .typing
             .read()
             .await
-            .get(&(hub_id, channel_id))
-        {
-            let signed_message =
-                OpenPGPMessage::new_literal("", serde_json::to_string(&message)?.as_str()).sign(
-                    &self.secret_key,
-                    String::new,
-                    pgp::crypto::HashAlgorithm::SHA2_256,
-                )?;
-            let signed_message_string = signed_message.to_armored_string(None)?;
-            let message = WebSocketMessage::text(signed_message_string);
-            for connection_id in subscribed_arc.read().await.iter() {
-                if let Some(connection) = self.connected.read().await.get(connection_id) {
-                    let _ = connection.lock().await.send(message.clone()).await;
+            .get(&key)
+            .map(|users| users.keys().cloned().collect())
+            .unwrap_or_default();
+        users.sort();
+        users
+    }
+
+    /// Signs a [`ServerMessage`] with the server's key, giving the frame that is sent to clients.
+    fn sign(&self, message: &ServerMessage) -> Result<WebSocketMessage> {
+        let signed_message =
+            OpenPGPMessage::new_literal("", serde_json::to_string(message)?.as_str()).sign(
+                &self.secret_key,
+                String::new,
+                pgp::crypto::HashAlgorithm::SHA2_256,
+            )?;
+        Ok(WebSocketMessage::text(
+            signed_message.to_armored_string(None)?,
+        ))
+    }
+
+    /// Signs a [`ServerMessage`] and sends it as a new event to the given connections, connections that can no longer be sent to are disconnected.
+    /// A connection that is listed more than once gets the event once, see [`DeliveredEvents`].
+    async fn send_to(&self, message: ServerMessage, connection_ids: Vec<u128>) -> Result {
+        let message = self.sign(&message)?;
+        let started = Instant::now();
+        let connections = connection_ids.len();
+        let event_id = self.last_event_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut dead = Vec::new();
+        for connection_id in connection_ids {
+            let connection = self.connected.read().await.get(&connection_id).cloned();
+            let sent = match connection {
+                Some(connection) => {
+                    connection
+                        .lock()
+                        .await
+                        .send_event(event_id, message.clone())
+                        .await
                 }
+                None => false,
+            };
+            if !sent {
+                dead.push(connection_id);
             }
         }
+        for connection_id in dead {
+            self.disconnect(connection_id).await;
+        }
+        self.instrumentation
+            .fanout
+            .record(connections, started.elapsed());
         Ok(())
     }
+
+    /// Unsubscribes a connection from everything and forgets it.
+    /// Returns the users that were typing on the connection, see [`remove_typing_connection`].
+    async fn disconnect(&self, connection_id: u128) -> Vec<(HubId, ChannelId, String)> {
+        let subscribed = self.subscribed.write().await.remove(&connection_id);
+        if let Some(subscribed) = subscribed {
+            let subscribed = subscribed.read().await;
+            for channel in subscribed.0.iter() {
+                self.remove_channel_subscriber(channel, connection_id).await;
+            }
+            for hub in subscribed.1.iter() {
+                remove_subscriber(&self.subscribed_hubs, hub, connection_id).await;
+            }
+        }
+        self.connected.write().await.remove(&connection_id);
+        self.connection_users.write().await.remove(&connection_id);
+        self.intents.write().await.remove(&connection_id);
+        remove_typing_connection(&mut *self.typing.write().await, connection_id)
+    }
+
+    /// Removes one subscription from the list of a connection's subscriptions, forgetting the list once it is empty.
+    async fn remove_subscription(
+        &self,
+        connection_id: u128,
+        remove: impl FnOnce(&mut (HashSet<(HubId, ChannelId)>, HashSet<HubId>)),
+    ) {
+        let mut subscribed = self.subscribed.write().await;
+        let empty = match subscribed.get(&connection_id) {
+            Some(subscriptions) => {
+                let mut subscriptions = subscriptions.write().await;
+                remove(&mut subscriptions);
+                subscriptions.0.is_empty() && subscriptions.1.is_empty()
+            }
+            None => false,
+        };
+        if empty {
+            subscribed.remove(&connection_id);
+        }
+    }
+
+    /// Drops the channel subscriptions in a hub whose user can no longer read the channel, see [`unreadable_subscriptions`], and tells each of those connections with a [`ServerMessage::SubscriptionRevoked`].
+    /// Called after hub updates that may take away access so that those users stop getting the channel's messages. Access that is granted does not subscribe anyone.
+    async fn revalidate_channel_subscriptions(&self, hub_id: HubId) {
+        let hub = Hub::load(hub_id).await.ok();
+        let mut subscriptions = Vec::new();
+        for (key, subscribers) in self.subscribed_channels.read().await.iter() {
+            if key.0 == hub_id {
+                subscriptions.extend(
+                    subscribers
+                        .read()
+                        .await
+                        .iter()
+                        .map(|connection_id| (*key, *connection_id)),
+                );
+            }
+        }
+        let revoked = unreadable_subscriptions(
+            hub.as_ref(),
+            subscriptions,
+            &*self.connection_users.read().await,
+        );
+        for (key, connection_id) in revoked {
+            self.remove_subscription(connection_id, |subscriptions| {
+                subscriptions.0.remove(&key);
+            })
+            .await;
+            self.remove_channel_subscriber(&key, connection_id).await;
+            let _ = self
+                .send_to(
+                    ServerMessage::SubscriptionRevoked {
+                        hub_id: key.0,
+                        channel_id: key.1,
+                    },
+                    vec![connection_id],
+                )
+                .await;
+        }
+    }
+
+    /// Gets the IDs of the connections of a user.
+    async fn user_connections(&self, user_id: &str) -> Vec<u128> {
+        self.connection_users
+            .read()
+            .await
+            .iter()
+            .filter(|(_, connection_user)| connection_user.as_str() == user_id)
+            .map(|(connection_id, _)| *connection_id)
+            .collect()
+    }
+
+    /// Gets the connections of a user that asked for delivery reports, see [`Intents::delivery_reports`].
+    async fn delivery_report_connections(&self, user_id: &str) -> Vec<u128> {
+        let intents = self.intents.read().await;
+        if intents.is_empty() {
+            return Vec::new();
+        }
+        self.user_connections(user_id)
+            .await
+            .into_iter()
+            .filter(|connection_id| {
+                intents
+                    .get(connection_id)
+                    .is_some_and(|intents| intents.delivery_reports)
+            })
+            .collect()
+    }
+
+    /// Unsubscribes the connections of a user that was kicked or banned from a hub from the hub, stops them typing in its channels and sends each connection a [`ServerMessage::RemovedFromHub`].
+    /// Their channel subscriptions are dropped by [`Server::revalidate_channel_subscriptions`].
+    async fn remove_from_hub(&self, hub_id: HubId, user_id: &str) {
+        let connection_ids = self.user_connections(user_id).await;
+        for connection_id in connection_ids.iter() {
+            self.remove_subscription(*connection_id, |subscriptions| {
+                subscriptions.1.remove(&hub_id);
+            })
+            .await;
+            remove_subscriber(&self.subscribed_hubs, &hub_id, *connection_id).await;
+        }
+        let stopped = remove_typing_user(&mut *self.typing.write().await, hub_id, user_id);
+        for channel_id in stopped {
+            let _ = self
+                .send_channel(
+                    ServerMessage::UserStoppedTyping {
+                        user_id: user_id.to_string(),
+                        hub_id,
+                        channel_id,
+                    },
+                    hub_id,
+                    channel_id,
+                )
+                .await;
+        }
+        if !connection_ids.is_empty() {
+            let _ = self
+                .send_to(ServerMessage::RemovedFromHub { hub_id }, connection_ids)
+                .await;
+        }
+    }
+
+    /// Sends a [`ServerMessage::Mention`] to every connection of the members of the groups a message mentions that can read its channel.
+    /// Members without any connection open get the mention counted in their offline summary instead, see [`crate::offline_summaries`].
+    async fn send_mentions(&self, message: &channel::Message) {
+        let hub = match Hub::load(message.hub_id).await {
+            Ok(hub) => hub,
+            Err(_) => return,
+        };
+        let connection_users = self.connection_users.read().await.clone();
+        let online: HashSet<&String> = connection_users.values().collect();
+        let mut offline: HashSet<String> = HashSet::new();
+        for group_id in message.group_mentions.iter() {
+            let group = match hub.groups.get(group_id) {
+                Some(group) => group,
+                None => continue,
+            };
+            let users: HashSet<&String> = group
+                .members
+                .iter()
+                .filter(|user_id| {
+                    hub.members
+                        .get(*user_id)
+                        .is_some_and(|member| hub.can_read_channel(member, message.channel_id))
+                })
+                .collect();
+            offline.extend(
+                users
+                    .iter()
+                    .filter(|user_id| !online.contains(*user_id))
+                    .map(|user_id| user_id.to_string()),
+            );
+            let connection_ids: Vec<u128> = connection_users
+                .iter()
+                .filter(|(_, user_id)| users.contains(user_id))
+                .map(|(connection_id, _)| *connection_id)
+                .collect();
+            let _ = self
+                .send_to(
+                    ServerMessage::Mention {
+                        hub_id: message.hub_id,
+                        channel_id: message.channel_id,
+                        message_id: message.id,
+                        group_id: *group_id,
+                    },
+                    connection_ids,
+                )
+                .await;
+        }
+        if !offline.is_empty() {
+            crate::offline_summaries::record_mention(
+                offline,
+                message.hub_id,
+                message.channel_id,
+                message.id,
+            );
+        }
+    }
 }
 
-impl Actor for Server {}
+#[async_trait]
+impl Actor for Server {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> xactor::Result<()> {
+        subscribers().push((
+            self.instrumentation.server.clone(),
+            ctx.address().downgrade(),
+        ));
+        Ok(())
+    }
+
+    async fn stopped(&mut self, _ctx: &mut Context<Self>) {
+        subscribers().retain(|(stats, _)| !Arc::ptr_eq(stats, &self.instrumentation.server));
+    }
+}
+
+/// The running [`Server`]s with their statistics, each of them gets the notifications given to [`publish`].
+/// The addresses are weak so a server whose runtime was dropped without stopping it is removed on the next notification.
+static SUBSCRIBERS: SyncMutex<Vec<(Arc<ActorStats>, WeakAddr<Server>)>> =
+    SyncMutex::new(Vec::new());
+
+fn subscribers() -> SyncMutexGuard<'static, Vec<(Arc<ActorStats>, WeakAddr<Server>)>> {
+    SUBSCRIBERS.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Sends a notification to every running [`Server`] without waiting for it to be handled, it is counted as waiting in their mailboxes (see [`ActorStats::sent`]).
+pub async fn publish(notification: ServerNotification) {
+    subscribers().retain(|(stats, server)| match server.upgrade() {
+        Some(server) => {
+            stats.sent();
+            if server.send(notification.clone()).is_err() {
+                stats.unsent();
+            }
+            true
+        }
+        None => false,
+    });
+}
 
 #[async_trait]
 impl Handler<client_command::Connect> for Server {
-    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: client_command::Connect) -> u128 {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        msg: client_command::Connect,
+    ) -> Result<u128> {
+        let _timer = self.instrumentation.server.clone().start();
         let mut connection_set = self.connected.write().await;
+        let mut connection_users = self.connection_users.write().await;
+        if let Some(max) = crate::quotas::limits().max_connections_per_user {
+            let open = connection_users
+                .values()
+                .filter(|user_id| **user_id == msg.user_id)
+                .count();
+            if open >= max {
+                return Err(Error::TooManyConnections(max));
+            }
+        }
         let mut id = rand::random::<u128>();
         while connection_set.contains_key(&id) {
             id = rand::random::<u128>();
         }
-        connection_set.insert(id, msg.websocket_writer);
-        id
+        connection_set.insert(
+            id,
+            Arc::new(Mutex::new(ConnectionOutbox::new(msg.websocket_writer))),
+        );
+        connection_users.insert(id, msg.user_id);
+        Ok(id)
     }
 }
 
 #[async_trait]
 impl Handler<client_command::Disconnect> for Server {
     async fn handle(&mut self, _ctx: &mut Context<Self>, msg: client_command::Disconnect) {
-        if let Some(subscribed) = self.subscribed.write().await.remove(&msg.connection_id) {
-            let subscribed = subscribed.write().await;
-            let subscribed_channels = self.subscribed_channels.write().await;
-            for channel in subscribed.0.iter() {
-                if let Some(subs) = subscribed_channels.get(&channel) {
-                    subs.write().await.remove(&msg.connection_id);
-                }
-            }
-            drop(subscribed_channels);
-            let subscribed_hubs = self.subscribed_hubs.write().await;
-            for hub in subscribed.1.iter() {
-                if let Some(subs) = subscribed_hubs.get(&hub) {
-                    subs.write().await.remove(&msg.connection_id);
-                }
-            }
-            drop(subscribed_hubs);
-            self.connected.write().await.remove(&msg.connection_id);
+        let _timer = self.instrumentation.server.clone().start();
+        for (hub_id, channel_id, user_id) in self.disconnect(msg.connection_id).await {
+            let _ = self
+                .send_channel(
+                    ServerMessage::UserStoppedTyping {
+                        user_id,
+                        hub_id,
+                        channel_id,
+                    },
+                    hub_id,
+                    channel_id,
+                )
+                .await;
         }
     }
 }
@@ -553,26 +2032,25 @@ impl Handler<client_command::SubscribeHub> for Server {
         _ctx: &mut Context<Self>,
         msg: client_command::SubscribeHub,
     ) -> Result {
+        let _timer = self.instrumentation.server.clone().start();
         Hub::load(msg.hub_id)
             .await
             .and_then(|hub| Ok(hub.get_member(&msg.user_id)?.clone()))?;
-        self.subscribed
-            .write()
-            .await
-            .entry(msg.connection_id)
-            .or_default()
-            .write()
-            .await
-            .1
-            .insert(msg.hub_id);
-        self.subscribed_hubs
-            .write()
-            .await
-            .entry(msg.hub_id)
-            .or_default()
-            .write()
-            .await
-            .insert(msg.connection_id);
+        {
+            let mut subscribed = self.subscribed.write().await;
+            let mut subscriptions = subscribed
+                .entry(msg.connection_id)
+                .or_default()
+                .write()
+                .await;
+            if let Some(max) = crate::quotas::limits().max_hub_subscriptions_per_connection {
+                if !subscriptions.1.contains(&msg.hub_id) && subscriptions.1.len() >= max {
+                    return Err(Error::TooManyHubSubscriptions(max));
+                }
+            }
+            subscriptions.1.insert(msg.hub_id);
+        }
+        add_subscriber(&self.subscribed_hubs, msg.hub_id, msg.connection_id).await;
         Ok(())
     }
 }
@@ -580,12 +2058,12 @@ impl Handler<client_command::SubscribeHub> for Server {
 #[async_trait]
 impl Handler<client_command::UnsubscribeHub> for Server {
     async fn handle(&mut self, _ctx: &mut Context<Self>, msg: client_command::UnsubscribeHub) {
-        if let Some(subs) = self.subscribed.write().await.get(&msg.connection_id) {
-            subs.write().await.1.remove(&msg.hub_id);
-        }
-        if let Some(subs) = self.subscribed_hubs.write().await.get(&msg.hub_id) {
-            subs.write().await.remove(&msg.connection_id);
-        }
+        let _timer = self.instrumentation.server.clone().start();
+        self.remove_subscription(msg.connection_id, |subscriptions| {
+            subscriptions.1.remove(&msg.hub_id);
+        })
+        .await;
+        remove_subscriber(&self.subscribed_hubs, &msg.hub_id, msg.connection_id).await;
     }
 }
 
@@ -596,26 +2074,42 @@ impl Handler<client_command::SubscribeChannel> for Server {
         _ctx: &mut Context<Self>,
         msg: client_command::SubscribeChannel,
     ) -> Result {
+        let _timer = self.instrumentation.server.clone().start();
         Hub::load(msg.hub_id)
-            .await
-            .and_then(|hub| {
-                if let Ok(member) = hub.get_member(&msg.user_id) {
-                    let member = member.clone();
-                    Ok((hub, member))
-                } else {
-                    Err(Error::MemberNotFound)
-                }
-            })
-            .and_then(|(hub, user)| {
-                check_permission!(
-                    user,
-                    msg.channel_id,
-                    crate::permission::ChannelPermission::Read,
-                    hub
-                );
-                Ok(())
-            })?;
+            .await?
+            .check_can_read_channel(&msg.user_id, msg.channel_id)?;
         let key = (msg.hub_id, msg.channel_id);
+        if let Some(max) = crate::quotas::limits().max_channel_subscriptions_per_connection {
+            if let Some(subscriptions) = self.subscribed.read().await.get(&msg.connection_id) {
+                let subscriptions = subscriptions.read().await;
+                if !subscriptions.0.contains(&key) && subscriptions.0.len() >= max {
+                    return Err(Error::TooManyChannelSubscriptions(max));
+                }
+            }
+        }
+        if msg.with_snapshot {
+            let mut messages = crate::api::get_messages(
+                &msg.user_id,
+                msg.hub_id,
+                msg.channel_id,
+                DateTime::<Utc>::from(std::time::UNIX_EPOCH),
+                Utc::now(),
+                true,
+                crate::SNAPSHOT_MESSAGES,
+            )
+            .await?;
+            messages.reverse();
+            self.send_to(
+                ServerMessage::ChannelSnapshot {
+                    hub_id: msg.hub_id,
+                    channel_id: msg.channel_id,
+                    messages,
+                    typing: self.typing_users(key).await,
+                },
+                vec![msg.connection_id],
+            )
+            .await?;
+        }
         self.subscribed
             .write()
             .await
@@ -625,28 +2119,103 @@ impl Handler<client_command::SubscribeChannel> for Server {
             .await
             .0
             .insert(key);
-        self.subscribed_channels
-            .write()
-            .await
-            .entry(key)
-            .or_default()
-            .write()
-            .await
-            .insert(msg.connection_id);
+        add_subscriber(&self.subscribed_channels, key, msg.connection_id).await;
+        self.channel_events.write().await.open(key);
         Ok(())
     }
 }
 
+#[async_trait]
+impl Handler<client_command::ResumeProfile> for Server {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        msg: client_command::ResumeProfile,
+    ) -> Result<SubscriptionProfile> {
+        let _timer = self.instrumentation.server.clone().start();
+        let profile = SubscriptionProfile::load(&msg.user_id).await?;
+        let mut allowed = SubscriptionProfile::default();
+        let mut denied = SubscriptionProfile::default();
+        for (hub_id, (with_hub, channels)) in profile.by_hub() {
+            let hub = Hub::load(hub_id)
+                .await
+                .ok()
+                .filter(|hub| hub.get_member(&msg.user_id).is_ok());
+            if with_hub {
+                if hub.is_some() {
+                    allowed.hubs.insert(hub_id);
+                } else {
+                    denied.hubs.insert(hub_id);
+                }
+            }
+            for channel_id in channels {
+                let readable = hub.as_ref().is_some_and(|hub| {
+                    hub.check_can_read_channel(&msg.user_id, channel_id).is_ok()
+                });
+                if readable {
+                    allowed.channels.insert((hub_id, channel_id));
+                } else {
+                    denied.channels.insert((hub_id, channel_id));
+                }
+            }
+        }
+        let limits = crate::quotas::limits();
+        let mut hubs = Vec::new();
+        let mut channels = Vec::new();
+        {
+            let mut subscribed = self.subscribed.write().await;
+            let mut subscriptions = subscribed
+                .entry(msg.connection_id)
+                .or_default()
+                .write()
+                .await;
+            for hub_id in allowed.hubs {
+                if subscriptions.1.contains(&hub_id)
+                    || limits
+                        .max_hub_subscriptions_per_connection
+                        .is_none_or(|max| subscriptions.1.len() < max)
+                {
+                    subscriptions.1.insert(hub_id);
+                    hubs.push(hub_id);
+                } else {
+                    denied.hubs.insert(hub_id);
+                }
+            }
+            for key in allowed.channels {
+                if subscriptions.0.contains(&key)
+                    || limits
+                        .max_channel_subscriptions_per_connection
+                        .is_none_or(|max| subscriptions.0.len() < max)
+                {
+                    subscriptions.0.insert(key);
+                    channels.push(key);
+                } else {
+                    denied.channels.insert(key);
+                }
+            }
+        }
+        for hub_id in hubs {
+            add_subscriber(&self.subscribed_hubs, hub_id, msg.connection_id).await;
+        }
+        for key in channels {
+            add_subscriber(&self.subscribed_channels, key, msg.connection_id).await;
+            self.channel_events.write().await.open(key);
+        }
+        Ok(denied)
+    }
+}
+
 #[async_trait]
 impl Handler<client_command::UnsubscribeChannel> for Server {
     async fn handle(&mut self, _ctx: &mut Context<Self>, msg: client_command::UnsubscribeChannel) {
+        let _timer = self.instrumentation.server.clone().start();
         let key = (msg.hub_id, msg.channel_id);
-        if let Some(subs) = self.subscribed.write().await.get(&msg.connection_id) {
-            subs.write().await.0.remove(&key);
-        }
-        if let Some(subs) = self.subscribed_channels.write().await.get(&key) {
-            subs.write().await.remove(&msg.connection_id);
-        }
+        self.remove_subscription(msg.connection_id, |subscriptions| {
+            subscriptions.0.remove(&key);
+        })
+        .await;
+        self.remove_channel_subscriber(&key, msg.connection_id)
+            .await;
     }
 }
 
@@ -657,6 +2226,7 @@ impl Handler<client_command::StartTyping> for Server {
         _ctx: &mut Context<Self>,
         msg: client_command::StartTyping,
     ) -> Result {
+        let _timer = self.instrumentation.server.clone().start();
         Hub::load(msg.hub_id)
             .await
             .and_then(|hub| {
@@ -676,6 +2246,14 @@ impl Handler<client_command::StartTyping> for Server {
                 );
                 Ok(())
             })?;
+        {
+            let mut typing = self.typing.write().await;
+            let users = typing.entry((msg.hub_id, msg.channel_id)).or_default();
+            if users.contains_key(&msg.user_id) {
+                return Err(Error::AlreadyTyping);
+            }
+            users.insert(msg.user_id.clone(), msg.connection_id);
+        }
         let _ = self
             .send_channel(
                 ServerMessage::UserStartedTyping {
@@ -698,6 +2276,7 @@ impl Handler<client_command::StopTyping> for Server {
         _ctx: &mut Context<Self>,
         msg: client_command::StopTyping,
     ) -> Result {
+        let _timer = self.instrumentation.server.clone().start();
         Hub::load(msg.hub_id)
             .await
             .and_then(|hub| {
@@ -717,6 +2296,15 @@ impl Handler<client_command::StopTyping> for Server {
                 );
                 Ok(())
             })?;
+        {
+            let key = (msg.hub_id, msg.channel_id);
+            let mut typing = self.typing.write().await;
+            let users = typing.get_mut(&key).ok_or(Error::NotTyping)?;
+            users.remove(&msg.user_id).ok_or(Error::NotTyping)?;
+            if users.is_empty() {
+                typing.remove(&key);
+            }
+        }
         let _ = self
             .send_channel(
                 ServerMessage::UserStoppedTyping {
@@ -732,9 +2320,132 @@ impl Handler<client_command::StopTyping> for Server {
     }
 }
 
+#[async_trait]
+impl Handler<client_command::QuerySubscriptions> for Server {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        msg: client_command::QuerySubscriptions,
+    ) -> (Vec<HubId>, Vec<(HubId, ChannelId)>) {
+        let _timer = self.instrumentation.server.clone().start();
+        let subscribed = self
+            .subscribed
+            .read()
+            .await
+            .get(&msg.connection_id)
+            .cloned();
+        match subscribed {
+            Some(subscribed) => {
+                let subscribed = subscribed.read().await;
+                let mut hubs: Vec<HubId> = subscribed.1.iter().copied().collect();
+                hubs.sort();
+                let mut channels: Vec<(HubId, ChannelId)> = subscribed.0.iter().copied().collect();
+                channels.sort();
+                (hubs, channels)
+            }
+            None => (Vec::new(), Vec::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl Handler<client_command::SetIntents> for Server {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: client_command::SetIntents) {
+        let _timer = self.instrumentation.server.clone().start();
+        let mut intents = self.intents.write().await;
+        if msg.intents == Intents::default() {
+            intents.remove(&msg.connection_id);
+        } else {
+            intents.insert(msg.connection_id, msg.intents);
+        }
+    }
+}
+
+#[async_trait]
+impl Handler<client_command::AckMessages> for Server {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        msg: client_command::AckMessages,
+    ) -> Result {
+        let _timer = self.instrumentation.server.clone().start();
+        if msg.message_ids.len() > MAX_ACKS_PER_COMMAND {
+            return Err(Error::TooManyAcks(MAX_ACKS_PER_COMMAND));
+        }
+        let subscribed = match self
+            .subscribed_channels
+            .read()
+            .await
+            .get(&(msg.hub_id, msg.channel_id))
+        {
+            Some(subscribers) => subscribers.read().await.contains(&msg.connection_id),
+            None => false,
+        };
+        if !subscribed {
+            return Ok(());
+        }
+        for message_id in msg.message_ids {
+            let sender = self
+                .pending_deliveries
+                .acknowledge(&(msg.hub_id, msg.channel_id, message_id), &msg.user_id);
+            if let Some(sender) = sender {
+                let connection_ids = self.delivery_report_connections(&sender).await;
+                if connection_ids.is_empty() {
+                    continue;
+                }
+                self.send_to(
+                    ServerMessage::Delivered {
+                        hub_id: msg.hub_id,
+                        channel_id: msg.channel_id,
+                        message_id,
+                    },
+                    connection_ids,
+                )
+                .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Handler<client_command::QueryTyping> for Server {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        msg: client_command::QueryTyping,
+    ) -> Result<Vec<String>> {
+        let _timer = self.instrumentation.server.clone().start();
+        Hub::load(msg.hub_id)
+            .await?
+            .check_can_read_channel(&msg.user_id, msg.channel_id)?;
+        Ok(self.typing_users((msg.hub_id, msg.channel_id)).await)
+    }
+}
+
+#[async_trait]
+impl Handler<client_command::EventsSince> for Server {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        msg: client_command::EventsSince,
+    ) -> Result<EventsSince> {
+        let _timer = self.instrumentation.server.clone().start();
+        Hub::load(msg.hub_id)
+            .await?
+            .check_can_read_channel(&msg.user_id, msg.channel_id)?;
+        Ok(self.channel_events.read().await.since(
+            &(msg.hub_id, msg.channel_id),
+            msg.epoch,
+            msg.seq,
+        ))
+    }
+}
+
 #[async_trait]
 impl Handler<ServerNotification> for Server {
-    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: ServerNotification) {
+    async fn handle(&mut self, ctx: &mut Context<Self>, msg: ServerNotification) {
+        let _timer = self.instrumentation.server.clone().start();
         match msg {
             ServerNotification::NewMessage(
                 hub_id,
@@ -742,40 +2453,272 @@ impl Handler<ServerNotification> for Server {
                 message_id,
                 armoured_message,
                 message,
+            ) => {
+                // Not waited for, indexing can fall behind without slowing down delivery, see `message_pipeline::send`.
+                if let Err(err) =
+                    self.message_server
+                        .send(QueueMessageForIndex(NewMessageForIndex {
+                            hub_id,
+                            channel_id,
+                            message: message.clone(),
+                        }))
+                {
+                    error!("Unable to send a message to be indexed: {}", err);
+                }
+                let _ = self
+                    .send_channel_event(hub_id, channel_id, |event_epoch, event_seq| {
+                        ServerMessage::ChatMessage {
+                            hub_id,
+                            channel_id,
+                            message_id,
+                            armoured_message,
+                            flags: message.flags,
+                            moved: false,
+                            author_type: message.author_type,
+                            event_seq,
+                            event_epoch,
+                        }
+                    })
+                    .await;
+                if !message.group_mentions.is_empty() && !message.has_flags(Message::SILENT) {
+                    self.send_mentions(&message).await;
+                }
+                if !self
+                    .delivery_report_connections(&message.sender)
+                    .await
+                    .is_empty()
+                {
+                    self.pending_deliveries
+                        .push((hub_id, channel_id, message_id), message.sender);
+                }
+            }
+            ServerNotification::MessageMoved(
+                hub_id,
+                channel_id,
+                message_id,
+                armoured_message,
+                message,
+            ) => {
+                // Indexed under the channel it was moved into, mentions were already sent when it was first sent.
+                if let Err(err) =
+                    self.message_server
+                        .send(QueueMessageForIndex(NewMessageForIndex {
+                            hub_id,
+                            channel_id,
+                            message: message.clone(),
+                        }))
+                {
+                    error!("Unable to send a message to be indexed: {}", err);
+                }
+                let _ = self
+                    .send_channel_event(hub_id, channel_id, |event_epoch, event_seq| {
+                        ServerMessage::ChatMessage {
+                            hub_id,
+                            channel_id,
+                            message_id,
+                            armoured_message,
+                            flags: message.flags,
+                            moved: true,
+                            author_type: message.author_type,
+                            event_seq,
+                            event_epoch,
+                        }
+                    })
+                    .await;
+            }
+            ServerNotification::MessageEdited(
+                hub_id,
+                channel_id,
+                message_id,
+                armoured_message,
+                message,
             ) => {
                 let _ = self
                     .message_server
-                    .call(NewMessageForIndex {
+                    .call(EditedMessageForIndex {
                         hub_id,
                         channel_id,
                         message,
                     })
                     .await;
                 let _ = self
-                    .send_channel(
-                        ServerMessage::ChatMessage {
+                    .send_channel_event(hub_id, channel_id, |event_epoch, event_seq| {
+                        ServerMessage::ChatMessageEdited {
                             hub_id,
                             channel_id,
                             message_id,
                             armoured_message,
-                        },
+                            event_seq,
+                            event_epoch,
+                        }
+                    })
+                    .await;
+            }
+            ServerNotification::MessagesDeleted(hub_id, channel_id, message_ids) => {
+                let _ = self
+                    .message_server
+                    .call(DeletedMessagesForIndex {
                         hub_id,
                         channel_id,
-                    )
+                        message_ids: message_ids.clone(),
+                    })
+                    .await;
+                let _ = self
+                    .send_channel_event(hub_id, channel_id, |event_epoch, event_seq| {
+                        ServerMessage::MessagesDeleted {
+                            hub_id,
+                            channel_id,
+                            message_ids,
+                            event_seq,
+                            event_epoch,
+                        }
+                    })
                     .await;
             }
-            ServerNotification::HubUpdated(hub_id, update_type) => {
+            ServerNotification::HubDeletionRequested(user_id, hub_id, expires) => {
+                let connection_ids = self.user_connections(&user_id).await;
+                if !connection_ids.is_empty() {
+                    let _ = self
+                        .send_to(
+                            ServerMessage::HubDeletionRequested { hub_id, expires },
+                            connection_ids,
+                        )
+                        .await;
+                }
+            }
+            ServerNotification::DraftUpdated(user_id, hub_id, channel_id, content) => {
+                let connection_ids = self.user_connections(&user_id).await;
+                if !connection_ids.is_empty() {
+                    let _ = self
+                        .send_to(
+                            ServerMessage::DraftUpdated {
+                                hub_id,
+                                channel_id,
+                                content,
+                            },
+                            connection_ids,
+                        )
+                        .await;
+                }
+            }
+            ServerNotification::LinkPreviews(hub_id, channel_id, message_id, link_previews) => {
                 let _ = self
-                    .send_hub(
-                        ServerMessage::HubUpdated {
+                    .send_channel(
+                        ServerMessage::LinkPreviews {
                             hub_id,
-                            update_type,
+                            channel_id,
+                            message_id,
+                            link_previews,
                         },
-                        &hub_id,
+                        hub_id,
+                        channel_id,
                     )
                     .await;
             }
+            ServerNotification::HubUpdated(hub_id, update_type, version) => {
+                let removed = match &update_type {
+                    HubUpdateType::ChannelDeleted(channel_id) => {
+                        self.channel_events
+                            .write()
+                            .await
+                            .remove_channel(&(hub_id, *channel_id));
+                        self.message_server
+                            .call(ChannelDeletedForIndex {
+                                hub_id,
+                                channel_id: *channel_id,
+                            })
+                            .await
+                    }
+                    HubUpdateType::HubDeleted => {
+                        self.channel_events.write().await.remove_hub(hub_id);
+                        self.message_server
+                            .call(HubDeletedForIndex { hub_id })
+                            .await
+                    }
+                    _ => Ok(Ok(())),
+                };
+                match removed {
+                    Ok(Err(err)) => error!(
+                        "Unable to remove the data of {:?} in hub {}: {}",
+                        update_type,
+                        hub_id,
+                        err.chain()
+                    ),
+                    Err(err) => error!("Unable to reach the message server: {}", err),
+                    Ok(Ok(())) => {}
+                }
+                if update_type.may_revoke_access() {
+                    self.revalidate_channel_subscriptions(hub_id).await;
+                }
+                match &update_type {
+                    HubUpdateType::UserKicked(user_id) | HubUpdateType::UserBanned(user_id) => {
+                        self.remove_from_hub(hub_id, user_id).await
+                    }
+                    _ => {}
+                }
+                // Most hubs have nobody subscribed, their updates are not collected at all.
+                let subscribed = match self.subscribed_hubs.read().await.get(&hub_id) {
+                    Some(subscribers) => !subscribers.read().await.is_empty(),
+                    None => false,
+                };
+                if subscribed && self.pending_hub_updates.push(hub_id, update_type, version) {
+                    ctx.send_later(
+                        FlushHubUpdates(hub_id),
+                        Duration::from_millis(HUB_UPDATE_COALESCE_MS),
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Handler<FlushHubUpdates> for Server {
+    /// Sends the collected updates of a hub to its subscribers. Writing to the connections is done in a separate task, [`HUB_UPDATE_FANOUT_CONCURRENCY`] at a time, so that a hub with many subscribers does not hold up the server; connections that could not be written to are disconnected afterwards.
+    async fn handle(&mut self, ctx: &mut Context<Self>, msg: FlushHubUpdates) {
+        let _timer = self.instrumentation.server.clone().start();
+        let hub_id = msg.0;
+        let message = match self.pending_hub_updates.take(hub_id) {
+            Some(message) => message,
+            None => return,
+        };
+        let connection_ids: HashSet<u128> = match self.subscribed_hubs.read().await.get(&hub_id) {
+            Some(subscribers) => subscribers.read().await.clone(),
+            None => return,
+        };
+        if connection_ids.is_empty() {
+            return;
         }
+        self.check_fanout(&[hub_id], &[], connection_ids.len());
+        let frame = match self.sign(&message) {
+            Ok(frame) => frame,
+            Err(err) => {
+                error!(
+                    "Unable to sign the updates of hub {}: {}",
+                    hub_id,
+                    err.chain()
+                );
+                return;
+            }
+        };
+        let connections = {
+            let connected = self.connected.read().await;
+            connection_ids
+                .into_iter()
+                .map(|connection_id| (connection_id, connected.get(&connection_id).cloned()))
+                .collect::<Vec<_>>()
+        };
+        let fanout = self.instrumentation.fanout.clone();
+        let server = InstrumentedAddr::new(ctx.address(), self.instrumentation.server.clone());
+        tokio::spawn(async move {
+            let started = Instant::now();
+            let count = connections.len();
+            let dead = deliver(connections, frame, HUB_UPDATE_FANOUT_CONCURRENCY).await;
+            fanout.record(count, started.elapsed());
+            for connection_id in dead {
+                let _ = server.send(client_command::Disconnect { connection_id });
+            }
+        });
     }
 }
 
@@ -785,7 +2728,496 @@ impl Handler<GetMessageServer> for Server {
         &mut self,
         _ctx: &mut Context<Self>,
         _msg: GetMessageServer,
-    ) -> Addr<MessageServer> {
+    ) -> InstrumentedAddr<MessageServer> {
+        let _timer = self.instrumentation.server.clone().start();
         self.message_server.clone()
     }
 }
+
+#[async_trait]
+impl Handler<CloseConnections> for Server {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: CloseConnections) {
+        let _timer = self.instrumentation.server.clone().start();
+        let code = msg.0;
+        let connection_ids: Vec<u128> = self.connected.read().await.keys().copied().collect();
+        let _ = self
+            .send_to(
+                ServerMessage::Closing {
+                    code: code.code(),
+                    detail: code.reason().to_string(),
+                },
+                connection_ids.clone(),
+            )
+            .await;
+        for connection_id in connection_ids {
+            let connection = self.connected.read().await.get(&connection_id).cloned();
+            if let Some(connection) = connection {
+                let outbox = connection.lock().await;
+                let _ = outbox.writer.lock().await.send(code.close_frame()).await;
+            }
+            self.disconnect(connection_id).await;
+        }
+    }
+}
+
+#[async_trait]
+impl Handler<Ping> for Server {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Ping) {
+        let _timer = self.instrumentation.server.clone().start();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, HashSet};
+
+    use tokio::sync::RwLock;
+
+    #[cfg(feature = "search")]
+    use super::PendingMessages;
+    use super::{
+        add_subscriber, remove_subscriber, remove_typing_connection, remove_typing_user,
+        unreadable_subscriptions, DeliveredEvents, HubUpdateType, PendingHubUpdates,
+    };
+    #[cfg(feature = "search")]
+    use crate::MessageId;
+    use crate::{
+        channel::Channel, hub::Hub, permission::ChannelPermission, websocket::ServerMessage,
+        ChannelId, HubId,
+    };
+
+    #[tokio::test]
+    async fn empty_subscriber_entries_are_removed() {
+        let map = RwLock::new(HashMap::new());
+        for connection_id in 0..10_000u128 {
+            add_subscriber(&map, connection_id % 7, connection_id).await;
+        }
+        assert_eq!(map.read().await.len(), 7);
+        for connection_id in 0..10_000u128 {
+            remove_subscriber(&map, &(connection_id % 7), connection_id).await;
+        }
+        assert!(map.read().await.is_empty());
+    }
+
+    #[test]
+    fn typing_removed_on_disconnect() {
+        let hub_id = HubId::from_u128(1);
+        let first = (hub_id, ChannelId::from_u128(2));
+        let second = (hub_id, ChannelId::from_u128(3));
+        let mut typing = HashMap::new();
+        typing
+            .entry(first)
+            .or_insert_with(HashMap::new)
+            .insert("a".to_string(), 1);
+        typing.entry(first).or_default().insert("b".to_string(), 2);
+        typing.entry(second).or_default().insert("a".to_string(), 1);
+        let mut removed = remove_typing_connection(&mut typing, 1);
+        removed.sort();
+        assert_eq!(
+            removed,
+            vec![
+                (first.0, first.1, "a".to_string()),
+                (second.0, second.1, "a".to_string())
+            ]
+        );
+        assert_eq!(typing.len(), 1);
+        assert_eq!(typing[&first].len(), 1);
+        assert!(remove_typing_connection(&mut typing, 1).is_empty());
+        // Kicked from the hub while typing on another connection.
+        assert_eq!(remove_typing_user(&mut typing, hub_id, "b"), vec![first.1]);
+        assert!(typing.is_empty());
+    }
+
+    #[test]
+    fn revoked_read_drops_subscription() {
+        let hub_id = HubId::from_u128(1);
+        let channel_id = ChannelId::from_u128(2);
+        let key = (hub_id, channel_id);
+        let mut hub = Hub::new("hub".to_string(), hub_id, "owner".to_string());
+        hub.channels.insert(
+            channel_id,
+            Channel::new("chat".to_string(), channel_id, hub_id),
+        );
+        hub.user_join("member".to_string()).unwrap();
+        hub.get_member_mut("member")
+            .unwrap()
+            .set_channel_permission(channel_id, ChannelPermission::Read, Some(true));
+        let mut users = HashMap::new();
+        users.insert(1, "owner".to_string());
+        users.insert(2, "member".to_string());
+        let subscriptions = vec![(key, 1), (key, 2)];
+        assert!(unreadable_subscriptions(Some(&hub), subscriptions.clone(), &users).is_empty());
+        // Read is revoked while both connections are subscribed.
+        hub.get_member_mut("member")
+            .unwrap()
+            .set_channel_permission(channel_id, ChannelPermission::Read, Some(false));
+        assert_eq!(
+            unreadable_subscriptions(Some(&hub), subscriptions.clone(), &users),
+            vec![(key, 2)]
+        );
+        assert_eq!(
+            unreadable_subscriptions(None, subscriptions.clone(), &users),
+            subscriptions
+        );
+        assert_eq!(
+            unreadable_subscriptions(Some(&hub), vec![(key, 3)], &users),
+            vec![(key, 3)]
+        );
+    }
+
+    #[cfg(feature = "search")]
+    /// Feeds `count` messages through [`PendingMessages::push`] and returns the (1 based) numbers of the messages that caused a commit.
+    fn commits(threshold: usize, count: u128) -> Vec<u128> {
+        let mut pending = PendingMessages::new(MessageId::nil());
+        let mut result = Vec::new();
+        for n in 1..=count {
+            if pending.push(MessageId::from_u128(n), threshold) {
+                assert_eq!(pending.last, MessageId::from_u128(n));
+                pending.commited();
+                result.push(n);
+            }
+        }
+        result
+    }
+
+    #[cfg(feature = "search")]
+    #[test]
+    fn commit_threshold() {
+        assert_eq!(commits(0, 3), vec![1, 2, 3]);
+        assert_eq!(commits(1, 3), vec![1, 2, 3]);
+        assert_eq!(commits(3, 7), vec![3, 6]);
+        assert_eq!(commits(usize::MAX, 300), Vec::<u128>::new());
+    }
+
+    #[cfg(feature = "search")]
+    #[test]
+    fn count_saturates() {
+        let mut pending = PendingMessages::new(MessageId::nil());
+        pending.count = usize::MAX - 1;
+        assert!(pending.push(MessageId::nil(), usize::MAX));
+        assert!(pending.push(MessageId::nil(), usize::MAX));
+        assert_eq!(pending.count, usize::MAX);
+        pending.commited();
+        assert_eq!(pending.count, 0);
+    }
+
+    #[test]
+    fn overlapping_subscriptions_deliver_once() {
+        // Connection 1 is subscribed to the hub and to the channel, 2 only to the channel.
+        let recipients = [1u128, 1, 2];
+        let mut delivered: HashMap<u128, DeliveredEvents> = HashMap::new();
+        let mut received: HashMap<u128, Vec<u64>> = HashMap::new();
+        for event_id in 1..=3 {
+            for connection_id in recipients.iter() {
+                if delivered.entry(*connection_id).or_default().admit(event_id) {
+                    received.entry(*connection_id).or_default().push(event_id);
+                }
+            }
+        }
+        assert_eq!(received[&1], vec![1, 2, 3]);
+        assert_eq!(received[&2], vec![1, 2, 3]);
+        assert!(!delivered.get_mut(&1).unwrap().admit(2));
+    }
+
+    #[cfg(feature = "search")]
+    #[test]
+    fn most_recent_channels() {
+        use chrono::{Duration, Utc};
+
+        let now = Utc::now();
+        let hub_id = HubId::random();
+        let mut recent = super::RecentChannels::default();
+        for (n, hours) in [(1, 30), (2, 1), (3, 5), (4, 2)].iter() {
+            recent.0.insert(
+                (hub_id, ChannelId::from_u128(*n)),
+                now - Duration::hours(*hours),
+            );
+        }
+        let ids = |channels: Vec<(HubId, ChannelId)>| {
+            channels
+                .iter()
+                .map(|(_, channel_id)| channel_id.as_u128())
+                .collect::<Vec<u128>>()
+        };
+        assert_eq!(
+            ids(recent.most_recent(10, now - Duration::hours(24))),
+            vec![2, 4, 3]
+        );
+        assert_eq!(
+            ids(recent.most_recent(2, now - Duration::hours(24))),
+            vec![2, 4]
+        );
+    }
+
+    #[cfg(feature = "search")]
+    #[tokio::test]
+    async fn deleted_channel_index_is_removed() {
+        use super::{
+            ChannelDeletedForIndex, MessageServer, NewMessageForIndex, SearchMessageIndex,
+        };
+        use xactor::Actor;
+
+        let hub_id = HubId::random();
+        let channel_id = ChannelId::from_u128(2);
+        let server = MessageServer::default().start().await.unwrap();
+        let search = || SearchMessageIndex {
+            hub_id,
+            channel_id,
+            limit: 10,
+            query: "hello".to_string(),
+            not_before: None,
+        };
+        let message = crate::channel::Message {
+            id: MessageId::from_u128(1),
+            hub_id,
+            channel_id,
+            sender: "0123456789ABCDEF0123456789ABCDEF01234567".to_string(),
+            created: chrono::Utc::now(),
+            content: "hello".to_string(),
+            group_mentions: Vec::new(),
+            forwarded_from: None,
+            flags: 0,
+            author_type: crate::channel::AuthorType::User,
+            override_name: None,
+            override_avatar: None,
+        };
+        server
+            .call(NewMessageForIndex {
+                hub_id,
+                channel_id,
+                message,
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            server.call(search()).await.unwrap().unwrap(),
+            vec![MessageId::from_u128(1)]
+        );
+        server
+            .call(ChannelDeletedForIndex { hub_id, channel_id })
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!crate::paths::channel_dir(hub_id, channel_id).exists());
+        // A channel created again with the same ID starts with an empty index.
+        assert!(server.call(search()).await.unwrap().unwrap().is_empty());
+        let _ = tokio::fs::remove_dir_all(crate::paths::hub_data_dir(hub_id)).await;
+    }
+
+    #[cfg(feature = "search")]
+    #[tokio::test]
+    async fn limited_search_leaves_out_history() {
+        use super::{MessageServer, NewMessageForIndex, SearchMessageIndex};
+        use crate::channel::{Channel, SignedMessage};
+        use chrono::{Duration, Utc};
+        use xactor::Actor;
+
+        let hub_id = HubId::random();
+        let channel_id = ChannelId::from_u128(4);
+        let server = MessageServer::default().start().await.unwrap();
+        let joined = Utc::now();
+        let channel = Channel::new(String::new(), channel_id, hub_id);
+        channel.create_dir().await.unwrap();
+        for (n, created) in [joined - Duration::days(1), joined + Duration::seconds(1)]
+            .iter()
+            .enumerate()
+        {
+            let id = MessageId::from_u128(n as u128 + 1);
+            channel
+                .add_message(SignedMessage::new(id, *created, String::new()))
+                .await
+                .unwrap();
+            server
+                .call(NewMessageForIndex {
+                    hub_id,
+                    channel_id,
+                    message: crate::channel::Message {
+                        id,
+                        hub_id,
+                        channel_id,
+                        sender: "0123456789ABCDEF0123456789ABCDEF01234567".to_string(),
+                        created: *created,
+                        content: "hello".to_string(),
+                        group_mentions: Vec::new(),
+                        forwarded_from: None,
+                        flags: 0,
+                        author_type: crate::channel::AuthorType::User,
+                        override_name: None,
+                        override_avatar: None,
+                    },
+                })
+                .await
+                .unwrap()
+                .unwrap();
+        }
+        let search = |not_before| SearchMessageIndex {
+            hub_id,
+            channel_id,
+            limit: 10,
+            query: "hello".to_string(),
+            not_before,
+        };
+        assert_eq!(server.call(search(None)).await.unwrap().unwrap().len(), 2);
+        assert_eq!(
+            server.call(search(Some(joined))).await.unwrap().unwrap(),
+            vec![MessageId::from_u128(2)]
+        );
+        let _ = tokio::fs::remove_dir_all(crate::paths::hub_data_dir(hub_id)).await;
+    }
+
+    #[cfg(feature = "search")]
+    #[tokio::test]
+    async fn unreadable_index_is_rebuilt() {
+        let hub_id = HubId::random();
+        let channel_id = ChannelId::from_u128(5);
+        let dir_path = crate::paths::channel_index_dir(hub_id, channel_id);
+        tokio::fs::create_dir_all(&dir_path).await.unwrap();
+        tokio::fs::write(dir_path.join("meta.json"), "{\"segments\": [")
+            .await
+            .unwrap();
+        tokio::fs::write(dir_path.join(".tantivy-writer.lock"), "")
+            .await
+            .unwrap();
+        let recoveries = crate::instrumentation::Instrumentation::default()
+            .snapshot()
+            .index_recoveries;
+        super::open_index(hub_id, channel_id).await.unwrap();
+        let mut quarantined = Vec::new();
+        let mut dir = tokio::fs::read_dir(crate::paths::channel_dir(hub_id, channel_id))
+            .await
+            .unwrap();
+        while let Some(entry) = dir.next_entry().await.unwrap() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with("index.corrupt-") {
+                quarantined.push(entry.path());
+            }
+        }
+        assert_eq!(quarantined.len(), 1);
+        assert!(!quarantined[0].join(".tantivy-writer.lock").exists());
+        assert!(
+            crate::instrumentation::Instrumentation::default()
+                .snapshot()
+                .index_recoveries
+                > recoveries
+        );
+        let _ = tokio::fs::remove_dir_all(crate::paths::hub_data_dir(hub_id)).await;
+    }
+
+    /// Every update type, the match in [`hub_update_types_round_trip`] has no wildcard so a new variant does not compile until it is added here.
+    fn all_update_types() -> Vec<HubUpdateType> {
+        let user = "0123456789ABCDEF0123456789ABCDEF01234567".to_string();
+        let channel_id = ChannelId::from_u128(2);
+        vec![
+            HubUpdateType::HubDeleted,
+            HubUpdateType::HubRenamed,
+            HubUpdateType::HubDescriptionUpdated,
+            HubUpdateType::UserJoined(user.clone()),
+            HubUpdateType::UserLeft(user.clone()),
+            HubUpdateType::UserBanned(user.clone()),
+            HubUpdateType::UserMuted(user.clone()),
+            HubUpdateType::UserUnmuted(user.clone()),
+            HubUpdateType::UserUnbanned(user.clone()),
+            HubUpdateType::UserKicked(user.clone()),
+            HubUpdateType::UserHubPermissionChanged(user.clone()),
+            HubUpdateType::UserChannelPermissionChanged(user.clone(), channel_id),
+            HubUpdateType::MemberNicknameChanged(user.clone(), None, Some("nick".to_string())),
+            HubUpdateType::ChannelCreated(channel_id),
+            HubUpdateType::ChannelDeleted(channel_id),
+            HubUpdateType::ChannelRenamed(channel_id),
+            HubUpdateType::ChannelDescriptionUpdated(channel_id),
+            HubUpdateType::HubIconUpdated,
+            HubUpdateType::HubBannerUpdated,
+            HubUpdateType::HubPreviewUpdated,
+            HubUpdateType::GroupMentionableChanged(crate::ID::from_u128(3)),
+            HubUpdateType::NicknamePolicyChanged,
+            HubUpdateType::ChannelPermissionsChanged(channel_id),
+            HubUpdateType::GroupDisplayChanged(crate::ID::from_u128(4)),
+            HubUpdateType::LeaderboardChanged,
+            HubUpdateType::ContentPolicyChanged,
+            HubUpdateType::OwnerChanged(user),
+            HubUpdateType::GroupPermissionsChanged(crate::ID::from_u128(5)),
+        ]
+    }
+
+    #[test]
+    fn hub_update_types_round_trip() {
+        let mut seen = HashSet::new();
+        for update_type in all_update_types() {
+            let name = match &update_type {
+                HubUpdateType::HubDeleted => "HubDeleted",
+                HubUpdateType::HubRenamed => "HubRenamed",
+                HubUpdateType::HubDescriptionUpdated => "HubDescriptionUpdated",
+                HubUpdateType::UserJoined(_) => "UserJoined",
+                HubUpdateType::UserLeft(_) => "UserLeft",
+                HubUpdateType::UserBanned(_) => "UserBanned",
+                HubUpdateType::UserMuted(_) => "UserMuted",
+                HubUpdateType::UserUnmuted(_) => "UserUnmuted",
+                HubUpdateType::UserUnbanned(_) => "UserUnbanned",
+                HubUpdateType::UserKicked(_) => "UserKicked",
+                HubUpdateType::UserHubPermissionChanged(_) => "UserHubPermissionChanged",
+                HubUpdateType::UserChannelPermissionChanged(_, _) => "UserChannelPermissionChanged",
+                HubUpdateType::MemberNicknameChanged(_, _, _) => "MemberNicknameChanged",
+                HubUpdateType::ChannelCreated(_) => "ChannelCreated",
+                HubUpdateType::ChannelDeleted(_) => "ChannelDeleted",
+                HubUpdateType::ChannelRenamed(_) => "ChannelRenamed",
+                HubUpdateType::ChannelDescriptionUpdated(_) => "ChannelDescriptionUpdated",
+                HubUpdateType::HubIconUpdated => "HubIconUpdated",
+                HubUpdateType::HubBannerUpdated => "HubBannerUpdated",
+                HubUpdateType::HubPreviewUpdated => "HubPreviewUpdated",
+                HubUpdateType::GroupMentionableChanged(_) => "GroupMentionableChanged",
+                HubUpdateType::NicknamePolicyChanged => "NicknamePolicyChanged",
+                HubUpdateType::ChannelPermissionsChanged(_) => "ChannelPermissionsChanged",
+                HubUpdateType::GroupDisplayChanged(_) => "GroupDisplayChanged",
+                HubUpdateType::LeaderboardChanged => "LeaderboardChanged",
+                HubUpdateType::ContentPolicyChanged => "ContentPolicyChanged",
+                HubUpdateType::OwnerChanged(_) => "OwnerChanged",
+                HubUpdateType::GroupPermissionsChanged(_) => "GroupPermissionsChanged",
+            };
+            assert!(seen.insert(name), "{} is listed twice", name);
+            // The payload must survive the WebSocket frame that clients receive.
+            let frame = serde_json::to_string(&ServerMessage::HubUpdated {
+                hub_id: HubId::from_u128(1),
+                update_type: update_type.clone(),
+                version: 1,
+            })
+            .unwrap();
+            match serde_json::from_str(&frame).unwrap() {
+                ServerMessage::HubUpdated {
+                    update_type: received,
+                    ..
+                } => assert_eq!(received, update_type),
+                other => panic!("{} was received as {:?}", name, other),
+            }
+        }
+    }
+
+    #[test]
+    fn coalesce_hub_updates() {
+        let (hub_id, other_hub) = (HubId::from_u128(1), HubId::from_u128(2));
+        let mut pending = PendingHubUpdates::default();
+        assert!(pending.push(hub_id, HubUpdateType::HubRenamed, 3));
+        assert!(!pending.push(hub_id, HubUpdateType::HubIconUpdated, 4));
+        assert!(!pending.push(hub_id, HubUpdateType::HubRenamed, 5));
+        assert!(pending.push(other_hub, HubUpdateType::HubRenamed, 1));
+        match pending.take(hub_id) {
+            Some(ServerMessage::HubUpdates {
+                update_types,
+                version,
+                ..
+            }) => {
+                assert_eq!(
+                    update_types,
+                    vec![HubUpdateType::HubRenamed, HubUpdateType::HubIconUpdated]
+                );
+                assert_eq!(version, 5);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(pending.take(hub_id).is_none());
+        assert!(matches!(
+            pending.take(other_hub),
+            Some(ServerMessage::HubUpdated { version: 1, .. })
+        ));
+    }
+}
@@ -1,8 +1,12 @@
 use std::convert::TryFrom;
 
 use crate::error::Result;
-use crate::{channel::Message, error::Error};
-use chrono::{Duration, Utc};
+use crate::{
+    channel::Message,
+    clock::{Clock, SystemClock},
+    error::Error,
+};
+use chrono::{DateTime, Duration, Utc};
 use pgp::crypto::{hash::HashAlgorithm, sym::SymmetricKeyAlgorithm};
 use pgp::packet::LiteralData;
 use pgp::types::KeyTrait;
@@ -98,7 +102,7 @@ impl Message {
     }
 
     pub fn from_double_signed(message: &str) -> Result<Self> {
-        let client_signed = OpenPGPMessage::from_string(message)?.0;
+        let client_signed = OpenPGPMessage::from_string(message)?.0.decompress()?;
         if let Some(d) = client_signed.get_literal() {
             if let Some(s) = d.to_string() {
                 return Message::try_from(OpenPGPMessage::from_string(&s)?.0);
@@ -114,6 +118,8 @@ impl Message {
     ) -> Result<Self> {
         let client_signed = OpenPGPMessage::from_string(message_str)?.0;
         client_signed.verify(client_public_key)?;
+        // Clients may compress the message they sign, see [`Message::sign_final`].
+        let client_signed = client_signed.decompress()?;
         if let Some(d) = client_signed.get_literal() {
             if let Some(s) = d.to_string() {
                 let server_signed = OpenPGPMessage::from_string(&s)?.0;
@@ -214,8 +220,7 @@ pub async fn get_or_import_public_key(
         return Err(Error::InvalidFingerprint);
     }
     let fingerprint = hex::encode_upper(fingerprint);
-    let file_name = format!("{}{}.asc", USER_PUBLIC_KEY_FOLDER, fingerprint);
-    let path = std::path::Path::new(&file_name);
+    let path = crate::paths::user_public_key_file(&fingerprint);
     if path.is_file() {
         Ok(SignedPublicKey::from_string(&tokio::fs::read_to_string(path).await?)?.0)
     } else {
@@ -241,10 +246,39 @@ pub async fn get_or_import_public_key(
     }
 }
 
+/// Seconds a signature can be dated ahead of the server's clock, to allow for clocks that are slightly off.
+pub const MAX_SIGNATURE_CLOCK_SKEW: i64 = 10;
+
+/// Checks that a signature was not made in the future, up to [`MAX_SIGNATURE_CLOCK_SKEW`] seconds ahead of `clock` are allowed.
+///
+/// # Errors
+///
+/// This function returns [`Error::InvalidMessage`] if the signature is dated too far ahead.
+pub fn check_signature_time(created: &DateTime<Utc>, clock: &dyn Clock) -> Result {
+    let latest = clock
+        .now()
+        .checked_add_signed(Duration::seconds(MAX_SIGNATURE_CLOCK_SKEW))
+        .ok_or(Error::UnexpectedServerArg)?;
+    if created > &latest {
+        Err(Error::InvalidMessage)
+    } else {
+        Ok(())
+    }
+}
+
 pub fn verify_message_extract(
     public_key: &SignedPublicKey,
     message: &str,
 ) -> Result<(String, String)> {
+    let (content, user_id) = verify_message_extract_bytes(public_key, message)?;
+    Ok((String::from_utf8(content)?, user_id))
+}
+
+/// Same as [`verify_message_extract`] but returns the content as bytes, for binary content such as images.
+pub fn verify_message_extract_bytes(
+    public_key: &SignedPublicKey,
+    message: &str,
+) -> Result<(Vec<u8>, String)> {
     let message = OpenPGPMessage::from_string(message)?.0;
     message.verify(&public_key)?;
     let message = message.decompress()?;
@@ -254,19 +288,38 @@ pub fn verify_message_extract(
         signature,
     } = message
     {
-        let sig_time = signature.created().ok_or(Error::InvalidMessage)?;
-        let expire = Utc::now()
-            .checked_add_signed(Duration::seconds(10))
-            .ok_or(Error::UnexpectedServerArg)?;
-        if sig_time > &expire {
-            return Err(Error::InvalidMessage);
-        }
+        check_signature_time(
+            signature.created().ok_or(Error::InvalidMessage)?,
+            &SystemClock,
+        )?;
         let message = message.ok_or(Error::InvalidMessage)?;
         let literal_message = message.get_literal().ok_or(Error::InvalidMessage)?;
 
-        let content = String::from_utf8(literal_message.data().to_vec())?;
-        Ok((content, hex::encode_upper(public_key.fingerprint())))
+        Ok((
+            literal_message.data().to_vec(),
+            crate::identity_links::user_id(public_key)?,
+        ))
     } else {
         Err(Error::InvalidMessage)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{check_signature_time, MAX_SIGNATURE_CLOCK_SKEW};
+    use crate::clock::ManualClock;
+
+    #[test]
+    fn signatures_from_the_future() {
+        let now = Utc.timestamp_millis_opt(1_600_000_000_000).unwrap();
+        let clock = ManualClock::new(now);
+        let latest = now + Duration::seconds(MAX_SIGNATURE_CLOCK_SKEW);
+        assert!(check_signature_time(&now, &clock).is_ok());
+        assert!(check_signature_time(&latest, &clock).is_ok());
+        assert!(check_signature_time(&(latest + Duration::milliseconds(1)), &clock).is_err());
+        clock.advance(Duration::milliseconds(1));
+        assert!(check_signature_time(&(latest + Duration::milliseconds(1)), &clock).is_ok());
+    }
+}
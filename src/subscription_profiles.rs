@@ -0,0 +1,148 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, IoContext},
+    locks::KeyedLocks,
+    ChannelId, HubId, Result,
+};
+
+/// Folder where the subscription profile of each user is stored.
+pub const SUBSCRIPTION_PROFILES_FOLDER: &str = "data/users/subscription_profiles/";
+
+/// Maximum number of hubs and channels together in a subscription profile.
+pub const MAX_PROFILE_ENTRIES: usize = 1024;
+
+/// Makes sure only one subscription profile of a user is saved at a time, see [`set`].
+static PROFILES_LOCKS: KeyedLocks<String> = KeyedLocks::new();
+
+/// Hubs and channels (as `(hub, channel)` pairs) a user wants to be subscribed to, stored so that a reconnecting client can subscribe to all of them with one [`crate::websocket::ClientMessage::ResumeProfile`].
+/// Whether the user can still read them is only checked when the profile is resumed.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct SubscriptionProfile {
+    #[serde(default)]
+    pub hubs: BTreeSet<HubId>,
+    #[serde(default)]
+    pub channels: BTreeSet<(HubId, ChannelId)>,
+}
+
+impl SubscriptionProfile {
+    /// Gets the path of the file that a user's subscription profile is stored in.
+    pub fn get_path(user_id: &str) -> PathBuf {
+        crate::paths::subscription_profiles_file(user_id)
+    }
+
+    /// Loads the subscription profile of a user, a user without the file has an empty one.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The user ID is not a hex encoded fingerprint.
+    /// * The file exists but could not be read.
+    /// * The file's contents could not be deserialized.
+    pub async fn load(user_id: &str) -> Result<Self> {
+        if hex::decode(user_id).is_err() {
+            return Err(Error::InvalidFingerprint);
+        }
+        let path = Self::get_path(user_id);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(bincode::deserialize(&bytes)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_path(path),
+        }
+    }
+
+    /// Saves the subscription profile of a user, the file is removed if the profile is empty.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The subscription profiles folder does not exist and could not be created.
+    /// * The data could not be written to the disk or the empty file could not be removed.
+    pub async fn save(&self, user_id: &str) -> Result {
+        let path = Self::get_path(user_id);
+        if self.is_empty() {
+            return match tokio::fs::remove_file(&path).await {
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                result => result.with_path(path),
+            };
+        }
+        tokio::fs::create_dir_all(SUBSCRIPTION_PROFILES_FOLDER)
+            .await
+            .with_path(SUBSCRIPTION_PROFILES_FOLDER)?;
+        tokio::fs::write(&path, bincode::serialize(self)?)
+            .await
+            .with_path(path)
+    }
+
+    /// Returns true if the profile has no hubs and no channels.
+    pub fn is_empty(&self) -> bool {
+        self.hubs.is_empty() && self.channels.is_empty()
+    }
+
+    /// Groups the profile by hub, so that each hub only has to be loaded once. The `bool` is true if the hub itself is in the profile.
+    pub fn by_hub(&self) -> BTreeMap<HubId, (bool, Vec<ChannelId>)> {
+        let mut hubs: BTreeMap<HubId, (bool, Vec<ChannelId>)> = BTreeMap::new();
+        for hub_id in &self.hubs {
+            hubs.entry(*hub_id).or_default().0 = true;
+        }
+        for (hub_id, channel_id) in &self.channels {
+            hubs.entry(*hub_id).or_default().1.push(*channel_id);
+        }
+        hubs
+    }
+}
+
+/// Replaces the subscription profile of a user, an empty profile removes it.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The profile has more than [`MAX_PROFILE_ENTRIES`] hubs and channels, [`Error::TooManyProfileEntries`].
+/// * The profile could not be saved for any of the reasons outlined in [`SubscriptionProfile::save`].
+pub async fn set(user_id: &str, profile: &SubscriptionProfile) -> Result {
+    if hex::decode(user_id).is_err() {
+        return Err(Error::InvalidFingerprint);
+    }
+    if profile.hubs.len() + profile.channels.len() > MAX_PROFILE_ENTRIES {
+        return Err(Error::TooManyProfileEntries(MAX_PROFILE_ENTRIES));
+    }
+    let _guard = PROFILES_LOCKS.lock(user_id.to_string()).await;
+    profile.save(user_id).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::SubscriptionProfile;
+    use crate::{ChannelId, HubId};
+
+    #[test]
+    fn grouped_by_hub() {
+        let first = HubId::from_u128(1);
+        let second = HubId::from_u128(2);
+        let mut profile = SubscriptionProfile::default();
+        profile.hubs.insert(first);
+        profile.channels.insert((second, ChannelId::from_u128(2)));
+        profile.channels.insert((first, ChannelId::from_u128(1)));
+        profile.channels.insert((second, ChannelId::from_u128(1)));
+        let hubs = profile.by_hub();
+        assert_eq!(hubs.len(), 2);
+        assert_eq!(hubs[&first], (true, vec![ChannelId::from_u128(1)]));
+        assert_eq!(
+            hubs[&second],
+            (
+                false,
+                vec![ChannelId::from_u128(1), ChannelId::from_u128(2)]
+            )
+        );
+    }
+}
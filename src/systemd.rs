@@ -0,0 +1,106 @@
+use std::{os::unix::io::FromRawFd, sync::Arc, time::Duration};
+
+use sd_notify::NotifyState;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_stream::wrappers::TcpListenerStream;
+
+use crate::{
+    error::Result,
+    instrumentation::InstrumentedAddr,
+    server::{Ping, Server},
+};
+
+/// First file descriptor passed by systemd when using socket activation.
+pub const SD_LISTEN_FDS_START: i32 = 3;
+
+/// Takes the listening socket passed by systemd, `None` if the server was not socket activated.
+///
+/// # Errors
+///
+/// This function will return an error in the following situations, but is not
+/// limited to just these cases:
+///
+/// * The passed socket could not be registered with the async runtime.
+pub fn take_listener() -> Result<Option<TcpListenerStream>> {
+    let for_this_process = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        == Some(std::process::id());
+    let fds = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|fds| fds.parse::<i32>().ok())
+        .unwrap_or(0);
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+    if !for_this_process || fds < 1 {
+        return Ok(None);
+    }
+    if fds > 1 {
+        warn!(
+            "systemd passed {} sockets, only the first one is used.",
+            fds
+        );
+    }
+    // Safety: systemd hands ownership of the passed file descriptors, starting at SD_LISTEN_FDS_START, to this process.
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(SD_LISTEN_FDS_START) };
+    listener.set_nonblocking(true)?;
+    info!("Using socket passed by systemd.");
+    Ok(Some(TcpListenerStream::new(
+        tokio::net::TcpListener::from_std(listener)?,
+    )))
+}
+
+/// Sends a notification to systemd, does nothing if the server was not started by systemd.
+fn notify(state: NotifyState) {
+    if let Err(err) = sd_notify::notify(false, &[state]) {
+        warn!("Failed to notify systemd: {}", err);
+    }
+}
+
+/// Tells systemd that the server is ready to accept connections.
+pub fn notify_ready() {
+    notify(NotifyState::Ready);
+}
+
+/// Tells systemd that the server is shutting down.
+pub fn notify_stopping() {
+    notify(NotifyState::Stopping);
+}
+
+/// Starts sending watchdog pings to systemd if `WATCHDOG_USEC` is set.
+/// A ping is only sent if the [`Server`] actor responds to a [`Ping`] in time.
+pub fn spawn_watchdog(server: Arc<InstrumentedAddr<Server>>) {
+    let mut usec = 0;
+    if !sd_notify::watchdog_enabled(false, &mut usec) {
+        return;
+    }
+    let interval = Duration::from_micros(usec) / 2;
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            match tokio::time::timeout(interval, server.call(Ping)).await {
+                Ok(Ok(())) => notify(NotifyState::Watchdog),
+                _ => warn!("Server actor did not respond in time, skipping watchdog ping."),
+            }
+        }
+    });
+}
+
+/// Completes when the process receives `SIGTERM` or `SIGINT`, telling systemd that the server is stopping.
+pub async fn shutdown_signal() {
+    let terminate = async {
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(_) => futures::future::pending::<()>().await,
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = terminate => {},
+    }
+    info!("Shutting down.");
+    notify_stopping();
+}
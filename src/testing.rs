@@ -0,0 +1,539 @@
+use std::{
+    convert::TryFrom,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use pgp::{packet::LiteralData, types::KeyTrait, Message as OpenPGPMessage, SignedPublicKey};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use tempfile::TempDir;
+use tokio::sync::oneshot;
+
+use crate::{
+    channel::{Channel, Message, SignedMessage},
+    client::WicrsClient,
+    config::{Config, LimitsConfig},
+    error::{Error, IoContext},
+    httpapi::ServerBuilder,
+    hub::Hub,
+    locks::{KeyedLock, KeyedLocks},
+    permission::{ChannelPermission, HubPermission},
+    permission_hook::PermissionHook,
+    signing::{KeyPair, USER_PUBLIC_KEY_FOLDER},
+    ChannelId, HubId, MessageId, Result,
+};
+
+/// Key server given to test servers, nothing listens on it so test keys are never uploaded anywhere.
+const TEST_KEY_SERVER: &str = "http://127.0.0.1:9";
+
+/// Makes test servers run one at a time, each of them changes the working directory of the process, see [`TestServer`].
+static SERVER_LOCK: KeyedLocks<()> = KeyedLocks::new();
+
+/// A user of a [`TestServer`]. Requests are authenticated by signing them, so the key pair is all a client needs.
+pub struct TestUser {
+    /// Fingerprint of the user's key, the ID the server knows the user by.
+    pub user_id: String,
+    pub key_pair: KeyPair,
+}
+
+impl TestUser {
+    /// Generates a key pair for a new user and stores its public key where the server looks for keys before asking the key server.
+    async fn new(name: &str) -> Result<Self> {
+        let key_pair = KeyPair::new(format!("{} <{}@wicrs.test>", name, name))?;
+        let user_id = hex::encode_upper(key_pair.public_key.fingerprint());
+        tokio::fs::create_dir_all(USER_PUBLIC_KEY_FOLDER)
+            .await
+            .with_path(USER_PUBLIC_KEY_FOLDER)?;
+        let path = Self::public_key_path(&user_id);
+        tokio::fs::write(&path, key_pair.public_key.to_armored_bytes(None)?)
+            .await
+            .with_path(path)?;
+        Ok(Self { user_id, key_pair })
+    }
+
+    fn public_key_path(user_id: &str) -> PathBuf {
+        crate::paths::user_public_key_file(user_id)
+    }
+}
+
+/// A WICRS server listening on an ephemeral local port, for integration tests of bots and clients. Stopped when dropped.
+///
+/// The server keeps its data in a temporary directory that is removed when it is dropped. Data is stored relative to the working directory, so the working directory of the whole process is that temporary directory while the server runs and test servers run one at a time.
+/// Tests that read or write the `data` folder themselves should not run at the same time as a test server.
+pub struct TestServer {
+    /// URL of the server, without a trailing slash (e.g. `http://127.0.0.1:41234`).
+    pub base_url: String,
+    pub address: SocketAddr,
+    /// Public key the server signs its responses with.
+    pub server_public_key: SignedPublicKey,
+    /// A user listed in the server's `admins`.
+    pub admin: TestUser,
+    /// A user without any special rights.
+    pub user: TestUser,
+    shutdown: Option<oneshot::Sender<()>>,
+    _data_dir: TestDataDir,
+    _lock: KeyedLock<'static, ()>,
+}
+
+impl TestServer {
+    /// Creates an HTTP API client that signs its requests as `user`.
+    pub fn client(&self, user: &TestUser) -> WicrsClient {
+        WicrsClient::new(
+            self.base_url.clone(),
+            user.key_pair.clone(),
+            self.server_public_key.clone(),
+        )
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+/// Temporary directory a [`TestServer`] keeps its data in. It is the working directory of the process until it is dropped, then it is removed.
+struct TestDataDir {
+    /// Working directory of the process before, it is changed back when the directory is dropped.
+    previous: PathBuf,
+    _dir: TempDir,
+}
+
+impl TestDataDir {
+    /// Creates a temporary directory and makes it the working directory of the process.
+    fn enter() -> Result<Self> {
+        let dir = tempfile::tempdir()?;
+        let previous = std::env::current_dir()?;
+        std::env::set_current_dir(dir.path()).with_path(dir.path())?;
+        Ok(Self {
+            previous,
+            _dir: dir,
+        })
+    }
+}
+
+impl Drop for TestDataDir {
+    fn drop(&mut self) {
+        // The directory itself is removed after this, once it is no longer the working directory.
+        let _ = std::env::set_current_dir(&self.previous);
+    }
+}
+
+/// Starts a [`TestServer`] in a new temporary directory with a new server key pair and two new users, one of them an admin.
+/// Waits until the test server that is already running is dropped, if there is one. Generating the keys takes a few seconds.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * The temporary directory could not be created or made the working directory.
+/// * A key pair could not be generated or a user's public key could not be stored.
+/// * The server could not be built for any of the reasons outlined by [`ServerBuilder::build`].
+pub async fn spawn_test_server() -> Result<TestServer> {
+    let lock = SERVER_LOCK.lock(()).await;
+    let data_dir = TestDataDir::enter()?;
+    let admin = TestUser::new("admin").await?;
+    let user = TestUser::new("user").await?;
+    let server_key_pair = KeyPair::new("WICRS Test Server <server@wicrs.test>")?;
+    let server_public_key = server_key_pair.public_key.clone();
+    let config = Config {
+        key_server: TEST_KEY_SERVER.to_string(),
+        limits: LimitsConfig {
+            admins: vec![admin.user_id.clone()],
+            ..LimitsConfig::default()
+        },
+        ..Config::default()
+    };
+    let wicrs = ServerBuilder::new(config)
+        .key_pair(server_key_pair)
+        .build()
+        .await?;
+    let (shutdown, stopped) = oneshot::channel::<()>();
+    let (address, serving) = warp::serve(wicrs.routes())
+        .try_bind_with_graceful_shutdown(([127, 0, 0, 1], 0), async {
+            let _ = stopped.await;
+        })
+        .map_err(|err| Error::Other(format!("Unable to bind the test server: {}", err)))?;
+    tokio::spawn(serving);
+    Ok(TestServer {
+        base_url: format!("http://{}", address),
+        address,
+        server_public_key,
+        admin,
+        user,
+        shutdown: Some(shutdown),
+        _data_dir: data_dir,
+        _lock: lock,
+    })
+}
+
+/// Words seeded messages are made of, so that searches for any of them have plenty of results.
+const SEED_WORDS: &[&str] = &[
+    "the", "a", "and", "to", "of", "is", "it", "that", "in", "you", "for", "on", "with", "this",
+    "be", "was", "have", "not", "are", "just", "server", "channel", "message", "release", "build",
+    "test", "bug", "fix", "deploy", "search", "index", "latency", "thanks", "maybe", "tomorrow",
+    "meeting", "coffee", "lunch", "weekend", "question", "answer", "idea", "great", "broken",
+    "works", "again", "later", "today", "rust", "tokio", "warp", "hub", "member", "key", "lol",
+];
+
+/// A [`PermissionHook`] for tests of embedding applications. It counts how often it is asked about one user, denies them the permissions in `deny` and tries to grant them the ones in `grant`; every other user keeps the hub's decisions.
+/// The hook is shared by the whole process once it is registered, tests should give it a user that only they use.
+#[derive(Debug, Default)]
+pub struct CountingPermissionHook {
+    /// ID of the user the hook changes the decisions of.
+    pub user_id: String,
+    pub deny_hub: Vec<HubPermission>,
+    pub deny_channel: Vec<ChannelPermission>,
+    /// Channel permissions the hook answers `true` for, only given if it was registered with `can_grant`.
+    pub grant_channel: Vec<ChannelPermission>,
+    hub_calls: AtomicUsize,
+    channel_calls: AtomicUsize,
+}
+
+impl CountingPermissionHook {
+    /// Creates a hook that keeps every decision about the given user until permissions are added to it.
+    pub fn new(user_id: impl Into<String>) -> Self {
+        Self {
+            user_id: user_id.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Number of hub permission checks of the user the hook was asked about.
+    pub fn hub_calls(&self) -> usize {
+        self.hub_calls.load(Ordering::Relaxed)
+    }
+
+    /// Number of channel permission checks of the user the hook was asked about.
+    pub fn channel_calls(&self) -> usize {
+        self.channel_calls.load(Ordering::Relaxed)
+    }
+}
+
+impl PermissionHook for CountingPermissionHook {
+    fn hub_permission(
+        &self,
+        user_id: &str,
+        _hub: &Hub,
+        permission: HubPermission,
+        default: bool,
+    ) -> bool {
+        if user_id != self.user_id {
+            return default;
+        }
+        self.hub_calls.fetch_add(1, Ordering::Relaxed);
+        default && !self.deny_hub.contains(&permission)
+    }
+
+    fn channel_permission(
+        &self,
+        user_id: &str,
+        _hub: &Hub,
+        _channel_id: ChannelId,
+        permission: ChannelPermission,
+        default: bool,
+    ) -> bool {
+        if user_id != self.user_id {
+            return default;
+        }
+        self.channel_calls.fetch_add(1, Ordering::Relaxed);
+        (default || self.grant_channel.contains(&permission))
+            && !self.deny_channel.contains(&permission)
+    }
+}
+
+/// Number of messages written to the channel files at once while seeding.
+const SEED_WRITE_BATCH: usize = 1000;
+
+/// What [`seed_hub`] generates. The same options always give the same hub, channels, members and message contents and times.
+#[derive(Clone, Debug)]
+pub struct SeedOptions {
+    /// Number of channels, at least one is created.
+    pub channels: usize,
+    /// Number of members besides the owner.
+    pub members: usize,
+    pub messages_per_channel: usize,
+    /// Number of days before `end` that the messages are spread over.
+    pub days: u32,
+    /// Time of the newest possible message.
+    pub end: DateTime<Utc>,
+    /// Seed of the random number generator, different seeds give different hubs.
+    pub seed: u64,
+}
+
+impl Default for SeedOptions {
+    fn default() -> Self {
+        Self {
+            channels: 4,
+            members: 100,
+            messages_per_channel: 1000,
+            days: 30,
+            end: Utc.with_ymd_and_hms(2021, 6, 1, 0, 0, 0).unwrap(),
+            seed: 0,
+        }
+    }
+}
+
+/// A hub created by [`seed_hub`].
+#[derive(Clone, Debug)]
+pub struct SeededHub {
+    pub hub_id: HubId,
+    pub channel_ids: Vec<ChannelId>,
+    /// IDs of the members, the owner first. They are random fingerprints without keys, so nobody can sign in as them.
+    pub member_ids: Vec<String>,
+    /// Number of messages that were stored.
+    pub messages: usize,
+    /// Number of messages that were added to search indexes, `0` without the `search` feature.
+    pub indexed: usize,
+}
+
+/// Generates a random user ID, a hex encoded fingerprint.
+fn random_fingerprint(rng: &mut StdRng) -> String {
+    hex::encode_upper(rng.gen::<[u8; 20]>())
+}
+
+/// Generates the content of a message: mostly short chat lines, some paragraphs and a few long messages, sometimes with a link.
+fn random_content(rng: &mut StdRng) -> String {
+    let words = match rng.gen_range(0..100) {
+        0..=69 => rng.gen_range(1..12),
+        70..=94 => rng.gen_range(12..60),
+        _ => rng.gen_range(60..300),
+    };
+    let mut content = (0..words)
+        .map(|_| SEED_WORDS[rng.gen_range(0..SEED_WORDS.len())])
+        .collect::<Vec<&str>>()
+        .join(" ");
+    if rng.gen_ratio(1, 20) {
+        content.push_str(&format!(" https://example.com/{}", rng.gen::<u32>()));
+    }
+    content
+}
+
+/// Wraps a message the way messages are stored, signed by `key_pair` as both the server and the sender if it is given.
+fn seal_message(message: &Message, key_pair: Option<&KeyPair>) -> Result<String> {
+    let inner = match key_pair {
+        Some(key_pair) => message.sign(&key_pair.secret_key, String::new)?,
+        None => OpenPGPMessage::try_from(message)?,
+    }
+    .to_armored_string(None)?;
+    let outer = match key_pair {
+        Some(key_pair) => Message::sign_final(
+            &inner,
+            &key_pair.public_key,
+            &key_pair.secret_key,
+            String::new,
+        )?,
+        None => OpenPGPMessage::Literal(LiteralData::from_str(&message.id.to_string(), &inner)),
+    };
+    Ok(outer.to_armored_string(None)?)
+}
+
+/// Creates a hub filled with generated members and messages, to measure the server with realistic amounts of data.
+/// Everything is written the way the server writes it: the hub is saved, each channel gets a directory, messages are appended to the files of the days they were sent on and the search indexes are built from them.
+/// Messages are signed by `key_pair` as both the server and the sender. Without a key pair they are stored unsigned, which is much faster for large seeds and still readable since the server does not check the signatures of stored messages.
+/// Messages are not counted in quotas, leaderboards or hub activity.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * A hub with the ID the seed gives already exists, [`Error::Other`].
+/// * A message could not be signed.
+/// * The hub, a channel directory or the messages could not be written, see [`Hub::save`], [`Channel::create_dir`] and [`Channel::add_messages_on_created_day`].
+/// * A search index could not be built for any of the reasons outlined in [`crate::server::rebuild_index`].
+pub async fn seed_hub(options: &SeedOptions, key_pair: Option<&KeyPair>) -> Result<SeededHub> {
+    let mut rng = StdRng::seed_from_u64(options.seed);
+    let hub_id = HubId::from_u128(rng.gen());
+    if Hub::load(hub_id).await.is_ok() {
+        return Err(Error::Other(format!(
+            "Hub {} was already seeded, use another seed.",
+            hub_id
+        )));
+    }
+    let owner = random_fingerprint(&mut rng);
+    let mut hub = Hub::new(format!("Seed {}", options.seed), hub_id, owner.clone());
+    let mut member_ids = vec![owner];
+    for _ in 0..options.members {
+        let member_id = random_fingerprint(&mut rng);
+        hub.user_join(member_id.clone())?;
+        member_ids.push(member_id);
+    }
+    let mut channels = Vec::new();
+    for n in 0..options.channels.max(1) {
+        let channel = Channel::new(
+            format!("channel-{}", n),
+            ChannelId::from_u128(rng.gen()),
+            hub_id,
+        );
+        channel.create_dir().await?;
+        if let Some(group) = hub.groups.get_mut(&hub.default_group) {
+            group.set_channel_permission(channel.id, ChannelPermission::Read, Some(true));
+            group.set_channel_permission(channel.id, ChannelPermission::Write, Some(true));
+        }
+        hub.channels.insert(channel.id, channel.clone());
+        channels.push(channel);
+    }
+    hub.save().await?;
+
+    let span_ms = Duration::days(options.days.max(1).into()).num_milliseconds();
+    let start = options.end - Duration::milliseconds(span_ms);
+    let mut seeded = SeededHub {
+        hub_id,
+        channel_ids: channels.iter().map(|channel| channel.id).collect(),
+        member_ids,
+        messages: 0,
+        indexed: 0,
+    };
+    for channel in channels.iter() {
+        let mut offsets: Vec<i64> = (0..options.messages_per_channel)
+            .map(|_| rng.gen_range(0..=span_ms))
+            .collect();
+        offsets.sort_unstable();
+        let mut batch = Vec::with_capacity(SEED_WRITE_BATCH.min(offsets.len()));
+        for offset in offsets {
+            // Cubing the sample makes a few members send most of the messages, like in real hubs.
+            let sender = (rng.gen::<f64>().powi(3) * seeded.member_ids.len() as f64) as usize;
+            let mut message = Message::new(
+                seeded.member_ids[sender].clone(),
+                random_content(&mut rng),
+                hub_id,
+                channel.id,
+            );
+            message.id = MessageId::from_u128(rng.gen());
+            message.created = start + Duration::milliseconds(offset);
+            batch.push(SignedMessage::new(
+                message.id,
+                message.created,
+                seal_message(&message, key_pair)?,
+            ));
+            if batch.len() == SEED_WRITE_BATCH {
+                channel.add_messages_on_created_day(&batch).await?;
+                seeded.messages += batch.len();
+                batch.clear();
+            }
+        }
+        channel.add_messages_on_created_day(&batch).await?;
+        seeded.messages += batch.len();
+        match crate::server::rebuild_index(channel).await {
+            Ok(indexed) => seeded.indexed += indexed,
+            Err(Error::SearchDisabled) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(seeded)
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use std::sync::Arc;
+
+    use super::{random_content, spawn_test_server, CountingPermissionHook, SeedOptions};
+    use crate::{
+        channel::Channel,
+        hub::Hub,
+        permission::{ChannelPermission, HubPermission},
+        ChannelId, HubId,
+    };
+
+    #[tokio::test]
+    async fn clients_reach_test_server() {
+        let server = spawn_test_server().await.unwrap();
+        let info = server.client(&server.user).info().await.unwrap();
+        assert_eq!(info.key_server, super::TEST_KEY_SERVER);
+        assert!(crate::quotas::is_admin(&server.admin.user_id));
+        assert!(!crate::quotas::is_admin(&server.user.user_id));
+
+        let client = server.client(&server.user);
+        let hub = client
+            .create_hub(&crate::hub::NewHub {
+                name: "client".to_string(),
+                description: None,
+            })
+            .await
+            .unwrap();
+        let channel = client
+            .create_channel(
+                hub.id,
+                &crate::hub::NewChannel {
+                    name: "typed".to_string(),
+                    description: None,
+                    private: false,
+                    group_permissions: Vec::new(),
+                    member_permissions: Vec::new(),
+                },
+            )
+            .await
+            .unwrap();
+        let fields = client.hub(hub.id, Some("name,channels")).await.unwrap();
+        assert_eq!(fields.fields["name"], "client");
+        assert!(fields.fields["channels"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|listed| listed["id"] == channel.id.to_string()));
+        assert!(client.hub(hub.id, Some("bans")).await.is_err());
+
+        let data_dir = std::env::current_dir().unwrap();
+        assert!(data_dir
+            .join(crate::signing::USER_PUBLIC_KEY_FOLDER)
+            .is_dir());
+        drop(server);
+        assert!(!data_dir.exists());
+        assert_ne!(std::env::current_dir().unwrap(), data_dir);
+    }
+
+    #[test]
+    fn seeds_are_deterministic() {
+        let options = SeedOptions::default();
+        let contents = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..100)
+                .map(|_| random_content(&mut rng))
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(contents(options.seed), contents(options.seed));
+        assert_ne!(contents(options.seed), contents(options.seed + 1));
+        assert!(contents(options.seed)
+            .iter()
+            .all(|content| !content.is_empty() && content.len() <= crate::MESSAGE_MAX_SIZE));
+    }
+
+    #[test]
+    fn permission_hook_only_denies() {
+        let user_id = "7E57400C";
+        let hub_id = HubId::from_u128(0x400c);
+        let channel_id = ChannelId::from_u128(1);
+        let mut hub = Hub::new("hook".to_string(), hub_id, "AA01".to_string());
+        hub.channels.insert(
+            channel_id,
+            Channel::new("general".to_string(), channel_id, hub_id),
+        );
+        let mut member = hub.user_join(user_id.to_string()).unwrap();
+        member.set_permission(HubPermission::ManageChannels, Some(true));
+        member.set_channel_permission(channel_id, ChannelPermission::Read, Some(true));
+        member.set_channel_permission(channel_id, ChannelPermission::Write, Some(true));
+        hub.members.insert(user_id.to_string(), member.clone());
+
+        let hook = Arc::new(CountingPermissionHook {
+            deny_hub: vec![HubPermission::ManageChannels],
+            deny_channel: vec![ChannelPermission::Write],
+            grant_channel: vec![ChannelPermission::Manage],
+            ..CountingPermissionHook::new(user_id)
+        });
+        crate::permission_hook::set(hook.clone(), false);
+        assert!(hub.can_read_channel(&member, channel_id));
+        assert!(!member.has_channel_permission(channel_id, ChannelPermission::Write, &hub));
+        assert!(!member.has_channel_permission(channel_id, ChannelPermission::Manage, &hub));
+        assert!(!member.has_permission(HubPermission::ManageChannels, &hub));
+        assert_eq!((hook.hub_calls(), hook.channel_calls()), (1, 3));
+        // The owner is not the hook's user, its decisions are unchanged.
+        assert!(hub.check_can_read_channel("AA01", channel_id).is_ok());
+        assert_eq!((hook.hub_calls(), hook.channel_calls()), (1, 3));
+    }
+}
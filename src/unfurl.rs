@@ -0,0 +1,527 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::RwLock,
+    time::Duration,
+};
+
+use reqwest::{redirect::Policy, Url};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    channel::Message,
+    config::UnfurlConfig,
+    content_policy::ContentPolicy,
+    error::{Error, IoContext},
+    server::{self, ServerNotification},
+    ChannelId, HubId, MessageId, Result,
+};
+
+/// Maximum length in characters of the title of a link preview, longer titles are cut.
+pub const MAX_TITLE_LENGTH: usize = 256;
+
+/// Maximum length in characters of the description of a link preview, longer descriptions are cut.
+pub const MAX_DESCRIPTION_LENGTH: usize = 1024;
+
+/// Configuration set by [`set_config`], previews are disabled until it is called.
+static CONFIG: RwLock<Option<UnfurlConfig>> = RwLock::new(None);
+
+/// Sets the configuration used for link previews, called by the server on startup with the configuration it was given.
+pub fn set_config(config: UnfurlConfig) {
+    *CONFIG.write().unwrap_or_else(|err| err.into_inner()) = Some(config);
+}
+
+/// Gets the configuration used for link previews.
+pub fn config() -> UnfurlConfig {
+    CONFIG
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone()
+        .unwrap_or_default()
+}
+
+/// Preview of a page linked in a message, made from the page's OpenGraph tags.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LinkPreview {
+    /// URL as it appears in the message.
+    pub url: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// URL of the page's image, clients fetch it themselves if they want to show it.
+    pub image: Option<String>,
+    /// Name of the site the page is on.
+    pub site_name: Option<String>,
+}
+
+/// Link previews of a message, stored next to the channel's messages because messages are signed and can not be changed.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct MessagePreviews {
+    /// Previews in the order their URLs appear in the message, URLs that could not be previewed are left out.
+    pub previews: Vec<LinkPreview>,
+}
+
+impl MessagePreviews {
+    /// Loads the link previews of a message, a message without the file has none.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The file exists but could not be read.
+    /// * The file's contents could not be deserialized.
+    pub async fn load(hub_id: HubId, channel_id: ChannelId, message_id: MessageId) -> Result<Self> {
+        let path = crate::paths::message_previews_file(hub_id, channel_id, message_id);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(bincode::deserialize(&bytes)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_path(path),
+        }
+    }
+
+    /// Saves the link previews of a message.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The previews folder does not exist and could not be created.
+    /// * The data could not be written to the disk.
+    pub async fn save(
+        &self,
+        hub_id: HubId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    ) -> Result {
+        let folder = crate::paths::channel_previews_dir(hub_id, channel_id);
+        tokio::fs::create_dir_all(&folder).await.with_path(folder)?;
+        let path = crate::paths::message_previews_file(hub_id, channel_id, message_id);
+        tokio::fs::write(&path, bincode::serialize(self)?)
+            .await
+            .with_path(path)
+    }
+
+    /// Removes the link previews of a message, used when the message is deleted.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the file exists but could not be removed.
+    pub async fn remove(hub_id: HubId, channel_id: ChannelId, message_id: MessageId) -> Result {
+        let path = crate::paths::message_previews_file(hub_id, channel_id, message_id);
+        match tokio::fs::remove_file(&path).await {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result.with_path(path),
+        }
+    }
+}
+
+/// Finds the `http` and `https` URLs in the content of a message, in the order they appear and without duplicates.
+/// Punctuation at the end of a URL is treated as part of the surrounding text.
+pub fn find_urls(content: &str, max: usize) -> Vec<Url> {
+    let mut urls: Vec<Url> = Vec::new();
+    for word in content.split_whitespace() {
+        if urls.len() >= max {
+            break;
+        }
+        let start = match word.find("https://").or_else(|| word.find("http://")) {
+            Some(start) => start,
+            None => continue,
+        };
+        let candidate = word[start..].trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '>']);
+        if let Ok(url) = Url::parse(candidate) {
+            if url.host_str().is_some() && !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+    }
+    urls
+}
+
+/// Checks if an address can be reached from the internet, pages are never fetched from private, loopback, link-local or otherwise reserved addresses.
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_ipv4(ip),
+        IpAddr::V6(ip) => is_public_ipv6(ip),
+    }
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        // Shared address space (carrier-grade NAT).
+        || (a == 100 && (64..128).contains(&b))
+        // IETF protocol assignments.
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking.
+        || (a == 198 && (b == 18 || b == 19))
+        // Reserved for future use.
+        || a >= 240)
+}
+
+fn is_public_ipv6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    if let [0, 0, 0, 0, 0, 0xffff, high, low] = segments {
+        // IPv4-mapped addresses reach the IPv4 address.
+        return is_public_ipv4(Ipv4Addr::new(
+            (high >> 8) as u8,
+            high as u8,
+            (low >> 8) as u8,
+            low as u8,
+        ));
+    }
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // IPv4-compatible and NAT64 addresses can reach private IPv4 addresses.
+        || segments[..6] == [0, 0, 0, 0, 0, 0]
+        || segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0]
+        // Unique local.
+        || (segments[0] & 0xfe00) == 0xfc00
+        // Link-local.
+        || (segments[0] & 0xffc0) == 0xfe80
+        // Documentation.
+        || (segments[0] == 0x2001 && segments[1] == 0xdb8))
+}
+
+/// Checks if a host is in the configured list of allowed hosts, an empty list allows every host.
+fn host_allowed(config: &UnfurlConfig, host: &str) -> bool {
+    config.allowed_hosts.is_empty()
+        || config.allowed_hosts.iter().any(|allowed| {
+            let allowed = allowed.trim_start_matches('.').to_ascii_lowercase();
+            host == allowed || host.ends_with(&format!(".{}", allowed))
+        })
+}
+
+/// Checks that a URL can be fetched and resolves its host to a public address, which the connection is then pinned to so that a second lookup can not return a private address.
+///
+/// # Errors
+///
+/// This function returns [`Error::UnfurlBlocked`] if the URL is not `http` or `https` on the default port, its host is not allowed or any of the host's addresses is not public.
+async fn resolve_public(config: &UnfurlConfig, url: &Url) -> Result<(String, SocketAddr)> {
+    let host = url
+        .host_str()
+        .ok_or(Error::UnfurlBlocked)?
+        .to_ascii_lowercase();
+    let port = url.port_or_known_default().ok_or(Error::UnfurlBlocked)?;
+    if !matches!((url.scheme(), port), ("http", 80) | ("https", 443))
+        || !host_allowed(config, &host)
+    {
+        return Err(Error::UnfurlBlocked);
+    }
+    let lookup = host.trim_start_matches('[').trim_end_matches(']');
+    let addresses: Vec<SocketAddr> = tokio::net::lookup_host((lookup, port)).await?.collect();
+    if addresses.is_empty() || addresses.iter().any(|address| !is_public_ip(address.ip())) {
+        return Err(Error::UnfurlBlocked);
+    }
+    Ok((host, addresses[0]))
+}
+
+/// Fetches the start of an HTML page, following redirects only to URLs that pass the same checks.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * A URL is blocked for any of the reasons outlined by [`resolve_public`].
+/// * There are more redirects than the configuration allows, or the page is not HTML.
+/// * The page could not be fetched.
+async fn fetch_page(config: &UnfurlConfig, url: &Url) -> Result<(Url, String)> {
+    let mut url = url.clone();
+    for _ in 0..=config.max_redirects {
+        let (host, address) = resolve_public(config, &url).await?;
+        let client = reqwest::Client::builder()
+            .redirect(Policy::none())
+            .no_proxy()
+            .resolve(&host, address)
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .user_agent(concat!("wicrs_server/", env!("CARGO_PKG_VERSION")))
+            .build()?;
+        let mut response = client
+            .get(url.clone())
+            .header("accept", "text/html")
+            .send()
+            .await?;
+        if response.status().is_redirection() {
+            let location = response
+                .headers()
+                .get("location")
+                .and_then(|location| location.to_str().ok())
+                .ok_or(Error::UnfurlBlocked)?;
+            url = url.join(location).map_err(|_| Error::UnfurlBlocked)?;
+            continue;
+        }
+        let is_html = response
+            .headers()
+            .get("content-type")
+            .and_then(|content_type| content_type.to_str().ok())
+            .is_some_and(|content_type| content_type.contains("text/html"));
+        if !response.status().is_success() || !is_html {
+            return Err(Error::UnfurlBlocked);
+        }
+        let mut page = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            page.extend_from_slice(&chunk);
+            if page.len() >= config.max_page_bytes {
+                page.truncate(config.max_page_bytes);
+                break;
+            }
+        }
+        return Ok((url, String::from_utf8_lossy(&page).into_owned()));
+    }
+    Err(Error::UnfurlBlocked)
+}
+
+/// Replaces the HTML entities that are common in page titles and descriptions.
+fn decode_entities(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+/// Parses the attributes of an HTML tag, names are lowercased.
+fn tag_attributes(tag: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    let mut rest = tag;
+    while let Some(equals) = rest.find('=') {
+        let name = rest[..equals]
+            .rsplit(|c: char| c.is_whitespace())
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let value = rest[equals + 1..].trim_start();
+        let (value, remaining) = match value.chars().next() {
+            Some(quote) if quote == '"' || quote == '\'' => match value[1..].find(quote) {
+                Some(end) => (&value[1..end + 1], &value[end + 2..]),
+                None => (&value[1..], ""),
+            },
+            _ => {
+                let end = value
+                    .find(|c: char| c.is_whitespace())
+                    .unwrap_or(value.len());
+                (&value[..end], &value[end..])
+            }
+        };
+        attributes.push((name, decode_entities(value)));
+        rest = remaining;
+    }
+    attributes
+}
+
+/// Trims a text, collapses its whitespace and cuts it to `max` characters, empty texts become `None`.
+fn clean_text(text: &str, max: usize) -> Option<String> {
+    let text: String = text
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(max)
+        .collect();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+/// Creates a preview from the OpenGraph tags of a page, falling back to its `<title>` and description meta tag.
+/// `url` is the URL from the message and `page_url` the URL the page was fetched from after redirects, relative image URLs are resolved against it.
+/// Returns `None` if the page has neither a title nor a description.
+pub fn parse_open_graph(url: &Url, page_url: &Url, html: &str) -> Option<LinkPreview> {
+    let lower = html.to_ascii_lowercase();
+    let mut preview = LinkPreview {
+        url: url.to_string(),
+        title: None,
+        description: None,
+        image: None,
+        site_name: None,
+    };
+    let mut fallback_description = None;
+    let mut position = 0;
+    while let Some(start) = lower[position..].find("<meta") {
+        let start = position + start;
+        let end = match lower[start..].find('>') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let attributes = tag_attributes(&html[start + 5..end]);
+        let attribute = |name: &str| {
+            attributes
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+        let key = attribute("property")
+            .or_else(|| attribute("name"))
+            .map(str::to_ascii_lowercase);
+        if let (Some(key), Some(content)) = (key, attribute("content")) {
+            match key.as_str() {
+                "og:title" => preview.title = clean_text(content, MAX_TITLE_LENGTH),
+                "og:description" => {
+                    preview.description = clean_text(content, MAX_DESCRIPTION_LENGTH)
+                }
+                "og:site_name" => preview.site_name = clean_text(content, MAX_TITLE_LENGTH),
+                "og:image" => {
+                    preview.image = page_url
+                        .join(content.trim())
+                        .ok()
+                        .filter(|image| matches!(image.scheme(), "http" | "https"))
+                        .map(String::from)
+                }
+                "description" => fallback_description = clean_text(content, MAX_DESCRIPTION_LENGTH),
+                _ => {}
+            }
+        }
+        position = end;
+    }
+    if preview.title.is_none() {
+        if let Some(start) = lower.find("<title") {
+            let start = lower[start..].find('>').map(|end| start + end + 1);
+            if let Some(start) = start {
+                let end = lower[start..]
+                    .find("</title")
+                    .map_or(lower.len(), |end| start + end);
+                preview.title = clean_text(&decode_entities(&html[start..end]), MAX_TITLE_LENGTH);
+            }
+        }
+    }
+    if preview.description.is_none() {
+        preview.description = fallback_description;
+    }
+    if preview.title.is_none() && preview.description.is_none() {
+        None
+    } else {
+        Some(preview)
+    }
+}
+
+/// Fetches the pages linked in a message and previews them, see [`spawn`].
+async fn unfurl(config: UnfurlConfig, message: Message, urls: Vec<Url>) -> Result {
+    if !ContentPolicy::load(message.hub_id)
+        .await?
+        .allow_link_previews
+    {
+        return Ok(());
+    }
+    let mut previews = MessagePreviews::default();
+    for url in urls {
+        match fetch_page(&config, &url).await {
+            Ok((page_url, html)) => {
+                if let Some(preview) = parse_open_graph(&url, &page_url, &html) {
+                    previews.previews.push(preview);
+                }
+            }
+            Err(err) => debug!("Unable to preview {}: {}", url, err.chain()),
+        }
+    }
+    if previews.previews.is_empty() {
+        return Ok(());
+    }
+    previews
+        .save(message.hub_id, message.channel_id, message.id)
+        .await?;
+    server::publish(ServerNotification::LinkPreviews(
+        message.hub_id,
+        message.channel_id,
+        message.id,
+        previews.previews,
+    ))
+    .await;
+    Ok(())
+}
+
+/// Starts creating previews of the pages linked in a message in the background if link previews are enabled in the configuration and allowed by the message's hub (see [`ContentPolicy::allow_link_previews`]).
+/// Once they are stored the [`crate::server::Server`] sends them to the clients subscribed to the message's channel.
+pub fn spawn(message: &Message) {
+    let config = config();
+    if !config.enabled {
+        return;
+    }
+    let urls = find_urls(&message.content, config.max_urls_per_message);
+    if urls.is_empty() {
+        return;
+    }
+    let message = message.clone();
+    tokio::spawn(async move {
+        let message_id = message.id;
+        if let Err(err) = unfurl(config, message, urls).await {
+            warn!(
+                "Unable to store the link previews of message {}: {}",
+                message_id,
+                err.chain()
+            );
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+
+    use reqwest::Url;
+
+    use super::{find_urls, is_public_ip, parse_open_graph};
+
+    #[test]
+    fn blocks_private_addresses() {
+        for address in &[
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a00:1",
+        ] {
+            assert!(
+                !is_public_ip(address.parse::<IpAddr>().unwrap()),
+                "{} is not public",
+                address
+            );
+        }
+        for address in &["93.184.216.34", "2606:2800:220:1:248:1893:25c8:1946"] {
+            assert!(is_public_ip(address.parse::<IpAddr>().unwrap()));
+        }
+    }
+
+    #[test]
+    fn finds_urls_and_parses_previews() {
+        let urls = find_urls(
+            "See https://example.com/a, (http://example.org) and https://example.com/a again.",
+            3,
+        );
+        assert_eq!(
+            urls.iter().map(Url::as_str).collect::<Vec<_>>(),
+            vec!["https://example.com/a", "http://example.org/"]
+        );
+        let html = r#"<html><head><title>Fallback</title>
+            <meta property="og:title" content="A &amp; B">
+            <META name='description' content='Plain description'>
+            <meta property="og:image" content="/image.png" />
+            </head></html>"#;
+        let page = Url::parse("https://www.example.com/a").unwrap();
+        let preview = parse_open_graph(&urls[0], &page, html).unwrap();
+        assert_eq!(preview.title.as_deref(), Some("A & B"));
+        assert_eq!(preview.description.as_deref(), Some("Plain description"));
+        assert_eq!(
+            preview.image.as_deref(),
+            Some("https://www.example.com/image.png")
+        );
+        assert!(parse_open_graph(&urls[0], &page, "<html></html>").is_none());
+    }
+}
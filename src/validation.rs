@@ -0,0 +1,344 @@
+use std::sync::RwLock;
+
+use thiserror::Error;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::{
+    config::{DescriptionsConfig, NameRules, NamesConfig},
+    error::{Error, Result},
+};
+
+/// Maximum number of line breaks in a row in message content, longer runs of blank lines are shortened to this.
+pub const MAX_CONSECUTIVE_NEWLINES: usize = 3;
+
+/// Name rules set by [`set_name_rules`], the defaults are used until it is called.
+static NAME_RULES: RwLock<Option<NamesConfig>> = RwLock::new(None);
+
+/// Description limits set by [`set_description_rules`], the defaults are used until it is called.
+static DESCRIPTION_RULES: RwLock<Option<DescriptionsConfig>> = RwLock::new(None);
+
+/// Kinds of objects that have names, each kind has its own [`NameRules`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NameKind {
+    Hub,
+    Channel,
+}
+
+/// Reasons a name can be rejected for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Error)]
+pub enum InvalidNameReason {
+    #[error("name is too short")]
+    TooShort,
+    #[error("name is too long")]
+    TooLong,
+    #[error("name contains the character {0:?} which is not allowed")]
+    IllegalCharacter(char),
+    #[error("name only contains whitespace")]
+    OnlyWhitespace,
+}
+
+impl From<InvalidNameReason> for Error {
+    fn from(reason: InvalidNameReason) -> Self {
+        Self::InvalidName(reason)
+    }
+}
+
+/// Sets the rules used by [`validate_name`], called by the server on startup with the rules from its configuration.
+pub fn set_name_rules(rules: NamesConfig) {
+    *NAME_RULES.write().unwrap_or_else(|err| err.into_inner()) = Some(rules);
+}
+
+/// Gets the rules for a kind of name.
+pub fn name_rules(kind: NameKind) -> NameRules {
+    let rules = NAME_RULES.read().unwrap_or_else(|err| err.into_inner());
+    let rules = rules.clone().unwrap_or_default();
+    match kind {
+        NameKind::Hub => rules.hub,
+        NameKind::Channel => rules.channel,
+    }
+}
+
+/// Checks if owners can only have one hub with each name, see [`NamesConfig::unique_hub_names_per_owner`].
+pub fn unique_hub_names_per_owner() -> bool {
+    NAME_RULES
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .as_ref()
+        .is_some_and(|rules| rules.unique_hub_names_per_owner)
+}
+
+/// Checks if the channels of a hub need different names, see [`NamesConfig::allow_duplicate_channel_names`].
+pub fn unique_channel_names() -> bool {
+    NAME_RULES
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .as_ref()
+        .is_none_or(|rules| !rules.allow_duplicate_channel_names)
+}
+
+/// Folds the case of a name so that names that only differ in case (`General`, `general`, `GENERAL`, `STRASSE` and `straße`) compare equal.
+/// Compatibility characters are normalized first, so for example the ligature `ﬁ` matches `fi`.
+pub fn fold_name(name: &str) -> String {
+    name.nfkc()
+        .collect::<String>()
+        .to_uppercase()
+        .to_lowercase()
+}
+
+/// Checks a name against the rules for its kind, returning the normalized name that should be stored.
+/// Leading and trailing whitespace is removed and any other run of whitespace is replaced by a single space.
+///
+/// # Errors
+///
+/// This function returns an [`Error::InvalidName`] for any of the following reasons:
+///
+/// * The name is empty or only whitespace.
+/// * The normalized name is shorter or longer than allowed by the [`NameRules`] for its kind.
+/// * The name contains a character that is not allowed by the [`NameRules`] for its kind.
+pub fn validate_name(kind: NameKind, name: &str) -> Result<String> {
+    validate_name_with(&name_rules(kind), name)
+}
+
+/// Same as [`validate_name`] but with the given rules.
+pub fn validate_name_with(rules: &NameRules, name: &str) -> Result<String> {
+    let normalized = name.split_whitespace().collect::<Vec<&str>>().join(" ");
+    if normalized.is_empty() && !name.is_empty() {
+        return Err(InvalidNameReason::OnlyWhitespace.into());
+    }
+    if let Some(illegal) = normalized
+        .chars()
+        .find(|c| !is_allowed_char(*c, rules.extended_characters))
+    {
+        return Err(InvalidNameReason::IllegalCharacter(illegal).into());
+    }
+    let length = normalized.chars().count();
+    if length < rules.min_length {
+        Err(InvalidNameReason::TooShort.into())
+    } else if length > rules.max_length {
+        Err(InvalidNameReason::TooLong.into())
+    } else {
+        Ok(normalized)
+    }
+}
+
+/// Checks if a character may be used in a name, whitespace other than a space is removed before this is used.
+fn is_allowed_char(c: char, extended: bool) -> bool {
+    c == ' '
+        || c.is_ascii_alphanumeric()
+        || c.is_ascii_punctuation()
+        || (extended && c.is_alphanumeric())
+}
+
+/// Kinds of descriptions, each has its own maximum length in [`DescriptionsConfig`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DescriptionKind {
+    /// The short description shown in lists.
+    Short,
+    /// The long markdown description.
+    Long,
+}
+
+/// Sets the limits used by [`validate_description`], called by the server on startup with the limits from its configuration.
+pub fn set_description_rules(rules: DescriptionsConfig) {
+    *DESCRIPTION_RULES
+        .write()
+        .unwrap_or_else(|err| err.into_inner()) = Some(rules);
+}
+
+/// Gets the maximum length of a kind of description.
+pub fn max_description_length(kind: DescriptionKind) -> usize {
+    let rules = DESCRIPTION_RULES
+        .read()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone()
+        .unwrap_or_default();
+    match kind {
+        DescriptionKind::Short => rules.max_length,
+        DescriptionKind::Long => rules.max_long_length,
+    }
+}
+
+/// Checks that a description is not longer than the limit for its kind, the length is counted in Unicode scalar values.
+///
+/// # Errors
+///
+/// This function returns [`Error::DescriptionTooLong`] with the limit if the description is too long.
+pub fn validate_description(kind: DescriptionKind, description: &str) -> Result {
+    let max = max_description_length(kind);
+    if description.chars().count() > max {
+        Err(Error::DescriptionTooLong(max))
+    } else {
+        Ok(())
+    }
+}
+
+/// Normalizes the content of a message so that clients can render it safely, returning the content that should be sent.
+///
+/// * Control characters other than line feeds and tabs are removed, as are the characters that override or isolate the text direction and byte order marks.
+/// * The content is normalized to Unicode NFC.
+/// * Runs of zero width characters are shortened to one character, single zero width joiners are kept for emoji sequences.
+/// * Trailing whitespace is removed from every line and blank lines at the start and end are removed.
+/// * More than [`MAX_CONSECUTIVE_NEWLINES`] line breaks in a row are shortened to [`MAX_CONSECUTIVE_NEWLINES`].
+///
+/// # Errors
+///
+/// This function returns [`Error::InvalidText`] if nothing but whitespace is left.
+pub fn normalize_message_content(content: &str) -> Result<String> {
+    let stripped: String = content.chars().filter(|c| !is_stripped_char(*c)).collect();
+    let mut normalized = String::with_capacity(stripped.len());
+    let mut previous_zero_width = false;
+    for c in stripped.nfc() {
+        let zero_width = is_zero_width_char(c);
+        if !(zero_width && previous_zero_width) {
+            normalized.push(c);
+        }
+        previous_zero_width = zero_width;
+    }
+    let mut result = String::with_capacity(normalized.len());
+    let mut newlines = 0;
+    for (n, line) in normalized.split('\n').enumerate() {
+        if n > 0 {
+            newlines += 1;
+        }
+        let line = line.trim_end_matches([' ', '\t']);
+        if line.is_empty() {
+            continue;
+        }
+        if !result.is_empty() {
+            for _ in 0..newlines.min(MAX_CONSECUTIVE_NEWLINES) {
+                result.push('\n');
+            }
+        }
+        newlines = 0;
+        result.push_str(line);
+    }
+    if result
+        .chars()
+        .all(|c| c.is_whitespace() || is_zero_width_char(c))
+    {
+        return Err(Error::InvalidText);
+    }
+    Ok(result)
+}
+
+/// Checks if a character is removed from message content by [`normalize_message_content`].
+fn is_stripped_char(c: char) -> bool {
+    (c.is_control() && c != '\n' && c != '\t')
+        || matches!(c, '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}' | '\u{FEFF}')
+}
+
+/// Checks if a character has no width.
+fn is_zero_width_char(c: char) -> bool {
+    matches!(c, '\u{200B}'..='\u{200D}' | '\u{2060}')
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        fold_name, normalize_message_content, validate_description, validate_name_with,
+        DescriptionKind, InvalidNameReason,
+    };
+    use crate::{config::NameRules, error::Error};
+
+    fn reason(rules: &NameRules, name: &str) -> Option<InvalidNameReason> {
+        match validate_name_with(rules, name) {
+            Err(Error::InvalidName(reason)) => Some(reason),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn normalizes_whitespace() {
+        let rules = NameRules::default();
+        assert_eq!(
+            validate_name_with(&rules, "  general \t chat ").unwrap(),
+            "general chat"
+        );
+        assert_eq!(
+            reason(&rules, &" ".repeat(31)),
+            Some(InvalidNameReason::OnlyWhitespace)
+        );
+        assert_eq!(reason(&rules, ""), Some(InvalidNameReason::TooShort));
+    }
+
+    #[test]
+    fn counts_length_in_chars() {
+        let rules = NameRules {
+            min_length: 2,
+            max_length: 4,
+            extended_characters: true,
+        };
+        assert_eq!(validate_name_with(&rules, "éééé").unwrap(), "éééé");
+        assert_eq!(reason(&rules, "ééééé"), Some(InvalidNameReason::TooLong));
+        assert_eq!(reason(&rules, "é"), Some(InvalidNameReason::TooShort));
+    }
+
+    #[test]
+    fn description_errors_state_the_limit() {
+        assert!(validate_description(DescriptionKind::Short, &"é".repeat(512)).is_ok());
+        let err = validate_description(DescriptionKind::Short, &"é".repeat(513)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "description is longer than the limit of 512 characters"
+        );
+        assert!(validate_description(DescriptionKind::Long, &"é".repeat(513)).is_ok());
+    }
+
+    #[test]
+    fn extended_characters() {
+        let mut rules = NameRules::default();
+        assert_eq!(validate_name_with(&rules, "café #1").unwrap(), "café #1");
+        assert_eq!(
+            reason(&rules, "chat\u{200b}"),
+            Some(InvalidNameReason::IllegalCharacter('\u{200b}'))
+        );
+        rules.extended_characters = false;
+        assert_eq!(
+            reason(&rules, "café"),
+            Some(InvalidNameReason::IllegalCharacter('é'))
+        );
+    }
+
+    #[test]
+    fn normalizes_message_content() {
+        let normalize = |content: &str| normalize_message_content(content).ok();
+        assert_eq!(normalize("a\0b\u{7}c"), Some("abc".to_string()));
+        assert_eq!(
+            normalize("\u{1b}[31mred\u{1b}[0m"),
+            Some("[31mred[0m".to_string())
+        );
+        assert_eq!(
+            normalize("\u{202E}txt.exe\u{2066}"),
+            Some("txt.exe".to_string())
+        );
+        assert_eq!(normalize("e\u{301}"), Some("\u{e9}".to_string()));
+        assert_eq!(
+            normalize(&format!("a{}b", "\u{200D}".repeat(1 << 20))),
+            Some("a\u{200D}b".to_string())
+        );
+        // Emoji sequences keep their joiners.
+        assert_eq!(
+            normalize("\u{1F469}\u{200D}\u{1F4BB}"),
+            Some("\u{1F469}\u{200D}\u{1F4BB}".to_string())
+        );
+        assert_eq!(
+            normalize("\n\nfirst\r\n\tindented  \n\n \n\t\n\n\n\nlast\n\n"),
+            Some("first\n\tindented\n\n\nlast".to_string())
+        );
+        for empty in &["", " \t\n", "\0\u{1b}", "\u{200B}\u{200D}", "\u{202E}\r\n"] {
+            assert!(matches!(
+                normalize_message_content(empty),
+                Err(Error::InvalidText)
+            ));
+        }
+    }
+
+    #[test]
+    fn folds_case() {
+        assert_eq!(fold_name("General"), fold_name("general"));
+        assert_eq!(fold_name("GENERAL"), fold_name("general"));
+        assert_eq!(fold_name("STRASSE"), fold_name("straße"));
+        assert_eq!(fold_name("ﬁles"), fold_name("FILES"));
+        assert_ne!(fold_name("general"), fold_name("generai"));
+    }
+}
@@ -0,0 +1,425 @@
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, IoContext},
+    locks::{KeyedLock, KeyedLocks},
+    ChannelId, HubId, Result, ID,
+};
+
+/// Folder where the webhooks of each hub are stored.
+pub const WEBHOOKS_FOLDER: &str = "data/hubs/webhooks/";
+
+/// Most webhooks a channel can have.
+pub const MAX_WEBHOOKS_PER_CHANNEL: usize = 16;
+
+/// Locks of the webhook files of hubs, see [`lock`].
+static WEBHOOK_LOCKS: KeyedLocks<HubId> = KeyedLocks::new();
+
+/// A webhook that posts messages in a channel, shown as sent by a [`crate::channel::AuthorType::Webhook`] author.
+/// Anyone that knows the webhook's token can post with it, see `/v3/webhook_send`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Webhook {
+    /// ID of the webhook.
+    pub id: ID,
+    /// ID of the channel the webhook posts in.
+    pub channel_id: ChannelId,
+    /// Name the webhook's messages are shown with unless a message overrides it, see [`crate::channel::validate_override_name`].
+    pub name: String,
+    /// URL of the avatar the webhook's messages are shown with unless a message overrides it.
+    pub avatar: Option<String>,
+    /// Secret that has to be given to post with the webhook, only shown to members that can manage the channel's webhooks.
+    pub token: String,
+    /// ID of the user that created the webhook, its messages are sent in their name.
+    pub created_by: String,
+    /// Time the webhook was created.
+    pub created: DateTime<Utc>,
+}
+
+/// Name and avatar of a webhook to be created, as given by its creator.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct NewWebhook {
+    pub name: String,
+    /// `https` URL of the webhook's avatar.
+    #[serde(default)]
+    pub avatar: Option<String>,
+}
+
+/// Message posted with a webhook, see [`crate::message_pipeline::send_webhook`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct WebhookMessage {
+    pub content: String,
+    /// Name the message is shown as sent by instead of the webhook's name.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// `https` URL of the avatar the message is shown with instead of the webhook's avatar.
+    #[serde(default)]
+    pub avatar: Option<String>,
+}
+
+/// Webhooks of a hub, stored separately from the hub itself.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct HubWebhooks {
+    /// Webhooks of every channel of the hub, oldest first.
+    pub webhooks: Vec<Webhook>,
+}
+
+impl HubWebhooks {
+    /// Gets the path of the file that a hub's webhooks are stored in.
+    pub fn get_path(hub_id: HubId) -> PathBuf {
+        crate::paths::webhooks_file(hub_id)
+    }
+
+    /// Loads the webhooks of a hub, a hub without the file has none.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The file exists but could not be read.
+    /// * The file's contents could not be deserialized.
+    pub async fn load(hub_id: HubId) -> Result<Self> {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => Ok(bincode::deserialize(&bytes)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_path(path),
+        }
+    }
+
+    /// Saves the webhooks of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error in the following situations, but is not
+    /// limited to just these cases:
+    ///
+    /// * The webhooks folder does not exist and could not be created.
+    /// * The data could not be written to the disk.
+    pub async fn save(&self, hub_id: HubId) -> Result {
+        tokio::fs::create_dir_all(WEBHOOKS_FOLDER)
+            .await
+            .with_path(WEBHOOKS_FOLDER)?;
+        let path = Self::get_path(hub_id);
+        tokio::fs::write(&path, bincode::serialize(self)?)
+            .await
+            .with_path(path)
+    }
+
+    /// Removes the webhooks of a hub.
+    ///
+    /// # Errors
+    ///
+    /// This function returns an error if the file exists but could not be removed.
+    pub async fn remove(hub_id: HubId) -> Result {
+        let path = Self::get_path(hub_id);
+        match tokio::fs::remove_file(&path).await {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result.with_path(path),
+        }
+    }
+
+    /// Gets the webhooks of a channel, oldest first.
+    pub fn channel_webhooks(&self, channel_id: ChannelId) -> Vec<Webhook> {
+        self.webhooks
+            .iter()
+            .filter(|webhook| webhook.channel_id == channel_id)
+            .cloned()
+            .collect()
+    }
+
+    /// Gets a webhook by its ID.
+    ///
+    /// # Errors
+    ///
+    /// This function returns [`Error::WebhookNotFound`] if the hub has no webhook with the ID.
+    pub fn get(&self, webhook_id: ID) -> Result<&Webhook> {
+        self.webhooks
+            .iter()
+            .find(|webhook| webhook.id == webhook_id)
+            .ok_or(Error::WebhookNotFound)
+    }
+
+    /// Gets a webhook by its ID if `token` is its token, the tokens are compared without stopping at the first difference.
+    ///
+    /// # Errors
+    ///
+    /// This function returns [`Error::WebhookNotFound`] if the hub has no webhook with the ID or the token is wrong, so tokens can not be told apart from IDs by guessing.
+    pub fn authenticate(&self, webhook_id: ID, token: &str) -> Result<&Webhook> {
+        self.get(webhook_id).and_then(|webhook| {
+            if crate::hub_deletion::constant_time_eq(&webhook.token, token) {
+                Ok(webhook)
+            } else {
+                Err(Error::WebhookNotFound)
+            }
+        })
+    }
+
+    /// Removes a webhook and returns it.
+    ///
+    /// # Errors
+    ///
+    /// This function returns [`Error::WebhookNotFound`] if the hub has no webhook with the ID.
+    pub fn remove_webhook(&mut self, webhook_id: ID) -> Result<Webhook> {
+        let index = self
+            .webhooks
+            .iter()
+            .position(|webhook| webhook.id == webhook_id)
+            .ok_or(Error::WebhookNotFound)?;
+        Ok(self.webhooks.remove(index))
+    }
+}
+
+/// Creates a new webhook token.
+pub fn generate_token() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+/// Locks the webhooks of a hub so that no other change is made to them until the guard is dropped.
+pub async fn lock(hub_id: HubId) -> KeyedLock<'static, HubId> {
+    WEBHOOK_LOCKS.lock(hub_id).await
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use super::{generate_token, HubWebhooks, Webhook};
+    use crate::{error::Error, ChannelId, ID};
+
+    #[test]
+    fn tokens_authenticate() {
+        let webhook = Webhook {
+            id: ID::from_u128(1),
+            channel_id: ChannelId::from_u128(2),
+            name: "alerts".to_string(),
+            avatar: None,
+            token: generate_token(),
+            created_by: "0123456789ABCDEF0123456789ABCDEF01234567".to_string(),
+            created: Utc::now(),
+        };
+        let mut webhooks = HubWebhooks {
+            webhooks: vec![webhook.clone()],
+        };
+        assert_eq!(
+            webhooks.authenticate(webhook.id, &webhook.token).unwrap(),
+            &webhook
+        );
+        assert!(matches!(
+            webhooks.authenticate(webhook.id, &generate_token()),
+            Err(Error::WebhookNotFound)
+        ));
+        assert!(matches!(
+            webhooks.authenticate(ID::from_u128(3), &webhook.token),
+            Err(Error::WebhookNotFound)
+        ));
+        assert_eq!(webhooks.remove_webhook(webhook.id).unwrap(), webhook);
+        assert!(webhooks.channel_webhooks(webhook.channel_id).is_empty());
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn unprivileged_members_are_refused() {
+        use super::NewWebhook;
+        use crate::{api, permission::ChannelPermission, testing::spawn_test_server};
+
+        let server = spawn_test_server().await.unwrap();
+        let owner = server.admin.user_id.as_str();
+        let user = server.user.user_id.as_str();
+        let hub_id = api::create_hub(owner, "webhooks").await.unwrap();
+        let channel_id = api::create_channel(owner, hub_id, "alerts").await.unwrap();
+        api::join_hub(user.to_string(), hub_id).await.unwrap();
+        for permission in [ChannelPermission::Read, ChannelPermission::Write]
+            .iter()
+            .copied()
+        {
+            api::set_member_channel_permission(
+                owner,
+                hub_id,
+                user,
+                channel_id,
+                permission,
+                Some(true),
+                None,
+            )
+            .await
+            .unwrap();
+        }
+        let new_webhook = NewWebhook {
+            name: "CI".to_string(),
+            avatar: Some("https://example.com/ci.png".to_string()),
+        };
+        let webhook = server
+            .client(&server.admin)
+            .create_webhook(hub_id, channel_id, &new_webhook)
+            .await
+            .unwrap();
+
+        let refused = |result| {
+            matches!(
+                result,
+                Err(Error::MissingChannelPermission(
+                    ChannelPermission::ManageWebhooks
+                ))
+            )
+        };
+        assert!(refused(
+            api::create_webhook(user, hub_id, channel_id, new_webhook.clone())
+                .await
+                .map(drop)
+        ));
+        assert!(refused(
+            api::get_webhooks(user, hub_id, channel_id).await.map(drop)
+        ));
+        assert!(refused(api::delete_webhook(user, hub_id, webhook.id).await));
+        let client = server.client(&server.user);
+        assert!(client
+            .create_webhook(hub_id, channel_id, &new_webhook)
+            .await
+            .is_err());
+        assert!(client.webhooks(hub_id, channel_id).await.is_err());
+        assert!(client.delete_webhook(hub_id, webhook.id).await.is_err());
+        assert_eq!(
+            api::get_webhooks(owner, hub_id, channel_id).await.unwrap(),
+            vec![webhook.clone()]
+        );
+
+        api::set_member_channel_permission(
+            owner,
+            hub_id,
+            user,
+            channel_id,
+            ChannelPermission::ManageWebhooks,
+            Some(true),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            client.webhooks(hub_id, channel_id).await.unwrap(),
+            vec![webhook.clone()]
+        );
+        client.delete_webhook(hub_id, webhook.id).await.unwrap();
+        assert!(client
+            .webhooks(hub_id, channel_id)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn posts_as_webhook() {
+        use std::convert::TryFrom;
+
+        use super::{NewWebhook, WebhookMessage};
+        use crate::{
+            api,
+            channel::{AuthorType, Message},
+            permission::ChannelPermission,
+            testing::spawn_test_server,
+        };
+
+        let server = spawn_test_server().await.unwrap();
+        let owner = server.admin.user_id.as_str();
+        let user = server.user.user_id.as_str();
+        let hub_id = api::create_hub(owner, "posting").await.unwrap();
+        let channel_id = api::create_channel(owner, hub_id, "builds").await.unwrap();
+        api::join_hub(user.to_string(), hub_id).await.unwrap();
+        let set_permission = |permission, setting| {
+            api::set_member_channel_permission(
+                owner,
+                hub_id,
+                user,
+                channel_id,
+                permission,
+                Some(setting),
+                None,
+            )
+        };
+        for permission in [
+            ChannelPermission::Read,
+            ChannelPermission::Write,
+            ChannelPermission::ManageWebhooks,
+        ]
+        .iter()
+        .copied()
+        {
+            set_permission(permission, true).await.unwrap();
+        }
+        let client = server.client(&server.user);
+        let webhook = client
+            .create_webhook(
+                hub_id,
+                channel_id,
+                &NewWebhook {
+                    name: "CI".to_string(),
+                    avatar: Some("https://example.com/ci.png".to_string()),
+                },
+            )
+            .await
+            .unwrap();
+        let post = |content: &str, name: Option<&str>| WebhookMessage {
+            content: content.to_string(),
+            name: name.map(str::to_string),
+            avatar: None,
+        };
+
+        let sent = client
+            .send_webhook(
+                hub_id,
+                webhook.id,
+                &webhook.token,
+                &post("Build passed.", None),
+            )
+            .await
+            .unwrap();
+        assert_eq!(sent.author_type, AuthorType::Webhook);
+        assert_eq!(sent.override_name.as_deref(), Some("CI"));
+        assert_eq!(
+            sent.override_avatar.as_deref(),
+            Some("https://example.com/ci.png")
+        );
+        let stored = Message::try_from(
+            api::get_message(owner, hub_id, channel_id, sent.id)
+                .await
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(stored.sender, user);
+        assert_eq!(stored.content, "Build passed.");
+        assert_eq!(stored.author_type, AuthorType::Webhook);
+        assert_eq!(stored.override_name.as_deref(), Some("CI"));
+
+        let renamed = client
+            .send_webhook(
+                hub_id,
+                webhook.id,
+                &webhook.token,
+                &post("Deployed.", Some("Deploy bot")),
+            )
+            .await
+            .unwrap();
+        assert_eq!(renamed.override_name.as_deref(), Some("Deploy bot"));
+        assert!(client
+            .send_webhook(hub_id, webhook.id, &generate_token(), &post("Hi.", None))
+            .await
+            .is_err());
+        assert!(client
+            .send_webhook(hub_id, webhook.id, &webhook.token, &post("Hi.", Some("")))
+            .await
+            .is_err());
+
+        // The webhook posts in its creator's name, who has to still be able to write in the channel.
+        set_permission(ChannelPermission::Write, false)
+            .await
+            .unwrap();
+        assert!(client
+            .send_webhook(hub_id, webhook.id, &webhook.token, &post("Hi.", None))
+            .await
+            .is_err());
+    }
+}
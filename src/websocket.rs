@@ -1,23 +1,43 @@
-use std::sync::Arc;
+#[cfg(feature = "websocket")]
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use crate::{
-    channel::Message,
-    error::Error,
-    hub::Hub,
-    permission::ChannelPermission,
-    server::{Server, ServerNotification},
+    api::types::{ChannelInfo, ExpandedMessage, HubInfo, HubMemberInfo},
+    channel::{AuthorType, SignedMessage},
+    delivery_reports::Intents,
+    offline_summaries::MissedMentions,
+    rendering::RenderFormat,
+    server::HubUpdateType,
+    subscription_profiles::SubscriptionProfile,
+    unfurl::LinkPreview,
+    ChannelId, HubId, MessageId, ID,
+};
+#[cfg(feature = "websocket")]
+use crate::{
+    error::{Error, Result},
+    instrumentation::{Instrumentation, InstrumentedAddr},
+    server::{client_command, Server},
+    signing::KeyPair,
 };
-use crate::{server::client_command, ID};
-use crate::{server::HubUpdateType, signing::KeyPair};
-use futures_util::{SinkExt, StreamExt};
+use chrono::{DateTime, Utc};
+#[cfg(feature = "websocket")]
+use futures_util::{stream::SplitSink, SinkExt, StreamExt};
+#[cfg(feature = "websocket")]
 use pgp::{crypto::HashAlgorithm, types::CompressionAlgorithm, Message as OpenPGPMessage};
-use pgp::{packet::LiteralData, types::KeyTrait, SignedPublicKey};
-use tokio::sync::Mutex;
-use warp::ws::WebSocket;
-use xactor::Addr;
-
-use crate::error::Result;
+#[cfg(feature = "websocket")]
+use pgp::{packet::LiteralData, types::KeyTrait, SignedPublicKey, SignedSecretKey};
+#[cfg(feature = "websocket")]
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "websocket")]
+use tokio::sync::Mutex;
+#[cfg(feature = "websocket")]
+use warp::{
+    ws::{WebSocket, Ws},
+    Filter, Rejection, Reply,
+};
 
 pub use warp::ws::Message as WebSocketMessage;
 
@@ -25,38 +45,136 @@ pub use warp::ws::Message as WebSocketMessage;
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum ClientMessage {
     SubscribeHub {
-        hub_id: ID,
+        hub_id: HubId,
     },
     UnsubscribeHub {
-        hub_id: ID,
+        hub_id: HubId,
     },
+    /// Subscribes to a channel, with `with_snapshot` the server first sends a [`ServerMessage::ChannelSnapshot`] of the channel.
     SubscribeChannel {
-        hub_id: ID,
-        channel_id: ID,
+        hub_id: HubId,
+        channel_id: ChannelId,
+        #[serde(default)]
+        with_snapshot: bool,
     },
     UnsubscribeChannel {
-        hub_id: ID,
-        channel_id: ID,
+        hub_id: HubId,
+        channel_id: ChannelId,
     },
     StartTyping {
-        hub_id: ID,
-        channel_id: ID,
+        hub_id: HubId,
+        channel_id: ChannelId,
     },
     StopTyping {
-        hub_id: ID,
-        channel_id: ID,
+        hub_id: HubId,
+        channel_id: ChannelId,
     },
     SendMessageInit {
-        hub_id: ID,
-        channel_id: ID,
+        hub_id: HubId,
+        channel_id: ChannelId,
         content: String,
+        /// Flags of the message, see [`crate::channel::Message::flags`].
+        #[serde(default)]
+        flags: u32,
     },
     SendMessage {
         signed_message: String,
     },
+    /// Asks for the hubs and channels the connection is subscribed to, answered with [`ServerMessage::Subscriptions`].
+    QuerySubscriptions,
+    /// Subscribes to every hub and channel in the user's subscription profile (see [`crate::subscription_profiles`]), answered with [`ServerMessage::ProfileResumed`].
+    ResumeProfile,
+    /// Asks for the users that are typing in a channel, answered with [`ServerMessage::Typing`].
+    QueryTyping {
+        hub_id: HubId,
+        channel_id: ChannelId,
+    },
+    /// Reads data the HTTP API also serves, answered with [`ServerMessage::ReadResult`] or [`ServerMessage::ReadFailed`] with the same `request_id`.
+    Read {
+        request_id: u64,
+        query: ReadQuery,
+    },
+    /// Sets the optional notifications the connection gets, replacing the ones set before.
+    SetIntents {
+        intents: Intents,
+    },
+    /// Acknowledges that the connection received [`ServerMessage::ChatMessage`]s of a channel it is subscribed to, at most [`crate::delivery_reports::MAX_ACKS_PER_COMMAND`] at once.
+    /// Only needed for the delivery reports of other users, clients can acknowledge the messages they received since their last acknowledgement together.
+    AckMessages {
+        hub_id: HubId,
+        channel_id: ChannelId,
+        message_ids: Vec<MessageId>,
+    },
+}
+
+/// Data that can be read with a [`ClientMessage::Read`], each maps to the [`crate::api`] function of the same name and has the same limits.
+/// Queries for messages with `expand_sender` set are answered with the display data of the senders joined on, see [`crate::api::expand_senders`].
+/// With `render` set they are also answered as expanded messages, with their content rendered in that format, see [`crate::api::render_messages`].
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum ReadQuery {
+    /// See [`crate::api::get_messages`].
+    GetMessages {
+        hub_id: HubId,
+        channel_id: ChannelId,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        #[serde(default)]
+        invert: bool,
+        max: usize,
+        #[serde(default)]
+        expand_sender: bool,
+        #[serde(default)]
+        render: Option<RenderFormat>,
+    },
+    /// See [`crate::api::get_messages_after`].
+    GetMessagesAfter {
+        hub_id: HubId,
+        channel_id: ChannelId,
+        from: MessageId,
+        max: usize,
+        #[serde(default)]
+        expand_sender: bool,
+        #[serde(default)]
+        render: Option<RenderFormat>,
+    },
+    /// See [`crate::api::get_message`].
+    GetMessage {
+        hub_id: HubId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        #[serde(default)]
+        expand_sender: bool,
+        #[serde(default)]
+        render: Option<RenderFormat>,
+    },
+    /// See [`crate::api::get_hub`].
+    GetHub { hub_id: HubId },
+    /// See [`crate::api::get_channel`].
+    GetChannel {
+        hub_id: HubId,
+        channel_id: ChannelId,
+    },
+    /// See [`crate::api::get_hub_member`].
+    GetHubMember { hub_id: HubId, user_id: String },
+}
+
+/// Data read by a [`ClientMessage::Read`].
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum ReadResult {
+    Messages(Vec<SignedMessage>),
+    Message(SignedMessage),
+    ExpandedMessages(Vec<ExpandedMessage>),
+    ExpandedMessage(ExpandedMessage),
+    Hub(HubInfo),
+    Channel(ChannelInfo),
+    HubMember(HubMemberInfo),
 }
 
 /// Messages that the server can send to clients.
+///
+/// Notifications (new messages, typing and so on) reach each connection in the order the server sent them, so the events of a channel are never reordered.
+/// A connection gets each notification at most once, even if more than one of its subscriptions matches it.
+/// Hub updates are the exception: they are collected for [`crate::server::HUB_UPDATE_COALESCE_MS`] milliseconds and sent apart from other notifications, so they can arrive after notifications that were sent after them. Their versions tell clients the order of the updates.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum ServerMessage {
     Error(String),
@@ -64,36 +182,538 @@ pub enum ServerMessage {
     NotSigned,
     CommandFailed,
     ChatMessage {
-        hub_id: ID,
-        channel_id: ID,
-        message_id: ID,
+        hub_id: HubId,
+        channel_id: ChannelId,
+        message_id: MessageId,
         armoured_message: String,
+        /// Flags of the message, see [`crate::channel::Message::flags`].
+        flags: u32,
+        /// True if the message was moved here from another channel (see [`crate::message_move`]), its signed content still names the channel it was sent in.
+        #[serde(default)]
+        moved: bool,
+        /// Who sent the message, see [`crate::channel::Message::author_type`].
+        #[serde(default)]
+        author_type: AuthorType,
+        /// Sequence number of the event in the channel, see [`crate::channel_events`].
+        event_seq: u64,
+        /// Epoch of `event_seq`, it changes when the server restarts and starts counting again, see [`crate::channel_events::ChannelEvents::epoch`].
+        event_epoch: u64,
+    },
+    /// A message that mentions a permission group the user is in, sent to each of the user's connections in addition to [`ServerMessage::ChatMessage`].
+    Mention {
+        hub_id: HubId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        group_id: ID,
+    },
+    /// The connection was unsubscribed from a channel because its user can no longer read it.
+    SubscriptionRevoked {
+        hub_id: HubId,
+        channel_id: ChannelId,
+    },
+    /// New version of a message that was edited, replaces the message with the same ID.
+    ChatMessageEdited {
+        hub_id: HubId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        armoured_message: String,
+        /// Sequence number of the event in the channel, see [`crate::channel_events`].
+        event_seq: u64,
+        /// Epoch of `event_seq`, it changes when the server restarts and starts counting again, see [`crate::channel_events::ChannelEvents::epoch`].
+        event_epoch: u64,
     },
     HubUpdated {
-        hub_id: ID,
+        hub_id: HubId,
         update_type: HubUpdateType,
+        version: u64,
+    },
+    /// Several updates made to a hub in a short time, in the order they were made and without repeats. `version` is the version of the hub after the last of them.
+    HubUpdates {
+        hub_id: HubId,
+        update_types: Vec<HubUpdateType>,
+        version: u64,
     },
     Success,
     UserStartedTyping {
         user_id: String,
-        hub_id: ID,
-        channel_id: ID,
+        hub_id: HubId,
+        channel_id: ChannelId,
     },
     UserStoppedTyping {
         user_id: String,
-        hub_id: ID,
-        channel_id: ID,
+        hub_id: HubId,
+        channel_id: ChannelId,
     },
     MessageForSigning {
         server_signed_message: String,
     },
+    /// Hubs and channels the connection is subscribed to, both sorted.
+    Subscriptions {
+        hubs: Vec<HubId>,
+        channels: Vec<(HubId, ChannelId)>,
+    },
+    /// Answer to a [`ClientMessage::ResumeProfile`], `denied` has the entries of the profile that the connection was not subscribed to because the user can no longer read them or the connection reached its subscription limits.
+    ProfileResumed {
+        denied: SubscriptionProfile,
+    },
+    /// Users that are typing in a channel, sorted by ID.
+    Typing {
+        hub_id: HubId,
+        channel_id: ChannelId,
+        user_ids: Vec<String>,
+    },
+    /// Last message before the server closes the connection, `code` is the [`CloseCode`] that the close frame will have.
+    Closing {
+        code: u16,
+        detail: String,
+    },
+    /// Latest messages (oldest first) and typing users of a channel, sent before any other message about the channel after a [`ClientMessage::SubscribeChannel`] with `with_snapshot`.
+    ChannelSnapshot {
+        hub_id: HubId,
+        channel_id: ChannelId,
+        messages: Vec<SignedMessage>,
+        typing: Vec<String>,
+    },
+    /// First message after the client authenticates, describes what the server supports. Clients do not need to answer it.
+    Hello(ServerHello),
+    /// Answer to a [`ClientMessage::Read`].
+    ReadResult {
+        request_id: u64,
+        result: ReadResult,
+    },
+    /// A [`ClientMessage::Read`] failed, `status` is the HTTP status code the HTTP API would have answered the same read with.
+    ReadFailed {
+        request_id: u64,
+        status: u16,
+        error: String,
+    },
+    /// Messages that were removed from a channel by a moderator, sent in batches of at most [`crate::message_purge::PURGE_BATCH_SIZE`].
+    MessagesDeleted {
+        hub_id: HubId,
+        channel_id: ChannelId,
+        message_ids: Vec<MessageId>,
+        /// Sequence number of the event in the channel, see [`crate::channel_events`].
+        event_seq: u64,
+        /// Epoch of `event_seq`, it changes when the server restarts and starts counting again, see [`crate::channel_events::ChannelEvents::epoch`].
+        event_epoch: u64,
+    },
+    /// Previews of the links in a message, sent once the server has fetched the linked pages, see [`crate::unfurl`].
+    LinkPreviews {
+        hub_id: HubId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        link_previews: Vec<LinkPreview>,
+    },
+    /// The connection's user was kicked or banned from a hub, the connection no longer gets any of the hub's events.
+    RemovedFromHub {
+        hub_id: HubId,
+    },
+    /// The connection's user asked to delete a hub, on this or another device. The hub is deleted if the deletion is confirmed before `expires`, see [`crate::hub_deletion`].
+    HubDeletionRequested {
+        hub_id: HubId,
+        expires: DateTime<Utc>,
+    },
+    /// A message the connection's user sent was acknowledged by a connection of another user, sent once per message to connections with [`Intents::delivery_reports`].
+    Delivered {
+        hub_id: HubId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+    },
+    /// The connection's user changed their draft in a channel, on this or another device, see [`crate::drafts`]. `content` is `null` if the draft was removed, for example because a message was sent in the channel.
+    DraftUpdated {
+        hub_id: HubId,
+        channel_id: ChannelId,
+        content: Option<String>,
+    },
+    /// Mentions the user missed while they had no connection open, sent right after the [`ServerMessage::Hello`] to the first connection they open afterwards if they missed any, see [`crate::offline_summaries`].
+    OfflineSummary {
+        mentions: Vec<MissedMentions>,
+    },
+}
+
+/// Version of the WebSocket protocol, sent in [`ServerHello::protocol_version`]. Matches the version in the path of the HTTP API.
+pub const PROTOCOL_VERSION: u32 = 3;
+
+/// Subprotocol clients have to request in the `Sec-WebSocket-Protocol` header of the handshake, the server echoes it back.
+/// The version of the protocol spoken over it is [`PROTOCOL_VERSION`], which is also in the [`ServerHello`].
+pub const SUBPROTOCOL: &str = "wicrs";
+
+/// Largest frame (and message) in bytes that the server accepts from clients.
+pub const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// Joins the display data of their senders onto messages and renders them if `render` is set, see [`crate::api::expand_senders`] and [`crate::api::render_messages`].
+#[cfg(feature = "websocket")]
+async fn expand_messages(
+    user_id: &str,
+    hub_id: HubId,
+    messages: Vec<SignedMessage>,
+    render: Option<RenderFormat>,
+) -> Result<Vec<ExpandedMessage>> {
+    let mut messages = crate::api::expand_senders(user_id, hub_id, messages).await?;
+    match render {
+        Some(RenderFormat::Html) => crate::api::render_messages(&mut messages)?,
+        None => {}
+    }
+    Ok(messages)
+}
+
+/// Answers a [`ClientMessage::Read`] by calling the [`crate::api`] function directly, reads do not wait for the [`Server`].
+#[cfg(feature = "websocket")]
+pub async fn answer_read(user_id: &str, request_id: u64, query: ReadQuery) -> ServerMessage {
+    let result = match query {
+        ReadQuery::GetMessages {
+            hub_id,
+            channel_id,
+            from,
+            to,
+            invert,
+            max,
+            expand_sender,
+            render,
+        } => match crate::api::get_messages(user_id, hub_id, channel_id, from, to, invert, max)
+            .await
+        {
+            Ok(messages) if expand_sender || render.is_some() => {
+                expand_messages(user_id, hub_id, messages, render)
+                    .await
+                    .map(ReadResult::ExpandedMessages)
+            }
+            result => result.map(ReadResult::Messages),
+        },
+        ReadQuery::GetMessagesAfter {
+            hub_id,
+            channel_id,
+            from,
+            max,
+            expand_sender,
+            render,
+        } => match crate::api::get_messages_after(user_id, hub_id, channel_id, from, max).await {
+            Ok(messages) if expand_sender || render.is_some() => {
+                expand_messages(user_id, hub_id, messages, render)
+                    .await
+                    .map(ReadResult::ExpandedMessages)
+            }
+            result => result.map(ReadResult::Messages),
+        },
+        ReadQuery::GetMessage {
+            hub_id,
+            channel_id,
+            message_id,
+            expand_sender,
+            render,
+        } => match crate::api::get_message(user_id, hub_id, channel_id, message_id).await {
+            Ok(message) if expand_sender || render.is_some() => {
+                expand_messages(user_id, hub_id, vec![message], render)
+                    .await
+                    .and_then(|mut messages| messages.pop().ok_or(Error::MessageNotFound))
+                    .map(ReadResult::ExpandedMessage)
+            }
+            result => result.map(ReadResult::Message),
+        },
+        ReadQuery::GetHub { hub_id } => crate::api::get_hub(user_id, hub_id)
+            .await
+            .map(|hub| ReadResult::Hub(HubInfo::from(&hub))),
+        ReadQuery::GetChannel { hub_id, channel_id } => {
+            crate::api::get_channel(user_id, hub_id, channel_id)
+                .await
+                .map(|channel| ReadResult::Channel(ChannelInfo::from(&channel)))
+        }
+        ReadQuery::GetHubMember {
+            hub_id,
+            user_id: member_id,
+        } => crate::api::get_hub_member(user_id, hub_id, &member_id)
+            .await
+            .map(|member| ReadResult::HubMember(HubMemberInfo::from(&member))),
+    };
+    match result {
+        Ok(result) => ServerMessage::ReadResult { request_id, result },
+        Err(err) => ServerMessage::ReadFailed {
+            request_id,
+            status: reqwest::StatusCode::from(&err).as_u16(),
+            error: err.to_string(),
+        },
+    }
+}
+
+/// Capabilities and limits of the server, sent to each client in [`ServerMessage::Hello`] so that clients do not need to hardcode them.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ServerHello {
+    /// See [`PROTOCOL_VERSION`].
+    pub protocol_version: u32,
+    /// Subprotocol the connection was accepted with, see [`SUBPROTOCOL`].
+    pub subprotocol: String,
+    /// Version of WICRS server, `None` if the server is configured not to show it.
+    pub server_version: Option<String>,
+    /// Seconds between the pings the server sends, `None` because the server does not send any.
+    pub heartbeat_interval: Option<u64>,
+    /// Seconds the server waits for an answer to a ping before closing the connection, `None` because idle connections are never closed.
+    pub heartbeat_timeout: Option<u64>,
+    /// Maximum length in bytes of the content of a chat message.
+    pub max_message_length: usize,
+    /// See [`MAX_FRAME_SIZE`].
+    pub max_frame_size: usize,
+    /// Maximum number of commands the client can send per minute, `None` for no limit.
+    pub commands_per_minute: Option<u32>,
+    /// Compression algorithms used for the OpenPGP messages the server sends.
+    pub compression: Vec<String>,
+    /// ID of the user the connection is authenticated as.
+    pub user_id: String,
+}
+
+#[cfg(feature = "websocket")]
+impl ServerHello {
+    /// Describes the server to a client authenticated as `user_id`.
+    pub fn new(user_id: String, limits: &ConnectionLimits) -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            subprotocol: SUBPROTOCOL.to_string(),
+            server_version: if limits.show_version {
+                Some(env!("CARGO_PKG_VERSION").to_string())
+            } else {
+                None
+            },
+            heartbeat_interval: None,
+            heartbeat_timeout: None,
+            max_message_length: crate::MESSAGE_MAX_SIZE,
+            max_frame_size: MAX_FRAME_SIZE,
+            commands_per_minute: limits.commands_per_minute,
+            compression: vec!["zip".to_string()],
+            user_id,
+        }
+    }
+}
+
+/// Codes the server closes WebSocket connections with, sent in the close frame after a [`ServerMessage::Closing`] with more detail where possible.
+///
+/// | Code | Meaning |
+/// |------|---------|
+/// | 4000 | Authentication failed or the challenge was not answered in time, reconnect and authenticate again. |
+/// | 4001 | The client broke the protocol (for example by sending binary frames) or the connection failed in a way it can not recover from. |
+/// | 4002 | The client sent more commands than it is allowed to, wait before reconnecting. |
+/// | 4003 | The server is shutting down, reconnect once it is back. |
+/// | 4004 | The user is suspended from the server. |
+/// | 4005 | The user already has as many connections open as they are allowed to, close one before reconnecting. |
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloseCode {
+    AuthExpired,
+    ProtocolError,
+    RateLimited,
+    ServerShutdown,
+    Suspended,
+    TooManyConnections,
 }
 
+impl CloseCode {
+    /// Numeric code sent in the close frame.
+    pub fn code(self) -> u16 {
+        match self {
+            CloseCode::AuthExpired => 4000,
+            CloseCode::ProtocolError => 4001,
+            CloseCode::RateLimited => 4002,
+            CloseCode::ServerShutdown => 4003,
+            CloseCode::Suspended => 4004,
+            CloseCode::TooManyConnections => 4005,
+        }
+    }
+
+    /// Short reason sent in the close frame.
+    pub fn reason(self) -> &'static str {
+        match self {
+            CloseCode::AuthExpired => "authentication expired",
+            CloseCode::ProtocolError => "protocol error",
+            CloseCode::RateLimited => "rate limited",
+            CloseCode::ServerShutdown => "server shutting down",
+            CloseCode::Suspended => "suspended",
+            CloseCode::TooManyConnections => "too many connections",
+        }
+    }
+
+    /// Gets the close code for a numeric code, `None` if it is not one of the server's codes.
+    pub fn from_code(code: u16) -> Option<Self> {
+        match code {
+            4000 => Some(CloseCode::AuthExpired),
+            4001 => Some(CloseCode::ProtocolError),
+            4002 => Some(CloseCode::RateLimited),
+            4003 => Some(CloseCode::ServerShutdown),
+            4004 => Some(CloseCode::Suspended),
+            4005 => Some(CloseCode::TooManyConnections),
+            _ => None,
+        }
+    }
+
+    /// Close frame with this code and its reason.
+    pub fn close_frame(self) -> WebSocketMessage {
+        WebSocketMessage::close_with(self.code(), self.reason())
+    }
+}
+
+/// Seconds a client has to answer the authentication challenge before the connection is closed with [`CloseCode::AuthExpired`].
+pub const AUTH_TIMEOUT: u64 = 30;
+
+/// Limits and settings applied to each WebSocket connection.
+#[cfg(feature = "websocket")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ConnectionLimits {
+    /// Time the client has to answer the authentication challenge.
+    pub auth_timeout: Duration,
+    /// Maximum number of commands the client can send per minute, `None` for no limit.
+    pub commands_per_minute: Option<u32>,
+    /// Whether to include the server's version in the [`ServerHello`].
+    pub show_version: bool,
+}
+
+#[cfg(feature = "websocket")]
+impl ConnectionLimits {
+    /// Gets the limits set in the server's configuration, see [`crate::quotas::limits`].
+    pub fn configured(show_version: bool) -> Self {
+        Self {
+            auth_timeout: Duration::from_secs(AUTH_TIMEOUT),
+            commands_per_minute: crate::quotas::limits().max_websocket_commands_per_minute,
+            show_version,
+        }
+    }
+}
+
+/// Checks the headers of a WebSocket handshake before the connection is upgraded.
+///
+/// # Errors
+///
+/// This function may return an error for any of the following reasons:
+///
+/// * [`Error::OriginNotAllowed`] if there is an `Origin` header and it is not in `allowed_origins` (`None` allows every origin).
+///   Browsers always send the header, requests without it come from native clients and are allowed.
+/// * [`Error::SubprotocolMissing`] if the client did not request [`SUBPROTOCOL`].
+#[cfg(feature = "websocket")]
+pub fn check_handshake(
+    origin: Option<&str>,
+    protocols: Option<&str>,
+    allowed_origins: Option<&[String]>,
+) -> Result {
+    if let (Some(origin), Some(allowed_origins)) = (origin, allowed_origins) {
+        let origin = origin.trim_end_matches('/');
+        if !allowed_origins
+            .iter()
+            .any(|allowed| allowed.trim_end_matches('/').eq_ignore_ascii_case(origin))
+        {
+            return Err(Error::OriginNotAllowed);
+        }
+    }
+    if protocols.is_some_and(|protocols| {
+        protocols
+            .split(',')
+            .any(|protocol| protocol.trim() == SUBPROTOCOL)
+    }) {
+        Ok(())
+    } else {
+        Err(Error::SubprotocolMissing)
+    }
+}
+
+/// Filter for the WebSocket handshake, extracts the `Origin` and `Sec-WebSocket-Protocol` headers for [`accept`].
+#[cfg(feature = "websocket")]
+pub fn handshake(
+) -> impl Filter<Extract = (Ws, Option<String>, Option<String>), Error = Rejection> + Clone {
+    warp::ws()
+        .and(warp::header::optional::<String>("origin"))
+        .and(warp::header::optional::<String>("sec-websocket-protocol"))
+}
+
+/// Upgrades the connection and passes it to `handler` if the handshake passes [`check_handshake`], otherwise answers with the error (`403 Forbidden`) without upgrading.
+#[cfg(feature = "websocket")]
+pub fn accept<F, Fut>(
+    ws: Ws,
+    origin: Option<String>,
+    protocols: Option<String>,
+    allowed_origins: Option<&[String]>,
+    handler: F,
+) -> warp::reply::Response
+where
+    F: FnOnce(WebSocket) -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = ()> + Send + 'static,
+{
+    match check_handshake(origin.as_deref(), protocols.as_deref(), allowed_origins) {
+        Ok(()) => warp::reply::with_header(
+            ws.max_frame_size(MAX_FRAME_SIZE)
+                .max_message_size(MAX_FRAME_SIZE)
+                .on_upgrade(handler),
+            "sec-websocket-protocol",
+            SUBPROTOCOL,
+        )
+        .into_response(),
+        Err(err) => err.into_response(),
+    }
+}
+
+/// Counts the commands sent on a connection in the current minute.
+#[cfg(feature = "websocket")]
+struct CommandRate {
+    window_start: Instant,
+    count: u32,
+}
+
+#[cfg(feature = "websocket")]
+impl CommandRate {
+    fn new(now: Instant) -> Self {
+        Self {
+            window_start: now,
+            count: 0,
+        }
+    }
+
+    /// Counts a command, returns false if it goes over the limit.
+    fn allow(&mut self, now: Instant, limit: Option<u32>) -> bool {
+        if now.duration_since(self.window_start) >= Duration::from_secs(60) {
+            self.window_start = now;
+            self.count = 0;
+        }
+        self.count = self.count.saturating_add(1);
+        limit.is_none_or(|limit| self.count <= limit)
+    }
+}
+
+/// Signs and compresses a message for a client.
+#[cfg(feature = "websocket")]
+fn sign_server_message(
+    message: &ServerMessage,
+    secret_key: &SignedSecretKey,
+) -> Result<WebSocketMessage> {
+    let message = OpenPGPMessage::new_literal("", serde_json::to_string(message)?.as_str())
+        .sign(secret_key, String::new, HashAlgorithm::SHA2_256)?
+        .compress(CompressionAlgorithm::ZIP)?;
+    Ok(WebSocketMessage::text(message.to_armored_string(None)?))
+}
+
+/// Sends a [`ServerMessage::Closing`] with the given detail and then the close frame for `code`.
+/// Failures are ignored, the connection is being closed either way.
+#[cfg(feature = "websocket")]
+async fn close(
+    outgoing: &mut SplitSink<WebSocket, WebSocketMessage>,
+    secret_key: &SignedSecretKey,
+    code: CloseCode,
+    detail: String,
+) {
+    if let Ok(message) = sign_server_message(
+        &ServerMessage::Closing {
+            code: code.code(),
+            detail,
+        },
+        secret_key,
+    ) {
+        let _ = outgoing.send(message).await;
+    }
+    let _ = outgoing.send(code.close_frame()).await;
+}
+
+/// Authenticates a WebSocket client and handles its commands until the connection is closed.
+/// Connections closed by the server get one of the [`CloseCode`]s.
+#[cfg(feature = "websocket")]
 pub async fn handle_connection(
     websocket: WebSocket,
     public_key: SignedPublicKey,
     server_keys: Arc<KeyPair>,
-    addr: Arc<Addr<Server>>,
+    addr: Arc<InstrumentedAddr<Server>>,
+    instrumentation: Arc<Instrumentation>,
+    limits: ConnectionLimits,
 ) -> Result {
     let (mut outgoing, mut incoming) = websocket.split();
     let key = rand::random::<u128>().to_string();
@@ -106,41 +726,151 @@ pub async fn handle_connection(
         .send(WebSocketMessage::text(message.to_armored_string(None)?))
         .await?;
 
-    if let Some(msg) = incoming.next().await {
+    let answer = match tokio::time::timeout(limits.auth_timeout, incoming.next()).await {
+        Ok(answer) => answer,
+        Err(_) => {
+            crate::audit!(
+                user = %hex::encode_upper(public_key.fingerprint()),
+                kind = "auth_failure",
+                "WebSocket client did not authenticate in time."
+            );
+            close(
+                &mut outgoing,
+                &server_keys.secret_key,
+                CloseCode::AuthExpired,
+                "the authentication challenge was not answered in time".to_string(),
+            )
+            .await;
+            return Err(Error::WsNotAuthenticated);
+        }
+    };
+    if let Some(msg) = answer {
         let msg = msg?;
-        if let Ok(text) = msg.to_str() {
-            let message = crate::signing::verify_message_extract(&public_key, text)?.0;
-            if message == key {
-                drop((message, key, text));
-                drop(msg);
-                let out_arc = Arc::new(Mutex::new(outgoing));
-                let connection_id: u128;
-                {
-                    let result = addr
-                        .call(client_command::Connect {
-                            websocket_writer: out_arc.clone(),
-                        })
-                        .await
-                        .map_err(|_| Error::InternalMessageFailed)?;
-                    connection_id = result;
+        let authenticated = msg
+            .to_str()
+            .ok()
+            .and_then(|text| crate::signing::verify_message_extract(&public_key, text).ok())
+            .is_some_and(|(message, _)| message == key);
+        if authenticated {
+            drop(key);
+            drop(msg);
+            let out_arc = Arc::new(Mutex::new(outgoing));
+            let user_id = crate::identity_links::user_id(&public_key)?;
+            let connection_id: u128;
+            {
+                let result = addr
+                    .call(client_command::Connect {
+                        user_id: user_id.clone(),
+                        websocket_writer: out_arc.clone(),
+                    })
+                    .await
+                    .map_err(|_| Error::InternalMessageFailed)?;
+                connection_id = match result {
+                    Ok(connection_id) => connection_id,
+                    Err(err) => {
+                        crate::audit!(
+                            user = %user_id,
+                            kind = "rate_limited",
+                            "WebSocket client has too many connections open."
+                        );
+                        close(
+                            &mut *out_arc.lock().await,
+                            &server_keys.secret_key,
+                            CloseCode::TooManyConnections,
+                            err.to_string(),
+                        )
+                        .await;
+                        return Err(err);
+                    }
+                };
+            }
+            crate::audit!(
+                user = %user_id,
+                connection = %connection_id,
+                kind = "auth",
+                "WebSocket client authenticated."
+            );
+            let internal_message_error = Error::InternalMessageFailed.to_string();
+            let mut rate = CommandRate::new(Instant::now());
+            let result = async {
+                // Sent after the challenge so that the challenge stays the first frame, clients that ignore the hello keep working.
+                out_arc
+                    .lock()
+                    .await
+                    .send(sign_server_message(
+                        &ServerMessage::Hello(ServerHello::new(user_id.clone(), &limits)),
+                        &server_keys.secret_key,
+                    )?)
+                    .await?;
+                match crate::offline_summaries::take(&user_id).await {
+                    Ok(summary) if !summary.is_empty() => {
+                        out_arc
+                            .lock()
+                            .await
+                            .send(sign_server_message(
+                                &ServerMessage::OfflineSummary {
+                                    mentions: summary.mentions,
+                                },
+                                &server_keys.secret_key,
+                            )?)
+                            .await?
+                    }
+                    Ok(_) => {}
+                    Err(err) => tracing::warn!(
+                        "Unable to get the offline summary of {}: {}",
+                        user_id,
+                        err.chain()
+                    ),
                 }
-                let user_id = hex::encode_upper(public_key.fingerprint());
-                let internal_message_error = Error::InternalMessageFailed.to_string();
                 while let Some(msg) = incoming.next().await {
                     let msg = msg?;
+                    if msg.is_binary() {
+                        close(
+                            &mut *out_arc.lock().await,
+                            &server_keys.secret_key,
+                            CloseCode::ProtocolError,
+                            "binary frames are not supported".to_string(),
+                        )
+                        .await;
+                        return Ok(());
+                    }
                     if let Ok(text) = msg.to_str() {
+                        if !rate.allow(Instant::now(), limits.commands_per_minute) {
+                            crate::audit!(
+                                user = %user_id,
+                                connection = %connection_id,
+                                kind = "rate_limited",
+                                "WebSocket client sent too many commands."
+                            );
+                            close(
+                                &mut *out_arc.lock().await,
+                                &server_keys.secret_key,
+                                CloseCode::RateLimited,
+                                format!(
+                                    "more than {} commands were sent in a minute",
+                                    limits.commands_per_minute.unwrap_or_default()
+                                ),
+                            )
+                            .await;
+                            return Ok(());
+                        }
                         let raw_response = if let Ok((command_text, _)) =
                             crate::signing::verify_message_extract(&public_key, text)
                         {
                             if let Ok(command) = serde_json::from_str(&command_text) {
                                 match command {
-                                    ClientMessage::SubscribeChannel { hub_id, channel_id } => {
+                                    ClientMessage::SubscribeChannel {
+                                        hub_id,
+                                        channel_id,
+                                        with_snapshot,
+                                    } => {
                                         if let Ok(result) = addr
                                             .call(client_command::SubscribeChannel {
                                                 user_id: user_id.clone(),
                                                 hub_id,
                                                 channel_id,
                                                 connection_id,
+                                                with_snapshot,
                                             })
                                             .await
                                         {
@@ -173,6 +903,7 @@ pub async fn handle_connection(
                                                 user_id: user_id.clone(),
                                                 hub_id,
                                                 channel_id,
+                                                connection_id,
                                             })
                                             .await
                                         {
@@ -232,64 +963,131 @@ pub async fn handle_connection(
                                             ServerMessage::Error(internal_message_error.clone())
                                         }
                                     }
+                                    ClientMessage::QuerySubscriptions => {
+                                        if let Ok((hubs, channels)) = addr
+                                            .call(client_command::QuerySubscriptions {
+                                                connection_id,
+                                            })
+                                            .await
+                                        {
+                                            ServerMessage::Subscriptions { hubs, channels }
+                                        } else {
+                                            ServerMessage::Error(internal_message_error.clone())
+                                        }
+                                    }
+                                    ClientMessage::ResumeProfile => {
+                                        if let Ok(result) = addr
+                                            .call(client_command::ResumeProfile {
+                                                user_id: user_id.clone(),
+                                                connection_id,
+                                            })
+                                            .await
+                                        {
+                                            result.map_or_else(
+                                                |err| ServerMessage::Error(err.to_string()),
+                                                |denied| ServerMessage::ProfileResumed { denied },
+                                            )
+                                        } else {
+                                            ServerMessage::Error(internal_message_error.clone())
+                                        }
+                                    }
+                                    ClientMessage::QueryTyping { hub_id, channel_id } => {
+                                        if let Ok(result) = addr
+                                            .call(client_command::QueryTyping {
+                                                user_id: user_id.clone(),
+                                                hub_id,
+                                                channel_id,
+                                            })
+                                            .await
+                                        {
+                                            result.map_or_else(
+                                                |err| ServerMessage::Error(err.to_string()),
+                                                |user_ids| ServerMessage::Typing {
+                                                    hub_id,
+                                                    channel_id,
+                                                    user_ids,
+                                                },
+                                            )
+                                        } else {
+                                            ServerMessage::Error(internal_message_error.clone())
+                                        }
+                                    }
                                     ClientMessage::SendMessageInit {
                                         hub_id,
                                         channel_id,
                                         content,
+                                        flags,
+                                    } => async {
+                                        Ok::<_, Error>(ServerMessage::MessageForSigning {
+                                            server_signed_message:
+                                                crate::message_pipeline::prepare(
+                                                    user_id.clone(),
+                                                    hub_id,
+                                                    channel_id,
+                                                    content,
+                                                    flags,
+                                                )
+                                                .await?
+                                                .sign(&server_keys.secret_key, String::new)?
+                                                .compress(CompressionAlgorithm::ZIP)?
+                                                .to_armored_string(None)?,
+                                        })
+                                    }
+                                    .await
+                                    .unwrap_or_else(|err| ServerMessage::Error(err.to_string())),
+                                    ClientMessage::Read { request_id, query } => {
+                                        answer_read(&user_id, request_id, query).await
+                                    }
+                                    ClientMessage::SetIntents { intents } => {
+                                        if addr
+                                            .call(client_command::SetIntents {
+                                                connection_id,
+                                                intents,
+                                            })
+                                            .await
+                                            .is_ok()
+                                        {
+                                            ServerMessage::Success
+                                        } else {
+                                            ServerMessage::Error(internal_message_error.clone())
+                                        }
+                                    }
+                                    ClientMessage::AckMessages {
+                                        hub_id,
+                                        channel_id,
+                                        message_ids,
                                     } => {
-                                        let hub = Hub::load(hub_id).await?;
-                                        let member = hub.get_member(&user_id)?;
-                                        crate::check_permission!(
-                                            &member,
-                                            channel_id,
-                                            ChannelPermission::Write,
-                                            &hub
-                                        );
-                                        ServerMessage::MessageForSigning {
-                                            server_signed_message: Message::new(
-                                                user_id.clone(),
-                                                content,
+                                        if let Ok(result) = addr
+                                            .call(client_command::AckMessages {
+                                                user_id: user_id.clone(),
+                                                connection_id,
                                                 hub_id,
                                                 channel_id,
+                                                message_ids,
+                                            })
+                                            .await
+                                        {
+                                            result.map_or_else(
+                                                |err| ServerMessage::Error(err.to_string()),
+                                                |_| ServerMessage::Success,
                                             )
-                                            .sign(&server_keys.secret_key, String::new)?
-                                            .compress(CompressionAlgorithm::ZIP)?
-                                            .to_armored_string(None)?,
+                                        } else {
+                                            ServerMessage::Error(internal_message_error.clone())
                                         }
                                     }
                                     ClientMessage::SendMessage { signed_message } => {
-                                        let message = Message::from_double_signed_verify(
-                                            &signed_message,
-                                            &server_keys.public_key,
+                                        crate::message_pipeline::send(
+                                            signed_message,
                                             &public_key,
-                                        )?;
-                                        if let Err(err) = crate::channel::Channel::write_message(
-                                            message.hub_id,
-                                            message.channel_id,
-                                            crate::channel::SignedMessage::new(
-                                                message.id,
-                                                message.created,
-                                                signed_message.clone(),
-                                            ),
+                                            &server_keys.public_key,
+                                            &addr,
+                                            &instrumentation,
                                         )
                                         .await
-                                        {
-                                            ServerMessage::Error(err.to_string())
-                                        } else if addr
-                                            .call(ServerNotification::NewMessage(
-                                                message.hub_id,
-                                                message.channel_id,
-                                                message.id,
-                                                signed_message,
-                                                message,
-                                            ))
-                                            .await
-                                            .is_ok()
-                                        {
-                                            ServerMessage::Success
-                                        } else {
-                                            ServerMessage::Error(internal_message_error.clone())
-                                        }
+                                        .map_or_else(
+                                            |err| ServerMessage::Error(err.to_string()),
+                                            |_| ServerMessage::Success,
+                                        )
                                     }
                                 }
                             } else {
@@ -298,26 +1096,368 @@ pub async fn handle_connection(
                         } else {
                             ServerMessage::NotSigned
                         };
-                        let message = OpenPGPMessage::new_literal(
-                            "",
-                            serde_json::to_string(&raw_response)?.as_str(),
-                        )
-                        .sign(
-                            &server_keys.secret_key,
-                            String::new,
-                            HashAlgorithm::SHA2_256,
-                        )?
-                        .compress(CompressionAlgorithm::ZIP)?;
-                        out_arc
-                            .lock()
-                            .await
-                            .send(WebSocketMessage::text(message.to_armored_string(None)?))
-                            .await?;
+                        let message = sign_server_message(&raw_response, &server_keys.secret_key)?;
+                        out_arc.lock().await.send(message).await?;
                     }
                 }
-                return Ok(());
+                Ok::<_, Error>(())
+            }
+            .await;
+            if let Err(err) = &result {
+                // Does nothing if the connection itself failed.
+                close(
+                    &mut *out_arc.lock().await,
+                    &server_keys.secret_key,
+                    CloseCode::ProtocolError,
+                    err.to_string(),
+                )
+                .await;
             }
+            // Unsubscribe the client from everything, however the connection ended.
+            let _ = addr.send(client_command::Disconnect { connection_id });
+            return result;
         }
     }
+    crate::audit!(
+        user = %hex::encode_upper(public_key.fingerprint()),
+        kind = "auth_failure",
+        "WebSocket client failed to authenticate."
+    );
+    close(
+        &mut outgoing,
+        &server_keys.secret_key,
+        CloseCode::AuthExpired,
+        "the answer to the authentication challenge was not valid".to_string(),
+    )
+    .await;
     Err(Error::WsNotAuthenticated)
 }
+
+#[cfg(all(test, feature = "websocket"))]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use pgp::{crypto::HashAlgorithm, types::KeyTrait, Message as OpenPGPMessage, SignedPublicKey};
+    use warp::{test::RequestBuilder, ws::Ws, Filter, Rejection, Reply};
+    use xactor::Actor;
+
+    use super::{
+        accept, handle_connection, handshake, ClientMessage, CloseCode, ConnectionLimits,
+        ServerHello, ServerMessage, WebSocketMessage, SUBPROTOCOL,
+    };
+    use crate::{
+        instrumentation::{Instrumentation, InstrumentedAddr},
+        server::Server,
+        signing::{verify_message_extract, KeyPair},
+    };
+
+    fn route(
+        server_keys: Arc<KeyPair>,
+        client_key: SignedPublicKey,
+        addr: Arc<InstrumentedAddr<Server>>,
+        limits: ConnectionLimits,
+        allowed_origins: Option<Vec<String>>,
+    ) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+        handshake().map(
+            move |ws: Ws, origin: Option<String>, protocols: Option<String>| {
+                let server_keys = server_keys.clone();
+                let client_key = client_key.clone();
+                let addr = addr.clone();
+                accept(
+                    ws,
+                    origin,
+                    protocols,
+                    allowed_origins.as_deref(),
+                    move |websocket| async move {
+                        let _ = handle_connection(
+                            websocket,
+                            client_key,
+                            server_keys,
+                            addr,
+                            Arc::new(Instrumentation::default()),
+                            limits,
+                        )
+                        .await;
+                    },
+                )
+            },
+        )
+    }
+
+    async fn start_server(server_keys: &KeyPair) -> Arc<InstrumentedAddr<Server>> {
+        let instrumentation = Arc::new(Instrumentation::default());
+        let addr = Server::new(server_keys.secret_key.clone(), instrumentation.clone())
+            .await
+            .unwrap()
+            .start()
+            .await
+            .unwrap();
+        Arc::new(InstrumentedAddr::new(addr, instrumentation.server.clone()))
+    }
+
+    fn handshake_request(origin: Option<&str>, protocol: Option<&str>) -> RequestBuilder {
+        let mut request = warp::test::request()
+            .header("connection", "upgrade")
+            .header("upgrade", "websocket")
+            .header("sec-websocket-version", "13")
+            .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==");
+        if let Some(origin) = origin {
+            request = request.header("origin", origin);
+        }
+        if let Some(protocol) = protocol {
+            request = request.header("sec-websocket-protocol", protocol);
+        }
+        request
+    }
+
+    fn sign(keys: &KeyPair, text: &str) -> String {
+        OpenPGPMessage::new_literal("", text)
+            .sign(&keys.secret_key, String::new, HashAlgorithm::SHA2_256)
+            .unwrap()
+            .to_armored_string(None)
+            .unwrap()
+    }
+
+    fn read(keys: &KeyPair, message: &WebSocketMessage) -> String {
+        verify_message_extract(&keys.public_key, message.to_str().unwrap())
+            .unwrap()
+            .0
+    }
+
+    fn close_code(message: &WebSocketMessage) -> Option<CloseCode> {
+        message
+            .close_frame()
+            .and_then(|(code, _)| CloseCode::from_code(code))
+    }
+
+    #[test]
+    fn close_frames() {
+        for &code in &[
+            CloseCode::AuthExpired,
+            CloseCode::ProtocolError,
+            CloseCode::RateLimited,
+            CloseCode::ServerShutdown,
+            CloseCode::Suspended,
+        ] {
+            assert_eq!(close_code(&code.close_frame()), Some(code));
+        }
+    }
+
+    #[tokio::test]
+    async fn auth_expired_and_rate_limited() {
+        let server_keys = Arc::new(KeyPair::new("server").unwrap());
+        let client_keys = KeyPair::new("client").unwrap();
+        let addr = start_server(&server_keys).await;
+        let route = route(
+            server_keys.clone(),
+            client_keys.public_key.clone(),
+            addr,
+            ConnectionLimits {
+                auth_timeout: Duration::from_millis(200),
+                commands_per_minute: Some(2),
+                show_version: false,
+            },
+            None,
+        );
+
+        // The authentication challenge is never answered.
+        let mut client = warp::test::ws()
+            .header("sec-websocket-protocol", SUBPROTOCOL)
+            .handshake(route.clone())
+            .await
+            .unwrap();
+        client.recv().await.unwrap();
+        let closing: ServerMessage =
+            serde_json::from_str(&read(&server_keys, &client.recv().await.unwrap())).unwrap();
+        assert!(matches!(closing, ServerMessage::Closing { code: 4000, .. }));
+        // The test client ends the stream at the close frame instead of returning it, its code is checked in `close_frames`.
+        client.recv_closed().await.unwrap();
+
+        // One command more than the limit after authenticating.
+        let mut client = warp::test::ws()
+            .header("sec-websocket-protocol", SUBPROTOCOL)
+            .handshake(route)
+            .await
+            .unwrap();
+        let key = read(&server_keys, &client.recv().await.unwrap());
+        client.send_text(sign(&client_keys, &key)).await;
+        let hello: ServerMessage =
+            serde_json::from_str(&read(&server_keys, &client.recv().await.unwrap())).unwrap();
+        match hello {
+            ServerMessage::Hello(hello) => {
+                assert_eq!(
+                    hello.user_id,
+                    hex::encode_upper(client_keys.public_key.fingerprint())
+                );
+                assert_eq!(hello.server_version, None);
+                assert_eq!(hello.subprotocol, SUBPROTOCOL);
+                assert_eq!(hello.commands_per_minute, Some(2));
+            }
+            other => panic!("expected a hello, got {:?}", other),
+        }
+        let command = serde_json::to_string(&ClientMessage::QuerySubscriptions).unwrap();
+        for _ in 0..2 {
+            client.send_text(sign(&client_keys, &command)).await;
+            let response: ServerMessage =
+                serde_json::from_str(&read(&server_keys, &client.recv().await.unwrap())).unwrap();
+            assert!(matches!(response, ServerMessage::Subscriptions { .. }));
+        }
+        client.send_text(sign(&client_keys, &command)).await;
+        let closing: ServerMessage =
+            serde_json::from_str(&read(&server_keys, &client.recv().await.unwrap())).unwrap();
+        assert!(matches!(closing, ServerMessage::Closing { code: 4002, .. }));
+        // The test client ends the stream at the close frame instead of returning it, its code is checked in `close_frames`.
+        client.recv_closed().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn handshake_checks_origin_and_subprotocol() {
+        let server_keys = Arc::new(KeyPair::new("server").unwrap());
+        let client_keys = KeyPair::new("client").unwrap();
+        let addr = start_server(&server_keys).await;
+        let route = route(
+            server_keys,
+            client_keys.public_key.clone(),
+            addr,
+            ConnectionLimits {
+                auth_timeout: Duration::from_secs(30),
+                commands_per_minute: None,
+                show_version: false,
+            },
+            Some(vec!["https://app.example.com".to_string()]),
+        );
+
+        let accepted = handshake_request(Some("https://app.example.com"), Some("chat, wicrs"))
+            .reply(&route)
+            .await;
+        assert_eq!(accepted.status(), 101);
+        assert_eq!(accepted.headers()["sec-websocket-protocol"], SUBPROTOCOL);
+        // Native clients do not send an origin.
+        let native = handshake_request(None, Some(SUBPROTOCOL))
+            .reply(&route)
+            .await;
+        assert_eq!(native.status(), 101);
+
+        let cross_origin = handshake_request(Some("https://evil.example.com"), Some(SUBPROTOCOL))
+            .reply(&route)
+            .await;
+        assert_eq!(cross_origin.status(), 403);
+        let no_protocol = handshake_request(Some("https://app.example.com"), None)
+            .reply(&route)
+            .await;
+        assert_eq!(no_protocol.status(), 403);
+        let wrong_protocol = handshake_request(None, Some("chat")).reply(&route).await;
+        assert_eq!(wrong_protocol.status(), 403);
+        assert!(warp::test::ws().handshake(route).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn banned_user_gets_no_more_hub_events() {
+        let server_keys = Arc::new(KeyPair::new("server").unwrap());
+        let client_keys = KeyPair::new("client").unwrap();
+        let user_id = hex::encode_upper(client_keys.public_key.fingerprint());
+        let owner_id = hex::encode_upper(server_keys.public_key.fingerprint());
+        let hub_id = crate::api::create_hub(owner_id.as_str(), "banning")
+            .await
+            .unwrap();
+        crate::api::join_hub(user_id.clone(), hub_id).await.unwrap();
+        let addr = start_server(&server_keys).await;
+        let route = route(
+            server_keys.clone(),
+            client_keys.public_key.clone(),
+            addr,
+            ConnectionLimits {
+                auth_timeout: Duration::from_secs(30),
+                commands_per_minute: None,
+                show_version: false,
+            },
+            None,
+        );
+        let mut client = warp::test::ws()
+            .header("sec-websocket-protocol", SUBPROTOCOL)
+            .handshake(route)
+            .await
+            .unwrap();
+        let key = read(&server_keys, &client.recv().await.unwrap());
+        client.send_text(sign(&client_keys, &key)).await;
+        client.recv().await.unwrap();
+        let command = serde_json::to_string(&ClientMessage::SubscribeHub { hub_id }).unwrap();
+        client.send_text(sign(&client_keys, &command)).await;
+        let response: ServerMessage =
+            serde_json::from_str(&read(&server_keys, &client.recv().await.unwrap())).unwrap();
+        assert!(matches!(response, ServerMessage::Success));
+
+        crate::api::ban_user(&owner_id, hub_id, &user_id)
+            .await
+            .unwrap();
+        let removed: ServerMessage =
+            serde_json::from_str(&read(&server_keys, &client.recv().await.unwrap())).unwrap();
+        assert!(matches!(removed, ServerMessage::RemovedFromHub { hub_id: id } if id == hub_id));
+        crate::api::rename_hub(&owner_id, hub_id, "renamed", None)
+            .await
+            .unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(500), client.recv())
+                .await
+                .is_err()
+        );
+        assert!(crate::api::get_hub(&user_id, hub_id).await.is_err());
+        let token = crate::api::request_hub_deletion(&owner_id, hub_id)
+            .await
+            .unwrap();
+        crate::api::delete_hub(&owner_id, hub_id, &token.token)
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn hello_snapshot() {
+        let hello = ServerHello::new(
+            "0123456789ABCDEF0123456789ABCDEF01234567".to_string(),
+            &ConnectionLimits {
+                auth_timeout: Duration::from_secs(30),
+                commands_per_minute: Some(120),
+                show_version: false,
+            },
+        );
+        assert_eq!(
+            serde_json::to_string(&ServerMessage::Hello(hello)).unwrap(),
+            r#"{"Hello":{"protocol_version":3,"subprotocol":"wicrs","server_version":null,"heartbeat_interval":null,"heartbeat_timeout":null,"max_message_length":8192,"max_frame_size":65536,"commands_per_minute":120,"compression":["zip"],"user_id":"0123456789ABCDEF0123456789ABCDEF01234567"}}"#
+        );
+    }
+
+    #[test]
+    fn subscribe_without_snapshot() {
+        let command: ClientMessage = serde_json::from_str(
+            r#"{"SubscribeChannel":{"hub_id":"00000000-0000-0000-0000-000000000001","channel_id":"00000000-0000-0000-0000-000000000002"}}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            command,
+            ClientMessage::SubscribeChannel {
+                with_snapshot: false,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn read_failure_has_status() {
+        let answer = super::answer_read(
+            "0123456789ABCDEF0123456789ABCDEF01234567",
+            7,
+            super::ReadQuery::GetHub {
+                hub_id: crate::HubId::random(),
+            },
+        )
+        .await;
+        assert!(matches!(
+            answer,
+            ServerMessage::ReadFailed {
+                request_id: 7,
+                status: 404,
+                ..
+            }
+        ));
+    }
+}